@@ -0,0 +1,514 @@
+//! Protocol analyzer: flags frames that break BACnet encoding rules
+//!
+//! A field device sending malformed APDUs rarely upsets the gateway - it
+//! routes bytes - but it can confuse the head-end or the tools behind it,
+//! and nothing says which device it was. In analysis mode every routed APDU
+//! is walked tag by tag: the header length for its PDU type, tag lengths
+//! against the frame, opening/closing tag pairs, fixed-size application tags
+//! and the parameters of the most common services. Violations are counted
+//! per source (the MS/TP station or B/IP peer the frame arrived from), with
+//! the last few offending frames kept as examples.
+//!
+//! Off by default: walking every frame costs time the routing path can use.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use crate::talkers::TalkerAddress;
+
+/// Sources tracked at once; the one heard from least recently is evicted
+pub const MAX_ANALYZED_SOURCES: usize = 32;
+
+/// Offending frames kept per source, newest replacing oldest
+pub const EXAMPLES_PER_SOURCE: usize = 3;
+
+/// APDU bytes kept per example
+pub const EXAMPLE_BYTES: usize = 48;
+
+/// Way in which a frame breaks the encoding rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// PDU type 8-15
+    UnknownPduType,
+    /// Header too short (or too long) for its PDU type
+    HeaderLength,
+    /// A tag's length runs past the end of the frame
+    TagOverrun,
+    /// Closing tag without its opening tag, or an opening tag left open
+    UnbalancedTags,
+    /// Fixed-size application tag with the wrong length, or a reserved tag
+    ApplicationLength,
+    /// Service parameters missing, out of order or of the wrong form
+    ServiceParameters,
+}
+
+impl Violation {
+    pub const ALL: [Violation; 6] = [
+        Violation::UnknownPduType,
+        Violation::HeaderLength,
+        Violation::TagOverrun,
+        Violation::UnbalancedTags,
+        Violation::ApplicationLength,
+        Violation::ServiceParameters,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Violation::UnknownPduType => "unknown-pdu-type",
+            Violation::HeaderLength => "header-length",
+            Violation::TagOverrun => "tag-overrun",
+            Violation::UnbalancedTags => "unbalanced-tags",
+            Violation::ApplicationLength => "application-length",
+            Violation::ServiceParameters => "service-parameters",
+        }
+    }
+}
+
+/// One tag of the service parameters, at nesting depth 0 unless noted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Tag {
+    number: u8,
+    context: bool,
+    kind: TagKind,
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagKind {
+    Opening,
+    Closing,
+    /// Content length in octets (a Boolean's value is in the tag itself)
+    Value(usize),
+}
+
+impl Tag {
+    fn is_context_value(&self, number: u8) -> bool {
+        self.context && self.number == number && matches!(self.kind, TagKind::Value(_))
+    }
+
+    fn is_application(&self, number: u8) -> bool {
+        !self.context && self.number == number
+    }
+
+    fn len(&self) -> usize {
+        match self.kind {
+            TagKind::Value(len) => len,
+            _ => 0,
+        }
+    }
+}
+
+// Services whose parameters are checked
+const SERVICE_I_AM: u8 = 0;
+const SERVICE_WHO_IS: u8 = 8;
+const SERVICE_READ_PROPERTY: u8 = 12;
+const SERVICE_WRITE_PROPERTY: u8 = 15;
+
+const OBJECT_TYPE_DEVICE: u32 = 8;
+
+/// Check one APDU against the encoding rules
+pub fn check_apdu(apdu: &[u8]) -> Result<(), Violation> {
+    let Some(&first) = apdu.first() else { return Err(Violation::HeaderLength) };
+    let segmented = first & 0x08 != 0;
+    match first >> 4 {
+        // Confirmed-Request: later segments split tags, so only whole requests are walked
+        0 => {
+            let header = if segmented { 6 } else { 4 };
+            if apdu.len() < header {
+                return Err(Violation::HeaderLength);
+            }
+            let tags = walk(&apdu[header..])?;
+            if segmented {
+                return Ok(());
+            }
+            match apdu[3] {
+                SERVICE_READ_PROPERTY => check_read_property_request(&tags),
+                SERVICE_WRITE_PROPERTY => check_write_property_request(&tags),
+                _ => Ok(()),
+            }
+        }
+        // Unconfirmed-Request
+        1 => {
+            if apdu.len() < 2 {
+                return Err(Violation::HeaderLength);
+            }
+            let tags = walk(&apdu[2..])?;
+            match apdu[1] {
+                SERVICE_I_AM => check_i_am(&apdu[2..], &tags),
+                SERVICE_WHO_IS => check_who_is(&tags),
+                _ => Ok(()),
+            }
+        }
+        // SimpleACK
+        2 => (apdu.len() == 3).then_some(()).ok_or(Violation::HeaderLength),
+        // ComplexACK
+        3 => {
+            let header = if segmented { 5 } else { 3 };
+            if apdu.len() < header {
+                return Err(Violation::HeaderLength);
+            }
+            if segmented {
+                return Ok(());
+            }
+            let tags = walk(&apdu[header..])?;
+            match apdu[2] {
+                SERVICE_READ_PROPERTY => check_read_property_ack(&tags),
+                _ => Ok(()),
+            }
+        }
+        // SegmentACK
+        4 => (apdu.len() == 4).then_some(()).ok_or(Violation::HeaderLength),
+        // Error: class and code, unless the service wraps them in context tags
+        5 => {
+            if apdu.len() < 3 {
+                return Err(Violation::HeaderLength);
+            }
+            let tags = walk(&apdu[3..])?;
+            match tags.first() {
+                Some(tag) if tag.context && tag.kind == TagKind::Opening => Ok(()),
+                _ if tags.len() >= 2 && tags[0].is_application(9) && tags[1].is_application(9) => Ok(()),
+                _ => Err(Violation::ServiceParameters),
+            }
+        }
+        // Reject, Abort
+        6 | 7 => (apdu.len() == 3).then_some(()).ok_or(Violation::HeaderLength),
+        _ => Err(Violation::UnknownPduType),
+    }
+}
+
+/// Walk a sequence of tags, checking lengths and nesting
+fn walk(data: &[u8]) -> Result<Vec<Tag>, Violation> {
+    let mut tags = Vec::new();
+    let mut open: Vec<u8> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let octet = data[pos];
+        pos += 1;
+        let mut number = octet >> 4;
+        let context = octet & 0x08 != 0;
+        let lvt = octet & 0x07;
+        if number == 15 {
+            number = *data.get(pos).ok_or(Violation::TagOverrun)?;
+            pos += 1;
+        }
+
+        let kind = match lvt {
+            6 if context => TagKind::Opening,
+            7 if context => TagKind::Closing,
+            // Boolean: the value is the LVT itself
+            _ if !context && number == 1 => {
+                if lvt > 1 {
+                    return Err(Violation::ApplicationLength);
+                }
+                TagKind::Value(0)
+            }
+            5 => {
+                let len = match *data.get(pos).ok_or(Violation::TagOverrun)? {
+                    254 => {
+                        let bytes = data.get(pos + 1..pos + 3).ok_or(Violation::TagOverrun)?;
+                        pos += 2;
+                        usize::from(u16::from_be_bytes([bytes[0], bytes[1]]))
+                    }
+                    255 => {
+                        let bytes = data.get(pos + 1..pos + 5).ok_or(Violation::TagOverrun)?;
+                        pos += 4;
+                        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+                    }
+                    len => usize::from(len),
+                };
+                pos += 1;
+                TagKind::Value(len)
+            }
+            len => TagKind::Value(usize::from(len)),
+        };
+
+        let depth = open.len();
+        match kind {
+            TagKind::Opening => open.push(number),
+            TagKind::Closing => {
+                if open.pop() != Some(number) {
+                    return Err(Violation::UnbalancedTags);
+                }
+            }
+            TagKind::Value(len) => {
+                if len > data.len() - pos {
+                    return Err(Violation::TagOverrun);
+                }
+                if !context {
+                    check_application_tag(number, &data[pos..pos + len])?;
+                }
+                pos += len;
+            }
+        }
+        tags.push(Tag { number, context, kind, depth: if kind == TagKind::Closing { open.len() } else { depth } });
+    }
+    if !open.is_empty() {
+        return Err(Violation::UnbalancedTags);
+    }
+    Ok(tags)
+}
+
+/// Lengths the application tags allow
+fn check_application_tag(number: u8, content: &[u8]) -> Result<(), Violation> {
+    let ok = match number {
+        0 => content.is_empty(),
+        2 | 3 => (1..=8).contains(&content.len()),
+        4 | 10 | 11 | 12 => content.len() == 4,
+        5 => content.len() == 8,
+        6 => true,
+        // Character set octet first
+        7 => !content.is_empty(),
+        // Unused-bits octet first, 0 when there are no bits
+        8 => match content {
+            [] => false,
+            [unused] => *unused == 0,
+            [unused, ..] => *unused <= 7,
+        },
+        9 => (1..=4).contains(&content.len()),
+        _ => false,
+    };
+    ok.then_some(()).ok_or(Violation::ApplicationLength)
+}
+
+/// Top-level tags only (nested values and the closing tags inside them left out)
+fn top_level(tags: &[Tag]) -> Vec<Tag> {
+    tags.iter().filter(|t| t.depth == 0).copied().collect()
+}
+
+/// Object identifier, property identifier and optional array index
+fn check_property_reference(tags: &[Tag]) -> Result<usize, Violation> {
+    let ok = tags.len() >= 2
+        && tags[0].is_context_value(0)
+        && tags[0].len() == 4
+        && tags[1].is_context_value(1)
+        && (1..=4).contains(&tags[1].len());
+    if !ok {
+        return Err(Violation::ServiceParameters);
+    }
+    match tags.get(2) {
+        Some(tag) if tag.is_context_value(2) => {
+            if (1..=4).contains(&tag.len()) {
+                Ok(3)
+            } else {
+                Err(Violation::ServiceParameters)
+            }
+        }
+        _ => Ok(2),
+    }
+}
+
+/// Property value in opening/closing tag 3
+fn check_property_value(tags: &[Tag]) -> Result<usize, Violation> {
+    match tags {
+        [open, close, ..]
+            if open.context && open.number == 3 && open.kind == TagKind::Opening
+                && close.context && close.number == 3 && close.kind == TagKind::Closing => Ok(2),
+        _ => Err(Violation::ServiceParameters),
+    }
+}
+
+fn check_read_property_request(tags: &[Tag]) -> Result<(), Violation> {
+    let used = check_property_reference(tags)?;
+    (tags.len() == used).then_some(()).ok_or(Violation::ServiceParameters)
+}
+
+fn check_read_property_ack(tags: &[Tag]) -> Result<(), Violation> {
+    let tags = top_level(tags);
+    let used = check_property_reference(&tags)?;
+    let used = used + check_property_value(&tags[used..])?;
+    (tags.len() == used).then_some(()).ok_or(Violation::ServiceParameters)
+}
+
+fn check_write_property_request(tags: &[Tag]) -> Result<(), Violation> {
+    let tags = top_level(tags);
+    let used = check_property_reference(&tags)?;
+    let mut used = used + check_property_value(&tags[used..])?;
+    if let Some(priority) = tags.get(used).filter(|t| t.is_context_value(4)) {
+        if priority.len() != 1 {
+            return Err(Violation::ServiceParameters);
+        }
+        used += 1;
+    }
+    (tags.len() == used).then_some(()).ok_or(Violation::ServiceParameters)
+}
+
+/// Device identifier, Max_APDU, segmentation and vendor, all application tagged
+fn check_i_am(params: &[u8], tags: &[Tag]) -> Result<(), Violation> {
+    let ok = tags.len() == 4
+        && tags[0].is_application(12)
+        && tags[1].is_application(2)
+        && tags[2].is_application(9)
+        && tags[3].is_application(2)
+        // Object type in the top 10 bits of the identifier after the tag octet
+        && params
+            .get(1..5)
+            .is_some_and(|id| u32::from_be_bytes([id[0], id[1], id[2], id[3]]) >> 22 == OBJECT_TYPE_DEVICE);
+    ok.then_some(()).ok_or(Violation::ServiceParameters)
+}
+
+/// No parameters, or both limits
+fn check_who_is(tags: &[Tag]) -> Result<(), Violation> {
+    let ok = tags.is_empty()
+        || (tags.len() == 2
+            && tags[0].is_context_value(0)
+            && tags[1].is_context_value(1)
+            && (1..=3).contains(&tags[0].len())
+            && (1..=3).contains(&tags[1].len()));
+    ok.then_some(()).ok_or(Violation::ServiceParameters)
+}
+
+/// An offending frame kept as an example
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Example {
+    pub violation: Violation,
+    /// Length of the whole APDU
+    pub len: usize,
+    /// First `EXAMPLE_BYTES` of it
+    pub bytes: Vec<u8>,
+    pub at: Instant,
+}
+
+/// Conformance counters for one source
+#[derive(Debug, Clone)]
+pub struct SourceReport {
+    pub source: TalkerAddress,
+    pub checked: u64,
+    /// Per violation, in `Violation::ALL` order
+    pub violations: [u64; Violation::ALL.len()],
+    /// Newest last
+    pub examples: VecDeque<Example>,
+    pub last_seen: Instant,
+}
+
+impl SourceReport {
+    fn new(source: TalkerAddress, now: Instant) -> Self {
+        Self {
+            source,
+            checked: 0,
+            violations: [0; Violation::ALL.len()],
+            examples: VecDeque::new(),
+            last_seen: now,
+        }
+    }
+
+    pub fn nonconformant(&self) -> u64 {
+        self.violations.iter().sum()
+    }
+}
+
+/// Per-source conformance counters
+pub struct Analyzer {
+    sources: HashMap<TalkerAddress, SourceReport>,
+    started: Instant,
+}
+
+impl Analyzer {
+    pub fn new(now: Instant) -> Self {
+        Self { sources: HashMap::new(), started: now }
+    }
+
+    /// When analysis was switched on
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Check an APDU from `source`, counting and keeping it if it breaks the rules
+    pub fn check(&mut self, source: TalkerAddress, apdu: &[u8], now: Instant) -> Option<Violation> {
+        if !self.sources.contains_key(&source) && self.sources.len() >= MAX_ANALYZED_SOURCES {
+            if let Some(stalest) = self.sources.values().min_by_key(|s| s.last_seen).map(|s| s.source) {
+                self.sources.remove(&stalest);
+            }
+        }
+        let report = self.sources.entry(source).or_insert_with(|| SourceReport::new(source, now));
+        report.checked += 1;
+        report.last_seen = now;
+
+        let violation = check_apdu(apdu).err()?;
+        let index = Violation::ALL.iter().position(|&v| v == violation).unwrap_or(0);
+        report.violations[index] += 1;
+        if report.examples.len() >= EXAMPLES_PER_SOURCE {
+            report.examples.pop_front();
+        }
+        report.examples.push_back(Example {
+            violation,
+            len: apdu.len(),
+            bytes: apdu[..apdu.len().min(EXAMPLE_BYTES)].to_vec(),
+            at: now,
+        });
+        Some(violation)
+    }
+
+    /// All sources, most nonconformant frames first
+    pub fn reports(&self) -> Vec<SourceReport> {
+        let mut reports: Vec<SourceReport> = self.sources.values().cloned().collect();
+        reports.sort_by(|a, b| b.nonconformant().cmp(&a.nonconformant()).then(b.checked.cmp(&a.checked)));
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_well_formed_frames_pass() {
+        let frames: [&[u8]; 7] = [
+            // ReadProperty Device:1234 Object_Name, and its ACK
+            &[0x00, 0x05, 0x01, 0x0C, 0x0C, 0x02, 0x00, 0x04, 0xD2, 0x19, 0x4D],
+            &[0x30, 0x01, 0x0C, 0x0C, 0x02, 0x00, 0x04, 0xD2, 0x19, 0x4D, 0x3E, 0x75, 0x04, 0x00, 0x41, 0x48, 0x55, 0x3F],
+            // WriteProperty AV:1 Present_Value 72.5 at priority 8
+            &[0x00, 0x05, 0x02, 0x0F, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x19, 0x55, 0x3E, 0x44, 0x42, 0x91, 0x00, 0x00, 0x3F, 0x49, 0x08],
+            // I-Am Device:1234, 480, segmented-both, vendor 15
+            &[0x10, 0x00, 0xC4, 0x02, 0x00, 0x04, 0xD2, 0x22, 0x01, 0xE0, 0x91, 0x00, 0x21, 0x0F],
+            // Who-Is, global and ranged
+            &[0x10, 0x08],
+            &[0x10, 0x08, 0x09, 0x01, 0x1A, 0x03, 0xE8],
+            // Error: property, unknown-property
+            &[0x50, 0x01, 0x0C, 0x91, 0x02, 0x91, 0x20],
+        ];
+        for frame in frames {
+            assert_eq!(check_apdu(frame), Ok(()), "{:02X?}", frame);
+        }
+        assert_eq!(check_apdu(&[0x20, 0x01, 0x0F]), Ok(()));
+    }
+
+    #[test]
+    fn test_violations_detected() {
+        // Unknown PDU type, truncated SimpleACK
+        assert_eq!(check_apdu(&[0x90, 0x01, 0x02]), Err(Violation::UnknownPduType));
+        assert_eq!(check_apdu(&[0x20, 0x01]), Err(Violation::HeaderLength));
+        // Character string claiming 8 octets with 3 present
+        assert_eq!(check_apdu(&[0x10, 0x07, 0x75, 0x08, 0x00, 0x41, 0x42]), Err(Violation::TagOverrun));
+        // Opening tag 3 closed as 4
+        assert_eq!(check_apdu(&[0x10, 0x07, 0x3E, 0x21, 0x01, 0x4F]), Err(Violation::UnbalancedTags));
+        // Real with 3 octets
+        assert_eq!(check_apdu(&[0x10, 0x07, 0x43, 0x00, 0x00, 0x00]), Err(Violation::ApplicationLength));
+        // ReadProperty without the property identifier
+        assert_eq!(check_apdu(&[0x00, 0x05, 0x01, 0x0C, 0x0C, 0x02, 0x00, 0x04, 0xD2]), Err(Violation::ServiceParameters));
+        // Who-Is with only the low limit
+        assert_eq!(check_apdu(&[0x10, 0x08, 0x09, 0x01]), Err(Violation::ServiceParameters));
+        // I-Am for an Analog Input
+        assert_eq!(
+            check_apdu(&[0x10, 0x00, 0xC4, 0x00, 0x00, 0x00, 0x01, 0x22, 0x01, 0xE0, 0x91, 0x00, 0x21, 0x0F]),
+            Err(Violation::ServiceParameters)
+        );
+    }
+
+    #[test]
+    fn test_counts_and_examples_per_source() {
+        let now = Instant::now();
+        let mut analyzer = Analyzer::new(now);
+        let bad = [0x20, 0x01];
+        assert_eq!(analyzer.check(TalkerAddress::Mstp(5), &[0x10, 0x08], now), None);
+        for _ in 0..EXAMPLES_PER_SOURCE + 2 {
+            assert_eq!(analyzer.check(TalkerAddress::Mstp(7), &bad, now), Some(Violation::HeaderLength));
+        }
+
+        let reports = analyzer.reports();
+        assert_eq!(reports[0].source, TalkerAddress::Mstp(7));
+        assert_eq!((reports[0].checked, reports[0].nonconformant()), (EXAMPLES_PER_SOURCE as u64 + 2, EXAMPLES_PER_SOURCE as u64 + 2));
+        assert_eq!(reports[0].examples.len(), EXAMPLES_PER_SOURCE);
+        assert_eq!(reports[0].examples[0].bytes, bad);
+        assert_eq!((reports[1].checked, reports[1].nonconformant()), (1, 0));
+    }
+}
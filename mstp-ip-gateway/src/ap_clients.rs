@@ -0,0 +1,159 @@
+//! Stations connected to the configuration hotspot
+//!
+//! The soft AP's station list (MAC, RSSI) is joined with the DHCP server's
+//! lease table so the portal and display can show who is on the hotspot and
+//! which address they were given. A client can be kicked off with a
+//! deauthentication; it is free to reconnect if it still has the password.
+
+use esp_idf_svc::sys::{self, EspError};
+use log::info;
+use std::net::Ipv4Addr;
+
+/// Interface key of the default soft AP netif
+const AP_IFKEY: &std::ffi::CStr = c"WIFI_AP_DEF";
+
+/// Station associated with the soft AP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApClient {
+    pub mac: [u8; 6],
+    pub rssi: i8,
+    /// Address handed out by the DHCP server (None until it has a lease)
+    pub ip: Option<Ipv4Addr>,
+}
+
+/// List the stations associated with the soft AP and their DHCP leases
+pub fn list() -> Vec<ApClient> {
+    // SAFETY: wifi_sta_list_t is plain integers and byte arrays; zeroed is valid
+    let mut sta_list: sys::wifi_sta_list_t = unsafe { std::mem::zeroed() };
+    // SAFETY: fills the struct we own; fails harmlessly when the AP is down
+    if unsafe { sys::esp_wifi_ap_get_sta_list(&mut sta_list) } != sys::ESP_OK {
+        return Vec::new();
+    }
+    let stations = &sta_list.sta[..(sta_list.num.max(0) as usize).min(sta_list.sta.len())];
+
+    let mut pairs: Vec<sys::esp_netif_pair_mac_ip_t> = stations
+        .iter()
+        .map(|sta| {
+            // SAFETY: MAC/IP pair is plain integers; zeroed is valid
+            let mut pair: sys::esp_netif_pair_mac_ip_t = unsafe { std::mem::zeroed() };
+            pair.mac = sta.mac;
+            pair
+        })
+        .collect();
+    // SAFETY: the ifkey is a valid C string; a null handle is checked below
+    let netif = unsafe { sys::esp_netif_get_handle_from_ifkey(AP_IFKEY.as_ptr()) };
+    let leased = !netif.is_null()
+        && !pairs.is_empty()
+        // SAFETY: `pairs` holds exactly `len` entries for the DHCP server to fill in
+        && unsafe { sys::esp_netif_dhcps_get_clients_by_mac(netif, pairs.len() as i32, pairs.as_mut_ptr()) }
+            == sys::ESP_OK;
+
+    stations
+        .iter()
+        .zip(&pairs)
+        .map(|(sta, pair)| ApClient {
+            mac: sta.mac,
+            rssi: sta.rssi,
+            // lwIP keeps addresses in network byte order
+            ip: (leased && pair.ip.addr != 0).then(|| Ipv4Addr::from(pair.ip.addr.to_ne_bytes())),
+        })
+        .collect()
+}
+
+/// Lease time of the hotspot's DHCP server in minutes
+pub fn lease_minutes() -> Option<u32> {
+    // SAFETY: the ifkey is a valid C string; a null handle is checked below
+    let netif = unsafe { sys::esp_netif_get_handle_from_ifkey(AP_IFKEY.as_ptr()) };
+    if netif.is_null() {
+        return None;
+    }
+    let mut minutes: u32 = 0;
+    // SAFETY: the option value is a u32 and we pass its exact size
+    let err = unsafe {
+        sys::esp_netif_dhcps_option(
+            netif,
+            sys::esp_netif_dhcp_option_mode_t_ESP_NETIF_OP_GET,
+            sys::esp_netif_dhcp_option_id_t_ESP_NETIF_IP_ADDRESS_LEASE_TIME,
+            &mut minutes as *mut u32 as *mut core::ffi::c_void,
+            std::mem::size_of::<u32>() as u32,
+        )
+    };
+    (err == sys::ESP_OK).then_some(minutes)
+}
+
+/// Deauthenticate a client from the soft AP
+pub fn kick(mac: &[u8; 6]) -> Result<(), EspError> {
+    let mut aid: u16 = 0;
+    // SAFETY: `mac` is 6 bytes and `aid` is written by the call
+    unsafe {
+        EspError::convert(sys::esp_wifi_ap_get_sta_aid(mac.as_ptr(), &mut aid))?;
+        EspError::convert(sys::esp_wifi_deauth_sta(aid))?;
+    }
+    info!("Kicked hotspot client {} (AID {})", format_mac(mac), aid);
+    Ok(())
+}
+
+/// One-line summary for the display: the strongest client and how many more,
+/// e.g. "192.168.4.2 -58dBm +1" (MAC until the client has a lease)
+pub fn summary(clients: &[ApClient]) -> String {
+    let Some(best) = clients.iter().max_by_key(|c| c.rssi) else { return String::new() };
+    let who = best.ip.map(|ip| ip.to_string()).unwrap_or_else(|| format_mac(&best.mac));
+    match clients.len() - 1 {
+        0 => format!("{} {}dBm", who, best.rssi),
+        more => format!("{} {}dBm +{}", who, best.rssi, more),
+    }
+}
+
+/// "AA:BB:CC:DD:EE:FF"
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// Parse a MAC address written with ':' or '-' separators
+pub fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = s.trim().split([':', '-']);
+    for byte in mac.iter_mut() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mac_round_trip() {
+        let mac = [0x24, 0x0A, 0xC4, 0x01, 0xFE, 0x9B];
+        assert_eq!(format_mac(&mac), "24:0A:C4:01:FE:9B");
+        assert_eq!(parse_mac("24:0A:C4:01:FE:9B"), Some(mac));
+        assert_eq!(parse_mac(" 24-0a-c4-01-fe-9b "), Some(mac));
+    }
+
+    #[test]
+    fn test_parse_mac_rejects_malformed() {
+        assert_eq!(parse_mac(""), None);
+        assert_eq!(parse_mac("24:0A:C4:01:FE"), None);
+        assert_eq!(parse_mac("24:0A:C4:01:FE:9B:00"), None);
+        assert_eq!(parse_mac("240:A:C4:01:FE:9B"), None);
+        assert_eq!(parse_mac("24:0A:C4:01:FE:XZ"), None);
+    }
+
+    #[test]
+    fn test_summary_shows_strongest_client() {
+        let client = |last: u8, rssi: i8, ip: Option<Ipv4Addr>| ApClient { mac: [0x24, 0x0A, 0xC4, 0x01, 0xFE, last], rssi, ip };
+        assert_eq!(summary(&[]), "");
+        assert_eq!(summary(&[client(1, -70, None)]), "24:0A:C4:01:FE:01 -70dBm");
+        let clients = [
+            client(1, -70, Some(Ipv4Addr::new(192, 168, 4, 2))),
+            client(2, -58, Some(Ipv4Addr::new(192, 168, 4, 3))),
+            client(3, -81, None),
+        ];
+        assert_eq!(summary(&clients), "192.168.4.3 -58dBm +2");
+    }
+}
@@ -0,0 +1,480 @@
+//! Web portal user accounts and roles
+//!
+//! Accounts are checked with HTTP Basic authentication. Passwords are
+//! stored as PBKDF2-HMAC-SHA256 hashes (computed with the mbedtls
+//! implementation built into ESP-IDF). Since a browser resends the
+//! credentials with every request, logins that have already been checked
+//! are remembered for a while in a `LoginCache`. The portal stays open until the first admin account is created, so
+//! commissioning over the AP works out of the box.
+//!
+//! Scripts use API keys instead: `Authorization: Bearer <key>` on /api
+//! endpoints, each key limited to the scopes it was created with.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Maximum number of portal accounts
+pub const MAX_USERS: usize = 8;
+
+/// Maximum username length
+pub const MAX_USERNAME_LEN: usize = 32;
+
+/// Minimum password length
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// Salt length in bytes
+pub const SALT_LEN: usize = 16;
+
+/// SHA-256 digest length in bytes
+pub const HASH_LEN: usize = 32;
+
+/// PBKDF2 iterations (around a second on the ESP32, once per login thanks to the cache)
+const PBKDF2_ITERATIONS: u32 = 10_000;
+
+/// How long a checked login is remembered
+const LOGIN_CACHE_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Maximum number of API keys
+pub const MAX_API_KEYS: usize = 8;
+
+/// Random bytes in an API key (sent as 32 hex digits)
+const API_KEY_LEN: usize = 16;
+
+/// POST endpoints that change the configuration (prefix match)
+const CONFIG_API_PATHS: &[&str] = &["/api/config", "/api/wizard/", "/api/pair"];
+
+/// Access level of a portal account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Read-only access to status and diagnostics
+    Viewer,
+    /// Full access including configuration
+    Admin,
+}
+
+impl Role {
+    /// Whether this role grants at least the access of `required`
+    pub fn allows(self, required: Role) -> bool {
+        self == Role::Admin || required == Role::Viewer
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Role::Viewer => 0,
+            Role::Admin => 1,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Role::Viewer),
+            1 => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// Permission granted to an API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    /// GET endpoints: status, diagnostics, device lists
+    Read = 0,
+    /// Configuration changes
+    Config = 1,
+    /// Actions: scans, counter resets, kicking clients, fault injection
+    Control = 2,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 3] = [ApiScope::Read, ApiScope::Config, ApiScope::Control];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Config => "config",
+            ApiScope::Control => "control",
+        }
+    }
+
+    /// Scope an API request needs. Admin-only GETs count as configuration
+    /// reads; POSTs are configuration changes or actions.
+    pub fn required(is_get: bool, path: &str, role: Role) -> ApiScope {
+        if is_get {
+            if role == Role::Admin { ApiScope::Config } else { ApiScope::Read }
+        } else if CONFIG_API_PATHS.iter().any(|p| path.starts_with(p)) {
+            ApiScope::Config
+        } else {
+            ApiScope::Control
+        }
+    }
+}
+
+/// Set of API key scopes, stored as a bitmask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiScopes(u8);
+
+impl ApiScopes {
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & 0x07)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn allows(self, scope: ApiScope) -> bool {
+        self.0 & (1 << scope as u8) != 0
+    }
+
+    /// Comma-separated scope names
+    pub fn format(self) -> String {
+        ApiScope::ALL
+            .into_iter()
+            .filter(|scope| self.allows(*scope))
+            .map(ApiScope::as_str)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Parse a comma-separated list of scope names (at least one)
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut bits = 0;
+        for name in s.split(',').map(str::trim) {
+            let scope = ApiScope::ALL.into_iter().find(|scope| scope.as_str() == name)?;
+            bits |= 1 << scope as u8;
+        }
+        Some(Self(bits))
+    }
+}
+
+/// A portal account with its password hash
+#[derive(Debug, Clone)]
+pub struct UserAccount {
+    pub username: String,
+    pub role: Role,
+    pub salt: [u8; SALT_LEN],
+    pub hash: [u8; HASH_LEN],
+}
+
+impl UserAccount {
+    /// Create an account, hashing the password with a fresh random salt
+    pub fn new(username: &str, password: &str, role: Role) -> Result<Self, HashError> {
+        let salt = random_bytes::<SALT_LEN>();
+        let hash = hash_password(password, &salt)?;
+        Ok(Self { username: username.to_string(), role, salt, hash })
+    }
+
+    /// Check a password against the stored hash
+    pub fn verify(&self, password: &str) -> Result<bool, HashError> {
+        let hash = hash_password(password, &self.salt)?;
+        Ok(constant_time_eq(&hash, &self.hash))
+    }
+}
+
+/// PBKDF2 failed inside mbedtls (carries its error code)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashError(pub i32);
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "password hashing failed (mbedtls error -0x{:04X})", self.0.unsigned_abs())
+    }
+}
+
+impl std::error::Error for HashError {}
+
+/// Logins already verified, so requests resending the same Basic credentials
+/// skip PBKDF2. Only a keyed digest of the password is kept, under a key
+/// drawn at boot. An entry stops matching when the account's hash changes.
+pub struct LoginCache {
+    key: [u8; SALT_LEN],
+    entries: Vec<CachedLogin>,
+}
+
+struct CachedLogin {
+    username: String,
+    account_hash: [u8; HASH_LEN],
+    digest: [u8; HASH_LEN],
+    verified_at: Instant,
+}
+
+impl LoginCache {
+    pub fn new() -> Self {
+        Self { key: random_bytes::<SALT_LEN>(), entries: Vec::new() }
+    }
+
+    /// Whether this password was verified for this account within LOGIN_CACHE_TTL
+    pub fn contains(&self, user: &UserAccount, password: &str, now: Instant) -> bool {
+        let digest = self.digest(password);
+        self.entries.iter().any(|e| {
+            e.username == user.username
+                && e.account_hash == user.hash
+                && now.duration_since(e.verified_at) < LOGIN_CACHE_TTL
+                && constant_time_eq(&e.digest, &digest)
+        })
+    }
+
+    /// Remember a verified login, replacing the account's previous entry
+    pub fn insert(&mut self, user: &UserAccount, password: &str, now: Instant) {
+        let digest = self.digest(password);
+        self.entries.retain(|e| e.username != user.username && now.duration_since(e.verified_at) < LOGIN_CACHE_TTL);
+        if self.entries.len() >= MAX_USERS {
+            self.entries.remove(0);
+        }
+        self.entries.push(CachedLogin {
+            username: user.username.clone(),
+            account_hash: user.hash,
+            digest,
+            verified_at: now,
+        });
+    }
+
+    fn digest(&self, password: &str) -> [u8; HASH_LEN] {
+        let mut input = Vec::with_capacity(SALT_LEN + password.len());
+        input.extend_from_slice(&self.key);
+        input.extend_from_slice(password.as_bytes());
+        sha256(&input)
+    }
+}
+
+impl Default for LoginCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An API key. Only a salted hash is kept; the key itself is shown once.
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub name: String,
+    pub scopes: ApiScopes,
+    pub salt: [u8; SALT_LEN],
+    pub hash: [u8; HASH_LEN],
+}
+
+impl ApiKey {
+    /// Create a key with a fresh random secret; returns the key and the secret
+    pub fn generate(name: &str, scopes: ApiScopes) -> (Self, String) {
+        let secret: String = random_bytes::<API_KEY_LEN>().iter().map(|b| format!("{:02x}", b)).collect();
+        let salt = random_bytes::<SALT_LEN>();
+        let hash = hash_api_key(&secret, &salt);
+        (Self { name: name.to_string(), scopes, salt, hash }, secret)
+    }
+
+    /// Check a presented key against the stored hash
+    pub fn verify(&self, secret: &str) -> bool {
+        constant_time_eq(&hash_api_key(secret, &self.salt), &self.hash)
+    }
+}
+
+/// Random bytes from the hardware RNG
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut out = [0u8; N];
+    for chunk in out.chunks_mut(4) {
+        // SAFETY: esp_random() has no preconditions; with WiFi running it
+        // is backed by the hardware RNG
+        let r = unsafe { esp_idf_svc::sys::esp_random() };
+        chunk.copy_from_slice(&r.to_le_bytes()[..chunk.len()]);
+    }
+    out
+}
+
+fn constant_time_eq(a: &[u8; HASH_LEN], b: &[u8; HASH_LEN]) -> bool {
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether a username is acceptable (printable ASCII without ':' or spaces)
+pub fn valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= MAX_USERNAME_LEN
+        && username.bytes().all(|b| b.is_ascii_graphic() && b != b':')
+}
+
+/// PBKDF2-HMAC-SHA256 of the password
+fn hash_password(password: &str, salt: &[u8; SALT_LEN]) -> Result<[u8; HASH_LEN], HashError> {
+    let mut hash = [0u8; HASH_LEN];
+    // SAFETY: all buffers are valid for the lengths passed
+    let ret = unsafe {
+        esp_idf_svc::sys::mbedtls_pkcs5_pbkdf2_hmac_ext(
+            esp_idf_svc::sys::mbedtls_md_type_t_MBEDTLS_MD_SHA256,
+            password.as_ptr(),
+            password.len(),
+            salt.as_ptr(),
+            salt.len(),
+            PBKDF2_ITERATIONS,
+            HASH_LEN as u32,
+            hash.as_mut_ptr(),
+        )
+    };
+    if ret != 0 {
+        return Err(HashError(ret));
+    }
+    Ok(hash)
+}
+
+/// Single salted SHA-256 of an API key. Keys are 128 random bits, so
+/// stretching would add nothing but latency to every scripted request.
+fn hash_api_key(secret: &str, salt: &[u8; SALT_LEN]) -> [u8; HASH_LEN] {
+    let mut input = Vec::with_capacity(SALT_LEN + secret.len());
+    input.extend_from_slice(salt);
+    input.extend_from_slice(secret.as_bytes());
+    sha256(&input)
+}
+
+fn sha256(data: &[u8]) -> [u8; HASH_LEN] {
+    let mut out = [0u8; HASH_LEN];
+    // SAFETY: input and output buffers are valid for the given lengths;
+    // is224 = 0 selects SHA-256 (32-byte output)
+    unsafe {
+        esp_idf_svc::sys::mbedtls_sha256(data.as_ptr(), data.len(), out.as_mut_ptr(), 0);
+    }
+    out
+}
+
+/// Extract (username, password) from an `Authorization: Basic ...` header
+pub fn parse_basic_auth(header: &str) -> Option<(String, String)> {
+    let encoded = header.trim().strip_prefix("Basic ")?.trim();
+    let decoded = base64_decode(encoded)?;
+    let text = String::from_utf8(decoded).ok()?;
+    let (user, pass) = text.split_once(':')?;
+    Some((user.to_string(), pass.to_string()))
+}
+
+/// Extract the key from an `Authorization: Bearer ...` header
+pub fn parse_bearer_token(header: &str) -> Option<&str> {
+    let token = header.trim().strip_prefix("Bearer ")?.trim();
+    (!token.is_empty()).then_some(token)
+}
+
+/// Encode as standard base64 with padding
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let mut bytes = [0u8; 3];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let acc = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(acc >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode standard base64 (with optional padding)
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let data = input.trim_end_matches('=').as_bytes();
+    if data.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut acc = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            acc |= value(c)? << (18 - 6 * i);
+        }
+        let bytes = acc.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode("YWRtaW46c2VjcmV0").unwrap(), b"admin:secret");
+        assert_eq!(base64_decode("YQ==").unwrap(), b"a");
+        assert_eq!(base64_decode("YWI=").unwrap(), b"ab");
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert!(base64_decode("Y").is_none());
+        assert!(base64_decode("YW*=").is_none());
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b"admin:secret"), "YWRtaW46c2VjcmV0");
+        assert_eq!(base64_encode(b"a"), "YQ==");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_encode(b""), "");
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(base64_decode(&base64_encode(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn test_parse_basic_auth() {
+        assert_eq!(
+            parse_basic_auth("Basic dmlld2VyOnBhc3M6d29yZA=="),
+            Some(("viewer".to_string(), "pass:word".to_string()))
+        );
+        assert_eq!(parse_basic_auth("Bearer abc"), None);
+        assert_eq!(parse_basic_auth("Basic bm9jb2xvbg=="), None);
+    }
+
+    #[test]
+    fn test_roles_and_usernames() {
+        assert!(Role::Admin.allows(Role::Admin));
+        assert!(Role::Admin.allows(Role::Viewer));
+        assert!(Role::Viewer.allows(Role::Viewer));
+        assert!(!Role::Viewer.allows(Role::Admin));
+        assert_eq!(Role::from_u8(Role::Admin.to_u8()), Some(Role::Admin));
+        assert_eq!(Role::from_u8(7), None);
+
+        assert!(valid_username("facilities"));
+        assert!(!valid_username(""));
+        assert!(!valid_username("a:b"));
+        assert!(!valid_username("two words"));
+    }
+
+    #[test]
+    fn test_bearer_token_and_scopes() {
+        assert_eq!(parse_bearer_token("Bearer 0123abcd"), Some("0123abcd"));
+        assert_eq!(parse_bearer_token("Bearer  "), None);
+        assert_eq!(parse_bearer_token("Basic YWRtaW46c2VjcmV0"), None);
+
+        let scopes = ApiScopes::parse("read, control").unwrap();
+        assert!(scopes.allows(ApiScope::Read));
+        assert!(!scopes.allows(ApiScope::Config));
+        assert_eq!(scopes.format(), "read,control");
+        assert_eq!(ApiScopes::from_bits(scopes.bits()), scopes);
+        assert!(ApiScopes::parse("read,admin").is_none());
+        assert!(ApiScopes::parse("").is_none());
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(ApiScope::required(true, "/api/status", Role::Viewer), ApiScope::Read);
+        assert_eq!(ApiScope::required(true, "/api/debug/inject", Role::Admin), ApiScope::Config);
+        assert_eq!(ApiScope::required(false, "/api/config", Role::Admin), ApiScope::Config);
+        assert_eq!(ApiScope::required(false, "/api/wizard/check-address", Role::Admin), ApiScope::Config);
+        assert_eq!(ApiScope::required(false, "/api/scan", Role::Viewer), ApiScope::Control);
+        assert_eq!(ApiScope::required(false, "/api/reset-stats", Role::Admin), ApiScope::Control);
+    }
+}
@@ -0,0 +1,222 @@
+//! Automatic MS/TP station address selection
+//!
+//! Before joining the token ring the driver listens without transmitting.
+//! Every station heard as a frame source is in use, and Poll-For-Master
+//! frames that go unanswered mark addresses the ring's own masters found
+//! free. Once a full token rotation and some PFM activity have been seen, a
+//! free address in 0..=max_master is chosen and then watched for a while
+//! longer, so a station that only happened to be quiet is not duplicated.
+
+use std::time::{Duration, Instant};
+
+/// Give up waiting for a token rotation and PFM activity after this long
+pub const SURVEY_MAX_DURATION: Duration = Duration::from_secs(60);
+
+/// A bus with no frames at all for this long is treated as empty
+pub const SURVEY_QUIET_BUS: Duration = Duration::from_secs(10);
+
+/// How long a chosen address must stay silent before it is taken
+pub const SURVEY_VERIFY_TIME: Duration = Duration::from_secs(5);
+
+// MS/TP frame types seen during the survey (ASHRAE 135 Clause 9.3)
+const FRAME_TOKEN: u8 = 0x00;
+const FRAME_POLL_FOR_MASTER: u8 = 0x01;
+const FRAME_REPLY_TO_POLL_FOR_MASTER: u8 = 0x02;
+
+/// Highest master address
+const MAX_MASTER_ADDRESS: u8 = 127;
+
+/// Where the survey has got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurveyPhase {
+    /// Still learning which addresses are in use
+    Listening,
+    /// Watching the candidate for traffic before taking it
+    Verifying { candidate: u8, since: Instant },
+    /// Address chosen
+    Done(u8),
+    /// Every address up to max_master is in use
+    NoFreeAddress,
+}
+
+/// Passive survey of the MS/TP segment for a free master address
+#[derive(Debug)]
+pub struct AddressSurvey {
+    max_master: u8,
+    preferred: u8,
+    /// Addresses heard as a frame source
+    used: u128,
+    /// Addresses polled by another master without a reply
+    unanswered_polls: u128,
+    /// Destination of the last Poll-For-Master, until the next frame shows whether it replied
+    pending_poll: Option<u8>,
+    first_token_source: Option<u8>,
+    rotations: u32,
+    pfm_seen: bool,
+    started: Instant,
+    last_frame: Instant,
+    phase: SurveyPhase,
+}
+
+impl AddressSurvey {
+    /// Start a survey; `preferred` (usually the configured address) is kept if free
+    pub fn new(max_master: u8, preferred: u8, now: Instant) -> Self {
+        Self {
+            max_master: max_master.min(MAX_MASTER_ADDRESS),
+            preferred,
+            used: 0,
+            unanswered_polls: 0,
+            pending_poll: None,
+            first_token_source: None,
+            rotations: 0,
+            pfm_seen: false,
+            started: now,
+            last_frame: now,
+            phase: SurveyPhase::Listening,
+        }
+    }
+
+    /// Account for one valid frame heard on the bus
+    pub fn observe(&mut self, frame_type: u8, dest: u8, source: u8, now: Instant) {
+        self.last_frame = now;
+
+        if let Some(polled) = self.pending_poll.take() {
+            let replied = frame_type == FRAME_REPLY_TO_POLL_FOR_MASTER && source == polled;
+            if !replied {
+                self.unanswered_polls |= 1u128 << polled;
+            }
+        }
+
+        if source <= MAX_MASTER_ADDRESS {
+            self.used |= 1u128 << source;
+            self.unanswered_polls &= !(1u128 << source);
+        }
+
+        match frame_type {
+            FRAME_TOKEN => match self.first_token_source {
+                None => self.first_token_source = Some(source),
+                Some(first) if first == source => self.rotations += 1,
+                Some(_) => {}
+            },
+            FRAME_POLL_FOR_MASTER => {
+                self.pfm_seen = true;
+                if dest <= MAX_MASTER_ADDRESS {
+                    self.pending_poll = Some(dest);
+                }
+            }
+            _ => {}
+        }
+
+        if let SurveyPhase::Verifying { candidate, .. } = self.phase {
+            if source == candidate {
+                // Someone else already has it: go back and pick again
+                self.phase = SurveyPhase::Listening;
+            }
+        }
+    }
+
+    /// Advance the survey and return the current phase
+    pub fn poll(&mut self, now: Instant) -> SurveyPhase {
+        match self.phase {
+            SurveyPhase::Listening => {
+                let ring_seen = self.rotations > 0 && self.pfm_seen;
+                let quiet = now.saturating_duration_since(self.last_frame) >= SURVEY_QUIET_BUS;
+                let timed_out = now.saturating_duration_since(self.started) >= SURVEY_MAX_DURATION;
+                if ring_seen || quiet || timed_out {
+                    self.phase = match self.pick_candidate() {
+                        Some(candidate) => SurveyPhase::Verifying { candidate, since: now },
+                        None => SurveyPhase::NoFreeAddress,
+                    };
+                }
+            }
+            SurveyPhase::Verifying { candidate, since } => {
+                if now.saturating_duration_since(since) >= SURVEY_VERIFY_TIME {
+                    self.phase = SurveyPhase::Done(candidate);
+                }
+            }
+            SurveyPhase::Done(_) | SurveyPhase::NoFreeAddress => {}
+        }
+        self.phase
+    }
+
+    /// Masters heard during the survey (seeds the driver's ring knowledge)
+    pub fn used_addresses(&self) -> u128 {
+        self.used
+    }
+
+    /// The preferred address if free, then one other masters already poll
+    /// for, then the lowest free address
+    fn pick_candidate(&self) -> Option<u8> {
+        let is_free = |addr: u8| addr <= self.max_master && (self.used >> addr) & 1 == 0;
+        if is_free(self.preferred) {
+            return Some(self.preferred);
+        }
+        (0..=self.max_master)
+            .find(|&addr| is_free(addr) && (self.unanswered_polls >> addr) & 1 == 1)
+            .or_else(|| (0..=self.max_master).find(|&addr| is_free(addr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One token rotation among `masters`, each polling the address above it
+    fn rotation(survey: &mut AddressSurvey, masters: &[u8], now: Instant) {
+        for (i, &source) in masters.iter().enumerate() {
+            let next = masters[(i + 1) % masters.len()];
+            survey.observe(FRAME_POLL_FOR_MASTER, source + 1, source, now);
+            survey.observe(FRAME_TOKEN, next, source, now);
+        }
+    }
+
+    #[test]
+    fn test_keeps_preferred_address_when_free() {
+        let start = Instant::now();
+        let mut survey = AddressSurvey::new(127, 3, start);
+        rotation(&mut survey, &[1, 2, 10], start);
+        rotation(&mut survey, &[1, 2, 10], start);
+
+        assert_eq!(survey.poll(start), SurveyPhase::Verifying { candidate: 3, since: start });
+        assert_eq!(survey.poll(start + SURVEY_VERIFY_TIME), SurveyPhase::Done(3));
+        assert_eq!(survey.used_addresses(), (1 << 1) | (1 << 2) | (1 << 10));
+    }
+
+    #[test]
+    fn test_avoids_duplicate_and_prefers_polled_address() {
+        let start = Instant::now();
+        let mut survey = AddressSurvey::new(127, 6, start);
+        rotation(&mut survey, &[5, 6, 20], start);
+        assert_eq!(survey.poll(start), SurveyPhase::Listening);
+        rotation(&mut survey, &[5, 6, 20], start);
+
+        // 6 is taken; 7 was polled by master 6 without a reply, so it beats 0
+        assert_eq!(survey.poll(start), SurveyPhase::Verifying { candidate: 7, since: start });
+    }
+
+    #[test]
+    fn test_collision_during_verification_picks_again() {
+        let start = Instant::now();
+        let mut survey = AddressSurvey::new(127, 5, start);
+        rotation(&mut survey, &[1, 2], start);
+        rotation(&mut survey, &[1, 2], start);
+        assert!(matches!(survey.poll(start), SurveyPhase::Verifying { candidate: 5, .. }));
+
+        // A station at 5 that was quiet so far speaks up
+        survey.observe(FRAME_REPLY_TO_POLL_FOR_MASTER, 1, 5, start);
+        assert_eq!(survey.poll(start), SurveyPhase::Verifying { candidate: 3, since: start });
+    }
+
+    #[test]
+    fn test_quiet_bus_and_full_range() {
+        let start = Instant::now();
+        let mut survey = AddressSurvey::new(127, 7, start);
+        assert_eq!(survey.poll(start), SurveyPhase::Listening);
+        assert!(matches!(survey.poll(start + SURVEY_QUIET_BUS), SurveyPhase::Verifying { candidate: 7, .. }));
+
+        let mut full = AddressSurvey::new(3, 1, start);
+        rotation(&mut full, &[0, 1, 2, 3], start);
+        rotation(&mut full, &[0, 1, 2, 3], start);
+        assert_eq!(full.poll(start), SurveyPhase::NoFreeAddress);
+    }
+}
@@ -0,0 +1,329 @@
+//! Encrypted configuration backup
+//!
+//! The plain JSON export leaves out the WiFi and AP passwords. A backup
+//! carries the complete configuration, secrets included, encrypted with
+//! AES-256-GCM under a key derived from an operator passphrase
+//! (PBKDF2-HMAC-SHA256), so it can be moved between units safely.
+//!
+//! The configuration is stored as the same `key=value&...` form encoding
+//! the config page submits, so a restore goes through `parse_config_form`
+//! and gets the same validation as a manual edit. The blob is base64 text:
+//!
+//! ```text
+//! "BMCB" | version (1) | salt (16) | nonce (12) | ciphertext | tag (16)
+//! ```
+//!
+//! The header bytes are authenticated as additional data.
+
+use esp_idf_svc::sys;
+
+use crate::auth::{base64_decode, base64_encode, random_bytes};
+use crate::compat;
+use crate::config::{format_network_list, format_supervisory_stations, GatewayConfig};
+
+/// Minimum passphrase length
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// Suggested file name for downloads
+pub const BACKUP_FILE_NAME: &str = "bacman-config.bak";
+
+const MAGIC: &[u8; 4] = b"BMCB";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+
+/// PBKDF2 iterations (around a second on the ESP32, once per export/import)
+const KDF_ITERATIONS: u32 = 10_000;
+
+/// Serialize every configuration setting, passwords included, in the
+/// config page's form encoding
+pub fn config_form(config: &GatewayConfig) -> String {
+    let flag = |on: bool| if on { "1" } else { "0" };
+    let fields = [
+        ("wifi_ssid", config.wifi_ssid.clone()),
+        ("wifi_pass", config.wifi_password.clone()),
+        ("ap_ssid", config.ap_ssid.clone()),
+        ("ap_pass", config.ap_password.clone()),
+        ("ap_sta", flag(config.ap_with_sta).to_string()),
+        ("ap_timeout", config.ap_timeout_minutes.to_string()),
+        ("mstp_slave", flag(config.mstp_slave_mode).to_string()),
+        ("mstp_addr", config.mstp_address.to_string()),
+        ("mstp_max", config.mstp_max_master.to_string()),
+        ("mstp_baud", config.mstp_baud_rate.to_string()),
+        ("mstp_net", config.mstp_network.to_string()),
+        ("mstp_auto", flag(config.mstp_auto_address).to_string()),
+        ("mstp_pfm", config.mstp_pfm_aggressiveness.to_string()),
+        ("mstp_npdu", config.mstp_max_npdu.to_string()),
+        ("bp_high", config.backpressure_high.to_string()),
+        ("bp_low", config.backpressure_low.to_string()),
+        ("ip_port", config.bacnet_ip_port.to_string()),
+        ("ip_net", config.ip_network.to_string()),
+        ("ip_learn", flag(config.ip_network_learn).to_string()),
+        ("bip_mode", if config.bip_multicast_enabled { "multicast" } else { "broadcast" }.to_string()),
+        ("bip_group", config.bip_multicast_group.to_string()),
+        ("bcast_form", config.bip_broadcast_form.as_str().to_string()),
+        ("sup_station", format_supervisory_stations(&config.supervisory_stations)),
+        ("compat", compat::format_rules(&config.compat_rules)),
+        ("dup_suppress", flag(config.suppress_on_duplicate_network).to_string()),
+        ("whois_filter", flag(config.whois_filter_enabled).to_string()),
+        ("ann_scope", config.announce_scope.as_str().to_string()),
+        ("ann_nets", format_network_list(&config.announce_networks)),
+        ("npdu_hops", config.npdu_hop_count.to_string()),
+        ("npdu_prio", config.npdu_priority.as_str().to_string()),
+        ("npdu_lenient", flag(config.npdu_lenient).to_string()),
+        ("bbmd_addr", config.bbmd_address.map(|a| a.to_string()).unwrap_or_default()),
+        ("sbvll_drop", flag(config.secure_bvll_drop).to_string()),
+        ("hb_enabled", flag(config.heartbeat_enabled).to_string()),
+        ("hb_url", config.heartbeat_url.clone()),
+        ("hb_interval", config.heartbeat_interval_secs.to_string()),
+        ("pub_port", config.public_status_port.to_string()),
+        ("con_port", config.remote_console_port.to_string()),
+        ("site_name", config.site_name.clone()),
+        ("wh_enabled", flag(config.webhook_enabled).to_string()),
+        ("wh_url", config.webhook_url.clone()),
+        ("wh_scan_h", config.webhook_scan_hours.to_string()),
+        ("wh_err_thr", config.webhook_error_threshold.to_string()),
+        ("ntp_server", config.ntp_server.clone()),
+        ("timezone", config.timezone.clone()),
+        ("life_stats", flag(config.lifetime_stats_enabled).to_string()),
+        ("evt_spill", flag(config.event_spill_enabled).to_string()),
+        ("log_json", flag(config.log_json).to_string()),
+        ("wp_pin", config.write_protect_pin.to_string()),
+        ("fo_role", config.failover_role.as_str().to_string()),
+        ("fo_peer", config.failover_peer.map(|a| a.to_string()).unwrap_or_default()),
+        ("local_dev", flag(config.local_device_enabled).to_string()),
+        ("dev_inst", config.device_instance.to_string()),
+        ("dev_name", config.device_name.clone()),
+        ("dev_loc", config.device_location.clone()),
+        ("dev_desc", config.device_description.clone()),
+        ("dev_services", config.device_services.format()),
+    ];
+    let mut form: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, urlencoding::encode(value)))
+        .collect();
+    // Empty means "derive from the MAC"; leave the target unit's own
+    if !config.device_serial_number.is_empty() {
+        form.push(format!("dev_serial={}", urlencoding::encode(&config.device_serial_number)));
+    }
+    form.join("&")
+}
+
+/// Encrypt the full configuration into a base64 backup blob
+pub fn export_config(config: &GatewayConfig, passphrase: &str) -> anyhow::Result<String> {
+    check_passphrase(passphrase)?;
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(VERSION);
+    header.extend_from_slice(&random_bytes::<SALT_LEN>());
+    header.extend_from_slice(&random_bytes::<NONCE_LEN>());
+    let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let key = derive_key(passphrase, salt)?;
+    let plaintext = config_form(config);
+    let (ciphertext, tag) = gcm_encrypt(&key, nonce, &header, plaintext.as_bytes())?;
+
+    let mut blob = header;
+    blob.extend_from_slice(&ciphertext);
+    blob.extend_from_slice(&tag);
+    Ok(base64_encode(&blob))
+}
+
+/// Decrypt a backup blob back into config form encoding. A wrong
+/// passphrase and a damaged blob both fail authentication.
+pub fn import_config(blob: &str, passphrase: &str) -> anyhow::Result<String> {
+    check_passphrase(passphrase)?;
+
+    let compact: String = blob.chars().filter(|c| !c.is_whitespace()).collect();
+    let data = base64_decode(&compact).ok_or_else(|| anyhow::anyhow!("Backup is not valid base64"))?;
+    if data.len() < HEADER_LEN + TAG_LEN || !data.starts_with(MAGIC) {
+        anyhow::bail!("Not a BACman configuration backup");
+    }
+    if data[MAGIC.len()] != VERSION {
+        anyhow::bail!("Unsupported backup version {}", data[MAGIC.len()]);
+    }
+
+    let (header, rest) = data.split_at(HEADER_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let key = derive_key(passphrase, salt)?;
+    let plaintext = gcm_decrypt(&key, nonce, header, ciphertext, tag)?;
+    String::from_utf8(plaintext).map_err(|_| anyhow::anyhow!("Backup contents are corrupt"))
+}
+
+fn check_passphrase(passphrase: &str) -> anyhow::Result<()> {
+    if passphrase.len() < MIN_PASSPHRASE_LEN {
+        anyhow::bail!("Passphrase must be at least {} characters", MIN_PASSPHRASE_LEN);
+    }
+    Ok(())
+}
+
+/// PBKDF2-HMAC-SHA256 of the passphrase
+fn derive_key(passphrase: &str, salt: &[u8]) -> anyhow::Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    // SAFETY: all buffers are valid for the lengths passed
+    let ret = unsafe {
+        sys::mbedtls_pkcs5_pbkdf2_hmac_ext(
+            sys::mbedtls_md_type_t_MBEDTLS_MD_SHA256,
+            passphrase.as_ptr(),
+            passphrase.len(),
+            salt.as_ptr(),
+            salt.len(),
+            KDF_ITERATIONS,
+            KEY_LEN as u32,
+            key.as_mut_ptr(),
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("Key derivation failed ({})", ret);
+    }
+    Ok(key)
+}
+
+/// AES-GCM context that is freed on drop
+struct Gcm(sys::mbedtls_gcm_context);
+
+impl Gcm {
+    fn new(key: &[u8; KEY_LEN]) -> anyhow::Result<Self> {
+        // SAFETY: mbedtls_gcm_init() fully initializes the zeroed context
+        let mut gcm = Gcm(unsafe { std::mem::zeroed() });
+        unsafe { sys::mbedtls_gcm_init(&mut gcm.0) };
+        // SAFETY: the context is initialized and the key is KEY_LEN bytes
+        let ret = unsafe {
+            sys::mbedtls_gcm_setkey(&mut gcm.0, sys::mbedtls_cipher_id_t_MBEDTLS_CIPHER_ID_AES, key.as_ptr(), (KEY_LEN * 8) as u32)
+        };
+        if ret != 0 {
+            anyhow::bail!("AES key setup failed ({})", ret);
+        }
+        Ok(gcm)
+    }
+}
+
+impl Drop for Gcm {
+    fn drop(&mut self) {
+        // SAFETY: the context was initialized in new()
+        unsafe { sys::mbedtls_gcm_free(&mut self.0) };
+    }
+}
+
+fn gcm_encrypt(key: &[u8; KEY_LEN], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> anyhow::Result<(Vec<u8>, [u8; TAG_LEN])> {
+    let mut gcm = Gcm::new(key)?;
+    let mut ciphertext = vec![0u8; plaintext.len()];
+    let mut tag = [0u8; TAG_LEN];
+    // SAFETY: output has the input's length; all other buffers are valid
+    // for the lengths passed
+    let ret = unsafe {
+        sys::mbedtls_gcm_crypt_and_tag(
+            &mut gcm.0,
+            sys::MBEDTLS_GCM_ENCRYPT as i32,
+            plaintext.len(),
+            nonce.as_ptr(),
+            nonce.len(),
+            aad.as_ptr(),
+            aad.len(),
+            plaintext.as_ptr(),
+            ciphertext.as_mut_ptr(),
+            TAG_LEN,
+            tag.as_mut_ptr(),
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("Encryption failed ({})", ret);
+    }
+    Ok((ciphertext, tag))
+}
+
+fn gcm_decrypt(key: &[u8; KEY_LEN], nonce: &[u8], aad: &[u8], ciphertext: &[u8], tag: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut gcm = Gcm::new(key)?;
+    let mut plaintext = vec![0u8; ciphertext.len()];
+    // SAFETY: output has the input's length; all other buffers are valid
+    // for the lengths passed
+    let ret = unsafe {
+        sys::mbedtls_gcm_auth_decrypt(
+            &mut gcm.0,
+            ciphertext.len(),
+            nonce.as_ptr(),
+            nonce.len(),
+            aad.as_ptr(),
+            aad.len(),
+            tag.as_ptr(),
+            tag.len(),
+            ciphertext.as_ptr(),
+            plaintext.as_mut_ptr(),
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("Wrong passphrase or damaged backup");
+    }
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AnnounceScope, BroadcastForm};
+    use crate::failover::FailoverRole;
+    use crate::local_device::ServiceWhitelist;
+    use crate::npdu::NetworkPriority;
+    use crate::web::parse_config_form;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    #[test]
+    fn test_config_form_round_trips_through_parser() {
+        let mut config = GatewayConfig::default();
+        config.wifi_ssid = "Plant Room & Co".to_string();
+        config.wifi_password = "p@ss word=1".to_string();
+        config.ap_password = "another-secret".to_string();
+        config.mstp_slave_mode = true;
+        config.mstp_address = 200;
+        config.mstp_max_master = 90;
+        config.mstp_baud_rate = 76800;
+        config.mstp_network = 2001;
+        config.ip_network = 1001;
+        config.bip_multicast_enabled = true;
+        config.bip_broadcast_form = BroadcastForm::Both;
+        config.npdu_hop_count = 16;
+        config.npdu_priority = NetworkPriority::Urgent;
+        config.supervisory_stations = vec![SocketAddr::from(([10, 0, 0, 5], 47809)), SocketAddr::from(([10, 0, 0, 6], 47808))];
+        config.announce_scope = AnnounceScope::Ip;
+        config.announce_networks = vec![300, 400];
+        config.compat_rules = compat::parse_rules("10.0.5.0/24=niagara, 10.0.9.4=jci-cct").unwrap();
+        config.bbmd_address = Some(Ipv4Addr::new(10, 0, 0, 1));
+        config.heartbeat_enabled = true;
+        config.heartbeat_url = "https://fleet.example.com/hb?site=a&b=c".to_string();
+        config.remote_console_port = 2323;
+        config.timezone = "EST5EDT,M3.2.0,M11.1.0".to_string();
+        config.log_json = true;
+        config.device_instance = 123456;
+        config.device_location = "Level 2, riser".to_string();
+        config.device_serial_number = "SN-0042".to_string();
+        config.device_services = ServiceWhitelist::parse("read-property, who-is").unwrap();
+        config.write_protect_pin = 32;
+        config.failover_role = FailoverRole::Standby;
+        config.failover_peer = Some(Ipv4Addr::new(10, 0, 0, 2));
+
+        let mut restored = GatewayConfig::default();
+        assert!(parse_config_form(&config_form(&config), &mut restored).is_empty());
+        assert_eq!(restored, config);
+    }
+
+    #[test]
+    fn test_import_rejects_malformed_blobs() {
+        assert!(import_config("not base64!", "passphrase").is_err());
+        assert!(import_config(&base64_encode(b"BMCB\x01short"), "passphrase").is_err());
+        let mut blob = b"XXXX\x01".to_vec();
+        blob.resize(HEADER_LEN + TAG_LEN + 8, 0);
+        assert!(import_config(&base64_encode(&blob), "passphrase").is_err());
+        blob[..4].copy_from_slice(MAGIC);
+        blob[4] = 2;
+        assert!(import_config(&base64_encode(&blob), "passphrase").is_err());
+        assert!(import_config("", "short").is_err());
+    }
+}
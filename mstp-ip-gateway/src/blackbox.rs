@@ -0,0 +1,511 @@
+//! Blackbox recorder for the lead-up to a reset
+//!
+//! The event log and lifetime statistics say that the gateway restarted, but
+//! after a watchdog reset nothing says what it was doing. The blackbox keeps
+//! the last few events, routed frame summaries and free-heap samples in RTC
+//! slow memory, which software, panic and watchdog resets leave intact. The
+//! next boot reads the previous recording back before starting a new one;
+//! after an unexpected reset it is saved to NVS as the crash report, so it
+//! also survives later power cycles.
+
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::events::{EventCategory, Severity};
+use crate::lifetime::RebootReason;
+
+/// Events kept (state transitions from the event log, panics)
+pub const EVENT_SLOTS: usize = 24;
+
+/// Routed frame summaries kept
+pub const FRAME_SLOTS: usize = 16;
+
+/// Free-heap samples kept
+pub const HEAP_SLOTS: usize = 12;
+
+/// Longest event text kept (longer messages are cut)
+pub const EVENT_TEXT_LEN: usize = 40;
+
+/// How often the main loop samples the free heap
+pub const HEAP_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Encoded entry sizes: kind and uptime, then the record
+const EVENT_RECORD_LEN: usize = 1 + 4 + 3 + EVENT_TEXT_LEN;
+const FRAME_RECORD_LEN: usize = 1 + 4 + 11;
+const HEAP_RECORD_LEN: usize = 1 + 4 + 4;
+
+/// Largest encoded crash report
+pub const MAX_REPORT_LEN: usize =
+    2 + EVENT_SLOTS * EVENT_RECORD_LEN + FRAME_SLOTS * FRAME_RECORD_LEN + HEAP_SLOTS * HEAP_RECORD_LEN;
+
+/// Marks RTC memory that holds a recording rather than power-on noise
+const MAGIC: u32 = 0xB1AC_B0C5;
+
+/// Crash report encoding version
+const REPORT_VERSION: u8 = 1;
+
+/// Which side a routed frame arrived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSource {
+    Mstp(u8),
+    Ip(SocketAddrV4),
+}
+
+/// One routed frame, reduced to what identifies it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameSummary {
+    pub source: FrameSource,
+    pub len: u16,
+    /// APDU type (upper nibble of the first octet); None for network layer messages
+    pub apdu_type: Option<u8>,
+    pub service: Option<u8>,
+}
+
+impl FrameSummary {
+    pub fn new(source: FrameSource, len: usize, apdu: Option<&[u8]>) -> Self {
+        let apdu_type = apdu.and_then(|a| a.first()).map(|b| b >> 4);
+        let service = match (apdu_type, apdu) {
+            (Some(0), Some(a)) => a.get(3).copied(),
+            (Some(1), Some(a)) => a.get(1).copied(),
+            (Some(2 | 3 | 5), Some(a)) => a.get(2).copied(),
+            _ => None,
+        };
+        Self { source, len: len.min(u16::MAX as usize) as u16, apdu_type, service }
+    }
+}
+
+impl fmt::Display for FrameSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source {
+            FrameSource::Mstp(mac) => write!(f, "from MS/TP {}", mac)?,
+            FrameSource::Ip(addr) => write!(f, "from IP {}", addr)?,
+        }
+        write!(f, ", {} bytes, ", self.len)?;
+        let kind = match self.apdu_type {
+            None => return write!(f, "network message"),
+            Some(0) => "confirmed request",
+            Some(1) => "unconfirmed request",
+            Some(2) => "simple ACK",
+            Some(3) => "complex ACK",
+            Some(4) => "segment ACK",
+            Some(5) => "error",
+            Some(6) => "reject",
+            Some(7) => "abort",
+            Some(_) => "unknown PDU",
+        };
+        match self.service {
+            Some(service) => write!(f, "{} (service {})", kind, service),
+            None => write!(f, "{}", kind),
+        }
+    }
+}
+
+/// What one blackbox entry holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Event { category: EventCategory, severity: Severity, text: String },
+    Frame(FrameSummary),
+    Heap { free: u32 },
+}
+
+/// A blackbox entry with the uptime it was recorded at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub uptime_ms: u32,
+    pub record: Record,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct EventSlot {
+    uptime_ms: u32,
+    category: u8,
+    severity: u8,
+    len: u8,
+    text: [u8; EVENT_TEXT_LEN],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct FrameSlot {
+    uptime_ms: u32,
+    /// 0 = MS/TP (`mac`), 1 = IP (`ip`, `port`)
+    source: u8,
+    mac: u8,
+    len: u16,
+    ip: [u8; 4],
+    port: u16,
+    /// 0xFF = none
+    apdu_type: u8,
+    service: u8,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct HeapSlot {
+    uptime_ms: u32,
+    free: u32,
+}
+
+/// Fixed ring of plain-data slots, valid for any bit pattern
+#[repr(C)]
+struct Ring<T: Copy, const N: usize> {
+    next: u32,
+    count: u32,
+    slots: [T; N],
+}
+
+impl<T: Copy, const N: usize> Ring<T, N> {
+    const fn new(empty: T) -> Self {
+        Self { next: 0, count: 0, slots: [empty; N] }
+    }
+
+    fn push(&mut self, value: T) {
+        let index = self.next as usize % N;
+        self.slots[index] = value;
+        self.next = ((index + 1) % N) as u32;
+        self.count = (self.count + 1).min(N as u32);
+    }
+
+    fn is_sane(&self) -> bool {
+        (self.next as usize) < N && self.count as usize <= N
+    }
+
+    /// Slots in recording order, oldest first
+    fn iter(&self) -> impl Iterator<Item = &T> {
+        let count = self.count as usize;
+        let start = (self.next as usize + N - count) % N;
+        (0..count).map(move |k| &self.slots[(start + k) % N])
+    }
+}
+
+/// The recording as laid out in RTC memory
+#[repr(C)]
+pub struct BlackBox {
+    magic: u32,
+    events: Ring<EventSlot, EVENT_SLOTS>,
+    frames: Ring<FrameSlot, FRAME_SLOTS>,
+    heap: Ring<HeapSlot, HEAP_SLOTS>,
+}
+
+impl BlackBox {
+    pub const fn new() -> Self {
+        Self {
+            magic: MAGIC,
+            events: Ring::new(EventSlot { uptime_ms: 0, category: 0, severity: 0, len: 0, text: [0; EVENT_TEXT_LEN] }),
+            frames: Ring::new(FrameSlot {
+                uptime_ms: 0,
+                source: 0,
+                mac: 0,
+                len: 0,
+                ip: [0; 4],
+                port: 0,
+                apdu_type: 0,
+                service: 0,
+            }),
+            heap: Ring::new(HeapSlot { uptime_ms: 0, free: 0 }),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.events.is_sane() && self.frames.is_sane() && self.heap.is_sane()
+    }
+
+    pub fn record_event(&mut self, uptime_ms: u32, category: EventCategory, severity: Severity, message: &str) {
+        let mut end = message.len().min(EVENT_TEXT_LEN);
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut text = [0; EVENT_TEXT_LEN];
+        text[..end].copy_from_slice(&message.as_bytes()[..end]);
+        self.events.push(EventSlot { uptime_ms, category: category as u8, severity: severity as u8, len: end as u8, text });
+    }
+
+    pub fn record_frame(&mut self, uptime_ms: u32, frame: &FrameSummary) {
+        let (source, mac, ip, port) = match frame.source {
+            FrameSource::Mstp(mac) => (0, mac, [0; 4], 0),
+            FrameSource::Ip(addr) => (1, 0, addr.ip().octets(), addr.port()),
+        };
+        self.frames.push(FrameSlot {
+            uptime_ms,
+            source,
+            mac,
+            len: frame.len,
+            ip,
+            port,
+            apdu_type: frame.apdu_type.unwrap_or(0xFF),
+            service: frame.service.unwrap_or(0xFF),
+        });
+    }
+
+    pub fn record_heap(&mut self, uptime_ms: u32, free: u32) {
+        self.heap.push(HeapSlot { uptime_ms, free });
+    }
+
+    /// Everything recorded, oldest first; None if the memory holds no recording
+    pub fn entries(&self) -> Option<Vec<Entry>> {
+        if !self.is_valid() {
+            return None;
+        }
+        let events = self.events.iter().map(|e| {
+            let len = usize::from(e.len).min(EVENT_TEXT_LEN);
+            Entry {
+                uptime_ms: e.uptime_ms,
+                record: Record::Event {
+                    category: EventCategory::from_u8(e.category),
+                    severity: Severity::from_u8(e.severity),
+                    text: String::from_utf8_lossy(&e.text[..len]).into_owned(),
+                },
+            }
+        });
+        let frames = self.frames.iter().map(|f| {
+            let source = match f.source {
+                0 => FrameSource::Mstp(f.mac),
+                _ => FrameSource::Ip(SocketAddrV4::new(Ipv4Addr::from(f.ip), f.port)),
+            };
+            let apdu_type = (f.apdu_type != 0xFF).then_some(f.apdu_type);
+            let service = (f.service != 0xFF).then_some(f.service);
+            Entry { uptime_ms: f.uptime_ms, record: Record::Frame(FrameSummary { source, len: f.len, apdu_type, service }) }
+        });
+        let heap = self.heap.iter().map(|h| Entry { uptime_ms: h.uptime_ms, record: Record::Heap { free: h.free } });
+
+        let mut entries: Vec<Entry> = events.chain(frames).chain(heap).collect();
+        entries.sort_by_key(|e| e.uptime_ms);
+        Some(entries)
+    }
+}
+
+impl Default for BlackBox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A previous boot's recording, kept after an unexpected reset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub reason: RebootReason,
+    pub entries: Vec<Entry>,
+}
+
+impl CrashReport {
+    /// Uptime of the last entry, i.e. roughly when the reset happened
+    pub fn last_uptime_ms(&self) -> u32 {
+        self.entries.last().map(|e| e.uptime_ms).unwrap_or(0)
+    }
+
+    /// Encode for NVS
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![REPORT_VERSION, self.reason as u8];
+        for entry in &self.entries {
+            match &entry.record {
+                Record::Event { category, severity, text } => {
+                    out.push(0);
+                    out.extend_from_slice(&entry.uptime_ms.to_be_bytes());
+                    let text = &text.as_bytes()[..text.len().min(EVENT_TEXT_LEN)];
+                    out.extend_from_slice(&[*category as u8, *severity as u8, text.len() as u8]);
+                    out.extend_from_slice(text);
+                }
+                Record::Frame(frame) => {
+                    out.push(1);
+                    out.extend_from_slice(&entry.uptime_ms.to_be_bytes());
+                    match frame.source {
+                        FrameSource::Mstp(mac) => out.extend_from_slice(&[0, mac, 0, 0, 0, 0, 0]),
+                        FrameSource::Ip(addr) => {
+                            out.push(1);
+                            out.extend_from_slice(&addr.ip().octets());
+                            out.extend_from_slice(&addr.port().to_be_bytes());
+                        }
+                    }
+                    out.extend_from_slice(&frame.len.to_be_bytes());
+                    out.push(frame.apdu_type.unwrap_or(0xFF));
+                    out.push(frame.service.unwrap_or(0xFF));
+                }
+                Record::Heap { free } => {
+                    out.push(2);
+                    out.extend_from_slice(&entry.uptime_ms.to_be_bytes());
+                    out.extend_from_slice(&free.to_be_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    /// Decode what `encode` stored; None if it is damaged or from another version
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (&version, rest) = data.split_first()?;
+        let (&reason, mut rest) = rest.split_first()?;
+        if version != REPORT_VERSION {
+            return None;
+        }
+        let u32_at = |b: &[u8], at: usize| Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?));
+        let mut entries = Vec::new();
+        while let Some((&kind, body)) = rest.split_first() {
+            let uptime_ms = u32_at(body, 0)?;
+            let body = &body[4..];
+            let (record, used) = match kind {
+                0 => {
+                    let header = body.get(..3)?;
+                    let len = usize::from(header[2]);
+                    let text = body.get(3..3 + len)?;
+                    let record = Record::Event {
+                        category: EventCategory::from_u8(header[0]),
+                        severity: Severity::from_u8(header[1]),
+                        text: String::from_utf8_lossy(text).into_owned(),
+                    };
+                    (record, 3 + len)
+                }
+                1 => {
+                    let b = body.get(..11)?;
+                    let source = match b[0] {
+                        0 => FrameSource::Mstp(b[1]),
+                        _ => FrameSource::Ip(SocketAddrV4::new(
+                            Ipv4Addr::new(b[1], b[2], b[3], b[4]),
+                            u16::from_be_bytes([b[5], b[6]]),
+                        )),
+                    };
+                    let frame = FrameSummary {
+                        source,
+                        len: u16::from_be_bytes([b[7], b[8]]),
+                        apdu_type: (b[9] != 0xFF).then_some(b[9]),
+                        service: (b[10] != 0xFF).then_some(b[10]),
+                    };
+                    (Record::Frame(frame), 11)
+                }
+                2 => (Record::Heap { free: u32_at(body, 0)? }, 4),
+                _ => return None,
+            };
+            entries.push(Entry { uptime_ms, record });
+            rest = &body[used..];
+        }
+        Some(Self { reason: RebootReason::from_u8(reason), entries })
+    }
+}
+
+/// The recording itself; `.rtc_noinit` is not cleared by the startup code
+#[link_section = ".rtc_noinit"]
+static mut RTC_BLACKBOX: MaybeUninit<BlackBox> = MaybeUninit::uninit();
+
+/// Serializes access to `RTC_BLACKBOX`
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Set by `init`; nothing is recorded before it
+static BOOT: OnceLock<Instant> = OnceLock::new();
+
+/// Read back what the previous boot recorded, then start a new recording.
+/// Called once, early in startup.
+pub fn init() -> Option<Vec<Entry>> {
+    let _guard = LOCK.lock().ok()?;
+    let blackbox = std::ptr::addr_of_mut!(RTC_BLACKBOX).cast::<BlackBox>();
+    // SAFETY: BlackBox is plain integers, so any bit pattern a reset (or power-on)
+    // left behind is a valid value; the volatile read keeps the compiler from
+    // treating the never-initialized static as undefined. LOCK is held.
+    let previous = unsafe { std::ptr::read_volatile(blackbox) }.entries();
+    // SAFETY: as above
+    unsafe { std::ptr::write_volatile(blackbox, BlackBox::new()) };
+    BOOT.get_or_init(Instant::now);
+    previous
+}
+
+/// Run `f` on the recording, once `init` has started it
+fn with_blackbox(f: impl FnOnce(&mut BlackBox, u32)) {
+    let Some(boot) = BOOT.get() else { return };
+    let uptime_ms = boot.elapsed().as_millis().min(u32::MAX as u128) as u32;
+    if let Ok(_guard) = LOCK.lock() {
+        // SAFETY: initialized by `init` (BOOT is set after it), and LOCK is held
+        let blackbox = unsafe { &mut *std::ptr::addr_of_mut!(RTC_BLACKBOX).cast::<BlackBox>() };
+        f(blackbox, uptime_ms);
+    }
+}
+
+/// What this boot has recorded so far, oldest first
+pub fn current() -> Option<Vec<Entry>> {
+    BOOT.get()?;
+    let _guard = LOCK.lock().ok()?;
+    // SAFETY: initialized by `init` (BOOT is set after it), and LOCK is held
+    let blackbox = unsafe { &*std::ptr::addr_of!(RTC_BLACKBOX).cast::<BlackBox>() };
+    blackbox.entries()
+}
+
+pub fn record_event(category: EventCategory, severity: Severity, message: &str) {
+    with_blackbox(|bb, now| bb.record_event(now, category, severity, message));
+}
+
+pub fn record_frame(frame: FrameSummary) {
+    with_blackbox(|bb, now| bb.record_frame(now, &frame));
+}
+
+pub fn record_heap(free: u32) {
+    with_blackbox(|bb, now| bb.record_heap(now, free));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip_frame() -> FrameSummary {
+        // Confirmed ReadProperty from a workstation
+        let apdu = [0x00, 0x05, 0x07, 0x0C, 0x0C];
+        FrameSummary::new(FrameSource::Ip("192.168.1.50:47808".parse().unwrap()), 25, Some(&apdu))
+    }
+
+    #[test]
+    fn test_rings_keep_newest_in_time_order() {
+        let mut bb = BlackBox::new();
+        for i in 0..EVENT_SLOTS as u32 + 4 {
+            bb.record_event(i * 10, EventCategory::Token, Severity::Warning, &format!("event {}", i));
+        }
+        bb.record_frame(45, &ip_frame());
+        bb.record_heap(55, 81_000);
+
+        let entries = bb.entries().unwrap();
+        assert_eq!(entries.len(), EVENT_SLOTS + 2);
+        assert_eq!(
+            entries[0].record,
+            Record::Event { category: EventCategory::Token, severity: Severity::Warning, text: "event 4".to_string() }
+        );
+        assert_eq!(entries[0].uptime_ms, 40);
+        assert_eq!(entries[1].record, Record::Frame(ip_frame()));
+        assert_eq!(entries[3].record, Record::Heap { free: 81_000 });
+        assert_eq!(ip_frame().to_string(), "from IP 192.168.1.50:47808, 25 bytes, confirmed request (service 12)");
+    }
+
+    #[test]
+    fn test_noise_is_not_a_recording() {
+        let mut bb = BlackBox::new();
+        bb.magic = 0x1234_5678;
+        assert!(bb.entries().is_none());
+
+        let mut bb = BlackBox::new();
+        bb.frames.next = FRAME_SLOTS as u32 + 3;
+        assert!(bb.entries().is_none());
+
+        // Long messages are cut on a character boundary
+        let mut bb = BlackBox::new();
+        bb.record_event(0, EventCategory::System, Severity::Error, &"é".repeat(30));
+        let entries = bb.entries().unwrap();
+        let Record::Event { text, .. } = &entries[0].record else { panic!("expected event") };
+        assert_eq!(text.len(), 40);
+    }
+
+    #[test]
+    fn test_crash_report_round_trip() {
+        let mut bb = BlackBox::new();
+        bb.record_event(100, EventCategory::Token, Severity::Warning, "MS/TP token lost");
+        bb.record_frame(150, &FrameSummary::new(FrameSource::Mstp(5), 12, None));
+        bb.record_frame(160, &ip_frame());
+        bb.record_heap(200, 12_345);
+        let report = CrashReport { reason: RebootReason::Watchdog, entries: bb.entries().unwrap() };
+
+        let encoded = report.encode();
+        assert!(encoded.len() <= MAX_REPORT_LEN);
+        assert_eq!(CrashReport::decode(&encoded), Some(report.clone()));
+        assert_eq!(report.last_uptime_ms(), 200);
+
+        assert!(CrashReport::decode(&encoded[..encoded.len() - 1]).is_none());
+        assert!(CrashReport::decode(&[9, 5]).is_none());
+    }
+}
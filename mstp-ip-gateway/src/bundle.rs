@@ -0,0 +1,124 @@
+//! Support bundle for vendor tickets
+//!
+//! One download that holds what support asks for first: the configuration
+//! (without passwords), statistics, the event and error logs, the recent
+//! blackbox capture, the crash report and firmware and heap figures. The
+//! files are packed into a plain ustar archive, which every OS can open,
+//! built in memory from the web state - nothing is restarted or paused.
+
+/// Suggested file name for downloads
+pub const BUNDLE_FILE_NAME: &str = "bacman-support.tar";
+
+/// Tar block size; headers and file contents are padded to it
+const BLOCK: usize = 512;
+
+/// ustar archive built in memory
+pub struct Tar {
+    data: Vec<u8>,
+    mtime: u64,
+}
+
+impl Tar {
+    /// Empty archive whose files carry `mtime` (UTC seconds, 0 when the clock is unset)
+    pub fn new(mtime: u64) -> Self {
+        Self { data: Vec::new(), mtime }
+    }
+
+    /// Append a regular file; names longer than 99 bytes are cut
+    pub fn add_file(&mut self, name: &str, contents: &[u8]) {
+        let mut header = [0u8; BLOCK];
+        let name = &name.as_bytes()[..name.len().min(99)];
+        header[..name.len()].copy_from_slice(name);
+        write_octal(&mut header[100..108], 0o644);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], contents.len() as u64);
+        write_octal(&mut header[136..148], self.mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // Checksum is taken with its own field as spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        write_octal(&mut header[148..155], u64::from(checksum));
+
+        self.data.extend_from_slice(&header);
+        self.data.extend_from_slice(contents);
+        self.data.resize(self.data.len().next_multiple_of(BLOCK), 0);
+    }
+
+    /// Close the archive with its two empty blocks
+    pub fn finish(mut self) -> Vec<u8> {
+        self.data.resize(self.data.len() + 2 * BLOCK, 0);
+        self.data
+    }
+}
+
+/// Zero-padded octal number filling `field` but its last byte, which is NUL
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&text.as_bytes()[text.len() - digits..]);
+    field[digits] = 0;
+}
+
+/// Heap figures at the time of the bundle
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub free: u32,
+    /// Lowest free heap since boot
+    pub min_free: u32,
+    /// Largest block that can be allocated now
+    pub largest_block: usize,
+}
+
+impl HeapStats {
+    pub fn read() -> Self {
+        // SAFETY: these calls only read allocator counters
+        unsafe {
+            Self {
+                free: esp_idf_sys::esp_get_free_heap_size(),
+                min_free: esp_idf_sys::esp_get_minimum_free_heap_size(),
+                largest_block: esp_idf_sys::heap_caps_get_largest_free_block(esp_idf_sys::MALLOC_CAP_8BIT),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octal_field(field: &[u8]) -> u64 {
+        let text = std::str::from_utf8(field).unwrap().trim_end_matches(['\0', ' ']);
+        u64::from_str_radix(text, 8).unwrap()
+    }
+
+    #[test]
+    fn test_tar_headers_and_padding() {
+        let mut tar = Tar::new(1_700_000_000);
+        tar.add_file("status.json", b"{\"ok\":true}");
+        tar.add_file("empty.json", b"");
+        let data = tar.finish();
+
+        // Header + one data block, a header alone, then the end marker
+        assert_eq!(data.len(), 3 * BLOCK + 2 * BLOCK);
+        let header = &data[..BLOCK];
+        assert_eq!(&header[..12], b"status.json\0");
+        assert_eq!(octal_field(&header[124..136]), 11);
+        assert_eq!(octal_field(&header[136..148]), 1_700_000_000);
+        assert_eq!(&header[257..263], b"ustar\0");
+        assert_eq!(&data[BLOCK..BLOCK + 11], b"{\"ok\":true}");
+        assert!(data[BLOCK + 11..2 * BLOCK].iter().all(|&b| b == 0));
+
+        let mut blank = header.to_vec();
+        blank[148..156].fill(b' ');
+        let sum: u64 = blank.iter().map(|&b| u64::from(b)).sum();
+        assert_eq!(octal_field(&header[148..156]), sum);
+
+        assert_eq!(&data[2 * BLOCK..2 * BLOCK + 10], b"empty.json");
+        assert_eq!(octal_field(&data[2 * BLOCK + 124..2 * BLOCK + 136]), 0);
+        assert!(data[3 * BLOCK..].iter().all(|&b| b == 0));
+    }
+}
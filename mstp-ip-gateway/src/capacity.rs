@@ -0,0 +1,221 @@
+//! MS/TP line capacity estimate
+//!
+//! Every byte on an MS/TP trunk takes 10 bit times (start, 8 data, stop), so
+//! the bytes of valid frames heard or sent over a window give the share of
+//! time the line was busy. Dividing by the tokens we received gives the
+//! traffic carried per token cycle. From those this projects what another
+//! routed packet, or a few more masters with the average station's traffic,
+//! would add to the token loop - a rough answer to "can this trunk take ten
+//! more devices?". It ignores retries and reply delays, so treat it as a
+//! lower bound.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Span of traffic the estimate covers
+pub const CAPACITY_WINDOW: Duration = Duration::from_secs(60);
+
+/// NPDU size used for the "one more routed packet" figure (a typical
+/// ReadProperty request or simple answer)
+pub const TYPICAL_ROUTED_NPDU: usize = 50;
+
+/// Line utilization above which answers start waiting noticeably for the token
+pub const UTILIZATION_LIMIT_PCT: f32 = 70.0;
+
+/// Extra masters shown in the projection
+pub const PROJECTED_DEVICES: [u32; 4] = [1, 5, 10, 20];
+
+/// Start bit, 8 data bits, stop bit
+const BITS_PER_BYTE: u64 = 10;
+
+/// Preamble, header and header CRC
+const FRAME_HEADER_LEN: usize = 8;
+
+/// Tturnaround: silence before a station may transmit after the last frame
+const TURNAROUND_BITS: u64 = 40;
+
+/// Driver counters the estimate is taken from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineCounters {
+    /// Bytes of valid frames heard or sent
+    pub line_bytes: u64,
+    /// Part of `line_bytes` in frames carrying data
+    pub data_bytes: u64,
+    /// Tokens received: one per token cycle while we are a master
+    pub tokens: u64,
+}
+
+/// Samples of the line counters over the last `CAPACITY_WINDOW`
+#[derive(Debug, Default)]
+pub struct CapacityMeter {
+    samples: VecDeque<(Instant, LineCounters)>,
+}
+
+impl CapacityMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sample; the oldest one still covering the window is kept
+    pub fn record(&mut self, now: Instant, counters: LineCounters) {
+        // Counters went backwards: statistics were reset
+        if self.samples.back().is_some_and(|(_, last)| counters.line_bytes < last.line_bytes) {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, counters));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= CAPACITY_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Estimate over the samples held, or None before a second sample
+    pub fn estimate(&self, baud_rate: u32, masters: u8, token_loop_ms: u32, max_npdu: usize) -> Option<CapacityEstimate> {
+        let ((first_at, first), (last_at, last)) = (self.samples.front()?, self.samples.back()?);
+        let window = last_at.duration_since(*first_at);
+        if window.is_zero() || baud_rate == 0 {
+            return None;
+        }
+        let line_bytes = last.line_bytes - first.line_bytes;
+        let data_bytes = last.data_bytes - first.data_bytes;
+        let cycles = last.tokens - first.tokens;
+        let pct = |bytes: u64| (bytes * BITS_PER_BYTE) as f32 * 100.0 / (baud_rate as f32 * window.as_secs_f32());
+
+        Some(CapacityEstimate {
+            window,
+            baud_rate,
+            masters: masters.max(1),
+            token_loop_ms,
+            utilization_pct: pct(line_bytes).min(100.0),
+            data_pct: pct(data_bytes).min(100.0),
+            bytes_per_cycle: (cycles > 0).then(|| (line_bytes / cycles) as u32),
+            data_bytes_per_cycle: (cycles > 0).then(|| (data_bytes / cycles) as u32),
+            extra_packet_ms: frame_time_ms(TYPICAL_ROUTED_NPDU, baud_rate),
+            extra_max_packet_ms: frame_time_ms(max_npdu, baud_rate),
+        })
+    }
+}
+
+/// Line capacity over the last window, with projections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CapacityEstimate {
+    pub window: Duration,
+    pub baud_rate: u32,
+    pub masters: u8,
+    pub token_loop_ms: u32,
+    /// Share of time some station was transmitting
+    pub utilization_pct: f32,
+    /// Part of that spent on frames carrying data (the rest is token passing and polling)
+    pub data_pct: f32,
+    /// Average bytes on the wire per token cycle; None while we hold no token
+    pub bytes_per_cycle: Option<u32>,
+    pub data_bytes_per_cycle: Option<u32>,
+    /// Added to the token loop by one more routed packet per cycle
+    pub extra_packet_ms: f32,
+    /// The same for a packet of the largest NPDU
+    pub extra_max_packet_ms: f32,
+}
+
+impl CapacityEstimate {
+    /// Projected token loop and utilization with `devices` more masters,
+    /// each passing the token on and carrying the average station's data
+    pub fn with_extra_devices(&self, devices: u32) -> Projection {
+        let masters = self.masters as f32;
+        let per_station_data = self.data_bytes_per_cycle.unwrap_or(0) as f32 / masters;
+        let per_station_ms = frame_time_ms(0, self.baud_rate)
+            + per_station_data * BITS_PER_BYTE as f32 * 1000.0 / self.baud_rate as f32;
+        let overhead_pct = self.utilization_pct - self.data_pct;
+        let utilization_pct = (overhead_pct + self.data_pct * (masters + devices as f32) / masters).min(100.0);
+        Projection {
+            devices,
+            token_loop_ms: self.token_loop_ms + (per_station_ms * devices as f32).round() as u32,
+            utilization_pct,
+            fits: utilization_pct < UTILIZATION_LIMIT_PCT,
+        }
+    }
+}
+
+/// What a number of added masters is expected to do to the ring
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+    pub devices: u32,
+    pub token_loop_ms: u32,
+    pub utilization_pct: f32,
+    /// Utilization stays below `UTILIZATION_LIMIT_PCT`
+    pub fits: bool,
+}
+
+/// Time on the wire for a frame with `payload` data bytes, turnaround included
+pub fn frame_time_ms(payload: usize, baud_rate: u32) -> f32 {
+    let bytes = FRAME_HEADER_LEN + if payload > 0 { payload + 2 } else { 0 };
+    let bits = bytes as u64 * BITS_PER_BYTE + TURNAROUND_BITS;
+    bits as f32 * 1000.0 / baud_rate.max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(line_bytes: u64, data_bytes: u64, tokens: u64) -> LineCounters {
+        LineCounters { line_bytes, data_bytes, tokens }
+    }
+
+    #[test]
+    fn test_utilization_from_bytes_on_the_wire() {
+        let start = Instant::now();
+        let mut meter = CapacityMeter::new();
+        meter.record(start, counters(0, 0, 0));
+        assert_eq!(meter.estimate(38400, 4, 20, 480), None);
+
+        // 10 s at 38400 baud carries 38400 bytes; 9600 of them is 25%
+        meter.record(start + Duration::from_secs(10), counters(9600, 4800, 400));
+        let estimate = meter.estimate(38400, 4, 20, 480).unwrap();
+        assert_eq!(estimate.window, Duration::from_secs(10));
+        assert!((estimate.utilization_pct - 25.0).abs() < 0.01);
+        assert!((estimate.data_pct - 12.5).abs() < 0.01);
+        assert_eq!(estimate.bytes_per_cycle, Some(24));
+        assert_eq!(estimate.data_bytes_per_cycle, Some(12));
+
+        // A statistics reset starts the window again
+        meter.record(start + Duration::from_secs(11), counters(10, 0, 1));
+        assert_eq!(meter.estimate(38400, 4, 20, 480), None);
+    }
+
+    #[test]
+    fn test_window_keeps_only_recent_samples() {
+        let start = Instant::now();
+        let mut meter = CapacityMeter::new();
+        for second in 0..=120 {
+            meter.record(start + Duration::from_secs(second), counters(second * 100, 0, second));
+        }
+        let estimate = meter.estimate(9600, 1, 10, 480).unwrap();
+        assert_eq!(estimate.window, CAPACITY_WINDOW);
+        assert!((estimate.utilization_pct - 100.0 * 1000.0 / 9600.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_projection_for_more_devices() {
+        // 50-byte NPDU: 60 bytes plus turnaround = 640 bit times
+        assert!((frame_time_ms(TYPICAL_ROUTED_NPDU, 38400) - 16.667).abs() < 0.01);
+        // Token frame: 8 bytes plus turnaround = 120 bit times
+        assert!((frame_time_ms(0, 38400) - 3.125).abs() < 0.01);
+
+        let estimate = CapacityEstimate {
+            window: CAPACITY_WINDOW,
+            baud_rate: 38400,
+            masters: 4,
+            token_loop_ms: 40,
+            utilization_pct: 30.0,
+            data_pct: 20.0,
+            bytes_per_cycle: Some(160),
+            data_bytes_per_cycle: Some(96),
+            extra_packet_ms: 0.0,
+            extra_max_packet_ms: 0.0,
+        };
+        // Each new station: a token pass (3.125 ms) and 24 data bytes (6.25 ms)
+        let projection = estimate.with_extra_devices(4);
+        assert_eq!(projection.token_loop_ms, 78);
+        assert!((projection.utilization_pct - 50.0).abs() < 0.01);
+        assert!(projection.fits);
+        assert!(!estimate.with_extra_devices(10).fits);
+    }
+}
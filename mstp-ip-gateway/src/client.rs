@@ -0,0 +1,470 @@
+//! BACnet client calls from the serial console
+//!
+//! Lets a bench script read and write properties on MS/TP devices over USB,
+//! without any network. A call is sent like the inventory's ReadProperty
+//! calls (local NPDU, proxy invoke IDs, retried on timeout) and its answer
+//! is decoded to text. One call runs at a time.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::inventory::{decode_character_string, OBJECT_TYPE_NAMES};
+use crate::rpm_proxy::{encode_read_property, parse_read_property_result, read_tag, PropertyRef, ReadResult};
+use crate::wpm_proxy::{encode_write_property, PropertyWrite, SERVICE_WRITE_PROPERTY};
+
+/// How long to wait for each answer
+pub const CLIENT_CALL_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Retransmissions of an unanswered call before it is given up
+pub const CLIENT_CALL_MAX_RETRIES: u8 = 2;
+
+/// How long the console waits for a call's outcome
+pub const CLIENT_CALL_DEADLINE: Duration =
+    Duration::from_secs(CLIENT_CALL_TIMEOUT.as_secs() * (CLIENT_CALL_MAX_RETRIES as u64 + 1) + 2);
+
+/// Property identifiers accepted by name
+const PROPERTY_NAMES: [(&str, u32); 16] = [
+    ("description", 28),
+    ("event-state", 36),
+    ("model-name", 70),
+    ("object-identifier", 75),
+    ("object-list", 76),
+    ("object-name", 77),
+    ("object-type", 79),
+    ("out-of-service", 81),
+    ("present-value", 85),
+    ("priority-array", 87),
+    ("relinquish-default", 104),
+    ("status-flags", 111),
+    ("system-status", 112),
+    ("units", 117),
+    ("vendor-identifier", 120),
+    ("vendor-name", 121),
+];
+
+/// Application tag numbers
+const TAG_NULL: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_UNSIGNED: u8 = 2;
+const TAG_SIGNED: u8 = 3;
+const TAG_REAL: u8 = 4;
+const TAG_DOUBLE: u8 = 5;
+const TAG_OCTET_STRING: u8 = 6;
+const TAG_CHARACTER_STRING: u8 = 7;
+const TAG_BIT_STRING: u8 = 8;
+const TAG_ENUMERATED: u8 = 9;
+const TAG_DATE: u8 = 10;
+const TAG_TIME: u8 = 11;
+const TAG_OBJECT_ID: u8 = 12;
+
+/// Error class names (Clause 21, BACnetErrorClass)
+const ERROR_CLASS_NAMES: [&str; 8] =
+    ["device", "object", "property", "resources", "security", "services", "vt", "communication"];
+
+/// Common error code names (Clause 21, BACnetErrorCode)
+const ERROR_CODE_NAMES: [(u32, &str); 10] = [
+    (0, "other"),
+    (9, "invalid-data-type"),
+    (25, "operational-problem"),
+    (27, "read-access-denied"),
+    (31, "unknown-object"),
+    (32, "unknown-property"),
+    (37, "value-out-of-range"),
+    (40, "write-access-denied"),
+    (42, "invalid-array-index"),
+    (50, "property-is-not-an-array"),
+];
+
+/// The service a call makes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientRequest {
+    ReadProperty(PropertyRef),
+    WriteProperty(PropertyWrite),
+}
+
+/// How a call ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientOutcome {
+    /// ReadProperty value, decoded
+    Value(String),
+    /// WriteProperty acknowledged
+    Written,
+    /// Error PDU (class, code)
+    Error(u32, u32),
+    /// Reject PDU with its reason
+    Reject(u8),
+    /// Abort PDU with its reason
+    Abort(u8),
+    /// No answer after the retries
+    NoResponse,
+}
+
+impl fmt::Display for ClientOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientOutcome::Value(value) => f.write_str(value),
+            ClientOutcome::Written => f.write_str("OK"),
+            ClientOutcome::Error(class, code) => write!(f, "Error: {}", error_name(*class, *code)),
+            ClientOutcome::Reject(reason) => write!(f, "Reject (reason {})", reason),
+            ClientOutcome::Abort(reason) => write!(f, "Abort (reason {})", reason),
+            ClientOutcome::NoResponse => f.write_str("No response"),
+        }
+    }
+}
+
+/// The request waiting for an answer
+#[derive(Debug, Clone)]
+struct Outstanding {
+    invoke_id: u8,
+    sent_at: Instant,
+    retries: u8,
+}
+
+/// One ReadProperty or WriteProperty toward an MS/TP device
+#[derive(Debug, Clone)]
+pub struct ClientCall {
+    pub mac: u8,
+    pub request: ClientRequest,
+    outstanding: Option<Outstanding>,
+    outcome: Option<ClientOutcome>,
+}
+
+impl ClientCall {
+    pub fn new(mac: u8, request: ClientRequest) -> Self {
+        Self { mac, request, outstanding: None, outcome: None }
+    }
+
+    /// The request to send now, as (APDU, MS/TP MAC)
+    ///
+    /// The first call takes `next_invoke_id` and advances it; a
+    /// retransmission reuses its own. Gives up with `NoResponse` once the
+    /// retries are spent.
+    pub fn poll(&mut self, now: Instant, next_invoke_id: &mut u8) -> Option<(Vec<u8>, u8)> {
+        if self.outcome.is_some() {
+            return None;
+        }
+        let invoke_id = match self.outstanding.as_mut() {
+            Some(outstanding) if now.duration_since(outstanding.sent_at) < CLIENT_CALL_TIMEOUT => return None,
+            Some(outstanding) if outstanding.retries < CLIENT_CALL_MAX_RETRIES => {
+                outstanding.retries += 1;
+                outstanding.sent_at = now;
+                outstanding.invoke_id
+            }
+            Some(_) => {
+                self.outstanding = None;
+                self.outcome = Some(ClientOutcome::NoResponse);
+                return None;
+            }
+            None => {
+                let invoke_id = *next_invoke_id;
+                *next_invoke_id = invoke_id.wrapping_add(1);
+                self.outstanding = Some(Outstanding { invoke_id, sent_at: now, retries: 0 });
+                invoke_id
+            }
+        };
+        let apdu = match &self.request {
+            ClientRequest::ReadProperty(r) => encode_read_property(invoke_id, r),
+            ClientRequest::WriteProperty(w) => encode_write_property(invoke_id, w),
+        };
+        Some((apdu, self.mac))
+    }
+
+    /// Whether an APDU from `mac` answers the outstanding request
+    pub fn matches(&self, mac: u8, apdu: &[u8]) -> bool {
+        match &self.outstanding {
+            Some(outstanding) => {
+                mac == self.mac
+                    && apdu.get(1) == Some(&outstanding.invoke_id)
+                    && matches!(apdu[0] >> 4, 2 | 3 | 5 | 6 | 7)
+            }
+            None => false,
+        }
+    }
+
+    /// Record the device's answer
+    pub fn record(&mut self, apdu: &[u8]) {
+        self.outstanding = None;
+        let outcome = match apdu[0] >> 4 {
+            6 => ClientOutcome::Reject(apdu.get(2).copied().unwrap_or(0)),
+            7 => ClientOutcome::Abort(apdu.get(2).copied().unwrap_or(0)),
+            5 => {
+                let (class, code) = decode_error_pdu(apdu).unwrap_or((0, 0));
+                ClientOutcome::Error(class, code)
+            }
+            2 if apdu.get(2) == Some(&SERVICE_WRITE_PROPERTY) => ClientOutcome::Written,
+            _ => match parse_read_property_result(apdu) {
+                Some(ReadResult::Value(value)) => ClientOutcome::Value(format_value(&value)),
+                _ => ClientOutcome::Error(0, 0),
+            },
+        };
+        self.outcome = Some(outcome);
+    }
+
+    /// The outcome, once the call has ended
+    pub fn take_outcome(&mut self) -> Option<ClientOutcome> {
+        self.outcome.take()
+    }
+}
+
+/// Error class and code of an Error PDU
+pub fn decode_error_pdu(apdu: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 3;
+    let mut enumerated = || {
+        let tag = read_tag(apdu, pos)?;
+        let value = apdu.get(pos + tag.header_len..pos + tag.header_len + tag.lvt as usize)?;
+        pos += tag.header_len + tag.lvt as usize;
+        (!tag.context && tag.number == TAG_ENUMERATED).then(|| decode_unsigned(value))
+    };
+    Some((enumerated()?, enumerated()?))
+}
+
+/// `class: code` by name where known, by number otherwise
+pub fn error_name(class: u32, code: u32) -> String {
+    let class_name = ERROR_CLASS_NAMES.get(class as usize).map(|s| s.to_string()).unwrap_or_else(|| class.to_string());
+    let code_name = ERROR_CODE_NAMES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| code.to_string());
+    format!("{}: {}", class_name, code_name)
+}
+
+/// Parse an object as `<type>:<instance>`, the type by name or number
+pub fn parse_object_id(s: &str) -> Result<u32, String> {
+    let (object_type, instance) = s.split_once(':').ok_or_else(|| format!("Object '{}' must be <type>:<instance>", s))?;
+    let object_type = match OBJECT_TYPE_NAMES.iter().position(|name| *name == object_type) {
+        Some(index) => index as u32,
+        None => object_type.parse::<u32>().ok().filter(|t| *t < 1024).ok_or_else(|| format!("Unknown object type '{}'", object_type))?,
+    };
+    let instance = instance
+        .parse::<u32>()
+        .ok()
+        .filter(|i| *i <= 0x3F_FFFF)
+        .ok_or_else(|| format!("Instance '{}' must be 0-4194303", instance))?;
+    Ok((object_type << 22) | instance)
+}
+
+/// Parse a property as `<name or number>` or `<name or number>[<index>]`
+pub fn parse_property(s: &str) -> Result<(u32, Option<u32>), String> {
+    let (property, index) = match s.strip_suffix(']').and_then(|s| s.split_once('[')) {
+        Some((property, index)) => {
+            let index = index.parse::<u32>().map_err(|_| format!("Array index '{}' must be a number", index))?;
+            (property, Some(index))
+        }
+        None => (s, None),
+    };
+    let property_id = match PROPERTY_NAMES.iter().find(|(name, _)| *name == property) {
+        Some((_, id)) => *id,
+        None => property.parse::<u32>().map_err(|_| format!("Unknown property '{}'", property))?,
+    };
+    Ok((property_id, index))
+}
+
+/// Encode a value typed on the console as an application-tagged value
+///
+/// Accepts `null`, `true`, `false`, plain numbers (a decimal point makes a
+/// Real, a sign a Signed, otherwise Unsigned) and the explicit forms
+/// `real:`, `unsigned:`, `signed:`, `enum:` and `string:`.
+pub fn parse_value(s: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("Invalid value '{}'", s);
+    match s.split_once(':') {
+        Some(("real", v)) => Ok(encode_application(TAG_REAL, &v.parse::<f32>().map_err(|_| invalid())?.to_be_bytes())),
+        Some(("unsigned", v)) => Ok(encode_application(TAG_UNSIGNED, &unsigned_bytes(v.parse().map_err(|_| invalid())?))),
+        Some(("signed", v)) => Ok(encode_application(TAG_SIGNED, &signed_bytes(v.parse().map_err(|_| invalid())?))),
+        Some(("enum", v)) => Ok(encode_application(TAG_ENUMERATED, &unsigned_bytes(v.parse().map_err(|_| invalid())?))),
+        Some(("string", v)) => {
+            let mut chars = vec![0u8]; // UTF-8
+            chars.extend_from_slice(v.as_bytes());
+            Ok(encode_application(TAG_CHARACTER_STRING, &chars))
+        }
+        Some(_) => Err(invalid()),
+        None => match s {
+            "null" => Ok(vec![TAG_NULL << 4]),
+            "true" => Ok(vec![(TAG_BOOLEAN << 4) | 1]),
+            "false" => Ok(vec![TAG_BOOLEAN << 4]),
+            _ if s.contains('.') => parse_value(&format!("real:{}", s)),
+            _ if s.starts_with('-') => parse_value(&format!("signed:{}", s)),
+            _ => parse_value(&format!("unsigned:{}", s)),
+        },
+    }
+}
+
+/// Application tag header and contents
+fn encode_application(tag: u8, data: &[u8]) -> Vec<u8> {
+    let mut v = Vec::with_capacity(data.len() + 4);
+    match data.len() {
+        len @ 0..=4 => v.push((tag << 4) | len as u8),
+        len @ 5..=253 => v.extend_from_slice(&[(tag << 4) | 5, len as u8]),
+        len => {
+            v.extend_from_slice(&[(tag << 4) | 5, 254]);
+            v.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+    v.extend_from_slice(data);
+    v
+}
+
+fn unsigned_bytes(value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|&&b| b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn signed_bytes(value: i32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Drop leading octets that only repeat the sign
+    let mut skip = 0;
+    while skip < 3 && ((bytes[skip] == 0x00 && bytes[skip + 1] & 0x80 == 0) || (bytes[skip] == 0xFF && bytes[skip + 1] & 0x80 != 0)) {
+        skip += 1;
+    }
+    bytes[skip..].to_vec()
+}
+
+fn decode_unsigned(data: &[u8]) -> u32 {
+    data.iter().take(4).fold(0u32, |acc, &b| (acc << 8) | b as u32)
+}
+
+/// Decode a property value (one or more application-tagged values) to text.
+/// Constructed values are shown as hex from the first context tag on.
+pub fn format_value(value: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut pos = 0;
+    while pos < value.len() {
+        let Some(tag) = read_tag(value, pos).filter(|tag| !tag.context) else {
+            parts.push(format!("raw {}", hex(&value[pos..])));
+            break;
+        };
+        let start = pos + tag.header_len;
+        // A boolean carries its value in the tag itself
+        let len = if tag.number == TAG_BOOLEAN { 0 } else { tag.lvt as usize };
+        let Some(data) = value.get(start..start + len) else {
+            parts.push(format!("raw {}", hex(&value[pos..])));
+            break;
+        };
+        parts.push(format_application(tag.number, tag.lvt, data));
+        pos = start + len;
+    }
+    parts.join(", ")
+}
+
+fn format_application(tag: u8, lvt: u32, data: &[u8]) -> String {
+    match tag {
+        TAG_NULL => "null".to_string(),
+        TAG_BOOLEAN => (lvt != 0).to_string(),
+        TAG_UNSIGNED => decode_unsigned(data).to_string(),
+        TAG_SIGNED if !data.is_empty() => {
+            let extended = data.iter().fold(if data[0] & 0x80 != 0 { -1i64 } else { 0 }, |acc, &b| (acc << 8) | b as i64);
+            extended.to_string()
+        }
+        TAG_REAL if data.len() == 4 => f32::from_be_bytes([data[0], data[1], data[2], data[3]]).to_string(),
+        TAG_DOUBLE if data.len() == 8 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(data);
+            f64::from_be_bytes(bytes).to_string()
+        }
+        TAG_OCTET_STRING => format!("0x{}", hex(data)),
+        TAG_CHARACTER_STRING => format!("\"{}\"", decode_character_string(data)),
+        TAG_BIT_STRING if !data.is_empty() => {
+            let bits = (data.len() - 1) * 8 - (data[0] as usize).min((data.len() - 1) * 8);
+            (0..bits).map(|i| if data[1 + i / 8] & (0x80 >> (i % 8)) != 0 { '1' } else { '0' }).collect::<String>()
+        }
+        TAG_ENUMERATED => format!("enum {}", decode_unsigned(data)),
+        TAG_DATE if data.len() == 4 => format!("{}-{:02}-{:02}", 1900 + data[0] as u32, data[1], data[2]),
+        TAG_TIME if data.len() == 4 => format!("{:02}:{:02}:{:02}.{:02}", data[0], data[1], data[2], data[3]),
+        TAG_OBJECT_ID if data.len() == 4 => {
+            let id = decode_unsigned(data);
+            let object_type = id >> 22;
+            match OBJECT_TYPE_NAMES.get(object_type as usize) {
+                Some(name) => format!("{}:{}", name, id & 0x3F_FFFF),
+                None => format!("{}:{}", object_type, id & 0x3F_FFFF),
+            }
+        }
+        _ => format!("raw {}", hex(data)),
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_console_arguments() {
+        assert_eq!(parse_object_id("analog-value:3"), Ok((2 << 22) | 3));
+        assert_eq!(parse_object_id("8:1234"), Ok((8 << 22) | 1234));
+        assert!(parse_object_id("analog-value").is_err());
+        assert!(parse_object_id("analog-value:4194304").is_err());
+
+        assert_eq!(parse_property("present-value"), Ok((85, None)));
+        assert_eq!(parse_property("priority-array[8]"), Ok((87, Some(8))));
+        assert_eq!(parse_property("512"), Ok((512, None)));
+        assert!(parse_property("no-such-property").is_err());
+
+        assert_eq!(parse_value("null"), Ok(vec![0x00]));
+        assert_eq!(parse_value("true"), Ok(vec![0x11]));
+        assert_eq!(parse_value("72.5"), Ok(vec![0x44, 0x42, 0x91, 0x00, 0x00]));
+        assert_eq!(parse_value("300"), Ok(vec![0x22, 0x01, 0x2C]));
+        assert_eq!(parse_value("-2"), Ok(vec![0x31, 0xFE]));
+        assert_eq!(parse_value("enum:1"), Ok(vec![0x91, 0x01]));
+        assert_eq!(parse_value("string:Hi"), Ok(vec![0x73, 0x00, b'H', b'i']));
+        assert!(parse_value("real:warm").is_err());
+    }
+
+    #[test]
+    fn test_formats_application_values() {
+        assert_eq!(format_value(&[0x44, 0x42, 0x91, 0x00, 0x00]), "72.5");
+        assert_eq!(format_value(&[0x10, 0x21, 0x05, 0x91, 0x01]), "false, 5, enum 1");
+        assert_eq!(format_value(&[0x75, 0x04, 0x00, b'A', b'H', b'U']), "\"AHU\"");
+        assert_eq!(format_value(&[0x82, 0x04, 0x40]), "0100");
+        assert_eq!(format_value(&[0xC4, 0x00, 0x80, 0x00, 0x03]), "analog-value:3");
+        assert_eq!(format_value(&[0x31, 0xFE]), "-2");
+        assert_eq!(format_value(&[0x0E, 0x21, 0x01, 0x0F]), "raw 0E21010F");
+    }
+
+    #[test]
+    fn test_read_call_retries_and_decodes_answer() {
+        let start = Instant::now();
+        let reference = PropertyRef { object_id: (2 << 22) | 3, property_id: 85, array_index: None };
+        let mut call = ClientCall::new(5, ClientRequest::ReadProperty(reference));
+        let mut next_invoke_id = 7;
+
+        let (apdu, mac) = call.poll(start, &mut next_invoke_id).unwrap();
+        assert_eq!((apdu[2], mac, next_invoke_id), (7, 5, 8));
+        assert!(call.poll(start, &mut next_invoke_id).is_none());
+
+        // Retransmissions reuse the invoke ID
+        let (apdu, _) = call.poll(start + CLIENT_CALL_TIMEOUT, &mut next_invoke_id).unwrap();
+        assert_eq!((apdu[2], next_invoke_id), (7, 8));
+
+        let ack = [0x30, 0x07, 0x0C, 0x0C, 0x00, 0x80, 0x00, 0x03, 0x19, 0x55, 0x3E, 0x44, 0x42, 0x91, 0x00, 0x00, 0x3F];
+        assert!(!call.matches(6, &ack));
+        assert!(call.matches(5, &ack));
+        call.record(&ack);
+        assert_eq!(call.take_outcome(), Some(ClientOutcome::Value("72.5".to_string())));
+    }
+
+    #[test]
+    fn test_write_call_outcomes() {
+        let start = Instant::now();
+        let write = PropertyWrite { object_id: (2 << 22) | 3, property_id: 85, array_index: None, value: vec![0x00], priority: Some(8) };
+        let mut call = ClientCall::new(5, ClientRequest::WriteProperty(write.clone()));
+        let mut next_invoke_id = 0;
+        call.poll(start, &mut next_invoke_id).unwrap();
+        call.record(&[0x50, 0x00, 0x0F, 0x91, 0x02, 0x91, 0x28]);
+        let outcome = call.take_outcome().unwrap();
+        assert_eq!(outcome, ClientOutcome::Error(2, 40));
+        assert_eq!(outcome.to_string(), "Error: property: write-access-denied");
+
+        // Unanswered: given up after the retries
+        let mut call = ClientCall::new(5, ClientRequest::WriteProperty(write));
+        call.poll(start, &mut next_invoke_id).unwrap();
+        for retry in 1..=CLIENT_CALL_MAX_RETRIES as u32 {
+            assert!(call.poll(start + CLIENT_CALL_TIMEOUT * retry, &mut next_invoke_id).is_some());
+        }
+        assert!(call.poll(start + CLIENT_CALL_TIMEOUT * 10, &mut next_invoke_id).is_none());
+        assert_eq!(call.take_outcome(), Some(ClientOutcome::NoResponse));
+    }
+}
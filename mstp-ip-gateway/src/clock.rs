@@ -0,0 +1,169 @@
+//! Wall-clock time from SNTP
+//!
+//! The RTC starts at the 1970 epoch on every boot, so UTC timestamps are only
+//! reported once SNTP has set the clock; until then callers fall back to
+//! uptime. Events stored as `Instant`s are converted to UTC by their age, so
+//! frames and events recorded before the first sync get real timestamps too.
+//! The configured POSIX TZ string sets local time for log lines and the portal,
+//! and is kept parsed for the Device object's date and time properties.
+
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
+use log::{info, warn};
+use std::ffi::CString;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::tz::{civil_from_days, LocalTime, TimeZone};
+
+/// NTP server used when none is configured
+pub const DEFAULT_NTP_SERVER: &str = "pool.ntp.org";
+
+/// POSIX TZ string used when none is configured
+pub const DEFAULT_TIMEZONE: &str = "UTC0";
+
+/// Longest accepted NTP server name or TZ string
+pub const MAX_CLOCK_STRING_LEN: usize = 63;
+
+/// Clock readings before this (2024-01-01) mean SNTP has not synced yet
+const MIN_VALID_EPOCH_SECS: u64 = 1_704_067_200;
+
+/// The applied timezone, parsed. Also held while the C library's TZ state is
+/// changed or read, so `tzset` never runs under a `localtime_r`.
+static TIMEZONE: Mutex<TimeZone> = Mutex::new(TimeZone::UTC);
+
+/// Start SNTP against `server`; the returned handle must be kept alive
+pub fn start_sntp(server: &str) -> anyhow::Result<EspSntp<'static>> {
+    let server = if server.is_empty() { DEFAULT_NTP_SERVER } else { server };
+    let mut conf = SntpConf::default();
+    conf.servers[0] = server;
+    let sntp = EspSntp::new(&conf)?;
+    info!("SNTP started with server {}", server);
+    Ok(sntp)
+}
+
+/// Apply a POSIX TZ string (e.g. "CET-1CEST,M3.5.0,M10.5.0/3") to local time
+pub fn apply_timezone(tz: &str) {
+    let tz = if tz.is_empty() { DEFAULT_TIMEZONE } else { tz };
+    let (Ok(value), Some(parsed)) = (CString::new(tz), TimeZone::parse(tz)) else {
+        warn!("Ignoring invalid timezone {:?}", tz);
+        return;
+    };
+    let mut timezone = TIMEZONE.lock().unwrap();
+    *timezone = parsed;
+    // SAFETY: both strings are NUL-terminated and outlive the calls (setenv
+    // copies the value). newlib's environment and TZ rules are process-wide
+    // and not locked against readers; holding TIMEZONE keeps format_local's
+    // localtime_r from reading them while they change, and nothing else in
+    // the firmware touches the environment.
+    unsafe {
+        esp_idf_svc::sys::setenv(c"TZ".as_ptr(), value.as_ptr(), 1);
+        esp_idf_svc::sys::tzset();
+    }
+    drop(timezone);
+    info!("Timezone set to {}", tz);
+}
+
+/// Current UTC time, or None until SNTP has set the clock
+pub fn utc_now() -> Option<SystemTime> {
+    let now = SystemTime::now();
+    let secs = now.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (secs >= MIN_VALID_EPOCH_SECS).then_some(now)
+}
+
+/// Current UTC time in seconds since the epoch, or None until SNTP has set the clock
+pub fn utc_secs() -> Option<u64> {
+    Some(utc_now()?.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// The applied timezone (UTC until one is applied)
+pub fn timezone() -> TimeZone {
+    *TIMEZONE.lock().unwrap()
+}
+
+/// Current local time in the applied timezone, or None until SNTP has set the clock
+pub fn local_now() -> Option<LocalTime> {
+    let millis = utc_now()?.duration_since(UNIX_EPOCH).ok()?.as_millis();
+    Some(timezone().local(millis as i64))
+}
+
+/// UTC time at which `instant` occurred, or None until SNTP has set the clock
+pub fn utc_at(instant: Instant) -> Option<SystemTime> {
+    utc_now()?.checked_sub(instant.elapsed())
+}
+
+/// Current UTC day (days since 1970-01-01), or None until SNTP has set the clock
+pub fn utc_day() -> Option<u32> {
+    let secs = utc_now()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some((secs / 86_400) as u32)
+}
+
+/// ISO 8601 date of a day count since 1970-01-01, e.g. "2026-10-18"
+pub fn format_date(days: u32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// ISO 8601 UTC timestamp with milliseconds, e.g. "2026-10-18T09:30:05.123Z"
+pub fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Local time in the configured timezone, e.g. "2026-10-18 11:30:05 CEST"
+pub fn format_local(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as esp_idf_svc::sys::time_t;
+    // SAFETY: `tm` is a plain C struct of integers (and a zone name pointer
+    // localtime_r fills in), for which all zero bits is a valid value
+    let mut tm: esp_idf_svc::sys::tm = unsafe { std::mem::zeroed() };
+    let mut zone = [0u8; 16];
+    // Keeps apply_timezone's tzset from changing the rules mid-conversion
+    let _timezone = TIMEZONE.lock().unwrap();
+    // SAFETY: `secs` and `tm` are valid for the calls; strftime writes at most
+    // `zone.len()` bytes including the NUL. The TZ rules these read are only
+    // changed under TIMEZONE, which is held.
+    unsafe {
+        esp_idf_svc::sys::localtime_r(&secs, &mut tm);
+        esp_idf_svc::sys::strftime(zone.as_mut_ptr() as *mut _, zone.len() as _, c"%Z".as_ptr(), &tm);
+    }
+    let zone_len = zone.iter().position(|&b| b == 0).unwrap_or(0);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        String::from_utf8_lossy(&zone[..zone_len])
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_315_805_123);
+        assert_eq!(format_utc(time), "2026-10-18T09:30:05.123Z");
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(20_744), "2026-10-18");
+        assert_eq!(format_date(0), "1970-01-01");
+    }
+}
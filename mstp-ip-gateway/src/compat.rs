@@ -0,0 +1,192 @@
+//! Compatibility profiles for BACnet/IP clients with known quirks
+//!
+//! Some workstation tools mishandle parts of the standard, so a few of the
+//! gateway's B/IP behaviours can be changed per client subnet. Each rule
+//! maps a subnet (`10.0.5.0/24`, or a single host) to a profile; the most
+//! specific match wins and every other client gets `Default`, which is the
+//! gateway's long-standing behaviour.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// Rules kept (they share one NVS string)
+pub const MAX_COMPAT_RULES: usize = 6;
+
+/// Behaviour switches applied to traffic for one client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatQuirks {
+    /// Unicast the local device's I-Am to the requester as well as broadcasting it
+    pub unicast_i_am: bool,
+    /// Send unicasts as Forwarded-NPDU (carrying our B/IP address) instead of
+    /// Original-Unicast-NPDU
+    pub forwarded_npdu: bool,
+    /// Leave SNET/SADR out of messages on final delivery to the client
+    pub strip_sadr: bool,
+    /// Largest window granted in a SegmentAck (None = whatever the client proposed)
+    pub max_segment_window: Option<u8>,
+}
+
+/// Named sets of quirks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompatProfile {
+    /// Standard behaviour
+    Default,
+    /// Expects Original-NPDU, binds answers to the device itself rather than
+    /// via the router, and copes best with one segment at a time
+    JciCct,
+    /// Lists a device twice when its I-Am also arrives unicast; learns the
+    /// router from Forwarded-NPDU
+    Niagara,
+    /// Drops segments under large windows on busy WiFi
+    Yabe,
+}
+
+impl CompatProfile {
+    pub const ALL: [CompatProfile; 4] = [
+        CompatProfile::Default,
+        CompatProfile::JciCct,
+        CompatProfile::Niagara,
+        CompatProfile::Yabe,
+    ];
+
+    /// Name used in rules and the JSON export
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompatProfile::Default => "default",
+            CompatProfile::JciCct => "jci-cct",
+            CompatProfile::Niagara => "niagara",
+            CompatProfile::Yabe => "yabe",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str().eq_ignore_ascii_case(s))
+    }
+
+    pub fn quirks(&self) -> CompatQuirks {
+        match self {
+            CompatProfile::Default => CompatQuirks {
+                unicast_i_am: true,
+                forwarded_npdu: false,
+                strip_sadr: false,
+                max_segment_window: None,
+            },
+            CompatProfile::JciCct => CompatQuirks {
+                unicast_i_am: true,
+                forwarded_npdu: false,
+                strip_sadr: true,
+                max_segment_window: Some(1),
+            },
+            CompatProfile::Niagara => CompatQuirks {
+                unicast_i_am: false,
+                forwarded_npdu: true,
+                strip_sadr: false,
+                max_segment_window: None,
+            },
+            CompatProfile::Yabe => CompatQuirks {
+                unicast_i_am: true,
+                forwarded_npdu: false,
+                strip_sadr: false,
+                max_segment_window: Some(4),
+            },
+        }
+    }
+}
+
+/// A client subnet and the profile for it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatRule {
+    pub network: Ipv4Addr,
+    pub prefix_len: u8,
+    pub profile: CompatProfile,
+}
+
+impl CompatRule {
+    fn mask(&self) -> u32 {
+        if self.prefix_len == 0 { 0 } else { !0u32 << (32 - u32::from(self.prefix_len)) }
+    }
+
+    pub fn matches(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & self.mask() == u32::from(self.network)
+    }
+}
+
+impl fmt::Display for CompatRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}={}", self.network, self.prefix_len, self.profile.as_str())
+    }
+}
+
+/// Parse `subnet/len=profile` rules separated by commas or semicolons.
+/// A bare address is a single host (/32).
+pub fn parse_rules(s: &str) -> Result<Vec<CompatRule>, String> {
+    let mut rules = Vec::new();
+    for item in s.split([',', ';']).map(str::trim).filter(|i| !i.is_empty()) {
+        let (subnet, profile) = item
+            .split_once('=')
+            .ok_or_else(|| format!("'{}': expected subnet=profile", item))?;
+        let profile = CompatProfile::parse(profile.trim())
+            .ok_or_else(|| format!("'{}': unknown profile", profile.trim()))?;
+        let (addr, prefix_len) = match subnet.trim().split_once('/') {
+            Some((addr, len)) => (addr, len.parse::<u8>().ok().filter(|l| *l <= 32)),
+            None => (subnet.trim(), Some(32)),
+        };
+        let (Ok(addr), Some(prefix_len)) = (addr.parse::<Ipv4Addr>(), prefix_len) else {
+            return Err(format!("'{}': invalid subnet", subnet.trim()));
+        };
+        let mut rule = CompatRule { network: addr, prefix_len, profile };
+        rule.network = Ipv4Addr::from(u32::from(addr) & rule.mask());
+        rules.push(rule);
+    }
+    if rules.len() > MAX_COMPAT_RULES {
+        return Err(format!("at most {} rules", MAX_COMPAT_RULES));
+    }
+    Ok(rules)
+}
+
+/// Rules in the form `parse_rules` accepts
+pub fn format_rules(rules: &[CompatRule]) -> String {
+    rules.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Quirks for a client: the most specific matching rule, else `Default`
+pub fn quirks_for(rules: &[CompatRule], ip: Ipv4Addr) -> CompatQuirks {
+    rules
+        .iter()
+        .filter(|r| r.matches(ip))
+        .max_by_key(|r| r.prefix_len)
+        .map_or(CompatProfile::Default, |r| r.profile)
+        .quirks()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format_rules() {
+        let rules = parse_rules("10.0.5.17/24=niagara; 10.0.9.4=JCI-CCT,").unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].network, Ipv4Addr::new(10, 0, 5, 0));
+        assert_eq!(rules[1].prefix_len, 32);
+        assert_eq!(rules[1].profile, CompatProfile::JciCct);
+        assert_eq!(format_rules(&rules), "10.0.5.0/24=niagara, 10.0.9.4/32=jci-cct");
+        assert_eq!(parse_rules(&format_rules(&rules)).unwrap(), rules);
+
+        assert!(parse_rules("").unwrap().is_empty());
+        assert!(parse_rules("10.0.5.0/24").is_err());
+        assert!(parse_rules("10.0.5.0/33=yabe").is_err());
+        assert!(parse_rules("10.0.5.0/24=bogus").is_err());
+        let too_many = ["10.0.0.1=yabe"; MAX_COMPAT_RULES + 1].join(",");
+        assert!(parse_rules(&too_many).is_err());
+    }
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let rules = parse_rules("10.0.0.0/8=yabe, 10.0.5.0/24=niagara, 10.0.5.17=jci-cct").unwrap();
+        assert_eq!(quirks_for(&rules, Ipv4Addr::new(10, 0, 5, 17)), CompatProfile::JciCct.quirks());
+        assert_eq!(quirks_for(&rules, Ipv4Addr::new(10, 0, 5, 18)), CompatProfile::Niagara.quirks());
+        assert_eq!(quirks_for(&rules, Ipv4Addr::new(10, 1, 0, 1)), CompatProfile::Yabe.quirks());
+        assert_eq!(quirks_for(&rules, Ipv4Addr::new(192, 168, 1, 5)), CompatProfile::Default.quirks());
+    }
+}
@@ -8,6 +8,18 @@ use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
 use log::{info, warn};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
+use crate::auth::{ApiKey, ApiScopes, Role, UserAccount, HASH_LEN, MAX_API_KEYS, MAX_USERNAME_LEN, MAX_USERS, SALT_LEN};
+use crate::blackbox::{CrashReport, MAX_REPORT_LEN};
+use crate::compat::{self, CompatRule};
+use crate::events::{EventLog, MAX_SPILL_LEN};
+use crate::failover::FailoverRole;
+use crate::lifetime::{LifetimeStats, LIFETIME_RECORD_LEN};
+use crate::local_device::ServiceWhitelist;
+use crate::npdu::{NetworkPriority, MAX_HOP_COUNT};
+use crate::schedule::{ScheduledChange, MAX_SCHEDULE_LEN};
+use crate::sla::{SlaTable, MAX_SLA_RECORD_LEN};
+use crate::write_protect;
+
 /// NVS namespace for gateway configuration
 const NVS_NAMESPACE: &str = "bacman_cfg";
 
@@ -19,24 +31,101 @@ mod nvs_keys {
     pub const MSTP_MAX: &str = "mstp_max";
     pub const MSTP_BAUD: &str = "mstp_baud";
     pub const MSTP_NET: &str = "mstp_net";
+    pub const MSTP_PFM: &str = "mstp_pfm";
+    pub const MSTP_NPDU: &str = "mstp_npdu";
+    pub const MSTP_AUTO: &str = "mstp_auto";
+    pub const MSTP_SLAVE: &str = "mstp_slave";
+    pub const BP_HIGH: &str = "bp_high";
+    pub const BP_LOW: &str = "bp_low";
     pub const IP_PORT: &str = "ip_port";
     pub const IP_NET: &str = "ip_net";
+    pub const IP_LEARN: &str = "ip_learn";
+    pub const BIP_MCAST: &str = "bip_mcast";
+    pub const BIP_GROUP: &str = "bip_group";
+    pub const BCAST_FORM: &str = "bcast_form";
+    pub const SUP_IP: &str = "sup_ip";
+    pub const SUP_PORT: &str = "sup_port";
+    pub const SUP_LIST: &str = "sup_list";
+    pub const ANNOUNCE_SCOPE: &str = "ann_scope";
+    pub const ANNOUNCE_NETS: &str = "ann_nets";
+    pub const COMPAT: &str = "compat";
+    pub const DUP_SUPPRESS: &str = "dup_suppress";
+    pub const WHOIS_FILTER: &str = "whois_filter";
+    pub const NPDU_HOPS: &str = "npdu_hops";
+    pub const NPDU_PRIO: &str = "npdu_prio";
+    pub const NPDU_LENIENT: &str = "npdu_lenient";
+    pub const BBMD_ADDR: &str = "bbmd_addr";
+    pub const SBVLL_DROP: &str = "sbvll_drop";
     pub const DEV_INST: &str = "dev_inst";
+    pub const LOCAL_DEV: &str = "local_dev";
     pub const DEV_NAME: &str = "dev_name";
+    pub const DEV_LOC: &str = "dev_loc";
+    pub const DEV_DESC: &str = "dev_desc";
+    pub const DEV_SERIAL: &str = "dev_serial";
+    pub const DEV_SVC: &str = "dev_svc";
     pub const CONFIGURED: &str = "configured";
+    pub const WIZ_STEP: &str = "wiz_step";
     // AP mode settings
     pub const AP_SSID: &str = "ap_ssid";
     pub const AP_PASS: &str = "ap_pass";
+    pub const AP_STA: &str = "ap_sta";
+    pub const AP_TIMEOUT: &str = "ap_timeout";
     // BDT persistence (stores as comma-separated IP:port list)
     pub const BDT_ENTRIES: &str = "bdt_entries";
     pub const BDT_COUNT: &str = "bdt_count";
     // Routing table persistence
     pub const RT_ENTRIES: &str = "rt_entries";
     pub const RT_COUNT: &str = "rt_count";
+    // Static routes to distant networks
+    pub const SR_ENTRIES: &str = "sr_entries";
+    pub const SR_COUNT: &str = "sr_count";
+    // Heartbeat reporting
+    pub const HB_ENABLED: &str = "hb_enabled";
+    pub const HB_URL: &str = "hb_url";
+    pub const HB_INTERVAL: &str = "hb_interval";
+    pub const PUB_PORT: &str = "pub_port";
+    pub const CON_PORT: &str = "con_port";
+    pub const SITE_NAME: &str = "site_name";
+    // Webhook notifications
+    pub const WH_ENABLED: &str = "wh_enabled";
+    pub const WH_URL: &str = "wh_url";
+    pub const WH_SCAN_H: &str = "wh_scan_h";
+    pub const WH_ERR_THR: &str = "wh_err_thr";
+    // Wall clock
+    pub const NTP_SERVER: &str = "ntp_server";
+    pub const TIMEZONE: &str = "timezone";
+    // Web portal accounts
+    pub const USR_ENTRIES: &str = "usr_entries";
+    pub const USR_COUNT: &str = "usr_count";
+    // API keys for scripts
+    pub const KEY_ENTRIES: &str = "key_entries";
+    pub const KEY_COUNT: &str = "key_count";
+    // Device friendly names and notes
+    pub const LBL_ENTRIES: &str = "lbl_entries";
+    pub const LBL_COUNT: &str = "lbl_count";
+    // Lifetime statistics
+    pub const LIFE_EN: &str = "life_en";
+    pub const LIFE_STATS: &str = "life_stats";
+    // Event log spillover
+    pub const EVT_SPILL: &str = "evt_spill";
+    pub const EVT_LOG: &str = "evt_log";
+    // Log output format
+    pub const LOG_JSON: &str = "log_json";
+    // Hardware write-protect jumper
+    pub const WP_PIN: &str = "wp_pin";
+    // Redundancy pair
+    pub const FO_ROLE: &str = "fo_role";
+    pub const FO_PEER: &str = "fo_peer";
+    // Blackbox recording kept after an unexpected reset
+    pub const CRASH_BB: &str = "crash_bb";
+    // Per-device response statistics
+    pub const SLA_STATS: &str = "sla_stats";
+    // One-time configuration change waiting for its time or for the ring
+    pub const SCHED_CHG: &str = "sched_chg";
 }
 
 /// Gateway configuration settings
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GatewayConfig {
     // WiFi Station mode settings
     pub wifi_ssid: String,
@@ -45,20 +134,120 @@ pub struct GatewayConfig {
     // WiFi Access Point mode settings
     pub ap_ssid: String,
     pub ap_password: String,
+    /// Keep the soft AP up alongside the station connection (APSTA)
+    pub ap_with_sta: bool,
+    /// Turn the APSTA soft AP off after this many minutes (0 = never)
+    pub ap_timeout_minutes: u16,
 
     // MS/TP settings
     pub mstp_address: u8,
+    /// Pick a free station address at startup (the last one found is preferred)
+    pub mstp_auto_address: bool,
+    /// Run as an MS/TP slave: answer requests for the gateway's own objects
+    /// without joining the token ring (station address may then be 0-254)
+    pub mstp_slave_mode: bool,
     pub mstp_max_master: u8,
     pub mstp_baud_rate: u32,
     pub mstp_network: u16,
+    pub mstp_pfm_aggressiveness: u8,
+    /// Largest NPDU routed onto MS/TP; longer IP messages get Reject-Message-To-Network
+    pub mstp_max_npdu: u16,
+    /// MS/TP send queue depth at which confirmed requests from IP get an Abort (0 = off)
+    pub backpressure_high: u8,
+    /// Queue depth at which confirmed requests from IP are accepted again
+    pub backpressure_low: u8,
 
     // BACnet/IP settings
     pub bacnet_ip_port: u16,
     pub ip_network: u16,
+    /// Learn the IP network number from Network-Number-Is (ip_network until then)
+    pub ip_network_learn: bool,
+    pub bip_multicast_enabled: bool,
+    pub bip_multicast_group: Ipv4Addr,
+    /// Directed subnet broadcast, limited broadcast (255.255.255.255), both,
+    /// or none at all where only the supervisory stations are reached
+    pub bip_broadcast_form: BroadcastForm,
+    /// Stations that get a Forwarded-NPDU unicast copy of every B/IP broadcast
+    pub supervisory_stations: Vec<SocketAddr>,
+    /// Ports the periodic I-Am-Router-To-Network announcements go out on
+    pub announce_scope: AnnounceScope,
+    /// Further networks announced with our own, e.g. ones behind a static route
+    pub announce_networks: Vec<u16>,
+    /// Compatibility profiles for B/IP clients, by source subnet
+    pub compat_rules: Vec<CompatRule>,
+    /// Stop routing into the MS/TP network while another router claims its number
+    pub suppress_on_duplicate_network: bool,
+    /// Don't forward a ranged Who-Is to MS/TP when every device in range is known on IP
+    pub whois_filter_enabled: bool,
+    /// Hop count on routed NPDUs the gateway originates (announcements, proxy replies)
+    pub npdu_hop_count: u8,
+    /// Network priority on NPDUs the gateway originates
+    pub npdu_priority: NetworkPriority,
+    /// Repair and route NPDUs with known legacy header deviations instead of rejecting them
+    pub npdu_lenient: bool,
+    /// BBMD this site registers with or peers to, checked by the reachability tool
+    pub bbmd_address: Option<Ipv4Addr>,
+    /// Drop Secure-BVLL messages silently instead of answering with a BVLC-Result NAK
+    /// (Annex J defines no result code for them, so dropping is the default)
+    pub secure_bvll_drop: bool,
 
     // Gateway settings
+    /// False = transparent router: no Device object, no I-Am, no local services
+    pub local_device_enabled: bool,
     pub device_instance: u32,
     pub device_name: String,
+    pub device_location: String,
+    pub device_description: String,
+    /// Empty = derived from the WiFi MAC at startup
+    pub device_serial_number: String,
+    /// Services the gateway's Device object answers
+    pub device_services: ServiceWhitelist,
+
+    // Heartbeat reporting
+    pub heartbeat_enabled: bool,
+    /// HTTPS endpoint that receives the JSON heartbeat
+    pub heartbeat_url: String,
+    pub heartbeat_interval_secs: u32,
+    /// TCP port of the read-only public status server (0 = disabled)
+    pub public_status_port: u16,
+    /// TCP port of the remote console (0 = disabled)
+    pub remote_console_port: u16,
+    /// Site label included in heartbeats to tell gateways apart in a fleet
+    pub site_name: String,
+
+    // Webhook notifications
+    pub webhook_enabled: bool,
+    /// HTTP(S) endpoint that receives event notifications
+    pub webhook_url: String,
+    /// Hours between scheduled discovery scans (0 = none)
+    pub webhook_scan_hours: u32,
+    /// Routing and CRC errors per window that trigger a notification (0 = off)
+    pub webhook_error_threshold: u32,
+
+    // Wall clock
+    /// SNTP server (Station mode only)
+    pub ntp_server: String,
+    /// POSIX TZ string for local time in log lines and the portal
+    pub timezone: String,
+
+    /// Checkpoint cumulative counters, boot count and reboot reason to NVS
+    pub lifetime_stats_enabled: bool,
+    /// Keep the latest event log warnings and errors in NVS across reboots
+    pub event_spill_enabled: bool,
+    /// Write log output as JSON lines instead of ESP-IDF text
+    pub log_json: bool,
+    /// GPIO sampled for the write-protect jumper (0 = none)
+    pub write_protect_pin: u8,
+    /// Part this unit plays in a primary/standby redundancy pair
+    pub failover_role: FailoverRole,
+    /// IP address of the other unit of the pair
+    pub failover_peer: Option<Ipv4Addr>,
+
+    // Commissioning state (not edited directly)
+    /// True once a complete configuration has been saved
+    pub configured: bool,
+    /// Current step of the first-run wizard (0 = not started)
+    pub commissioning_step: u8,
 }
 
 impl Default for GatewayConfig {
@@ -73,20 +262,76 @@ impl Default for GatewayConfig {
             // Password must be 8+ characters for WPA2
             ap_ssid: "BACman-Gateway".to_string(),
             ap_password: "bacnet123".to_string(),
+            ap_with_sta: false,     // AP only when button B is pressed
+            ap_timeout_minutes: 0,  // Never turn the APSTA hotspot off
 
             // MS/TP settings
             mstp_address: 3,        // Gateway's MS/TP address (0-127 for master)
+            mstp_auto_address: false, // Fixed address unless enabled
+            mstp_slave_mode: false, // Take part in the token ring as a master
             mstp_max_master: 127,   // Maximum master address on network
             mstp_baud_rate: 38400,  // Standard MS/TP baud rate
             mstp_network: 65001,    // BACnet network number for MS/TP side
+            mstp_pfm_aggressiveness: 0, // 0 = standard Poll-For-Master sweep
+            mstp_max_npdu: 501,     // Full classic MS/TP frame
+            backpressure_high: 12,  // Abort new confirmed requests at 12 of 16 queued frames
+            backpressure_low: 8,    // ...until the queue drains to 8
 
             // BACnet/IP settings
             bacnet_ip_port: 47808,  // Standard BACnet/IP port (0xBAC0)
             ip_network: 10001,      // BACnet network number for IP side
+            ip_network_learn: false,
+            bip_multicast_enabled: false, // Subnet broadcast unless site requires Annex J.7 multicast
+            bip_multicast_group: Ipv4Addr::new(239, 255, 186, 192),
+            bip_broadcast_form: BroadcastForm::Directed,
+            supervisory_stations: Vec::new(),
+            announce_scope: AnnounceScope::Both,
+            announce_networks: Vec::new(),
+            compat_rules: Vec::new(),
+            suppress_on_duplicate_network: false, // Warn only
+            whois_filter_enabled: true,
+            npdu_hop_count: MAX_HOP_COUNT,
+            npdu_priority: NetworkPriority::Normal,
+            npdu_lenient: false, // Strict
+            bbmd_address: None,
+            secure_bvll_drop: true, // Annex J has no NAK code for Secure-BVLL
 
             // Gateway device settings
+            local_device_enabled: true,
             device_instance: 1234,
             device_name: "BACman-Gateway".to_string(),
+            device_location: String::new(),
+            device_description: "BACnet MS/TP to IP Gateway".to_string(),
+            device_serial_number: String::new(),
+            device_services: ServiceWhitelist::ALL,
+
+            // Heartbeat reporting - off until an endpoint is configured
+            heartbeat_enabled: false,
+            heartbeat_url: String::new(),
+            heartbeat_interval_secs: 300,
+            public_status_port: 0,
+            remote_console_port: 0,
+            site_name: String::new(),
+
+            // Webhook notifications - off until an endpoint is configured
+            webhook_enabled: false,
+            webhook_url: String::new(),
+            webhook_scan_hours: 0,
+            webhook_error_threshold: 0,
+
+            // Wall clock - UTC unless a zone is configured
+            ntp_server: "pool.ntp.org".to_string(),
+            timezone: "UTC0".to_string(),
+
+            lifetime_stats_enabled: false, // Opt-in: checkpoints write to flash
+            event_spill_enabled: false, // Opt-in: spills write to flash
+            log_json: false,
+            write_protect_pin: 0,
+            failover_role: FailoverRole::Off, // Single unit
+            failover_peer: None,
+
+            configured: false,
+            commissioning_step: 0,
         }
     }
 }
@@ -110,14 +355,25 @@ impl GatewayConfig {
             .map(|v| v != 0)
             .unwrap_or(false);
 
-        if !configured {
+        let mut config = Self::default();
+
+        // Site info can be written over BACnet before the gateway is configured
+        Self::load_site_info(&nvs, &mut config);
+
+        // An unfinished commissioning wizard resumes with its saved values
+        config.commissioning_step = nvs.get_u8(nvs_keys::WIZ_STEP).ok().flatten().unwrap_or(0);
+
+        if !configured && config.commissioning_step == 0 {
             info!("No saved configuration found, using defaults");
-            return Ok(Self::default());
+            return Ok(config);
         }
 
-        info!("Loading configuration from NVS...");
-
-        let mut config = Self::default();
+        if configured {
+            info!("Loading configuration from NVS...");
+        } else {
+            info!("Resuming commissioning at step {}", config.commissioning_step);
+        }
+        config.configured = configured;
 
         // Load WiFi Station mode settings
         if let Ok(Some(ssid)) = Self::get_string(&nvs, nvs_keys::WIFI_SSID) {
@@ -134,11 +390,23 @@ impl GatewayConfig {
         if let Ok(Some(ap_pass)) = Self::get_string(&nvs, nvs_keys::AP_PASS) {
             config.ap_password = ap_pass;
         }
+        if let Ok(Some(apsta)) = nvs.get_u8(nvs_keys::AP_STA) {
+            config.ap_with_sta = apsta != 0;
+        }
+        if let Ok(Some(minutes)) = nvs.get_u16(nvs_keys::AP_TIMEOUT) {
+            config.ap_timeout_minutes = minutes;
+        }
 
         // Load MS/TP settings
         if let Ok(Some(addr)) = nvs.get_u8(nvs_keys::MSTP_ADDR) {
             config.mstp_address = addr;
         }
+        if let Ok(Some(auto)) = nvs.get_u8(nvs_keys::MSTP_AUTO) {
+            config.mstp_auto_address = auto != 0;
+        }
+        if let Ok(Some(slave)) = nvs.get_u8(nvs_keys::MSTP_SLAVE) {
+            config.mstp_slave_mode = slave != 0;
+        }
         if let Ok(Some(max)) = nvs.get_u8(nvs_keys::MSTP_MAX) {
             config.mstp_max_master = max;
         }
@@ -148,6 +416,18 @@ impl GatewayConfig {
         if let Ok(Some(net)) = nvs.get_u16(nvs_keys::MSTP_NET) {
             config.mstp_network = net;
         }
+        if let Ok(Some(pfm)) = nvs.get_u8(nvs_keys::MSTP_PFM) {
+            config.mstp_pfm_aggressiveness = pfm;
+        }
+        if let Ok(Some(npdu)) = nvs.get_u16(nvs_keys::MSTP_NPDU) {
+            config.mstp_max_npdu = npdu;
+        }
+        if let Ok(Some(high)) = nvs.get_u8(nvs_keys::BP_HIGH) {
+            config.backpressure_high = high;
+        }
+        if let Ok(Some(low)) = nvs.get_u8(nvs_keys::BP_LOW) {
+            config.backpressure_low = low;
+        }
 
         // Load BACnet/IP settings
         if let Ok(Some(port)) = nvs.get_u16(nvs_keys::IP_PORT) {
@@ -156,15 +436,153 @@ impl GatewayConfig {
         if let Ok(Some(net)) = nvs.get_u16(nvs_keys::IP_NET) {
             config.ip_network = net;
         }
+        if let Ok(Some(learn)) = nvs.get_u8(nvs_keys::IP_LEARN) {
+            config.ip_network_learn = learn != 0;
+        }
+        if let Ok(Some(mcast)) = nvs.get_u8(nvs_keys::BIP_MCAST) {
+            config.bip_multicast_enabled = mcast != 0;
+        }
+        if let Ok(Some(group)) = nvs.get_u32(nvs_keys::BIP_GROUP) {
+            let group = Ipv4Addr::from(group);
+            if group.is_multicast() {
+                config.bip_multicast_group = group;
+            }
+        }
+        if let Ok(Some(form)) = nvs.get_u8(nvs_keys::BCAST_FORM) {
+            config.bip_broadcast_form = BroadcastForm::from_u8(form);
+        }
+        if let Ok(Some(list)) = Self::get_string(&nvs, nvs_keys::SUP_LIST) {
+            match parse_supervisory_stations(&list) {
+                Ok(stations) => config.supervisory_stations = stations,
+                Err(e) => warn!("Ignoring stored supervisory stations '{}': {}", list, e),
+            }
+        } else if let Ok(Some(ip)) = nvs.get_u32(nvs_keys::SUP_IP) {
+            // Single station saved before the list; 0 = not configured
+            let ip = Ipv4Addr::from(ip);
+            let port = nvs.get_u16(nvs_keys::SUP_PORT).ok().flatten().unwrap_or(47808);
+            if !ip.is_unspecified() {
+                config.supervisory_stations = vec![SocketAddr::new(IpAddr::V4(ip), port)];
+            }
+        }
+        if let Ok(Some(scope)) = nvs.get_u8(nvs_keys::ANNOUNCE_SCOPE) {
+            config.announce_scope = AnnounceScope::from_u8(scope);
+        }
+        if let Ok(Some(list)) = Self::get_string(&nvs, nvs_keys::ANNOUNCE_NETS) {
+            match parse_network_list(&list) {
+                Ok(networks) => config.announce_networks = networks,
+                Err(e) => warn!("Ignoring stored announced networks '{}': {}", list, e),
+            }
+        }
+        if let Ok(Some(rules)) = Self::get_string(&nvs, nvs_keys::COMPAT) {
+            match compat::parse_rules(&rules) {
+                Ok(rules) => config.compat_rules = rules,
+                Err(e) => warn!("Ignoring stored compatibility rules: {}", e),
+            }
+        }
+        if let Ok(Some(suppress)) = nvs.get_u8(nvs_keys::DUP_SUPPRESS) {
+            config.suppress_on_duplicate_network = suppress != 0;
+        }
+        if let Ok(Some(filter)) = nvs.get_u8(nvs_keys::WHOIS_FILTER) {
+            config.whois_filter_enabled = filter != 0;
+        }
+        if let Ok(Some(hops)) = nvs.get_u8(nvs_keys::NPDU_HOPS) {
+            // 0 would be discarded by the first router
+            if hops > 0 {
+                config.npdu_hop_count = hops;
+            }
+        }
+        if let Ok(Some(prio)) = nvs.get_u8(nvs_keys::NPDU_PRIO) {
+            config.npdu_priority = NetworkPriority::from_u8(prio);
+        }
+        if let Ok(Some(lenient)) = nvs.get_u8(nvs_keys::NPDU_LENIENT) {
+            config.npdu_lenient = lenient != 0;
+        }
+        if let Ok(Some(bbmd)) = nvs.get_u32(nvs_keys::BBMD_ADDR) {
+            // 0 = not configured
+            config.bbmd_address = Some(Ipv4Addr::from(bbmd)).filter(|a| !a.is_unspecified());
+        }
+        if let Ok(Some(drop)) = nvs.get_u8(nvs_keys::SBVLL_DROP) {
+            config.secure_bvll_drop = drop != 0;
+        }
 
         // Load device settings
+        if let Ok(Some(enabled)) = nvs.get_u8(nvs_keys::LOCAL_DEV) {
+            config.local_device_enabled = enabled != 0;
+        }
         if let Ok(Some(inst)) = nvs.get_u32(nvs_keys::DEV_INST) {
             config.device_instance = inst;
         }
+        if let Ok(Some(bits)) = nvs.get_u8(nvs_keys::DEV_SVC) {
+            config.device_services = ServiceWhitelist::from_bits(bits);
+        }
         if let Ok(Some(name)) = Self::get_string(&nvs, nvs_keys::DEV_NAME) {
             config.device_name = name;
         }
 
+        // Load heartbeat settings
+        if let Ok(Some(enabled)) = nvs.get_u8(nvs_keys::HB_ENABLED) {
+            config.heartbeat_enabled = enabled != 0;
+        }
+        if let Ok(Some(url)) = Self::get_string(&nvs, nvs_keys::HB_URL) {
+            config.heartbeat_url = url;
+        }
+        if let Ok(Some(interval)) = nvs.get_u32(nvs_keys::HB_INTERVAL) {
+            config.heartbeat_interval_secs = interval;
+        }
+        if let Ok(Some(port)) = nvs.get_u16(nvs_keys::PUB_PORT) {
+            config.public_status_port = port;
+        }
+        if let Ok(Some(port)) = nvs.get_u16(nvs_keys::CON_PORT) {
+            config.remote_console_port = port;
+        }
+        if let Ok(Some(site)) = Self::get_string(&nvs, nvs_keys::SITE_NAME) {
+            config.site_name = site;
+        }
+
+        // Load webhook settings
+        if let Ok(Some(enabled)) = nvs.get_u8(nvs_keys::WH_ENABLED) {
+            config.webhook_enabled = enabled != 0;
+        }
+        if let Ok(Some(url)) = Self::get_string(&nvs, nvs_keys::WH_URL) {
+            config.webhook_url = url;
+        }
+        if let Ok(Some(hours)) = nvs.get_u32(nvs_keys::WH_SCAN_H) {
+            config.webhook_scan_hours = hours;
+        }
+        if let Ok(Some(threshold)) = nvs.get_u32(nvs_keys::WH_ERR_THR) {
+            config.webhook_error_threshold = threshold;
+        }
+
+        // Load wall clock settings
+        if let Ok(Some(server)) = Self::get_string(&nvs, nvs_keys::NTP_SERVER) {
+            config.ntp_server = server;
+        }
+        if let Ok(Some(tz)) = Self::get_string(&nvs, nvs_keys::TIMEZONE) {
+            config.timezone = tz;
+        }
+
+        if let Ok(Some(enabled)) = nvs.get_u8(nvs_keys::LIFE_EN) {
+            config.lifetime_stats_enabled = enabled != 0;
+        }
+        if let Ok(Some(enabled)) = nvs.get_u8(nvs_keys::EVT_SPILL) {
+            config.event_spill_enabled = enabled != 0;
+        }
+        if let Ok(Some(enabled)) = nvs.get_u8(nvs_keys::LOG_JSON) {
+            config.log_json = enabled != 0;
+        }
+        if let Ok(Some(pin)) = nvs.get_u8(nvs_keys::WP_PIN) {
+            if write_protect::valid_pin(pin) {
+                config.write_protect_pin = pin;
+            }
+        }
+        if let Ok(Some(role)) = nvs.get_u8(nvs_keys::FO_ROLE) {
+            config.failover_role = FailoverRole::from_u8(role);
+        }
+        if let Ok(Some(peer)) = nvs.get_u32(nvs_keys::FO_PEER) {
+            // 0 = not configured
+            config.failover_peer = Some(Ipv4Addr::from(peer)).filter(|a| !a.is_unspecified());
+        }
+
         info!("Configuration loaded from NVS");
         Ok(config)
     }
@@ -174,39 +592,142 @@ impl GatewayConfig {
         let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
 
         info!("Saving configuration to NVS...");
+        self.write_settings(&mut nvs)?;
 
+        // Mark as configured (completes any commissioning in progress)
+        nvs.set_u8(nvs_keys::CONFIGURED, 1)?;
+        nvs.set_u8(nvs_keys::WIZ_STEP, 0)?;
+
+        info!("Configuration saved to NVS");
+        Ok(())
+    }
+
+    /// Save the values entered so far in the commissioning wizard and the
+    /// step to resume at, without marking the gateway as configured
+    pub fn save_commissioning_progress(&self, nvs_partition: EspNvsPartition<NvsDefault>) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        self.write_settings(&mut nvs)?;
+        nvs.set_u8(nvs_keys::WIZ_STEP, self.commissioning_step)?;
+        info!("Commissioning progress saved (step {})", self.commissioning_step);
+        Ok(())
+    }
+
+    /// Write all settings values to NVS
+    fn write_settings(&self, nvs: &mut EspNvs<NvsDefault>) -> Result<(), anyhow::Error> {
         // Save WiFi Station mode settings
-        Self::set_string(&mut nvs, nvs_keys::WIFI_SSID, &self.wifi_ssid)?;
-        Self::set_string(&mut nvs, nvs_keys::WIFI_PASS, &self.wifi_password)?;
+        Self::set_string(nvs, nvs_keys::WIFI_SSID, &self.wifi_ssid)?;
+        Self::set_string(nvs, nvs_keys::WIFI_PASS, &self.wifi_password)?;
 
         // Save WiFi AP mode settings
-        Self::set_string(&mut nvs, nvs_keys::AP_SSID, &self.ap_ssid)?;
-        Self::set_string(&mut nvs, nvs_keys::AP_PASS, &self.ap_password)?;
+        Self::set_string(nvs, nvs_keys::AP_SSID, &self.ap_ssid)?;
+        Self::set_string(nvs, nvs_keys::AP_PASS, &self.ap_password)?;
+        nvs.set_u8(nvs_keys::AP_STA, self.ap_with_sta as u8)?;
+        nvs.set_u16(nvs_keys::AP_TIMEOUT, self.ap_timeout_minutes)?;
 
         // Save MS/TP settings
         nvs.set_u8(nvs_keys::MSTP_ADDR, self.mstp_address)?;
+        nvs.set_u8(nvs_keys::MSTP_AUTO, self.mstp_auto_address as u8)?;
+        nvs.set_u8(nvs_keys::MSTP_SLAVE, self.mstp_slave_mode as u8)?;
         nvs.set_u8(nvs_keys::MSTP_MAX, self.mstp_max_master)?;
         nvs.set_u32(nvs_keys::MSTP_BAUD, self.mstp_baud_rate)?;
         nvs.set_u16(nvs_keys::MSTP_NET, self.mstp_network)?;
+        nvs.set_u8(nvs_keys::MSTP_PFM, self.mstp_pfm_aggressiveness)?;
+        nvs.set_u16(nvs_keys::MSTP_NPDU, self.mstp_max_npdu)?;
+        nvs.set_u8(nvs_keys::BP_HIGH, self.backpressure_high)?;
+        nvs.set_u8(nvs_keys::BP_LOW, self.backpressure_low)?;
 
         // Save BACnet/IP settings
         nvs.set_u16(nvs_keys::IP_PORT, self.bacnet_ip_port)?;
         nvs.set_u16(nvs_keys::IP_NET, self.ip_network)?;
+        nvs.set_u8(nvs_keys::IP_LEARN, self.ip_network_learn as u8)?;
+        nvs.set_u8(nvs_keys::BIP_MCAST, self.bip_multicast_enabled as u8)?;
+        nvs.set_u32(nvs_keys::BIP_GROUP, u32::from(self.bip_multicast_group))?;
+        nvs.set_u8(nvs_keys::BCAST_FORM, self.bip_broadcast_form as u8)?;
+        Self::set_string(nvs, nvs_keys::SUP_LIST, &format_supervisory_stations(&self.supervisory_stations))?;
+        nvs.set_u8(nvs_keys::ANNOUNCE_SCOPE, self.announce_scope as u8)?;
+        Self::set_string(nvs, nvs_keys::ANNOUNCE_NETS, &format_network_list(&self.announce_networks))?;
+        Self::set_string(nvs, nvs_keys::COMPAT, &compat::format_rules(&self.compat_rules))?;
+        nvs.set_u8(nvs_keys::DUP_SUPPRESS, self.suppress_on_duplicate_network as u8)?;
+        nvs.set_u8(nvs_keys::WHOIS_FILTER, self.whois_filter_enabled as u8)?;
+        nvs.set_u8(nvs_keys::NPDU_HOPS, self.npdu_hop_count)?;
+        nvs.set_u8(nvs_keys::NPDU_PRIO, self.npdu_priority as u8)?;
+        nvs.set_u8(nvs_keys::NPDU_LENIENT, self.npdu_lenient as u8)?;
+        nvs.set_u32(nvs_keys::BBMD_ADDR, self.bbmd_address.map(u32::from).unwrap_or(0))?;
+        nvs.set_u8(nvs_keys::SBVLL_DROP, self.secure_bvll_drop as u8)?;
 
         // Save device settings
+        nvs.set_u8(nvs_keys::LOCAL_DEV, self.local_device_enabled as u8)?;
         nvs.set_u32(nvs_keys::DEV_INST, self.device_instance)?;
-        Self::set_string(&mut nvs, nvs_keys::DEV_NAME, &self.device_name)?;
+        Self::set_string(nvs, nvs_keys::DEV_NAME, &self.device_name)?;
+        Self::set_string(nvs, nvs_keys::DEV_LOC, &self.device_location)?;
+        Self::set_string(nvs, nvs_keys::DEV_DESC, &self.device_description)?;
+        Self::set_string(nvs, nvs_keys::DEV_SERIAL, &self.device_serial_number)?;
+        nvs.set_u8(nvs_keys::DEV_SVC, self.device_services.bits())?;
 
-        // Mark as configured
-        nvs.set_u8(nvs_keys::CONFIGURED, 1)?;
+        // Save heartbeat settings
+        nvs.set_u8(nvs_keys::HB_ENABLED, self.heartbeat_enabled as u8)?;
+        Self::set_string(nvs, nvs_keys::HB_URL, &self.heartbeat_url)?;
+        nvs.set_u32(nvs_keys::HB_INTERVAL, self.heartbeat_interval_secs)?;
+        nvs.set_u16(nvs_keys::PUB_PORT, self.public_status_port)?;
+        nvs.set_u16(nvs_keys::CON_PORT, self.remote_console_port)?;
+        Self::set_string(nvs, nvs_keys::SITE_NAME, &self.site_name)?;
 
-        info!("Configuration saved to NVS");
+        // Save webhook settings
+        nvs.set_u8(nvs_keys::WH_ENABLED, self.webhook_enabled as u8)?;
+        Self::set_string(nvs, nvs_keys::WH_URL, &self.webhook_url)?;
+        nvs.set_u32(nvs_keys::WH_SCAN_H, self.webhook_scan_hours)?;
+        nvs.set_u32(nvs_keys::WH_ERR_THR, self.webhook_error_threshold)?;
+
+        // Save wall clock settings
+        Self::set_string(nvs, nvs_keys::NTP_SERVER, &self.ntp_server)?;
+        Self::set_string(nvs, nvs_keys::TIMEZONE, &self.timezone)?;
+
+        nvs.set_u8(nvs_keys::LIFE_EN, self.lifetime_stats_enabled as u8)?;
+        nvs.set_u8(nvs_keys::EVT_SPILL, self.event_spill_enabled as u8)?;
+        nvs.set_u8(nvs_keys::LOG_JSON, self.log_json as u8)?;
+        nvs.set_u8(nvs_keys::WP_PIN, self.write_protect_pin)?;
+        nvs.set_u8(nvs_keys::FO_ROLE, self.failover_role as u8)?;
+        nvs.set_u32(nvs_keys::FO_PEER, self.failover_peer.map(u32::from).unwrap_or(0))?;
+
+        Ok(())
+    }
+
+    /// Load Location, Description and Serial_Number
+    fn load_site_info(nvs: &EspNvs<NvsDefault>, config: &mut Self) {
+        if let Ok(Some(loc)) = Self::get_string(nvs, nvs_keys::DEV_LOC) {
+            config.device_location = loc;
+        }
+        if let Ok(Some(desc)) = Self::get_string(nvs, nvs_keys::DEV_DESC) {
+            config.device_description = desc;
+        }
+        if let Ok(Some(serial)) = Self::get_string(nvs, nvs_keys::DEV_SERIAL) {
+            config.device_serial_number = serial;
+        }
+    }
+
+    /// Save only Location, Description and Serial_Number
+    /// (used when they are written over BACnet, without committing other pending edits)
+    pub fn save_site_info(&self, nvs_partition: EspNvsPartition<NvsDefault>) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        Self::set_string(&mut nvs, nvs_keys::DEV_LOC, &self.device_location)?;
+        Self::set_string(&mut nvs, nvs_keys::DEV_DESC, &self.device_description)?;
+        Self::set_string(&mut nvs, nvs_keys::DEV_SERIAL, &self.device_serial_number)?;
+        info!("Device site info saved to NVS");
+        Ok(())
+    }
+
+    /// Save only the MS/TP station address (chosen by auto-address at startup)
+    pub fn save_mstp_address(&self, nvs_partition: EspNvsPartition<NvsDefault>) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.set_u8(nvs_keys::MSTP_ADDR, self.mstp_address)?;
+        info!("MS/TP station address {} saved to NVS", self.mstp_address);
         Ok(())
     }
 
     /// Helper to get string from NVS
     fn get_string(nvs: &EspNvs<NvsDefault>, key: &str) -> Result<Option<String>, anyhow::Error> {
-        let mut buf = [0u8; 64];
+        // Sized for the compatibility rules, the longest stored string
+        let mut buf = [0u8; 200];
         match nvs.get_str(key, &mut buf) {
             Ok(Some(s)) => Ok(Some(s.to_string())),
             Ok(None) => Ok(None),
@@ -227,6 +748,7 @@ impl GatewayConfig {
     pub fn clear_nvs(nvs_partition: EspNvsPartition<NvsDefault>) -> Result<(), anyhow::Error> {
         let nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
         nvs.set_u8(nvs_keys::CONFIGURED, 0)?;
+        nvs.set_u8(nvs_keys::WIZ_STEP, 0)?;
         info!("Configuration cleared - will use defaults on next boot");
         Ok(())
     }
@@ -247,6 +769,178 @@ pub struct RoutingTableEntryConfig {
     pub port_info: Vec<u8>,
 }
 
+/// How B/IP broadcasts are addressed; some OT firewalls drop one form or the other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BroadcastForm {
+    /// Directed subnet broadcast (e.g. 192.168.1.255)
+    #[default]
+    Directed = 0,
+    /// Limited broadcast (255.255.255.255)
+    Limited = 1,
+    /// One copy of each
+    Both = 2,
+    /// No broadcast; the supervisory stations' unicast copies only
+    Disabled = 3,
+}
+
+impl BroadcastForm {
+    /// Decode the NVS value, falling back to directed broadcast
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => BroadcastForm::Limited,
+            2 => BroadcastForm::Both,
+            3 => BroadcastForm::Disabled,
+            _ => BroadcastForm::Directed,
+        }
+    }
+
+    /// Form value used by the portal and console
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BroadcastForm::Directed => "directed",
+            BroadcastForm::Limited => "limited",
+            BroadcastForm::Both => "both",
+            BroadcastForm::Disabled => "none",
+        }
+    }
+}
+
+/// Most supervisory stations that get a copy of each B/IP broadcast
+pub const MAX_SUPERVISORY_STATIONS: usize = 4;
+
+/// Parse a comma separated list of supervisory stations, each IP or IP:port
+/// (default 47808); empty clears the list
+pub fn parse_supervisory_stations(s: &str) -> Result<Vec<SocketAddr>, &'static str> {
+    let usable = |ip: &Ipv4Addr| !ip.is_unspecified() && !ip.is_multicast() && !ip.is_broadcast();
+    let mut stations = Vec::new();
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let station = if let Ok(v) = item.parse::<SocketAddr>() {
+            v
+        } else if let Ok(v) = item.parse::<Ipv4Addr>() {
+            SocketAddr::new(IpAddr::V4(v), 47808)
+        } else {
+            return Err("expected IP or IP:port");
+        };
+        if !matches!(station.ip(), IpAddr::V4(ip) if usable(&ip)) {
+            return Err("expected a unicast IPv4 address");
+        }
+        if !stations.contains(&station) {
+            stations.push(station);
+        }
+    }
+    if stations.len() > MAX_SUPERVISORY_STATIONS {
+        return Err("too many supervisory stations");
+    }
+    Ok(stations)
+}
+
+pub fn format_supervisory_stations(stations: &[SocketAddr]) -> String {
+    stations.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Where the periodic I-Am-Router-To-Network announcements are broadcast.
+/// Who-Is-Router-To-Network queries are answered either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AnnounceScope {
+    /// The MS/TP side networks on IP and the IP side networks on MS/TP
+    #[default]
+    Both = 0,
+    /// On the IP network only
+    Ip = 1,
+    /// On the MS/TP trunk only
+    Mstp = 2,
+    /// No periodic announcements
+    Off = 3,
+}
+
+impl AnnounceScope {
+    /// Decode the NVS value, falling back to both ports
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => AnnounceScope::Ip,
+            2 => AnnounceScope::Mstp,
+            3 => AnnounceScope::Off,
+            _ => AnnounceScope::Both,
+        }
+    }
+
+    /// Form value used by the portal and console
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnnounceScope::Both => "both",
+            AnnounceScope::Ip => "ip",
+            AnnounceScope::Mstp => "mstp",
+            AnnounceScope::Off => "none",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [AnnounceScope::Both, AnnounceScope::Ip, AnnounceScope::Mstp, AnnounceScope::Off]
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+    }
+
+    pub fn on_ip(self) -> bool {
+        matches!(self, AnnounceScope::Both | AnnounceScope::Ip)
+    }
+
+    pub fn on_mstp(self) -> bool {
+        matches!(self, AnnounceScope::Both | AnnounceScope::Mstp)
+    }
+}
+
+/// Most extra networks carried in the router announcements
+pub const MAX_ANNOUNCED_NETWORKS: usize = 16;
+
+/// Parse a comma separated list of network numbers (1-65534); empty clears the list
+pub fn parse_network_list(s: &str) -> Result<Vec<u16>, &'static str> {
+    let mut networks = Vec::new();
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let network = item.parse::<u16>().map_err(|_| "expected network numbers")?;
+        if network == 0 || network == 0xFFFF {
+            return Err("network numbers are 1-65534");
+        }
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+    }
+    if networks.len() > MAX_ANNOUNCED_NETWORKS {
+        return Err("too many networks");
+    }
+    Ok(networks)
+}
+
+pub fn format_network_list(networks: &[u16]) -> String {
+    networks.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Next-hop router for a static route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteNextHop {
+    /// Router on the MS/TP trunk (MAC address)
+    Mstp(u8),
+    /// Router on the BACnet/IP network (IP:port)
+    Ip(SocketAddr),
+}
+
+impl std::fmt::Display for RouteNextHop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteNextHop::Mstp(mac) => write!(f, "MS/TP {}", mac),
+            RouteNextHop::Ip(addr) => write!(f, "IP {}", addr),
+        }
+    }
+}
+
+/// Static route entry for NVS persistence
+#[derive(Debug, Clone)]
+pub struct StaticRouteConfig {
+    pub network: u16,
+    pub next_hop: RouteNextHop,
+}
+
 /// BDT and Routing Table persistence functions
 pub struct NetworkTablePersistence;
 
@@ -407,6 +1101,94 @@ impl NetworkTablePersistence {
         }
     }
 
+    /// Save static routes to NVS
+    /// Format: count (u8), then for each entry: network (2 bytes BE) + kind (1 byte:
+    /// 0 = MS/TP, 1 = IP) + 6 bytes (MAC then padding, or IP + port BE)
+    pub fn save_static_routes(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+        entries: &[StaticRouteConfig],
+    ) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+
+        let count = entries.len().min(255) as u8;
+        nvs.set_u8(nvs_keys::SR_COUNT, count)?;
+
+        if count == 0 {
+            info!("Static routes cleared from NVS");
+            return Ok(());
+        }
+
+        // Serialize entries: 9 bytes each
+        let mut buf = Vec::with_capacity(count as usize * 9);
+        for entry in entries.iter().take(count as usize) {
+            buf.extend_from_slice(&entry.network.to_be_bytes());
+            match entry.next_hop {
+                RouteNextHop::Mstp(mac) => {
+                    buf.push(0);
+                    buf.extend_from_slice(&[mac, 0, 0, 0, 0, 0]);
+                }
+                RouteNextHop::Ip(addr) => {
+                    let ip = match addr.ip() {
+                        IpAddr::V4(ipv4) => ipv4,
+                        IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+                    };
+                    buf.push(1);
+                    buf.extend_from_slice(&ip.octets());
+                    buf.extend_from_slice(&addr.port().to_be_bytes());
+                }
+            }
+        }
+
+        nvs.set_blob(nvs_keys::SR_ENTRIES, &buf)?;
+        info!("Saved {} static routes to NVS", count);
+        Ok(())
+    }
+
+    /// Load static routes from NVS
+    pub fn load_static_routes(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+    ) -> Result<Vec<StaticRouteConfig>, anyhow::Error> {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for static route load: {}", e);
+                return Ok(Vec::new());
+            }
+        };
+
+        let count = nvs.get_u8(nvs_keys::SR_COUNT)?.unwrap_or(0);
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = vec![0u8; count as usize * 9];
+        match nvs.get_blob(nvs_keys::SR_ENTRIES, &mut buf) {
+            Ok(Some(data)) => {
+                let mut entries = Vec::with_capacity(count as usize);
+                for chunk in data.chunks_exact(9) {
+                    let network = u16::from_be_bytes([chunk[0], chunk[1]]);
+                    let next_hop = match chunk[2] {
+                        0 => RouteNextHop::Mstp(chunk[3]),
+                        1 => {
+                            let ip = Ipv4Addr::new(chunk[3], chunk[4], chunk[5], chunk[6]);
+                            let port = u16::from_be_bytes([chunk[7], chunk[8]]);
+                            RouteNextHop::Ip(SocketAddr::new(IpAddr::V4(ip), port))
+                        }
+                        _ => continue,
+                    };
+                    entries.push(StaticRouteConfig { network, next_hop });
+                }
+                info!("Loaded {} static routes from NVS", entries.len());
+                Ok(entries)
+            }
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => {
+                warn!("Failed to read static routes from NVS: {}", e);
+                Ok(Vec::new())
+            }
+        }
+    }
+
     /// Clear BDT and routing table from NVS
     pub fn clear_tables(nvs_partition: EspNvsPartition<NvsDefault>) -> Result<(), anyhow::Error> {
         let nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
@@ -416,3 +1198,466 @@ impl NetworkTablePersistence {
         Ok(())
     }
 }
+
+/// Size of one stored account: role + name length + padded name + salt + hash
+const USER_RECORD_LEN: usize = 2 + MAX_USERNAME_LEN + SALT_LEN + HASH_LEN;
+
+/// Web portal account persistence functions
+pub struct UserAccountPersistence;
+
+impl UserAccountPersistence {
+    /// Save portal accounts to NVS
+    /// Format: fixed-size records of role (u8), name length (u8), name (zero padded), salt, hash
+    pub fn save_users(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+        users: &[UserAccount],
+    ) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+
+        let count = users.len().min(MAX_USERS) as u8;
+        nvs.set_u8(nvs_keys::USR_COUNT, count)?;
+
+        if count == 0 {
+            info!("Portal accounts cleared from NVS");
+            return Ok(());
+        }
+
+        let mut buf = Vec::with_capacity(count as usize * USER_RECORD_LEN);
+        for user in users.iter().take(count as usize) {
+            let name = user.username.as_bytes();
+            let name_len = name.len().min(MAX_USERNAME_LEN);
+            buf.push(user.role.to_u8());
+            buf.push(name_len as u8);
+            buf.extend_from_slice(&name[..name_len]);
+            buf.resize(buf.len() + MAX_USERNAME_LEN - name_len, 0);
+            buf.extend_from_slice(&user.salt);
+            buf.extend_from_slice(&user.hash);
+        }
+
+        nvs.set_blob(nvs_keys::USR_ENTRIES, &buf)?;
+        info!("Saved {} portal accounts to NVS", count);
+        Ok(())
+    }
+
+    /// Load portal accounts from NVS
+    pub fn load_users(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+    ) -> Result<Vec<UserAccount>, anyhow::Error> {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for account load: {}", e);
+                return Ok(Vec::new());
+            }
+        };
+
+        let count = nvs.get_u8(nvs_keys::USR_COUNT)?.unwrap_or(0).min(MAX_USERS as u8);
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = vec![0u8; count as usize * USER_RECORD_LEN];
+        match nvs.get_blob(nvs_keys::USR_ENTRIES, &mut buf) {
+            Ok(Some(data)) => {
+                let mut users = Vec::with_capacity(count as usize);
+                for chunk in data.chunks_exact(USER_RECORD_LEN) {
+                    let role = match Role::from_u8(chunk[0]) {
+                        Some(role) => role,
+                        None => continue,
+                    };
+                    let name_len = (chunk[1] as usize).min(MAX_USERNAME_LEN);
+                    let name_end = 2 + name_len;
+                    let salt_start = 2 + MAX_USERNAME_LEN;
+                    let hash_start = salt_start + SALT_LEN;
+                    let username = match std::str::from_utf8(&chunk[2..name_end]) {
+                        Ok(name) => name.to_string(),
+                        Err(_) => continue,
+                    };
+                    let mut salt = [0u8; SALT_LEN];
+                    salt.copy_from_slice(&chunk[salt_start..hash_start]);
+                    let mut hash = [0u8; HASH_LEN];
+                    hash.copy_from_slice(&chunk[hash_start..hash_start + HASH_LEN]);
+                    users.push(UserAccount { username, role, salt, hash });
+                }
+                info!("Loaded {} portal accounts from NVS", users.len());
+                Ok(users)
+            }
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => {
+                warn!("Failed to read portal accounts from NVS: {}", e);
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+/// API key persistence functions (records laid out like portal accounts,
+/// with the scope bits in place of the role)
+pub struct ApiKeyPersistence;
+
+impl ApiKeyPersistence {
+    /// Save API keys to NVS
+    /// Format: fixed-size records of scopes (u8), name length (u8), name (zero padded), salt, hash
+    pub fn save_keys(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+        keys: &[ApiKey],
+    ) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+
+        let count = keys.len().min(MAX_API_KEYS) as u8;
+        nvs.set_u8(nvs_keys::KEY_COUNT, count)?;
+
+        if count == 0 {
+            info!("API keys cleared from NVS");
+            return Ok(());
+        }
+
+        let mut buf = Vec::with_capacity(count as usize * USER_RECORD_LEN);
+        for key in keys.iter().take(count as usize) {
+            let name = key.name.as_bytes();
+            let name_len = name.len().min(MAX_USERNAME_LEN);
+            buf.push(key.scopes.bits());
+            buf.push(name_len as u8);
+            buf.extend_from_slice(&name[..name_len]);
+            buf.resize(buf.len() + MAX_USERNAME_LEN - name_len, 0);
+            buf.extend_from_slice(&key.salt);
+            buf.extend_from_slice(&key.hash);
+        }
+
+        nvs.set_blob(nvs_keys::KEY_ENTRIES, &buf)?;
+        info!("Saved {} API keys to NVS", count);
+        Ok(())
+    }
+
+    /// Load API keys from NVS
+    pub fn load_keys(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+    ) -> Result<Vec<ApiKey>, anyhow::Error> {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for API key load: {}", e);
+                return Ok(Vec::new());
+            }
+        };
+
+        let count = nvs.get_u8(nvs_keys::KEY_COUNT)?.unwrap_or(0).min(MAX_API_KEYS as u8);
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = vec![0u8; count as usize * USER_RECORD_LEN];
+        match nvs.get_blob(nvs_keys::KEY_ENTRIES, &mut buf) {
+            Ok(Some(data)) => {
+                let mut keys = Vec::with_capacity(count as usize);
+                for chunk in data.chunks_exact(USER_RECORD_LEN) {
+                    let name_len = (chunk[1] as usize).min(MAX_USERNAME_LEN);
+                    let salt_start = 2 + MAX_USERNAME_LEN;
+                    let hash_start = salt_start + SALT_LEN;
+                    let name = match std::str::from_utf8(&chunk[2..2 + name_len]) {
+                        Ok(name) => name.to_string(),
+                        Err(_) => continue,
+                    };
+                    let mut salt = [0u8; SALT_LEN];
+                    salt.copy_from_slice(&chunk[salt_start..hash_start]);
+                    let mut hash = [0u8; HASH_LEN];
+                    hash.copy_from_slice(&chunk[hash_start..hash_start + HASH_LEN]);
+                    keys.push(ApiKey { name, scopes: ApiScopes::from_bits(chunk[0]), salt, hash });
+                }
+                info!("Loaded {} API keys from NVS", keys.len());
+                Ok(keys)
+            }
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => {
+                warn!("Failed to read API keys from NVS: {}", e);
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+/// Maximum number of device labels stored in NVS
+pub const MAX_DEVICE_LABELS: usize = 64;
+
+/// Maximum length in bytes of a device friendly name
+pub const MAX_LABEL_NAME_LEN: usize = 32;
+
+/// Maximum length in bytes of device notes
+pub const MAX_LABEL_NOTES_LEN: usize = 64;
+
+/// Size of one stored label: instance + name length + padded name + notes length + padded notes
+const LABEL_RECORD_LEN: usize = 4 + 1 + MAX_LABEL_NAME_LEN + 1 + MAX_LABEL_NOTES_LEN;
+
+/// Operator-assigned friendly name and notes for a BACnet device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceLabel {
+    pub device_instance: u32,
+    pub name: String,
+    pub notes: String,
+}
+
+/// Device label persistence functions
+pub struct DeviceLabelPersistence;
+
+impl DeviceLabelPersistence {
+    /// Save device labels to NVS
+    /// Format: fixed-size records of instance (u32 BE), name length, name (zero padded),
+    /// notes length, notes (zero padded)
+    pub fn save_labels(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+        labels: &[DeviceLabel],
+    ) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+
+        let count = labels.len().min(MAX_DEVICE_LABELS) as u8;
+        nvs.set_u8(nvs_keys::LBL_COUNT, count)?;
+
+        if count == 0 {
+            info!("Device labels cleared from NVS");
+            return Ok(());
+        }
+
+        let mut buf = Vec::with_capacity(count as usize * LABEL_RECORD_LEN);
+        for label in labels.iter().take(count as usize) {
+            buf.extend_from_slice(&label.device_instance.to_be_bytes());
+            for (text, max_len) in [(&label.name, MAX_LABEL_NAME_LEN), (&label.notes, MAX_LABEL_NOTES_LEN)] {
+                let bytes = text.as_bytes();
+                let len = bytes.len().min(max_len);
+                buf.push(len as u8);
+                buf.extend_from_slice(&bytes[..len]);
+                buf.resize(buf.len() + max_len - len, 0);
+            }
+        }
+
+        nvs.set_blob(nvs_keys::LBL_ENTRIES, &buf)?;
+        info!("Saved {} device labels to NVS", count);
+        Ok(())
+    }
+
+    /// Load device labels from NVS
+    pub fn load_labels(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+    ) -> Result<Vec<DeviceLabel>, anyhow::Error> {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for device label load: {}", e);
+                return Ok(Vec::new());
+            }
+        };
+
+        let count = nvs.get_u8(nvs_keys::LBL_COUNT)?.unwrap_or(0).min(MAX_DEVICE_LABELS as u8);
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = vec![0u8; count as usize * LABEL_RECORD_LEN];
+        match nvs.get_blob(nvs_keys::LBL_ENTRIES, &mut buf) {
+            Ok(Some(data)) => {
+                let mut labels = Vec::with_capacity(count as usize);
+                for chunk in data.chunks_exact(LABEL_RECORD_LEN) {
+                    let device_instance = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                    let name_len = (chunk[4] as usize).min(MAX_LABEL_NAME_LEN);
+                    let notes_start = 5 + MAX_LABEL_NAME_LEN;
+                    let notes_len = (chunk[notes_start] as usize).min(MAX_LABEL_NOTES_LEN);
+                    let name = std::str::from_utf8(&chunk[5..5 + name_len]);
+                    let notes = std::str::from_utf8(&chunk[notes_start + 1..notes_start + 1 + notes_len]);
+                    let (Ok(name), Ok(notes)) = (name, notes) else {
+                        continue;
+                    };
+                    labels.push(DeviceLabel {
+                        device_instance,
+                        name: name.to_string(),
+                        notes: notes.to_string(),
+                    });
+                }
+                info!("Loaded {} device labels from NVS", labels.len());
+                Ok(labels)
+            }
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => {
+                warn!("Failed to read device labels from NVS: {}", e);
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+/// Lifetime statistics persistence functions
+pub struct LifetimeStatsPersistence;
+
+impl LifetimeStatsPersistence {
+    /// Save the lifetime totals as one fixed-size record
+    pub fn save(
+        nvs_partition: EspNvsPartition<NvsDefault>,
+        stats: &LifetimeStats,
+    ) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.set_blob(nvs_keys::LIFE_STATS, &stats.encode())?;
+        Ok(())
+    }
+
+    /// Load the lifetime totals; a missing or unreadable record starts from zero
+    pub fn load(nvs_partition: EspNvsPartition<NvsDefault>) -> LifetimeStats {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for lifetime stats load: {}", e);
+                return LifetimeStats::default();
+            }
+        };
+
+        let mut buf = [0u8; LIFETIME_RECORD_LEN];
+        match nvs.get_blob(nvs_keys::LIFE_STATS, &mut buf) {
+            Ok(Some(data)) => LifetimeStats::decode(data),
+            Ok(None) => LifetimeStats::default(),
+            Err(e) => {
+                warn!("Failed to read lifetime stats from NVS: {}", e);
+                LifetimeStats::default()
+            }
+        }
+    }
+}
+
+/// Per-device response statistics persistence functions
+pub struct SlaPersistence;
+
+impl SlaPersistence {
+    /// Save the response statistics table
+    pub fn save(nvs_partition: EspNvsPartition<NvsDefault>, table: &SlaTable) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.set_blob(nvs_keys::SLA_STATS, &table.encode())?;
+        Ok(())
+    }
+
+    /// Load the response statistics; a missing or unreadable table starts empty
+    pub fn load(nvs_partition: EspNvsPartition<NvsDefault>) -> SlaTable {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for response statistics load: {}", e);
+                return SlaTable::new();
+            }
+        };
+
+        let mut buf = vec![0u8; MAX_SLA_RECORD_LEN];
+        match nvs.get_blob(nvs_keys::SLA_STATS, &mut buf) {
+            Ok(Some(data)) => SlaTable::decode(data),
+            Ok(None) => SlaTable::new(),
+            Err(e) => {
+                warn!("Failed to read response statistics from NVS: {}", e);
+                SlaTable::new()
+            }
+        }
+    }
+}
+
+/// Crash report persistence functions
+pub struct CrashReportPersistence;
+
+impl CrashReportPersistence {
+    /// Save the blackbox recording of an unexpected reset
+    pub fn save(nvs_partition: EspNvsPartition<NvsDefault>, report: &CrashReport) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.set_blob(nvs_keys::CRASH_BB, &report.encode())?;
+        Ok(())
+    }
+
+    /// Load the last crash report, if one was saved and is still readable
+    pub fn load(nvs_partition: EspNvsPartition<NvsDefault>) -> Option<CrashReport> {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for crash report load: {}", e);
+                return None;
+            }
+        };
+
+        let mut buf = vec![0u8; MAX_REPORT_LEN];
+        match nvs.get_blob(nvs_keys::CRASH_BB, &mut buf) {
+            Ok(Some(data)) => CrashReport::decode(data),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to read crash report from NVS: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Forget the saved crash report
+    pub fn clear(nvs_partition: EspNvsPartition<NvsDefault>) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.remove(nvs_keys::CRASH_BB)?;
+        Ok(())
+    }
+}
+
+/// Event log spillover persistence functions
+pub struct EventLogPersistence;
+
+impl EventLogPersistence {
+    /// Save spilled events (as encoded by `EventLog::encode_spill`)
+    pub fn save(nvs_partition: EspNvsPartition<NvsDefault>, data: &[u8]) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.set_blob(nvs_keys::EVT_LOG, data)?;
+        Ok(())
+    }
+
+    /// Restore events spilled by a previous boot into `log`
+    pub fn load(nvs_partition: EspNvsPartition<NvsDefault>, log: &mut EventLog) {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for event log load: {}", e);
+                return;
+            }
+        };
+
+        let mut buf = vec![0u8; MAX_SPILL_LEN];
+        match nvs.get_blob(nvs_keys::EVT_LOG, &mut buf) {
+            Ok(Some(data)) => log.restore(data),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read event log from NVS: {}", e),
+        }
+    }
+}
+
+/// Scheduled configuration change persistence functions
+pub struct ScheduledChangePersistence;
+
+impl ScheduledChangePersistence {
+    /// Save the scheduled change, replacing any other
+    pub fn save(nvs_partition: EspNvsPartition<NvsDefault>, change: &ScheduledChange) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.set_blob(nvs_keys::SCHED_CHG, &change.encode())?;
+        Ok(())
+    }
+
+    /// Load the scheduled change, if one is stored and still readable
+    pub fn load(nvs_partition: EspNvsPartition<NvsDefault>) -> Option<ScheduledChange> {
+        let nvs = match EspNvs::new(nvs_partition, NVS_NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(e) => {
+                warn!("Failed to open NVS for scheduled change load: {}", e);
+                return None;
+            }
+        };
+
+        let mut buf = vec![0u8; MAX_SCHEDULE_LEN];
+        match nvs.get_blob(nvs_keys::SCHED_CHG, &mut buf) {
+            Ok(Some(data)) => ScheduledChange::decode(data),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to read scheduled change from NVS: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Forget the scheduled change
+    pub fn clear(nvs_partition: EspNvsPartition<NvsDefault>) -> Result<(), anyhow::Error> {
+        let mut nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
+        nvs.remove(nvs_keys::SCHED_CHG)?;
+        Ok(())
+    }
+}
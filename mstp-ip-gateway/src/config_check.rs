@@ -0,0 +1,282 @@
+//! Configuration validation
+//!
+//! `parse_config_form` refuses malformed values one field at a time. The
+//! checks here look at the configuration as a whole - station address against
+//! Max_Master, distinct network numbers, a device instance nobody else uses -
+//! before it is applied or saved. Errors block the change; warnings are shown
+//! alongside it and the change goes ahead.
+
+use crate::config::{BroadcastForm, GatewayConfig};
+use crate::failover::FailoverRole;
+#[cfg(feature = "gpio-trace")]
+use crate::gpio_trace;
+
+/// Valid MS/TP baud rates per ASHRAE 135
+const VALID_MSTP_BAUD_RATES: [u32; 5] = [9600, 19200, 38400, 76800, 115200];
+
+/// Highest station address a master may use
+const MAX_MASTER_ADDRESS: u8 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueLevel {
+    Warning,
+    Error,
+}
+
+impl IssueLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueLevel::Warning => "warning",
+            IssueLevel::Error => "error",
+        }
+    }
+}
+
+/// One problem found in a configuration, tied to the form key it concerns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub level: IssueLevel,
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub fn error(field: &str, message: impl Into<String>) -> Self {
+        Self { level: IssueLevel::Error, field: field.to_string(), message: message.into() }
+    }
+
+    pub fn warning(field: &str, message: impl Into<String>) -> Self {
+        Self { level: IssueLevel::Warning, field: field.to_string(), message: message.into() }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == IssueLevel::Error
+    }
+}
+
+/// True if any issue should block the change
+pub fn has_errors(issues: &[ConfigIssue]) -> bool {
+    issues.iter().any(ConfigIssue::is_error)
+}
+
+/// Check constraints between fields.
+/// `discovered_instances` are the device instances heard on the network.
+pub fn check(config: &GatewayConfig, discovered_instances: &[u32]) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+
+    if !config.mstp_slave_mode {
+        if config.mstp_address > MAX_MASTER_ADDRESS {
+            issues.push(ConfigIssue::error(
+                "mstp_addr",
+                format!("Station address {} is only valid in slave mode (masters use 0-127)", config.mstp_address),
+            ));
+        } else if config.mstp_max_master < config.mstp_address {
+            issues.push(ConfigIssue::error(
+                "mstp_max",
+                format!(
+                    "Max Master ({}) must be at least the station address ({})",
+                    config.mstp_max_master, config.mstp_address
+                ),
+            ));
+        }
+    } else if config.mstp_auto_address {
+        issues.push(ConfigIssue::warning("mstp_auto", "Automatic addressing is not used in slave mode"));
+    }
+
+    if !VALID_MSTP_BAUD_RATES.contains(&config.mstp_baud_rate) {
+        issues.push(ConfigIssue::error(
+            "mstp_baud",
+            format!("{} baud is not an MS/TP rate (9600, 19200, 38400, 76800 or 115200)", config.mstp_baud_rate),
+        ));
+    }
+
+    if config.mstp_network == config.ip_network {
+        issues.push(ConfigIssue::error(
+            "ip_net",
+            format!("MS/TP and IP networks must differ (both are {})", config.ip_network),
+        ));
+    }
+
+    // The two units of a redundancy pair share one device instance
+    if config.local_device_enabled
+        && config.failover_role == FailoverRole::Off
+        && discovered_instances.contains(&config.device_instance)
+    {
+        issues.push(ConfigIssue::error(
+            "dev_inst",
+            format!("Device instance {} is already used by a device on the network", config.device_instance),
+        ));
+    }
+
+    if config.backpressure_high != 0 && config.backpressure_low >= config.backpressure_high {
+        issues.push(ConfigIssue::error(
+            "bp_low",
+            format!(
+                "Backpressure low mark ({}) must be below the high mark ({})",
+                config.backpressure_low, config.backpressure_high
+            ),
+        ));
+    }
+
+    if config.bip_broadcast_form == BroadcastForm::Disabled && config.supervisory_stations.is_empty() {
+        issues.push(ConfigIssue::error(
+            "bcast_form",
+            "B/IP broadcasts can only be turned off with at least one supervisory station to receive them",
+        ));
+    }
+
+    if let Some(network) = config.announce_networks.iter().find(|&&n| n == config.mstp_network || n == config.ip_network) {
+        issues.push(ConfigIssue::warning(
+            "ann_nets",
+            format!("Network {} is directly connected and is announced anyway", network),
+        ));
+    }
+
+    if config.remote_console_port != 0 && config.remote_console_port == config.public_status_port {
+        issues.push(ConfigIssue::error(
+            "con_port",
+            format!("Port {} is already used by the public status server", config.remote_console_port),
+        ));
+    }
+
+    if config.wifi_ssid.is_empty() {
+        issues.push(ConfigIssue::warning("wifi_ssid", "No site WiFi network set - only the hotspot will be available"));
+    } else if config.ap_ssid == config.wifi_ssid {
+        // With both radios up the gateway would advertise the site's own network name
+        let message = format!("Hotspot SSID '{}' is the same as the site WiFi network", config.ap_ssid);
+        issues.push(if config.ap_with_sta {
+            ConfigIssue::error("ap_ssid", message)
+        } else {
+            ConfigIssue::warning("ap_ssid", message)
+        });
+    }
+
+    if config.heartbeat_enabled && config.heartbeat_url.is_empty() {
+        issues.push(ConfigIssue::warning("hb_url", "Heartbeat is enabled but has no endpoint URL"));
+    }
+    if config.webhook_enabled && config.webhook_url.is_empty() {
+        issues.push(ConfigIssue::warning("wh_url", "Webhook is enabled but has no URL"));
+    }
+
+    if config.failover_role != FailoverRole::Off {
+        if config.failover_peer.is_none() {
+            issues.push(ConfigIssue::error("fo_peer", "A redundancy pair needs the other unit's IP address"));
+        }
+        if config.mstp_auto_address && !config.mstp_slave_mode {
+            issues.push(ConfigIssue::error(
+                "mstp_auto",
+                "Both units of a redundancy pair must use the same fixed station address",
+            ));
+        }
+    }
+
+    #[cfg(feature = "gpio-trace")]
+    if gpio_trace::uses_pin(config.write_protect_pin as i32) {
+        issues.push(ConfigIssue::error(
+            "wp_pin",
+            format!("G{} is driven by the GPIO trace markers in this build", config.write_protect_pin),
+        ));
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> GatewayConfig {
+        GatewayConfig {
+            wifi_ssid: "Site".to_string(),
+            ap_ssid: "BACman-Setup".to_string(),
+            mstp_address: 3,
+            mstp_max_master: 127,
+            mstp_baud_rate: 38400,
+            mstp_network: 1,
+            ip_network: 2,
+            ..Default::default()
+        }
+    }
+
+    fn fields(issues: &[ConfigIssue]) -> Vec<(&str, IssueLevel)> {
+        issues.iter().map(|i| (i.field.as_str(), i.level)).collect()
+    }
+
+    #[test]
+    fn test_valid_config_has_no_issues() {
+        assert!(check(&valid_config(), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_conflicts_reported_per_field() {
+        let mut config = valid_config();
+        config.mstp_address = 40;
+        config.mstp_max_master = 20;
+        config.mstp_network = 2;
+        config.mstp_baud_rate = 57600;
+        config.local_device_enabled = true;
+        config.device_instance = 1234;
+        config.ap_with_sta = true;
+        config.ap_ssid = "Site".to_string();
+        config.bip_broadcast_form = BroadcastForm::Disabled;
+        config.public_status_port = 8080;
+        config.remote_console_port = 8080;
+
+        let issues = check(&config, &[99, 1234]);
+        assert_eq!(
+            fields(&issues),
+            vec![
+                ("mstp_max", IssueLevel::Error),
+                ("mstp_baud", IssueLevel::Error),
+                ("ip_net", IssueLevel::Error),
+                ("dev_inst", IssueLevel::Error),
+                ("bcast_form", IssueLevel::Error),
+                ("con_port", IssueLevel::Error),
+                ("ap_ssid", IssueLevel::Error),
+            ]
+        );
+        assert!(has_errors(&issues));
+
+        config.supervisory_stations = vec!["10.0.0.5:47808".parse().unwrap()];
+        assert!(!fields(&check(&config, &[])).contains(&("bcast_form", IssueLevel::Error)));
+    }
+
+    #[test]
+    fn test_slave_addresses_and_warnings() {
+        let mut config = valid_config();
+        config.mstp_address = 200;
+        assert_eq!(fields(&check(&config, &[])), vec![("mstp_addr", IssueLevel::Error)]);
+
+        // A slave may sit above 127 and above Max_Master
+        config.mstp_slave_mode = true;
+        config.mstp_max_master = 10;
+        assert!(check(&config, &[]).is_empty());
+
+        // Same SSID with the hotspot alone only warns
+        config.ap_ssid = "Site".to_string();
+        config.heartbeat_enabled = true;
+        config.announce_networks = vec![300, 2];
+        let issues = check(&config, &[]);
+        assert_eq!(
+            fields(&issues),
+            vec![("ann_nets", IssueLevel::Warning), ("ap_ssid", IssueLevel::Warning), ("hb_url", IssueLevel::Warning)]
+        );
+        assert!(!has_errors(&issues));
+    }
+
+    #[test]
+    fn test_redundancy_pair_needs_peer_and_fixed_address() {
+        let mut config = valid_config();
+        config.failover_role = FailoverRole::Standby;
+        config.mstp_auto_address = true;
+        assert_eq!(
+            fields(&check(&config, &[config.device_instance])),
+            vec![("fo_peer", IssueLevel::Error), ("mstp_auto", IssueLevel::Error)]
+        );
+
+        // The primary's device instance is expected on the network
+        config.failover_peer = Some("10.0.0.2".parse().unwrap());
+        config.mstp_auto_address = false;
+        assert!(check(&config, &[config.device_instance]).is_empty());
+    }
+}
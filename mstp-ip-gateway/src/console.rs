@@ -0,0 +1,539 @@
+//! Serial console (USB UART) for headless provisioning
+//!
+//! Reads command lines from stdin and answers on stdout, alongside the log
+//! output. Settings use the same keys and validation as the web portal's
+//! configuration form. The readprop, writeprop and whois commands wait for
+//! their answers, so bench scripts can drive MS/TP devices over USB alone.
+
+use log::{info, warn};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::{self, ClientCall, ClientRequest, CLIENT_CALL_DEADLINE};
+use crate::compat;
+use crate::config::{format_network_list, format_supervisory_stations, DeviceLabelPersistence, GatewayConfig, UserAccountPersistence};
+use crate::config_check;
+use crate::events::{self, EventCategory, Severity};
+use crate::failover::{self, FailoverRole};
+use crate::nvs_writer::{self, NvsWrite};
+use crate::rpm_proxy::PropertyRef;
+use crate::scan::{ScanProfile, SCAN_REPLY_WINDOW};
+use crate::web::{validate_config_form, WebState};
+use crate::write_protect;
+use crate::wpm_proxy::PropertyWrite;
+
+/// Stack size for the console thread
+const CONSOLE_STACK_SIZE: usize = 8192;
+
+/// Poll interval while no input is available
+const CONSOLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Longest accepted command line
+pub const MAX_LINE_LEN: usize = 256;
+
+/// Where a command line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// USB UART
+    Serial,
+    /// Telnet session (see `remote_console`)
+    Remote,
+}
+
+impl Origin {
+    fn as_str(self) -> &'static str {
+        match self {
+            Origin::Serial => "serial console",
+            Origin::Remote => "remote console",
+        }
+    }
+}
+
+const HELP_TEXT: &str = "\
+Commands:
+  help                 Show this help
+  show config          Show current settings (passwords hidden)
+  show stats           Show MS/TP and routing statistics
+  set <key> <value>    Change a setting (same keys as the web config form)
+  save                 Save settings to NVS (apply with reboot)
+  scan [profile]       Start a Who-Is scan: quick (default), directed
+                       (each MAC 0-127) or ranged <low> <high> <window>
+  whois [<low> <high>] Send Who-Is (optionally for an instance range) and list
+                       the MS/TP devices that answer
+  readprop <mac> <object> <property>
+                       Read a property, e.g. readprop 5 analog-value:3 present-value
+  writeprop <mac> <object> <property> <value> [@<priority>]
+                       Write a property, e.g. writeprop 5 analog-value:3 present-value 72.5 @8
+                       Values: null true false 72.5 300 -2 enum:1 string:text
+                       Properties take an array index as name[index]
+  reboot               Restart the gateway
+  factory-reset        Erase settings, accounts and device labels, then restart
+                       (USB console only)
+Keys: wifi_ssid wifi_pass ap_ssid ap_pass ap_sta ap_timeout mstp_addr mstp_auto mstp_slave
+      mstp_max mstp_baud mstp_net mstp_pfm mstp_npdu bp_high bp_low ip_port ip_net ip_learn
+      bip_mode bip_group bcast_form sup_station compat dup_suppress whois_filter ann_scope
+      ann_nets npdu_hops npdu_prio npdu_lenient bbmd_addr sbvll_drop local_dev dev_inst dev_name
+      dev_loc dev_desc dev_serial hb_enabled hb_url hb_interval pub_port con_port site_name
+      wh_enabled wh_url wh_scan_h wh_err_thr ntp_server timezone life_stats evt_spill log_json
+      wp_pin fo_role fo_peer";
+
+/// Spawn the console thread
+pub fn spawn_console_task(web_state: Arc<Mutex<WebState>>) -> anyhow::Result<()> {
+    thread::Builder::new()
+        .stack_size(CONSOLE_STACK_SIZE)
+        .spawn(move || console_task(web_state))?;
+    Ok(())
+}
+
+fn console_task(web_state: Arc<Mutex<WebState>>) {
+    info!("Serial console ready - type 'help' for commands");
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+
+    loop {
+        // stdin is non-blocking on ESP-IDF: a partial line stays in the buffer
+        // until the newline arrives
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => thread::sleep(CONSOLE_POLL_INTERVAL),
+            Ok(_) if line.ends_with('\n') => {
+                let command = line.trim().to_string();
+                line.clear();
+                if !command.is_empty() {
+                    let reply = execute(&command, &web_state, Origin::Serial);
+                    println!("{}", reply);
+                    let _ = std::io::stdout().flush();
+                }
+            }
+            Ok(_) => {}
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(CONSOLE_POLL_INTERVAL);
+            }
+            Err(e) => {
+                warn!("Console read error: {}", e);
+                line.clear();
+                thread::sleep(CONSOLE_POLL_INTERVAL);
+            }
+        }
+        if line.len() > MAX_LINE_LEN {
+            println!("Line too long");
+            line.clear();
+        }
+    }
+}
+
+/// Run one command line and return the text to print
+pub fn execute(command: &str, web_state: &Mutex<WebState>, origin: Origin) -> String {
+    let mut words = command.splitn(3, char::is_whitespace);
+    let verb = words.next().unwrap_or("");
+    let arg1 = words.next().unwrap_or("").trim();
+    let rest = words.next().unwrap_or("").trim();
+
+    match (verb, arg1) {
+        ("help", _) | ("?", _) => HELP_TEXT.to_string(),
+        ("show", "config") => show_config(&web_state.lock().unwrap()),
+        ("show", "stats") => show_stats(&web_state.lock().unwrap()),
+        ("show", _) => "Usage: show config | show stats".to_string(),
+        ("set", "") => "Usage: set <key> <value>".to_string(),
+        ("set", _) | ("save", _) | ("factory-reset", _) if write_protect::is_locked() => {
+            write_protect::LOCKED_MESSAGE.to_string()
+        }
+        ("set", key) => set_value(web_state, key, rest, origin),
+        ("save", _) => save_config(web_state, origin),
+        ("scan", profile) => start_scan(web_state, profile, rest),
+        ("whois", low) => who_is(web_state, low, rest),
+        ("readprop", mac) => read_property(web_state, mac, rest),
+        ("writeprop", mac) => write_property(web_state, mac, rest),
+        ("reboot", _) => {
+            restart_soon();
+            "Rebooting...".to_string()
+        }
+        ("factory-reset", _) if origin == Origin::Remote => {
+            "factory-reset needs the USB console".to_string()
+        }
+        ("factory-reset", _) => factory_reset(web_state),
+        _ => format!("Unknown command '{}' - type 'help'", verb),
+    }
+}
+
+fn show_config(state: &WebState) -> String {
+    let c = &state.config;
+    let hidden = |s: &str| if s.is_empty() { "(not set)" } else { "********" };
+    format!(
+        "wifi_ssid     {}\n\
+         wifi_pass     {}\n\
+         ap_ssid       {}\n\
+         ap_pass       {}\n\
+         ap_sta        {}\n\
+         ap_timeout    {}\n\
+         mstp_addr     {}\n\
+         mstp_auto     {}\n\
+         mstp_slave    {}\n\
+         mstp_max      {}\n\
+         mstp_baud     {}\n\
+         mstp_net      {}\n\
+         mstp_pfm      {}\n\
+         mstp_npdu     {}\n\
+         bp_high       {}\n\
+         bp_low        {}\n\
+         ip_port       {}\n\
+         ip_net        {}\n\
+         ip_learn      {}\n\
+         bip_mode      {}\n\
+         bip_group     {}\n\
+         bcast_form    {}\n\
+         sup_station   {}\n\
+         compat        {}\n\
+         dup_suppress  {}\n\
+         whois_filter  {}\n\
+         ann_scope     {}\n\
+         ann_nets      {}\n\
+         npdu_hops     {}\n\
+         npdu_prio     {}\n\
+         npdu_lenient  {}\n\
+         bbmd_addr     {}\n\
+         sbvll_drop    {}\n\
+         local_dev     {}\n\
+         dev_inst      {}\n\
+         dev_name      {}\n\
+         dev_loc       {}\n\
+         dev_desc      {}\n\
+         dev_serial    {}\n\
+         hb_enabled    {}\n\
+         hb_url        {}\n\
+         hb_interval   {}\n\
+         pub_port      {}\n\
+         con_port      {}\n\
+         site_name     {}\n\
+         wh_enabled    {}\n\
+         wh_url        {}\n\
+         wh_scan_h     {}\n\
+         wh_err_thr    {}\n\
+         ntp_server    {}\n\
+         timezone      {}\n\
+         life_stats    {}\n\
+         evt_spill     {}\n\
+         log_json      {}\n\
+         wp_pin        {}{}\n\
+         fo_role       {}{}\n\
+         fo_peer       {}\n\
+         configured    {}",
+        c.wifi_ssid,
+        hidden(&c.wifi_password),
+        c.ap_ssid,
+        hidden(&c.ap_password),
+        c.ap_with_sta as u8,
+        c.ap_timeout_minutes,
+        c.mstp_address,
+        c.mstp_auto_address as u8,
+        c.mstp_slave_mode as u8,
+        c.mstp_max_master,
+        c.mstp_baud_rate,
+        c.mstp_network,
+        c.mstp_pfm_aggressiveness,
+        c.mstp_max_npdu,
+        c.backpressure_high,
+        c.backpressure_low,
+        c.bacnet_ip_port,
+        c.ip_network,
+        c.ip_network_learn as u8,
+        if c.bip_multicast_enabled { "multicast" } else { "broadcast" },
+        c.bip_multicast_group,
+        c.bip_broadcast_form.as_str(),
+        if c.supervisory_stations.is_empty() {
+            "(not set)".to_string()
+        } else {
+            format_supervisory_stations(&c.supervisory_stations)
+        },
+        if c.compat_rules.is_empty() { "(none)".to_string() } else { compat::format_rules(&c.compat_rules) },
+        c.suppress_on_duplicate_network as u8,
+        c.whois_filter_enabled as u8,
+        c.announce_scope.as_str(),
+        if c.announce_networks.is_empty() { "(none)".to_string() } else { format_network_list(&c.announce_networks) },
+        c.npdu_hop_count,
+        c.npdu_priority.as_str(),
+        c.npdu_lenient as u8,
+        c.bbmd_address.map(|a| a.to_string()).unwrap_or_else(|| "(not set)".to_string()),
+        c.secure_bvll_drop as u8,
+        c.local_device_enabled as u8,
+        c.device_instance,
+        c.device_name,
+        c.device_location,
+        c.device_description,
+        c.device_serial_number,
+        c.heartbeat_enabled as u8,
+        c.heartbeat_url,
+        c.heartbeat_interval_secs,
+        c.public_status_port,
+        c.remote_console_port,
+        c.site_name,
+        c.webhook_enabled as u8,
+        c.webhook_url,
+        c.webhook_scan_hours,
+        c.webhook_error_threshold,
+        c.ntp_server,
+        c.timezone,
+        c.lifetime_stats_enabled as u8,
+        c.event_spill_enabled as u8,
+        c.log_json as u8,
+        c.write_protect_pin,
+        if write_protect::is_locked() { " (jumper fitted)" } else { "" },
+        c.failover_role.as_str(),
+        match c.failover_role {
+            FailoverRole::Off => "",
+            _ if failover::is_standing_by() => " (standing by)",
+            _ => " (active)",
+        },
+        c.failover_peer.map(|a| a.to_string()).unwrap_or_else(|| "(not set)".to_string()),
+        c.configured,
+    )
+}
+
+fn show_stats(state: &WebState) -> String {
+    let m = &state.mstp_stats;
+    let g = &state.gateway_stats;
+    format!(
+        "uptime        {}\n\
+         wifi          {} ({})\n\
+         mstp          rx={} tx={} crc_errors={} frame_errors={} masters={} token_loop={}ms\n\
+         routing       mstp->ip={} ip->mstp={} errors={} txn_timeouts={} active_txns={}\n\
+         held frames   now={} total={} expired={}\n\
+         devices       {} discovered (scan {})",
+        state.uptime_formatted(),
+        if state.wifi_connected { "connected" } else { "disconnected" },
+        state.ip_address,
+        m.rx_frames,
+        m.tx_frames,
+        m.crc_errors,
+        m.frame_errors,
+        m.master_count,
+        m.token_loop_time_ms,
+        g.mstp_to_ip_packets,
+        g.ip_to_mstp_packets,
+        g.routing_errors,
+        g.transaction_timeouts,
+        g.active_transactions,
+        g.held_now,
+        g.held_frames,
+        g.held_expired,
+        state.discovered_devices.len(),
+        state.scan_id,
+    )
+}
+
+/// Apply one setting through the web form parser so validation matches the portal
+fn set_value(web_state: &Mutex<WebState>, key: &str, value: &str, origin: Origin) -> String {
+    let form = format!("{}={}", key, urlencoding::encode(value));
+    let mut state = web_state.lock().unwrap();
+    let (candidate, issues) = validate_config_form(&form, &state);
+    if config_check::has_errors(&issues) {
+        let reasons: Vec<&str> = issues.iter().filter(|i| i.is_error()).map(|i| i.message.as_str()).collect();
+        return format!("'{}' refused: {}", key, reasons.join("; "));
+    }
+    if candidate == state.config {
+        return format!("'{}' not changed (unknown key or same value)", key);
+    }
+    state.config = candidate;
+    // Location/Description/Serial_Number apply at runtime, as from the web portal
+    state.site_info_update_requested = true;
+    info!("Setting '{}' changed via {}", key, origin.as_str());
+    format!("{} updated - 'save' to persist, then 'reboot' to apply", key)
+}
+
+fn save_config(web_state: &Mutex<WebState>, origin: Origin) -> String {
+    let mut state = web_state.lock().unwrap();
+    if !state.queue_nvs_write(NvsWrite::Config(Box::new(state.config.clone()))) {
+        return "NVS not available".to_string();
+    }
+    state.config.configured = true;
+    state.config.commissioning_step = 0;
+    info!("Configuration save queued via {}", origin.as_str());
+    events::record(EventCategory::Config, Severity::Info, &format!("Configuration saved via {}", origin.as_str()));
+    "Configuration saved. Reboot to apply changes; the event log confirms the write to NVS.".to_string()
+}
+
+/// Start a Who-Is sweep: `scan [quick | directed | ranged <low> <high> <window>]`
+fn start_scan(web_state: &Mutex<WebState>, profile: &str, args: &str) -> String {
+    let profile = match profile {
+        "" | "quick" => ScanProfile::Quick,
+        "directed" => ScanProfile::Directed,
+        "ranged" => {
+            let numbers: Vec<u32> = args.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+            let [low, high, window] = numbers[..] else {
+                return "Usage: scan ranged <low> <high> <window>".to_string();
+            };
+            match ScanProfile::ranged(low, high, window) {
+                Ok(profile) => profile,
+                Err(e) => return e,
+            }
+        }
+        _ => return "Usage: scan [quick | directed | ranged <low> <high> <window>]".to_string(),
+    };
+
+    let mut state = web_state.lock().unwrap();
+    if state.scan_in_progress {
+        return "Scan already in progress".to_string();
+    }
+    let scan_id = state.request_scan(profile);
+    info!("Who-Is scan {} ({}) requested via serial console", scan_id, profile);
+    format!(
+        "Scan {} started ({} request(s) over ~{}s) - results appear in 'show stats' and the web portal",
+        scan_id,
+        profile.step_count(),
+        profile.duration(state.config.mstp_baud_rate).as_secs()
+    )
+}
+
+/// Send Who-Is and list the devices that answered: `whois [<low> <high>]`
+fn who_is(web_state: &Mutex<WebState>, low: &str, high: &str) -> String {
+    let profile = match (low.parse::<u32>(), high.parse::<u32>()) {
+        _ if low.is_empty() => ScanProfile::Quick,
+        (Ok(low), Ok(high)) => match ScanProfile::ranged(low, high, high.saturating_sub(low) + 1) {
+            Ok(profile) => profile,
+            Err(e) => return e,
+        },
+        _ => return "Usage: whois [<low> <high>]".to_string(),
+    };
+
+    let (scan_id, deadline) = {
+        let mut state = web_state.lock().unwrap();
+        if state.scan_in_progress {
+            return "Scan already in progress".to_string();
+        }
+        let scan_id = state.request_scan(profile);
+        let wait = profile.duration(state.config.mstp_baud_rate) + SCAN_REPLY_WINDOW + Duration::from_secs(2);
+        (scan_id, Instant::now() + wait)
+    };
+    info!("Who-Is scan {} ({}) requested via serial console", scan_id, profile);
+
+    // The main loop ends the scan once late I-Am replies have had time to arrive
+    while web_state.lock().unwrap().scan_in_progress && Instant::now() < deadline {
+        thread::sleep(CONSOLE_POLL_INTERVAL);
+    }
+    let state = web_state.lock().unwrap();
+    let mut lines: Vec<String> = state
+        .discovered_devices
+        .iter()
+        .filter(|d| d.last_scan_id == scan_id)
+        .map(|d| {
+            format!(
+                "device {} mac {} vendor {} max_apdu {}",
+                d.device_instance,
+                d.mac_address,
+                d.vendor_name(),
+                d.max_apdu_length
+            )
+        })
+        .collect();
+    lines.push(format!("{} device(s) answered", lines.len()));
+    lines.join("\n")
+}
+
+/// `readprop <mac> <object> <property>[<index>]`
+fn read_property(web_state: &Mutex<WebState>, mac: &str, args: &str) -> String {
+    let usage = "Usage: readprop <mac> <object> <property>";
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (Some(mac), [object, property]) = (parse_mac(mac), &words[..]) else {
+        return usage.to_string();
+    };
+    match parse_read_request(object, property) {
+        Ok(request) => run_client_call(web_state, ClientCall::new(mac, request)),
+        Err(e) => e,
+    }
+}
+
+fn parse_read_request(object: &str, property: &str) -> Result<ClientRequest, String> {
+    let object_id = client::parse_object_id(object)?;
+    let (property_id, array_index) = client::parse_property(property)?;
+    Ok(ClientRequest::ReadProperty(PropertyRef { object_id, property_id, array_index }))
+}
+
+/// `writeprop <mac> <object> <property>[<index>] <value> [@<priority>]`
+fn write_property(web_state: &Mutex<WebState>, mac: &str, args: &str) -> String {
+    let usage = "Usage: writeprop <mac> <object> <property> <value> [@<priority>]";
+    let mut words = args.splitn(3, char::is_whitespace);
+    let (Some(mac), Some(object), Some(property), Some(value)) = (parse_mac(mac), words.next(), words.next(), words.next()) else {
+        return usage.to_string();
+    };
+    match parse_write_request(object, property, value.trim()) {
+        Ok(request) => run_client_call(web_state, ClientCall::new(mac, request)),
+        Err(e) => e,
+    }
+}
+
+fn parse_write_request(object: &str, property: &str, value: &str) -> Result<ClientRequest, String> {
+    let object_id = client::parse_object_id(object)?;
+    let (property_id, array_index) = client::parse_property(property)?;
+    // A trailing @<n> is the priority; strings may contain spaces
+    let (value, priority) = match value.rsplit_once(" @") {
+        Some((value, priority)) => {
+            let priority = priority.parse::<u8>().ok().filter(|p| (1..=16).contains(p));
+            (value.trim_end(), Some(priority.ok_or("Priority must be 1-16")?))
+        }
+        None => (value, None),
+    };
+    let value = client::parse_value(value)?;
+    Ok(ClientRequest::WriteProperty(PropertyWrite { object_id, property_id, array_index, value, priority }))
+}
+
+/// MS/TP MAC of a device that can answer a confirmed request (not broadcast)
+fn parse_mac(s: &str) -> Option<u8> {
+    s.parse::<u8>().ok().filter(|mac| *mac < 255)
+}
+
+/// Hand a call to the main loop and wait for its outcome
+fn run_client_call(web_state: &Mutex<WebState>, call: ClientCall) -> String {
+    {
+        let mut state = web_state.lock().unwrap();
+        if !state.mstp_port_enabled {
+            return "MS/TP port is disabled".to_string();
+        }
+        state.client_outcome = None;
+        state.client_call_request = Some(call);
+    }
+    let deadline = Instant::now() + CLIENT_CALL_DEADLINE;
+    while Instant::now() < deadline {
+        thread::sleep(CONSOLE_POLL_INTERVAL);
+        if let Some(outcome) = web_state.lock().unwrap().client_outcome.take() {
+            return outcome.to_string();
+        }
+    }
+    "No answer from the gateway".to_string()
+}
+
+/// Erase settings, portal accounts and device labels, then restart. Needs physical access
+/// to the USB port, so it also recovers a gateway whose admin login is lost.
+fn factory_reset(web_state: &Mutex<WebState>) -> String {
+    let mut state = web_state.lock().unwrap();
+    // Nothing still queued may land after the erase
+    if let Some(ref writer) = state.nvs_writer {
+        writer.flush(nvs_writer::FLUSH_TIMEOUT);
+    }
+    if let Some(nvs) = state.nvs_partition.clone() {
+        if let Err(e) = GatewayConfig::clear_nvs(nvs.clone()) {
+            return format!("Factory reset failed: {}", e);
+        }
+        if let Err(e) = UserAccountPersistence::save_users(nvs.clone(), &[]) {
+            return format!("Failed to clear portal accounts: {}", e);
+        }
+        if let Err(e) = DeviceLabelPersistence::save_labels(nvs, &[]) {
+            return format!("Failed to clear device labels: {}", e);
+        }
+    }
+    state.config = GatewayConfig::default();
+    state.users.clear();
+    state.device_labels = Arc::default();
+    warn!("Factory reset via serial console");
+    restart_soon();
+    "Settings, accounts and device labels erased. Rebooting...".to_string()
+}
+
+/// Restart after the reply has been printed
+fn restart_soon() {
+    thread::spawn(|| {
+        thread::sleep(Duration::from_millis(500));
+        // SAFETY: esp_restart() is always safe to call on ESP32 - it performs a software reset
+        unsafe { esp_idf_svc::sys::esp_restart(); }
+    });
+}
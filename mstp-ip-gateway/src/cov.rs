@@ -0,0 +1,255 @@
+//! Change-of-value subscriptions to the gateway's own objects
+//!
+//! Head-ends subscribe (SubscribeCOV, Clause 13.14) to a Network Port or the
+//! Gateway Diagnostics object and get an UnconfirmedCOVNotification when one
+//! of its monitored values changes, instead of polling the counters. This
+//! table tracks who subscribed to what, for how long, and when a notification
+//! is due; the local device encodes the values.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::datalink::LinkAddress;
+use crate::npdu::NetworkAddress;
+
+/// Subscriptions held at once (all objects and subscribers together)
+pub const MAX_COV_SUBSCRIPTIONS: usize = 8;
+
+/// Shortest gap between two notifications for one subscription, so an error
+/// counter that moves constantly does not turn into a notification flood
+pub const MIN_NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where a request came from: the station that sent it on one of the
+/// gateway's links and, when it was routed from another network, its
+/// SNET/SADR. Notifications go back the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requester {
+    pub link: LinkAddress,
+    pub source: Option<NetworkAddress>,
+}
+
+impl fmt::Display for Requester {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "net {} MAC {:02X?} via {}", source.network, source.address, self.link),
+            None => write!(f, "{}", self.link),
+        }
+    }
+}
+
+/// One subscription
+#[derive(Debug, Clone)]
+pub struct CovSubscription {
+    pub subscriber: Requester,
+    pub process_id: u32,
+    pub object_id: u32,
+    /// None for an indefinite subscription (lifetime 0)
+    expires_at: Option<Instant>,
+    /// Monitored values as last notified (None until the initial notification)
+    last_values: Option<Vec<u8>>,
+    last_sent: Option<Instant>,
+}
+
+impl CovSubscription {
+    /// Seconds left, as reported in notifications (0 = indefinite)
+    pub fn time_remaining(&self, now: Instant) -> u32 {
+        self.expires_at
+            .map_or(0, |at| at.saturating_duration_since(now).as_secs().max(1) as u32)
+    }
+}
+
+/// A notification to send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DueNotification {
+    pub subscriber: Requester,
+    pub process_id: u32,
+    pub object_id: u32,
+    pub time_remaining: u32,
+}
+
+/// Result of an accepted SubscribeCOV
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscribeOutcome {
+    New,
+    /// Same subscriber, process and object: lifetime restarted
+    Renewed,
+}
+
+/// Active subscriptions
+#[derive(Debug, Default)]
+pub struct CovTable {
+    subscriptions: Vec<CovSubscription>,
+}
+
+impl CovTable {
+    pub const fn new() -> Self {
+        Self { subscriptions: Vec::new() }
+    }
+
+    /// Add a subscription, or renew it if the subscriber already has one for
+    /// this process and object. `lifetime` is in seconds, 0 = indefinite.
+    /// Either way the next `due` sends a fresh initial notification.
+    /// Returns None when the table is full.
+    pub fn subscribe(
+        &mut self,
+        subscriber: Requester,
+        process_id: u32,
+        object_id: u32,
+        lifetime: u32,
+        now: Instant,
+    ) -> Option<SubscribeOutcome> {
+        let expires_at = (lifetime > 0).then(|| now + Duration::from_secs(lifetime as u64));
+        if let Some(existing) = self.find_mut(&subscriber, process_id, object_id) {
+            existing.expires_at = expires_at;
+            existing.last_values = None;
+            existing.last_sent = None;
+            return Some(SubscribeOutcome::Renewed);
+        }
+        if self.subscriptions.len() >= MAX_COV_SUBSCRIPTIONS {
+            return None;
+        }
+        self.subscriptions.push(CovSubscription {
+            subscriber,
+            process_id,
+            object_id,
+            expires_at,
+            last_values: None,
+            last_sent: None,
+        });
+        Some(SubscribeOutcome::New)
+    }
+
+    /// Cancel a subscription; cancelling one that does not exist is not an error
+    pub fn cancel(&mut self, subscriber: &Requester, process_id: u32, object_id: u32) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions
+            .retain(|s| !(s.subscriber == *subscriber && s.process_id == process_id && s.object_id == object_id));
+        self.subscriptions.len() != before
+    }
+
+    /// Drop subscriptions whose lifetime has run out; returns how many
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|s| s.expires_at.map_or(true, |at| now < at));
+        before - self.subscriptions.len()
+    }
+
+    /// Subscriptions that need a notification: new or renewed ones, and those
+    /// whose object's monitored values (as encoded by `values`) changed since
+    /// the last one, at most once per `MIN_NOTIFY_INTERVAL`. Objects `values`
+    /// no longer knows (a Network Port that went away) are skipped.
+    pub fn due(&mut self, now: Instant, mut values: impl FnMut(u32) -> Option<Vec<u8>>) -> Vec<DueNotification> {
+        let mut due = Vec::new();
+        for subscription in self.subscriptions.iter_mut() {
+            if subscription.last_sent.is_some_and(|at| now.duration_since(at) < MIN_NOTIFY_INTERVAL) {
+                continue;
+            }
+            let Some(current) = values(subscription.object_id) else { continue };
+            if subscription.last_values.as_ref() == Some(&current) {
+                continue;
+            }
+            subscription.last_values = Some(current);
+            subscription.last_sent = Some(now);
+            due.push(DueNotification {
+                subscriber: subscription.subscriber.clone(),
+                process_id: subscription.process_id,
+                object_id: subscription.object_id,
+                time_remaining: subscription.time_remaining(now),
+            });
+        }
+        due
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    pub fn subscriptions(&self) -> &[CovSubscription] {
+        &self.subscriptions
+    }
+
+    fn find_mut(&mut self, subscriber: &Requester, process_id: u32, object_id: u32) -> Option<&mut CovSubscription> {
+        self.subscriptions
+            .iter_mut()
+            .find(|s| s.subscriber == *subscriber && s.process_id == process_id && s.object_id == object_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head_end() -> Requester {
+        Requester { link: LinkAddress::Ip("192.168.1.10:47808".parse().unwrap()), source: None }
+    }
+
+    #[test]
+    fn test_subscription_notifies_on_change_and_expires() {
+        let mut table = CovTable::new();
+        let start = Instant::now();
+        let mut value = 1u8;
+
+        assert_eq!(table.subscribe(head_end(), 7, 100, 60, start), Some(SubscribeOutcome::New));
+
+        // Initial notification, then nothing until the value changes
+        let due = table.due(start, |_| Some(vec![value]));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].process_id, 7);
+        assert_eq!(due[0].time_remaining, 60);
+        assert!(table.due(start + Duration::from_secs(2), |_| Some(vec![value])).is_empty());
+
+        // A change inside the minimum interval waits for it
+        table.subscribe(head_end(), 7, 100, 60, start);
+        table.due(start, |_| Some(vec![value]));
+        value = 2;
+        assert!(table.due(start + Duration::from_millis(500), |_| Some(vec![value])).is_empty());
+        let due = table.due(start + Duration::from_secs(1), |_| Some(vec![value]));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].time_remaining, 59);
+
+        // Gone objects are skipped, expired subscriptions dropped
+        assert!(table.due(start + Duration::from_secs(5), |_| None).is_empty());
+        assert_eq!(table.expire(start + Duration::from_secs(59)), 0);
+        assert_eq!(table.expire(start + Duration::from_secs(60)), 1);
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn test_resubscribe_renews_and_cancel_removes() {
+        let mut table = CovTable::new();
+        let start = Instant::now();
+
+        table.subscribe(head_end(), 1, 100, 10, start);
+        table.due(start, |_| Some(vec![0]));
+
+        // Resubscribing restarts the lifetime and sends a fresh initial notification
+        let later = start + Duration::from_secs(8);
+        assert_eq!(table.subscribe(head_end(), 1, 100, 10, later), Some(SubscribeOutcome::Renewed));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.due(later, |_| Some(vec![0])).len(), 1);
+        assert_eq!(table.expire(start + Duration::from_secs(12)), 0);
+
+        // Indefinite subscriptions never expire and report 0 seconds left
+        let other = Requester { link: LinkAddress::Mstp(12), source: Some(NetworkAddress::new(5, &[3])) };
+        table.subscribe(other.clone(), 1, 100, 0, start);
+        assert_eq!(table.subscriptions()[1].time_remaining(start), 0);
+        assert_eq!(table.expire(start + Duration::from_secs(86_400)), 1);
+        assert_eq!(table.len(), 1);
+
+        assert!(table.cancel(&other, 1, 100));
+        assert!(!table.cancel(&other, 1, 100));
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn test_table_full() {
+        let mut table = CovTable::new();
+        let now = Instant::now();
+        for process_id in 0..MAX_COV_SUBSCRIPTIONS as u32 {
+            assert!(table.subscribe(head_end(), process_id, 100, 0, now).is_some());
+        }
+        assert_eq!(table.subscribe(head_end(), 99, 100, 0, now), None);
+        // Renewing an existing one still works when full
+        assert_eq!(table.subscribe(head_end(), 0, 100, 30, now), Some(SubscribeOutcome::Renewed));
+    }
+}
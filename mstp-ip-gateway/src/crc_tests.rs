@@ -90,6 +90,7 @@ fn calculate_data_crc_register(data: &[u8]) -> u16 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mstp_driver::{calculate_data_crc as driver_data_crc, calculate_header_crc as driver_header_crc};
 
     // =========================================================================
     // PHASE 1.1: Header CRC-8 Tests (ASHRAE 135 Annex G.1)
@@ -450,7 +451,7 @@ mod tests {
         let frame = [0x55u8, 0xFF, 0x00, 0x10, 0x05, 0x00, 0x00, 0x8C];
 
         // Corrupt one bit in destination
-        let mut corrupted = frame.clone();
+        let mut corrupted = frame;
         corrupted[3] ^= 0x01;  // Flip LSB of destination
 
         let crc_check = calculate_header_crc_register(&corrupted[2..8]);
@@ -476,6 +477,76 @@ mod tests {
 
         println!("✓ Data CRC single bit error detection test PASSED");
     }
+
+    // =========================================================================
+    // MS/TP Driver CRC Tests (table-driven, must match the Annex G algorithms bit for bit)
+    // =========================================================================
+
+    #[test]
+    fn test_driver_crc_ashrae_vectors() {
+        assert_eq!(driver_header_crc(&[0x00, 0x10, 0x05, 0x00, 0x00]), 0x8C);
+        assert_eq!(driver_data_crc(&[0x01, 0x22, 0x30]), 0xBD10);
+        assert_eq!(driver_data_crc(&[]), 0x0000);
+
+        println!("✓ Driver CRC ASHRAE vector test PASSED");
+    }
+
+    #[test]
+    fn test_driver_crc_validates_complete_frames() {
+        // Token 0x05 -> 0x10 from Annex G.1, and a data frame carrying the Annex G.2 bytes
+        let token = [0x55u8, 0xFF, 0x00, 0x10, 0x05, 0x00, 0x00, 0x8C];
+        assert_eq!(driver_header_crc(&token[2..7]), token[7]);
+
+        let data = [0x01u8, 0x22, 0x30];
+        let header = [0x06u8, 0x0A, 0x14, 0x00, data.len() as u8];
+        let data_crc = driver_data_crc(&data);
+        let mut frame = vec![0x55, 0xFF];
+        frame.extend_from_slice(&header);
+        frame.push(driver_header_crc(&header));
+        frame.extend_from_slice(&data);
+        frame.extend_from_slice(&data_crc.to_le_bytes());
+        assert_eq!(&frame[11..], &[0x10, 0xBD], "data CRC is sent LSB first");
+        assert_eq!(calculate_header_crc_register(&frame[2..8]), 0x55);
+        assert_eq!(calculate_data_crc_register(&frame[8..]), 0xF0B8);
+
+        println!("✓ Driver CRC complete frame test PASSED");
+    }
+
+    #[test]
+    fn test_driver_header_crc_matches_parallel_algorithm() {
+        // Every single-byte input exercises every table entry
+        for byte in 0..=255u8 {
+            assert_eq!(driver_header_crc(&[byte]), calculate_header_crc(&[byte]));
+        }
+        for frame_type in 0..8u8 {
+            for dest in [0x00u8, 0x05, 0x7F, 0xFF] {
+                let header = [frame_type, dest, 0x03, 0x01, 0xF5];
+                assert_eq!(driver_header_crc(&header), calculate_header_crc(&header));
+            }
+        }
+
+        println!("✓ Driver header CRC equivalence test PASSED");
+    }
+
+    #[test]
+    fn test_driver_data_crc_matches_bitwise_algorithm() {
+        for byte in 0..=255u8 {
+            assert_eq!(driver_data_crc(&[byte]), calculate_data_crc(&[byte]));
+        }
+        // Pseudo-random payloads up to the extended frame size
+        let mut seed = 0x1234_5678u32;
+        let mut data = Vec::with_capacity(1497);
+        for len in [2usize, 3, 7, 64, 480, 501, 1476, 1497] {
+            data.clear();
+            for _ in 0..len {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                data.push((seed >> 16) as u8);
+            }
+            assert_eq!(driver_data_crc(&data), calculate_data_crc(&data), "len={}", len);
+        }
+
+        println!("✓ Driver data CRC equivalence test PASSED");
+    }
 }
 
 // Run tests with: cargo test --lib crc_tests
@@ -0,0 +1,699 @@
+//! Data link abstraction between the gateway core and the physical ports
+//!
+//! Each port (MS/TP over RS-485, BACnet/IP over UDP) implements `DataLink`
+//! so the routing code can address it through a trait object. This keeps
+//! the gateway independent of the transport and lets tests substitute a
+//! recording link for the real socket or UART. Each port's receive task
+//! polls its own link and hands the frames to the gateway.
+
+use log::{debug, info, warn};
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errors::{self, Classify, ErrorKind, ErrorSource};
+use crate::gateway::build_bvlc;
+use crate::mstp_driver::{MstpDriver, MstpError};
+use crate::npdu::Npdu;
+
+/// Maximum frames returned by a single `poll()`
+pub const MAX_POLL_FRAMES: usize = 16;
+
+/// How often an idle transmit task flushes its link
+const TX_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Consecutive B/IP socket errors after which the socket is re-bound
+pub const SOCKET_ERROR_LIMIT: u32 = 20;
+
+/// How long a re-bind waits for other tasks to let go of the old socket
+const REBIND_RELEASE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Receive timeout, so the receive task notices a re-bound socket
+pub const SOCKET_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Read timeout while collecting datagrams already waiting after the first
+const BATCH_READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// Address of a station on one of the gateway's data links
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkAddress {
+    /// MS/TP MAC address (255 = broadcast)
+    Mstp(u8),
+    /// BACnet/IP address (B/IP broadcast or multicast address for broadcasts)
+    Ip(SocketAddr),
+}
+
+impl fmt::Display for LinkAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkAddress::Mstp(mac) => write!(f, "MS/TP {}", mac),
+            LinkAddress::Ip(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+/// A frame received from a data link
+#[derive(Debug, Clone)]
+pub struct ReceivedFrame {
+    /// Link payload (NPDU for MS/TP, complete BVLC message for B/IP)
+    pub data: Vec<u8>,
+    pub source: LinkAddress,
+}
+
+/// Data link errors
+#[derive(Debug)]
+pub enum DataLinkError {
+    /// Destination belongs to a different kind of link
+    WrongAddressType(LinkAddress),
+    /// Transmit queue is full
+    QueueFull,
+    Io(String),
+}
+
+impl fmt::Display for DataLinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataLinkError::WrongAddressType(addr) => write!(f, "Address {} is not on this link", addr),
+            DataLinkError::QueueFull => write!(f, "Transmit queue full"),
+            DataLinkError::Io(s) => write!(f, "I/O error: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for DataLinkError {}
+
+impl Classify for DataLinkError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            DataLinkError::WrongAddressType(_) => ErrorKind::InvalidAddress,
+            DataLinkError::QueueFull => ErrorKind::QueueFull,
+            DataLinkError::Io(_) => ErrorKind::Io,
+        }
+    }
+}
+
+/// A BACnet data link port
+pub trait DataLink: Send {
+    /// Short name for logs ("MS/TP", "B/IP")
+    fn name(&self) -> &'static str;
+
+    /// Send an NPDU to a station (or the broadcast address) on this link,
+    /// adding whatever link-layer framing the port needs
+    fn send(&mut self, npdu: &[u8], dest: LinkAddress) -> Result<(), DataLinkError>;
+
+    /// Send an already framed link payload (e.g. a BVLC control message).
+    /// Links without their own framing treat this the same as `send`.
+    fn send_raw(&mut self, frame: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        self.send(frame, dest)
+    }
+
+    /// Transmit anything the link is holding back (e.g. delayed frames);
+    /// called regularly from the gateway's housekeeping
+    fn flush(&mut self) {}
+
+    /// Collect frames received since the last poll (never blocks for long)
+    fn poll(&mut self) -> Vec<ReceivedFrame>;
+
+    /// Whether the link can carry traffic right now (for MS/TP, whether
+    /// the token ring is up). Frames for a link that is down are held.
+    fn is_up(&self) -> bool {
+        true
+    }
+
+    /// Address that reaches every station on this link
+    fn broadcast_addr(&self) -> LinkAddress;
+}
+
+/// A link shared with another task, locked for each call. The gateway sends
+/// on the MS/TP driver this way while the MS/TP task polls it.
+pub struct SharedLink<L> {
+    inner: Arc<Mutex<L>>,
+    name: &'static str,
+    broadcast: LinkAddress,
+}
+
+impl<L: DataLink> SharedLink<L> {
+    pub fn new(inner: Arc<Mutex<L>>) -> Self {
+        let (name, broadcast) = {
+            let link = inner.lock().unwrap();
+            (link.name(), link.broadcast_addr())
+        };
+        Self { inner, name, broadcast }
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut L) -> T) -> Result<T, DataLinkError> {
+        let mut link = self.inner.lock().map_err(|_| DataLinkError::Io(format!("{} link lock poisoned", self.name)))?;
+        Ok(f(&mut link))
+    }
+}
+
+impl<L: DataLink> DataLink for SharedLink<L> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn send(&mut self, npdu: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        self.with(|link| link.send(npdu, dest))?
+    }
+
+    fn send_raw(&mut self, frame: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        self.with(|link| link.send_raw(frame, dest))?
+    }
+
+    fn flush(&mut self) {
+        let _ = self.with(|link| link.flush());
+    }
+
+    fn poll(&mut self) -> Vec<ReceivedFrame> {
+        self.with(|link| link.poll()).unwrap_or_default()
+    }
+
+    fn is_up(&self) -> bool {
+        self.with(|link| link.is_up()).unwrap_or(false)
+    }
+
+    fn broadcast_addr(&self) -> LinkAddress {
+        self.broadcast
+    }
+}
+
+/// The B/IP UDP socket, shared by the receive task, the transmit task and
+/// the main loop, and re-bound in place when it stops working (e.g. after
+/// the netif restarts).
+///
+/// Users take a handle with `current()` for each operation instead of
+/// keeping one, so a re-bind only has to wait for calls already in flight.
+#[derive(Debug)]
+pub struct BipSocket {
+    port: u16,
+    slot: Mutex<Option<Arc<UdpSocket>>>,
+    consecutive_errors: AtomicU32,
+    recoveries: AtomicU64,
+}
+
+impl BipSocket {
+    /// Bind the B/IP port on all interfaces
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let socket = Self::open(port)?;
+        Ok(Self {
+            port,
+            slot: Mutex::new(Some(Arc::new(socket))),
+            consecutive_errors: AtomicU32::new(0),
+            recoveries: AtomicU64::new(0),
+        })
+    }
+
+    fn open(port: u16) -> io::Result<UdpSocket> {
+        let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))?;
+        socket.set_broadcast(true)?;
+        socket.set_read_timeout(Some(SOCKET_READ_TIMEOUT))?;
+        Ok(socket)
+    }
+
+    /// The socket, or None while it is being re-bound
+    pub fn current(&self) -> Option<Arc<UdpSocket>> {
+        self.slot.lock().unwrap().clone()
+    }
+
+    /// Note a successful send or receive
+    pub fn record_ok(&self) {
+        self.consecutive_errors.store(0, Ordering::Relaxed);
+    }
+
+    /// Note a failed send or receive. An error meaning the socket itself is
+    /// gone reaches the limit at once.
+    pub fn record_error(&self, e: &io::Error) {
+        if is_fatal_socket_error(e) {
+            self.consecutive_errors.store(SOCKET_ERROR_LIMIT, Ordering::Relaxed);
+        } else {
+            self.consecutive_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The socket keeps failing, or a previous re-bind did not succeed
+    pub fn needs_rebind(&self) -> bool {
+        self.consecutive_errors.load(Ordering::Relaxed) >= SOCKET_ERROR_LIMIT || self.current().is_none()
+    }
+
+    /// Close the socket and bind a fresh one on the same port. On failure
+    /// the port is left closed and `needs_rebind()` stays true.
+    pub fn rebind(&self) -> io::Result<()> {
+        let old = self.slot.lock().unwrap().take();
+        if let Some(mut old) = old {
+            // The port is only free once every handle has been dropped
+            let started = Instant::now();
+            loop {
+                match Arc::try_unwrap(old) {
+                    Ok(socket) => {
+                        drop(socket);
+                        break;
+                    }
+                    Err(shared) if started.elapsed() < REBIND_RELEASE_TIMEOUT => {
+                        old = shared;
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(_) => {
+                        warn!("Old B/IP socket still in use - binding the new one anyway");
+                        break;
+                    }
+                }
+            }
+        }
+        let socket = Self::open(self.port)?;
+        *self.slot.lock().unwrap() = Some(Arc::new(socket));
+        self.consecutive_errors.store(0, Ordering::Relaxed);
+        let recoveries = self.recoveries.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("B/IP socket re-bound on port {} (recovery #{})", self.port, recoveries);
+        Ok(())
+    }
+
+    /// The UDP port the socket is bound to
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Times the socket has been re-bound since boot
+    pub fn recoveries(&self) -> u64 {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+}
+
+/// Errors that mean the socket (or its netif binding) is unusable, rather
+/// than one datagram failing
+fn is_fatal_socket_error(e: &io::Error) -> bool {
+    const EBADF: i32 = 9;
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::AddrNotAvailable
+    ) || e.raw_os_error() == Some(EBADF)
+}
+
+/// BACnet/IP port (Annex J) on the UDP socket shared with the receive task
+///
+/// The link's broadcast address is the limited broadcast on the B/IP port.
+/// The gateway works out the address(es) it actually broadcasts to (directed,
+/// limited or multicast) from its current interfaces, so a copy here can't
+/// go stale when the address changes or the socket is re-bound.
+pub struct BipLink {
+    socket: Arc<BipSocket>,
+    broadcast: SocketAddr,
+}
+
+impl BipLink {
+    pub fn new(socket: Arc<BipSocket>) -> Self {
+        let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, socket.port()));
+        Self { socket, broadcast }
+    }
+
+    fn ip_dest(dest: LinkAddress) -> Result<SocketAddr, DataLinkError> {
+        match dest {
+            LinkAddress::Ip(addr) => Ok(addr),
+            other => Err(DataLinkError::WrongAddressType(other)),
+        }
+    }
+
+    /// Read datagrams from the socket. With `wait` the first read blocks for
+    /// up to `SOCKET_READ_TIMEOUT`; the rest only collect what is already
+    /// waiting. Read timeouts are used rather than non-blocking mode, since
+    /// the transmit task sends on the same socket.
+    pub fn receive(&mut self, wait: bool) -> Vec<ReceivedFrame> {
+        let mut frames = Vec::new();
+        let Some(socket) = self.socket.current() else { return frames };
+        let mut buffer = [0u8; 1500];
+        let mut short_timeout = false;
+        while frames.len() < MAX_POLL_FRAMES {
+            if !short_timeout && (!wait || !frames.is_empty()) {
+                if socket.set_read_timeout(Some(BATCH_READ_TIMEOUT)).is_err() {
+                    break;
+                }
+                short_timeout = true;
+            }
+            match socket.recv_from(&mut buffer) {
+                Ok((len, source)) => {
+                    self.socket.record_ok();
+                    frames.push(ReceivedFrame {
+                        data: buffer[..len].to_vec(),
+                        source: LinkAddress::Ip(source),
+                    });
+                }
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+                Err(e) => {
+                    warn!("B/IP receive error: {}", e);
+                    errors::record(ErrorSource::Ip, ErrorKind::Io, &e.to_string());
+                    self.socket.record_error(&e);
+                    break;
+                }
+            }
+        }
+        if short_timeout {
+            let _ = socket.set_read_timeout(Some(SOCKET_READ_TIMEOUT));
+        }
+        frames
+    }
+}
+
+impl DataLink for BipLink {
+    fn name(&self) -> &'static str {
+        "B/IP"
+    }
+
+    /// Wrap the NPDU in Original-Unicast-NPDU or, for the broadcast
+    /// address, Original-Broadcast-NPDU
+    fn send(&mut self, npdu: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        let addr = Self::ip_dest(dest)?;
+        let frame = build_bvlc(npdu, addr == self.broadcast);
+        self.send_raw(&frame, dest)
+    }
+
+    fn send_raw(&mut self, frame: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        let addr = Self::ip_dest(dest)?;
+        let socket = self.socket.current().ok_or_else(|| DataLinkError::Io("B/IP socket is being re-bound".to_string()))?;
+        match socket.send_to(frame, addr) {
+            Ok(bytes_sent) => {
+                debug!("IP TX: sent {} bytes to {}", bytes_sent, addr);
+                self.socket.record_ok();
+                Ok(())
+            }
+            Err(e) => {
+                self.socket.record_error(&e);
+                Err(DataLinkError::Io(e.to_string()))
+            }
+        }
+    }
+
+    /// Collect the datagrams already waiting on the socket
+    fn poll(&mut self) -> Vec<ReceivedFrame> {
+        self.receive(false)
+    }
+
+    fn is_up(&self) -> bool {
+        self.socket.current().is_some()
+    }
+
+    fn broadcast_addr(&self) -> LinkAddress {
+        LinkAddress::Ip(self.broadcast)
+    }
+}
+
+/// Counters shared between a `QueuedLink` and its transmit task
+#[derive(Debug, Default)]
+pub struct TxQueueStats {
+    /// Frames accepted into the queue
+    pub queued: AtomicU64,
+    /// Frames handed to the underlying link successfully
+    pub sent: AtomicU64,
+    /// Frames dropped because the queue was full
+    pub dropped: AtomicU64,
+    /// Frames the underlying link failed to send
+    pub send_errors: AtomicU64,
+    /// Frames currently waiting in the queue
+    pub depth: AtomicUsize,
+}
+
+/// A frame waiting for the transmit task
+struct TxRequest {
+    data: Vec<u8>,
+    dest: LinkAddress,
+    /// Already framed (`send_raw`) rather than a bare NPDU (`send`)
+    raw: bool,
+}
+
+/// Transmit-only front end for a link driven by its own thread
+///
+/// Sends are queued on a bounded channel and return immediately, so a slow
+/// socket (full ARP queue, lwIP back-pressure) cannot stall the caller while
+/// it holds the gateway lock. When the queue is full the frame is dropped
+/// and counted. Reception stays with the port's receive task.
+pub struct QueuedLink {
+    name: &'static str,
+    broadcast: LinkAddress,
+    tx: SyncSender<TxRequest>,
+    stats: Arc<TxQueueStats>,
+}
+
+impl QueuedLink {
+    /// Move `inner` to a new transmit thread fed by a queue of `capacity` frames
+    pub fn spawn(
+        mut inner: Box<dyn DataLink>,
+        capacity: usize,
+        stack_size: usize,
+    ) -> std::io::Result<(Self, Arc<TxQueueStats>)> {
+        let name = inner.name();
+        let broadcast = inner.broadcast_addr();
+        let stats = Arc::new(TxQueueStats::default());
+        let (tx, rx) = mpsc::sync_channel::<TxRequest>(capacity);
+
+        let task_stats = Arc::clone(&stats);
+        thread::Builder::new()
+            .name(format!("{} TX", name))
+            .stack_size(stack_size)
+            .spawn(move || {
+                info!("{} transmit task started (queue {} frames)", name, capacity);
+                loop {
+                    match rx.recv_timeout(TX_FLUSH_INTERVAL) {
+                        Ok(request) => {
+                            task_stats.depth.fetch_sub(1, Ordering::Relaxed);
+                            let result = if request.raw {
+                                inner.send_raw(&request.data, request.dest)
+                            } else {
+                                inner.send(&request.data, request.dest)
+                            };
+                            match result {
+                                Ok(()) => {
+                                    task_stats.sent.fetch_add(1, Ordering::Relaxed);
+                                }
+                                Err(e) => {
+                                    task_stats.send_errors.fetch_add(1, Ordering::Relaxed);
+                                    warn!("{} TX failed to {}: {}", name, request.dest, e);
+                                }
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    inner.flush();
+                }
+                info!("{} transmit task stopped", name);
+            })?;
+
+        let link = Self { name, broadcast, tx, stats: Arc::clone(&stats) };
+        Ok((link, stats))
+    }
+
+    fn enqueue(&mut self, data: &[u8], dest: LinkAddress, raw: bool) -> Result<(), DataLinkError> {
+        // Count before sending so the task never sees the depth go negative
+        self.stats.depth.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(TxRequest { data: data.to_vec(), dest, raw }) {
+            Ok(()) => {
+                self.stats.queued.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                self.stats.depth.fetch_sub(1, Ordering::Relaxed);
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(DataLinkError::QueueFull)
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.stats.depth.fetch_sub(1, Ordering::Relaxed);
+                Err(DataLinkError::Io(format!("{} transmit task stopped", self.name)))
+            }
+        }
+    }
+}
+
+impl DataLink for QueuedLink {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn send(&mut self, npdu: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        self.enqueue(npdu, dest, false)
+    }
+
+    fn send_raw(&mut self, frame: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        self.enqueue(frame, dest, true)
+    }
+
+    /// The transmit task owns the link; frames arrive through the receive task
+    fn poll(&mut self) -> Vec<ReceivedFrame> {
+        Vec::new()
+    }
+
+    fn broadcast_addr(&self) -> LinkAddress {
+        self.broadcast
+    }
+}
+
+impl DataLink for MstpDriver<'_> {
+    fn name(&self) -> &'static str {
+        "MS/TP"
+    }
+
+    /// Queue the NPDU for the next token; the frame type follows the
+    /// NPDU's data-expecting-reply bit
+    fn send(&mut self, npdu: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        let mac = match dest {
+            LinkAddress::Mstp(mac) => mac,
+            other => return Err(DataLinkError::WrongAddressType(other)),
+        };
+        let expecting_reply = Npdu::decode(npdu).is_ok_and(|npdu| npdu.expecting_reply);
+        self.send_frame(npdu, mac, expecting_reply).map_err(|e| match e {
+            MstpError::BufferFull => DataLinkError::QueueFull,
+            other => DataLinkError::Io(other.to_string()),
+        })
+    }
+
+    /// Run the MS/TP state machine and collect any data frames addressed to us
+    fn poll(&mut self) -> Vec<ReceivedFrame> {
+        self.record_poll();
+        let mut frames = Vec::new();
+        while frames.len() < MAX_POLL_FRAMES {
+            match self.receive_frame() {
+                Ok(Some((data, source))) => frames.push(ReceivedFrame {
+                    data,
+                    source: LinkAddress::Mstp(source),
+                }),
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("MS/TP poll error: {}", e);
+                    errors::record_error(ErrorSource::Mstp, &e);
+                    break;
+                }
+            }
+        }
+        frames
+    }
+
+    fn is_up(&self) -> bool {
+        self.is_ring_up()
+    }
+
+    fn broadcast_addr(&self) -> LinkAddress {
+        LinkAddress::Mstp(0xFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+
+    /// Link that records each send and, when gated, waits for a go-ahead first
+    struct TestLink {
+        sent: Arc<Mutex<Vec<(Vec<u8>, bool)>>>,
+        gate: Option<Receiver<()>>,
+    }
+
+    impl TestLink {
+        fn record(&mut self, data: &[u8], raw: bool) -> Result<(), DataLinkError> {
+            if let Some(gate) = &self.gate {
+                let _ = gate.recv();
+            }
+            self.sent.lock().unwrap().push((data.to_vec(), raw));
+            Ok(())
+        }
+    }
+
+    impl DataLink for TestLink {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+        fn send(&mut self, npdu: &[u8], _dest: LinkAddress) -> Result<(), DataLinkError> {
+            self.record(npdu, false)
+        }
+        fn send_raw(&mut self, frame: &[u8], _dest: LinkAddress) -> Result<(), DataLinkError> {
+            self.record(frame, true)
+        }
+        fn poll(&mut self) -> Vec<ReceivedFrame> {
+            Vec::new()
+        }
+        fn broadcast_addr(&self) -> LinkAddress {
+            LinkAddress::Mstp(0xFF)
+        }
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for transmit task");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_queued_link_sends_in_order() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let inner = TestLink { sent: Arc::clone(&sent), gate: None };
+        let (mut link, stats) = QueuedLink::spawn(Box::new(inner), 4, 16 * 1024).unwrap();
+
+        assert_eq!(link.broadcast_addr(), LinkAddress::Mstp(0xFF));
+        link.send(&[1], LinkAddress::Mstp(1)).unwrap();
+        link.send_raw(&[2], LinkAddress::Mstp(2)).unwrap();
+
+        wait_for(|| stats.sent.load(Ordering::Relaxed) == 2);
+        assert_eq!(*sent.lock().unwrap(), vec![(vec![1], false), (vec![2], true)]);
+        assert_eq!(stats.queued.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.depth.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_queued_link_drops_when_full() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let (go, gate) = mpsc::channel();
+        let inner = TestLink { sent: Arc::clone(&sent), gate: Some(gate) };
+        let (mut link, stats) = QueuedLink::spawn(Box::new(inner), 2, 16 * 1024).unwrap();
+
+        // The task takes the first frame and blocks in the link; two more fill the queue
+        link.send(&[0], LinkAddress::Mstp(1)).unwrap();
+        wait_for(|| stats.depth.load(Ordering::Relaxed) == 0);
+        link.send(&[1], LinkAddress::Mstp(1)).unwrap();
+        link.send(&[2], LinkAddress::Mstp(1)).unwrap();
+        assert!(matches!(link.send(&[3], LinkAddress::Mstp(1)), Err(DataLinkError::QueueFull)));
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.depth.load(Ordering::Relaxed), 2);
+
+        for _ in 0..3 {
+            go.send(()).unwrap();
+        }
+        wait_for(|| stats.sent.load(Ordering::Relaxed) == 3);
+        let frames: Vec<Vec<u8>> = sent.lock().unwrap().iter().map(|(d, _)| d.clone()).collect();
+        assert_eq!(frames, vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn test_bip_socket_rebinds_after_persistent_errors() {
+        let socket = BipSocket::bind(0).unwrap();
+        assert!(!socket.needs_rebind());
+
+        // Occasional send failures are not enough, and a success clears them
+        let transient = io::Error::other("no route to host");
+        for _ in 1..SOCKET_ERROR_LIMIT {
+            socket.record_error(&transient);
+        }
+        assert!(!socket.needs_rebind());
+        socket.record_ok();
+        socket.record_error(&transient);
+        assert!(!socket.needs_rebind());
+
+        // A dead socket is re-bound straight away
+        socket.record_error(&io::Error::from(io::ErrorKind::NotConnected));
+        assert!(socket.needs_rebind());
+
+        // A handle still held by another task delays the re-bind until dropped
+        let held = socket.current().unwrap();
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(held);
+        });
+        socket.rebind().unwrap();
+        holder.join().unwrap();
+        assert!(!socket.needs_rebind());
+        assert!(socket.current().is_some());
+        assert_eq!(socket.recoveries(), 1);
+    }
+}
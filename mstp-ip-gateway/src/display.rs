@@ -7,8 +7,16 @@
 //! - Screen 0: Status (traffic stats, loop time)
 //! - Screen 1: Connection (WiFi, MSTP status, baud, address)
 //! - Screen 2: Splash (BACman logo)
+//!
+//! Rendering runs in its own low-priority task on a fixed cadence: each
+//! screen region is tied to a dirty-field bit and only changed regions are
+//! repainted.
 
 use display_interface_spi::SPIInterface;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
 use embedded_graphics::{
     mono_font::{ascii::{FONT_6X13, FONT_9X18_BOLD, FONT_10X20}, MonoTextStyle},
     pixelcolor::Rgb565,
@@ -20,9 +28,13 @@ use esp_idf_svc::hal::{
     delay::FreeRtos,
     gpio::{OutputPin, PinDriver},
     spi::{SpiDeviceDriver, SpiDriver},
+    task::thread::ThreadSpawnConfiguration,
 };
+use log::warn;
 use mipidsi::{models::ST7789, options::{ColorInversion, Orientation, Rotation}, Builder};
 
+use crate::web::WebState;
+
 /// Display width in pixels (landscape mode - swapped)
 #[allow(dead_code)]
 pub const DISPLAY_WIDTH: u32 = 240;
@@ -34,6 +46,35 @@ pub const DISPLAY_HEIGHT: u32 = 135;
 #[allow(dead_code)]
 pub const NUM_SCREENS: u8 = 4;
 
+/// How often the render task repaints dirty regions
+pub const RENDER_INTERVAL: Duration = Duration::from_millis(500);
+
+/// FreeRTOS priority of the render task (below the routing tasks)
+const RENDER_TASK_PRIORITY: u8 = 1;
+
+/// Stack size for the render task
+const RENDER_STACK_SIZE: usize = 8192;
+
+/// Dirty-field bits, one per independently repainted screen region
+pub mod field {
+    pub const WIFI: u16 = 1 << 0;
+    pub const NETWORKS: u16 = 1 << 1;
+    pub const RX_FRAMES: u16 = 1 << 2;
+    pub const TX_FRAMES: u16 = 1 << 3;
+    pub const TOKEN_LOOP: u16 = 1 << 4;
+    pub const CRC_ERRORS: u16 = 1 << 5;
+    pub const MASTERS: u16 = 1 << 6;
+    pub const MSTP_STATE: u16 = 1 << 7;
+    pub const BAUD_RATE: u16 = 1 << 8;
+    pub const ADDRESS: u16 = 1 << 9;
+    pub const AP_MODE: u16 = 1 << 10;
+    pub const AP_SSID: u16 = 1 << 11;
+    pub const AP_IP: u16 = 1 << 12;
+    pub const AP_CLIENTS: u16 = 1 << 13;
+    pub const SCHEDULE: u16 = 1 << 14;
+    pub const ALL: u16 = u16::MAX;
+}
+
 /// Display screen types
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub enum DisplayScreen {
@@ -91,6 +132,59 @@ pub struct GatewayStatus {
     pub ap_ssid: String,
     pub ap_ip: String,
     pub ap_clients: u8,
+    /// Strongest hotspot client and its lease (see `ap_clients::summary`)
+    pub ap_client_summary: String,
+    // Duplicate MS/TP network number heard on the IP side
+    pub network_conflict: Option<u16>,
+    /// Countdown to a scheduled configuration change or its rollback
+    pub schedule_countdown: String,
+}
+
+impl GatewayStatus {
+    /// Dirty-field bits for the values that differ from `prev`
+    pub fn changed_fields(&self, prev: &GatewayStatus) -> u16 {
+        let mut dirty = 0;
+        let mut mark = |changed: bool, bit: u16| {
+            if changed {
+                dirty |= bit;
+            }
+        };
+        mark(
+            self.wifi_connected != prev.wifi_connected
+                || self.ip_address != prev.ip_address
+                || self.ap_mode_active != prev.ap_mode_active,
+            field::WIFI,
+        );
+        mark(
+            self.mstp_network != prev.mstp_network
+                || self.ip_network != prev.ip_network
+                || self.network_conflict != prev.network_conflict,
+            field::NETWORKS,
+        );
+        mark(self.rx_frames != prev.rx_frames, field::RX_FRAMES);
+        mark(self.tx_frames != prev.tx_frames, field::TX_FRAMES);
+        mark(self.token_loop_ms != prev.token_loop_ms, field::TOKEN_LOOP);
+        mark(self.crc_errors != prev.crc_errors, field::CRC_ERRORS);
+        mark(self.master_count != prev.master_count, field::MASTERS);
+        mark(
+            self.mstp_state != prev.mstp_state || self.has_token != prev.has_token,
+            field::MSTP_STATE,
+        );
+        mark(self.mstp_baud_rate != prev.mstp_baud_rate, field::BAUD_RATE);
+        mark(
+            self.mstp_address != prev.mstp_address || self.mstp_max_master != prev.mstp_max_master,
+            field::ADDRESS,
+        );
+        mark(self.ap_mode_active != prev.ap_mode_active, field::AP_MODE);
+        mark(self.ap_ssid != prev.ap_ssid, field::AP_SSID);
+        mark(self.ap_ip != prev.ap_ip, field::AP_IP);
+        mark(
+            self.ap_clients != prev.ap_clients || self.ap_client_summary != prev.ap_client_summary,
+            field::AP_CLIENTS,
+        );
+        mark(self.schedule_countdown != prev.schedule_countdown, field::SCHEDULE);
+        dirty
+    }
 }
 
 /// Display wrapper for M5StickC Plus2
@@ -105,6 +199,8 @@ where
     backlight: PinDriver<'static, BL, esp_idf_svc::hal::gpio::Output>,
     /// Track previous status for incremental updates
     last_status: Option<GatewayStatus>,
+    /// Screen currently shown (None until the first render)
+    rendered_screen: Option<DisplayScreen>,
 }
 
 #[allow(dead_code)]
@@ -141,7 +237,7 @@ where
         display.clear(Rgb565::BLACK)
             .map_err(|e| anyhow::anyhow!("Clear failed: {:?}", e))?;
 
-        Ok(Self { display, backlight, last_status: None })
+        Ok(Self { display, backlight, last_status: None, rendered_screen: None })
     }
 
     /// Show splash screen with BACman branding
@@ -289,44 +385,15 @@ where
         let yellow = MonoTextStyle::new(&FONT_6X13, Rgb565::YELLOW);
         let red = MonoTextStyle::new(&FONT_6X13, Rgb565::RED);
 
-        // First time: draw full layout
-        if self.last_status.is_none() {
-            self.clear()?;
-            self.draw_static_layout()?;
-
-            // Draw all values - show mode (AP/STA) and IP
-            let (mode_text, wifi_style) = if status.ap_mode_active {
-                ("AP", green)  // AP mode is always "connected" when active
-            } else if status.wifi_connected {
-                ("STA", green)
-            } else {
-                ("---", yellow)
-            };
-            self.draw_value(10, 35, 230, &format!("{}:{}", mode_text, status.ip_address), wifi_style)?;
-
-            let net_text = format!("{}<->{}", status.mstp_network, status.ip_network);
-            self.draw_value(34, 55, 80, &net_text, white)?;
-
-            self.draw_value(28, 75, 60, &status.rx_frames.to_string(), green)?;
-            self.draw_value(118, 75, 70, &status.tx_frames.to_string(), green)?;
-            self.draw_value(40, 95, 50, &format!("{}ms", status.token_loop_ms), white)?;
-
-            let err_style = if status.crc_errors > 0 { red } else { green };
-            self.draw_value(124, 95, 40, &status.crc_errors.to_string(), err_style)?;
-            self.draw_value(182, 95, 30, &status.master_count.to_string(), white)?;
-
-            self.last_status = Some(status.clone());
+        let dirty = self.dirty_fields(status, Self::draw_static_layout)?;
+        if dirty == 0 {
             return Ok(());
         }
 
-        // Incremental update - only changed fields
-        // Use take() instead of clone() to avoid allocation (we'll replace it anyway)
-        let last = self.last_status.take().unwrap();
-
-        // WiFi mode and status
-        if last.wifi_connected != status.wifi_connected || last.ip_address != status.ip_address || last.ap_mode_active != status.ap_mode_active {
+        // WiFi mode (AP/STA) and IP
+        if dirty & field::WIFI != 0 {
             let (mode_text, wifi_style) = if status.ap_mode_active {
-                ("AP", green)
+                ("AP", green)  // AP mode is always "connected" when active
             } else if status.wifi_connected {
                 ("STA", green)
             } else {
@@ -336,41 +403,90 @@ where
         }
 
         // Network numbers (rarely change)
-        if last.mstp_network != status.mstp_network || last.ip_network != status.ip_network {
-            let net_text = format!("{}<->{}", status.mstp_network, status.ip_network);
-            self.draw_value(34, 55, 80, &net_text, white)?;
+        if dirty & field::NETWORKS != 0 {
+            self.draw_networks(status, white, red)?;
         }
 
-        // RX frames
-        if last.rx_frames != status.rx_frames {
+        if dirty & field::RX_FRAMES != 0 {
             self.draw_value(28, 75, 60, &status.rx_frames.to_string(), green)?;
         }
 
-        // TX frames
-        if last.tx_frames != status.tx_frames {
+        if dirty & field::TX_FRAMES != 0 {
             self.draw_value(118, 75, 70, &status.tx_frames.to_string(), green)?;
         }
 
-        // Token loop time
-        if last.token_loop_ms != status.token_loop_ms {
+        if dirty & field::TOKEN_LOOP != 0 {
             self.draw_value(40, 95, 50, &format!("{}ms", status.token_loop_ms), white)?;
         }
 
-        // CRC errors
-        if last.crc_errors != status.crc_errors {
+        if dirty & field::CRC_ERRORS != 0 {
             let err_style = if status.crc_errors > 0 { red } else { green };
             self.draw_value(124, 95, 40, &status.crc_errors.to_string(), err_style)?;
         }
 
-        // Master count
-        if last.master_count != status.master_count {
+        if dirty & field::MASTERS != 0 {
             self.draw_value(182, 95, 30, &status.master_count.to_string(), white)?;
         }
 
+        if dirty & field::SCHEDULE != 0 {
+            self.draw_value(10, 115, 220, &status.schedule_countdown, yellow)?;
+        }
+
         self.last_status = Some(status.clone());
         Ok(())
     }
 
+    /// Draw the network numbers, or a warning while a duplicate is detected
+    fn draw_networks(
+        &mut self,
+        status: &GatewayStatus,
+        normal: MonoTextStyle<'_, Rgb565>,
+        warning: MonoTextStyle<'_, Rgb565>,
+    ) -> Result<(), anyhow::Error> {
+        match status.network_conflict {
+            Some(network) => self.draw_value(34, 55, 80, &format!("DUP {}!", network), warning),
+            None => {
+                let net_text = format!("{}<->{}", status.mstp_network, status.ip_network);
+                self.draw_value(34, 55, 80, &net_text, normal)
+            }
+        }
+    }
+
+    /// Fields that need repainting for `status`. After a reset the screen is
+    /// cleared and its static `layout` drawn, and every field is dirty.
+    fn dirty_fields(
+        &mut self,
+        status: &GatewayStatus,
+        layout: fn(&mut Self) -> Result<(), anyhow::Error>,
+    ) -> Result<u16, anyhow::Error> {
+        if let Some(last) = &self.last_status {
+            return Ok(status.changed_fields(last));
+        }
+        self.clear()?;
+        layout(self)?;
+        Ok(field::ALL)
+    }
+
+    /// Bring `screen` up to date, repainting only the regions whose fields
+    /// changed. Switching screens (or `full_redraw`) repaints everything.
+    pub fn render(&mut self, screen: DisplayScreen, status: &GatewayStatus, full_redraw: bool) -> Result<(), anyhow::Error> {
+        if full_redraw || self.rendered_screen != Some(screen) {
+            self.clear_and_reset()?;
+            self.rendered_screen = Some(screen);
+            if screen == DisplayScreen::Splash {
+                return self.show_splash_screen();
+            }
+        }
+
+        match screen {
+            DisplayScreen::Status => self.update_status(status),
+            DisplayScreen::Connection => self.update_connection(status),
+            DisplayScreen::APConfig => self.update_ap_config(status),
+            // Splash screen is static, no updates needed
+            DisplayScreen::Splash => Ok(()),
+        }
+    }
+
     /// Clear the display
     pub fn clear(&mut self) -> Result<(), anyhow::Error> {
         self.display.clear(Rgb565::BLACK)
@@ -426,83 +542,45 @@ where
         let red = MonoTextStyle::new(&FONT_6X13, Rgb565::RED);
         let white = MonoTextStyle::new(&FONT_6X13, Rgb565::WHITE);
 
-        // First time: draw full layout
-        if self.last_status.is_none() {
-            self.clear()?;
-            self.draw_connection_layout()?;
+        let dirty = self.dirty_fields(status, Self::draw_connection_layout)?;
+        if dirty == 0 {
+            return Ok(());
+        }
 
-            // WiFi status with IP
+        // WiFi status with IP
+        if dirty & field::WIFI != 0 {
             let (wifi_text, wifi_style) = if status.wifi_connected {
                 (format!("Connected ({})", status.ip_address), green)
             } else {
                 ("Disconnected".to_string(), red)
             };
             self.draw_value(46, 35, 190, &wifi_text, wifi_style)?;
+        }
 
-            // MS/TP state
+        // MS/TP state and token status
+        if dirty & field::MSTP_STATE != 0 {
             let mstp_style = if status.has_token { green } else { yellow };
             self.draw_value(50, 55, 180, &status.mstp_state, mstp_style)?;
 
-            // Baud rate
-            self.draw_value(46, 75, 100, &format!("{}", status.mstp_baud_rate), white)?;
-
-            // Address info
-            let addr_text = format!("{} (max: {})", status.mstp_address, status.mstp_max_master);
-            self.draw_value(46, 95, 150, &addr_text, white)?;
-
-            // Token status
             let (token_text, token_style) = if status.has_token {
                 ("Have Token", green)
             } else {
                 ("Waiting", yellow)
             };
             self.draw_value(50, 115, 100, token_text, token_style)?;
-
-            self.last_status = Some(status.clone());
-            return Ok(());
-        }
-
-        // Incremental update - only changed fields
-        // Use take() instead of clone() to avoid allocation (we'll replace it anyway)
-        let last = self.last_status.take().unwrap();
-
-        // WiFi status
-        if last.wifi_connected != status.wifi_connected || last.ip_address != status.ip_address {
-            let (wifi_text, wifi_style) = if status.wifi_connected {
-                (format!("Connected ({})", status.ip_address), green)
-            } else {
-                ("Disconnected".to_string(), red)
-            };
-            self.draw_value(46, 35, 190, &wifi_text, wifi_style)?;
-        }
-
-        // MS/TP state
-        if last.mstp_state != status.mstp_state || last.has_token != status.has_token {
-            let mstp_style = if status.has_token { green } else { yellow };
-            self.draw_value(50, 55, 180, &status.mstp_state, mstp_style)?;
         }
 
         // Baud rate (rarely changes)
-        if last.mstp_baud_rate != status.mstp_baud_rate {
+        if dirty & field::BAUD_RATE != 0 {
             self.draw_value(46, 75, 100, &format!("{}", status.mstp_baud_rate), white)?;
         }
 
         // Address info (rarely changes)
-        if last.mstp_address != status.mstp_address || last.mstp_max_master != status.mstp_max_master {
+        if dirty & field::ADDRESS != 0 {
             let addr_text = format!("{} (max: {})", status.mstp_address, status.mstp_max_master);
             self.draw_value(46, 95, 150, &addr_text, white)?;
         }
 
-        // Token status
-        if last.has_token != status.has_token {
-            let (token_text, token_style) = if status.has_token {
-                ("Have Token", green)
-            } else {
-                ("Waiting", yellow)
-            };
-            self.draw_value(50, 115, 100, token_text, token_style)?;
-        }
-
         self.last_status = Some(status.clone());
         Ok(())
     }
@@ -549,71 +627,23 @@ where
         let yellow = MonoTextStyle::new(&FONT_6X13, Rgb565::YELLOW);
         let white = MonoTextStyle::new(&FONT_6X13, Rgb565::WHITE);
 
-        // First time: draw full layout
-        if self.last_status.is_none() {
-            self.clear()?;
-            self.draw_ap_config_layout()?;
-
-            // AP mode status
-            let (status_text, status_style) = if status.ap_mode_active {
-                ("ACTIVE", green)
-            } else {
-                ("Inactive", yellow)
-            };
-            self.draw_value(58, 35, 100, status_text, status_style)?;
-
-            // SSID
-            let ssid_display = if status.ap_ssid.len() > 18 {
-                &status.ap_ssid[..18]
-            } else {
-                &status.ap_ssid
-            };
-            self.draw_value(46, 55, 180, ssid_display, white)?;
-
-            // IP Address
-            let ip_text = if status.ap_mode_active {
-                &status.ap_ip
-            } else {
-                "192.168.4.1"
-            };
-            self.draw_value(28, 75, 150, ip_text, white)?;
-
-            // Connected clients
-            let clients_text = if status.ap_mode_active {
-                format!("{}", status.ap_clients)
-            } else {
-                "-".to_string()
-            };
-            self.draw_value(64, 95, 50, &clients_text, white)?;
-
-            self.last_status = Some(status.clone());
+        let dirty = self.dirty_fields(status, Self::draw_ap_config_layout)?;
+        if dirty == 0 {
             return Ok(());
         }
 
-        // Incremental update - only changed fields
-        // Use take() instead of clone() to avoid allocation (we'll replace it anyway)
-        let last = self.last_status.take().unwrap();
-
         // AP mode status
-        if last.ap_mode_active != status.ap_mode_active {
+        if dirty & field::AP_MODE != 0 {
             let (status_text, status_style) = if status.ap_mode_active {
                 ("ACTIVE", green)
             } else {
                 ("Inactive", yellow)
             };
             self.draw_value(58, 35, 100, status_text, status_style)?;
-
-            // Also update IP when mode changes
-            let ip_text = if status.ap_mode_active {
-                &status.ap_ip
-            } else {
-                "192.168.4.1"
-            };
-            self.draw_value(28, 75, 150, ip_text, white)?;
         }
 
         // SSID (rarely changes)
-        if last.ap_ssid != status.ap_ssid {
+        if dirty & field::AP_SSID != 0 {
             let ssid_display = if status.ap_ssid.len() > 18 {
                 &status.ap_ssid[..18]
             } else {
@@ -622,8 +652,8 @@ where
             self.draw_value(46, 55, 180, ssid_display, white)?;
         }
 
-        // IP address
-        if last.ap_ip != status.ap_ip {
+        // IP address (shows the default while the AP is down)
+        if dirty & (field::AP_MODE | field::AP_IP) != 0 {
             let ip_text = if status.ap_mode_active {
                 &status.ap_ip
             } else {
@@ -633,13 +663,15 @@ where
         }
 
         // Connected clients
-        if last.ap_clients != status.ap_clients || last.ap_mode_active != status.ap_mode_active {
+        if dirty & (field::AP_MODE | field::AP_CLIENTS) != 0 {
             let clients_text = if status.ap_mode_active {
                 format!("{}", status.ap_clients)
             } else {
                 "-".to_string()
             };
             self.draw_value(64, 95, 50, &clients_text, white)?;
+            let summary = if status.ap_mode_active { status.ap_client_summary.as_str() } else { "" };
+            self.draw_value(10, 110, 220, summary, white)?;
         }
 
         self.last_status = Some(status.clone());
@@ -658,3 +690,79 @@ where
         Ok(())
     }
 }
+
+/// Screen contents published by the main loop for the render task
+#[derive(Default)]
+pub struct DisplayState {
+    pub screen: DisplayScreen,
+    pub status: GatewayStatus,
+    /// Clear and repaint the whole screen on the next render
+    pub full_redraw: bool,
+    /// Title and subtitle shown instead of any screen (shutdown notice)
+    pub message: Option<(&'static str, &'static str)>,
+}
+
+/// Spawn the render task. It repaints the dirty regions of the current
+/// screen every `RENDER_INTERVAL` at low priority, so drawing over SPI never
+/// delays routing, and records how long each render took in the web state.
+pub fn spawn_render_task<DC, RST, BL>(
+    mut display: Display<DC, RST, BL>,
+    state: Arc<Mutex<DisplayState>>,
+    web_state: Arc<Mutex<WebState>>,
+) -> anyhow::Result<()>
+where
+    DC: OutputPin + Send + 'static,
+    RST: OutputPin + Send + 'static,
+    BL: OutputPin + Send + 'static,
+{
+    ThreadSpawnConfiguration {
+        name: Some(b"display\0"),
+        priority: RENDER_TASK_PRIORITY,
+        ..Default::default()
+    }
+    .set()?;
+    let spawned = thread::Builder::new()
+        .stack_size(RENDER_STACK_SIZE)
+        .spawn(move || {
+            let mut status = GatewayStatus::default();
+            let mut message_shown = false;
+            loop {
+                thread::sleep(RENDER_INTERVAL);
+
+                // Copy out under the lock; drawing happens without it
+                let (screen, full_redraw, message) = match state.lock() {
+                    Ok(mut shared) => {
+                        status.clone_from(&shared.status);
+                        (shared.screen, std::mem::take(&mut shared.full_redraw), shared.message)
+                    }
+                    Err(_) => continue,
+                };
+
+                // A message replaces the screens for good; draw it once
+                if let Some((title, subtitle)) = message {
+                    if !message_shown {
+                        if let Err(e) = display.show_status_message(title, subtitle) {
+                            warn!("Failed to show display message: {}", e);
+                        }
+                        message_shown = true;
+                    }
+                    continue;
+                }
+
+                let started = Instant::now();
+                if let Err(e) = display.render(screen, &status, full_redraw) {
+                    warn!("Failed to update {:?} display: {}", screen, e);
+                }
+                let elapsed_us = started.elapsed().as_micros().min(u32::MAX as u128) as u32;
+
+                if let Ok(mut web) = web_state.try_lock() {
+                    web.display_render_us = elapsed_us;
+                    web.display_render_max_us = web.display_render_max_us.max(elapsed_us);
+                }
+            }
+        });
+    // Restore the default for threads spawned after this one
+    ThreadSpawnConfiguration::default().set()?;
+    spawned?;
+    Ok(())
+}
@@ -0,0 +1,243 @@
+//! Unified error log
+//!
+//! Routing (`GatewayError`), MS/TP driver (`MstpError`) and web/API failures
+//! are mapped onto one set of error kinds. The most recent errors and a
+//! counter per kind are kept in RAM and served by `/api/errors` and the
+//! proprietary Gateway Diagnostics object of the local BACnet device, so a
+//! single place answers "what's going wrong on this router".
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Recent errors kept in RAM; the oldest are dropped first
+pub const MAX_RECENT_ERRORS: usize = 16;
+
+/// Longest detail text kept (longer ones are truncated)
+pub const MAX_DETAIL_LEN: usize = 60;
+
+/// Part of the gateway an error came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSource {
+    /// Routing between the ports (NPDU/BVLC handling, transactions)
+    Routing,
+    /// MS/TP driver and RS-485 line
+    Mstp,
+    /// B/IP socket
+    Ip,
+    /// Web portal and API
+    Web,
+}
+
+impl ErrorSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorSource::Routing => "routing",
+            ErrorSource::Mstp => "mstp",
+            ErrorSource::Ip => "ip",
+            ErrorSource::Web => "web",
+        }
+    }
+}
+
+/// What went wrong, shared by every error type in the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidFrame = 0,
+    InvalidAddress = 1,
+    NetworkUnreachable = 2,
+    HopCountExhausted = 3,
+    /// Malformed NPDU or BVLC content
+    Encoding = 4,
+    Io = 5,
+    Crc = 6,
+    Timeout = 7,
+    QueueFull = 8,
+    FrameTooLong = 9,
+    BusyMedium = 10,
+    /// Bad or missing credentials, or a role without access
+    Unauthorized = 11,
+    /// A request or upload that could not be used
+    BadRequest = 12,
+}
+
+impl ErrorKind {
+    /// Every kind, in code order (the Error_Counts array follows it)
+    pub const ALL: [ErrorKind; 13] = [
+        ErrorKind::InvalidFrame,
+        ErrorKind::InvalidAddress,
+        ErrorKind::NetworkUnreachable,
+        ErrorKind::HopCountExhausted,
+        ErrorKind::Encoding,
+        ErrorKind::Io,
+        ErrorKind::Crc,
+        ErrorKind::Timeout,
+        ErrorKind::QueueFull,
+        ErrorKind::FrameTooLong,
+        ErrorKind::BusyMedium,
+        ErrorKind::Unauthorized,
+        ErrorKind::BadRequest,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidFrame => "invalid-frame",
+            ErrorKind::InvalidAddress => "invalid-address",
+            ErrorKind::NetworkUnreachable => "network-unreachable",
+            ErrorKind::HopCountExhausted => "hop-count-exhausted",
+            ErrorKind::Encoding => "encoding",
+            ErrorKind::Io => "io",
+            ErrorKind::Crc => "crc",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::QueueFull => "queue-full",
+            ErrorKind::FrameTooLong => "frame-too-long",
+            ErrorKind::BusyMedium => "busy-medium",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::BadRequest => "bad-request",
+        }
+    }
+}
+
+/// An error type that maps onto the shared error kinds
+pub trait Classify: fmt::Display {
+    fn kind(&self) -> ErrorKind;
+}
+
+/// One recorded error
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorRecord {
+    pub at: Instant,
+    pub source: ErrorSource,
+    pub kind: ErrorKind,
+    pub detail: String,
+}
+
+/// Recent errors plus a running count per kind
+#[derive(Debug)]
+pub struct ErrorLog {
+    recent: VecDeque<ErrorRecord>,
+    counts: [u32; ErrorKind::ALL.len()],
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorLog {
+    pub const fn new() -> Self {
+        Self { recent: VecDeque::new(), counts: [0; ErrorKind::ALL.len()] }
+    }
+
+    /// Add an error, dropping the oldest once the buffer is full
+    pub fn push(&mut self, source: ErrorSource, kind: ErrorKind, detail: &str, now: Instant) {
+        if self.recent.len() >= MAX_RECENT_ERRORS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(ErrorRecord { at: now, source, kind, detail: truncate(detail) });
+        let count = &mut self.counts[kind as usize];
+        *count = count.saturating_add(1);
+    }
+
+    /// Recent errors, newest first
+    pub fn recent(&self) -> impl Iterator<Item = &ErrorRecord> {
+        self.recent.iter().rev()
+    }
+
+    /// Errors of `kind` since boot (or the last reset)
+    pub fn count(&self, kind: ErrorKind) -> u32 {
+        self.counts[kind as usize]
+    }
+
+    /// Errors of every kind since boot (or the last reset)
+    pub fn total(&self) -> u32 {
+        self.counts.iter().fold(0u32, |sum, &c| sum.saturating_add(c))
+    }
+
+    /// Forget recent errors and zero the counters
+    pub fn reset(&mut self) {
+        self.recent.clear();
+        self.counts = [0; ErrorKind::ALL.len()];
+    }
+}
+
+/// Cut a detail to `MAX_DETAIL_LEN` bytes on a character boundary
+fn truncate(detail: &str) -> String {
+    let mut end = detail.len().min(MAX_DETAIL_LEN);
+    while !detail.is_char_boundary(end) {
+        end -= 1;
+    }
+    detail[..end].to_string()
+}
+
+/// Process-wide log, so any task can record without threading state through
+static LOG: Mutex<ErrorLog> = Mutex::new(ErrorLog::new());
+
+/// Record an error now
+pub fn record(source: ErrorSource, kind: ErrorKind, detail: &str) {
+    if let Ok(mut log) = LOG.lock() {
+        log.push(source, kind, detail, Instant::now());
+    }
+}
+
+/// Record a classified error now, with its message as the detail
+pub fn record_error(source: ErrorSource, error: &impl Classify) {
+    record(source, error.kind(), &error.to_string());
+}
+
+/// Run `f` with the error log locked
+pub fn with_log<R>(f: impl FnOnce(&mut ErrorLog) -> R) -> Option<R> {
+    LOG.lock().ok().map(|mut log| f(&mut log))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestError;
+
+    impl fmt::Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "CRC error")
+        }
+    }
+
+    impl Classify for TestError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Crc
+        }
+    }
+
+    #[test]
+    fn test_keeps_newest_errors_and_counts_all() {
+        let mut log = ErrorLog::new();
+        let now = Instant::now();
+        for i in 0..MAX_RECENT_ERRORS + 4 {
+            log.push(ErrorSource::Routing, ErrorKind::Encoding, &format!("bad NPDU {}", i), now);
+        }
+        log.push(ErrorSource::Mstp, ErrorKind::Crc, &"x".repeat(100), now);
+
+        assert_eq!(log.recent().count(), MAX_RECENT_ERRORS);
+        let newest = log.recent().next().unwrap();
+        assert_eq!(newest.kind, ErrorKind::Crc);
+        assert_eq!(newest.detail.len(), MAX_DETAIL_LEN);
+        assert_eq!(log.recent().last().unwrap().detail, "bad NPDU 5");
+        assert_eq!(log.count(ErrorKind::Encoding), (MAX_RECENT_ERRORS + 4) as u32);
+        assert_eq!(log.total(), (MAX_RECENT_ERRORS + 5) as u32);
+
+        log.reset();
+        assert_eq!(log.total(), 0);
+        assert_eq!(log.recent().count(), 0);
+    }
+
+    #[test]
+    fn test_kind_codes_follow_all_order() {
+        for (code, kind) in ErrorKind::ALL.iter().enumerate() {
+            assert_eq!(*kind as usize, code);
+        }
+        assert_eq!(TestError.kind().as_str(), "crc");
+        assert_eq!(ErrorSource::Web.as_str(), "web");
+    }
+}
@@ -0,0 +1,338 @@
+//! Structured event log
+//!
+//! Discrete events (WiFi up/down, AP mode toggles, token lost/recovered,
+//! configuration saved, peers going unreachable) are kept in a RAM ring
+//! buffer alongside the plain text log, each with a category, severity and
+//! timestamp, so the portal can show a timeline of what happened when.
+//! Optionally, warnings and errors spill over to NVS so the last few survive
+//! a reboot; those are shown with the UTC time they were stored with.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::blackbox;
+
+/// Events kept in RAM; the oldest are dropped first
+pub const MAX_EVENTS: usize = 128;
+
+/// Warnings and errors written to NVS when spillover is enabled
+pub const MAX_SPILLED_EVENTS: usize = 32;
+
+/// Longest message kept (longer ones are truncated)
+pub const MAX_MESSAGE_LEN: usize = 80;
+
+/// Spilled record header: severity, category, UTC seconds, message length
+const SPILL_HEADER_LEN: usize = 1 + 1 + 8 + 1;
+
+/// Largest spill blob
+pub const MAX_SPILL_LEN: usize = MAX_SPILLED_EVENTS * (SPILL_HEADER_LEN + MAX_MESSAGE_LEN);
+
+/// Minimum time between NVS spills (kept slow to spare the flash)
+pub const SPILL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How bad an event is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info = 0,
+    Warning = 1,
+    Error = 2,
+}
+
+impl Severity {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Severity::Warning,
+            2 => Severity::Error,
+            _ => Severity::Info,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [Severity::Info, Severity::Warning, Severity::Error]
+            .into_iter()
+            .find(|sev| sev.as_str() == s)
+    }
+}
+
+/// What part of the gateway an event is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventCategory {
+    /// Boot and shutdown
+    System = 0,
+    /// Station link up/down
+    Wifi = 1,
+    /// Configuration hotspot on/off
+    AccessPoint = 2,
+    /// MS/TP token ring lost/recovered
+    Token = 3,
+    /// Configuration saved or restored
+    Config = 4,
+    /// BDT peer, gateway or remote network unreachable
+    Peer = 5,
+    /// B/IP socket re-bound, local address changed
+    Ip = 6,
+    /// Remote console logins, changes and logouts
+    Console = 7,
+}
+
+impl EventCategory {
+    pub const ALL: [EventCategory; 8] = [
+        EventCategory::System,
+        EventCategory::Wifi,
+        EventCategory::AccessPoint,
+        EventCategory::Token,
+        EventCategory::Config,
+        EventCategory::Peer,
+        EventCategory::Ip,
+        EventCategory::Console,
+    ];
+
+    pub fn from_u8(value: u8) -> Self {
+        Self::ALL.get(usize::from(value)).copied().unwrap_or(EventCategory::System)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventCategory::System => "system",
+            EventCategory::Wifi => "wifi",
+            EventCategory::AccessPoint => "ap",
+            EventCategory::Token => "token",
+            EventCategory::Config => "config",
+            EventCategory::Peer => "peer",
+            EventCategory::Ip => "ip",
+            EventCategory::Console => "console",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == s)
+    }
+}
+
+/// One recorded event
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Increasing number for polling (`since=`), restarts at 1 every boot
+    pub seq: u32,
+    /// When it happened this boot; None for events restored from NVS
+    pub at: Option<Instant>,
+    /// UTC seconds stored with a restored event (None if the clock was unset)
+    pub stored_utc_secs: Option<u64>,
+    pub category: EventCategory,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Ring buffer of recent events
+#[derive(Debug)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+    next_seq: u32,
+    /// A warning or error arrived since the last spill
+    spill_pending: bool,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventLog {
+    pub const fn new() -> Self {
+        Self { events: VecDeque::new(), next_seq: 1, spill_pending: false }
+    }
+
+    /// Add an event, dropping the oldest once the buffer is full
+    pub fn push(&mut self, category: EventCategory, severity: Severity, message: &str, now: Instant) {
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event {
+            seq: self.next_seq,
+            at: Some(now),
+            stored_utc_secs: None,
+            category,
+            severity,
+            message: truncate(message),
+        });
+        self.next_seq = self.next_seq.wrapping_add(1).max(1);
+        if severity >= Severity::Warning {
+            self.spill_pending = true;
+        }
+    }
+
+    /// Events newer than `since`, oldest first, optionally filtered
+    pub fn query(&self, since: u32, category: Option<EventCategory>, min_severity: Severity) -> Vec<Event> {
+        self.events
+            .iter()
+            .filter(|e| e.seq > since)
+            .filter(|e| category.is_none_or(|c| c == e.category))
+            .filter(|e| e.severity >= min_severity)
+            .cloned()
+            .collect()
+    }
+
+    /// Clear the spill flag, returning whether a spill is due
+    pub fn take_spill_pending(&mut self) -> bool {
+        std::mem::take(&mut self.spill_pending)
+    }
+
+    /// Serialize the latest warnings and errors for NVS. Each record is
+    /// severity, category, UTC seconds (0 = unknown, big-endian), message
+    /// length and the message bytes. `utc_of` converts this boot's instants.
+    pub fn encode_spill(&self, utc_of: impl Fn(Instant) -> Option<u64>) -> Vec<u8> {
+        let spilled: Vec<&Event> = self.events.iter().filter(|e| e.severity >= Severity::Warning).collect();
+        let start = spilled.len().saturating_sub(MAX_SPILLED_EVENTS);
+        let mut buf = Vec::new();
+        for event in &spilled[start..] {
+            let utc = event.at.and_then(&utc_of).or(event.stored_utc_secs).unwrap_or(0);
+            buf.push(event.severity as u8);
+            buf.push(event.category as u8);
+            buf.extend_from_slice(&utc.to_be_bytes());
+            buf.push(event.message.len() as u8);
+            buf.extend_from_slice(event.message.as_bytes());
+        }
+        buf
+    }
+
+    /// Put events spilled by a previous boot in front of this boot's.
+    /// A truncated record ends the restore.
+    pub fn restore(&mut self, data: &[u8]) {
+        let mut restored = Vec::new();
+        let mut rest = data;
+        while rest.len() >= SPILL_HEADER_LEN {
+            let len = usize::from(rest[SPILL_HEADER_LEN - 1]);
+            let Some(message) = rest.get(SPILL_HEADER_LEN..SPILL_HEADER_LEN + len) else { break };
+            let utc = u64::from_be_bytes(rest[2..10].try_into().unwrap_or_default());
+            restored.push(Event {
+                seq: 0,
+                at: None,
+                stored_utc_secs: (utc != 0).then_some(utc),
+                category: EventCategory::from_u8(rest[1]),
+                severity: Severity::from_u8(rest[0]),
+                message: String::from_utf8_lossy(message).into_owned(),
+            });
+            rest = &rest[SPILL_HEADER_LEN + len..];
+        }
+        let room = MAX_EVENTS.saturating_sub(self.events.len());
+        let skip = restored.len().saturating_sub(room);
+        for event in restored.into_iter().skip(skip).rev() {
+            self.events.push_front(event);
+        }
+        // Restored events sort before everything recorded this boot
+        for (seq, event) in self.events.iter_mut().enumerate() {
+            event.seq = seq as u32 + 1;
+        }
+        self.next_seq = self.events.len() as u32 + 1;
+    }
+}
+
+/// Cut a message to `MAX_MESSAGE_LEN` bytes on a character boundary
+fn truncate(message: &str) -> String {
+    let mut end = message.len().min(MAX_MESSAGE_LEN);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message[..end].to_string()
+}
+
+/// Process-wide log, so any task can record without threading state through
+static LOG: Mutex<EventLog> = Mutex::new(EventLog::new());
+
+/// Record an event now
+pub fn record(category: EventCategory, severity: Severity, message: &str) {
+    if let Ok(mut log) = LOG.lock() {
+        log.push(category, severity, message, Instant::now());
+    }
+    blackbox::record_event(category, severity, message);
+}
+
+/// Run `f` with the event log locked
+pub fn with_log<R>(f: impl FnOnce(&mut EventLog) -> R) -> Option<R> {
+    LOG.lock().ok().map(|mut log| f(&mut log))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_keeps_newest_events() {
+        let mut log = EventLog::new();
+        let now = Instant::now();
+        for i in 0..MAX_EVENTS + 10 {
+            log.push(EventCategory::Wifi, Severity::Info, &format!("event {}", i), now);
+        }
+        let all = log.query(0, None, Severity::Info);
+        assert_eq!(all.len(), MAX_EVENTS);
+        assert_eq!(all[0].message, "event 10");
+        assert_eq!(all.last().unwrap().seq, (MAX_EVENTS + 10) as u32);
+
+        // Polling only returns what is new
+        let since = all.last().unwrap().seq;
+        log.push(EventCategory::Token, Severity::Warning, "token lost", now);
+        let new = log.query(since, None, Severity::Info);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].message, "token lost");
+    }
+
+    #[test]
+    fn test_query_filters_category_and_severity() {
+        let mut log = EventLog::new();
+        let now = Instant::now();
+        log.push(EventCategory::Wifi, Severity::Info, "wifi up", now);
+        log.push(EventCategory::Wifi, Severity::Warning, "wifi down", now);
+        log.push(EventCategory::Peer, Severity::Warning, "peer unreachable", now);
+        log.push(EventCategory::Config, Severity::Info, "config saved", now);
+
+        assert_eq!(log.query(0, Some(EventCategory::Wifi), Severity::Info).len(), 2);
+        let warnings = log.query(0, None, Severity::Warning);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.iter().all(|e| e.severity == Severity::Warning));
+        assert!(log.query(0, None, Severity::Error).is_empty());
+        assert_eq!(EventCategory::parse("ap"), Some(EventCategory::AccessPoint));
+        assert_eq!(Severity::parse("error"), Some(Severity::Error));
+    }
+
+    #[test]
+    fn test_spill_round_trip_keeps_warnings_only() {
+        let mut log = EventLog::new();
+        let now = Instant::now();
+        log.push(EventCategory::Wifi, Severity::Info, "wifi up", now);
+        assert!(!log.take_spill_pending());
+        log.push(EventCategory::Token, Severity::Warning, "token lost", now);
+        log.push(EventCategory::Peer, Severity::Error, &"x".repeat(200), now);
+        assert!(log.take_spill_pending());
+        assert!(!log.take_spill_pending());
+
+        let data = log.encode_spill(|_| Some(1_700_000_000));
+
+        let mut next_boot = EventLog::new();
+        next_boot.push(EventCategory::System, Severity::Info, "gateway started", now);
+        next_boot.restore(&data);
+        let events = next_boot.query(0, None, Severity::Info);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].message, "token lost");
+        assert_eq!(events[0].at, None);
+        assert_eq!(events[0].stored_utc_secs, Some(1_700_000_000));
+        assert_eq!(events[1].severity, Severity::Error);
+        assert_eq!(events[1].message.len(), MAX_MESSAGE_LEN);
+        assert_eq!(events[2].message, "gateway started");
+        assert_eq!(events.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        // A truncated blob restores the complete records only
+        let mut partial = EventLog::new();
+        partial.restore(&data[..data.len() - 5]);
+        assert_eq!(partial.query(0, None, Severity::Info).len(), 1);
+    }
+}
@@ -0,0 +1,321 @@
+//! Primary/standby redundancy pair
+//!
+//! Two units on the same trunk and subnet share one configuration: station
+//! address, network numbers and device instance. One is configured primary,
+//! the other standby. The passive unit listens on MS/TP without transmitting
+//! and ignores B/IP; the pair exchange a heartbeat datagram every second on
+//! `HEARTBEAT_PORT`. A passive unit takes over when it has heard neither a
+//! heartbeat nor a frame from the shared station address for `PEER_TIMEOUT`:
+//! it joins the token ring at that address, announces the networks and routes.
+//! Needing both keeps a broken IP link from putting the address on the trunk
+//! twice.
+//!
+//! A primary coming back stays passive while the standby is active. The
+//! standby hands back once the primary has been heard steadily for
+//! `FALLBACK_HOLD`: it goes quiet first and says so in its heartbeat, and the
+//! primary takes over on that heartbeat.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// UDP port the pair exchange heartbeats on
+pub const HEARTBEAT_PORT: u16 = 47820;
+
+/// How often each unit sends its heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Heartbeat and MS/TP silence after which the passive unit takes over
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a returning primary must be heard before the standby hands back
+pub const FALLBACK_HOLD: Duration = Duration::from_secs(30);
+
+/// Heartbeat datagram: magic, version, role, active flag, sequence number
+const HEARTBEAT_MAGIC: &[u8; 4] = b"BMFO";
+const HEARTBEAT_VERSION: u8 = 1;
+const HEARTBEAT_LEN: usize = 11;
+
+/// Set while this unit stands by, for the B/IP receive task
+static STANDING_BY: AtomicBool = AtomicBool::new(false);
+
+/// Whether this unit is the passive half of a redundancy pair
+pub fn is_standing_by() -> bool {
+    STANDING_BY.load(Ordering::Relaxed)
+}
+
+/// Record whether this unit stands by
+pub fn set_standing_by(standing_by: bool) {
+    STANDING_BY.store(standing_by, Ordering::Relaxed);
+}
+
+/// Part a unit plays in a redundancy pair
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailoverRole {
+    /// Single unit, always active
+    #[default]
+    Off = 0,
+    Primary = 1,
+    Standby = 2,
+}
+
+impl FailoverRole {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => FailoverRole::Primary,
+            2 => FailoverRole::Standby,
+            _ => FailoverRole::Off,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(FailoverRole::Off),
+            "primary" => Some(FailoverRole::Primary),
+            "standby" => Some(FailoverRole::Standby),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailoverRole::Off => "off",
+            FailoverRole::Primary => "primary",
+            FailoverRole::Standby => "standby",
+        }
+    }
+}
+
+/// What one unit tells the other every `HEARTBEAT_INTERVAL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub role: FailoverRole,
+    pub active: bool,
+    pub sequence: u32,
+}
+
+impl Heartbeat {
+    pub fn encode(&self) -> [u8; HEARTBEAT_LEN] {
+        let mut out = [0u8; HEARTBEAT_LEN];
+        out[..4].copy_from_slice(HEARTBEAT_MAGIC);
+        out[4] = HEARTBEAT_VERSION;
+        out[5] = self.role as u8;
+        out[6] = self.active as u8;
+        out[7..].copy_from_slice(&self.sequence.to_be_bytes());
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != HEARTBEAT_LEN || &data[..4] != HEARTBEAT_MAGIC || data[4] != HEARTBEAT_VERSION {
+            return None;
+        }
+        Some(Self {
+            role: FailoverRole::from_u8(data[5]),
+            active: data[6] != 0,
+            sequence: u32::from_be_bytes([data[7], data[8], data[9], data[10]]),
+        })
+    }
+}
+
+/// Change this unit has to make
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// Join the ring at the shared address, announce and route
+    TakeOver,
+    /// Go quiet and leave the trunk to the other unit
+    StandDown,
+}
+
+/// Redundancy state for the portal
+#[derive(Debug, Clone)]
+pub struct FailoverStatus {
+    pub role: FailoverRole,
+    pub active: bool,
+    /// Seconds since the peer's last heartbeat, None if never heard
+    pub peer_heard_secs: Option<u64>,
+    pub peer_active: bool,
+    pub takeovers: u32,
+    /// Seconds since this unit last took over or stood down, None if it hasn't
+    pub changed_secs: Option<u64>,
+}
+
+/// One unit's half of the pair
+pub struct Failover {
+    role: FailoverRole,
+    active: bool,
+    started: Instant,
+    peer_heard: Option<Instant>,
+    peer_active: bool,
+    // Start of the current unbroken run of peer heartbeats
+    peer_steady_since: Option<Instant>,
+    station_heard: Option<Instant>,
+    takeovers: u32,
+    changed: Option<Instant>,
+    sequence: u32,
+}
+
+impl Failover {
+    /// Paired units start passive until they know what the other is doing
+    pub fn new(role: FailoverRole, now: Instant) -> Self {
+        Self {
+            role,
+            active: role == FailoverRole::Off,
+            started: now,
+            peer_heard: None,
+            peer_active: false,
+            peer_steady_since: None,
+            station_heard: None,
+            takeovers: 0,
+            changed: None,
+            sequence: 0,
+        }
+    }
+
+    pub fn role(&self) -> FailoverRole {
+        self.role
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Note a heartbeat from the other unit
+    pub fn on_heartbeat(&mut self, heartbeat: Heartbeat, now: Instant) {
+        if !self.peer_alive(now) {
+            self.peer_steady_since = Some(now);
+        }
+        self.peer_heard = Some(now);
+        self.peer_active = heartbeat.active;
+    }
+
+    /// Note a frame from the shared station address heard while passive
+    pub fn on_station_heard(&mut self, at: Instant) {
+        self.station_heard = Some(at);
+    }
+
+    /// This unit's next heartbeat
+    pub fn heartbeat(&mut self) -> Heartbeat {
+        self.sequence = self.sequence.wrapping_add(1);
+        Heartbeat { role: self.role, active: self.active, sequence: self.sequence }
+    }
+
+    fn peer_alive(&self, now: Instant) -> bool {
+        self.peer_heard.is_some_and(|t| now.duration_since(t) < PEER_TIMEOUT)
+    }
+
+    /// Nobody on the trunk or the LAN is using the shared address
+    fn peer_gone(&self, now: Instant) -> bool {
+        now.duration_since(self.started) >= PEER_TIMEOUT
+            && !self.peer_alive(now)
+            && self.station_heard.map_or(true, |t| now.duration_since(t) >= PEER_TIMEOUT)
+    }
+
+    /// Decide whether to take over or stand down, and record the change
+    pub fn poll(&mut self, now: Instant) -> Option<Transition> {
+        let transition = match (self.role, self.active) {
+            (FailoverRole::Off, _) | (FailoverRole::Primary, true) => None,
+            // The standby handing back says so; otherwise wait for it to be gone
+            (FailoverRole::Primary, false) => {
+                let handed_back = self.peer_alive(now) && !self.peer_active;
+                (handed_back || self.peer_gone(now)).then_some(Transition::TakeOver)
+            }
+            (FailoverRole::Standby, false) => self.peer_gone(now).then_some(Transition::TakeOver),
+            // Both active (the primary came back without hearing us): the primary keeps it
+            (FailoverRole::Standby, true) => {
+                let steady = self.peer_steady_since.is_some_and(|t| now.duration_since(t) >= FALLBACK_HOLD);
+                (self.peer_alive(now) && (self.peer_active || steady)).then_some(Transition::StandDown)
+            }
+        }?;
+
+        self.active = transition == Transition::TakeOver;
+        self.station_heard = None;
+        self.changed = Some(now);
+        if self.active {
+            self.takeovers += 1;
+        }
+        Some(transition)
+    }
+
+    pub fn status(&self, now: Instant) -> FailoverStatus {
+        FailoverStatus {
+            role: self.role,
+            active: self.active,
+            peer_heard_secs: self.peer_heard.map(|t| now.duration_since(t).as_secs()),
+            peer_active: self.peer_active,
+            takeovers: self.takeovers,
+            changed_secs: self.changed.map(|t| now.duration_since(t).as_secs()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(role: FailoverRole, active: bool) -> Heartbeat {
+        Heartbeat { role, active, sequence: 1 }
+    }
+
+    #[test]
+    fn test_heartbeat_round_trip() {
+        let hb = Heartbeat { role: FailoverRole::Standby, active: true, sequence: 0x01020304 };
+        assert_eq!(Heartbeat::decode(&hb.encode()), Some(hb));
+        assert_eq!(Heartbeat::decode(b"BMFO"), None);
+        assert_eq!(FailoverRole::parse(FailoverRole::Primary.as_str()), Some(FailoverRole::Primary));
+    }
+
+    #[test]
+    fn test_standby_takes_over_only_when_heartbeats_and_station_are_silent() {
+        let t0 = Instant::now();
+        let mut standby = Failover::new(FailoverRole::Standby, t0);
+        assert!(!standby.is_active());
+
+        // Heartbeats stop but the primary is still on the trunk: stay passive
+        standby.on_heartbeat(heartbeat(FailoverRole::Primary, true), t0);
+        standby.on_station_heard(t0 + Duration::from_secs(4));
+        assert_eq!(standby.poll(t0 + Duration::from_secs(6)), None);
+
+        // Both silent for the timeout
+        assert_eq!(standby.poll(t0 + Duration::from_secs(9)), Some(Transition::TakeOver));
+        assert!(standby.is_active());
+        assert_eq!(standby.status(t0 + Duration::from_secs(9)).takeovers, 1);
+    }
+
+    #[test]
+    fn test_returning_primary_gets_the_trunk_back_after_the_hold() {
+        let t0 = Instant::now();
+        let mut standby = Failover::new(FailoverRole::Standby, t0);
+        assert_eq!(standby.poll(t0 + PEER_TIMEOUT), Some(Transition::TakeOver));
+
+        // The primary boots and hears the active standby: it stays passive
+        let t1 = t0 + Duration::from_secs(60);
+        let mut primary = Failover::new(FailoverRole::Primary, t1);
+        let mut now = t1;
+        while now < t1 + FALLBACK_HOLD {
+            primary.on_heartbeat(standby.heartbeat(), now);
+            standby.on_heartbeat(primary.heartbeat(), now);
+            assert_eq!(primary.poll(now), None);
+            assert_eq!(standby.poll(now), None);
+            now += HEARTBEAT_INTERVAL;
+        }
+
+        // The standby stands down, and the primary takes over on its next heartbeat
+        standby.on_heartbeat(primary.heartbeat(), now);
+        assert_eq!(standby.poll(now), Some(Transition::StandDown));
+        primary.on_heartbeat(standby.heartbeat(), now);
+        assert_eq!(primary.poll(now), Some(Transition::TakeOver));
+        assert!(primary.is_active() && !standby.is_active());
+
+        // The standby stays passive while the primary is heard
+        standby.on_heartbeat(primary.heartbeat(), now + HEARTBEAT_INTERVAL);
+        assert_eq!(standby.poll(now + HEARTBEAT_INTERVAL), None);
+    }
+
+    #[test]
+    fn test_active_standby_yields_to_active_primary() {
+        let t0 = Instant::now();
+        let mut standby = Failover::new(FailoverRole::Standby, t0);
+        assert_eq!(standby.poll(t0 + PEER_TIMEOUT), Some(Transition::TakeOver));
+        standby.on_heartbeat(heartbeat(FailoverRole::Primary, true), t0 + PEER_TIMEOUT);
+        assert_eq!(standby.poll(t0 + PEER_TIMEOUT), Some(Transition::StandDown));
+    }
+}
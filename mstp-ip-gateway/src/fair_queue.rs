@@ -0,0 +1,127 @@
+//! Per-source fair queuing of received B/IP datagrams
+//!
+//! The IP receive task used to route datagrams strictly in arrival order, so a
+//! head-end polling flat out could fill the MS/TP token window while the other
+//! clients waited behind it. Datagrams waiting on the socket are now queued per
+//! source and routed round-robin, one per source at a time. The queues are
+//! small: a source that overfills its queue loses its newest datagrams, and the
+//! drops are counted against that peer.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+/// Datagrams held for one source before its newest are dropped
+pub const MAX_QUEUED_PER_PEER: usize = 8;
+
+/// Sources with datagrams queued at once; datagrams from further sources are dropped
+pub const MAX_QUEUED_PEERS: usize = 16;
+
+/// Round-robin queue of datagrams keyed by source address
+pub struct FairQueue {
+    /// Sources with queued datagrams, in service order
+    queues: VecDeque<(SocketAddr, VecDeque<Vec<u8>>)>,
+    /// Drops per source since the last `take_drops`
+    drops: HashMap<SocketAddr, u64>,
+}
+
+impl FairQueue {
+    pub fn new() -> Self {
+        Self { queues: VecDeque::new(), drops: HashMap::new() }
+    }
+
+    /// Queue a datagram behind the others from the same source.
+    /// Returns false (and counts a drop) if there is no room for it.
+    pub fn push(&mut self, source: SocketAddr, data: &[u8]) -> bool {
+        if let Some((_, queue)) = self.queues.iter_mut().find(|(addr, _)| *addr == source) {
+            if queue.len() >= MAX_QUEUED_PER_PEER {
+                *self.drops.entry(source).or_insert(0) += 1;
+                return false;
+            }
+            queue.push_back(data.to_vec());
+            return true;
+        }
+        if self.queues.len() >= MAX_QUEUED_PEERS {
+            *self.drops.entry(source).or_insert(0) += 1;
+            return false;
+        }
+        self.queues.push_back((source, VecDeque::from([data.to_vec()])));
+        true
+    }
+
+    /// Next datagram to route: the oldest from the source whose turn it is
+    pub fn pop(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+        let (source, mut queue) = self.queues.pop_front()?;
+        let data = queue.pop_front()?;
+        if !queue.is_empty() {
+            self.queues.push_back((source, queue));
+        }
+        Some((source, data))
+    }
+
+    /// Sources with datagrams queued, i.e. the length of one round
+    pub fn peer_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Drops per source since the last call
+    pub fn take_drops(&mut self) -> Vec<(SocketAddr, u64)> {
+        self.drops.drain().collect()
+    }
+}
+
+impl Default for FairQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn peer(last: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, last)), 47808)
+    }
+
+    #[test]
+    fn test_sources_served_round_robin() {
+        let mut queue = FairQueue::new();
+        for i in 0..4 {
+            queue.push(peer(10), &[i]);
+        }
+        queue.push(peer(20), &[100]);
+        queue.push(peer(30), &[200]);
+        queue.push(peer(20), &[101]);
+
+        let order: Vec<(SocketAddr, u8)> = std::iter::from_fn(|| queue.pop()).map(|(addr, data)| (addr, data[0])).collect();
+        assert_eq!(
+            order,
+            vec![(peer(10), 0), (peer(20), 100), (peer(30), 200), (peer(10), 1), (peer(20), 101), (peer(10), 2), (peer(10), 3)]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_overflow_dropped_and_counted_per_source() {
+        let mut queue = FairQueue::new();
+        for i in 0..MAX_QUEUED_PER_PEER + 3 {
+            queue.push(peer(10), &[i as u8]);
+        }
+        for i in 0..MAX_QUEUED_PEERS {
+            assert_eq!(queue.push(peer(100 + i as u8), &[0]), i < MAX_QUEUED_PEERS - 1);
+        }
+        assert_eq!(queue.peer_count(), MAX_QUEUED_PEERS);
+
+        let mut drops = queue.take_drops();
+        drops.sort();
+        assert_eq!(drops, vec![(peer(10), 3), (peer(100 + MAX_QUEUED_PEERS as u8 - 1), 1)]);
+        assert!(queue.take_drops().is_empty());
+        // The oldest datagrams were kept
+        assert_eq!(queue.pop(), Some((peer(10), vec![0])));
+    }
+}
@@ -0,0 +1,246 @@
+//! Frame-level fault injection for robustness testing
+//!
+//! Only built with the `fault-injection` feature. Lets a tester drop,
+//! duplicate, delay or (on MS/TP) CRC-corrupt a percentage of the frames
+//! the gateway transmits, to exercise a head-end's retry behavior and the
+//! gateway's own transaction timeouts without physical fault induction.
+
+use log::warn;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::datalink::{DataLink, DataLinkError, LinkAddress, ReceivedFrame};
+
+/// Longest delay that can be injected
+pub const MAX_DELAY_MS: u32 = 10_000;
+
+/// Frames held back by the delay fault at any one time (per link)
+pub const MAX_DELAYED_FRAMES: usize = 16;
+
+/// Fault rates, set via /api/debug/inject
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultSettings {
+    /// Percentage of frames silently dropped
+    pub drop_pct: u8,
+    /// Percentage of MS/TP data frames sent with a bad data CRC
+    pub corrupt_pct: u8,
+    /// Percentage of frames sent twice
+    pub duplicate_pct: u8,
+    /// Delay applied to every frame (0 = none)
+    pub delay_ms: u32,
+}
+
+impl FaultSettings {
+    pub fn is_active(&self) -> bool {
+        self.drop_pct > 0 || self.corrupt_pct > 0 || self.duplicate_pct > 0 || self.delay_ms > 0
+    }
+}
+
+/// Counts of injected faults
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaultStats {
+    pub dropped: u64,
+    pub corrupted: u64,
+    pub duplicated: u64,
+    pub delayed: u64,
+}
+
+/// What to do with one outgoing frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultAction {
+    pub drop: bool,
+    pub duplicate: bool,
+    pub delay: Option<Duration>,
+}
+
+/// Rolls the configured fault rates for each frame
+#[derive(Debug)]
+pub struct FaultInjector {
+    settings: FaultSettings,
+    stats: FaultStats,
+    rng: u32,
+}
+
+impl FaultInjector {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            settings: FaultSettings::default(),
+            stats: FaultStats::default(),
+            // xorshift state must be non-zero
+            rng: seed | 1,
+        }
+    }
+
+    pub fn settings(&self) -> FaultSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: FaultSettings) {
+        if settings != self.settings && settings.is_active() {
+            warn!(
+                "Fault injection active: drop={}% corrupt={}% duplicate={}% delay={}ms",
+                settings.drop_pct, settings.corrupt_pct, settings.duplicate_pct, settings.delay_ms
+            );
+        }
+        self.settings = settings;
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    /// Decide the drop/duplicate/delay faults for a frame about to be queued
+    pub fn decide(&mut self) -> FaultAction {
+        if !self.settings.is_active() {
+            return FaultAction::default();
+        }
+        let mut action = FaultAction::default();
+        if self.roll(self.settings.drop_pct) {
+            self.stats.dropped += 1;
+            action.drop = true;
+            return action;
+        }
+        if self.roll(self.settings.duplicate_pct) {
+            self.stats.duplicated += 1;
+            action.duplicate = true;
+        }
+        if self.settings.delay_ms > 0 {
+            self.stats.delayed += 1;
+            action.delay = Some(Duration::from_millis(self.settings.delay_ms as u64));
+        }
+        action
+    }
+
+    /// Decide whether a data frame about to be transmitted gets a bad CRC
+    pub fn corrupt_crc(&mut self) -> bool {
+        let corrupt = self.roll(self.settings.corrupt_pct);
+        if corrupt {
+            self.stats.corrupted += 1;
+        }
+        corrupt
+    }
+
+    /// True with probability pct/100
+    fn roll(&mut self, pct: u8) -> bool {
+        if pct == 0 {
+            return false;
+        }
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng % 100) < pct as u32
+    }
+}
+
+/// Data link wrapper that applies injected faults to outgoing frames.
+/// The injector is shared so settings can be changed while the link is
+/// owned by the gateway.
+pub struct FaultyLink {
+    inner: Box<dyn DataLink>,
+    faults: Arc<Mutex<FaultInjector>>,
+    delayed: VecDeque<(Instant, Vec<u8>, LinkAddress)>,
+}
+
+impl FaultyLink {
+    pub fn new(inner: Box<dyn DataLink>, faults: Arc<Mutex<FaultInjector>>) -> Self {
+        Self { inner, faults, delayed: VecDeque::new() }
+    }
+}
+
+impl DataLink for FaultyLink {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn send(&mut self, npdu: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        self.inner.send(npdu, dest)
+    }
+
+    fn send_raw(&mut self, frame: &[u8], dest: LinkAddress) -> Result<(), DataLinkError> {
+        self.flush();
+        let action = match self.faults.lock() {
+            Ok(mut faults) => faults.decide(),
+            Err(_) => FaultAction::default(),
+        };
+        if action.drop {
+            return Ok(());
+        }
+        let copies = if action.duplicate { 2 } else { 1 };
+        match action.delay {
+            Some(delay) => {
+                if self.delayed.len() + copies > MAX_DELAYED_FRAMES {
+                    return Err(DataLinkError::QueueFull);
+                }
+                for _ in 0..copies {
+                    self.delayed.push_back((Instant::now() + delay, frame.to_vec(), dest));
+                }
+                Ok(())
+            }
+            None => {
+                for _ in 0..copies {
+                    self.inner.send_raw(frame, dest)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+        while self.delayed.front().is_some_and(|(due, _, _)| *due <= now) {
+            if let Some((_, frame, dest)) = self.delayed.pop_front() {
+                if let Err(e) = self.inner.send_raw(&frame, dest) {
+                    warn!("Delayed frame to {} failed: {}", dest, e);
+                }
+            }
+        }
+        self.inner.flush();
+    }
+
+    fn poll(&mut self) -> Vec<ReceivedFrame> {
+        self.inner.poll()
+    }
+
+    fn is_up(&self) -> bool {
+        self.inner.is_up()
+    }
+
+    fn broadcast_addr(&self) -> LinkAddress {
+        self.inner.broadcast_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inactive_injector_passes_everything() {
+        let mut faults = FaultInjector::new(1234);
+        for _ in 0..100 {
+            assert_eq!(faults.decide(), FaultAction::default());
+            assert!(!faults.corrupt_crc());
+        }
+        assert_eq!(faults.stats().dropped, 0);
+    }
+
+    #[test]
+    fn test_fault_rates() {
+        let mut faults = FaultInjector::new(42);
+        faults.set_settings(FaultSettings { drop_pct: 100, ..Default::default() });
+        assert!((0..50).all(|_| faults.decide().drop));
+        assert_eq!(faults.stats().dropped, 50);
+
+        faults.set_settings(FaultSettings { duplicate_pct: 100, delay_ms: 250, ..Default::default() });
+        let action = faults.decide();
+        assert!(!action.drop && action.duplicate);
+        assert_eq!(action.delay, Some(Duration::from_millis(250)));
+
+        // Roughly half the frames at 50%
+        faults.set_settings(FaultSettings { corrupt_pct: 50, ..Default::default() });
+        let corrupted = (0..1000).filter(|_| faults.corrupt_crc()).count();
+        assert!((350..650).contains(&corrupted), "corrupted {} of 1000", corrupted);
+    }
+}
@@ -4,15 +4,39 @@
 //! following ASHRAE 135-2024 requirements for network layer routing.
 
 use log::{debug, info, trace, warn};
-use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::{Duration, Instant};
 
 use bacnet_rs::app::{Apdu, SegmentationManager};
 use bacnet_rs::service::{AbortReason, ConfirmedServiceChoice};
-use crate::config::{BdtEntryConfig, NetworkTablePersistence, RoutingTableEntryConfig};
+use crate::compat::{self, CompatQuirks, CompatRule};
+use crate::client::{ClientCall, ClientOutcome};
+use crate::analyzer::{Analyzer, SourceReport};
+use crate::config::{
+    format_network_list, format_supervisory_stations, AnnounceScope, BdtEntryConfig, BroadcastForm,
+    NetworkTablePersistence, RouteNextHop, RoutingTableEntryConfig, SlaPersistence, StaticRouteConfig,
+};
+use crate::datalink::{DataLink, DataLinkError, LinkAddress};
+use crate::errors::{self, Classify, ErrorKind, ErrorSource};
+use crate::events::{self, EventCategory, Severity};
+use crate::inventory::{InventoryDevice, InventoryJob, InventoryProgress};
+use crate::names::{NameCache, NameTarget};
+use crate::netnum::{self, HeardNetworks, LearnOutcome, NetworkNumber, NetworkNumberQuality, RouterMode, NL_NETWORK_NUMBER_IS, NL_WHAT_IS_NETWORK_NUMBER};
+use crate::npdu::{NetworkAddress, Npdu, NpduError, NpduQuirk};
+use crate::nvs_writer::{NvsWrite, NvsWriter};
+use crate::peers::{PeerStats, PeerTable};
+use crate::router_query::{RouterQuery, RouterQueryKind};
+use crate::rpm_proxy::{
+    ProxyStep, RpmProxy, MAX_RPM_PROXIES, REJECT_UNRECOGNIZED_SERVICE, RPM_PROXY_MAX_RETRIES,
+    RPM_PROXY_READ_TIMEOUT,
+};
+use crate::service_stats::{ErrorCount, ServiceCounters, ServiceStats};
+use crate::sla::{DeviceSla, SlaTable, SLA_CHECKPOINT_INTERVAL};
+use crate::talkers::{TalkerAddress, TalkerRanking, TalkerTable, TopTalker, TALKER_WINDOW};
 use crate::transaction::{PendingTransaction, TransactionTable, TransactionStats};
+use crate::wpm_proxy::{WpmProxy, WpmStep, MAX_WPM_PROXIES, WPM_PROXY_MAX_RETRIES, WPM_PROXY_WRITE_TIMEOUT};
 use esp_idf_svc::nvs::{EspNvsPartition, NvsDefault};
 
 /// BACnet/IP BVLC function codes (ASHRAE 135 Annex J)
@@ -28,11 +52,13 @@ const BVLC_DELETE_FDT_ENTRY: u8 = 0x08;
 const BVLC_DISTRIBUTE_BROADCAST: u8 = 0x09;
 const BVLC_ORIGINAL_UNICAST: u8 = 0x0A;
 const BVLC_ORIGINAL_BROADCAST: u8 = 0x0B;
+const BVLC_SECURE_BVLL: u8 = 0x0C;
 
 /// Network layer message types (ASHRAE 135 Clause 6)
 const NL_WHO_IS_ROUTER_TO_NETWORK: u8 = 0x00;
 const NL_I_AM_ROUTER_TO_NETWORK: u8 = 0x01;
 const NL_REJECT_MESSAGE_TO_NETWORK: u8 = 0x03;
+const NL_ROUTER_BUSY_TO_NETWORK: u8 = 0x04;
 const NL_INITIALIZE_ROUTING_TABLE: u8 = 0x06;
 const NL_INITIALIZE_ROUTING_TABLE_ACK: u8 = 0x07;
 
@@ -66,21 +92,217 @@ const BVLC_RESULT_REGISTER_FD_NAK: u16 = 0x0030;
 const BVLC_RESULT_READ_FDT_NAK: u16 = 0x0040;
 const BVLC_RESULT_DELETE_FDT_NAK: u16 = 0x0050;
 const BVLC_RESULT_DISTRIBUTE_NAK: u16 = 0x0060;
+/// Annex J assigns no result code to Secure-BVLL, so this is only sent when a
+/// site opts in to answering (the default is to drop them silently)
+const BVLC_RESULT_SECURE_BVLL_NAK: u16 = 0x0070;
+
+/// Distinct sources whose Secure-BVLL messages are counted individually
+pub const MAX_SECURE_BVLL_SOURCES: usize = 16;
+
+/// Distinct (source, quirk) pairs logged once in lenient NPDU mode; later
+/// sources are only counted
+const MAX_NPDU_QUIRK_SOURCES: usize = 64;
 
 /// Default address table entry age (1 hour)
 const DEFAULT_ADDRESS_AGE: Duration = Duration::from_secs(3600);
 
+/// Largest NPDU a classic MS/TP frame carries (Clause 9: 501-octet data field)
+pub const MSTP_MAX_NPDU: usize = 501;
+
+/// Smallest accepted MS/TP max NPDU setting
+pub const MIN_MSTP_MAX_NPDU: usize = 128;
+
+/// Largest NPDU carried over BACnet/IP (Annex J: 1497 octets)
+pub const IP_MAX_NPDU: usize = 1497;
+
 /// Default foreign device TTL (30 seconds per ASHRAE 135 Annex J)
 const DEFAULT_FD_TTL: Duration = Duration::from_secs(30);
 
 /// Minimum hop count for routing (ASHRAE 135)
 const MIN_HOP_COUNT: u8 = 1;
 
+/// How long after a foreign device's Distribute-Broadcast its discovery
+/// replies (I-Am, I-Have) are also unicast back to it
+const FD_REPLY_WINDOW: Duration = Duration::from_secs(10);
+
+/// Period between unsolicited router announcements on both ports
+const ROUTER_ANNOUNCE_PERIOD: Duration = Duration::from_secs(30);
+
+/// How long a duplicate network number stays flagged after the last
+/// conflicting I-Am-Router-To-Network was heard
+const DUPLICATE_NETWORK_HOLD: Duration = Duration::from_secs(300);
+
+/// How long an IP->MS/TP frame may wait for the MS/TP token before the
+/// client is told. Kept below the transaction timeout so a held request is
+/// never retried while it is still queued.
+const HELD_FRAME_TTL: Duration = Duration::from_secs(5);
+
+/// Maximum number of IP->MS/TP frames held while the token is lost
+const MAX_HELD_FRAMES: usize = 32;
+
+/// How long after forwarding toward a network a Reject-Message-To-Network
+/// for it is taken as a verdict on our route
+const RECENT_FORWARD_WINDOW: Duration = Duration::from_secs(30);
+
+/// How long a network reported unreachable is rejected locally before the
+/// route is tried again
+const UNREACHABLE_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Unconfirmed service choices that answer a broadcast discovery request
+const SERVICE_I_AM: u8 = 0;
+const SERVICE_I_HAVE: u8 = 1;
+
+/// Unconfirmed service choice for Who-Is
+const SERVICE_WHO_IS: u8 = 8;
+
+/// BACnet Device object type
+const OBJECT_TYPE_DEVICE: u32 = 8;
+
+/// Most device bindings kept; the one heard from longest ago is dropped first
+const MAX_DEVICE_BINDINGS: usize = 255;
+
+/// Another router on the IP side advertising our MS/TP network number
+#[derive(Debug, Clone)]
+pub struct NetworkConflict {
+    /// The duplicated network number
+    pub network: u16,
+    /// B/IP address of the conflicting router (latest seen)
+    pub router: SocketAddr,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    /// Number of conflicting announcements heard
+    pub count: u32,
+}
+
+/// Pending confirmed request as shown in diagnostics
+#[derive(Debug, Clone)]
+pub struct TransactionSummary {
+    pub invoke_id: u8,
+    pub service: ConfirmedServiceChoice,
+    /// IP client waiting for the response
+    pub client: SocketAddr,
+    pub dest_network: u16,
+    pub dest_mac: u8,
+    /// When the request (or its latest retry) was forwarded
+    pub sent_at: Instant,
+    pub timeout: Duration,
+    pub retries: u8,
+    pub max_retries: u8,
+    pub segmented: bool,
+}
+
+/// Segmented request from IP still being reassembled
+#[derive(Debug, Clone)]
+pub struct ReassemblySummary {
+    pub invoke_id: u8,
+    pub service_choice: u8,
+    pub client: SocketAddr,
+    /// When the first segment arrived
+    pub started_at: Instant,
+}
+
+/// Network reported unreachable by a Reject-Message-To-Network
+#[derive(Debug, Clone)]
+pub struct UnreachableNetwork {
+    pub network: u16,
+    /// Reject reason from the last Reject received (ASHRAE 135 Clause 6.4.4)
+    pub reason: u8,
+    /// Where the last Reject came from (e.g. "IP 192.168.1.1:47808", "MS/TP 12")
+    pub reported_by: String,
+    /// Routing toward the network resumes after this
+    pub until: Instant,
+    /// Number of Rejects received while the network was marked
+    pub count: u32,
+}
+
+/// Registered foreign device as shown on the FDT page
+#[derive(Debug, Clone)]
+pub struct ForeignDeviceSummary {
+    pub address: SocketAddr,
+    /// TTL supplied at registration, in seconds
+    pub ttl_seconds: u16,
+    /// Seconds left before the registration expires
+    pub remaining_seconds: u16,
+    /// Broadcasts forwarded to the device since it registered
+    pub forwarded: u64,
+}
+
+/// Learned address translation as shown on the bindings page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressBindingSummary {
+    pub mstp_address: u8,
+    pub ip_address: SocketAddr,
+    /// Seconds since the binding was last confirmed by traffic
+    pub age_seconds: u64,
+    /// Pinned bindings never age out and survive a flush
+    pub pinned: bool,
+}
+
+/// Entry in one of the address translation tables: an MS/TP address keys
+/// `mstp_to_ip`, a B/IP address keys `ip_to_mstp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKey {
+    Mstp(u8),
+    Ip(SocketAddr),
+}
+
+impl BindingKey {
+    /// Parse a table name ("mstp" or "ip") and the entry's address
+    pub fn parse(table: &str, key: &str) -> Option<Self> {
+        match table {
+            "mstp" => key.trim().parse().ok().map(BindingKey::Mstp),
+            "ip" => key.trim().parse().ok().map(BindingKey::Ip),
+            _ => None,
+        }
+    }
+}
+
+/// Change to the address translation tables requested from the portal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingEdit {
+    Pin(BindingKey, bool),
+    Remove(BindingKey),
+    /// Remove every binding that is not pinned, from both tables
+    Flush,
+}
+
+/// Supervisory station and how its copies of B/IP broadcasts fared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SupervisoryStation {
+    pub address: SocketAddr,
+    /// Copies handed to the IP link
+    pub sent: u64,
+    /// Copies the IP link refused (queue full or send error)
+    pub failed: u64,
+}
+
+/// Where a device was last heard announcing itself (I-Am)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceBinding {
+    pub device_instance: u32,
+    /// BACnet network number and MAC (1 byte on MS/TP, 6 on BACnet/IP)
+    pub network: u16,
+    pub mac: Vec<u8>,
+    /// Heard on the MS/TP port (false = heard on the IP port)
+    pub on_mstp: bool,
+}
+
+/// IP->MS/TP frame waiting for the MS/TP token to come back
+#[derive(Debug, Clone)]
+struct HeldFrame {
+    npdu: Vec<u8>,
+    dest_mac: u8,
+    expecting_reply: bool,
+    source_addr: SocketAddr,
+    queued_at: Instant,
+}
+
 /// Address table entry with timestamp for aging
 #[derive(Debug, Clone)]
 struct AddressEntry<T> {
     address: T,
     last_seen: Instant,
+    pinned: bool,
 }
 
 /// Foreign Device Table entry (ASHRAE 135 Annex J.5)
@@ -92,6 +314,8 @@ struct ForeignDeviceEntry {
     ttl_seconds: u16,
     /// Time when entry was registered/refreshed
     registered_at: Instant,
+    /// Broadcasts forwarded to the device since it registered
+    forwarded: u64,
 }
 
 /// Broadcast Distribution Table entry (ASHRAE 135 Annex J.3)
@@ -121,6 +345,7 @@ impl<T> AddressEntry<T> {
         Self {
             address,
             last_seen: Instant::now(),
+            pinned: false,
         }
     }
 
@@ -129,7 +354,7 @@ impl<T> AddressEntry<T> {
     }
 
     fn is_expired(&self, max_age: Duration) -> bool {
-        self.last_seen.elapsed() > max_age
+        !self.pinned && self.last_seen.elapsed() > max_age
     }
 }
 
@@ -139,6 +364,7 @@ impl ForeignDeviceEntry {
             address,
             ttl_seconds,
             registered_at: Instant::now(),
+            forwarded: 0,
         }
     }
 
@@ -201,6 +427,8 @@ pub struct BacnetGateway {
     // Network configuration
     mstp_network: u16,
     ip_network: u16,
+    // Configured, or learned from Network-Number-Is (a placeholder until then)
+    ip_network_quality: NetworkNumberQuality,
 
     // Local IP address for Forwarded-NPDU
     local_ip: Ipv4Addr,
@@ -209,47 +437,161 @@ pub struct BacnetGateway {
     // Subnet mask for directed broadcast calculation
     subnet_mask: Ipv4Addr,
 
+    // Soft AP address and mask while the AP runs alongside station mode (APSTA)
+    ap_interface: Option<(Ipv4Addr, Ipv4Addr)>,
+
+    // B/IP multicast group used instead of subnet broadcast (Annex J.7)
+    multicast_group: Option<Ipv4Addr>,
+
+    // Broadcast addressing when not using multicast, plus Forwarded-NPDU
+    // unicast copies to each supervisory station
+    broadcast_form: BroadcastForm,
+    supervisory_stations: Vec<SupervisoryStation>,
+
+    // Per-subnet compatibility profiles for B/IP clients with known quirks
+    compat_rules: Vec<CompatRule>,
+
+    // Largest NPDU routed onto MS/TP; longer messages are rejected with MessageTooLong
+    mstp_max_npdu: usize,
+
+    // MS/TP send queue backpressure: confirmed requests from IP get an Abort
+    // (buffer-overflow) from the high mark until the queue drains to the low mark
+    backpressure_high: usize,
+    backpressure_low: usize,
+    backpressure_active: bool,
+
+    // Secure-BVLL (Annex J security) messages: counted per source and dropped
+    // silently unless configured to answer with a NAK
+    secure_bvll_drop: bool,
+    secure_bvll_sources: HashMap<IpAddr, u32>,
+
+    // Write-protect jumper fitted: Write-BDT is NAKed and
+    // Initialize-Routing-Table updates are ignored
+    write_protected: bool,
+
+    // Passive half of a redundancy pair: nothing is routed, announced or sent
+    standby: bool,
+
+    // Protocol analysis mode: every routed APDU is checked against the
+    // encoding rules, with nonconformant frames counted per source
+    analyzer: Option<Analyzer>,
+
+    // Lenient NPDU parsing: known legacy header deviations are repaired and
+    // routed instead of rejected, each logged once per source
+    npdu_lenient: bool,
+    npdu_quirk_sources: HashSet<(String, NpduQuirk)>,
+
+    // Administrative port state; a disabled port neither sends nor receives routed traffic
+    mstp_port_enabled: bool,
+    ip_port_enabled: bool,
+
     // Address translation tables with aging
     mstp_to_ip: HashMap<u8, AddressEntry<SocketAddr>>,
     ip_to_mstp: HashMap<SocketAddr, AddressEntry<u8>>,
 
+    // Device instance -> address learned from I-Am on either port, aged like
+    // the address tables (Device_Address_Binding of the local device)
+    device_bindings: HashMap<u32, AddressEntry<DeviceBinding>>,
+
     // Foreign Device Table (ASHRAE 135 Annex J.5)
     // Key is IP address to prevent duplicates on re-registration
     foreign_device_table: HashMap<SocketAddr, ForeignDeviceEntry>,
+    // Registrations are NAKed while false; existing entries run out their TTL
+    accept_fd_registrations: bool,
 
     // Broadcast Distribution Table (ASHRAE 135 Annex J.3)
     // List of peer BBMDs for broadcast distribution across subnets
     broadcast_distribution_table: Vec<BdtEntry>,
 
+    // Foreign devices that recently sent a Distribute-Broadcast-To-Network
+    // Discovery replies from MS/TP are also sent back to them within FD_REPLY_WINDOW
+    fd_broadcast_origins: HashMap<SocketAddr, Instant>,
+
     // Routing table for Initialize-Routing-Table (ASHRAE 135 Clause 6.4)
     // Key is destination network number
     routing_table: HashMap<u16, RoutingTableEntry>,
 
+    // Static routes to distant networks (DNET -> next-hop router), configured
+    // via the web portal for sites without Initialize-Routing-Table support
+    static_routes: HashMap<u16, RouteNextHop>,
+
+    // Reject-Message-To-Network handling: when we last forwarded toward each
+    // remote DNET, and networks backed off after a Reject
+    recent_forwards: HashMap<u16, Instant>,
+    unreachable_networks: HashMap<u16, UnreachableNetwork>,
+
     // Address aging configuration
     address_max_age: Duration,
 
     // Pending transmissions for IP side
     ip_send_queue: Vec<(Vec<u8>, SocketAddr)>,
 
-    // Pending transmissions for MS/TP side (used for retries)
+    // Pending transmissions for MS/TP side until its link is set
     // Each entry: (npdu_data, dest_mac)
     mstp_send_queue: Vec<(Vec<u8>, u8)>,
 
+    // Store-and-forward queue for IP->MS/TP frames while the token is lost
+    held_frames: std::collections::VecDeque<HeldFrame>,
+
     // Statistics
     stats: GatewayStats,
 
-    // NVS partition for BDT and routing table persistence
-    nvs_partition: Option<EspNvsPartition<NvsDefault>>,
+    // Packets/bytes per routed source and destination address (rolling window)
+    talkers: TalkerTable,
+
+    // Per-endpoint counters for the B/IP peers the gateway hears from
+    peers: PeerTable,
+
+    // Response statistics per MS/TP device, checkpointed to NVS
+    sla: SlaTable,
+    sla_saved_at: Instant,
 
-    // UDP socket for sending (shared with receive thread via Arc)
-    ip_socket: Option<Arc<UdpSocket>>,
+    // Requests and answers per confirmed service, with the error reason histogram
+    service_stats: ServiceStats,
 
-    // Router announcement sent flag
-    router_announced: bool,
+    // Background writer for BDT and routing table persistence
+    nvs_writer: Option<NvsWriter>,
+
+    // BACnet/IP port for sending (its socket is shared with the receive thread)
+    ip_link: Option<Box<dyn DataLink>>,
+
+    // MS/TP port for sending (its driver is shared with the MS/TP receive thread)
+    mstp_link: Option<Box<dyn DataLink>>,
+
+    // Router announcement scheduling
+    last_router_announce: Option<Instant>,
+    router_announce_requested: bool,
+    announce_scope: AnnounceScope,
+    // Further networks announced when a static route leads to them
+    announce_networks: Vec<u16>,
 
     // Transaction tracking for confirmed services
     transactions: TransactionTable,
 
+    // MS/TP devices that rejected ReadPropertyMultiple; their RPMs are answered
+    // with ReadProperty calls made by the gateway
+    rpm_unsupported: HashSet<u8>,
+    rpm_proxies: Vec<RpmProxy>,
+    // Likewise for WritePropertyMultiple, executed as WriteProperty calls
+    wpm_unsupported: HashSet<u8>,
+    wpm_proxies: Vec<WpmProxy>,
+    next_proxy_invoke_id: u8,
+
+    // Site inventory walking MS/TP object lists (shares the proxy invoke IDs)
+    inventory: Option<InventoryJob>,
+    // ReadProperty/WriteProperty made from the serial console (shares the proxy invoke IDs)
+    client_call: Option<ClientCall>,
+    // Object_Name of bound devices, shown in the portal (shares the proxy invoke IDs)
+    device_names: NameCache,
+
+    // Diagnostic routing table query to a remote router, kept after it finishes
+    router_query: Option<RouterQuery>,
+    // Test broadcast checking a freshly paired peer BBMD, kept after it finishes
+    pair_probe: Option<RouterQuery>,
+    // New network numbers checked before a staged configuration change
+    // commits, with the routers that answered for them (router, networks)
+    network_probe: Option<(Vec<u16>, Vec<(String, Vec<u16>)>)>,
+
     // Segmentation manager for reassembling large messages
     segmentation: SegmentationManager,
 
@@ -260,6 +602,17 @@ pub struct BacnetGateway {
     // Segment transmission tracking for retransmission
     // Key is (invoke_id, sequence_number)
     segment_transmissions: HashMap<(u8, u8), SegmentTransmission>,
+
+    // Duplicate MS/TP network number detection
+    network_conflict: Option<NetworkConflict>,
+    network_conflict_raised: bool,
+    suppress_on_conflict: bool,
+
+    // Network numbers other routers announce on IP, for commissioning suggestions
+    heard_networks: HeardNetworks,
+
+    // Keep ranged Who-Is off the token ring when every device in range is on IP
+    whois_filter: bool,
 }
 
 /// Gateway statistics
@@ -272,6 +625,40 @@ pub struct GatewayStats {
     pub routing_errors: u64,
     pub transaction_timeouts: u64,
 
+    // Store-and-forward queue counters (IP->MS/TP while the token is lost)
+    pub held_frames: u64,
+    pub held_released: u64,
+    pub held_expired: u64,
+    pub held_overflows: u64,
+    pub held_high_water: usize,
+
+    // Reject-Message-To-Network received from other routers
+    pub rejects_received: u64,
+
+    // Messages rejected as too long for the destination port's max NPDU
+    pub messages_too_long: u64,
+
+    // Messages dropped or rejected because a port is administratively disabled
+    pub port_disabled_drops: u64,
+
+    // RPM requests answered with ReadProperty calls for devices without RPM
+    pub rpm_proxied: u64,
+
+    // WPM requests executed as WriteProperty calls for devices without WPM
+    pub wpm_proxied: u64,
+
+    // Ranged Who-Is from IP not forwarded because the range is all on IP
+    pub whois_suppressed: u64,
+
+    // Confirmed requests from IP aborted because the MS/TP send queue was backed up
+    pub backpressure_aborts: u64,
+
+    // Secure-BVLL messages received (the gateway has no B/IP security support)
+    pub secure_bvll_received: u64,
+
+    // NPDUs repaired in lenient mode, indexed by NpduQuirk
+    pub npdu_quirks: [u64; NpduQuirk::ALL.len()],
+
     // Byte counters
     pub mstp_to_ip_bytes: u64,
     pub ip_to_mstp_bytes: u64,
@@ -305,25 +692,76 @@ impl BacnetGateway {
         Self {
             mstp_network,
             ip_network,
+            ip_network_quality: NetworkNumberQuality::Configured,
             local_ip,
             local_port,
             subnet_mask,
+            ap_interface: None,
+            multicast_group: None,
+            broadcast_form: BroadcastForm::Directed,
+            supervisory_stations: Vec::new(),
+            compat_rules: Vec::new(),
+            mstp_max_npdu: MSTP_MAX_NPDU,
+            backpressure_high: 0,
+            backpressure_low: 0,
+            backpressure_active: false,
+            secure_bvll_drop: true,
+            write_protected: false,
+            standby: false,
+            analyzer: None,
+            secure_bvll_sources: HashMap::new(),
+            npdu_lenient: false,
+            npdu_quirk_sources: HashSet::new(),
+            mstp_port_enabled: true,
+            ip_port_enabled: true,
             mstp_to_ip: HashMap::new(),
             ip_to_mstp: HashMap::new(),
+            device_bindings: HashMap::new(),
             foreign_device_table: HashMap::new(),
+            accept_fd_registrations: true,
             broadcast_distribution_table: Vec::new(),
+            fd_broadcast_origins: HashMap::new(),
             routing_table: HashMap::new(),
+            static_routes: HashMap::new(),
+            recent_forwards: HashMap::new(),
+            unreachable_networks: HashMap::new(),
             address_max_age: DEFAULT_ADDRESS_AGE,
             ip_send_queue: Vec::new(),
             mstp_send_queue: Vec::new(),
+            held_frames: std::collections::VecDeque::new(),
             stats: GatewayStats::default(),
-            nvs_partition: None,
-            ip_socket: None,
-            router_announced: false,
+            talkers: TalkerTable::new(TALKER_WINDOW, Instant::now()),
+            peers: PeerTable::new(),
+            sla: SlaTable::new(),
+            service_stats: ServiceStats::new(),
+            sla_saved_at: Instant::now(),
+            nvs_writer: None,
+            ip_link: None,
+            mstp_link: None,
+            last_router_announce: None,
+            router_announce_requested: true,
+            announce_scope: AnnounceScope::Both,
+            announce_networks: Vec::new(),
             transactions: TransactionTable::new(),
+            rpm_unsupported: HashSet::new(),
+            rpm_proxies: Vec::new(),
+            wpm_unsupported: HashSet::new(),
+            wpm_proxies: Vec::new(),
+            next_proxy_invoke_id: 0,
+            inventory: None,
+            client_call: None,
+            device_names: NameCache::default(),
+            router_query: None,
+            pair_probe: None,
+            network_probe: None,
             segmentation: SegmentationManager::new(),
             segmented_request_info: HashMap::new(),
             segment_transmissions: HashMap::new(),
+            network_conflict: None,
+            network_conflict_raised: false,
+            suppress_on_conflict: false,
+            heard_networks: HeardNetworks::default(),
+            whois_filter: true,
         }
     }
 
@@ -368,6 +806,237 @@ impl BacnetGateway {
             "Updated gateway local IP to {}, subnet {}, broadcast {}",
             ip, mask, broadcast
         );
+        // New address: let the IP side learn about us right away
+        self.router_announce_requested = true;
+    }
+
+    /// Our address on the station (or AP-only) interface
+    pub fn local_ip(&self) -> Ipv4Addr {
+        self.local_ip
+    }
+
+    /// Set the soft AP interface that runs alongside the station interface
+    /// (APSTA mode), or `None` when only one interface is up.
+    ///
+    /// Broadcasts are then also sent on the AP subnet, and our own traffic
+    /// echoed back on either interface is recognised.
+    pub fn set_ap_interface(&mut self, ap: Option<(Ipv4Addr, Ipv4Addr)>) {
+        match ap {
+            Some((ip, mask)) => info!(
+                "Soft AP interface {} (subnet {}) active alongside station",
+                ip, mask
+            ),
+            None => info!("Soft AP interface removed"),
+        }
+        self.ap_interface = ap;
+        self.router_announce_requested = true;
+    }
+
+    /// Whether `addr` is one of our own B/IP addresses
+    fn is_own_address(&self, addr: SocketAddr) -> bool {
+        let IpAddr::V4(ip) = addr.ip() else { return false };
+        addr.port() == self.local_port
+            && (ip == self.local_ip || self.ap_interface.is_some_and(|(ap_ip, _)| ap_ip == ip))
+    }
+
+    /// Limit the NPDU size routed onto MS/TP (clamped to MIN_MSTP_MAX_NPDU..=501)
+    pub fn set_mstp_max_npdu(&mut self, max_npdu: usize) {
+        self.mstp_max_npdu = max_npdu.clamp(MIN_MSTP_MAX_NPDU, MSTP_MAX_NPDU);
+        info!("MS/TP max NPDU: {} bytes", self.mstp_max_npdu);
+    }
+
+    /// Abort new confirmed requests from IP once the MS/TP send queue reaches
+    /// `high` frames, until it drains to `low` (high 0 = off)
+    pub fn set_backpressure(&mut self, high: usize, low: usize) {
+        self.backpressure_high = high;
+        self.backpressure_low = low.min(high.saturating_sub(1));
+        self.backpressure_active = false;
+    }
+
+    /// Report the MS/TP send queue depth before routing from IP
+    pub fn set_mstp_queue_depth(&mut self, depth: usize) {
+        if self.backpressure_high == 0 {
+            self.backpressure_active = false;
+        } else if !self.backpressure_active && depth >= self.backpressure_high {
+            warn!("MS/TP send queue at {} frames - aborting new confirmed requests from IP", depth);
+            self.backpressure_active = true;
+        } else if self.backpressure_active && depth <= self.backpressure_low {
+            info!("MS/TP send queue drained to {} frames - accepting confirmed requests from IP", depth);
+            self.backpressure_active = false;
+        }
+    }
+
+    /// Whether confirmed requests from IP are currently being aborted
+    pub fn backpressure_active(&self) -> bool {
+        self.backpressure_active
+    }
+
+    /// Drop Secure-BVLL messages silently (the default) instead of answering
+    /// with a NAK whose result code Annex J does not define
+    pub fn set_secure_bvll_drop(&mut self, drop: bool) {
+        self.secure_bvll_drop = drop;
+    }
+
+    /// Refuse BACnet writes to the BDT and routing table while the write-protect jumper is fitted
+    pub fn set_write_protected(&mut self, write_protected: bool) {
+        self.write_protected = write_protected;
+    }
+
+    /// Stand by for the other unit of a redundancy pair: received frames are
+    /// dropped and nothing is sent on IP. Taking over announces the networks.
+    pub fn set_standby(&mut self, standby: bool) {
+        if self.standby == standby {
+            return;
+        }
+        self.standby = standby;
+        if !standby {
+            self.request_router_announce();
+        }
+    }
+
+    /// Switch protocol analysis on or off; switching it on starts fresh counters
+    pub fn set_protocol_analysis(&mut self, enabled: bool) {
+        if enabled == self.analyzer.is_some() {
+            return;
+        }
+        self.analyzer = enabled.then(|| Analyzer::new(Instant::now()));
+        info!("Protocol analysis {}", if enabled { "enabled" } else { "disabled" });
+    }
+
+    /// When protocol analysis was switched on, or None while it is off
+    pub fn protocol_analysis_started(&self) -> Option<Instant> {
+        self.analyzer.as_ref().map(|a| a.started())
+    }
+
+    /// Conformance counters per source, most nonconformant first
+    pub fn analyzer_reports(&self) -> Vec<SourceReport> {
+        self.analyzer.as_ref().map(|a| a.reports()).unwrap_or_default()
+    }
+
+    /// Check a routed APDU in protocol analysis mode
+    fn analyze(&mut self, source: TalkerAddress, apdu: &[u8]) {
+        let Some(analyzer) = self.analyzer.as_mut() else { return };
+        if let Some(violation) = analyzer.check(source, apdu, Instant::now()) {
+            debug!("Nonconformant frame from {}: {} - {}", source, violation.as_str(), hex_dump(apdu, 32));
+        }
+    }
+
+    /// Secure-BVLL message counts per source address, most frequent first
+    pub fn secure_bvll_sources(&self) -> Vec<(IpAddr, u32)> {
+        let mut sources: Vec<_> = self.secure_bvll_sources.iter().map(|(&ip, &n)| (ip, n)).collect();
+        sources.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        sources
+    }
+
+    /// Tolerate known legacy NPDU header deviations instead of rejecting the frame
+    pub fn set_npdu_lenient(&mut self, lenient: bool) {
+        self.npdu_lenient = lenient;
+    }
+
+    /// In lenient mode, the NPDU re-encoded without its quirk when `data`
+    /// has one; the quirk is counted and logged the first time `source` shows it
+    fn repair_npdu(&mut self, data: &[u8], source: fmt::Arguments) -> Option<Vec<u8>> {
+        if !self.npdu_lenient {
+            return None;
+        }
+        let (npdu, quirk) = Npdu::decode_lenient(data).ok()?;
+        let quirk = quirk?;
+        self.stats.npdu_quirks[quirk as usize] += 1;
+        let key = (source.to_string(), quirk);
+        if !self.npdu_quirk_sources.contains(&key) && self.npdu_quirk_sources.len() < MAX_NPDU_QUIRK_SOURCES {
+            warn!("Tolerating NPDU quirk {} from {} - {}", quirk.as_str(), key.0, hex_dump(data, 64));
+            self.npdu_quirk_sources.insert(key);
+        }
+        Some(npdu.encode())
+    }
+
+    /// Administratively enable or disable the MS/TP port.
+    ///
+    /// While disabled, frames received from MS/TP are dropped and traffic
+    /// from IP for the MS/TP network is answered with Reject-Message-To-Network.
+    pub fn set_mstp_port_enabled(&mut self, enabled: bool) {
+        if self.mstp_port_enabled != enabled {
+            info!("MS/TP port {}", if enabled { "enabled" } else { "disabled" });
+        }
+        self.mstp_port_enabled = enabled;
+    }
+
+    /// Administratively enable or disable the IP port (see `set_mstp_port_enabled`)
+    pub fn set_ip_port_enabled(&mut self, enabled: bool) {
+        if self.ip_port_enabled != enabled {
+            info!("IP port {}", if enabled { "enabled" } else { "disabled" });
+        }
+        self.ip_port_enabled = enabled;
+    }
+
+    /// Whether the MS/TP port is routing traffic
+    pub fn mstp_port_enabled(&self) -> bool {
+        self.mstp_port_enabled
+    }
+
+    /// Whether the IP port is routing traffic
+    pub fn ip_port_enabled(&self) -> bool {
+        self.ip_port_enabled
+    }
+
+    /// Use a B/IP multicast group instead of subnet broadcast (Annex J.7).
+    /// `None` reverts to directed subnet broadcast.
+    pub fn set_multicast_group(&mut self, group: Option<Ipv4Addr>) {
+        match group {
+            Some(g) => info!("B/IP broadcasts will use multicast group {}", g),
+            None => info!("B/IP broadcasts will use subnet broadcast"),
+        }
+        self.multicast_group = group;
+    }
+
+    /// Choose directed, limited, both or no broadcast forms, and the stations
+    /// that get a unicast copy of every B/IP broadcast. Stations that stay
+    /// configured keep their counters.
+    pub fn set_broadcast_form(&mut self, form: BroadcastForm, supervisory_stations: &[SocketAddr]) {
+        let form = if form == BroadcastForm::Disabled && supervisory_stations.is_empty() {
+            warn!("B/IP broadcasts can't be disabled without a supervisory station - using directed broadcast");
+            BroadcastForm::Directed
+        } else {
+            form
+        };
+        info!(
+            "B/IP broadcast form: {}{}",
+            form.as_str(),
+            if supervisory_stations.is_empty() {
+                String::new()
+            } else {
+                format!(", unicast copy to {}", format_supervisory_stations(supervisory_stations))
+            }
+        );
+        self.broadcast_form = form;
+        let previous = std::mem::take(&mut self.supervisory_stations);
+        self.supervisory_stations = supervisory_stations
+            .iter()
+            .map(|&address| {
+                previous.iter().find(|s| s.address == address).cloned().unwrap_or(SupervisoryStation { address, sent: 0, failed: 0 })
+            })
+            .collect();
+    }
+
+    /// Supervisory stations with their delivery counters, in configured order
+    pub fn supervisory_stations(&self) -> &[SupervisoryStation] {
+        &self.supervisory_stations
+    }
+
+    /// Replace the per-subnet client compatibility rules
+    pub fn set_compat_rules(&mut self, rules: Vec<CompatRule>) {
+        if !rules.is_empty() {
+            info!("B/IP compatibility profiles: {}", compat::format_rules(&rules));
+        }
+        self.compat_rules = rules;
+    }
+
+    /// Quirks to apply to traffic for a B/IP client
+    pub fn compat_quirks(&self, client: SocketAddr) -> CompatQuirks {
+        match client.ip() {
+            IpAddr::V4(ip) => compat::quirks_for(&self.compat_rules, ip),
+            IpAddr::V6(_) => compat::CompatProfile::Default.quirks(),
+        }
     }
 
     /// Set custom address aging timeout
@@ -376,8 +1045,9 @@ impl BacnetGateway {
     }
 
     /// Set NVS partition for BDT and routing table persistence
-    /// Loads existing BDT and routing table from NVS if available
-    pub fn set_nvs_partition(&mut self, partition: EspNvsPartition<NvsDefault>) {
+    /// Loads existing BDT and routing table from NVS if available; changes
+    /// are saved through `writer` so flash writes never hold the gateway lock
+    pub fn set_nvs_partition(&mut self, partition: EspNvsPartition<NvsDefault>, writer: NvsWriter) {
         // Load existing BDT from NVS
         if let Ok(bdt_entries) = NetworkTablePersistence::load_bdt(partition.clone()) {
             if !bdt_entries.is_empty() {
@@ -407,12 +1077,26 @@ impl BacnetGateway {
             }
         }
 
-        self.nvs_partition = Some(partition);
+        // Load static routes from NVS
+        if let Ok(sr_entries) = NetworkTablePersistence::load_static_routes(partition.clone()) {
+            self.static_routes = sr_entries
+                .into_iter()
+                .map(|e| (e.network, e.next_hop))
+                .collect();
+            if !self.static_routes.is_empty() {
+                info!("Loaded {} static routes from NVS", self.static_routes.len());
+            }
+        }
+
+        // Load per-device response statistics from NVS
+        self.sla = SlaPersistence::load(partition);
+
+        self.nvs_writer = Some(writer);
     }
 
-    /// Save current BDT to NVS
+    /// Queue the current BDT for NVS
     fn save_bdt_to_nvs(&self) {
-        if let Some(ref partition) = self.nvs_partition {
+        if let Some(ref writer) = self.nvs_writer {
             let entries: Vec<BdtEntryConfig> = self.broadcast_distribution_table
                 .iter()
                 .map(|e| BdtEntryConfig {
@@ -420,15 +1104,13 @@ impl BacnetGateway {
                     broadcast_mask: Self::ipv4_to_u32(e.mask),
                 })
                 .collect();
-            if let Err(e) = NetworkTablePersistence::save_bdt(partition.clone(), &entries) {
-                warn!("Failed to save BDT to NVS: {}", e);
-            }
+            writer.submit(NvsWrite::Bdt(entries));
         }
     }
 
-    /// Save current routing table to NVS
+    /// Queue the current routing table for NVS
     fn save_routing_table_to_nvs(&self) {
-        if let Some(ref partition) = self.nvs_partition {
+        if let Some(ref writer) = self.nvs_writer {
             let entries: Vec<RoutingTableEntryConfig> = self.routing_table
                 .values()
                 .map(|e| RoutingTableEntryConfig {
@@ -437,9 +1119,26 @@ impl BacnetGateway {
                     port_info: e.port_info.clone(),
                 })
                 .collect();
-            if let Err(e) = NetworkTablePersistence::save_routing_table(partition.clone(), &entries) {
-                warn!("Failed to save routing table to NVS: {}", e);
-            }
+            writer.submit(NvsWrite::RoutingTable(entries));
+        }
+    }
+
+    /// Queue the current static routes for NVS
+    fn save_static_routes_to_nvs(&self) {
+        if let Some(ref writer) = self.nvs_writer {
+            let entries: Vec<StaticRouteConfig> = self.static_routes
+                .iter()
+                .map(|(&network, &next_hop)| StaticRouteConfig { network, next_hop })
+                .collect();
+            writer.submit(NvsWrite::StaticRoutes(entries));
+        }
+    }
+
+    /// Queue the per-device response statistics for NVS
+    fn save_sla_to_nvs(&mut self) {
+        self.sla_saved_at = Instant::now();
+        if let Some(ref writer) = self.nvs_writer {
+            writer.submit(NvsWrite::Sla(self.sla.clone()));
         }
     }
 
@@ -467,6 +1166,11 @@ impl BacnetGateway {
             .collect()
     }
 
+    /// Get number of BDT entries
+    pub fn bdt_count(&self) -> usize {
+        self.broadcast_distribution_table.len()
+    }
+
     /// Add a BDT entry (for web UI) and persist to NVS
     pub fn add_bdt_entry(&mut self, address: SocketAddr, mask: Ipv4Addr) {
         // Check if entry already exists
@@ -494,6 +1198,152 @@ impl BacnetGateway {
         self.save_bdt_to_nvs();
     }
 
+    /// Get registered foreign devices for web UI, sorted by address
+    pub fn foreign_device_summaries(&self) -> Vec<ForeignDeviceSummary> {
+        let mut devices: Vec<ForeignDeviceSummary> = self.foreign_device_table
+            .values()
+            .filter(|e| !e.is_expired())
+            .map(|e| ForeignDeviceSummary {
+                address: e.address,
+                ttl_seconds: e.ttl_seconds,
+                remaining_seconds: e.remaining_ttl(),
+                forwarded: e.forwarded,
+            })
+            .collect();
+        devices.sort_by_key(|d| d.address);
+        devices
+    }
+
+    /// Remove a foreign device registration (for web UI)
+    pub fn remove_foreign_device(&mut self, address: SocketAddr) -> bool {
+        let removed = self.foreign_device_table.remove(&address).is_some();
+        if removed {
+            info!("Removed foreign device: {}", address);
+        }
+        removed
+    }
+
+    /// Accept or reject (Register-Foreign-Device NAK) new foreign device registrations
+    pub fn set_accept_fd_registrations(&mut self, accept: bool) {
+        if self.accept_fd_registrations != accept {
+            info!("Foreign device registrations {}", if accept { "accepted" } else { "rejected" });
+        }
+        self.accept_fd_registrations = accept;
+    }
+
+    /// Whether foreign device registrations are accepted
+    pub fn accept_fd_registrations(&self) -> bool {
+        self.accept_fd_registrations
+    }
+
+    /// Get static routes for web UI, sorted by network number
+    pub fn get_static_routes(&self) -> Vec<(u16, RouteNextHop)> {
+        let mut routes: Vec<(u16, RouteNextHop)> = self.static_routes
+            .iter()
+            .map(|(&network, &next_hop)| (network, next_hop))
+            .collect();
+        routes.sort_by_key(|(network, _)| *network);
+        routes
+    }
+
+    /// Add or replace a static route (for web UI) and persist to NVS
+    ///
+    /// Directly connected networks and the global broadcast network are refused.
+    pub fn add_static_route(&mut self, network: u16, next_hop: RouteNextHop) -> bool {
+        if network == 0 || network == 0xFFFF || network == self.mstp_network || network == self.ip_network {
+            warn!("Refusing static route to directly connected or reserved network {}", network);
+            return false;
+        }
+        self.static_routes.insert(network, next_hop);
+        info!("Added static route: network {} via {}", network, next_hop);
+        self.save_static_routes_to_nvs();
+        true
+    }
+
+    /// Next hop for a static route, unless the network is backed off after a Reject
+    fn static_route_for(&self, network: u16) -> Option<RouteNextHop> {
+        if self.unreachable_networks.contains_key(&network) {
+            debug!("Network {} is backed off after a Reject - not routing", network);
+            return None;
+        }
+        self.static_routes.get(&network).copied()
+    }
+
+    /// Networks currently backed off after a Reject-Message-To-Network
+    pub fn unreachable_networks(&self) -> Vec<UnreachableNetwork> {
+        let mut networks: Vec<UnreachableNetwork> = self.unreachable_networks.values().cloned().collect();
+        networks.sort_by_key(|n| n.network);
+        networks
+    }
+
+    /// Handle a received Reject-Message-To-Network (ASHRAE 135 Clause 6.4.4)
+    ///
+    /// If we recently forwarded toward the rejected DNET, the network is backed
+    /// off for `UNREACHABLE_BACKOFF` and transactions toward it are aborted.
+    /// The Reject itself is still relayed toward its destination by the caller.
+    fn note_reject_received(&mut self, data: &[u8], npdu_len: usize, reported_by: String) {
+        // Message type (1) + reason (1) + DNET (2)
+        if npdu_len + 4 > data.len() {
+            warn!("Malformed Reject-Message-To-Network from {}", reported_by);
+            return;
+        }
+        let reason = data[npdu_len + 1];
+        let network = u16::from_be_bytes([data[npdu_len + 2], data[npdu_len + 3]]);
+        self.stats.rejects_received += 1;
+
+        let recently_forwarded = self
+            .recent_forwards
+            .get(&network)
+            .is_some_and(|at| at.elapsed() < RECENT_FORWARD_WINDOW);
+        if !recently_forwarded {
+            debug!(
+                "Reject-Message-To-Network from {} for network {} (reason {}) - not routed by us",
+                reported_by, network, reason
+            );
+            return;
+        }
+
+        warn!(
+            "Network {} unreachable (reason {}) reported by {} - backing off for {}s",
+            network, reason, reported_by, UNREACHABLE_BACKOFF.as_secs()
+        );
+        events::record(
+            EventCategory::Peer,
+            Severity::Warning,
+            &format!("Network {} unreachable (reason {}) reported by {}", network, reason, reported_by),
+        );
+        let until = Instant::now() + UNREACHABLE_BACKOFF;
+        self.unreachable_networks
+            .entry(network)
+            .and_modify(|n| {
+                n.reason = reason;
+                n.reported_by = reported_by.clone();
+                n.until = until;
+                n.count += 1;
+            })
+            .or_insert(UnreachableNetwork {
+                network,
+                reason,
+                reported_by,
+                until,
+                count: 1,
+            });
+
+        for tx in self.transactions.remove_for_network(network) {
+            if let Err(e) = self.send_abort_to_client(&tx, AbortReason::Other) {
+                warn!("Failed to abort transaction to {}: {}", tx.source_addr, e);
+            }
+        }
+    }
+
+    /// Remove a static route (for web UI) and persist to NVS
+    pub fn remove_static_route(&mut self, network: u16) {
+        if self.static_routes.remove(&network).is_some() {
+            info!("Removed static route to network {}", network);
+            self.save_static_routes_to_nvs();
+        }
+    }
+
     /// Get routing table entries for web UI
     pub fn get_routing_table_entries(&self) -> Vec<(u16, u8, Vec<u8>)> {
         self.routing_table
@@ -526,34 +1376,341 @@ impl BacnetGateway {
         }
     }
 
-    /// Set the IP socket for sending (shared with receive thread)
-    pub fn set_ip_socket(&mut self, socket: Arc<UdpSocket>) {
-        // Drain any queued packets that were waiting for the socket
-        let queued: Vec<_> = self.ip_send_queue.drain(..).collect();
-        if !queued.is_empty() {
-            info!("Draining {} queued IP packets after socket set", queued.len());
-            for (data, dest) in queued {
-                if let Err(e) = socket.send_to(&data, dest) {
-                    warn!("Failed to send queued packet to {}: {}", dest, e);
-                }
+    /// MS/TP to IP translations for web UI, by MS/TP address
+    pub fn mstp_to_ip_bindings(&self) -> Vec<AddressBindingSummary> {
+        let mut bindings: Vec<AddressBindingSummary> = self.mstp_to_ip
+            .iter()
+            .map(|(mstp, e)| AddressBindingSummary {
+                mstp_address: *mstp,
+                ip_address: e.address,
+                age_seconds: e.last_seen.elapsed().as_secs(),
+                pinned: e.pinned,
+            })
+            .collect();
+        bindings.sort_by_key(|b| b.mstp_address);
+        bindings
+    }
+
+    /// IP to MS/TP translations for web UI, by IP address
+    pub fn ip_to_mstp_bindings(&self) -> Vec<AddressBindingSummary> {
+        let mut bindings: Vec<AddressBindingSummary> = self.ip_to_mstp
+            .iter()
+            .map(|(ip, e)| AddressBindingSummary {
+                mstp_address: e.address,
+                ip_address: *ip,
+                age_seconds: e.last_seen.elapsed().as_secs(),
+                pinned: e.pinned,
+            })
+            .collect();
+        bindings.sort_by_key(|b| b.ip_address);
+        bindings
+    }
+
+    /// Pin, unpin, delete or flush address translations (for web UI).
+    /// Returns false if the entry to pin or delete is not in its table.
+    pub fn apply_binding_edit(&mut self, edit: BindingEdit) -> bool {
+        match edit {
+            BindingEdit::Pin(BindingKey::Mstp(mstp), pinned) => self.mstp_to_ip.get_mut(&mstp).map(|e| e.pinned = pinned).is_some(),
+            BindingEdit::Pin(BindingKey::Ip(ip), pinned) => self.ip_to_mstp.get_mut(&ip).map(|e| e.pinned = pinned).is_some(),
+            BindingEdit::Remove(BindingKey::Mstp(mstp)) => self.mstp_to_ip.remove(&mstp).is_some(),
+            BindingEdit::Remove(BindingKey::Ip(ip)) => self.ip_to_mstp.remove(&ip).is_some(),
+            BindingEdit::Flush => {
+                let before = self.mstp_to_ip.len() + self.ip_to_mstp.len();
+                self.mstp_to_ip.retain(|_, e| e.pinned);
+                self.ip_to_mstp.retain(|_, e| e.pinned);
+                info!("Flushed {} address bindings", before - self.mstp_to_ip.len() - self.ip_to_mstp.len());
+                true
             }
         }
-        self.ip_socket = Some(socket);
     }
 
-    /// Process transaction timeouts and retry or send Abort PDUs to clients
-    ///
-    /// This should be called periodically (e.g., every 1 second) from the main loop.
-    /// Returns the number of transactions that timed out.
-    ///
-    /// Implements retry mechanism per Phase 5.4:
-    /// - If retries remaining: retransmit NPDU to MS/TP and re-add transaction with backoff
-    /// - If retries exhausted: send Abort to IP client
-    pub fn process_transaction_timeouts(&mut self) -> usize {
-        let timed_out = self.transactions.check_timeouts();
-        let count = timed_out.len();
+    /// Remember where a device announced itself, from an I-Am APDU
+    fn learn_device_binding(&mut self, apdu: &[u8], network: u16, mac: Vec<u8>, on_mstp: bool) {
+        // I-Am service data starts with the Device object identifier (application tag 12)
+        if apdu.len() < 7 || apdu[2] != 0xC4 {
+            return;
+        }
+        let object_id = u32::from_be_bytes([apdu[3], apdu[4], apdu[5], apdu[6]]);
+        if object_id >> 22 != OBJECT_TYPE_DEVICE {
+            return;
+        }
+        let device_instance = object_id & 0x3F_FFFF;
+        let binding = DeviceBinding { device_instance, network, mac, on_mstp };
+        if let Some(target) = self.name_target(&binding) {
+            self.device_names.observe(device_instance, target);
+        }
 
-        for tx in timed_out {
+        if let Some(entry) = self.device_bindings.get_mut(&device_instance) {
+            if entry.address != binding {
+                debug!("Device {} now at network {} MAC {:02X?}", device_instance, binding.network, binding.mac);
+                entry.address = binding;
+            }
+            entry.touch();
+            return;
+        }
+        if self.device_bindings.len() >= MAX_DEVICE_BINDINGS {
+            let oldest = self.device_bindings.iter().min_by_key(|(_, e)| e.last_seen).map(|(i, _)| *i);
+            if let Some(oldest) = oldest {
+                self.device_bindings.remove(&oldest);
+            }
+        }
+        debug!(
+            "Learned device {} at network {} MAC {:02X?} via {} port",
+            device_instance, binding.network, binding.mac, if binding.on_mstp { "MS/TP" } else { "IP" }
+        );
+        self.device_bindings.insert(device_instance, AddressEntry::new(binding));
+    }
+
+    /// Device bindings learned from I-Am, sorted by device instance
+    pub fn device_bindings(&self) -> Vec<DeviceBinding> {
+        let mut bindings: Vec<DeviceBinding> = self.device_bindings.values().map(|e| e.address.clone()).collect();
+        bindings.sort_by_key(|b| b.device_instance);
+        bindings
+    }
+
+    /// Where to read a bound device's name: devices on the gateway's own
+    /// networks only, not those behind other routers
+    fn name_target(&self, binding: &DeviceBinding) -> Option<NameTarget> {
+        match binding.mac.as_slice() {
+            &[mac] if binding.on_mstp && binding.network == self.mstp_network => Some(NameTarget::Mstp(mac)),
+            [_, _, _, _, _, _] if !binding.on_mstp && binding.network == self.ip_network => {
+                self.resolve_ip_address(&binding.mac).ok().map(NameTarget::Ip)
+            }
+            _ => None,
+        }
+    }
+
+    /// Object_Name of bound devices read so far, by device instance
+    pub fn device_names(&self) -> HashMap<u32, String> {
+        self.device_names.names()
+    }
+
+    /// Bound devices whose name has not been read yet
+    pub fn device_names_pending(&self) -> usize {
+        self.device_names.pending()
+    }
+
+    /// Read every device name again (the portal's refresh button)
+    pub fn refresh_device_names(&mut self) {
+        info!("Device names will be read again");
+        self.device_names.invalidate_all();
+    }
+
+    /// Send the next device name read: MS/TP reads wait like the
+    /// inventory's, IP reads go out while the IP port is enabled
+    fn poll_device_names(&mut self) {
+        let mstp_ready = self.mstp_port_enabled
+            && self.transactions.is_empty()
+            && self.rpm_proxies.is_empty()
+            && self.wpm_proxies.is_empty()
+            && self.inventory.as_ref().map_or(true, |job| job.is_finished());
+        let ip_ready = self.ip_port_enabled;
+        let ready = |target: NameTarget| match target {
+            NameTarget::Mstp(_) => mstp_ready,
+            NameTarget::Ip(_) => ip_ready,
+        };
+        let Some((apdu, target)) = self.device_names.poll(Instant::now(), &mut self.next_proxy_invoke_id, ready) else {
+            return;
+        };
+        let npdu = local_request_npdu(&apdu);
+        match target {
+            NameTarget::Mstp(mac) => self.queue_mstp_retransmit(npdu, mac),
+            NameTarget::Ip(addr) => {
+                if let Err(e) = self.send_ip_packet(&build_bvlc(&npdu, false), addr) {
+                    debug!("Device name read to {} not sent: {}", addr, e);
+                }
+            }
+        }
+    }
+
+    /// Skip forwarding a ranged Who-Is to MS/TP when every device in the
+    /// range is already bound to the IP side
+    pub fn set_whois_filter(&mut self, enabled: bool) {
+        self.whois_filter = enabled;
+    }
+
+    /// Learn the IP network number from Network-Number-Is instead of trusting
+    /// the configured one, which only carries traffic until then
+    pub fn set_ip_network_learning(&mut self, enabled: bool) {
+        if enabled {
+            self.ip_network_quality = NetworkNumberQuality::Unknown;
+        }
+    }
+
+    /// The IP port's network number and its quality
+    pub fn ip_network_number(&self) -> NetworkNumber {
+        NetworkNumber { number: self.ip_network, quality: self.ip_network_quality }
+    }
+
+    /// The MS/TP port's network number (always configured)
+    pub fn mstp_network_number(&self) -> NetworkNumber {
+        NetworkNumber::configured(self.mstp_network)
+    }
+
+    /// Routing, or pass-through while the IP network number is unknown
+    pub fn router_mode(&self) -> RouterMode {
+        RouterMode::of(&[self.mstp_network_number(), self.ip_network_number()])
+    }
+
+    /// Answer What-Is-Network-Number and apply Network-Number-Is heard on the
+    /// IP port (`from_ip`) or the MS/TP port. Neither message is ever routed,
+    /// so a routed copy is dropped. Returns the Network-Number-Is to broadcast
+    /// back on the same port.
+    fn handle_network_number_message(
+        &mut self,
+        msg_type: u8,
+        body: &[u8],
+        routed: bool,
+        from_ip: bool,
+        sender: &str,
+    ) -> Option<Vec<u8>> {
+        if routed {
+            return None;
+        }
+        let mut port = if from_ip { self.ip_network_number() } else { self.mstp_network_number() };
+        if msg_type == NL_WHAT_IS_NETWORK_NUMBER {
+            debug!("What-Is-Network-Number from {}, ours is {}", sender, port);
+            return port.is_known().then(|| Npdu::network_message(&netnum::network_number_is(port)).encode());
+        }
+
+        let (number, configured) = netnum::parse_network_number_is(body)?;
+        if from_ip && configured {
+            self.heard_networks.ip_network = Some(number);
+        }
+        let other_port = if from_ip { self.mstp_network } else { self.ip_network };
+        match port.learn(number, configured) {
+            LearnOutcome::Unchanged => {}
+            LearnOutcome::Learned(number) if number == other_port => {
+                warn!("Network-Number-Is {} from {} is our other port's number, not learned", number, sender);
+            }
+            LearnOutcome::Learned(number) => {
+                // Only the IP port learns; the MS/TP number is always configured
+                info!("Learned IP network number {} from {}", number, sender);
+                events::record(
+                    EventCategory::Ip,
+                    Severity::Info,
+                    &format!("IP network number {} learned from {}", number, sender),
+                );
+                self.ip_network = number;
+                self.ip_network_quality = port.quality;
+                self.router_announce_requested = true;
+            }
+            LearnOutcome::Conflict(number) => {
+                warn!("{} announces network number {} for a port configured as {}", sender, number, port.number);
+                events::record(
+                    EventCategory::Ip,
+                    Severity::Warning,
+                    &format!("{} announces network number {}, configured as {}", sender, number, port.number),
+                );
+            }
+        }
+        None
+    }
+
+    /// True when every instance in low..=high has a binding on the IP side.
+    /// A range wider than the binding cache can never be fully known.
+    fn whois_range_on_ip(&self, low: u32, high: u32) -> bool {
+        if low > high || (high - low) as usize >= self.device_bindings.len() {
+            return false;
+        }
+        (low..=high).all(|instance| {
+            self.device_bindings.get(&instance).is_some_and(|e| !e.address.on_mstp)
+        })
+    }
+
+    /// Set the BACnet/IP data link used for sending
+    pub fn set_ip_link(&mut self, mut link: Box<dyn DataLink>) {
+        // Drain any queued packets that were waiting for the link
+        let queued: Vec<_> = self.ip_send_queue.drain(..).collect();
+        if !queued.is_empty() {
+            info!("Draining {} queued IP packets after {} link set", queued.len(), link.name());
+            for (data, dest) in queued {
+                if let Err(e) = link.send_raw(&data, LinkAddress::Ip(dest)) {
+                    warn!("Failed to send queued packet to {}: {}", dest, e);
+                }
+            }
+        }
+        self.ip_link = Some(link);
+    }
+
+    /// Set the MS/TP data link used for sending
+    pub fn set_mstp_link(&mut self, mut link: Box<dyn DataLink>) {
+        let queued: Vec<_> = self.mstp_send_queue.drain(..).collect();
+        if !queued.is_empty() {
+            info!("Draining {} queued MS/TP frames after {} link set", queued.len(), link.name());
+            for (npdu, dest_mac) in queued {
+                if let Err(e) = link.send(&npdu, LinkAddress::Mstp(dest_mac)) {
+                    warn!("Failed to send queued frame to MS/TP {}: {}", dest_mac, e);
+                }
+            }
+        }
+        self.mstp_link = Some(link);
+    }
+
+    /// Route a datagram received on the B/IP port, sending anything bound
+    /// for MS/TP through the MS/TP link
+    pub fn receive_from_ip(&mut self, data: &[u8], source_addr: SocketAddr) -> Result<(), GatewayError> {
+        if let Some((npdu, dest_mac)) = self.route_from_ip(data, source_addr)? {
+            trace!("IP->MS/TP routing to MS/TP {}: {} bytes", dest_mac, npdu.len());
+            self.forward_to_mstp(npdu, dest_mac, source_addr);
+        }
+        Ok(())
+    }
+
+    /// Route a frame received on the MS/TP port, sending any reply (reject
+    /// or proxied request) back through the MS/TP link
+    pub fn receive_from_mstp(&mut self, data: &[u8], source_addr: u8) -> Result<(), GatewayError> {
+        if let Some((npdu, dest_mac)) = self.route_from_mstp(data, source_addr)? {
+            self.send_on_mstp(npdu, dest_mac);
+        }
+        Ok(())
+    }
+
+    /// Send an IP->MS/TP frame, or hold it while the ring is down (or other
+    /// frames are already held, to keep ordering). Held frames are released
+    /// by `send_held_frames` or failed on TTL expiry.
+    fn forward_to_mstp(&mut self, npdu: Vec<u8>, dest_mac: u8, source_addr: SocketAddr) {
+        let expecting_reply = Npdu::decode(&npdu).is_ok_and(|npdu| npdu.expecting_reply);
+        let Some(link) = self.mstp_link.as_mut() else {
+            self.mstp_send_queue.push((npdu, dest_mac));
+            return;
+        };
+        if !link.is_up() || !self.held_frames.is_empty() {
+            self.hold_for_mstp(npdu, dest_mac, expecting_reply, source_addr);
+            return;
+        }
+        if let Err(e) = link.send(&npdu, LinkAddress::Mstp(dest_mac)) {
+            warn!("Failed to send to MS/TP {}: {}, holding frame", dest_mac, e);
+            errors::record_error(ErrorSource::Mstp, &e);
+            self.hold_for_mstp(npdu, dest_mac, expecting_reply, source_addr);
+        }
+    }
+
+    /// Send an NPDU on MS/TP, or queue it until the MS/TP link is set
+    fn send_on_mstp(&mut self, npdu: Vec<u8>, dest_mac: u8) {
+        match self.mstp_link.as_mut() {
+            Some(link) => {
+                if let Err(e) = link.send(&npdu, LinkAddress::Mstp(dest_mac)) {
+                    warn!("Failed to send to MS/TP {}: {}", dest_mac, e);
+                }
+            }
+            None => self.mstp_send_queue.push((npdu, dest_mac)),
+        }
+    }
+
+    /// Process transaction timeouts and retry or send Abort PDUs to clients
+    ///
+    /// This should be called periodically (e.g., every 1 second) from the main loop.
+    /// Returns the number of transactions that timed out.
+    ///
+    /// Implements retry mechanism per Phase 5.4:
+    /// - If retries remaining: retransmit NPDU to MS/TP and re-add transaction with backoff
+    /// - If retries exhausted: send Abort to IP client
+    pub fn process_transaction_timeouts(&mut self) -> usize {
+        let timed_out = self.transactions.check_timeouts();
+        let count = timed_out.len();
+
+        for tx in timed_out {
             if tx.retries < tx.max_retries {
                 // Retries remaining - retransmit to MS/TP
                 info!(
@@ -591,6 +1748,9 @@ impl BacnetGateway {
 
                 // Track timeout in statistics
                 self.stats.transaction_timeouts += 1;
+                self.peers.record_unanswered(tx.source_addr);
+                self.sla.record_timeout(tx.dest_mac);
+                self.service_stats.record_timeout(tx.service as u8);
 
                 if let Err(e) = self.send_abort_to_client(&tx, AbortReason::Other) {
                     warn!(
@@ -605,28 +1765,526 @@ impl BacnetGateway {
             debug!("Processed {} transaction timeout(s)", count);
         }
 
+        self.process_rpm_proxy_timeouts();
+        self.process_wpm_proxy_timeouts();
+        if let Some((npdu, dest_mac)) = self.poll_inventory() {
+            self.queue_mstp_retransmit(npdu, dest_mac);
+        }
+        if let Some((apdu, dest_mac)) = self.client_call.as_mut().and_then(|call| call.poll(Instant::now(), &mut self.next_proxy_invoke_id)) {
+            self.queue_mstp_retransmit(local_request_npdu(&apdu), dest_mac);
+        }
+        self.poll_device_names();
+
         count
     }
 
-    /// Queue an NPDU for retransmission to MS/TP
+    /// Start a site inventory of the given (device instance, MS/TP MAC) pairs.
+    /// Returns false while another inventory is still running.
+    pub fn start_inventory(&mut self, targets: &[(u32, u8)]) -> bool {
+        if self.inventory.as_ref().is_some_and(|job| !job.is_finished()) {
+            return false;
+        }
+        info!("Starting site inventory of {} MS/TP device(s)", targets.len());
+        self.inventory = Some(InventoryJob::new(targets));
+        true
+    }
+
+    /// Stop the running inventory; what was read so far is kept
+    pub fn cancel_inventory(&mut self) {
+        if let Some(job) = self.inventory.as_mut() {
+            info!("Site inventory cancelled");
+            job.cancel();
+        }
+    }
+
+    /// Progress of the running inventory, if any
+    pub fn inventory_progress(&self) -> Option<InventoryProgress> {
+        self.inventory.as_ref().filter(|job| !job.is_finished()).map(|job| job.progress())
+    }
+
+    /// The devices of a finished inventory, handed over once
+    pub fn take_finished_inventory(&mut self) -> Option<Vec<InventoryDevice>> {
+        if !self.inventory.as_ref()?.is_finished() {
+            return None;
+        }
+        let job = self.inventory.take()?;
+        let progress = job.progress();
+        info!("Site inventory finished: {} device(s), {} object(s)", progress.devices_total, progress.objects_found);
+        Some(job.into_devices())
+    }
+
+    /// Make a ReadProperty or WriteProperty call to an MS/TP device; it goes
+    /// out with the next housekeeping pass. Replaces any earlier call.
+    pub fn start_client_call(&mut self, call: ClientCall) {
+        info!("Client call to MS/TP {}: {:?}", call.mac, call.request);
+        self.client_call = Some(call);
+    }
+
+    /// The outcome of the client call, handed over once it has ended
+    pub fn take_client_outcome(&mut self) -> Option<ClientOutcome> {
+        let outcome = self.client_call.as_mut()?.take_outcome()?;
+        self.client_call = None;
+        Some(outcome)
+    }
+
+    /// Ask a remote router for its routing table or reachable networks.
+    /// Replaces the previous query and its answers.
+    pub fn start_router_query(&mut self, target: SocketAddr, kind: RouterQueryKind) -> Result<(), GatewayError> {
+        info!("Querying router {} with {}", target, kind);
+        self.router_query = Some(RouterQuery::new(target, kind, Instant::now()));
+        let bvlc = build_bvlc(&kind.encode(), false);
+        self.send_ip_packet(&bvlc, target)
+    }
+
+    /// The running or last finished router query
+    pub fn router_query(&self) -> Option<&RouterQuery> {
+        self.router_query.as_ref()
+    }
+
+    /// Broadcast a Who-Is-Router-To-Network for a paired peer's MS/TP network.
+    /// It leaves the subnet only through the BDT, so the peer answering shows
+    /// that broadcasts reach it.
+    pub fn start_pair_probe(&mut self, peer: SocketAddr, network: u16) -> Result<(), GatewayError> {
+        info!("Test broadcast to paired peer {} for network {}", peer, network);
+        self.pair_probe = Some(RouterQuery::new(peer, RouterQueryKind::WhoIsRouter, Instant::now()));
+        let [hi, lo] = network.to_be_bytes();
+        let npdu = Npdu::network_message(&[NL_WHO_IS_ROUTER_TO_NETWORK, hi, lo]).encode();
+        self.send_ip_broadcast(&build_bvlc(&npdu, true))?;
+        let local_addr = SocketAddr::new(IpAddr::V4(self.local_ip), self.local_port);
+        self.forward_to_bdt_entries(&npdu, local_addr)
+    }
+
+    /// The running or last finished pairing test broadcast
+    pub fn pair_probe(&self) -> Option<&RouterQuery> {
+        self.pair_probe.as_ref()
+    }
+
+    /// Ask every router whether it reaches `networks`, before a configuration
+    /// change starts using them. Who-Is-Router-To-Network goes out on IP (and
+    /// to the BDT peers); the NPDUs returned are for the caller to broadcast
+    /// on MS/TP. Answers are collected until `stop_network_probe`.
+    pub fn start_network_probe(&mut self, networks: &[u16]) -> Result<Vec<Vec<u8>>, GatewayError> {
+        info!("Checking whether any router reaches networks {:?}", networks);
+        self.network_probe = Some((networks.to_vec(), Vec::new()));
+        let local_addr = SocketAddr::new(IpAddr::V4(self.local_ip), self.local_port);
+        let mut mstp_npdus = Vec::new();
+        for &network in networks {
+            let [hi, lo] = network.to_be_bytes();
+            let npdu = Npdu::network_message(&[NL_WHO_IS_ROUTER_TO_NETWORK, hi, lo]).encode();
+            self.send_ip_broadcast(&build_bvlc(&npdu, true))?;
+            self.forward_to_bdt_entries(&npdu, local_addr)?;
+            mstp_npdus.push(npdu);
+        }
+        Ok(mstp_npdus)
+    }
+
+    /// Routers that announced a probed network since the last call
+    pub fn take_network_probe_answers(&mut self) -> Vec<(String, Vec<u16>)> {
+        self.network_probe.as_mut().map(|(_, answers)| std::mem::take(answers)).unwrap_or_default()
+    }
+
+    pub fn stop_network_probe(&mut self) {
+        self.network_probe = None;
+    }
+
+    /// Note an I-Am-Router-To-Network for the network probe if it lists a probed network
+    fn note_network_probe_answer(&mut self, router: String, networks: &[u8]) {
+        let Some((probed, answers)) = self.network_probe.as_mut() else {
+            return;
+        };
+        let networks: Vec<u16> = networks
+            .chunks_exact(2)
+            .map(|n| u16::from_be_bytes([n[0], n[1]]))
+            .filter(|n| probed.contains(n))
+            .collect();
+        if !networks.is_empty() {
+            info!("Router {} reaches probed networks {:?}", router, networks);
+            answers.push((router, networks));
+        }
+    }
+
+    /// Next inventory ReadProperty as an NPDU for MS/TP, held back while
+    /// client requests or RPM/WPM proxies are waiting on the token ring
+    fn poll_inventory(&mut self) -> Option<(Vec<u8>, u8)> {
+        if !self.mstp_port_enabled
+            || !self.transactions.is_empty()
+            || !self.rpm_proxies.is_empty()
+            || !self.wpm_proxies.is_empty()
+        {
+            return None;
+        }
+        let job = self.inventory.as_mut()?;
+        let (apdu, dest_mac) = job.poll(Instant::now(), &mut self.next_proxy_invoke_id)?;
+        Some((local_request_npdu(&apdu), dest_mac))
+    }
+
+    /// Retransmit unanswered proxy ReadProperty calls, giving up with an Abort to the client
+    fn process_rpm_proxy_timeouts(&mut self) {
+        let now = Instant::now();
+        let mut index = 0;
+        while index < self.rpm_proxies.len() {
+            let proxy = &mut self.rpm_proxies[index];
+            if now.duration_since(proxy.sent_at) < RPM_PROXY_READ_TIMEOUT {
+                index += 1;
+            } else if proxy.retries < RPM_PROXY_MAX_RETRIES {
+                proxy.retries += 1;
+                proxy.sent_at = now;
+                debug!(
+                    "RPM proxy ReadProperty to MS/TP {} timed out, retry {}/{}",
+                    proxy.dest_mac, proxy.retries, RPM_PROXY_MAX_RETRIES
+                );
+                let (request, dest_mac) = (local_request_npdu(&proxy.request()), proxy.dest_mac);
+                self.queue_mstp_retransmit(request, dest_mac);
+                index += 1;
+            } else {
+                let proxy = self.rpm_proxies.swap_remove(index);
+                let (read, total) = proxy.progress();
+                warn!(
+                    "RPM proxy for {} (invoke_id={}) gave up: MS/TP {} stopped answering after {}/{} properties",
+                    proxy.client, proxy.client_invoke_id, proxy.dest_mac, read, total
+                );
+                self.stats.transaction_timeouts += 1;
+                self.peers.record_unanswered(proxy.client);
+                if let Err(e) = self.send_abort(proxy.client_invoke_id, proxy.client, AbortReason::Other) {
+                    warn!("Failed to send timeout abort to {}: {}", proxy.client, e);
+                }
+            }
+        }
+    }
+
+    /// Start answering an RPM with ReadProperty calls
+    ///
+    /// `routed_npdu` is the client's request as routed onto MS/TP. When it can
+    /// be translated, the client's transaction is replaced by the proxy and the
+    /// first ReadProperty NPDU is returned.
+    fn start_rpm_proxy(&mut self, routed_npdu: &[u8], dest_mac: u8, client: SocketAddr) -> Option<Vec<u8>> {
+        if self.rpm_proxies.len() >= MAX_RPM_PROXIES {
+            debug!("RPM proxy table full - forwarding RPM from {} unchanged", client);
+            return None;
+        }
+        let apdu = Npdu::decode(routed_npdu).ok()?.payload;
+        let invoke_id = self.next_proxy_invoke_id;
+        let proxy = RpmProxy::new(apdu, client, dest_mac, invoke_id, Instant::now())?;
+        self.next_proxy_invoke_id = invoke_id.wrapping_add(1);
+        self.transactions.remove(proxy.client_invoke_id, dest_mac);
+        self.stats.rpm_proxied += 1;
+        info!(
+            "Answering RPM from {} (invoke_id={}) for MS/TP {} with {} ReadProperty calls",
+            client, proxy.client_invoke_id, dest_mac, proxy.progress().1
+        );
+        let request = local_request_npdu(&proxy.request());
+        self.rpm_proxies.push(proxy);
+        Some(request)
+    }
+
+    /// Retry an RPM or WPM the device rejected as an unrecognized service as
+    /// ReadProperty or WriteProperty calls
+    ///
+    /// The device is remembered so later requests to it are translated straight away.
+    fn proxy_rejected_request(&mut self, apdu: &[u8], source_addr: u8) -> Option<Vec<u8>> {
+        // Reject PDU: [type, invoke ID, reason]
+        if apdu.len() < 3 || apdu[0] & 0xF0 != 0x60 || apdu[2] != REJECT_UNRECOGNIZED_SERVICE {
+            return None;
+        }
+        let tx = self.transactions.get(apdu[1], source_addr)?;
+        let (service, routed_npdu, client) = (tx.service, tx.original_npdu.clone(), tx.source_addr);
+        match service {
+            ConfirmedServiceChoice::ReadPropertyMultiple => {
+                if self.rpm_unsupported.insert(source_addr) {
+                    info!("MS/TP {} does not support ReadPropertyMultiple - translating to ReadProperty", source_addr);
+                }
+                self.start_rpm_proxy(&routed_npdu, source_addr, client)
+            }
+            ConfirmedServiceChoice::WritePropertyMultiple => {
+                if self.wpm_unsupported.insert(source_addr) {
+                    info!("MS/TP {} does not support WritePropertyMultiple - translating to WriteProperty", source_addr);
+                }
+                self.start_wpm_proxy(&routed_npdu, source_addr, client)
+            }
+            _ => None,
+        }
+    }
+
+    /// Feed a device's answer to the proxy at `index`
+    ///
+    /// Returns the next ReadProperty to send, or `None` once the client has
+    /// been sent the ReadPropertyMultiple-ACK (or an Abort).
+    fn continue_rpm_proxy(&mut self, index: usize, apdu: &[u8]) -> Option<(Vec<u8>, u8)> {
+        let next_invoke_id = self.next_proxy_invoke_id;
+        let step = self.rpm_proxies[index].record(apdu, next_invoke_id, Instant::now());
+        if let ProxyStep::Next(request) = step {
+            self.next_proxy_invoke_id = next_invoke_id.wrapping_add(1);
+            return Some((local_request_npdu(&request), self.rpm_proxies[index].dest_mac));
+        }
+
+        let proxy = self.rpm_proxies.swap_remove(index);
+        let result = match step {
+            ProxyStep::Complete(ack) => {
+                debug!(
+                    "RPM proxy for {} complete: {} properties, {} byte ACK",
+                    proxy.client, proxy.progress().1, ack.len()
+                );
+                self.send_proxy_reply(&ack, proxy.dest_mac, proxy.client)
+            }
+            ProxyStep::TooLong => {
+                warn!(
+                    "RPM proxy for {}: ACK exceeds the client's max APDU of {} bytes",
+                    proxy.client, proxy.client_max_apdu
+                );
+                self.send_abort(proxy.client_invoke_id, proxy.client, AbortReason::SegmentationNotSupported)
+            }
+            ProxyStep::Failed | ProxyStep::Next(_) => {
+                warn!(
+                    "RPM proxy for {}: MS/TP {} answered ReadProperty with {:02X?}",
+                    proxy.client, proxy.dest_mac, &apdu[..apdu.len().min(4)]
+                );
+                self.send_abort(proxy.client_invoke_id, proxy.client, AbortReason::Other)
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to answer proxied RPM from {}: {}", proxy.client, e);
+        }
+        None
+    }
+
+    /// Send a proxy's final APDU to the client as the device would:
+    /// SNET/SADR identify it on the MS/TP network
+    fn send_proxy_reply(&mut self, apdu: &[u8], dest_mac: u8, client: SocketAddr) -> Result<(), GatewayError> {
+        let npdu = Npdu { source: Some(NetworkAddress::new(self.mstp_network, &[dest_mac])), ..Npdu::local(apdu) }.encode();
+        self.stats.mstp_to_ip_packets += 1;
+        self.stats.mstp_to_ip_bytes += npdu.len() as u64;
+        let bvlc = build_bvlc(&npdu, false);
+        self.send_ip_packet(&bvlc, client)
+    }
+
+    /// Retransmit unanswered proxy WriteProperty calls, giving up with an Abort to the client
+    fn process_wpm_proxy_timeouts(&mut self) {
+        let now = Instant::now();
+        let mut index = 0;
+        while index < self.wpm_proxies.len() {
+            let proxy = &mut self.wpm_proxies[index];
+            if now.duration_since(proxy.sent_at) < WPM_PROXY_WRITE_TIMEOUT {
+                index += 1;
+            } else if proxy.retries < WPM_PROXY_MAX_RETRIES {
+                proxy.retries += 1;
+                proxy.sent_at = now;
+                debug!(
+                    "WPM proxy WriteProperty to MS/TP {} timed out, retry {}/{}",
+                    proxy.dest_mac, proxy.retries, WPM_PROXY_MAX_RETRIES
+                );
+                let (request, dest_mac) = (local_request_npdu(&proxy.request()), proxy.dest_mac);
+                self.queue_mstp_retransmit(request, dest_mac);
+                index += 1;
+            } else {
+                let proxy = self.wpm_proxies.swap_remove(index);
+                let (written, total) = proxy.progress();
+                warn!(
+                    "WPM proxy for {} (invoke_id={}) gave up: MS/TP {} stopped answering after {}/{} writes",
+                    proxy.client, proxy.client_invoke_id, proxy.dest_mac, written, total
+                );
+                self.stats.transaction_timeouts += 1;
+                self.peers.record_unanswered(proxy.client);
+                if let Err(e) = self.send_abort(proxy.client_invoke_id, proxy.client, AbortReason::Other) {
+                    warn!("Failed to send timeout abort to {}: {}", proxy.client, e);
+                }
+            }
+        }
+    }
+
+    /// Start executing a WPM as WriteProperty calls
+    ///
+    /// `routed_npdu` is the client's request as routed onto MS/TP. When it can
+    /// be translated, the client's transaction is replaced by the proxy and the
+    /// first WriteProperty NPDU is returned.
+    fn start_wpm_proxy(&mut self, routed_npdu: &[u8], dest_mac: u8, client: SocketAddr) -> Option<Vec<u8>> {
+        if self.wpm_proxies.len() >= MAX_WPM_PROXIES {
+            debug!("WPM proxy table full - forwarding WPM from {} unchanged", client);
+            return None;
+        }
+        let apdu = Npdu::decode(routed_npdu).ok()?.payload;
+        let invoke_id = self.next_proxy_invoke_id;
+        let proxy = WpmProxy::new(apdu, client, dest_mac, invoke_id, Instant::now())?;
+        self.next_proxy_invoke_id = invoke_id.wrapping_add(1);
+        self.transactions.remove(proxy.client_invoke_id, dest_mac);
+        self.stats.wpm_proxied += 1;
+        info!(
+            "Executing WPM from {} (invoke_id={}) for MS/TP {} as {} WriteProperty calls",
+            client, proxy.client_invoke_id, dest_mac, proxy.progress().1
+        );
+        let request = local_request_npdu(&proxy.request());
+        self.wpm_proxies.push(proxy);
+        Some(request)
+    }
+
+    /// Feed a device's answer to the WPM proxy at `index`
+    ///
+    /// Returns the next WriteProperty to send, or `None` once the client has
+    /// been sent the result (or an Abort).
+    fn continue_wpm_proxy(&mut self, index: usize, apdu: &[u8]) -> Option<(Vec<u8>, u8)> {
+        let next_invoke_id = self.next_proxy_invoke_id;
+        let step = self.wpm_proxies[index].record(apdu, next_invoke_id, Instant::now());
+        if let WpmStep::Next(request) = step {
+            self.next_proxy_invoke_id = next_invoke_id.wrapping_add(1);
+            return Some((local_request_npdu(&request), self.wpm_proxies[index].dest_mac));
+        }
+
+        let proxy = self.wpm_proxies.swap_remove(index);
+        let result = match step {
+            WpmStep::Complete(reply) => {
+                let (written, total) = proxy.progress();
+                debug!("WPM proxy for {} complete: {}/{} values written", proxy.client, written, total);
+                self.send_proxy_reply(&reply, proxy.dest_mac, proxy.client)
+            }
+            WpmStep::Failed | WpmStep::Next(_) => {
+                warn!(
+                    "WPM proxy for {}: MS/TP {} answered WriteProperty with {:02X?}",
+                    proxy.client, proxy.dest_mac, &apdu[..apdu.len().min(4)]
+                );
+                self.send_abort(proxy.client_invoke_id, proxy.client, AbortReason::Other)
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to answer proxied WPM from {}: {}", proxy.client, e);
+        }
+        None
+    }
+
+    /// Re-send a timed-out request to MS/TP
     ///
     /// This is used by the retry mechanism to re-send timed-out requests.
     fn queue_mstp_retransmit(&mut self, npdu: Vec<u8>, dest_mac: u8) {
-        debug!(
-            "Queuing MS/TP retransmit: {} bytes to MAC {} (queue_len={})",
-            npdu.len(),
+        info!("Retransmitting {} bytes to MS/TP MAC {}", npdu.len(), dest_mac);
+        self.send_on_mstp(npdu, dest_mac);
+    }
+
+    /// Drain the frames queued for MS/TP before its link was set
+    #[cfg(test)]
+    pub fn drain_mstp_send_queue(&mut self) -> Vec<(Vec<u8>, u8)> {
+        self.mstp_send_queue.drain(..).collect()
+    }
+
+    /// Hold an IP->MS/TP frame until the MS/TP token is back
+    ///
+    /// When the queue is full the new frame is refused straight away so the
+    /// client gets an Abort/Reject instead of waiting for a timeout.
+    pub fn hold_for_mstp(&mut self, npdu: Vec<u8>, dest_mac: u8, expecting_reply: bool, source_addr: SocketAddr) {
+        let frame = HeldFrame {
+            npdu,
             dest_mac,
-            self.mstp_send_queue.len() + 1
+            expecting_reply,
+            source_addr,
+            queued_at: Instant::now(),
+        };
+
+        if self.held_frames.len() >= MAX_HELD_FRAMES {
+            warn!(
+                "Store-and-forward queue full ({} frames), refusing frame from {} to MS/TP {}",
+                MAX_HELD_FRAMES, source_addr, dest_mac
+            );
+            self.stats.held_overflows += 1;
+            self.fail_held_frame(&frame);
+            return;
+        }
+
+        debug!(
+            "MS/TP token lost, holding {} bytes from {} for MS/TP {} (queue_len={})",
+            frame.npdu.len(), source_addr, dest_mac, self.held_frames.len() + 1
         );
-        self.mstp_send_queue.push((npdu, dest_mac));
+        self.held_frames.push_back(frame);
+        self.stats.held_frames += 1;
+        self.stats.held_high_water = self.stats.held_high_water.max(self.held_frames.len());
     }
 
-    /// Drain the MS/TP send queue and return all pending transmissions
+    /// Release up to `max` held frames, oldest first, once the MS/TP token is back
     ///
-    /// The caller (main loop) should call this periodically and send the frames
-    /// via the MS/TP driver.
-    pub fn drain_mstp_send_queue(&mut self) -> Vec<(Vec<u8>, u8)> {
-        self.mstp_send_queue.drain(..).collect()
+    /// Each entry: (npdu_data, dest_mac, expecting_reply)
+    pub fn release_held_frames(&mut self, max: usize) -> Vec<(Vec<u8>, u8, bool)> {
+        let count = max.min(self.held_frames.len());
+        if count == 0 {
+            return Vec::new();
+        }
+        info!(
+            "MS/TP token restored, releasing {} of {} held frame(s)",
+            count, self.held_frames.len()
+        );
+        self.stats.held_released += count as u64;
+        self.held_frames
+            .drain(..count)
+            .map(|f| (f.npdu, f.dest_mac, f.expecting_reply))
+            .collect()
+    }
+
+    /// Send up to `max` held frames through the MS/TP link once it is up
+    ///
+    /// Returns the number of frames released.
+    pub fn send_held_frames(&mut self, max: usize) -> usize {
+        if !self.mstp_link.as_ref().is_some_and(|link| link.is_up()) {
+            return 0;
+        }
+        let released = self.release_held_frames(max);
+        let count = released.len();
+        for (npdu, dest_mac, _) in released {
+            self.send_on_mstp(npdu, dest_mac);
+        }
+        count
+    }
+
+    /// Drop held frames older than the TTL and tell their clients
+    ///
+    /// Returns the number of frames that expired.
+    pub fn expire_held_frames(&mut self) -> usize {
+        let mut count = 0;
+        while let Some(frame) = self.held_frames.front() {
+            if frame.queued_at.elapsed() < HELD_FRAME_TTL {
+                break;
+            }
+            let frame = self.held_frames.pop_front().unwrap();
+            warn!(
+                "Held frame from {} for MS/TP {} expired after {:.1}s",
+                frame.source_addr, frame.dest_mac, frame.queued_at.elapsed().as_secs_f32()
+            );
+            self.stats.held_expired += 1;
+            self.fail_held_frame(&frame);
+            count += 1;
+        }
+        count
+    }
+
+    /// Number of frames currently held for MS/TP
+    pub fn held_frame_count(&self) -> usize {
+        self.held_frames.len()
+    }
+
+    /// Tell the IP client a held frame will not be delivered
+    ///
+    /// Confirmed requests get an Abort (and their transaction is dropped);
+    /// other unicast frames get a Reject-Message-To-Network (router busy).
+    /// Broadcasts are dropped silently.
+    fn fail_held_frame(&mut self, frame: &HeldFrame) {
+        let apdu = match Npdu::decode(&frame.npdu) {
+            Ok(npdu) if !npdu.network_message => Some(npdu.payload),
+            Ok(_) => None,
+            Err(_) => return,
+        };
+
+        let result = match apdu {
+            Some(apdu) if apdu.len() >= 3 && (apdu[0] >> 4) == 0 => {
+                // Confirmed request: [type/flags, max segs/apdu, invoke_id, ...]
+                let invoke_id = apdu[2];
+                self.transactions.remove(invoke_id, frame.dest_mac);
+                self.send_abort(invoke_id, frame.source_addr, AbortReason::Other)
+            }
+            _ if frame.dest_mac != 255 => {
+                let reject_npdu = self.build_reject_message_to_network(RejectReason::RouterBusy, self.mstp_network);
+                let bvlc = build_bvlc(&reject_npdu, false);
+                self.send_ip_packet(&bvlc, frame.source_addr)
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            warn!("Failed to notify {} of undelivered frame: {}", frame.source_addr, e);
+        }
     }
 
     /// Send an Abort PDU to the IP client for a timed-out transaction
@@ -634,32 +2292,39 @@ impl BacnetGateway {
         &mut self,
         tx: &PendingTransaction,
         reason: AbortReason,
+    ) -> Result<(), GatewayError> {
+        self.send_abort(tx.invoke_id, tx.source_addr, reason)
+    }
+
+    /// Send an Abort PDU for `invoke_id` to an IP client
+    fn send_abort(
+        &mut self,
+        invoke_id: u8,
+        dest: SocketAddr,
+        reason: AbortReason,
     ) -> Result<(), GatewayError> {
         // Build Abort APDU
         let abort_apdu = Apdu::Abort {
             server: true,  // Gateway is acting as server (forwarding abort)
-            invoke_id: tx.invoke_id,
+            invoke_id,
             abort_reason: reason as u8,
         };
 
         let apdu_bytes = abort_apdu.encode();
 
         // Build NPDU (simple local response, no routing info needed)
-        let mut npdu = Vec::with_capacity(apdu_bytes.len() + 2);
-        npdu.push(0x01); // NPDU version
-        npdu.push(0x00); // Control: no routing info, expecting reply = false
-        npdu.extend_from_slice(&apdu_bytes);
+        let npdu = Npdu::local(&apdu_bytes).encode();
 
         // Build BVLC wrapper (Original-Unicast-NPDU)
         let bvlc = build_bvlc(&npdu, false);
 
         // Send to original client
         debug!(
-            "Sending timeout Abort to {}: invoke_id={} reason={:?}",
-            tx.source_addr, tx.invoke_id, reason
+            "Sending Abort to {}: invoke_id={} reason={:?}",
+            dest, invoke_id, reason
         );
 
-        self.send_ip_packet(&bvlc, tx.source_addr)
+        self.send_ip_packet(&bvlc, dest)
     }
 
     /// Get transaction table statistics
@@ -672,6 +2337,42 @@ impl BacnetGateway {
         self.transactions.len()
     }
 
+    /// Active transactions for the web UI, oldest first
+    pub fn transaction_summaries(&self) -> Vec<TransactionSummary> {
+        let mut summaries: Vec<TransactionSummary> = self
+            .transactions
+            .iter()
+            .map(|tx| TransactionSummary {
+                invoke_id: tx.invoke_id,
+                service: tx.service,
+                client: tx.source_addr,
+                dest_network: tx.dest_network,
+                dest_mac: tx.dest_mac,
+                sent_at: tx.created_at,
+                timeout: tx.timeout,
+                retries: tx.retries,
+                max_retries: tx.max_retries,
+                segmented: tx.segmented,
+            })
+            .collect();
+        summaries.sort_by_key(|s| s.sent_at);
+        summaries
+    }
+
+    /// Abort a stuck transaction from the web UI
+    ///
+    /// Drops it from the table and sends an Abort to the waiting IP client so
+    /// it stops retrying. Returns false if the transaction no longer exists.
+    pub fn abort_transaction(&mut self, invoke_id: u8, dest_mac: u8) -> bool {
+        let Some(tx) = self.transactions.abort(invoke_id, dest_mac) else {
+            return false;
+        };
+        if let Err(e) = self.send_abort_to_client(&tx, AbortReason::Other) {
+            warn!("Failed to abort transaction to {}: {}", tx.source_addr, e);
+        }
+        true
+    }
+
     /// Process a segmented request from IP and reassemble
     ///
     /// Returns:
@@ -818,6 +2519,12 @@ impl BacnetGateway {
         negative: bool,
         dest: SocketAddr,
     ) -> Result<(), GatewayError> {
+        // Some clients lose segments when granted their full proposed window
+        let window_size = match self.compat_quirks(dest).max_segment_window {
+            Some(limit) => window_size.min(limit),
+            None => window_size,
+        };
+
         // Build SegmentAck APDU
         let segment_ack = Apdu::SegmentAck {
             negative,
@@ -830,10 +2537,7 @@ impl BacnetGateway {
         let apdu_bytes = segment_ack.encode();
 
         // Build NPDU (simple local response)
-        let mut npdu = Vec::with_capacity(apdu_bytes.len() + 2);
-        npdu.push(0x01); // NPDU version
-        npdu.push(0x00); // Control: no routing info
-        npdu.extend_from_slice(&apdu_bytes);
+        let npdu = Npdu::local(&apdu_bytes).encode();
 
         // Build BVLC wrapper
         let bvlc = build_bvlc(&npdu, false);
@@ -870,6 +2574,22 @@ impl BacnetGateway {
         self.segmentation.active_reassemblies()
     }
 
+    /// Segmented requests being reassembled, for the web UI, oldest first
+    pub fn reassembly_summaries(&self) -> Vec<ReassemblySummary> {
+        let mut summaries: Vec<ReassemblySummary> = self
+            .segmented_request_info
+            .iter()
+            .map(|(invoke_id, info)| ReassemblySummary {
+                invoke_id: *invoke_id,
+                service_choice: info.service_choice,
+                client: info.source_addr,
+                started_at: info.created_at,
+            })
+            .collect();
+        summaries.sort_by_key(|s| s.started_at);
+        summaries
+    }
+
     /// Handle incoming Segment-ACK (marks segments as acknowledged)
     pub fn handle_segment_ack(&mut self, invoke_id: u8, sequence_number: u8, negative: bool) {
         if negative {
@@ -977,9 +2697,12 @@ impl BacnetGateway {
 
     /// Route a frame from MS/TP to IP
     ///
-    /// Returns `Ok(None)` on success, or `Ok(Some((reject_npdu, dest_addr)))` if a reject
-    /// message should be sent back to the MS/TP source.
+    /// Returns `Ok(None)` on success, or `Ok(Some((npdu, dest_addr)))` for a frame that
+    /// goes back out on MS/TP: a reject to the source, or a relay to a router on the trunk.
     pub fn route_from_mstp(&mut self, data: &[u8], source_addr: u8) -> Result<Option<(Vec<u8>, u8)>, GatewayError> {
+        if self.standby {
+            return Ok(None);
+        }
         if data.len() < 2 {
             warn!(
                 "Malformed packet from MS/TP {}: too short ({} bytes) - {}",
@@ -991,9 +2714,23 @@ impl BacnetGateway {
             return Err(GatewayError::InvalidFrame);
         }
 
-        // Parse NPDU
-        let (npdu, _npdu_len) = match parse_npdu(data) {
-            Ok(result) => result,
+        if !self.mstp_port_enabled {
+            trace!("MS/TP port disabled - dropping frame from MS/TP {}", source_addr);
+            self.stats.port_disabled_drops += 1;
+            return Ok(None);
+        }
+
+        // Parse NPDU, after repairing known legacy deviations in lenient mode
+        let repaired;
+        let data: &[u8] = match self.repair_npdu(data, format_args!("MS/TP {}", source_addr)) {
+            Some(npdu) => {
+                repaired = npdu;
+                &repaired
+            }
+            None => data,
+        };
+        let npdu = match Npdu::decode(data) {
+            Ok(npdu) => npdu,
             Err(e) => {
                 warn!(
                     "Failed to parse NPDU from MS/TP {}: {} - {}",
@@ -1002,28 +2739,26 @@ impl BacnetGateway {
                     hex_dump(data, 64)
                 );
                 self.stats.routing_errors += 1;
-                return Err(e);
+                return Err(e.into());
             }
         };
 
         // Validate hop count before routing (ASHRAE 135 Clause 6.2.2)
         // If hop count reaches 0, message must be discarded
-        if let Some(hop_count) = npdu.hop_count {
-            if hop_count < MIN_HOP_COUNT {
-                warn!(
-                    "Discarding message from MS/TP {}: hop count exhausted (was {}) - {}",
-                    source_addr,
-                    hop_count,
-                    hex_dump(data, 32)
-                );
-                self.stats.routing_errors += 1;
-                return Err(GatewayError::HopCountExhausted);
-            }
+        if npdu.hop_count < MIN_HOP_COUNT {
+            warn!(
+                "Discarding message from MS/TP {}: hop count exhausted (was {}) - {}",
+                source_addr,
+                npdu.hop_count,
+                hex_dump(data, 32)
+            );
+            self.stats.routing_errors += 1;
+            return Err(GatewayError::HopCountExhausted);
         }
 
-        info!(
-            "MS/TP->IP route: src_mac={} network_msg={} dest_present={} hop_count={:?}",
-            source_addr, npdu.network_message, npdu.destination_present, npdu.hop_count
+        trace!(
+            "MS/TP->IP route: src_mac={} network_msg={} dest_present={} hop_count={}",
+            source_addr, npdu.network_message, npdu.destination.is_some(), npdu.hop_count
         );
 
         // Handle network layer messages (Who-Is-Router-To-Network, etc.)
@@ -1031,14 +2766,65 @@ impl BacnetGateway {
             return self.handle_network_message_from_mstp(data, &npdu, source_addr)
                 .map(|()| None);
         }
+        self.analyze(TalkerAddress::Mstp(source_addr), npdu.payload);
+
+        // IP port disabled: unicasts are rejected so the source doesn't wait, broadcasts dropped
+        if !self.ip_port_enabled {
+            self.stats.port_disabled_drops += 1;
+            return match npdu.destination {
+                Some(ref dest) if dest.network != 0xFFFF && !dest.address.is_empty() => {
+                    debug!("IP port disabled - rejecting frame from MS/TP {} for network {}", source_addr, dest.network);
+                    let reject_npdu = self.build_reject_message_to_network(RejectReason::RouterBusy, dest.network);
+                    Ok(Some((reject_npdu, source_addr)))
+                }
+                _ => Ok(None),
+            };
+        }
+
+        // Answers to ReadProperty/WriteProperty calls the gateway made for an RPM or WPM proxy
+        let apdu_data = npdu.payload;
+        if npdu.destination.is_none() {
+            if let Some(index) = self.rpm_proxies.iter().position(|p| p.matches(source_addr, apdu_data)) {
+                return Ok(self.continue_rpm_proxy(index, apdu_data));
+            }
+            if let Some(index) = self.wpm_proxies.iter().position(|p| p.matches(source_addr, apdu_data)) {
+                return Ok(self.continue_wpm_proxy(index, apdu_data));
+            }
+            if let Some(job) = self.inventory.as_mut().filter(|job| job.matches(source_addr, apdu_data)) {
+                job.record(apdu_data);
+                return Ok(self.poll_inventory());
+            }
+            if let Some(call) = self.client_call.as_mut().filter(|call| call.matches(source_addr, apdu_data)) {
+                call.record(apdu_data);
+                return Ok(None);
+            }
+            if self.device_names.matches(NameTarget::Mstp(source_addr), apdu_data) {
+                self.device_names.record(apdu_data, Instant::now());
+                return Ok(None);
+            }
+        }
+        if let Some(request) = self.proxy_rejected_request(apdu_data, source_addr) {
+            return Ok(Some((request, source_addr)));
+        }
 
         // Parse APDU for transaction tracking and response routing
-        let apdu_data = &data[_npdu_len..];
         let mut response_dest: Option<SocketAddr> = None;
+        let mut is_discovery_reply = false;
 
         if !apdu_data.is_empty() {
             match parse_apdu(apdu_data) {
                 Ok(apdu_info) => {
+                    is_discovery_reply = apdu_info.apdu_type == ApduTypeClass::UnconfirmedRequest
+                        && matches!(apdu_info.service, Some(SERVICE_I_AM) | Some(SERVICE_I_HAVE));
+
+                    if apdu_info.apdu_type == ApduTypeClass::UnconfirmedRequest && apdu_info.service == Some(SERVICE_I_AM) {
+                        let (network, mac) = match npdu.source {
+                            Some(ref src) => (src.network, src.address.clone()),
+                            None => (self.mstp_network, vec![source_addr]),
+                        };
+                        self.learn_device_binding(apdu_data, network, mac, true);
+                    }
+
                     // Check if this is a response to a confirmed request
                     if apdu_info.is_response() {
                         if let Some(invoke_id) = apdu_info.invoke_id {
@@ -1069,6 +2855,9 @@ impl BacnetGateway {
                                         transaction.created_at.elapsed().as_secs_f32(),
                                         is_segmented_response
                                     );
+                                    let success = matches!(apdu_info.apdu_type, ApduTypeClass::SimpleAck | ApduTypeClass::ComplexAck);
+                                    self.sla.record_answer(source_addr, transaction.created_at.elapsed(), success);
+                                    self.service_stats.record_answer(transaction.service as u8, apdu_data, source_addr, Instant::now());
                                     response_dest = Some(transaction.source_addr);
                                 } else {
                                     // No matching transaction - will fall back to broadcast routing
@@ -1089,16 +2878,45 @@ impl BacnetGateway {
         }
 
         // Determine destination - use transaction-based routing if available
+        // (via_router: unicast to a next-hop router, so DNET/DADR must be kept)
+        let mut via_router = false;
         let dest_addr = if let Some(unicast_dest) = response_dest {
             // Response routing: send directly to original requester
             unicast_dest
         } else if let Some(ref dest) = npdu.destination {
-            if dest.network == self.ip_network {
+            if dest.network == self.ip_network && dest.address.is_empty() {
+                // Remote broadcast on the IP network (DLEN=0)
+                self.get_broadcast_address()
+            } else if dest.network == self.ip_network {
                 // Specific device on IP network
                 self.resolve_ip_address(&dest.address)?
             } else if dest.network == 0xFFFF {
                 // Global broadcast
                 self.get_broadcast_address()
+            } else if let Some(next_hop) = self.static_route_for(dest.network) {
+                self.recent_forwards.insert(dest.network, Instant::now());
+                match next_hop {
+                    RouteNextHop::Ip(router) => {
+                        debug!("Static route: network {} via IP router {}", dest.network, router);
+                        via_router = true;
+                        router
+                    }
+                    RouteNextHop::Mstp(mac) => {
+                        // Next hop shares the trunk with the source - relay with one
+                        // hop spent, so a loop between routers on it dies out
+                        let relayed = Npdu { hop_count: npdu.hop_count - 1, ..npdu.clone() };
+                        if relayed.hop_count == 0 {
+                            warn!(
+                                "Discarding relay from MS/TP {} to network {} via MS/TP router {}: hop count exhausted",
+                                source_addr, dest.network, mac
+                            );
+                            self.stats.routing_errors += 1;
+                            return Err(GatewayError::HopCountExhausted);
+                        }
+                        debug!("Static route: network {} via MS/TP router {}", dest.network, mac);
+                        return Ok(Some((relayed.encode(), mac)));
+                    }
+                }
             } else {
                 // Unknown network - send Reject-Message-To-Network back to source
                 warn!(
@@ -1125,7 +2943,7 @@ impl BacnetGateway {
 
         // Determine if this is a broadcast or unicast
         let is_broadcast = match dest_addr.ip() {
-            IpAddr::V4(ipv4) => ipv4.is_broadcast() || ipv4.octets()[3] == 255,
+            IpAddr::V4(ipv4) => ipv4.is_broadcast() || ipv4.is_multicast() || ipv4.octets()[3] == 255,
             IpAddr::V6(ipv6) => ipv6.is_multicast(),
         };
 
@@ -1133,36 +2951,61 @@ impl BacnetGateway {
         // For unicast responses going directly to IP client: final_delivery = true
         // This strips DNET/DADR per ASHRAE 135 - the destination is the UDP endpoint itself
         // For broadcasts: final_delivery = false (may be re-routed by other routers)
-        let final_delivery = !is_broadcast;
-        let routed_npdu = build_routed_npdu(
-            data,
-            self.mstp_network,
-            &[source_addr],
-            &npdu,
-            final_delivery,
-        )?;
-        let bvlc = self.build_original_npdu(&routed_npdu, is_broadcast);
+        let final_delivery = !is_broadcast && !via_router;
+        let routed_npdu = npdu.routed(self.mstp_network, &[source_addr], final_delivery).encode();
+        if routed_npdu.len() > IP_MAX_NPDU {
+            warn!(
+                "NPDU from MS/TP {} too long for B/IP: {} > {} bytes - rejecting",
+                source_addr, routed_npdu.len(), IP_MAX_NPDU
+            );
+            self.stats.messages_too_long += 1;
+            let network = npdu.destination.as_ref().map(|d| d.network).unwrap_or(self.ip_network);
+            let reject_npdu = self.build_reject_message_to_network(RejectReason::MessageTooLong, network);
+            return Ok(Some((reject_npdu, source_addr)));
+        }
+        let local_addr = SocketAddr::new(IpAddr::V4(self.local_ip), self.local_port);
+        let bvlc = if is_broadcast {
+            self.build_original_npdu(&routed_npdu, true)
+        } else {
+            let quirks = self.compat_quirks(dest_addr);
+            let stripped;
+            let npdu_out = if final_delivery && quirks.strip_sadr {
+                stripped = strip_source(&routed_npdu);
+                &stripped
+            } else {
+                &routed_npdu
+            };
+            if quirks.forwarded_npdu {
+                self.build_forwarded_npdu(npdu_out, local_addr)
+            } else {
+                self.build_original_npdu(npdu_out, false)
+            }
+        };
 
         // Send via IP
-        info!("MS/TP->IP SEND: {} bytes to {} (BVLC: {:02X?})",
-              bvlc.len(), dest_addr, &bvlc[..bvlc.len().min(20)]);
-        self.send_ip_packet(&bvlc, dest_addr)?;
+        trace!("MS/TP->IP SEND to {}: {}", dest_addr, hex_dump(&bvlc, 20));
+        if dest_addr == self.get_broadcast_address() {
+            self.send_ip_broadcast(&bvlc)?;
+        } else {
+            self.send_ip_packet(&bvlc, dest_addr)?;
+        }
 
         // Also forward to registered foreign devices and BDT entries if this is a broadcast
-        let is_broadcast_or_multicast = match dest_addr.ip() {
-            IpAddr::V4(ipv4) => ipv4.is_broadcast() || ipv4.is_multicast(),
-            IpAddr::V6(ipv6) => ipv6.is_multicast(),
-        };
-        if is_broadcast_or_multicast {
-            self.forward_to_foreign_devices(&bvlc)?;
-            // Forward to BDT entries - use local IP as source for Forwarded-NPDU
-            let local_addr = SocketAddr::new(IpAddr::V4(self.local_ip), self.local_port);
+        // (directed subnet broadcasts included, not just 255.255.255.255)
+        if is_broadcast {
+            // Forward to foreign devices and BDT entries - use local IP as source for Forwarded-NPDU
+            self.forward_to_foreign_devices(&routed_npdu, local_addr)?;
             self.forward_to_bdt_entries(&routed_npdu, local_addr)?;
+        } else if is_discovery_reply {
+            // Unicast discovery reply - make sure foreign devices that asked via
+            // Distribute-Broadcast still see it
+            self.forward_to_fd_origins(&routed_npdu, local_addr, dest_addr)?;
         }
 
         self.stats.mstp_to_ip_packets += 1;
         self.stats.mstp_to_ip_bytes += bvlc.len() as u64;
         let now = Instant::now();
+        self.talkers.record(TalkerAddress::Mstp(source_addr), TalkerAddress::Ip(dest_addr), bvlc.len(), now);
         self.stats.last_activity = Some(now);
         self.stats.last_mstp_activity = Some(now);
 
@@ -1171,12 +3014,61 @@ impl BacnetGateway {
 
     /// Get the broadcast address for the local subnet
     /// Uses directed broadcast (subnet broadcast) instead of limited broadcast (255.255.255.255)
-    /// for better compatibility with routers and firewalls
+    /// for better compatibility with routers and firewalls, or the configured
+    /// multicast group when B/IP multicast (Annex J.7) is enabled
     fn get_broadcast_address(&self) -> SocketAddr {
+        if let Some(group) = self.multicast_group {
+            return SocketAddr::new(IpAddr::V4(group), self.local_port);
+        }
         let broadcast = Self::calculate_broadcast_address(self.local_ip, self.subnet_mask);
         SocketAddr::new(IpAddr::V4(broadcast), self.local_port)
     }
 
+    /// Send a broadcast BVLC on the IP side in the configured form(s)
+    ///
+    /// A multicast group replaces both broadcast forms; with broadcasts
+    /// disabled only the supervisory stations are sent to. Each supervisory
+    /// station gets its own unicast copy as a Forwarded-NPDU, so it treats the
+    /// message as the broadcast it was; refused copies are counted per station
+    /// rather than failing the broadcast.
+    fn send_ip_broadcast(&mut self, bvlc: &[u8]) -> Result<(), GatewayError> {
+        if self.broadcast_form != BroadcastForm::Disabled {
+            let use_limited = self.multicast_group.is_none() && self.broadcast_form != BroadcastForm::Directed;
+            if !use_limited || self.broadcast_form == BroadcastForm::Both {
+                let broadcast = self.get_broadcast_address();
+                self.send_ip_packet(bvlc, broadcast)?;
+            }
+            if use_limited {
+                let limited = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), self.local_port);
+                self.send_ip_packet(bvlc, limited)?;
+            }
+            if let Some((ap_ip, ap_mask)) = self.ap_interface.filter(|_| self.multicast_group.is_none()) {
+                let ap_broadcast = Self::calculate_broadcast_address(ap_ip, ap_mask);
+                self.send_ip_packet(bvlc, SocketAddr::new(IpAddr::V4(ap_broadcast), self.local_port))?;
+            }
+        }
+        if self.supervisory_stations.is_empty() {
+            return Ok(());
+        }
+
+        let forwarded;
+        let copy = if bvlc.get(1) == Some(&BVLC_ORIGINAL_BROADCAST) && bvlc.len() >= 4 {
+            let local_addr = SocketAddr::new(IpAddr::V4(self.local_ip), self.local_port);
+            forwarded = self.build_forwarded_npdu(&bvlc[4..], local_addr);
+            &forwarded
+        } else {
+            bvlc
+        };
+        for index in 0..self.supervisory_stations.len() {
+            let address = self.supervisory_stations[index].address;
+            match self.send_ip_packet(copy, address) {
+                Ok(()) => self.supervisory_stations[index].sent += 1,
+                Err(_) => self.supervisory_stations[index].failed += 1,
+            }
+        }
+        Ok(())
+    }
+
     /// Build a Forwarded-NPDU BVLC message (ASHRAE 135 Annex J.4.5)
     ///
     /// Per ASHRAE 135 Annex J.4.5, Forwarded-NPDU messages MUST contain the
@@ -1251,29 +3143,34 @@ impl BacnetGateway {
         result
     }
 
-    /// Send a packet via IP socket
+    /// Send a BVLC message via the IP data link
     fn send_ip_packet(&mut self, data: &[u8], dest: SocketAddr) -> Result<(), GatewayError> {
-        if let Some(ref socket) = self.ip_socket {
-            match socket.send_to(data, dest) {
-                Ok(bytes_sent) => {
-                    debug!("IP TX: sent {} bytes to {}", bytes_sent, dest);
-                    Ok(())
-                }
-                Err(e) => {
-                    warn!("IP TX failed to {}: {}", dest, e);
-                    Err(GatewayError::IoError(e.to_string()))
+        if self.standby {
+            return Ok(());
+        }
+        if let Some(link) = self.ip_link.as_mut() {
+            link.send_raw(data, LinkAddress::Ip(dest)).map_err(|e| {
+                // Queue overflows are counted by the link; logging each one would add to the stall
+                match e {
+                    DataLinkError::QueueFull => debug!("IP TX queue full, dropped packet for {}", dest),
+                    _ => warn!("IP TX failed to {}: {}", dest, e),
                 }
-            }
+                GatewayError::IoError(e.to_string())
+            })?;
+            self.peers.record_sent(dest, data.len());
+            Ok(())
         } else {
-            // Queue for later - this shouldn't happen after set_ip_socket is called
-            warn!("IP socket not set! Queuing packet for {} (queue_len={})", dest, self.ip_send_queue.len() + 1);
+            // Queue for later - this shouldn't happen after set_ip_link is called
+            warn!("IP link not set! Queuing packet for {} (queue_len={})", dest, self.ip_send_queue.len() + 1);
             self.ip_send_queue.push((data.to_vec(), dest));
+            self.peers.record_sent(dest, data.len());
             Ok(())
         }
     }
 
     /// Forward a broadcast message to all registered foreign devices
-    fn forward_to_foreign_devices(&mut self, data: &[u8]) -> Result<(), GatewayError> {
+    /// Sends Forwarded-NPDU messages per ASHRAE 135 Annex J.4.5
+    fn forward_to_foreign_devices(&mut self, npdu_data: &[u8], source_addr: SocketAddr) -> Result<(), GatewayError> {
         // Remove expired entries first
         self.foreign_device_table.retain(|addr, entry| {
             let keep = !entry.is_expired();
@@ -1283,11 +3180,57 @@ impl BacnetGateway {
             keep
         });
 
-        // Forward to each foreign device
-        for entry in self.foreign_device_table.values() {
-            if let Some(ref socket) = self.ip_socket {
-                if let Err(e) = socket.send_to(data, entry.address) {
-                    warn!("Failed to forward to foreign device {}: {}", entry.address, e);
+        if self.foreign_device_table.is_empty() {
+            return Ok(());
+        }
+
+        // Build Forwarded-NPDU with original source address
+        let forwarded = self.build_forwarded_npdu(npdu_data, source_addr);
+
+        // Forward to each foreign device
+        for entry in self.foreign_device_table.values_mut() {
+            if let Some(link) = self.ip_link.as_mut() {
+                match link.send_raw(&forwarded, LinkAddress::Ip(entry.address)) {
+                    Ok(_) => entry.forwarded += 1,
+                    Err(e) => warn!("Failed to forward to foreign device {}: {}", entry.address, e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Forward a unicast discovery reply to foreign devices whose Distribute-Broadcast
+    /// is still within FD_REPLY_WINDOW (excluding the reply's own destination)
+    fn forward_to_fd_origins(
+        &mut self,
+        npdu_data: &[u8],
+        source_addr: SocketAddr,
+        exclude: SocketAddr,
+    ) -> Result<(), GatewayError> {
+        // Drop origins that aged out or are no longer registered
+        let fdt = &self.foreign_device_table;
+        self.fd_broadcast_origins.retain(|addr, at| {
+            at.elapsed() < FD_REPLY_WINDOW && fdt.get(addr).is_some_and(|e| !e.is_expired())
+        });
+
+        if self.fd_broadcast_origins.is_empty() {
+            return Ok(());
+        }
+
+        let forwarded = self.build_forwarded_npdu(npdu_data, source_addr);
+        for addr in self.fd_broadcast_origins.keys() {
+            if *addr == exclude {
+                continue;
+            }
+            if let Some(link) = self.ip_link.as_mut() {
+                match link.send_raw(&forwarded, LinkAddress::Ip(*addr)) {
+                    Ok(_) => {
+                        debug!("Forwarded discovery reply to foreign device {}", addr);
+                        if let Some(entry) = self.foreign_device_table.get_mut(addr) {
+                            entry.forwarded += 1;
+                        }
+                    }
+                    Err(e) => warn!("Failed to forward reply to foreign device {}: {}", addr, e),
                 }
             }
         }
@@ -1306,8 +3249,8 @@ impl BacnetGateway {
 
         // Forward to each BDT entry
         for entry in &self.broadcast_distribution_table {
-            if let Some(ref socket) = self.ip_socket {
-                if let Err(e) = socket.send_to(&forwarded, entry.address) {
+            if let Some(link) = self.ip_link.as_mut() {
+                if let Err(e) = link.send_raw(&forwarded, LinkAddress::Ip(entry.address)) {
                     warn!("Failed to forward to BDT entry {}: {}", entry.address, e);
                 } else {
                     trace!("Forwarded broadcast to BDT entry: {}", entry.address);
@@ -1321,15 +3264,29 @@ impl BacnetGateway {
     fn handle_network_message_from_mstp(
         &mut self,
         data: &[u8],
-        npdu: &NpduInfo,
+        npdu: &Npdu,
         _source_addr: u8,
     ) -> Result<(), GatewayError> {
-        let (_, npdu_len) = parse_npdu(data)?;
+        let npdu_len = npdu.header_len();
         if npdu_len >= data.len() {
             return Err(GatewayError::InvalidFrame);
         }
 
         let msg_type = data[npdu_len];
+        if msg_type == NL_REJECT_MESSAGE_TO_NETWORK {
+            self.note_reject_received(data, npdu_len, format!("MS/TP {}", _source_addr));
+        }
+        if msg_type == NL_I_AM_ROUTER_TO_NETWORK {
+            self.note_network_probe_answer(format!("MS/TP {}", _source_addr), &data[npdu_len + 1..]);
+        }
+        if msg_type == NL_WHAT_IS_NETWORK_NUMBER || msg_type == NL_NETWORK_NUMBER_IS {
+            let sender = format!("MS/TP {}", _source_addr);
+            let body = &data[npdu_len + 1..];
+            if let Some(reply) = self.handle_network_number_message(msg_type, body, npdu.source.is_some(), false, &sender) {
+                self.send_on_mstp(reply, 0xFF);
+            }
+            return Ok(());
+        }
 
         match msg_type {
             NL_WHO_IS_ROUTER_TO_NETWORK => {
@@ -1343,40 +3300,40 @@ impl BacnetGateway {
 
                 debug!("  Requested network: {:?}, our IP network: {}", requested_network, self.ip_network);
 
+                // An IP network number not learned yet is never claimed
+                let ip_network = self.ip_network_number().known();
                 let is_our_network = requested_network.is_none()
-                    || requested_network == Some(self.ip_network)
+                    || (ip_network.is_some() && requested_network == ip_network)
                     || requested_network == Some(self.mstp_network)
                     || requested_network == Some(0xFFFF);
 
                 if is_our_network {
                     // Respond with I-Am-Router-To-Network for both our networks
                     // Response is broadcast on IP to reach the original requester
-                    let response = self.build_i_am_router_to_network(&[self.ip_network, self.mstp_network]);
+                    let networks: Vec<u16> = ip_network.into_iter().chain([self.mstp_network]).collect();
+                    let response = self.build_i_am_router_to_network(&networks);
                     let bvlc = build_bvlc(&response, true);
-                    let broadcast = self.get_broadcast_address();
-                    self.send_ip_packet(&bvlc, broadcast)?;
-                    debug!("  Sent I-Am-Router-To-Network: networks {:?}", [self.ip_network, self.mstp_network]);
+                    self.send_ip_broadcast(&bvlc)?;
+                    debug!("  Sent I-Am-Router-To-Network: networks {:?}", networks);
                 }
 
                 // Forward to IP network for other routers to respond (6.5.3)
                 // This allows routers on the IP side to respond if they know the network
                 if requested_network.is_none() || !is_our_network {
                     debug!("  Forwarding Who-Is-Router-To-Network to IP for other routers");
-                    let routed_npdu = build_routed_npdu(data, self.mstp_network, &[_source_addr], npdu, false)?;
+                    let routed_npdu = npdu.routed(self.mstp_network, &[_source_addr], false).encode();
                     let gateway_addr = SocketAddr::new(IpAddr::V4(self.local_ip), self.local_port);
                     let bvlc = self.build_forwarded_npdu(&routed_npdu, gateway_addr);
-                    let dest = self.get_broadcast_address();
-                    self.send_ip_packet(&bvlc, dest)?;
+                    self.send_ip_broadcast(&bvlc)?;
                 }
             }
             _ => {
                 // Forward other network messages to IP side
-                let routed_npdu = build_routed_npdu(data, self.mstp_network, &[_source_addr], npdu, false)?;
+                let routed_npdu = npdu.routed(self.mstp_network, &[_source_addr], false).encode();
                 // For MS/TP->IP routing, use gateway's IP as source (MS/TP devices have no IP)
                 let gateway_addr = SocketAddr::new(IpAddr::V4(self.local_ip), self.local_port);
                 let bvlc = self.build_forwarded_npdu(&routed_npdu, gateway_addr);
-                let dest = self.get_broadcast_address();
-                self.send_ip_packet(&bvlc, dest)?;
+                self.send_ip_broadcast(&bvlc)?;
             }
         }
         Ok(())
@@ -1384,10 +3341,103 @@ impl BacnetGateway {
 
     /// Route a frame from IP to MS/TP
     /// Returns the data and destination address for MS/TP
+    ///
+    /// An NPDU longer than the MS/TP max NPDU cannot be split by a router, so
+    /// it is answered with Reject-Message-To-Network (MessageTooLong) instead
+    /// of being handed to the driver. The same applies (RouterBusy) while the
+    /// MS/TP port is administratively disabled.
+    ///
+    /// RPMs and WPMs for devices known to lack them are carried out by the
+    /// gateway itself; the returned NPDU is then its first ReadProperty or
+    /// WriteProperty.
     pub fn route_from_ip(
         &mut self,
         data: &[u8],
         source_addr: SocketAddr,
+    ) -> Result<Option<(Vec<u8>, u8)>, GatewayError> {
+        if self.standby {
+            trace!("Standing by - dropping packet from {}", source_addr);
+            return Ok(None);
+        }
+        if !self.ip_port_enabled {
+            trace!("IP port disabled - dropping packet from {}", source_addr);
+            self.stats.port_disabled_drops += 1;
+            return Ok(None);
+        }
+
+        self.peers.record_received(source_addr, data.len(), Instant::now());
+        let routed = match self.route_ip_frame(data, source_addr) {
+            Ok(routed) => routed,
+            Err(e) => {
+                if !matches!(e, GatewayError::IoError(_)) {
+                    self.peers.record_error(source_addr);
+                }
+                return Err(e);
+            }
+        };
+        let Some((npdu, mstp_dest)) = routed else {
+            return Ok(None);
+        };
+
+        let reason = if !self.mstp_port_enabled {
+            debug!("MS/TP port disabled - not routing {} bytes from {} to MS/TP {}", npdu.len(), source_addr, mstp_dest);
+            self.stats.port_disabled_drops += 1;
+            RejectReason::RouterBusy
+        } else if npdu.len() > self.mstp_max_npdu {
+            warn!(
+                "NPDU from {} too long for MS/TP {}: {} > {} bytes - rejecting",
+                source_addr, mstp_dest, npdu.len(), self.mstp_max_npdu
+            );
+            self.stats.messages_too_long += 1;
+            RejectReason::MessageTooLong
+        } else {
+            if self.backpressure_active {
+                if let Some(invoke_id) = confirmed_invoke_id(&npdu) {
+                    // Tell the client now rather than letting the request time out in the queue
+                    debug!("MS/TP queue backed up - aborting request {} from {} to MS/TP {}", invoke_id, source_addr, mstp_dest);
+                    self.transactions.remove(invoke_id, mstp_dest);
+                    self.stats.backpressure_aborts += 1;
+                    self.peers.record_rejected(source_addr);
+                    self.send_abort(invoke_id, source_addr, AbortReason::BufferOverflow)?;
+                    return Ok(None);
+                }
+            }
+            if confirmed_invoke_id(&npdu).is_some() {
+                self.peers.record_confirmed_request(source_addr);
+            }
+            if self.rpm_unsupported.contains(&mstp_dest) {
+                if let Some(request) = self.start_rpm_proxy(&npdu, mstp_dest, source_addr) {
+                    return Ok(Some((request, mstp_dest)));
+                }
+            }
+            if self.wpm_unsupported.contains(&mstp_dest) {
+                if let Some(request) = self.start_wpm_proxy(&npdu, mstp_dest, source_addr) {
+                    return Ok(Some((request, mstp_dest)));
+                }
+            }
+            return Ok(Some((npdu, mstp_dest)));
+        };
+
+        // The request will never reach the device: don't track or retry it
+        if let Some(invoke_id) = confirmed_invoke_id(&npdu) {
+            self.transactions.remove(invoke_id, mstp_dest);
+        }
+        if mstp_dest == 255 && reason == RejectReason::RouterBusy {
+            // Broadcasts are dropped silently
+            return Ok(None);
+        }
+        self.peers.record_rejected(source_addr);
+        let reject_npdu = self.build_reject_message_to_network(reason, self.mstp_network);
+        let bvlc = build_bvlc(&reject_npdu, false);
+        self.send_ip_packet(&bvlc, source_addr)?;
+        Ok(None)
+    }
+
+    /// Route a BVLC frame received on the IP port; see `route_from_ip`
+    fn route_ip_frame(
+        &mut self,
+        data: &[u8],
+        source_addr: SocketAddr,
     ) -> Result<Option<(Vec<u8>, u8)>, GatewayError> {
         if data.len() < 4 {
             warn!(
@@ -1447,6 +3497,9 @@ impl BacnetGateway {
             BVLC_DISTRIBUTE_BROADCAST => {
                 return self.handle_distribute_broadcast(data, source_addr);
             }
+            BVLC_SECURE_BVLL => {
+                return self.handle_secure_bvll(source_addr);
+            }
             _ => {}
         }
 
@@ -1484,9 +3537,17 @@ impl BacnetGateway {
             return Err(GatewayError::InvalidFrame);
         }
 
-        // Parse NPDU
-        let (npdu, _npdu_len) = match parse_npdu(npdu_data) {
-            Ok(result) => result,
+        // Parse NPDU, after repairing known legacy deviations in lenient mode
+        let repaired;
+        let npdu_data: &[u8] = match self.repair_npdu(npdu_data, format_args!("{}", source_addr)) {
+            Some(npdu) => {
+                repaired = npdu;
+                &repaired
+            }
+            None => npdu_data,
+        };
+        let npdu = match Npdu::decode(npdu_data) {
+            Ok(npdu) => npdu,
             Err(e) => {
                 warn!(
                     "Failed to parse NPDU from {}: {} - {}",
@@ -1495,42 +3556,79 @@ impl BacnetGateway {
                     hex_dump(npdu_data, 64)
                 );
                 self.stats.routing_errors += 1;
-                return Err(e);
+                return Err(e.into());
             }
         };
 
         // Validate hop count before routing (ASHRAE 135 Clause 6.2.2)
-        if let Some(hop_count) = npdu.hop_count {
-            if hop_count < MIN_HOP_COUNT {
-                warn!(
-                    "Discarding message from {}: hop count exhausted (was {}) - {}",
-                    source_addr,
-                    hop_count,
-                    hex_dump(npdu_data, 32)
-                );
-                self.stats.routing_errors += 1;
-                return Err(GatewayError::HopCountExhausted);
-            }
+        if npdu.hop_count < MIN_HOP_COUNT {
+            warn!(
+                "Discarding message from {}: hop count exhausted (was {}) - {}",
+                source_addr,
+                npdu.hop_count,
+                hex_dump(npdu_data, 32)
+            );
+            self.stats.routing_errors += 1;
+            return Err(GatewayError::HopCountExhausted);
         }
 
         debug!(
-            "Routing IP->MS/TP: src={} network_msg={} dest_present={} hop_count={:?}",
-            source_addr, npdu.network_message, npdu.destination_present, npdu.hop_count
+            "Routing IP->MS/TP: src={} network_msg={} dest_present={} hop_count={}",
+            source_addr, npdu.network_message, npdu.destination.is_some(), npdu.hop_count
         );
 
         // Handle network layer messages
         if npdu.network_message {
             return self.handle_network_message_from_ip(npdu_data, &npdu, source_addr);
         }
+        self.analyze(TalkerAddress::Ip(source_addr), npdu.payload);
+
+        // Stand down for our MS/TP network while another router claims its number
+        if self.is_mstp_routing_suppressed()
+            && npdu.destination.as_ref().is_some_and(|d| d.network == self.mstp_network)
+        {
+            debug!("Dropping IP->MS/TP message from {}: network {} is duplicated", source_addr, self.mstp_network);
+            return Ok(None);
+        }
 
         // Parse APDU for transaction tracking (after NPDU header)
-        let (_npdu_parsed, npdu_len) = parse_npdu(npdu_data)?;
-        let apdu_data = &npdu_data[npdu_len..];
+        let apdu_data = npdu.payload;
+
+        // Answer to a device name read the gateway made itself
+        if npdu.destination.is_none() && self.device_names.matches(NameTarget::Ip(source_addr), apdu_data) {
+            self.device_names.record(apdu_data, Instant::now());
+            return Ok(None);
+        }
 
         // Try to parse APDU and handle segmentation
         if !apdu_data.is_empty() {
             match parse_apdu(apdu_data) {
                 Ok(apdu_info) => {
+                    if apdu_info.apdu_type == ApduTypeClass::UnconfirmedRequest && apdu_info.service == Some(SERVICE_I_AM) {
+                        let (network, mac) = match npdu.source {
+                            Some(ref src) => (src.network, src.address.clone()),
+                            // Forwarded-NPDU carries the originating B/IP address
+                            None if bvlc_function == BVLC_FORWARDED_NPDU => (self.ip_network, data[4..10].to_vec()),
+                            None => (self.ip_network, ip_to_mac(&source_addr)),
+                        };
+                        self.learn_device_binding(apdu_data, network, mac, false);
+                    }
+
+                    // Ranged Who-Is for devices all known on IP: nothing on MS/TP will answer
+                    if self.whois_filter
+                        && apdu_info.apdu_type == ApduTypeClass::UnconfirmedRequest
+                        && apdu_info.service == Some(SERVICE_WHO_IS)
+                        && npdu.destination.as_ref().map_or(true, |d| d.network == self.mstp_network || d.network == 0xFFFF)
+                    {
+                        if let Some((low, high)) = who_is_range(apdu_data) {
+                            if self.whois_range_on_ip(low, high) {
+                                debug!("Who-Is {}..{} from {}: all devices on IP, not forwarding to MS/TP", low, high, source_addr);
+                                self.stats.whois_suppressed += 1;
+                                return Ok(None);
+                            }
+                        }
+                    }
+
                     // Handle segmented requests - buffer and reassemble
                     if apdu_info.segmented && apdu_info.apdu_type == ApduTypeClass::ConfirmedRequest {
                         if let Some(invoke_id) = apdu_info.invoke_id {
@@ -1544,7 +3642,7 @@ impl BacnetGateway {
                                 let service_choice = apdu_data[5];
                                 let segment_payload = &apdu_data[segment_header_len..];
 
-                                info!(
+                                debug!(
                                     "Segmented request: invoke_id={} seq={} service={} more_follows={} payload_len={}",
                                     invoke_id, sequence_number, service_choice, apdu_info.more_follows, segment_payload.len()
                                 );
@@ -1573,8 +3671,8 @@ impl BacnetGateway {
                                 ) {
                                     Ok(Some((complete_apdu, original_npdu))) => {
                                         // Reassembly complete - forward to MS/TP
-                                        // Parse original NPDU to get routing info
-                                        let (orig_npdu_info, orig_npdu_len) = parse_npdu(&original_npdu)?;
+                                        // Original NPDU routing info, carrying the reassembled APDU
+                                        let orig_npdu_info = Npdu { payload: &complete_apdu, ..Npdu::decode(&original_npdu)? };
 
                                         // Determine MS/TP destination
                                         let mstp_dest = if let Some(ref dest) = orig_npdu_info.destination {
@@ -1589,23 +3687,14 @@ impl BacnetGateway {
                                             255
                                         };
 
-                                        // Build new NPDU with reassembled APDU
-                                        // Create a synthetic "original data" with our complete APDU
-                                        let mut synthetic_npdu = original_npdu[..orig_npdu_len].to_vec();
-                                        synthetic_npdu.extend_from_slice(&complete_apdu);
-
                                         let final_delivery = orig_npdu_info.destination
                                             .as_ref()
                                             .map(|d| d.network == self.mstp_network || d.network == 0xFFFF)
                                             .unwrap_or(true);
 
-                                        let routed_npdu = build_routed_npdu(
-                                            &synthetic_npdu,
-                                            self.ip_network,
-                                            &ip_to_mac(&source_addr),
-                                            &orig_npdu_info,
-                                            final_delivery,
-                                        )?;
+                                        let routed_npdu = orig_npdu_info
+                                            .routed(self.ip_network, &ip_to_mac(&source_addr), final_delivery)
+                                            .encode();
 
                                         // Create transaction for the reassembled request
                                         if let Ok(service) = ConfirmedServiceChoice::try_from(complete_apdu[3]) {
@@ -1620,18 +3709,20 @@ impl BacnetGateway {
                                                 true, // Segmented request
                                                 routed_npdu.clone(), // Original NPDU for retry
                                             );
-                                            if let Err(e) = self.transactions.add(transaction) {
-                                                debug!("Failed to create transaction for reassembled request: {}", e);
+                                            match self.transactions.add(transaction) {
+                                                Ok(()) => self.service_stats.record_request(service as u8),
+                                                Err(e) => debug!("Failed to create transaction for reassembled request: {}", e),
                                             }
                                         }
 
                                         self.stats.ip_to_mstp_packets += 1;
                                         self.stats.ip_to_mstp_bytes += routed_npdu.len() as u64;
                                         let now = Instant::now();
+                                        self.talkers.record(TalkerAddress::Ip(source_addr), TalkerAddress::Mstp(mstp_dest), routed_npdu.len(), now);
                                         self.stats.last_activity = Some(now);
                                         self.stats.last_ip_activity = Some(now);
 
-                                        info!(
+                                        debug!(
                                             "Forwarding reassembled APDU to MS/TP: invoke_id={} dest={} len={}",
                                             invoke_id, mstp_dest, routed_npdu.len()
                                         );
@@ -1661,12 +3752,19 @@ impl BacnetGateway {
                                     if dest.address.is_empty() { 255 } else { dest.address[0] }
                                 } else if dest.network == 0xFFFF {
                                     255 // Global broadcast
+                                } else if let Some(RouteNextHop::Mstp(router)) = self.static_routes.get(&dest.network) {
+                                    *router // Remote network behind an MS/TP router
                                 } else {
                                     255 // Unknown network - will be rejected later
                                 }
                             } else {
                                 255 // No destination - local broadcast
                             };
+                            // Remote DNET is recorded so a Reject for it can abort the transaction
+                            let dest_network = npdu.destination.as_ref()
+                                .map(|d| d.network)
+                                .filter(|&n| n != 0xFFFF)
+                                .unwrap_or(self.mstp_network);
 
                             // Convert service code to ConfirmedServiceChoice
                             if let Ok(service) = ConfirmedServiceChoice::try_from(service_raw) {
@@ -1690,28 +3788,22 @@ impl BacnetGateway {
                                 // Only create transaction if message is for MS/TP network
                                 if mstp_dest > 0 {
                                     // Build routed NPDU now so we can store it
-                                    if let Ok(routed_npdu) = build_routed_npdu(
-                                        npdu_data,
-                                        self.ip_network,
-                                        &ip_to_mac(&source_addr),
-                                        &npdu,
-                                        final_delivery,
-                                    ) {
-                                        let transaction = PendingTransaction::new(
-                                            invoke_id,
-                                            source_addr,
-                                            npdu.source.as_ref().map(|s| s.network),
-                                            npdu.source.as_ref().map(|s| s.address.clone()).unwrap_or_default(),
-                                            self.mstp_network,
-                                            dest_mac,
-                                            service,
-                                            false, // Non-segmented
-                                            routed_npdu, // Original NPDU for retry
-                                        );
+                                    let routed_npdu = npdu.routed(self.ip_network, &ip_to_mac(&source_addr), final_delivery).encode();
+                                    let transaction = PendingTransaction::new(
+                                        invoke_id,
+                                        source_addr,
+                                        npdu.source.as_ref().map(|s| s.network),
+                                        npdu.source.as_ref().map(|s| s.address.clone()).unwrap_or_default(),
+                                        dest_network,
+                                        dest_mac,
+                                        service,
+                                        false, // Non-segmented
+                                        routed_npdu, // Original NPDU for retry
+                                    );
 
-                                        if let Err(e) = self.transactions.add(transaction) {
-                                            debug!("Failed to create transaction for invoke_id={}: {}", invoke_id, e);
-                                        }
+                                    match self.transactions.add(transaction) {
+                                        Ok(()) => self.service_stats.record_request(service as u8),
+                                        Err(e) => debug!("Failed to create transaction for invoke_id={}: {}", invoke_id, e),
                                     }
                                 }
                             }
@@ -1742,6 +3834,22 @@ impl BacnetGateway {
             } else if dest.network == self.ip_network {
                 // Message is for the IP network, not MS/TP - don't route
                 return Ok(None);
+            } else if let Some(next_hop) = self.static_route_for(dest.network) {
+                self.recent_forwards.insert(dest.network, Instant::now());
+                match next_hop {
+                    // Router on the MS/TP trunk - keep DNET/DADR for it
+                    RouteNextHop::Mstp(mac) => {
+                        debug!("Static route: network {} via MS/TP router {}", dest.network, mac);
+                        (mac, false)
+                    }
+                    RouteNextHop::Ip(router) => {
+                        debug!("Static route: network {} via IP router {}", dest.network, router);
+                        let routed_npdu = npdu.routed(self.ip_network, &ip_to_mac(&source_addr), false).encode();
+                        let bvlc = build_bvlc(&routed_npdu, false);
+                        self.send_ip_packet(&bvlc, router)?;
+                        return Ok(None);
+                    }
+                }
             } else {
                 // Unknown network - send Reject-Message-To-Network back to IP source
                 warn!(
@@ -1770,17 +3878,12 @@ impl BacnetGateway {
 
         // Build NPDU with source network info
         // final_delivery=true strips DNET/DADR per ASHRAE 135 Clause 6.2.2
-        let routed_npdu = build_routed_npdu(
-            npdu_data,
-            self.ip_network,
-            &ip_to_mac(&source_addr),
-            &npdu,
-            final_delivery,
-        )?;
+        let routed_npdu = npdu.routed(self.ip_network, &ip_to_mac(&source_addr), final_delivery).encode();
 
         self.stats.ip_to_mstp_packets += 1;
         self.stats.ip_to_mstp_bytes += routed_npdu.len() as u64;
         let now = Instant::now();
+        self.talkers.record(TalkerAddress::Ip(source_addr), TalkerAddress::Mstp(mstp_dest), routed_npdu.len(), now);
         self.stats.last_activity = Some(now);
         self.stats.last_ip_activity = Some(now);
 
@@ -1819,6 +3922,13 @@ impl BacnetGateway {
             source_addr, ttl_seconds
         );
 
+        if !self.accept_fd_registrations {
+            info!("Foreign device registrations disabled, rejecting {}", source_addr);
+            let result = self.build_bvlc_result(BVLC_RESULT_REGISTER_FD_NAK);
+            self.send_ip_packet(&result, source_addr)?;
+            return Ok(None);
+        }
+
         // Update or insert entry - using HashMap keyed by address prevents duplicates
         if let Some(entry) = self.foreign_device_table.get_mut(&source_addr) {
             // Re-registration: refresh TTL (fixes duplicate entry bug)
@@ -1897,6 +4007,32 @@ impl BacnetGateway {
         Ok(None)
     }
 
+    /// Handle a Secure-BVLL message (ASHRAE 135 Annex J security).
+    /// The gateway holds no B/IP security keys, so the message is counted
+    /// against its source and dropped silently, or refused with a
+    /// BVLC-Result NAK when so configured.
+    /// Annex J has no result code for this, so the NAK is opt-in.
+    fn handle_secure_bvll(&mut self, source_addr: SocketAddr) -> Result<Option<(Vec<u8>, u8)>, GatewayError> {
+        self.stats.secure_bvll_received += 1;
+        let ip = source_addr.ip();
+        if self.secure_bvll_sources.contains_key(&ip) || self.secure_bvll_sources.len() < MAX_SECURE_BVLL_SOURCES {
+            *self.secure_bvll_sources.entry(ip).or_insert(0) += 1;
+        }
+        let count = self.secure_bvll_sources.get(&ip).copied().unwrap_or(0);
+        if count == 1 {
+            warn!("Secure-BVLL from {} - B/IP security is not supported", source_addr);
+        } else {
+            debug!("Secure-BVLL from {} ({} so far)", source_addr, count);
+        }
+
+        if self.secure_bvll_drop {
+            return Ok(None);
+        }
+        let result = self.build_bvlc_result(BVLC_RESULT_SECURE_BVLL_NAK);
+        self.send_ip_packet(&result, source_addr)?;
+        Ok(None)
+    }
+
     /// Handle Read-Broadcast-Distribution-Table BVLC message (ASHRAE 135 Annex J.3)
     fn handle_read_bdt(&mut self, source_addr: SocketAddr) -> Result<Option<(Vec<u8>, u8)>, GatewayError> {
         debug!("Read-BDT request from {}", source_addr);
@@ -1926,6 +4062,13 @@ impl BacnetGateway {
             return Ok(None);
         }
 
+        if self.write_protected {
+            warn!("Write-BDT from {} refused - configuration is write-protected", source_addr);
+            let result = self.build_bvlc_result(BVLC_RESULT_WRITE_BDT_NAK);
+            self.send_ip_packet(&result, source_addr)?;
+            return Ok(None);
+        }
+
         // Each BDT entry is 10 bytes: 4 IP + 2 port + 4 mask
         let entry_data = &data[4..];
         if entry_data.len() % 10 != 0 {
@@ -2013,11 +4156,13 @@ impl BacnetGateway {
 
         let npdu_data = &data[4..];
 
+        // Remember the origin so unicast discovery replies can find their way back
+        self.fd_broadcast_origins.insert(source_addr, Instant::now());
+
         // Forward as Forwarded-NPDU to local broadcast and other foreign devices
         // CRITICAL: Use original sender's address per ASHRAE 135 Annex J.4.5
         let forwarded = self.build_forwarded_npdu(npdu_data, source_addr);
-        let broadcast_addr = self.get_broadcast_address();
-        self.send_ip_packet(&forwarded, broadcast_addr)?;
+        self.send_ip_broadcast(&forwarded)?;
 
         // Forward to other foreign devices (excluding sender)
         // Collect addresses first to avoid borrow issues
@@ -2026,29 +4171,26 @@ impl BacnetGateway {
             .map(|entry| entry.address)
             .collect();
         for addr in fd_addresses {
-            if let Err(e) = self.send_ip_packet(&forwarded, addr) {
-                warn!("Failed to forward to foreign device {}: {}", addr, e);
+            match self.send_ip_packet(&forwarded, addr) {
+                Ok(()) => {
+                    if let Some(entry) = self.foreign_device_table.get_mut(&addr) {
+                        entry.forwarded += 1;
+                    }
+                }
+                Err(e) => warn!("Failed to forward to foreign device {}: {}", addr, e),
             }
         }
 
         // Also route to MS/TP network
-        let (npdu, _) = parse_npdu(npdu_data)?;
+        let npdu = Npdu::decode(npdu_data)?;
 
         // Validate hop count
-        if let Some(hop_count) = npdu.hop_count {
-            if hop_count < MIN_HOP_COUNT {
-                return Err(GatewayError::HopCountExhausted);
-            }
+        if npdu.hop_count < MIN_HOP_COUNT {
+            return Err(GatewayError::HopCountExhausted);
         }
 
-        // Delivering to local MS/TP network = final delivery
-        let routed_npdu = build_routed_npdu(
-            npdu_data,
-            self.ip_network,
-            &ip_to_mac(&source_addr),
-            &npdu,
-            true, // Final delivery - strip DNET/DADR
-        )?;
+        // Delivering to local MS/TP network = final delivery (strip DNET/DADR)
+        let routed_npdu = npdu.routed(self.ip_network, &ip_to_mac(&source_addr), true).encode();
 
         Ok(Some((routed_npdu, 255))) // Broadcast to MS/TP
     }
@@ -2057,15 +4199,44 @@ impl BacnetGateway {
     fn handle_network_message_from_ip(
         &mut self,
         data: &[u8],
-        npdu: &NpduInfo,
+        npdu: &Npdu,
         source_addr: SocketAddr,
     ) -> Result<Option<(Vec<u8>, u8)>, GatewayError> {
-        let (_, npdu_len) = parse_npdu(data)?;
+        let npdu_len = npdu.header_len();
         if npdu_len >= data.len() {
             return Err(GatewayError::InvalidFrame);
         }
 
         let msg_type = data[npdu_len];
+        if msg_type == NL_REJECT_MESSAGE_TO_NETWORK {
+            self.note_reject_received(data, npdu_len, format!("IP {}", source_addr));
+        }
+        let answered_query = self
+            .router_query
+            .as_mut()
+            .is_some_and(|query| query.record_reply(source_addr, &data[npdu_len..], Instant::now()));
+        if let Some(probe) = self.pair_probe.as_mut() {
+            probe.record_reply(source_addr, &data[npdu_len..], Instant::now());
+        }
+        if msg_type == NL_I_AM_ROUTER_TO_NETWORK && !self.is_own_address(source_addr) {
+            self.note_network_probe_answer(source_addr.to_string(), &data[npdu_len + 1..]);
+            self.heard_networks.note_announcement(source_addr, &data[npdu_len + 1..]);
+        }
+        if msg_type == NL_WHAT_IS_NETWORK_NUMBER || msg_type == NL_NETWORK_NUMBER_IS {
+            if !self.is_own_address(source_addr) {
+                let body = &data[npdu_len + 1..];
+                let routed = npdu.source.is_some();
+                if let Some(reply) = self.handle_network_number_message(msg_type, body, routed, true, &source_addr.to_string()) {
+                    self.send_ip_broadcast(&build_bvlc(&reply, true))?;
+                }
+            }
+            return Ok(None);
+        }
+        if answered_query && msg_type == NL_INITIALIZE_ROUTING_TABLE_ACK {
+            // The ack answers our own query; it is not for the MS/TP side
+            debug!("Initialize-Routing-Table-Ack from {} recorded", source_addr);
+            return Ok(None);
+        }
 
         match msg_type {
             NL_WHO_IS_ROUTER_TO_NETWORK => {
@@ -2079,33 +4250,49 @@ impl BacnetGateway {
 
                 debug!("  Requested network: {:?}, our MS/TP network: {}", requested_network, self.mstp_network);
 
+                // An IP network number not learned yet is never claimed
+                let ip_network = self.ip_network_number().known();
                 let is_our_network = requested_network.is_none()
-                    || requested_network == Some(self.mstp_network)
-                    || requested_network == Some(self.ip_network)
+                    || (requested_network == Some(self.mstp_network) && self.is_mstp_network_advertised())
+                    || (ip_network.is_some() && requested_network == ip_network)
                     || requested_network == Some(0xFFFF);
 
-                if is_our_network {
+                // Include both networks we route to
+                let networks: Vec<u16> = [self.is_mstp_network_advertised().then_some(self.mstp_network), ip_network]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                if is_our_network && !networks.is_empty() {
                     // Respond with I-Am-Router-To-Network
-                    // Include both networks we route to
-                    let response = self.build_i_am_router_to_network(&[self.mstp_network, self.ip_network]);
+                    let response = self.build_i_am_router_to_network(&networks);
                     let bvlc = build_bvlc(&response, true);
 
                     // Send to broadcast for network discovery
-                    let broadcast = self.get_broadcast_address();
-                    self.send_ip_packet(&bvlc, broadcast)?;
+                    self.send_ip_broadcast(&bvlc)?;
 
                     // Also send directly to the requester (common BACnet practice)
                     // This ensures they receive our response even if broadcast fails
-                    debug!("  Sending I-Am-Router-To-Network: networks {:?}", [self.mstp_network, self.ip_network]);
+                    debug!("  Sending I-Am-Router-To-Network: networks {:?}", networks);
                     self.send_ip_packet(&bvlc, source_addr)?;
                 }
 
+                // Networks behind a static MS/TP next hop are reachable through us too
+                if let Some(network) = requested_network {
+                    if matches!(self.static_routes.get(&network), Some(RouteNextHop::Mstp(_))) {
+                        debug!("  Sending I-Am-Router-To-Network for static route to {}", network);
+                        let response = self.build_i_am_router_to_network(&[network]);
+                        let bvlc = build_bvlc(&response, true);
+                        self.send_ip_broadcast(&bvlc)?;
+                        return Ok(None);
+                    }
+                }
+
                 // Forward to MS/TP network for other routers to respond (6.5.3)
                 // This allows routers on the MS/TP side to respond if they know the network
                 if requested_network.is_none() || !is_our_network {
                     debug!("  Forwarding Who-Is-Router-To-Network to MS/TP for other routers");
                     // Build NPDU with source info to route responses back
-                    let forwarded = build_routed_npdu(data, self.ip_network, &ip_to_mac(&source_addr), npdu, true)?;
+                    let forwarded = npdu.routed(self.ip_network, &ip_to_mac(&source_addr), true).encode();
                     return Ok(Some((forwarded, 255))); // Broadcast on MS/TP
                 }
             }
@@ -2113,9 +4300,17 @@ impl BacnetGateway {
                 debug!("Received Initialize-Routing-Table from IP (source: {})", source_addr);
                 return self.handle_initialize_routing_table(data, npdu_len, source_addr);
             }
+            NL_I_AM_ROUTER_TO_NETWORK => {
+                debug!("Received I-Am-Router-To-Network from IP (source: {})", source_addr);
+                self.check_duplicate_network(data, npdu_len, source_addr);
+
+                // Forward to MS/TP network - final delivery
+                let routed_npdu = npdu.routed(self.ip_network, &ip_to_mac(&source_addr), true).encode();
+                return Ok(Some((routed_npdu, 255)));
+            }
             _ => {
                 // Forward to MS/TP network - final delivery
-                let routed_npdu = build_routed_npdu(data, self.ip_network, &ip_to_mac(&source_addr), npdu, true)?;
+                let routed_npdu = npdu.routed(self.ip_network, &ip_to_mac(&source_addr), true).encode();
                 return Ok(Some((routed_npdu, 255)));
             }
         }
@@ -2140,6 +4335,12 @@ impl BacnetGateway {
         let num_ports = data[offset];
         offset += 1;
 
+        // No Ack, so the sender does not take the update as applied
+        if self.write_protected && num_ports > 0 {
+            warn!("Initialize-Routing-Table from {} ignored - configuration is write-protected", source_addr);
+            return Ok(None);
+        }
+
         info!(
             "Initialize-Routing-Table from {}: {} ports",
             source_addr, num_ports
@@ -2214,11 +4415,7 @@ impl BacnetGateway {
 
     /// Build Initialize-Routing-Table-Ack message (ASHRAE 135 Clause 6.4)
     fn build_initialize_routing_table_ack(&self) -> Vec<u8> {
-        vec![
-            0x01, // NPDU version
-            0x80, // Control: network layer message, no DNET/SNET
-            NL_INITIALIZE_ROUTING_TABLE_ACK,
-        ]
+        Npdu::network_message(&[NL_INITIALIZE_ROUTING_TABLE_ACK]).encode()
     }
 
     /// Build a BVLC-Result message (ASHRAE 135 Annex J.2.1)
@@ -2286,22 +4483,21 @@ impl BacnetGateway {
 
     /// Build an I-Am-Router-To-Network message (ASHRAE 135 Clause 6.4.2)
     fn build_i_am_router_to_network(&self, networks: &[u16]) -> Vec<u8> {
-        let mut result = Vec::new();
-
-        // NPDU header
-        result.push(0x01); // Version
-        result.push(0x80); // Control: network layer message, no DNET/SNET
-
-        // Network layer message type
-        result.push(NL_I_AM_ROUTER_TO_NETWORK);
-
-        // List of reachable networks
+        // Message type, then the list of reachable networks
+        let mut message = vec![NL_I_AM_ROUTER_TO_NETWORK];
         for &network in networks {
-            result.push((network >> 8) as u8);
-            result.push((network & 0xFF) as u8);
+            message.extend_from_slice(&network.to_be_bytes());
         }
+        Npdu::network_message(&message).encode()
+    }
 
-        result
+    /// Build a Router-Busy-To-Network message (ASHRAE 135 Clause 6.4.5)
+    fn build_router_busy_to_network(&self, networks: &[u16]) -> Vec<u8> {
+        let mut message = vec![NL_ROUTER_BUSY_TO_NETWORK];
+        for &network in networks {
+            message.extend_from_slice(&network.to_be_bytes());
+        }
+        Npdu::network_message(&message).encode()
     }
 
     /// Build a Reject-Message-To-Network message (ASHRAE 135 Clause 6.4.4)
@@ -2315,23 +4511,8 @@ impl BacnetGateway {
     /// - Reject reason (1 byte)
     /// - DNET (2 bytes) - the network that could not be reached
     fn build_reject_message_to_network(&self, reason: RejectReason, dnet: u16) -> Vec<u8> {
-        let mut result = Vec::new();
-
-        // NPDU header
-        result.push(0x01); // Version
-        result.push(0x80); // Control: network layer message, no DNET/SNET
-
-        // Network layer message type
-        result.push(NL_REJECT_MESSAGE_TO_NETWORK);
-
-        // Reject reason
-        result.push(reason as u8);
-
-        // DNET that was unreachable
-        result.push((dnet >> 8) as u8);
-        result.push((dnet & 0xFF) as u8);
-
-        result
+        let [dnet_high, dnet_low] = dnet.to_be_bytes();
+        Npdu::network_message(&[NL_REJECT_MESSAGE_TO_NETWORK, reason as u8, dnet_high, dnet_low]).encode()
     }
 
     /// Send a Reject-Message-To-Network back to the source
@@ -2339,7 +4520,7 @@ impl BacnetGateway {
         &mut self,
         reason: RejectReason,
         dnet: u16,
-        source: &NpduInfo,
+        source: &Npdu,
         received_from_ip: bool,
         ip_source: Option<SocketAddr>,
     ) -> Result<(), GatewayError> {
@@ -2373,23 +4554,250 @@ impl BacnetGateway {
         Ok(())
     }
 
-    /// Announce this router's presence on startup
-    pub fn announce_router(&mut self) -> Result<(), GatewayError> {
-        if self.router_announced {
-            return Ok(());
+    /// Request an immediate router announcement (link-up, config change)
+    pub fn request_router_announce(&mut self) {
+        self.router_announce_requested = true;
+    }
+
+    /// Choose the ports the router announcements go out on and the further
+    /// networks they carry besides the directly connected ones
+    pub fn set_router_announcements(&mut self, scope: AnnounceScope, networks: &[u16]) {
+        self.announce_networks = networks
+            .iter()
+            .copied()
+            .filter(|&network| network != self.mstp_network && network != self.ip_network)
+            .collect();
+        info!(
+            "Router announcements: {}{}",
+            scope.as_str(),
+            if self.announce_networks.is_empty() {
+                String::new()
+            } else {
+                format!(", also networks {}", format_network_list(&self.announce_networks))
+            }
+        );
+        self.announce_scope = scope;
+    }
+
+    /// Networks announced on one port: the directly connected network on the
+    /// other side, plus the configured further networks whose static route
+    /// leads out of that side. Nothing is announced while either port is
+    /// administratively disabled, as no traffic can be routed through us.
+    fn announced_networks(&self, on_ip: bool) -> Vec<u16> {
+        if !self.mstp_port_enabled || !self.ip_port_enabled {
+            return Vec::new();
+        }
+        let connected = if on_ip {
+            self.is_mstp_network_advertised().then_some(self.mstp_network)
+        } else {
+            self.ip_network_number().known()
+        };
+        let routed = self.announce_networks.iter().copied().filter(|&network| {
+            match self.static_route_for(network) {
+                Some(RouteNextHop::Mstp(_)) => on_ip,
+                Some(RouteNextHop::Ip(_)) => !on_ip,
+                None => false,
+            }
+        });
+        connected.into_iter().chain(routed).collect()
+    }
+
+    /// Announce this router's presence when the schedule says so.
+    ///
+    /// Broadcasts I-Am-Router-To-Network for the networks on the MS/TP side
+    /// on IP and returns the matching announcement for the networks on the
+    /// IP side, which the caller broadcasts on MS/TP; the announcement scope
+    /// can leave either out. The first announcement after startup also
+    /// carries Network-Number-Is for each configured number; while the IP
+    /// number is unknown, What-Is-Network-Number asks for it instead of the
+    /// IP network being announced. Nothing is sent while the MS/TP token has
+    /// not been acquired; a pending announcement stays due until it has.
+    pub fn announce_router(&mut self, mstp_token_ok: bool) -> Option<Vec<Vec<u8>>> {
+        let due = self.router_announce_requested
+            || self.last_router_announce
+                .map_or(true, |t| t.elapsed() >= ROUTER_ANNOUNCE_PERIOD);
+        if !due || !mstp_token_ok || self.standby {
+            return None;
         }
 
-        info!("Announcing router presence for networks {} and {}",
-              self.mstp_network, self.ip_network);
+        self.router_announce_requested = false;
+        let startup = self.last_router_announce.is_none();
+        self.last_router_announce = Some(Instant::now());
+
+        let ip_networks = if self.announce_scope.on_ip() { self.announced_networks(true) } else { Vec::new() };
+        let mstp_networks = if self.announce_scope.on_mstp() { self.announced_networks(false) } else { Vec::new() };
+        info!("Announcing router presence: networks {:?} on IP, {:?} on MS/TP", ip_networks, mstp_networks);
+
+        // Send I-Am-Router-To-Network for the MS/TP side on IP
+        // (our MS/TP network is left out while another router owns that number)
+        if !ip_networks.is_empty() {
+            let response = self.build_i_am_router_to_network(&ip_networks);
+            let bvlc = build_bvlc(&response, true);
+            if let Err(e) = self.send_ip_broadcast(&bvlc) {
+                warn!("Failed to send I-Am-Router-To-Network on IP: {}", e);
+            }
+        }
 
-        // Send I-Am-Router-To-Network for MS/TP network on IP side
-        let response = self.build_i_am_router_to_network(&[self.mstp_network]);
-        let bvlc = build_bvlc(&response, true);
-        let broadcast = self.get_broadcast_address();
-        self.send_ip_packet(&bvlc, broadcast)?;
+        let ip_number = self.ip_network_number();
+        let ip_message = match ip_number.quality {
+            NetworkNumberQuality::Unknown => Some(vec![NL_WHAT_IS_NETWORK_NUMBER]),
+            NetworkNumberQuality::Configured if startup => Some(netnum::network_number_is(ip_number)),
+            _ => None,
+        };
+        if let Some(message) = ip_message {
+            let bvlc = build_bvlc(&Npdu::network_message(&message).encode(), true);
+            if let Err(e) = self.send_ip_broadcast(&bvlc) {
+                warn!("Failed to send network number message on IP: {}", e);
+            }
+        }
 
-        self.router_announced = true;
-        Ok(())
+        // I-Am-Router-To-Network for the IP side, for the MS/TP side
+        let mut mstp_messages: Vec<Vec<u8>> = (!mstp_networks.is_empty())
+            .then(|| self.build_i_am_router_to_network(&mstp_networks))
+            .into_iter()
+            .collect();
+        if startup {
+            let message = netnum::network_number_is(self.mstp_network_number());
+            mstp_messages.push(Npdu::network_message(&message).encode());
+        }
+        Some(mstp_messages)
+    }
+
+    /// Final announcement before the gateway goes down.
+    ///
+    /// Broadcasts Router-Busy-To-Network for the MS/TP network on the IP side
+    /// and returns the matching message for the IP network, which the caller
+    /// broadcasts on MS/TP, so peers stop routing through us right away
+    /// instead of waiting for their requests to time out.
+    pub fn announce_going_down(&mut self) -> Vec<u8> {
+        info!("Announcing Router-Busy for networks {} and {}", self.mstp_network, self.ip_network);
+        let busy = self.build_router_busy_to_network(&[self.mstp_network]);
+        let bvlc = build_bvlc(&busy, true);
+        if let Err(e) = self.send_ip_broadcast(&bvlc) {
+            warn!("Failed to send Router-Busy-To-Network on IP: {}", e);
+        }
+        self.build_router_busy_to_network(&[self.ip_network])
+    }
+
+    /// Queue the BDT, routing table, static routes and response statistics
+    /// for NVS; flush the writer afterwards when they must be on flash
+    pub fn flush_to_nvs(&mut self) {
+        self.save_bdt_to_nvs();
+        self.save_routing_table_to_nvs();
+        self.save_static_routes_to_nvs();
+        self.save_sla_to_nvs();
+    }
+
+    /// Roll the response statistics over to `day` (days since 1970-01-01,
+    /// UTC; None while the clock is unset) and checkpoint them to NVS on a
+    /// day change or every `SLA_CHECKPOINT_INTERVAL`
+    pub fn advance_sla(&mut self, day: Option<u32>) {
+        let rolled = day.is_some_and(|day| self.sla.set_day(day));
+        if rolled || self.sla_saved_at.elapsed() >= SLA_CHECKPOINT_INTERVAL {
+            self.save_sla_to_nvs();
+        }
+    }
+
+    /// Broadcast a locally originated NPDU on the IP side
+    pub fn broadcast_on_ip(&mut self, npdu: &[u8]) -> Result<(), GatewayError> {
+        let bvlc = build_bvlc(npdu, true);
+        self.send_ip_broadcast(&bvlc)
+    }
+
+    /// Send a locally originated NPDU to one B/IP address
+    pub fn send_on_ip(&mut self, npdu: &[u8], dest: SocketAddr) -> Result<(), GatewayError> {
+        let bvlc = build_bvlc(npdu, false);
+        self.send_ip_packet(&bvlc, dest)
+    }
+
+    /// Stop routing IP traffic into the MS/TP network while a duplicate
+    /// network number is flagged
+    pub fn set_suppress_on_conflict(&mut self, suppress: bool) {
+        self.suppress_on_conflict = suppress;
+    }
+
+    /// Stop or resume top talker counting (shed by the low-memory governor)
+    pub fn set_top_talkers_enabled(&mut self, enabled: bool) {
+        self.talkers.set_enabled(enabled);
+    }
+
+    /// Get the current duplicate network number conflict, if any
+    pub fn network_conflict(&self) -> Option<&NetworkConflict> {
+        self.network_conflict.as_ref()
+    }
+
+    /// Get a newly detected conflict once, so the caller can raise an event
+    pub fn take_new_network_conflict(&mut self) -> Option<NetworkConflict> {
+        if self.network_conflict_raised {
+            self.network_conflict_raised = false;
+            self.network_conflict.clone()
+        } else {
+            None
+        }
+    }
+
+    /// Network numbers other routers have announced on the IP side
+    pub fn heard_networks(&self) -> &HeardNetworks {
+        &self.heard_networks
+    }
+
+    /// Acknowledge and clear the duplicate network number conflict
+    pub fn clear_network_conflict(&mut self) {
+        if self.network_conflict.take().is_some() {
+            info!("Duplicate network number conflict cleared");
+        }
+        self.network_conflict_raised = false;
+    }
+
+    /// Check whether routing into the MS/TP network is suppressed by a conflict
+    fn is_mstp_routing_suppressed(&self) -> bool {
+        self.suppress_on_conflict && self.network_conflict.is_some()
+    }
+
+    /// Whether our MS/TP network is claimed in I-Am-Router-To-Network: not
+    /// while routing into it is suppressed or its port is disabled
+    fn is_mstp_network_advertised(&self) -> bool {
+        self.mstp_port_enabled && !self.is_mstp_routing_suppressed()
+    }
+
+    /// Check an I-Am-Router-To-Network heard on IP for our MS/TP network number
+    fn check_duplicate_network(&mut self, data: &[u8], npdu_len: usize, source_addr: SocketAddr) {
+        // Ignore our own announcements echoed back
+        if self.is_own_address(source_addr) {
+            return;
+        }
+
+        let advertises_ours = data[npdu_len + 1..]
+            .chunks_exact(2)
+            .any(|n| u16::from_be_bytes([n[0], n[1]]) == self.mstp_network);
+        if !advertises_ours {
+            return;
+        }
+
+        let now = Instant::now();
+        match self.network_conflict {
+            Some(ref mut conflict) => {
+                conflict.router = source_addr;
+                conflict.last_seen = now;
+                conflict.count += 1;
+            }
+            None => {
+                warn!(
+                    "DUPLICATE NETWORK NUMBER: router {} also advertises network {}{}",
+                    source_addr,
+                    self.mstp_network,
+                    if self.suppress_on_conflict { " - suppressing routing to MS/TP" } else { "" }
+                );
+                self.network_conflict = Some(NetworkConflict {
+                    network: self.mstp_network,
+                    router: source_addr,
+                    first_seen: now,
+                    last_seen: now,
+                    count: 1,
+                });
+                self.network_conflict_raised = true;
+            }
+        }
     }
 
     /// Resolve an IP address from BACnet MAC address
@@ -2406,6 +4814,10 @@ impl BacnetGateway {
 
     /// Process periodic housekeeping tasks
     pub fn process_housekeeping(&mut self) {
+        if let Some(link) = self.ip_link.as_mut() {
+            link.flush();
+        }
+
         // Clean up old address mappings
         let max_age = self.address_max_age;
 
@@ -2432,6 +4844,16 @@ impl BacnetGateway {
             keep
         });
 
+        // Forget devices that have not announced themselves recently
+        self.device_bindings.retain(|instance, entry| {
+            let keep = !entry.is_expired(max_age);
+            if !keep {
+                debug!("Aged out binding for device {}", instance);
+                self.device_names.forget(*instance);
+            }
+            keep
+        });
+
         // Remove expired foreign device entries (ASHRAE 135 Annex J.5.3)
         self.foreign_device_table.retain(|addr, entry| {
             let keep = !entry.is_expired();
@@ -2441,6 +4863,31 @@ impl BacnetGateway {
             keep
         });
 
+        // Forget Distribute-Broadcast origins outside the reply window
+        self.fd_broadcast_origins.retain(|_, at| at.elapsed() < FD_REPLY_WINDOW);
+
+        // Retry routes whose Reject backoff has elapsed
+        self.recent_forwards.retain(|_, at| at.elapsed() < RECENT_FORWARD_WINDOW);
+        self.unreachable_networks.retain(|network, n| {
+            let keep = Instant::now() < n.until;
+            if !keep {
+                info!("Backoff for unreachable network {} elapsed - routing resumes", network);
+            }
+            keep
+        });
+
+        // A duplicate network number is resolved once the other router goes quiet
+        if self
+            .network_conflict
+            .as_ref()
+            .is_some_and(|c| c.last_seen.elapsed() >= DUPLICATE_NETWORK_HOLD)
+        {
+            info!("No duplicate announcements for network {} - conflict resolved", self.mstp_network);
+            self.clear_network_conflict();
+        }
+
+        self.talkers.rotate(Instant::now());
+
         // Log if any entries were removed
         let mstp_removed = mstp_before - self.mstp_to_ip.len();
         let ip_removed = ip_before - self.ip_to_mstp.len();
@@ -2453,14 +4900,44 @@ impl BacnetGateway {
         }
     }
 
-    /// Get number of registered foreign devices
-    pub fn foreign_device_count(&self) -> usize {
-        self.foreign_device_table.len()
+    /// Get number of registered foreign devices
+    pub fn foreign_device_count(&self) -> usize {
+        self.foreign_device_table.len()
+    }
+
+    /// Get gateway statistics
+    pub fn get_stats(&self) -> &GatewayStats {
+        &self.stats
+    }
+
+    /// Busiest routed source/destination addresses over the last one to two minutes
+    pub fn top_talkers(&self, n: usize, ranking: TalkerRanking) -> Vec<TopTalker> {
+        self.talkers.top(n, ranking)
+    }
+
+    /// Counters for each B/IP peer heard from, busiest first
+    pub fn peer_stats(&self) -> Vec<PeerStats> {
+        self.peers.snapshot()
+    }
+
+    /// Count datagrams from a B/IP peer dropped by the receive fair queue
+    pub fn record_ip_queue_drops(&mut self, addr: SocketAddr, count: u64) {
+        self.peers.record_dropped(addr, count, Instant::now());
+    }
+
+    /// Response statistics for every tracked MS/TP device, by MAC
+    pub fn sla_report(&self) -> Vec<DeviceSla> {
+        self.sla.snapshot()
     }
 
-    /// Get gateway statistics
-    pub fn get_stats(&self) -> &GatewayStats {
-        &self.stats
+    /// Requests routed onto MS/TP and how they were answered, by confirmed service
+    pub fn service_counters(&self) -> Vec<(u8, ServiceCounters)> {
+        self.service_stats.counters()
+    }
+
+    /// Most frequent Error/Reject/Abort answers by service, and how many did not fit the histogram
+    pub fn top_service_errors(&self, n: usize) -> (Vec<ErrorCount>, u64) {
+        (self.service_stats.top_errors(n), self.service_stats.untracked())
     }
 
     /// Check network health based on recent activity
@@ -2525,6 +5002,7 @@ impl BacnetGateway {
             MS/TP->IP: {} pkts ({} bytes), last: {}, status: {}\n  \
             IP->MS/TP: {} pkts ({} bytes), last: {}, status: {}\n  \
             Errors: {} routing, {} timeouts\n  \
+            Held for MS/TP: {} now, {} held, {} released, {} expired, {} refused\n  \
             Active transactions: {}, Foreign devices: {}",
             self.stats.mstp_to_ip_packets,
             self.stats.mstp_to_ip_bytes,
@@ -2536,6 +5014,11 @@ impl BacnetGateway {
             ip_status,
             self.stats.routing_errors,
             self.stats.transaction_timeouts,
+            self.held_frames.len(),
+            self.stats.held_frames,
+            self.stats.held_released,
+            self.stats.held_expired,
+            self.stats.held_overflows,
             self.transactions.len(),
             self.foreign_device_table.len()
         )
@@ -2577,6 +5060,25 @@ impl std::fmt::Display for GatewayError {
 
 impl std::error::Error for GatewayError {}
 
+impl From<NpduError> for GatewayError {
+    fn from(e: NpduError) -> Self {
+        GatewayError::NpduError(e.to_string())
+    }
+}
+
+impl Classify for GatewayError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            GatewayError::InvalidFrame => ErrorKind::InvalidFrame,
+            GatewayError::InvalidAddress => ErrorKind::InvalidAddress,
+            GatewayError::NetworkUnreachable(_) => ErrorKind::NetworkUnreachable,
+            GatewayError::IoError(_) => ErrorKind::Io,
+            GatewayError::NpduError(_) | GatewayError::BvlcError(_) => ErrorKind::Encoding,
+            GatewayError::HopCountExhausted => ErrorKind::HopCountExhausted,
+        }
+    }
+}
+
 /// APDU type classification for transaction tracking
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ApduTypeClass {
@@ -2777,227 +5279,89 @@ fn parse_apdu(data: &[u8]) -> Result<ApduInfo, GatewayError> {
     }
 }
 
-/// Parsed NPDU information
-#[allow(dead_code)]
-struct NpduInfo {
-    network_message: bool,
-    destination_present: bool,
-    source_present: bool,
-    expecting_reply: bool,
-    priority: u8,
-    destination: Option<NetworkAddress>,
-    source: Option<NetworkAddress>,
-    hop_count: Option<u8>,
-}
-
-/// Network address
-struct NetworkAddress {
-    network: u16,
-    address: Vec<u8>,
+/// Hex dump of a frame, formatted only when it is actually written
+///
+/// Log macros skip their arguments for filtered levels, so passing this
+/// instead of a pre-built string keeps per-packet debug lines free when
+/// they are off.
+pub struct HexDump<'a> {
+    data: &'a [u8],
+    max_bytes: usize,
 }
 
-/// Create a hex dump string for error logging
-///
-/// Returns a formatted hex string showing up to `max_bytes` of data.
-/// Format: "len=N [01 02 03 04...]" or "len=N [01 02 03...and M more]"
-fn hex_dump(data: &[u8], max_bytes: usize) -> String {
-    let show_bytes = data.len().min(max_bytes);
-    let hex_str: Vec<String> = data[..show_bytes]
-        .iter()
-        .map(|b| format!("{:02X}", b))
-        .collect();
-
-    if data.len() > max_bytes {
-        format!(
-            "len={} [{} ...and {} more]",
-            data.len(),
-            hex_str.join(" "),
-            data.len() - max_bytes
-        )
-    } else {
-        format!("len={} [{}]", data.len(), hex_str.join(" "))
+impl std::fmt::Display for HexDump<'_> {
+    /// "len=N [01 02 03 04]" or "len=N [01 02 03 ...and M more]"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "len={} [", self.data.len())?;
+        for (i, b) in self.data.iter().take(self.max_bytes).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02X}", b)?;
+        }
+        if self.data.len() > self.max_bytes {
+            write!(f, " ...and {} more", self.data.len() - self.max_bytes)?;
+        }
+        f.write_str("]")
     }
 }
 
-/// Parse NPDU header
-fn parse_npdu(data: &[u8]) -> Result<(NpduInfo, usize), GatewayError> {
-    if data.len() < 2 {
-        return Err(GatewayError::NpduError(format!(
-            "NPDU too short: {} bytes (minimum 2)",
-            data.len()
-        )));
-    }
-
-    let version = data[0];
-    if version != 1 {
-        return Err(GatewayError::NpduError(format!(
-            "Invalid NPDU version: expected 1, got {}",
-            version
-        )));
-    }
-
-    let control = data[1];
-    let network_message = (control & 0x80) != 0;
-    let destination_present = (control & 0x20) != 0;
-    let source_present = (control & 0x08) != 0;
-    let expecting_reply = (control & 0x04) != 0;
-    let priority = control & 0x03;
-
-    let mut pos = 2;
-
-    // Parse destination
-    let destination = if destination_present {
-        if pos + 3 > data.len() {
-            return Err(GatewayError::NpduError(format!(
-                "NPDU destination truncated: need {} bytes, have {}",
-                pos + 3,
-                data.len()
-            )));
-        }
-        let network = ((data[pos] as u16) << 8) | (data[pos + 1] as u16);
-        let addr_len = data[pos + 2] as usize;
-        pos += 3;
-
-        if pos + addr_len > data.len() {
-            return Err(GatewayError::NpduError(format!(
-                "NPDU destination address truncated: need {} bytes, have {}",
-                pos + addr_len,
-                data.len()
-            )));
-        }
-        let address = data[pos..pos + addr_len].to_vec();
-        pos += addr_len;
-
-        Some(NetworkAddress { network, address })
-    } else {
-        None
-    };
-
-    // Parse source
-    let source = if source_present {
-        if pos + 3 > data.len() {
-            return Err(GatewayError::NpduError(format!(
-                "NPDU source truncated: need {} bytes, have {}",
-                pos + 3,
-                data.len()
-            )));
-        }
-        let network = ((data[pos] as u16) << 8) | (data[pos + 1] as u16);
-        let addr_len = data[pos + 2] as usize;
-        pos += 3;
-
-        if pos + addr_len > data.len() {
-            return Err(GatewayError::NpduError(format!(
-                "NPDU source address truncated: need {} bytes, have {}",
-                pos + addr_len,
-                data.len()
-            )));
-        }
-        let address = data[pos..pos + addr_len].to_vec();
-        pos += addr_len;
-
-        Some(NetworkAddress { network, address })
-    } else {
-        None
-    };
-
-    // Parse hop count
-    let hop_count = if destination_present {
-        if pos >= data.len() {
-            return Err(GatewayError::NpduError(format!(
-                "NPDU hop count missing: need {} bytes, have {}",
-                pos + 1,
-                data.len()
-            )));
-        }
-        let hc = data[pos];
-        pos += 1;
-        Some(hc)
-    } else {
-        None
-    };
-
-    Ok((
-        NpduInfo {
-            network_message,
-            destination_present,
-            source_present,
-            expecting_reply,
-            priority,
-            destination,
-            source,
-            hop_count,
-        },
-        pos,
-    ))
+/// Lazy hex dump showing up to `max_bytes` of data
+pub fn hex_dump(data: &[u8], max_bytes: usize) -> HexDump<'_> {
+    HexDump { data, max_bytes }
 }
 
-/// Build a routed NPDU with source network information
+/// Remove SNET/SADR from an NPDU that has no destination specifier
 ///
-/// Per ASHRAE 135 Clause 6.2.2: When delivering to the final destination network,
-/// the DNET/DADR fields must be stripped from the NPDU. Set `final_delivery` to true
-/// when the destination network matches the local network being delivered to.
-fn build_routed_npdu(
-    original_data: &[u8],
-    source_network: u16,
-    source_address: &[u8],
-    npdu: &NpduInfo,
-    final_delivery: bool,
-) -> Result<Vec<u8>, GatewayError> {
-    let mut result = Vec::new();
-
-    // Version
-    result.push(1);
-
-    // Build control byte
-    let mut control = npdu.priority;
-    if npdu.network_message {
-        control |= 0x80;
-    }
-    // ASHRAE 135 Clause 6.2.2: Strip DNET/DADR for final delivery
-    if npdu.destination.is_some() && !final_delivery {
-        control |= 0x20;
-    }
-    // Always set source present since we're routing
-    control |= 0x08;
-    if npdu.expecting_reply {
-        control |= 0x04;
-    }
-    result.push(control);
-
-    // Destination (only if NOT final delivery per ASHRAE 135 Clause 6.2.2)
-    if let Some(ref dest) = npdu.destination {
-        if !final_delivery {
-            result.push((dest.network >> 8) as u8);
-            result.push((dest.network & 0xFF) as u8);
-            result.push(dest.address.len() as u8);
-            result.extend_from_slice(&dest.address);
+/// Used on final delivery to clients that mis-bind replies carrying a
+/// source address; NPDUs that still have DNET/DADR are returned unchanged.
+fn strip_source(npdu: &[u8]) -> Vec<u8> {
+    match Npdu::decode(npdu) {
+        Ok(decoded) if decoded.destination.is_none() && decoded.source.is_some() => {
+            Npdu { source: None, ..decoded }.encode()
         }
+        _ => npdu.to_vec(),
     }
+}
 
-    // Source (always add for routing)
-    result.push((source_network >> 8) as u8);
-    result.push((source_network & 0xFF) as u8);
-    result.push(source_address.len() as u8);
-    result.extend_from_slice(source_address);
-
-    // Hop count (if destination present and NOT final delivery)
-    if npdu.destination.is_some() && !final_delivery {
-        let hc = npdu.hop_count.unwrap_or(255).saturating_sub(1);
-        result.push(hc);
+/// Invoke ID of the confirmed request carried in an NPDU, None for anything else
+fn confirmed_invoke_id(npdu: &[u8]) -> Option<u8> {
+    let npdu = Npdu::decode(npdu).ok()?;
+    if npdu.network_message {
+        return None;
     }
+    let apdu = parse_apdu(npdu.payload).ok()?;
+    match apdu.apdu_type {
+        ApduTypeClass::ConfirmedRequest => apdu.invoke_id,
+        _ => None,
+    }
+}
+
+/// Device instance range limits of a Who-Is APDU, None when unbounded
+fn who_is_range(apdu: &[u8]) -> Option<(u32, u32)> {
+    let (low, rest) = context_unsigned(apdu.get(2..)?, 0)?;
+    let (high, _) = context_unsigned(rest, 1)?;
+    Some((low, high))
+}
 
-    // Copy APDU (everything after NPDU header)
-    let (_, npdu_len) = parse_npdu(original_data)?;
-    if npdu_len < original_data.len() {
-        result.extend_from_slice(&original_data[npdu_len..]);
+/// Decode a context-tagged unsigned (1-4 octets), returning it and the remaining data
+fn context_unsigned(data: &[u8], tag: u8) -> Option<(u32, &[u8])> {
+    let (&first, rest) = data.split_first()?;
+    let len = (first & 0x07) as usize;
+    if first & 0xF8 != (tag << 4) | 0x08 || !(1..=4).contains(&len) || rest.len() < len {
+        return None;
     }
+    let value = rest[..len].iter().fold(0u32, |v, &b| (v << 8) | b as u32);
+    Some((value, &rest[len..]))
+}
 
-    Ok(result)
+/// Local NPDU (no network layer information) for a confirmed request the gateway sends itself
+fn local_request_npdu(apdu: &[u8]) -> Vec<u8> {
+    Npdu { expecting_reply: true, ..Npdu::local(apdu) }.encode()
 }
 
 /// Build BVLC wrapper for NPDU
-fn build_bvlc(npdu: &[u8], broadcast: bool) -> Vec<u8> {
+pub fn build_bvlc(npdu: &[u8], broadcast: bool) -> Vec<u8> {
     let mut result = Vec::with_capacity(4 + npdu.len());
 
     // BVLC header
@@ -3037,18 +5401,21 @@ fn ip_to_mac(addr: &SocketAddr) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::analyzer::Violation;
+    use crate::client::ClientRequest;
+    use crate::rpm_proxy::PropertyRef;
 
     #[test]
     fn test_hex_dump_short() {
         let data = vec![0x01, 0x02, 0x03, 0x04];
-        let result = hex_dump(&data, 64);
+        let result = hex_dump(&data, 64).to_string();
         assert_eq!(result, "len=4 [01 02 03 04]");
     }
 
     #[test]
     fn test_hex_dump_long() {
         let data = vec![0xAA; 100]; // 100 bytes of 0xAA
-        let result = hex_dump(&data, 8);
+        let result = hex_dump(&data, 8).to_string();
         assert!(result.contains("len=100"));
         assert!(result.contains("...and 92 more"));
         assert!(result.contains("AA AA AA AA AA AA AA AA"));
@@ -3057,64 +5424,10 @@ mod tests {
     #[test]
     fn test_hex_dump_empty() {
         let data = vec![];
-        let result = hex_dump(&data, 64);
+        let result = hex_dump(&data, 64).to_string();
         assert_eq!(result, "len=0 []");
     }
 
-    #[test]
-    fn test_parse_npdu_too_short() {
-        let data = vec![0x01]; // Only 1 byte
-        let result = parse_npdu(&data);
-        assert!(result.is_err());
-        if let Err(GatewayError::NpduError(msg)) = result {
-            assert!(msg.contains("too short"));
-            assert!(msg.contains("minimum 2"));
-        }
-    }
-
-    #[test]
-    fn test_parse_npdu_invalid_version() {
-        let data = vec![0x02, 0x00]; // Version 2 (invalid)
-        let result = parse_npdu(&data);
-        assert!(result.is_err());
-        if let Err(GatewayError::NpduError(msg)) = result {
-            assert!(msg.contains("Invalid NPDU version"));
-            assert!(msg.contains("expected 1, got 2"));
-        }
-    }
-
-    #[test]
-    fn test_parse_npdu_truncated_destination() {
-        // NPDU with destination flag set but incomplete data
-        let data = vec![
-            0x01, // Version
-            0x20, // Control: destination present
-            0x00, 0x01, // DNET = 1
-            0x05, // DADR length = 5 (but no address follows)
-        ];
-        let result = parse_npdu(&data);
-        assert!(result.is_err());
-        if let Err(GatewayError::NpduError(msg)) = result {
-            assert!(msg.contains("destination address truncated"));
-        }
-    }
-
-    #[test]
-    fn test_parse_npdu_valid_simple() {
-        // Simple NPDU with no destination or source
-        let data = vec![
-            0x01, // Version
-            0x00, // Control: no flags
-        ];
-        let result = parse_npdu(&data);
-        assert!(result.is_ok());
-        let (npdu, len) = result.unwrap();
-        assert_eq!(len, 2);
-        assert!(!npdu.network_message);
-        assert!(!npdu.destination_present);
-        assert!(!npdu.source_present);
-    }
-
     #[test]
     fn test_reject_reason_codes() {
         // Verify reject reason enum values match BACnet spec
@@ -3143,4 +5456,1192 @@ mod tests {
         assert_eq!(reject[4], (999 >> 8) as u8); // DNET high byte
         assert_eq!(reject[5], (999 & 0xFF) as u8); // DNET low byte
     }
+
+    #[test]
+    fn test_static_routes() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let router: SocketAddr = "192.168.1.1:47808".parse().unwrap();
+
+        // Directly connected and reserved networks are refused
+        assert!(!gateway.add_static_route(1, RouteNextHop::Mstp(10)));
+        assert!(!gateway.add_static_route(2, RouteNextHop::Ip(router)));
+        assert!(!gateway.add_static_route(0xFFFF, RouteNextHop::Mstp(10)));
+
+        assert!(gateway.add_static_route(300, RouteNextHop::Ip(router)));
+        assert!(gateway.add_static_route(100, RouteNextHop::Mstp(10)));
+        // Re-adding replaces the next hop
+        assert!(gateway.add_static_route(100, RouteNextHop::Mstp(12)));
+        assert_eq!(
+            gateway.get_static_routes(),
+            vec![(100, RouteNextHop::Mstp(12)), (300, RouteNextHop::Ip(router))]
+        );
+
+        gateway.remove_static_route(100);
+        assert_eq!(gateway.get_static_routes(), vec![(300, RouteNextHop::Ip(router))]);
+    }
+
+    #[test]
+    fn test_mstp_relay_spends_a_hop() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        assert!(gateway.add_static_route(100, RouteNextHop::Mstp(10)));
+
+        // Who-Is to network 100 (broadcast), hop count 5, from MS/TP 3
+        let mut npdu = vec![0x01, 0x20, 0x00, 0x64, 0x00, 0x05, 0x10, 0x08];
+        let (relayed, mac) = gateway.route_from_mstp(&npdu, 3).unwrap().unwrap();
+        assert_eq!(mac, 10);
+        assert_eq!(Npdu::decode(&relayed).unwrap().hop_count, 4);
+        assert_eq!(relayed[6..], npdu[6..]);
+
+        // The last hop is not relayed
+        npdu[5] = 1;
+        assert!(matches!(gateway.route_from_mstp(&npdu, 3), Err(GatewayError::HopCountExhausted)));
+    }
+
+    #[test]
+    fn test_reject_backs_off_recently_forwarded_network() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        assert!(gateway.add_static_route(500, RouteNextHop::Mstp(10)));
+        gateway.recent_forwards.insert(500, Instant::now());
+
+        // Reject-Message-To-Network, reason 1 (not router to DNET)
+        let reject_600 = [0x01, 0x80, NL_REJECT_MESSAGE_TO_NETWORK, 0x01, 0x02, 0x58];
+        gateway.note_reject_received(&reject_600, 2, "MS/TP 10".to_string());
+        assert!(gateway.unreachable_networks().is_empty(), "network 600 was never routed by us");
+
+        let reject_500 = [0x01, 0x80, NL_REJECT_MESSAGE_TO_NETWORK, 0x01, 0x01, 0xF4];
+        gateway.note_reject_received(&reject_500, 2, "MS/TP 10".to_string());
+        let unreachable = gateway.unreachable_networks();
+        assert_eq!(unreachable.len(), 1);
+        assert_eq!(unreachable[0].network, 500);
+        assert_eq!(unreachable[0].reason, 1);
+        assert_eq!(gateway.static_route_for(500), None);
+        assert_eq!(gateway.get_stats().rejects_received, 2);
+    }
+
+    #[test]
+    fn test_held_frames_bounded_and_released() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+        // Confirmed ReadProperty, invoke_id 7
+        let npdu = vec![0x01, 0x04, 0x00, 0x05, 0x07, 0x0C];
+
+        for _ in 0..MAX_HELD_FRAMES + 1 {
+            gateway.hold_for_mstp(npdu.clone(), 5, true, client);
+        }
+        assert_eq!(gateway.held_frame_count(), MAX_HELD_FRAMES);
+        assert_eq!(gateway.get_stats().held_overflows, 1);
+        assert_eq!(gateway.get_stats().held_high_water, MAX_HELD_FRAMES);
+
+        // Nothing is old enough to expire yet
+        assert_eq!(gateway.expire_held_frames(), 0);
+
+        let released = gateway.release_held_frames(16);
+        assert_eq!(released.len(), 16);
+        assert_eq!(released[0], (npdu, 5, true));
+        assert_eq!(gateway.held_frame_count(), MAX_HELD_FRAMES - 16);
+
+        let released = gateway.release_held_frames(usize::MAX);
+        assert_eq!(released.len(), MAX_HELD_FRAMES - 16);
+        assert_eq!(gateway.held_frame_count(), 0);
+        assert_eq!(gateway.get_stats().held_released, MAX_HELD_FRAMES as u64);
+    }
+
+    #[test]
+    fn test_ip_message_too_long_for_mstp_is_rejected() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        gateway.set_mstp_max_npdu(100);
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // Confirmed WriteProperty (invoke_id 9) to MS/TP MAC 5 on network 1, 120-byte APDU
+        let mut npdu = vec![0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, 0x09, 0x0F];
+        npdu.resize(7 + 120, 0x00);
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_none());
+        assert_eq!(gateway.get_stats().messages_too_long, 1);
+        assert_eq!(gateway.active_transaction_count(), 0);
+
+        // Reject-Message-To-Network, reason 4 (message too long), DNET 1, back to the client
+        let (reject, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, client);
+        assert_eq!(reject[4..], [0x01, 0x80, NL_REJECT_MESSAGE_TO_NETWORK, 0x04, 0x00, 0x01]);
+
+        // The same request fits once the limit is back at the classic frame size
+        gateway.set_mstp_max_npdu(MSTP_MAX_NPDU);
+        let (routed, mac) = gateway.route_from_ip(&bvlc, client).unwrap().unwrap();
+        assert_eq!(mac, 5);
+        assert!(routed.len() <= MSTP_MAX_NPDU);
+    }
+
+    #[test]
+    fn test_disabled_ports_reject_and_drop() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // Confirmed ReadProperty (invoke_id 9) to MS/TP MAC 5 on network 1
+        let npdu = [0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, 0x09, 0x0C, 0x0C, 0x02, 0x00, 0x00, 0x01, 0x19, 0x4D];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+
+        // MS/TP port disabled: the client gets Reject (router busy, DNET 1), nothing is tracked
+        gateway.set_mstp_port_enabled(false);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_none());
+        assert_eq!(gateway.active_transaction_count(), 0);
+        let (reject, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, client);
+        assert_eq!(reject[4..], [0x01, 0x80, NL_REJECT_MESSAGE_TO_NETWORK, 0x02, 0x00, 0x01]);
+
+        // Frames from MS/TP are dropped while the port is disabled
+        let iam = [0x01, 0x20, 0xFF, 0xFF, 0x00, 0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05];
+        let sent = gateway.ip_send_queue.len();
+        assert!(gateway.route_from_mstp(&iam, 5).unwrap().is_none());
+        assert_eq!(gateway.ip_send_queue.len(), sent);
+
+        // IP port disabled: IP input is dropped, MS/TP unicasts to IP are rejected back
+        gateway.set_mstp_port_enabled(true);
+        gateway.set_ip_port_enabled(false);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_none());
+        assert_eq!(gateway.ip_send_queue.len(), sent);
+
+        let to_ip = [0x01, 0x24, 0x00, 0x02, 0x06, 192, 168, 1, 50, 0xBA, 0xC0, 0xFF, 0x10, 0x08];
+        let (reject, mac) = gateway.route_from_mstp(&to_ip, 5).unwrap().unwrap();
+        assert_eq!(mac, 5);
+        assert_eq!(reject, [0x01, 0x80, NL_REJECT_MESSAGE_TO_NETWORK, 0x02, 0x00, 0x02]);
+        assert!(gateway.route_from_mstp(&iam, 5).unwrap().is_none());
+        assert_eq!(gateway.get_stats().port_disabled_drops, 5);
+
+        // Re-enabled: routing resumes
+        gateway.set_ip_port_enabled(true);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_backpressure_aborts_confirmed_requests_until_queue_drains() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        gateway.set_backpressure(12, 8);
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // Confirmed ReadProperty (invoke_id 9) to MS/TP MAC 5 on network 1
+        let npdu = [0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, 0x09, 0x0C, 0x0C, 0x02, 0x00, 0x00, 0x01, 0x19, 0x4D];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+
+        // Queue at the high mark: the client gets Abort (buffer-overflow), nothing is tracked
+        gateway.set_mstp_queue_depth(12);
+        assert!(gateway.backpressure_active());
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_none());
+        assert_eq!(gateway.active_transaction_count(), 0);
+        let (abort, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, client);
+        assert_eq!(abort[4..], [0x01, 0x00, 0x71, 0x09, 0x01]);
+
+        // Still above the low mark: keep aborting
+        gateway.set_mstp_queue_depth(10);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_none());
+        assert_eq!(gateway.get_stats().backpressure_aborts, 2);
+
+        // Drained to the low mark: routing resumes
+        gateway.set_mstp_queue_depth(8);
+        assert!(!gateway.backpressure_active());
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_some());
+        assert_eq!(gateway.active_transaction_count(), 1);
+    }
+
+    #[test]
+    fn test_secure_bvll_counted_per_source_and_dropped() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let peer: SocketAddr = "192.168.1.60:47808".parse().unwrap();
+        let other: SocketAddr = "192.168.1.61:47808".parse().unwrap();
+        let secure = [0x81, BVLC_SECURE_BVLL, 0x00, 0x08, 0xDE, 0xAD, 0xBE, 0xEF];
+
+        // Dropped silently by default: counted, never answered or routed
+        let sent = gateway.ip_send_queue.len();
+        assert!(gateway.route_from_ip(&secure, peer).unwrap().is_none());
+        assert!(gateway.route_from_ip(&secure, other).unwrap().is_none());
+        assert_eq!(gateway.ip_send_queue.len(), sent);
+        assert_eq!(gateway.get_stats().routing_errors, 0);
+
+        // Opted in to answering: refused with a BVLC-Result NAK
+        gateway.set_secure_bvll_drop(false);
+        assert!(gateway.route_from_ip(&secure, peer).unwrap().is_none());
+        let (nak, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, peer);
+        assert_eq!(nak[..], [0x81, BVLC_RESULT, 0x00, 0x06, 0x00, 0x70]);
+
+        assert_eq!(gateway.get_stats().secure_bvll_received, 3);
+        assert_eq!(gateway.secure_bvll_sources(), vec![(peer.ip(), 2), (other.ip(), 1)]);
+    }
+
+    #[test]
+    fn test_write_protect_refuses_write_bdt() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let peer: SocketAddr = "192.168.1.60:47808".parse().unwrap();
+        // One entry: 192.168.2.10:47808, mask 255.255.255.255
+        let write_bdt = [
+            0x81, BVLC_WRITE_BDT, 0x00, 0x0E, 192, 168, 2, 10, 0xBA, 0xC0, 255, 255, 255, 255,
+        ];
+
+        gateway.set_write_protected(true);
+        assert!(gateway.route_from_ip(&write_bdt, peer).unwrap().is_none());
+        let (nak, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, peer);
+        assert_eq!(nak[..], [0x81, BVLC_RESULT, 0x00, 0x06, 0x00, 0x10]);
+        assert!(gateway.get_bdt_entries().is_empty());
+
+        gateway.set_write_protected(false);
+        assert!(gateway.route_from_ip(&write_bdt, peer).unwrap().is_none());
+        let (ack, _) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(ack[..], [0x81, BVLC_RESULT, 0x00, 0x06, 0x00, 0x00]);
+        assert_eq!(gateway.get_bdt_entries().len(), 1);
+    }
+
+    #[test]
+    fn test_standby_routes_and_sends_nothing() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let peer: SocketAddr = "192.168.1.60:47808".parse().unwrap();
+        let who_is = [0x01, 0x00, 0x10, 0x08];
+        let write_bdt = [
+            0x81, BVLC_WRITE_BDT, 0x00, 0x0E, 192, 168, 2, 10, 0xBA, 0xC0, 255, 255, 255, 255,
+        ];
+
+        gateway.set_standby(true);
+        assert!(gateway.route_from_mstp(&who_is, 5).unwrap().is_none());
+        assert!(gateway.route_from_ip(&write_bdt, peer).unwrap().is_none());
+        assert!(gateway.ip_send_queue.is_empty());
+        assert!(gateway.get_bdt_entries().is_empty());
+        assert!(gateway.announce_router(true).is_none());
+
+        // Taking over routes again and announces straight away
+        gateway.set_standby(false);
+        gateway.route_from_mstp(&who_is, 5).unwrap();
+        assert!(!gateway.ip_send_queue.is_empty());
+        assert!(gateway.announce_router(true).is_some());
+    }
+
+    #[test]
+    fn test_router_announce_schedule() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let i_am_router = |network: u16| {
+            let [hi, lo] = network.to_be_bytes();
+            vec![0x01, 0x80, NL_I_AM_ROUTER_TO_NETWORK, hi, lo]
+        };
+
+        // Held back until the MS/TP token has been acquired, and still due after
+        assert!(gateway.announce_router(false).is_none());
+        assert!(gateway.ip_send_queue.is_empty());
+        let mstp = gateway.announce_router(true).unwrap();
+        assert_eq!(mstp[0], i_am_router(2));
+        let (bvlc, _) = &gateway.ip_send_queue[0];
+        assert_eq!(bvlc[4..], i_am_router(1));
+
+        // Not due again within the period
+        gateway.ip_send_queue.clear();
+        assert!(gateway.announce_router(true).is_none());
+        assert!(gateway.ip_send_queue.is_empty());
+
+        // A request announces straight away, once
+        gateway.request_router_announce();
+        assert!(gateway.announce_router(false).is_none());
+        assert_eq!(gateway.announce_router(true).unwrap(), vec![i_am_router(2)]);
+        assert!(gateway.announce_router(true).is_none());
+
+        // Due again once the period has passed
+        gateway.last_router_announce = Some(Instant::now() - ROUTER_ANNOUNCE_PERIOD);
+        assert_eq!(gateway.announce_router(true).unwrap(), vec![i_am_router(2)]);
+        assert!(gateway.announce_router(true).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_network_number_detection() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let router: SocketAddr = "192.168.1.60:47808".parse().unwrap();
+        let own: SocketAddr = "192.168.1.100:47808".parse().unwrap();
+        let i_am_router = |networks: &[u16]| {
+            let mut frame = vec![0x81, BVLC_ORIGINAL_BROADCAST, 0x00, 0x00, 0x01, 0x80, NL_I_AM_ROUTER_TO_NETWORK];
+            for network in networks {
+                frame.extend_from_slice(&network.to_be_bytes());
+            }
+            frame[3] = frame.len() as u8;
+            frame
+        };
+        let who_is_mstp = [0x81, BVLC_ORIGINAL_UNICAST, 0x00, 0x0C, 0x01, 0x20, 0x00, 0x01, 0x00, 0xFF, 0x10, 0x08];
+
+        // Other networks, and our own announcement echoed back, are no conflict
+        gateway.route_from_ip(&i_am_router(&[7, 8]), router).unwrap();
+        gateway.route_from_ip(&i_am_router(&[1]), own).unwrap();
+        assert!(gateway.network_conflict().is_none());
+
+        // Another router claiming network 1 is flagged once
+        gateway.set_suppress_on_conflict(true);
+        gateway.route_from_ip(&i_am_router(&[7, 1]), router).unwrap();
+        let conflict = gateway.take_new_network_conflict().unwrap();
+        assert_eq!((conflict.network, conflict.router, conflict.count), (1, router, 1));
+        assert!(gateway.take_new_network_conflict().is_none());
+        gateway.route_from_ip(&i_am_router(&[1]), router).unwrap();
+        assert_eq!(gateway.network_conflict().unwrap().count, 2);
+        assert!(gateway.take_new_network_conflict().is_none());
+
+        // Routing into the MS/TP network stands down, and it is no longer announced
+        assert!(gateway.route_from_ip(&who_is_mstp, router).unwrap().is_none());
+        assert!(gateway.announced_networks(true).is_empty());
+
+        // Cleared by hand
+        gateway.clear_network_conflict();
+        assert!(gateway.network_conflict().is_none());
+        assert!(gateway.route_from_ip(&who_is_mstp, router).unwrap().is_some());
+        assert_eq!(gateway.announced_networks(true), vec![1]);
+
+        // Or once the other router has gone quiet
+        gateway.route_from_ip(&i_am_router(&[1]), router).unwrap();
+        assert!(gateway.take_new_network_conflict().is_some());
+        gateway.process_housekeeping();
+        assert!(gateway.network_conflict().is_some());
+        gateway.network_conflict.as_mut().unwrap().last_seen = Instant::now() - DUPLICATE_NETWORK_HOLD;
+        gateway.process_housekeeping();
+        assert!(gateway.network_conflict().is_none());
+    }
+
+    #[test]
+    fn test_protocol_analysis_counts_nonconformant_frames_per_source() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let who_is = [0x01, 0x00, 0x10, 0x08];
+        // Who-Is with a low limit but no high limit
+        let half_who_is = [0x01, 0x00, 0x10, 0x08, 0x09, 0x01];
+
+        gateway.route_from_mstp(&half_who_is, 5).unwrap();
+        assert!(gateway.analyzer_reports().is_empty());
+
+        gateway.set_protocol_analysis(true);
+        gateway.route_from_mstp(&who_is, 5).unwrap();
+        gateway.route_from_mstp(&half_who_is, 5).unwrap();
+        gateway.route_from_mstp(&who_is, 6).unwrap();
+
+        let reports = gateway.analyzer_reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].source, TalkerAddress::Mstp(5));
+        assert_eq!((reports[0].checked, reports[0].nonconformant()), (2, 1));
+        assert_eq!(reports[0].examples[0].violation, Violation::ServiceParameters);
+        assert_eq!(reports[0].examples[0].bytes, half_who_is[2..]);
+        assert_eq!((reports[1].checked, reports[1].nonconformant()), (1, 0));
+
+        gateway.set_protocol_analysis(false);
+        assert!(gateway.analyzer_reports().is_empty());
+        assert!(gateway.protocol_analysis_started().is_none());
+    }
+
+    #[test]
+    fn test_lenient_npdu_mode_repairs_and_counts_quirks() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // I-Am from MS/TP MAC 5 with a hop count but no destination specifier
+        let i_am = [0x01, 0x00, 0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05];
+        // DNET/DADR for MS/TP MAC 5 with the hop count left off
+        let npdu = [0x01, 0x20, 0x00, 0x01, 0x01, 0x05];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+
+        // Strict: the I-Am is not understood and the truncated header is refused
+        gateway.route_from_mstp(&i_am, 5).unwrap();
+        assert!(gateway.device_bindings().is_empty());
+        assert!(gateway.route_from_ip(&bvlc, client).is_err());
+        assert_eq!(gateway.get_stats().npdu_quirks, [0; 4]);
+
+        gateway.set_npdu_lenient(true);
+        gateway.ip_send_queue.clear();
+        gateway.route_from_mstp(&i_am, 5).unwrap();
+        gateway.route_from_mstp(&i_am, 5).unwrap();
+        assert_eq!(gateway.device_bindings().len(), 1);
+        // Forwarded to IP with the stray octet removed
+        let (forwarded, _) = gateway.ip_send_queue.last().unwrap();
+        assert!(forwarded.ends_with(&[0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05]));
+        assert!(gateway.route_from_ip(&bvlc, client).is_ok());
+
+        let stats = gateway.get_stats();
+        assert_eq!(stats.npdu_quirks[NpduQuirk::StrayHopCount as usize], 2);
+        assert_eq!(stats.npdu_quirks[NpduQuirk::MissingHopCount as usize], 1);
+        // Logged once per source and quirk
+        assert_eq!(gateway.npdu_quirk_sources.len(), 2);
+    }
+
+    #[test]
+    fn test_pinned_address_bindings_survive_aging_and_flush() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let a: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+        let b: SocketAddr = "192.168.1.51:47808".parse().unwrap();
+        gateway.learn_ip_address(a, 5);
+        gateway.learn_ip_address(b, 6);
+        gateway.learn_mstp_address(5, a);
+
+        assert_eq!(BindingKey::parse("ip", "192.168.1.50:47808"), Some(BindingKey::Ip(a)));
+        assert_eq!(BindingKey::parse("mstp", "300"), None);
+        assert!(gateway.apply_binding_edit(BindingEdit::Pin(BindingKey::Ip(a), true)));
+        assert!(!gateway.apply_binding_edit(BindingEdit::Pin(BindingKey::Mstp(9), true)));
+
+        // Everything unpinned ages out at once
+        gateway.set_address_max_age(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(2));
+        gateway.process_housekeeping();
+        let bindings = gateway.ip_to_mstp_bindings();
+        assert_eq!(bindings.len(), 1);
+        assert_eq!((bindings[0].ip_address, bindings[0].mstp_address, bindings[0].pinned), (a, 5, true));
+        assert!(gateway.mstp_to_ip_bindings().is_empty());
+
+        gateway.learn_mstp_address(6, b);
+        assert!(gateway.apply_binding_edit(BindingEdit::Flush));
+        assert!(gateway.mstp_to_ip_bindings().is_empty());
+        assert_eq!(gateway.ip_to_mstp_bindings().len(), 1);
+        assert!(gateway.apply_binding_edit(BindingEdit::Remove(BindingKey::Ip(a))));
+        assert!(gateway.ip_to_mstp_bindings().is_empty());
+    }
+
+    #[test]
+    fn test_peer_stats_track_requests_errors_and_rejects() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        gateway.set_mstp_max_npdu(100);
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+        let wrap = |npdu: &[u8]| {
+            let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+            bvlc.extend_from_slice(npdu);
+            bvlc
+        };
+
+        // Confirmed ReadProperty (invoke_id 10) to MS/TP MAC 5: routed
+        let read = [0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, 0x0A, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x55];
+        assert!(gateway.route_from_ip(&wrap(&read), client).unwrap().is_some());
+
+        // Too short to be a BVLC frame
+        assert!(gateway.route_from_ip(&[0x81, 0x0A], client).is_err());
+
+        // Too long for MS/TP: rejected back to the client
+        let mut long = read.to_vec();
+        long.resize(160, 0x00);
+        assert!(gateway.route_from_ip(&wrap(&long), client).unwrap().is_none());
+
+        let peers = gateway.peer_stats();
+        assert_eq!(peers.len(), 1);
+        let peer = &peers[0];
+        assert_eq!(peer.addr, client);
+        assert_eq!(peer.packets_received, 3);
+        assert_eq!(peer.bytes_received, (read.len() + 4 + 2 + 164) as u64);
+        assert_eq!((peer.confirmed_requests, peer.errors, peer.rejected), (1, 1, 1));
+        assert_eq!(peer.packets_sent, 1);
+    }
+
+    #[test]
+    fn test_broadcast_forms_and_supervisory_copy() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let directed: SocketAddr = "192.168.1.255:47808".parse().unwrap();
+        let limited: SocketAddr = "255.255.255.255:47808".parse().unwrap();
+        let supervisor: SocketAddr = "10.0.0.5:47809".parse().unwrap();
+        let iam = [0x01, 0x20, 0xFF, 0xFF, 0x00, 0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05];
+
+        let route = |gateway: &mut BacnetGateway| {
+            gateway.ip_send_queue.clear();
+            assert!(gateway.route_from_mstp(&iam, 5).unwrap().is_none());
+            gateway.ip_send_queue.iter().map(|(_, dest)| *dest).collect::<Vec<_>>()
+        };
+
+        assert_eq!(route(&mut gateway), vec![directed]);
+
+        gateway.set_broadcast_form(BroadcastForm::Limited, &[]);
+        assert_eq!(route(&mut gateway), vec![limited]);
+
+        gateway.set_broadcast_form(BroadcastForm::Both, &[supervisor]);
+        assert_eq!(route(&mut gateway), vec![directed, limited, supervisor]);
+
+        // The supervisory copy is a Forwarded-NPDU from the gateway
+        let (copy, _) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(copy[..10], [0x81, BVLC_FORWARDED_NPDU, 0x00, copy.len() as u8, 192, 168, 1, 100, 0xBA, 0xC0]);
+
+        // A multicast group replaces both forms; the supervisory copy stays
+        let group = Ipv4Addr::new(239, 255, 186, 192);
+        gateway.set_multicast_group(Some(group));
+        assert_eq!(route(&mut gateway), vec![SocketAddr::new(IpAddr::V4(group), 47808), supervisor]);
+        gateway.set_multicast_group(None);
+
+        // Broadcasts off: supervisory stations only, counters kept for stations that stay
+        let second: SocketAddr = "10.0.0.6:47808".parse().unwrap();
+        gateway.set_broadcast_form(BroadcastForm::Disabled, &[supervisor, second]);
+        assert_eq!(route(&mut gateway), vec![supervisor, second]);
+        let counts: Vec<_> = gateway.supervisory_stations().iter().map(|s| (s.address, s.sent, s.failed)).collect();
+        assert_eq!(counts, vec![(supervisor, 3, 0), (second, 1, 0)]);
+
+        // Not without somewhere to send them
+        gateway.set_broadcast_form(BroadcastForm::Disabled, &[]);
+        assert_eq!(route(&mut gateway), vec![directed]);
+    }
+
+    #[test]
+    fn test_compat_profiles_shape_unicast_delivery() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "10.0.5.17:47808".parse().unwrap();
+        // Unconfirmed I-Am from MS/TP 5 addressed to the client on network 2
+        let iam = [0x01, 0x20, 0x00, 0x02, 0x06, 10, 0, 5, 17, 0xBA, 0xC0, 0xFF,
+                   0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05];
+        let apdu = &iam[12..];
+
+        let route = |gateway: &mut BacnetGateway| {
+            gateway.ip_send_queue.clear();
+            assert!(gateway.route_from_mstp(&iam, 5).unwrap().is_none());
+            let (bvlc, dest) = gateway.ip_send_queue.pop().unwrap();
+            assert_eq!(dest, client);
+            bvlc
+        };
+
+        // Default: Original-Unicast-NPDU with SNET/SADR
+        let bvlc = route(&mut gateway);
+        assert_eq!(bvlc[..6], [0x81, 0x0A, 0x00, 0x11, 0x01, 0x08]);
+        assert_eq!(bvlc[6..10], [0x00, 0x01, 0x01, 0x05]);
+
+        gateway.set_compat_rules(compat::parse_rules("10.0.5.0/24=jci-cct").unwrap());
+        let bvlc = route(&mut gateway);
+        assert_eq!(bvlc[..6], [0x81, 0x0A, 0x00, 0x0D, 0x01, 0x00]);
+        assert_eq!(&bvlc[6..], apdu);
+
+        gateway.set_compat_rules(compat::parse_rules("10.0.5.17=niagara").unwrap());
+        let bvlc = route(&mut gateway);
+        assert_eq!(bvlc[..10], [0x81, 0x04, 0x00, 0x17, 192, 168, 1, 100, 0xBA, 0xC0]);
+        assert_eq!(bvlc[10..16], [0x01, 0x08, 0x00, 0x01, 0x01, 0x05]);
+    }
+
+    #[test]
+    fn test_router_query_records_remote_routing_table() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let router: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        gateway.start_router_query(router, RouterQueryKind::RoutingTable).unwrap();
+        let (bvlc, dest) = gateway.ip_send_queue.pop().unwrap();
+        assert_eq!(dest, router);
+        assert_eq!(bvlc, [0x81, 0x0A, 0x00, 0x08, 0x01, 0x80, 0x06, 0x00]);
+
+        // Ack with networks 5 and 6 behind ports 1 and 2: recorded, not routed on
+        let ack = [0x81, 0x0A, 0x00, 0x11, 0x01, 0x80, 0x07, 0x02, 0x00, 0x05, 0x01, 0x00, 0x00, 0x06, 0x02, 0x01, 0x7F];
+        assert!(gateway.route_from_ip(&ack, router).unwrap().is_none());
+        let query = gateway.router_query().unwrap();
+        assert!(query.answered);
+        assert_eq!(query.routes.iter().map(|r| r.network).collect::<Vec<_>>(), vec![5, 6]);
+        assert_eq!(query.routes[1].port_info, vec![0x7F]);
+    }
+
+    #[test]
+    fn test_device_bindings_learned_from_i_am() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+
+        // Device 5 at MS/TP MAC 5, and device 9 behind another router on network 7
+        let local = [0x01, 0x20, 0xFF, 0xFF, 0x00, 0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05];
+        gateway.route_from_mstp(&local, 5).unwrap();
+        let remote = [0x01, 0x28, 0xFF, 0xFF, 0x00, 0x00, 0x07, 0x01, 0x0A, 0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x09];
+        gateway.route_from_mstp(&remote, 12).unwrap();
+
+        // Device 1000 on the IP side
+        let npdu = [0x01, 0x00, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x03, 0xE8, 0x22, 0x01, 0xE0, 0x91, 0x00, 0x21, 0x05];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_BROADCAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+        gateway.route_from_ip(&bvlc, "192.168.1.60:47808".parse().unwrap()).unwrap();
+
+        // Not a Device object: ignored
+        let not_device = [0x01, 0x00, 0x10, 0x00, 0xC4, 0x00, 0x00, 0x00, 0x01];
+        gateway.route_from_mstp(&not_device, 6).unwrap();
+
+        let binding = |device_instance, network, mac: &[u8], on_mstp| DeviceBinding { device_instance, network, mac: mac.to_vec(), on_mstp };
+        assert_eq!(gateway.device_bindings(), vec![
+            binding(5, 1, &[5], true),
+            binding(9, 7, &[0x0A], true),
+            binding(1000, 2, &[192, 168, 1, 60, 0xBA, 0xC0], false),
+        ]);
+
+        // A device that moves is rebound, not duplicated
+        gateway.route_from_mstp(&local, 8).unwrap();
+        assert_eq!(gateway.device_bindings()[0], binding(5, 1, &[8], true));
+        assert_eq!(gateway.device_bindings().len(), 3);
+    }
+
+    #[test]
+    fn test_inventory_reads_through_mstp() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        assert!(gateway.start_inventory(&[(1234, 5)]));
+        assert!(!gateway.start_inventory(&[(1234, 5)]), "one inventory at a time");
+
+        // The first read goes out with the periodic housekeeping
+        gateway.process_transaction_timeouts();
+        let queued = gateway.drain_mstp_send_queue();
+        assert_eq!(queued.len(), 1);
+        let (npdu, mac) = &queued[0];
+        assert_eq!(*mac, 5);
+        assert_eq!(npdu[..], [0x01, 0x04, 0x00, 0x03, 0x00, 0x0C, 0x0C, 0x02, 0x00, 0x04, 0xD2, 0x19, 76, 0x29, 0x00]);
+
+        // Object_List has no entries: the device is done and the report handed over
+        let ack = [0x01, 0x00, 0x30, 0x00, 0x0C, 0x0C, 0x02, 0x00, 0x04, 0xD2, 0x19, 76, 0x29, 0x00, 0x3E, 0x21, 0x00, 0x3F];
+        assert!(gateway.route_from_mstp(&ack, 5).unwrap().is_none());
+        assert!(gateway.ip_send_queue.is_empty(), "inventory answers are not routed to IP");
+        assert!(gateway.inventory_progress().is_none());
+        let devices = gateway.take_finished_inventory().unwrap();
+        assert_eq!(devices[0].object_count, 0);
+        assert!(gateway.take_finished_inventory().is_none());
+    }
+
+    #[test]
+    fn test_device_name_read_after_i_am() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let i_am = [0x01, 0x20, 0xFF, 0xFF, 0x00, 0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05];
+        gateway.route_from_mstp(&i_am, 5).unwrap();
+        gateway.ip_send_queue.clear();
+
+        // Object_Name of Device 5 is read from MS/TP 5 with the housekeeping
+        gateway.process_transaction_timeouts();
+        let queued = gateway.drain_mstp_send_queue();
+        assert_eq!(queued.len(), 1);
+        let (npdu, mac) = &queued[0];
+        assert_eq!(*mac, 5);
+        assert_eq!(npdu[..], [0x01, 0x04, 0x00, 0x03, 0x00, 0x0C, 0x0C, 0x02, 0x00, 0x00, 0x05, 0x19, 77]);
+
+        let ack = [0x01, 0x00, 0x30, 0x00, 0x0C, 0x0C, 0x02, 0x00, 0x00, 0x05, 0x19, 77, 0x3E, 0x73, 0x00, b'A', b'B', 0x3F];
+        assert!(gateway.route_from_mstp(&ack, 5).unwrap().is_none());
+        assert!(gateway.ip_send_queue.is_empty(), "name answers are not routed to IP");
+        assert_eq!(gateway.device_names().get(&5).map(String::as_str), Some("AB"));
+        assert_eq!(gateway.device_names_pending(), 0);
+        gateway.refresh_device_names();
+        assert_eq!(gateway.device_names_pending(), 1);
+    }
+
+    #[test]
+    fn test_client_call_reads_through_mstp() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let reference = PropertyRef { object_id: (2 << 22) | 3, property_id: 85, array_index: None };
+        gateway.start_client_call(ClientCall::new(5, ClientRequest::ReadProperty(reference)));
+
+        gateway.process_transaction_timeouts();
+        let queued = gateway.drain_mstp_send_queue();
+        assert_eq!(queued.len(), 1);
+        let (npdu, mac) = &queued[0];
+        assert_eq!(*mac, 5);
+        assert_eq!(npdu[..], [0x01, 0x04, 0x00, 0x03, 0x00, 0x0C, 0x0C, 0x00, 0x80, 0x00, 0x03, 0x19, 85]);
+        assert!(gateway.take_client_outcome().is_none());
+
+        let ack = [0x01, 0x00, 0x30, 0x00, 0x0C, 0x0C, 0x00, 0x80, 0x00, 0x03, 0x19, 85, 0x3E, 0x91, 0x01, 0x3F];
+        assert!(gateway.route_from_mstp(&ack, 5).unwrap().is_none());
+        assert!(gateway.ip_send_queue.is_empty(), "client call answers are not routed to IP");
+        assert_eq!(gateway.take_client_outcome(), Some(ClientOutcome::Value("enum 1".to_string())));
+        assert!(gateway.take_client_outcome().is_none());
+    }
+
+    #[test]
+    fn test_going_down_announces_router_busy() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+
+        let mstp_busy = gateway.announce_going_down();
+        assert_eq!(mstp_busy, [0x01, 0x80, 0x04, 0x00, 0x02]);
+
+        let (bvlc, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, "192.168.1.255:47808".parse::<SocketAddr>().unwrap());
+        assert_eq!(bvlc[..], [0x81, BVLC_ORIGINAL_BROADCAST, 0x00, 0x09, 0x01, 0x80, 0x04, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_apsta_broadcasts_reach_both_subnets() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        gateway.set_ap_interface(Some((Ipv4Addr::new(192, 168, 4, 1), Ipv4Addr::new(255, 255, 255, 0))));
+
+        gateway.announce_going_down();
+        let dests: Vec<SocketAddr> = gateway.ip_send_queue.iter().map(|(_, d)| *d).collect();
+        assert!(dests.contains(&"192.168.1.255:47808".parse().unwrap()));
+        assert!(dests.contains(&"192.168.4.255:47808".parse().unwrap()));
+
+        assert!(gateway.is_own_address("192.168.4.1:47808".parse().unwrap()));
+        assert!(gateway.is_own_address("192.168.1.100:47808".parse().unwrap()));
+        gateway.set_ap_interface(None);
+        assert!(!gateway.is_own_address("192.168.4.1:47808".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ranged_who_is_for_ip_devices_not_forwarded() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let who_is = |low: u8, high: u8| {
+            let npdu = [0x01, 0x00, 0x10, 0x08, 0x09, low, 0x19, high];
+            let mut bvlc = vec![0x81, BVLC_ORIGINAL_BROADCAST, 0x00, (npdu.len() + 4) as u8];
+            bvlc.extend_from_slice(&npdu);
+            bvlc
+        };
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // Devices 10 and 11 announce on IP, device 12 on MS/TP
+        for (instance, port) in [(10u8, 0xC1u8), (11, 0xC2)] {
+            let npdu = [0x01, 0x00, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, instance, 0x22, 0x01, 0xE0, 0x91, 0x00, 0x21, 0x05];
+            let mut bvlc = vec![0x81, BVLC_ORIGINAL_BROADCAST, 0x00, (npdu.len() + 4) as u8];
+            bvlc.extend_from_slice(&npdu);
+            gateway.route_from_ip(&bvlc, SocketAddr::new(Ipv4Addr::new(192, 168, 1, 60).into(), 0xBA00 | port as u16)).unwrap();
+        }
+        let i_am = [0x01, 0x00, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x0C];
+        gateway.route_from_mstp(&i_am, 5).unwrap();
+
+        assert!(gateway.route_from_ip(&who_is(10, 11), client).unwrap().is_none());
+        assert!(gateway.route_from_ip(&who_is(11, 11), client).unwrap().is_none());
+        assert_eq!(gateway.get_stats().whois_suppressed, 2);
+
+        // Range includes an MS/TP device or an unknown instance: forwarded
+        assert!(gateway.route_from_ip(&who_is(10, 12), client).unwrap().is_some());
+        assert!(gateway.route_from_ip(&who_is(13, 13), client).unwrap().is_some());
+        // Unbounded Who-Is is always forwarded
+        let npdu = [0x01, 0x00, 0x10, 0x08];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_BROADCAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_some());
+
+        gateway.set_whois_filter(false);
+        assert!(gateway.route_from_ip(&who_is(10, 11), client).unwrap().is_some());
+        assert_eq!(gateway.get_stats().whois_suppressed, 2);
+    }
+
+    #[test]
+    fn test_rpm_proxied_after_device_rejects_it() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // RPM (invoke_id 0x21) to MS/TP MAC 5: AI-1 Present_Value and Status_Flags
+        let npdu = [
+            0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF,
+            0x00, 0x05, 0x21, 0x0E, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x1E, 0x09, 0x55, 0x09, 0x6F, 0x1F,
+        ];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_some());
+
+        // The device rejects RPM (unrecognized service): the gateway reads the properties itself
+        let reject = [0x01, 0x20, 0x00, 0x02, 0x06, 192, 168, 1, 50, 0xBA, 0xC0, 0xFF, 0x60, 0x21, 0x09];
+        let sent = gateway.ip_send_queue.len();
+        let (request, mac) = gateway.route_from_mstp(&reject, 5).unwrap().unwrap();
+        assert_eq!(mac, 5);
+        assert_eq!(request, [0x01, 0x04, 0x00, 0x03, 0x00, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x55]);
+        assert_eq!(gateway.ip_send_queue.len(), sent, "the Reject must not reach the client");
+        assert_eq!(gateway.active_transaction_count(), 0);
+
+        let ack = [0x01, 0x00, 0x30, 0x00, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x55, 0x3E, 0x44, 0x42, 0x91, 0x00, 0x00, 0x3F];
+        let (request, _) = gateway.route_from_mstp(&ack, 5).unwrap().unwrap();
+        assert_eq!(request[4], 0x01);
+        assert_eq!(request[12], 0x6F);
+
+        let ack = [0x01, 0x00, 0x30, 0x01, 0x0C, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x6F, 0x3E, 0x82, 0x04, 0x00, 0x3F];
+        assert!(gateway.route_from_mstp(&ack, 5).unwrap().is_none());
+
+        // ReadPropertyMultiple-ACK to the client, sourced from MS/TP network 1 MAC 5
+        let (reply, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, client);
+        assert_eq!(
+            reply[4..],
+            [
+                0x01, 0x08, 0x00, 0x01, 0x01, 0x05,
+                0x30, 0x21, 0x0E, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x1E,
+                0x29, 0x55, 0x4E, 0x44, 0x42, 0x91, 0x00, 0x00, 0x4F,
+                0x29, 0x6F, 0x4E, 0x82, 0x04, 0x00, 0x4F, 0x1F,
+            ]
+        );
+
+        // Later RPMs to the same device are translated straight away
+        let (request, mac) = gateway.route_from_ip(&bvlc, client).unwrap().unwrap();
+        assert_eq!(mac, 5);
+        assert_eq!(request[..6], [0x01, 0x04, 0x00, 0x03, 0x02, 0x0C]);
+        assert_eq!(gateway.get_stats().rpm_proxied, 2);
+    }
+
+    #[test]
+    fn test_wpm_proxied_after_device_rejects_it() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // WPM (invoke_id 0x31) to MS/TP MAC 5: AV-1 Present_Value = 21.5
+        let npdu = [
+            0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF,
+            0x00, 0x05, 0x31, 0x10, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x1E,
+            0x09, 0x55, 0x2E, 0x44, 0x41, 0xAC, 0x00, 0x00, 0x2F, 0x1F,
+        ];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_some());
+
+        // The device rejects WPM (unrecognized service): the gateway writes the value itself
+        let reject = [0x01, 0x20, 0x00, 0x02, 0x06, 192, 168, 1, 50, 0xBA, 0xC0, 0xFF, 0x60, 0x31, 0x09];
+        let sent = gateway.ip_send_queue.len();
+        let (request, mac) = gateway.route_from_mstp(&reject, 5).unwrap().unwrap();
+        assert_eq!(mac, 5);
+        assert_eq!(
+            request,
+            [0x01, 0x04, 0x00, 0x03, 0x00, 0x0F, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x19, 0x55, 0x3E, 0x44, 0x41, 0xAC, 0x00, 0x00, 0x3F]
+        );
+        assert_eq!(gateway.ip_send_queue.len(), sent, "the Reject must not reach the client");
+
+        // SimpleACK for the WriteProperty completes the WPM
+        assert!(gateway.route_from_mstp(&[0x01, 0x00, 0x20, 0x00, 0x0F], 5).unwrap().is_none());
+        let (reply, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, client);
+        assert_eq!(reply[4..], [0x01, 0x08, 0x00, 0x01, 0x01, 0x05, 0x20, 0x31, 0x10]);
+
+        // Later WPMs to the same device are translated straight away
+        let (request, _) = gateway.route_from_ip(&bvlc, client).unwrap().unwrap();
+        assert_eq!(request[..6], [0x01, 0x04, 0x00, 0x03, 0x01, 0x0F]);
+        assert_eq!(gateway.get_stats().wpm_proxied, 2);
+    }
+
+    #[test]
+    fn test_service_errors_counted_per_reason() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // Confirmed ReadProperty (invoke_id 9) to MS/TP MAC 5, answered with property/unknown-property
+        let npdu = [0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, 0x09, 0x0C, 0x0C, 0x02, 0x00, 0x00, 0x01, 0x19, 0xF0];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+        gateway.route_from_ip(&bvlc, client).unwrap();
+        let error = [0x01, 0x08, 0x00, 0x02, 0x06, 192, 168, 1, 50, 0xBA, 0xC0, 0x50, 0x09, 0x0C, 0x91, 0x02, 0x91, 0x20];
+        gateway.route_from_mstp(&error, 5).unwrap();
+
+        let counters = gateway.service_counters();
+        assert_eq!(counters, vec![(12, ServiceCounters { requests: 1, errors: 1, ..Default::default() })]);
+        let (top, untracked) = gateway.top_service_errors(5);
+        assert_eq!(untracked, 0);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].reason.to_string(), "error property: unknown-property");
+        assert_eq!(top[0].last_mac, 5);
+    }
+
+    #[test]
+    fn test_abort_transaction_notifies_client() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+
+        // Confirmed ReadProperty (invoke_id 9) to MS/TP MAC 5 on network 1
+        let npdu = [0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, 0x09, 0x0C, 0x0C, 0x02, 0x00, 0x00, 0x01, 0x19, 0x4D];
+        let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+        bvlc.extend_from_slice(&npdu);
+        assert!(gateway.route_from_ip(&bvlc, client).unwrap().is_some());
+
+        let summaries = gateway.transaction_summaries();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].invoke_id, 9);
+        assert_eq!(summaries[0].dest_mac, 5);
+        assert_eq!(summaries[0].client, client);
+
+        assert!(gateway.abort_transaction(9, 5));
+        assert!(!gateway.abort_transaction(9, 5));
+        assert_eq!(gateway.active_transaction_count(), 0);
+
+        // Abort PDU (type 7) for invoke_id 9 sent back to the client
+        let (abort, dest) = gateway.ip_send_queue.last().unwrap();
+        assert_eq!(*dest, client);
+        assert_eq!(abort[6] >> 4, 7);
+        assert_eq!(abort[7], 9);
+    }
+
+    /// Frames a `RecordingLink` has sent, with their destinations
+    type SentFrames = std::sync::Arc<std::sync::Mutex<Vec<(Vec<u8>, LinkAddress)>>>;
+
+    /// Data link that records what the gateway sends
+    struct RecordingLink {
+        sent: SentFrames,
+        up: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl RecordingLink {
+        fn new() -> (Box<Self>, SentFrames) {
+            let (link, sent, _) = Self::with_switch();
+            (link, sent)
+        }
+
+        /// Also returns the switch behind `is_up()`
+        fn with_switch() -> (Box<Self>, SentFrames, std::sync::Arc<std::sync::atomic::AtomicBool>) {
+            let sent = SentFrames::default();
+            let up = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
+            let link = Self { sent: std::sync::Arc::clone(&sent), up: std::sync::Arc::clone(&up) };
+            (Box::new(link), sent, up)
+        }
+    }
+
+    impl DataLink for RecordingLink {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn send(&mut self, npdu: &[u8], dest: LinkAddress) -> Result<(), crate::datalink::DataLinkError> {
+            self.sent.lock().unwrap().push((npdu.to_vec(), dest));
+            Ok(())
+        }
+
+        fn poll(&mut self) -> Vec<crate::datalink::ReceivedFrame> {
+            Vec::new()
+        }
+
+        fn is_up(&self) -> bool {
+            self.up.load(std::sync::atomic::Ordering::Relaxed)
+        }
+
+        fn broadcast_addr(&self) -> LinkAddress {
+            LinkAddress::Ip("192.168.1.255:47808".parse().unwrap())
+        }
+    }
+
+    /// Default test gateway with its IP port on a `RecordingLink`
+    fn gateway_with_recording_link() -> (BacnetGateway, SentFrames) {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let (link, sent) = RecordingLink::new();
+        gateway.set_ip_link(link);
+        (gateway, sent)
+    }
+
+    #[test]
+    fn test_ip_traffic_goes_through_data_link() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let npdu = [0x01, 0x00, 0x10, 0x08]; // Who-Is
+
+        // Sent before the link exists: queued, then flushed when it is set
+        gateway.broadcast_on_ip(&npdu).unwrap();
+        let (link, sent) = RecordingLink::new();
+        gateway.set_ip_link(link);
+        gateway.broadcast_on_ip(&npdu).unwrap();
+
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        for (frame, dest) in sent.iter() {
+            assert_eq!(*frame, build_bvlc(&npdu, true));
+            assert_eq!(*dest, LinkAddress::Ip("192.168.1.255:47808".parse().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_mstp_traffic_goes_through_data_link() {
+        let mut gateway = BacnetGateway::new_default(1, 2, Ipv4Addr::new(192, 168, 1, 100));
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+        // Confirmed ReadProperty to MS/TP MAC 5 on network 1
+        let request = |invoke_id: u8| {
+            let npdu = [0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, invoke_id, 0x0C];
+            let mut bvlc = vec![0x81, BVLC_ORIGINAL_UNICAST, 0x00, (npdu.len() + 4) as u8];
+            bvlc.extend_from_slice(&npdu);
+            bvlc
+        };
+
+        // Routed before the link exists: queued, then sent when it is set
+        gateway.receive_from_ip(&request(1), client).unwrap();
+        let (link, sent, up) = RecordingLink::with_switch();
+        gateway.set_mstp_link(link);
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        // Held while the ring is down, released once it is back
+        up.store(false, std::sync::atomic::Ordering::Relaxed);
+        gateway.receive_from_ip(&request(2), client).unwrap();
+        assert_eq!(gateway.held_frame_count(), 1);
+        assert_eq!(gateway.send_held_frames(16), 0);
+        up.store(true, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(gateway.send_held_frames(16), 1);
+
+        gateway.receive_from_ip(&request(3), client).unwrap();
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert!(sent.iter().all(|(_, dest)| *dest == LinkAddress::Mstp(5)));
+        assert_eq!(gateway.held_frame_count(), 0);
+    }
+
+    #[test]
+    fn test_pair_probe_goes_through_the_bdt() {
+        let (mut gateway, sent) = gateway_with_recording_link();
+        let peer: SocketAddr = "10.20.0.5:47808".parse().unwrap();
+        gateway.add_bdt_entry(peer, Ipv4Addr::BROADCAST);
+
+        gateway.start_pair_probe(peer, 7).unwrap();
+        let forwarded = sent.lock().unwrap().iter().find(|(_, dest)| *dest == LinkAddress::Ip(peer)).cloned().unwrap().0;
+        assert_eq!(forwarded[1], BVLC_FORWARDED_NPDU);
+        assert_eq!(&forwarded[10..], [0x01, 0x80, 0x00, 0x00, 0x07]);
+
+        // The peer announces network 7 back through its own BDT
+        let answer = [0x81, BVLC_FORWARDED_NPDU, 0x00, 0x11, 10, 20, 0, 5, 0xBA, 0xC0, 0x01, 0x80, 0x01, 0x00, 0x07, 0x00, 0x02];
+        gateway.route_from_ip(&answer, peer).unwrap();
+        let probe = gateway.pair_probe().unwrap();
+        assert!(probe.answered);
+        assert_eq!(probe.networks, vec![7, 2]);
+    }
+
+    #[test]
+    fn test_network_probe_collects_routers_for_new_networks() {
+        let (mut gateway, sent) = gateway_with_recording_link();
+
+        let mstp_queries = gateway.start_network_probe(&[5]).unwrap();
+        assert_eq!(mstp_queries, vec![vec![0x01, 0x80, 0x00, 0x00, 0x05]]);
+        assert!(sent.lock().unwrap().iter().any(|(frame, _)| frame[4..] == [0x01, 0x80, 0x00, 0x00, 0x05]));
+
+        // A router on IP reaching networks 4 and 5, one on MS/TP reaching only 9
+        let router: SocketAddr = "192.168.1.20:47808".parse().unwrap();
+        let i_am = [0x81, 0x0B, 0x00, 0x0B, 0x01, 0x80, 0x01, 0x00, 0x04, 0x00, 0x05];
+        gateway.route_from_ip(&i_am, router).unwrap();
+        gateway.route_from_mstp(&[0x01, 0x80, 0x01, 0x00, 0x09], 12).unwrap();
+        assert_eq!(gateway.take_network_probe_answers(), vec![("192.168.1.20:47808".to_string(), vec![5])]);
+        assert!(gateway.take_network_probe_answers().is_empty());
+
+        gateway.stop_network_probe();
+        gateway.route_from_ip(&i_am, router).unwrap();
+        assert!(gateway.take_network_probe_answers().is_empty());
+    }
+
+    #[test]
+    fn test_router_announcement_scope_and_further_networks() {
+        let (mut gateway, sent) = gateway_with_recording_link();
+        let i_am_on_ip = || -> Vec<Vec<u8>> {
+            let mut sent = sent.lock().unwrap();
+            sent.drain(..)
+                .filter(|(frame, _)| frame[6] == NL_I_AM_ROUTER_TO_NETWORK)
+                .map(|(frame, _)| frame[7..].to_vec())
+                .collect()
+        };
+
+        // 300 is behind an MS/TP router, 400 behind an IP router; 500 has no route and is left out
+        let router: SocketAddr = "192.168.1.20:47808".parse().unwrap();
+        assert!(gateway.add_static_route(300, RouteNextHop::Mstp(10)));
+        assert!(gateway.add_static_route(400, RouteNextHop::Ip(router)));
+        gateway.set_router_announcements(AnnounceScope::Both, &[300, 400, 500]);
+        let mstp = gateway.announce_router(true).unwrap();
+        assert_eq!(mstp[0], [0x01, 0x80, NL_I_AM_ROUTER_TO_NETWORK, 0x00, 0x02, 0x01, 0x90]);
+        assert_eq!(i_am_on_ip(), vec![vec![0x00, 0x01, 0x01, 0x2C]]);
+
+        gateway.set_router_announcements(AnnounceScope::Mstp, &[300, 400]);
+        gateway.request_router_announce();
+        assert_eq!(gateway.announce_router(true).unwrap().len(), 1);
+        assert!(i_am_on_ip().is_empty());
+
+        // Nothing is announced while a port is administratively disabled
+        gateway.set_router_announcements(AnnounceScope::Both, &[300, 400]);
+        gateway.set_mstp_port_enabled(false);
+        gateway.request_router_announce();
+        assert!(gateway.announce_router(true).unwrap().is_empty());
+        assert!(i_am_on_ip().is_empty());
+    }
+
+    #[test]
+    fn test_ip_network_number_learned_from_network_number_is() {
+        let (mut gateway, sent) = gateway_with_recording_link();
+        gateway.set_ip_network_learning(true);
+        assert_eq!(gateway.router_mode(), RouterMode::PassThrough);
+
+        // Unknown: the IP network is not announced on MS/TP, and its number is asked for
+        let announcements = gateway.announce_router(true).unwrap();
+        assert!(announcements.iter().all(|npdu| npdu[2] != NL_I_AM_ROUTER_TO_NETWORK));
+        assert!(sent.lock().unwrap().iter().any(|(frame, _)| frame[4..] == [0x01, 0x80, NL_WHAT_IS_NETWORK_NUMBER]));
+
+        // Only a router with a configured number is learned from
+        let router: SocketAddr = "192.168.1.20:47808".parse().unwrap();
+        gateway.route_from_ip(&[0x81, 0x0B, 0x00, 0x0A, 0x01, 0x80, 0x13, 0x07, 0xD1, 0x00], router).unwrap();
+        assert_eq!(gateway.router_mode(), RouterMode::PassThrough);
+        gateway.route_from_ip(&[0x81, 0x0B, 0x00, 0x0A, 0x01, 0x80, 0x13, 0x07, 0xD1, 0x01], router).unwrap();
+        assert_eq!(gateway.ip_network_number(), NetworkNumber { number: 2001, quality: NetworkNumberQuality::Learned });
+        assert_eq!(gateway.router_mode(), RouterMode::Routing);
+
+        // Networks announced by other routers are noted for the setup wizard
+        gateway.route_from_ip(&[0x81, 0x0B, 0x00, 0x09, 0x01, 0x80, NL_I_AM_ROUTER_TO_NETWORK, 0x00, 0x01], router).unwrap();
+        assert_eq!(gateway.heard_networks().reachable.keys().copied().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(gateway.heard_networks().ip_network, Some(2001));
+
+        // What-Is-Network-Number on MS/TP is answered there, not routed
+        gateway.route_from_mstp(&[0x01, 0x80, NL_WHAT_IS_NETWORK_NUMBER], 5).unwrap();
+        assert_eq!(gateway.drain_mstp_send_queue(), vec![(vec![0x01, 0x80, NL_NETWORK_NUMBER_IS, 0x00, 0x01, 0x01], 0xFF)]);
+    }
+
+    #[test]
+    fn test_foreign_device_table_management() {
+        let (mut gateway, sent) = gateway_with_recording_link();
+        let register = [0x81, BVLC_REGISTER_FOREIGN_DEVICE, 0x00, 0x06, 0x00, 0x3C];
+        let fd: SocketAddr = "10.0.0.5:47808".parse().unwrap();
+        gateway.route_from_ip(&register, fd).unwrap();
+
+        // Global broadcast I-Am from MS/TP reaches the foreign device
+        let i_am = [0x01, 0x20, 0xFF, 0xFF, 0x00, 0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x0C, 0x22, 0x01, 0xE0, 0x91, 0x00, 0x21, 0x05];
+        gateway.route_from_mstp(&i_am, 5).unwrap();
+        let devices = gateway.foreign_device_summaries();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].address, fd);
+        assert_eq!(devices[0].ttl_seconds, 60);
+        assert_eq!(devices[0].forwarded, 1);
+
+        // Registrations rejected: NAK and no new entry
+        gateway.set_accept_fd_registrations(false);
+        let other: SocketAddr = "10.0.0.6:47808".parse().unwrap();
+        gateway.route_from_ip(&register, other).unwrap();
+        let (nak, dest) = sent.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(dest, LinkAddress::Ip(other));
+        assert_eq!(nak[4..6], BVLC_RESULT_REGISTER_FD_NAK.to_be_bytes());
+        assert_eq!(gateway.foreign_device_count(), 1);
+
+        assert!(gateway.remove_foreign_device(fd));
+        assert!(!gateway.remove_foreign_device(fd));
+        assert!(gateway.foreign_device_summaries().is_empty());
+    }
+
+    #[test]
+    fn test_remote_broadcast_reaches_bdt_and_foreign_devices() {
+        let (mut gateway, sent) = gateway_with_recording_link();
+        let register = [0x81, BVLC_REGISTER_FOREIGN_DEVICE, 0x00, 0x06, 0x00, 0x3C];
+        let fd: SocketAddr = "10.0.0.5:47808".parse().unwrap();
+        let peer: SocketAddr = "10.20.0.1:47808".parse().unwrap();
+        gateway.route_from_ip(&register, fd).unwrap();
+        gateway.add_bdt_entry(peer, Ipv4Addr::BROADCAST);
+        sent.lock().unwrap().clear();
+
+        // Who-Is from MS/TP 5 for the whole IP network (DNET=2, DLEN=0)
+        let who_is = [0x01, 0x20, 0x00, 0x02, 0x00, 0xFF, 0x10, 0x08];
+        assert!(gateway.route_from_mstp(&who_is, 5).unwrap().is_none());
+
+        let sent = sent.lock().unwrap();
+        let dests: Vec<_> = sent.iter().map(|(_, dest)| *dest).collect();
+        let directed: SocketAddr = "192.168.1.255:47808".parse().unwrap();
+        assert_eq!(dests, vec![LinkAddress::Ip(directed), LinkAddress::Ip(fd), LinkAddress::Ip(peer)]);
+        assert_eq!(sent[0].0[1], BVLC_ORIGINAL_BROADCAST);
+        for (frame, _) in &sent[1..] {
+            // Forwarded-NPDU from the gateway, carrying SNET=1 SADR=5
+            assert_eq!(frame[..10], [0x81, BVLC_FORWARDED_NPDU, 0x00, frame.len() as u8, 192, 168, 1, 100, 0xBA, 0xC0]);
+            assert!(frame[10..].windows(4).any(|w| w == [0x00, 0x01, 0x01, 0x05]));
+            assert_eq!(frame[frame.len() - 2..], [0x10, 0x08]);
+        }
+        assert_eq!(gateway.foreign_device_summaries()[0].forwarded, 1);
+    }
+
+    #[test]
+    fn test_discovery_reply_forwarded_to_fd_origin() {
+        let (mut gateway, sent) = gateway_with_recording_link();
+        let register = [0x81, BVLC_REGISTER_FOREIGN_DEVICE, 0x00, 0x06, 0x00, 0x3C];
+        let fd: SocketAddr = "10.0.0.5:47808".parse().unwrap();
+        gateway.route_from_ip(&register, fd).unwrap();
+
+        // Unicast I-Am before the foreign device asked: no copy
+        let client: SocketAddr = "192.168.1.50:47808".parse().unwrap();
+        let i_am = |dest: [u8; 6]| {
+            let mut npdu = vec![0x01, 0x20, 0x00, 0x02, 0x06];
+            npdu.extend_from_slice(&dest);
+            npdu.extend_from_slice(&[0xFF, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05, 0x22, 0x01, 0xE0, 0x91, 0x00, 0x21, 0x05]);
+            npdu
+        };
+        let to_client = i_am([192, 168, 1, 50, 0xBA, 0xC0]);
+        let to_fd = i_am([10, 0, 0, 5, 0xBA, 0xC0]);
+        sent.lock().unwrap().clear();
+        gateway.route_from_mstp(&to_client, 5).unwrap();
+        let dests: Vec<_> = sent.lock().unwrap().iter().map(|(_, dest)| *dest).collect();
+        assert_eq!(dests, vec![LinkAddress::Ip(client)]);
+
+        // The foreign device sends Who-Is through Distribute-Broadcast
+        let distribute = [0x81, BVLC_DISTRIBUTE_BROADCAST, 0x00, 0x08, 0x01, 0x00, 0x10, 0x08];
+        gateway.route_from_ip(&distribute, fd).unwrap();
+
+        // A reply to another client is copied to the foreign device as a Forwarded-NPDU
+        sent.lock().unwrap().clear();
+        gateway.route_from_mstp(&to_client, 5).unwrap();
+        {
+            let sent = sent.lock().unwrap();
+            let dests: Vec<_> = sent.iter().map(|(_, dest)| *dest).collect();
+            assert_eq!(dests, vec![LinkAddress::Ip(client), LinkAddress::Ip(fd)]);
+            assert_eq!(sent[1].0[..10], [0x81, BVLC_FORWARDED_NPDU, 0x00, sent[1].0.len() as u8, 192, 168, 1, 100, 0xBA, 0xC0]);
+        }
+        assert_eq!(gateway.foreign_device_summaries()[0].forwarded, 1);
+
+        // A reply addressed to the foreign device itself is not duplicated
+        sent.lock().unwrap().clear();
+        gateway.route_from_mstp(&to_fd, 5).unwrap();
+        let dests: Vec<_> = sent.lock().unwrap().iter().map(|(_, dest)| *dest).collect();
+        assert_eq!(dests, vec![LinkAddress::Ip(fd)]);
+        assert_eq!(gateway.foreign_device_summaries()[0].forwarded, 1);
+    }
 }
@@ -0,0 +1,100 @@
+//! Low-memory feature shedding
+//!
+//! The main loop feeds the governor a free-heap sample every few seconds.
+//! Below each threshold one more optional feature is switched off, the most
+//! expendable first, so a busy network cannot run the heap dry and panic the
+//! gateway. A feature comes back once the heap has recovered past its
+//! threshold plus a margin, so it does not flap around the boundary.
+
+/// Optional features, in the order they are shed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Last received frames kept for /api/debug/frames
+    FrameDebug,
+    /// New entries in the discovered device list (known devices still update)
+    DiscoveryGrowth,
+    /// Top talker counters on the diagnostics page
+    TopTalkers,
+}
+
+impl Feature {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Feature::FrameDebug => "frame_debug",
+            Feature::DiscoveryGrowth => "discovery_growth",
+            Feature::TopTalkers => "top_talkers",
+        }
+    }
+}
+
+/// Free heap in bytes below which each feature is shed
+const SHED_BELOW: [(Feature, u32); 3] = [
+    (Feature::FrameDebug, 48 * 1024),
+    (Feature::DiscoveryGrowth, 36 * 1024),
+    (Feature::TopTalkers, 28 * 1024),
+];
+
+/// Extra free heap needed before a shed feature is restored
+const RESTORE_MARGIN: u32 = 8 * 1024;
+
+/// Tracks how many features are currently shed
+#[derive(Debug, Default)]
+pub struct Governor {
+    shed: usize,
+}
+
+impl Governor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a free-heap sample; true when the set of shed features changed
+    pub fn update(&mut self, free_heap: u32) -> bool {
+        let before = self.shed;
+        while self.shed < SHED_BELOW.len() && free_heap < SHED_BELOW[self.shed].1 {
+            self.shed += 1;
+        }
+        while self.shed > 0 && free_heap >= SHED_BELOW[self.shed - 1].1 + RESTORE_MARGIN {
+            self.shed -= 1;
+        }
+        self.shed != before
+    }
+
+    pub fn is_shed(&self, feature: Feature) -> bool {
+        SHED_BELOW[..self.shed].iter().any(|(f, _)| *f == feature)
+    }
+
+    /// Features currently switched off, in shedding order
+    pub fn shed_features(&self) -> Vec<Feature> {
+        SHED_BELOW[..self.shed].iter().map(|(f, _)| *f).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sheds_progressively_and_restores_with_margin() {
+        let mut governor = Governor::new();
+        assert!(!governor.update(100 * 1024));
+        assert!(governor.shed_features().is_empty());
+
+        assert!(governor.update(40 * 1024));
+        assert_eq!(governor.shed_features(), [Feature::FrameDebug]);
+
+        // A sudden drop sheds every level it passes at once
+        assert!(governor.update(20 * 1024));
+        assert_eq!(governor.shed_features(), [Feature::FrameDebug, Feature::DiscoveryGrowth, Feature::TopTalkers]);
+        assert!(governor.is_shed(Feature::TopTalkers));
+
+        // Just above a threshold is not enough to restore it
+        assert!(!governor.update(30 * 1024));
+        assert!(governor.update(37 * 1024));
+        assert_eq!(governor.shed_features(), [Feature::FrameDebug, Feature::DiscoveryGrowth]);
+
+        assert!(governor.update(60 * 1024));
+        assert!(governor.shed_features().is_empty());
+        assert!(!governor.is_shed(Feature::FrameDebug));
+    }
+}
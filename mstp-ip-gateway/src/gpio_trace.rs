@@ -0,0 +1,105 @@
+//! GPIO trace markers for MS/TP driver events
+//!
+//! Only built with the `gpio-trace` feature. Drives three spare pins on the
+//! M5StickC Plus2 headers at key driver events so a field tech with a cheap
+//! logic analyzer can line bus traffic on the RS-485 RX/TX pins up against
+//! what the driver actually did. Off until enabled via /api/debug/gpio.
+//!
+//! - G25: toggles on every token addressed to us
+//! - G32: high while a frame is being transmitted (UART write to TX complete)
+//! - G33: toggles on every header or data CRC error
+
+use esp_idf_svc::sys;
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Toggles on token received (HAT header G25)
+pub const TOKEN_PIN: i32 = 25;
+/// High for the duration of a frame transmission (Grove G32)
+pub const TX_PIN: i32 = 32;
+/// Toggles on CRC error (Grove G33)
+pub const CRC_ERROR_PIN: i32 = 33;
+
+const PINS: [i32; 3] = [TOKEN_PIN, TX_PIN, CRC_ERROR_PIN];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TOKEN_LEVEL: AtomicBool = AtomicBool::new(false);
+static CRC_ERROR_LEVEL: AtomicBool = AtomicBool::new(false);
+
+/// Configure the trace pins as outputs, driven low
+pub fn init() {
+    for pin in PINS {
+        // SAFETY: plain ESP-IDF GPIO calls on pins no driver has taken
+        let ok = unsafe {
+            sys::gpio_reset_pin(pin) == sys::ESP_OK
+                && sys::gpio_set_direction(pin, sys::gpio_mode_t_GPIO_MODE_OUTPUT) == sys::ESP_OK
+                && sys::gpio_set_level(pin, 0) == sys::ESP_OK
+        };
+        if !ok {
+            warn!("GPIO trace: failed to configure G{}", pin);
+        }
+    }
+}
+
+/// Enable or disable the trace markers; all pins are driven low on a change
+pub fn set_enabled(enabled: bool) {
+    if ENABLED.swap(enabled, Ordering::Relaxed) != enabled {
+        TOKEN_LEVEL.store(false, Ordering::Relaxed);
+        CRC_ERROR_LEVEL.store(false, Ordering::Relaxed);
+        for pin in PINS {
+            level(pin, false);
+        }
+        info!("GPIO trace {}", if enabled { "enabled" } else { "disabled" });
+    }
+}
+
+/// Whether `pin` is one of the trace outputs (configured by `init`)
+pub fn uses_pin(pin: i32) -> bool {
+    PINS.contains(&pin)
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A token addressed to this station was received
+#[inline]
+pub fn token_received() {
+    toggle(TOKEN_PIN, &TOKEN_LEVEL);
+}
+
+/// A frame is about to be written to the UART
+#[inline]
+pub fn tx_start() {
+    if enabled() {
+        level(TX_PIN, true);
+    }
+}
+
+/// The frame has left the UART (or the write failed)
+#[inline]
+pub fn tx_end() {
+    if enabled() {
+        level(TX_PIN, false);
+    }
+}
+
+/// A received frame failed its header or data CRC
+#[inline]
+pub fn crc_error() {
+    toggle(CRC_ERROR_PIN, &CRC_ERROR_LEVEL);
+}
+
+fn toggle(pin: i32, state: &AtomicBool) {
+    if enabled() {
+        let high = !state.fetch_xor(true, Ordering::Relaxed);
+        level(pin, high);
+    }
+}
+
+fn level(pin: i32, high: bool) {
+    // SAFETY: the pin was configured as an output by init()
+    unsafe {
+        sys::gpio_set_level(pin, high as u32);
+    }
+}
@@ -0,0 +1,67 @@
+//! Cached health flags for load balancers and uptime monitors
+//!
+//! The main loop publishes link state here as it runs; the `/healthz`
+//! handler only reads these atomics and the heap counter, so polling it
+//! never takes the web, gateway or MS/TP driver locks.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Free heap below which the gateway reports itself unhealthy
+pub const MIN_FREE_HEAP: u32 = 20 * 1024;
+
+/// WiFi station connected, or the configuration AP is up
+static WIFI_UP: AtomicBool = AtomicBool::new(false);
+
+/// MS/TP token ring is up (we have seen the token recently)
+static RING_UP: AtomicBool = AtomicBool::new(false);
+
+/// Record the WiFi link state
+pub fn set_wifi_up(up: bool) {
+    WIFI_UP.store(up, Ordering::Relaxed);
+}
+
+/// Record the MS/TP token ring state
+pub fn set_ring_up(up: bool) {
+    RING_UP.store(up, Ordering::Relaxed);
+}
+
+/// Point-in-time health summary
+#[derive(Debug, Clone, Copy)]
+pub struct HealthReport {
+    pub wifi_up: bool,
+    pub ring_up: bool,
+    pub free_heap: u32,
+}
+
+impl HealthReport {
+    /// Read the cached flags and the current free heap
+    pub fn snapshot() -> Self {
+        // SAFETY: esp_get_free_heap_size() only reads allocator counters
+        let free_heap = unsafe { esp_idf_sys::esp_get_free_heap_size() };
+        Self {
+            wifi_up: WIFI_UP.load(Ordering::Relaxed),
+            ring_up: RING_UP.load(Ordering::Relaxed),
+            free_heap,
+        }
+    }
+
+    pub fn heap_ok(&self) -> bool {
+        self.free_heap >= MIN_FREE_HEAP
+    }
+
+    /// True when every check passes (HTTP 200, otherwise 503)
+    pub fn healthy(&self) -> bool {
+        self.wifi_up && self.ring_up && self.heap_ok()
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"status":"{}","wifi_up":{},"ring_up":{},"heap_ok":{},"free_heap":{}}}"#,
+            if self.healthy() { "ok" } else { "unhealthy" },
+            self.wifi_up,
+            self.ring_up,
+            self.heap_ok(),
+            self.free_heap,
+        )
+    }
+}
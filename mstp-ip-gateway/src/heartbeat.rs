@@ -0,0 +1,223 @@
+//! Optional heartbeat reporting to a fleet monitoring endpoint
+//!
+//! Periodically POSTs a small JSON document (uptime, firmware version,
+//! site name and a stats summary) to a configured HTTPS URL so that
+//! operators can watch many gateways without inbound connectivity.
+//! Failed posts back off exponentially up to `MAX_BACKOFF`, less a random
+//! part so a fleet that lost the endpoint together doesn't retry in step.
+
+use embedded_svc::http::client::Client;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::{Configuration as HttpClientConfig, EspHttpConnection};
+use log::{info, warn};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::auth;
+use crate::clock;
+use crate::web::{json_escape, WebState};
+
+/// Shortest allowed reporting interval
+pub const MIN_INTERVAL_SECS: u32 = 30;
+
+/// Longest allowed reporting interval
+pub const MAX_INTERVAL_SECS: u32 = 86400;
+
+/// Maximum endpoint URL length (fits the NVS string buffer)
+pub const MAX_URL_LEN: usize = 128;
+
+/// Delay after the first failure, doubled on each further failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Upper bound on the retry delay
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Up to 1/JITTER_DIVISOR of the retry delay is taken off at random
+const JITTER_DIVISOR: u32 = 4;
+
+/// HTTP request timeout
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stack size for the heartbeat thread (TLS handshake needs headroom)
+const HEARTBEAT_STACK_SIZE: usize = 12288;
+
+/// Heartbeat delivery status, shown in the web portal
+#[derive(Debug, Clone, Default)]
+pub struct HeartbeatStatus {
+    /// Heartbeats accepted by the endpoint
+    pub sent: u64,
+    /// Failed attempts since the last success
+    pub consecutive_failures: u32,
+    /// When the endpoint last accepted a heartbeat
+    pub last_success: Option<Instant>,
+    /// Description of the most recent failure (empty after a success)
+    pub last_error: String,
+}
+
+/// Delay before the next attempt after `failures` consecutive failures,
+/// shortened by a share of up to 1/JITTER_DIVISOR picked by `random`
+pub fn backoff_delay(failures: u32, random: u32) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+    let shift = (failures - 1).min(16);
+    let delay = INITIAL_BACKOFF.saturating_mul(1 << shift).min(MAX_BACKOFF);
+    delay - (delay / JITTER_DIVISOR).mul_f64(random as f64 / u32::MAX as f64)
+}
+
+/// Spawn the heartbeat thread. Settings are read from the web state on each
+/// cycle, so the task idles while reporting is disabled or WiFi is down.
+pub fn spawn_heartbeat_task(web_state: Arc<Mutex<WebState>>) -> anyhow::Result<()> {
+    thread::Builder::new()
+        .stack_size(HEARTBEAT_STACK_SIZE)
+        .spawn(move || heartbeat_task(web_state))?;
+    Ok(())
+}
+
+fn heartbeat_task(web_state: Arc<Mutex<WebState>>) {
+    info!("Heartbeat task started");
+    let mut next_attempt = Instant::now();
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        if Instant::now() < next_attempt {
+            continue;
+        }
+
+        // Snapshot settings and payload without holding the lock during I/O
+        let (enabled, url, interval, payload, failures) = {
+            let state = match web_state.lock() {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let ready = state.config.heartbeat_enabled
+                && state.wifi_connected
+                && !state.config.heartbeat_url.is_empty();
+            (
+                ready,
+                state.config.heartbeat_url.clone(),
+                state.config.heartbeat_interval_secs.clamp(MIN_INTERVAL_SECS, MAX_INTERVAL_SECS),
+                if ready { build_payload(&state) } else { String::new() },
+                state.heartbeat.consecutive_failures,
+            )
+        };
+
+        if !enabled {
+            // Check again shortly in case it gets enabled or WiFi returns
+            next_attempt = Instant::now() + Duration::from_secs(5);
+            continue;
+        }
+
+        let result = post_json(&url, &payload);
+
+        let mut state = match web_state.lock() {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        match result {
+            Ok(()) => {
+                if failures > 0 {
+                    info!("Heartbeat delivered after {} failed attempts", failures);
+                }
+                state.heartbeat.sent += 1;
+                state.heartbeat.consecutive_failures = 0;
+                state.heartbeat.last_success = Some(Instant::now());
+                state.heartbeat.last_error.clear();
+                next_attempt = Instant::now() + Duration::from_secs(interval as u64);
+            }
+            Err(e) => {
+                let failures = failures.saturating_add(1);
+                let delay = backoff_delay(failures, u32::from_le_bytes(auth::random_bytes()));
+                warn!("Heartbeat to {} failed ({}), retrying in {}s", url, e, delay.as_secs());
+                state.heartbeat.consecutive_failures = failures;
+                state.heartbeat.last_error = e.to_string();
+                next_attempt = Instant::now() + delay;
+            }
+        }
+    }
+}
+
+/// Build the heartbeat JSON document
+fn build_payload(state: &WebState) -> String {
+    format!(
+        r#"{{"site_name":"{}","device_name":"{}","device_instance":{},"serial_number":"{}","firmware":"{}","uptime_secs":{},"ip_address":"{}","mstp_network":{},"ip_network":{},"master_count":{},"rx_frames":{},"tx_frames":{},"crc_errors":{},"token_pass_failures":{},"mstp_to_ip":{},"ip_to_mstp":{},"routing_errors":{},"transaction_timeouts":{},"network_conflict":{},"timestamp":{}}}"#,
+        json_escape(&state.config.site_name),
+        json_escape(&state.config.device_name),
+        state.config.device_instance,
+        json_escape(&state.config.device_serial_number),
+        env!("CARGO_PKG_VERSION"),
+        state.uptime_secs(),
+        state.ip_address,
+        state.config.mstp_network,
+        state.config.ip_network,
+        state.mstp_stats.master_count,
+        state.mstp_stats.rx_frames,
+        state.mstp_stats.tx_frames,
+        state.mstp_stats.crc_errors,
+        state.mstp_stats.token_pass_failures,
+        state.gateway_stats.mstp_to_ip_packets,
+        state.gateway_stats.ip_to_mstp_packets,
+        state.gateway_stats.routing_errors,
+        state.gateway_stats.transaction_timeouts,
+        state.network_conflict.is_some(),
+        clock::utc_now()
+            .map(|t| format!(r#""{}""#, clock::format_utc(t)))
+            .unwrap_or_else(|| "null".to_string()),
+    )
+}
+
+/// POST a JSON payload, treating any non-2xx response as a failure
+pub fn post_json(url: &str, payload: &str) -> anyhow::Result<()> {
+    let connection = EspHttpConnection::new(&HttpClientConfig {
+        timeout: Some(REQUEST_TIMEOUT),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+
+    let content_length = payload.len().to_string();
+    let headers = [
+        ("Content-Type", "application/json"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    let mut request = client.post(url, &headers)?;
+    request.write_all(payload.as_bytes())?;
+    request.flush()?;
+    let response = request.submit()?;
+
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        anyhow::bail!("HTTP {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay_growth_and_cap() {
+        assert_eq!(backoff_delay(0, 0), Duration::ZERO);
+        assert_eq!(backoff_delay(1, 0), Duration::from_secs(30));
+        assert_eq!(backoff_delay(2, 0), Duration::from_secs(60));
+        assert_eq!(backoff_delay(3, 0), Duration::from_secs(120));
+        assert_eq!(backoff_delay(7, 0), Duration::from_secs(1920));
+        assert_eq!(backoff_delay(8, 0), MAX_BACKOFF);
+        assert_eq!(backoff_delay(u32::MAX, 0), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_backoff_delay_jitter_bounds() {
+        assert_eq!(backoff_delay(1, u32::MAX), Duration::from_millis(22_500));
+        assert_eq!(backoff_delay(u32::MAX, u32::MAX), Duration::from_secs(2700));
+        for failures in 1..20 {
+            let full = backoff_delay(failures, 0);
+            for random in [1, 0x1234_5678, u32::MAX / 2, u32::MAX - 1] {
+                let delay = backoff_delay(failures, random);
+                assert!(delay <= full && delay >= full * 3 / 4, "{} failures, random {}: {:?}", failures, random, delay);
+            }
+        }
+    }
+}
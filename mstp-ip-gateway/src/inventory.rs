@@ -0,0 +1,457 @@
+//! Site inventory: object lists and names read from MS/TP devices
+//!
+//! After discovery the gateway walks each device's Object_List one element
+//! at a time (index 0 gives the length, so devices that can't segment work
+//! too) and reads every object's Object_Name. Only one ReadProperty is
+//! outstanding at a time, and the next one waits until no client request is
+//! in flight, so a survey never crowds live traffic off the token ring. The
+//! result downloads from the portal as JSON or CSV.
+
+use std::time::{Duration, Instant};
+
+use crate::rpm_proxy::{encode_read_property, parse_read_property_result, PropertyRef, ReadResult};
+
+/// How long to wait for each ReadProperty answer
+pub const INVENTORY_READ_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Retransmissions of an unanswered ReadProperty before the device is skipped
+pub const INVENTORY_MAX_RETRIES: u8 = 2;
+
+/// Most objects recorded per device; the rest of a longer list is skipped
+pub const MAX_INVENTORY_OBJECTS: u32 = 1024;
+
+/// BACnet object type and property identifiers used by the walk
+const OBJECT_TYPE_DEVICE: u32 = 8;
+const PROP_OBJECT_LIST: u32 = 76;
+const PROP_OBJECT_NAME: u32 = 77;
+
+/// Standard object type names (Clause 21, BACnetObjectType) up to access-door
+pub const OBJECT_TYPE_NAMES: [&str; 31] = [
+    "analog-input", "analog-output", "analog-value", "binary-input", "binary-output",
+    "binary-value", "calendar", "command", "device", "event-enrollment", "file", "group",
+    "loop", "multi-state-input", "multi-state-output", "notification-class", "program",
+    "schedule", "averaging", "multi-state-value", "trend-log", "life-safety-point",
+    "life-safety-zone", "accumulator", "pulse-converter", "event-log", "global-group",
+    "trend-log-multiple", "load-control", "structured-view", "access-door",
+];
+
+/// Application tag numbers
+const TAG_UNSIGNED: u8 = 2;
+const TAG_CHARACTER_STRING: u8 = 7;
+const TAG_OBJECT_ID: u8 = 12;
+
+/// One object found on a device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InventoryObject {
+    pub object_id: u32,
+    /// Object_Name, empty when it could not be read
+    pub name: String,
+}
+
+impl InventoryObject {
+    pub fn object_type(&self) -> u32 {
+        self.object_id >> 22
+    }
+
+    pub fn instance(&self) -> u32 {
+        self.object_id & 0x3F_FFFF
+    }
+
+    /// Standard name of the object type, or its number for newer and proprietary types
+    pub fn type_name(&self) -> String {
+        let object_type = self.object_type();
+        match OBJECT_TYPE_NAMES.get(object_type as usize) {
+            Some(name) => name.to_string(),
+            None => object_type.to_string(),
+        }
+    }
+}
+
+/// How far the walk of one device got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceOutcome {
+    /// Not read yet (or the job was cancelled first)
+    Pending,
+    Complete,
+    /// Gave up on the device, with the reason
+    Failed(&'static str),
+}
+
+impl DeviceOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceOutcome::Pending => "pending",
+            DeviceOutcome::Complete => "complete",
+            DeviceOutcome::Failed(reason) => reason,
+        }
+    }
+}
+
+/// Inventory of one MS/TP device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InventoryDevice {
+    pub device_instance: u32,
+    pub mac: u8,
+    /// The Device object's Object_Name
+    pub name: String,
+    /// Object_List length reported by the device
+    pub object_count: u32,
+    pub objects: Vec<InventoryObject>,
+    pub outcome: DeviceOutcome,
+}
+
+/// Progress of a running inventory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InventoryProgress {
+    /// Devices finished (read or given up on)
+    pub devices_done: usize,
+    pub devices_total: usize,
+    /// Objects found so far across all devices
+    pub objects_found: usize,
+}
+
+/// Next read for the device being walked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// Object_List[0]
+    ObjectCount,
+    /// Object_List[n], 1-based
+    ObjectId(u32),
+    /// Object_Name of objects[n]
+    ObjectName(usize),
+}
+
+/// The ReadProperty waiting for an answer
+#[derive(Debug)]
+struct Outstanding {
+    invoke_id: u8,
+    sent_at: Instant,
+    retries: u8,
+}
+
+/// A site inventory walking one device after another
+#[derive(Debug)]
+pub struct InventoryJob {
+    devices: Vec<InventoryDevice>,
+    current: usize,
+    step: Step,
+    outstanding: Option<Outstanding>,
+}
+
+impl InventoryJob {
+    /// Inventory the given (device instance, MS/TP MAC) pairs in order
+    pub fn new(targets: &[(u32, u8)]) -> Self {
+        let devices = targets
+            .iter()
+            .map(|&(device_instance, mac)| InventoryDevice {
+                device_instance,
+                mac,
+                name: String::new(),
+                object_count: 0,
+                objects: Vec::new(),
+                outcome: DeviceOutcome::Pending,
+            })
+            .collect();
+        Self { devices, current: 0, step: Step::ObjectCount, outstanding: None }
+    }
+
+    /// All devices have been walked (or the job was cancelled)
+    pub fn is_finished(&self) -> bool {
+        self.current >= self.devices.len()
+    }
+
+    /// Stop after the current read; unread devices stay `Pending`
+    pub fn cancel(&mut self) {
+        self.current = self.devices.len();
+        self.outstanding = None;
+    }
+
+    pub fn progress(&self) -> InventoryProgress {
+        InventoryProgress {
+            devices_done: self.current.min(self.devices.len()),
+            devices_total: self.devices.len(),
+            objects_found: self.devices.iter().map(|d| d.objects.len()).sum(),
+        }
+    }
+
+    /// The devices walked so far, in order
+    pub fn into_devices(self) -> Vec<InventoryDevice> {
+        self.devices
+    }
+
+    /// The ReadProperty to send now, as (APDU, MS/TP MAC)
+    ///
+    /// Returns `None` while an answer is still awaited. A new request takes
+    /// `next_invoke_id` and advances it; a retransmission reuses its own.
+    pub fn poll(&mut self, now: Instant, next_invoke_id: &mut u8) -> Option<(Vec<u8>, u8)> {
+        if let Some(outstanding) = self.outstanding.as_mut() {
+            if now.duration_since(outstanding.sent_at) < INVENTORY_READ_TIMEOUT {
+                return None;
+            }
+            if outstanding.retries < INVENTORY_MAX_RETRIES {
+                outstanding.retries += 1;
+                outstanding.sent_at = now;
+                let invoke_id = outstanding.invoke_id;
+                return self.request(invoke_id);
+            }
+            self.outstanding = None;
+            self.finish_device(DeviceOutcome::Failed("no response"));
+        }
+
+        let invoke_id = *next_invoke_id;
+        let request = self.request(invoke_id)?;
+        *next_invoke_id = invoke_id.wrapping_add(1);
+        self.outstanding = Some(Outstanding { invoke_id, sent_at: now, retries: 0 });
+        Some(request)
+    }
+
+    /// Whether an APDU from `mac` answers the outstanding ReadProperty
+    pub fn matches(&self, mac: u8, apdu: &[u8]) -> bool {
+        match (&self.outstanding, self.devices.get(self.current)) {
+            (Some(outstanding), Some(device)) => {
+                mac == device.mac
+                    && apdu.get(1) == Some(&outstanding.invoke_id)
+                    && matches!(apdu[0] >> 4, 3 | 5 | 6 | 7)
+            }
+            _ => false,
+        }
+    }
+
+    /// Record the device's answer to the outstanding ReadProperty
+    pub fn record(&mut self, apdu: &[u8]) {
+        self.outstanding = None;
+        // Abort and Reject count as errors for the property
+        let value = match parse_read_property_result(apdu) {
+            Some(ReadResult::Value(value)) => Some(value),
+            _ => None,
+        };
+        let Some(device) = self.devices.get_mut(self.current) else {
+            return;
+        };
+
+        match self.step {
+            Step::ObjectCount => {
+                let Some(count) = value.as_deref().and_then(|v| decode_application(v, TAG_UNSIGNED)) else {
+                    self.finish_device(DeviceOutcome::Failed("object list unreadable"));
+                    return;
+                };
+                let count = decode_unsigned(count);
+                device.object_count = count;
+                if count == 0 {
+                    self.finish_device(DeviceOutcome::Complete);
+                } else {
+                    self.step = Step::ObjectId(1);
+                }
+            }
+            Step::ObjectId(index) => {
+                let object_id = value.as_deref().and_then(|v| decode_application(v, TAG_OBJECT_ID));
+                if let Some(id) = object_id.filter(|id| id.len() == 4) {
+                    device.objects.push(InventoryObject { object_id: decode_unsigned(id), name: String::new() });
+                }
+                if index < device.object_count.min(MAX_INVENTORY_OBJECTS) {
+                    self.step = Step::ObjectId(index + 1);
+                } else if device.objects.is_empty() {
+                    self.finish_device(DeviceOutcome::Complete);
+                } else {
+                    self.step = Step::ObjectName(0);
+                }
+            }
+            Step::ObjectName(position) => {
+                if let Some(name) = value.as_deref().and_then(|v| decode_application(v, TAG_CHARACTER_STRING)) {
+                    let name = decode_character_string(name);
+                    let object = &mut device.objects[position];
+                    if object.object_type() == OBJECT_TYPE_DEVICE && object.instance() == device.device_instance {
+                        device.name = name.clone();
+                    }
+                    object.name = name;
+                }
+                if position + 1 < device.objects.len() {
+                    self.step = Step::ObjectName(position + 1);
+                } else {
+                    self.finish_device(DeviceOutcome::Complete);
+                }
+            }
+        }
+    }
+
+    /// ReadProperty request APDU for the current step
+    fn request(&self, invoke_id: u8) -> Option<(Vec<u8>, u8)> {
+        let device = self.devices.get(self.current)?;
+        let device_object = (OBJECT_TYPE_DEVICE << 22) | device.device_instance;
+        let property = match self.step {
+            Step::ObjectCount => PropertyRef { object_id: device_object, property_id: PROP_OBJECT_LIST, array_index: Some(0) },
+            Step::ObjectId(index) => PropertyRef { object_id: device_object, property_id: PROP_OBJECT_LIST, array_index: Some(index) },
+            Step::ObjectName(position) => PropertyRef {
+                object_id: device.objects[position].object_id,
+                property_id: PROP_OBJECT_NAME,
+                array_index: None,
+            },
+        };
+        Some((encode_read_property(invoke_id, &property), device.mac))
+    }
+
+    /// Close the current device and move on to the next
+    fn finish_device(&mut self, outcome: DeviceOutcome) {
+        if let Some(device) = self.devices.get_mut(self.current) {
+            device.outcome = outcome;
+        }
+        self.current += 1;
+        self.step = Step::ObjectCount;
+    }
+}
+
+/// Contents of an application-tagged value with the expected tag number
+pub fn decode_application(value: &[u8], tag: u8) -> Option<&[u8]> {
+    let (&first, rest) = value.split_first()?;
+    if first >> 4 != tag || first & 0x08 != 0 {
+        return None;
+    }
+    let (len, rest) = match first & 0x07 {
+        5 => match rest.split_first()? {
+            (&254, r) => (u16::from_be_bytes([*r.first()?, *r.get(1)?]) as usize, r.get(2..)?),
+            (&255, _) => return None,
+            (&n, r) => (n as usize, r),
+        },
+        n => (n as usize, rest),
+    };
+    rest.get(..len)
+}
+
+/// Big-endian unsigned of up to four octets
+fn decode_unsigned(data: &[u8]) -> u32 {
+    data.iter().take(4).fold(0u32, |acc, &b| (acc << 8) | b as u32)
+}
+
+/// CharacterString contents: character set octet, then the characters
+pub fn decode_character_string(data: &[u8]) -> String {
+    match data.split_first() {
+        // UCS-2, big-endian
+        Some((4, chars)) => {
+            let units: Vec<u16> = chars.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        // ISO 8859-1
+        Some((5, chars)) => chars.iter().map(|&b| b as char).collect(),
+        // UTF-8 (and anything else, best effort)
+        Some((_, chars)) => String::from_utf8_lossy(chars).into_owned(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ReadProperty-ACK for device 1234 Object_List with the given index and value
+    fn object_list_ack(invoke_id: u8, index: u8, value: &[u8]) -> Vec<u8> {
+        let mut apdu = vec![0x30, invoke_id, 12, 0x0C, 0x02, 0x00, 0x04, 0xD2, 0x19, 76, 0x29, index, 0x3E];
+        apdu.extend_from_slice(value);
+        apdu.push(0x3F);
+        apdu
+    }
+
+    /// ReadProperty-ACK carrying an Object_Name
+    fn name_ack(invoke_id: u8, object_id: u32, name: &str) -> Vec<u8> {
+        let mut apdu = vec![0x30, invoke_id, 12, 0x0C];
+        apdu.extend_from_slice(&object_id.to_be_bytes());
+        apdu.extend_from_slice(&[0x19, 77, 0x3E, 0x75, name.len() as u8 + 1, 0x00]);
+        apdu.extend_from_slice(name.as_bytes());
+        apdu.push(0x3F);
+        apdu
+    }
+
+    #[test]
+    fn test_walks_object_list_then_names() {
+        let now = Instant::now();
+        let mut invoke_id = 10;
+        let mut job = InventoryJob::new(&[(1234, 5)]);
+
+        // Object_List[0]
+        let (request, mac) = job.poll(now, &mut invoke_id).unwrap();
+        assert_eq!(mac, 5);
+        assert_eq!(request, vec![0x00, 0x03, 10, 12, 0x0C, 0x02, 0x00, 0x04, 0xD2, 0x19, 76, 0x29, 0x00]);
+        assert_eq!(invoke_id, 11);
+        assert!(job.poll(now, &mut invoke_id).is_none(), "one read at a time");
+
+        let ack = object_list_ack(10, 0, &[0x21, 0x02]);
+        assert!(job.matches(5, &ack));
+        assert!(!job.matches(6, &ack));
+        job.record(&ack);
+
+        // Object_List[1] and [2]: the Device object and AI-3
+        let (request, _) = job.poll(now, &mut invoke_id).unwrap();
+        assert_eq!(request[11..], [0x29, 0x01]);
+        job.record(&object_list_ack(11, 1, &[0xC4, 0x02, 0x00, 0x04, 0xD2]));
+        job.poll(now, &mut invoke_id).unwrap();
+        job.record(&object_list_ack(12, 2, &[0xC4, 0x00, 0x00, 0x00, 0x03]));
+
+        // Then each Object_Name
+        let (request, _) = job.poll(now, &mut invoke_id).unwrap();
+        assert_eq!(request, vec![0x00, 0x03, 13, 12, 0x0C, 0x02, 0x00, 0x04, 0xD2, 0x19, 77]);
+        job.record(&name_ack(13, 0x0200_04D2, "AHU-1"));
+        job.poll(now, &mut invoke_id).unwrap();
+        job.record(&name_ack(14, 3, "Supply Temp"));
+
+        assert!(job.is_finished());
+        assert_eq!(job.progress(), InventoryProgress { devices_done: 1, devices_total: 1, objects_found: 2 });
+        let devices = job.into_devices();
+        assert_eq!(devices[0].name, "AHU-1");
+        assert_eq!(devices[0].outcome, DeviceOutcome::Complete);
+        assert_eq!(devices[0].objects[1], InventoryObject { object_id: 3, name: "Supply Temp".to_string() });
+        assert_eq!(devices[0].objects[1].type_name(), "analog-input");
+        assert_eq!(devices[0].objects[0].type_name(), "device");
+    }
+
+    #[test]
+    fn test_silent_device_skipped_after_retries() {
+        let start = Instant::now();
+        let mut invoke_id = 0;
+        let mut job = InventoryJob::new(&[(1, 5), (2, 6)]);
+
+        let (first, _) = job.poll(start, &mut invoke_id).unwrap();
+        for retry in 1..=INVENTORY_MAX_RETRIES as u32 {
+            let (again, mac) = job.poll(start + INVENTORY_READ_TIMEOUT * retry, &mut invoke_id).unwrap();
+            assert_eq!((again.clone(), mac), (first.clone(), 5), "retransmission reuses the invoke ID");
+        }
+
+        // Out of retries: the next device is read instead
+        let (_, mac) = job.poll(start + INVENTORY_READ_TIMEOUT * 3, &mut invoke_id).unwrap();
+        assert_eq!(mac, 6);
+        assert_eq!(invoke_id, 2);
+
+        // Error for Object_List (unknown-property): device 2 fails, job done
+        job.record(&[0x50, 1, 12, 0x91, 0x02, 0x91, 0x20]);
+        assert!(job.is_finished());
+        let devices = job.into_devices();
+        assert_eq!(devices[0].outcome, DeviceOutcome::Failed("no response"));
+        assert_eq!(devices[1].outcome, DeviceOutcome::Failed("object list unreadable"));
+    }
+
+    #[test]
+    fn test_cancel_keeps_partial_results() {
+        let now = Instant::now();
+        let mut invoke_id = 0;
+        let mut job = InventoryJob::new(&[(1234, 5), (2, 6)]);
+        job.poll(now, &mut invoke_id).unwrap();
+        job.record(&object_list_ack(0, 0, &[0x21, 0x05]));
+        job.poll(now, &mut invoke_id).unwrap();
+        job.record(&object_list_ack(1, 1, &[0xC4, 0x02, 0x00, 0x04, 0xD2]));
+
+        job.cancel();
+        assert!(job.is_finished());
+        assert!(job.poll(now, &mut invoke_id).is_none());
+        let devices = job.into_devices();
+        assert_eq!(devices[0].objects.len(), 1);
+        assert_eq!(devices[0].object_count, 5);
+        assert_eq!(devices[1].outcome, DeviceOutcome::Pending);
+    }
+
+    #[test]
+    fn test_character_sets() {
+        assert_eq!(decode_character_string(&[0x00, b'A', b'B']), "AB");
+        assert_eq!(decode_character_string(&[0x04, 0x00, b'Z', 0x00, 0xE9]), "Zé");
+        assert_eq!(decode_character_string(&[0x05, 0xE9]), "é");
+        assert_eq!(decode_application(&[0x75, 0x03, 0x00, b'h', b'i'], TAG_CHARACTER_STRING), Some(&[0x00, b'h', b'i'][..]));
+        assert_eq!(decode_application(&[0x21, 0x07], TAG_OBJECT_ID), None);
+    }
+}
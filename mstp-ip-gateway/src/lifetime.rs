@@ -0,0 +1,236 @@
+//! Lifetime statistics kept across reboots
+//!
+//! The since-boot counters start from zero on every restart, which makes it
+//! hard to show how a gateway has behaved over months in the field. When
+//! enabled, cumulative totals are checkpointed to NVS every
+//! `CHECKPOINT_INTERVAL` and at graceful shutdown, along with the boot count
+//! and why the gateway last restarted. Anything routed after the last
+//! checkpoint is lost on an unannounced power cut.
+
+use std::time::Duration;
+
+/// How often the totals are written to NVS (kept slow to spare the flash)
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Length of the stored record: 5 counters, boot count, two reason codes
+pub const LIFETIME_RECORD_LEN: usize = 5 * 8 + 4 + 2;
+
+/// Why the gateway last started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootReason {
+    Unknown = 0,
+    PowerOn = 1,
+    ResetPin = 2,
+    /// Restart requested by firmware (config save, portal reboot)
+    Software = 3,
+    Panic = 4,
+    Watchdog = 5,
+    /// Woken from deep sleep
+    DeepSleep = 6,
+    /// Hardware brown-out reset, no chance to shut down
+    BrownOut = 7,
+    /// Graceful shutdown after the brown-out interrupt
+    BrownOutShutdown = 8,
+    /// Graceful shutdown from the power button
+    PowerButton = 9,
+}
+
+impl RebootReason {
+    /// Map an ESP-IDF `esp_reset_reason_t` value
+    pub fn from_reset_reason(code: u32) -> Self {
+        match code {
+            1 => RebootReason::PowerOn,
+            2 => RebootReason::ResetPin,
+            3 => RebootReason::Software,
+            4 => RebootReason::Panic,
+            5..=7 => RebootReason::Watchdog,
+            8 => RebootReason::DeepSleep,
+            9 => RebootReason::BrownOut,
+            _ => RebootReason::Unknown,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => RebootReason::PowerOn,
+            2 => RebootReason::ResetPin,
+            3 => RebootReason::Software,
+            4 => RebootReason::Panic,
+            5 => RebootReason::Watchdog,
+            6 => RebootReason::DeepSleep,
+            7 => RebootReason::BrownOut,
+            8 => RebootReason::BrownOutShutdown,
+            9 => RebootReason::PowerButton,
+            _ => RebootReason::Unknown,
+        }
+    }
+
+    /// A reset the firmware did not ask for (panic, watchdog, brown-out)
+    pub fn is_crash(&self) -> bool {
+        matches!(self, RebootReason::Panic | RebootReason::Watchdog | RebootReason::BrownOut)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RebootReason::Unknown => "unknown",
+            RebootReason::PowerOn => "power-on",
+            RebootReason::ResetPin => "reset pin",
+            RebootReason::Software => "software restart",
+            RebootReason::Panic => "panic",
+            RebootReason::Watchdog => "watchdog",
+            RebootReason::DeepSleep => "wake from deep sleep",
+            RebootReason::BrownOut => "brown-out reset",
+            RebootReason::BrownOutShutdown => "brown-out (graceful shutdown)",
+            RebootReason::PowerButton => "power button (graceful shutdown)",
+        }
+    }
+}
+
+/// Counters carried across reboots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LifetimeCounters {
+    pub mstp_to_ip_packets: u64,
+    pub ip_to_mstp_packets: u64,
+    pub routing_errors: u64,
+    pub crc_errors: u64,
+    pub uptime_secs: u64,
+}
+
+impl LifetimeCounters {
+    fn fields(&self) -> [u64; 5] {
+        [self.mstp_to_ip_packets, self.ip_to_mstp_packets, self.routing_errors, self.crc_errors, self.uptime_secs]
+    }
+
+    fn from_fields(f: [u64; 5]) -> Self {
+        Self {
+            mstp_to_ip_packets: f[0],
+            ip_to_mstp_packets: f[1],
+            routing_errors: f[2],
+            crc_errors: f[3],
+            uptime_secs: f[4],
+        }
+    }
+}
+
+/// Totals since first boot, plus the since-boot snapshot they were last advanced from
+#[derive(Debug, Clone, Default)]
+pub struct LifetimeStats {
+    pub totals: LifetimeCounters,
+    pub boot_count: u32,
+    pub last_reboot_reason: Option<RebootReason>,
+    /// Graceful shutdown in progress, reported as the reboot reason next boot
+    shutdown: Option<RebootReason>,
+    last_seen: LifetimeCounters,
+}
+
+impl LifetimeStats {
+    /// Count this boot. A graceful shutdown recorded last run explains the
+    /// restart better than the hardware reset reason does.
+    pub fn record_boot(&mut self, reset_reason: RebootReason) {
+        self.boot_count = self.boot_count.saturating_add(1);
+        self.last_reboot_reason = Some(self.shutdown.take().unwrap_or(reset_reason));
+        self.last_seen = LifetimeCounters::default();
+    }
+
+    /// Remember why the gateway is going down, for the next boot
+    pub fn record_shutdown(&mut self, reason: RebootReason) {
+        self.shutdown = Some(reason);
+    }
+
+    /// Advance the totals from the current since-boot counters. A counter
+    /// that went backwards was reset from the portal and counts from zero.
+    pub fn update(&mut self, since_boot: &LifetimeCounters) {
+        let mut totals = self.totals.fields();
+        for ((total, now), last) in totals.iter_mut().zip(since_boot.fields()).zip(self.last_seen.fields()) {
+            let delta = if now >= last { now - last } else { now };
+            *total = total.saturating_add(delta);
+        }
+        self.totals = LifetimeCounters::from_fields(totals);
+        self.last_seen = *since_boot;
+    }
+
+    /// Serialize for NVS: counters and boot count big-endian, then the reason codes
+    pub fn encode(&self) -> [u8; LIFETIME_RECORD_LEN] {
+        let mut buf = [0u8; LIFETIME_RECORD_LEN];
+        for (chunk, value) in buf.chunks_exact_mut(8).zip(self.totals.fields()) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+        buf[40..44].copy_from_slice(&self.boot_count.to_be_bytes());
+        buf[44] = self.last_reboot_reason.map(|r| r as u8).unwrap_or(0);
+        buf[45] = self.shutdown.map(|r| r as u8).unwrap_or(0);
+        buf
+    }
+
+    /// Parse a stored record; anything of the wrong length starts from zero
+    pub fn decode(data: &[u8]) -> Self {
+        if data.len() != LIFETIME_RECORD_LEN {
+            return Self::default();
+        }
+        let mut fields = [0u64; 5];
+        for (field, chunk) in fields.iter_mut().zip(data.chunks_exact(8)) {
+            *field = u64::from_be_bytes(chunk.try_into().unwrap_or_default());
+        }
+        let reason = |code: u8| (code != 0).then(|| RebootReason::from_u8(code));
+        Self {
+            totals: LifetimeCounters::from_fields(fields),
+            boot_count: u32::from_be_bytes([data[40], data[41], data[42], data[43]]),
+            last_reboot_reason: reason(data[44]),
+            shutdown: reason(data[45]),
+            last_seen: LifetimeCounters::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(packets: u64, uptime: u64) -> LifetimeCounters {
+        LifetimeCounters { mstp_to_ip_packets: packets, uptime_secs: uptime, ..Default::default() }
+    }
+
+    #[test]
+    fn test_totals_accumulate_across_boots() {
+        let mut stats = LifetimeStats::default();
+        stats.record_boot(RebootReason::PowerOn);
+        stats.update(&counters(10, 60));
+        stats.update(&counters(25, 120));
+        assert_eq!(stats.totals.mstp_to_ip_packets, 25);
+
+        // Counters reset from the portal: the new count is added, not lost
+        stats.update(&counters(5, 180));
+        assert_eq!(stats.totals.mstp_to_ip_packets, 30);
+        assert_eq!(stats.totals.uptime_secs, 180);
+
+        let mut next = LifetimeStats::decode(&stats.encode());
+        next.record_boot(RebootReason::Software);
+        next.update(&counters(7, 30));
+        assert_eq!(next.boot_count, 2);
+        assert_eq!(next.totals.mstp_to_ip_packets, 37);
+        assert_eq!(next.totals.uptime_secs, 210);
+        assert_eq!(next.last_reboot_reason, Some(RebootReason::Software));
+    }
+
+    #[test]
+    fn test_graceful_shutdown_reported_next_boot() {
+        let mut stats = LifetimeStats::default();
+        stats.record_boot(RebootReason::PowerOn);
+        stats.record_shutdown(RebootReason::PowerButton);
+
+        let mut next = LifetimeStats::decode(&stats.encode());
+        next.record_boot(RebootReason::DeepSleep);
+        assert_eq!(next.last_reboot_reason, Some(RebootReason::PowerButton));
+
+        // Only once: the boot after that uses the hardware reason again
+        let mut after = LifetimeStats::decode(&next.encode());
+        after.record_boot(RebootReason::from_reset_reason(4));
+        assert_eq!(after.last_reboot_reason, Some(RebootReason::Panic));
+    }
+
+    #[test]
+    fn test_decode_rejects_wrong_length() {
+        let stats = LifetimeStats::decode(&[0xFF; 10]);
+        assert_eq!(stats.boot_count, 0);
+        assert_eq!(stats.totals, LifetimeCounters::default());
+    }
+}
@@ -4,6 +4,15 @@
 //! to respond to Who-Is requests and be discoverable on the network.
 
 use log::{debug, info, trace};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::clock;
+use crate::cov::{CovTable, Requester, SubscribeOutcome, MAX_COV_SUBSCRIPTIONS};
+use crate::errors::{self, ErrorKind};
+use crate::netnum::NetworkNumber;
+use crate::tz::LocalTime;
 
 /// Vendor ID for Madlogix (using a placeholder - should register with ASHRAE)
 /// Per BACnet standard, unregistered vendors should use 0xFFFF or apply for one
@@ -12,24 +21,34 @@ const VENDOR_ID: u32 = 65535; // Unregistered vendor
 /// APDU types
 const APDU_UNCONFIRMED_REQUEST: u8 = 0x10;
 const APDU_CONFIRMED_REQUEST: u8 = 0x00;
+const APDU_SIMPLE_ACK: u8 = 0x20;
 const APDU_COMPLEX_ACK: u8 = 0x30;
 const APDU_ERROR: u8 = 0x50;
 const APDU_REJECT: u8 = 0x60;
 
 /// Reject reasons
+const REJECT_MISSING_REQUIRED_PARAMETER: u8 = 5;
 const REJECT_UNRECOGNIZED_SERVICE: u8 = 9;
 
 /// Unconfirmed service choices
 const SERVICE_WHO_IS: u8 = 8;
 const SERVICE_I_AM: u8 = 0;
+const SERVICE_UNCONFIRMED_COV_NOTIFICATION: u8 = 2;
+const SERVICE_UNCONFIRMED_EVENT_NOTIFICATION: u8 = 3;
 
 /// Confirmed service choices
+const SERVICE_SUBSCRIBE_COV: u8 = 5;
 const SERVICE_READ_PROPERTY: u8 = 12;
 const SERVICE_READ_PROPERTY_MULTIPLE: u8 = 14;
+const SERVICE_WRITE_PROPERTY: u8 = 15;
 
 /// Object types
+const OBJECT_TYPE_ANALOG_VALUE: u16 = 2;
 const OBJECT_TYPE_DEVICE: u16 = 8;
 const OBJECT_TYPE_NETWORK_PORT: u16 = 56;
+/// Proprietary Gateway Diagnostics object: recent errors and counters
+const OBJECT_TYPE_GATEWAY_DIAGNOSTICS: u16 = 128;
+const DIAGNOSTICS_INSTANCE: u32 = 1;
 
 /// Segmentation support values
 const SEGMENTATION_NOT_SUPPORTED: u32 = 3;
@@ -59,10 +78,13 @@ const PROP_DATABASE_REVISION: u32 = 155;
 const PROP_OBJECT_LIST: u32 = 76;
 const PROP_DESCRIPTION: u32 = 28;
 const PROP_LOCATION: u32 = 58;
+const PROP_SERIAL_NUMBER: u32 = 372;
 const PROP_MAX_INFO_FRAMES: u32 = 63;
 const PROP_MAX_MASTER: u32 = 64;
 const PROP_LOCAL_DATE: u32 = 56;
 const PROP_LOCAL_TIME: u32 = 57;
+const PROP_UTC_OFFSET: u32 = 119;
+const PROP_DAYLIGHT_SAVINGS_STATUS: u32 = 24;
 const PROP_DEVICE_ADDRESS_BINDING: u32 = 30;
 const PROP_NETWORK_TYPE: u32 = 208;
 const PROP_NETWORK_NUMBER: u32 = 425;
@@ -76,14 +98,170 @@ const PROP_OUT_OF_SERVICE: u32 = 81;
 const PROP_IP_ADDRESS: u32 = 400;
 const PROP_SUBNET_MASK: u32 = 411;
 const PROP_BIP_MODE: u32 = 408;
+const PROP_PROPERTY_LIST: u32 = 371;
+const PROP_PRESENT_VALUE: u32 = 85;
+const PROP_STATUS_FLAGS: u32 = 111;
+const PROP_EVENT_STATE: u32 = 36;
+const PROP_RELIABILITY: u32 = 103;
+const PROP_UNITS: u32 = 117;
+
+// Proprietary properties of the Gateway Diagnostics object
+const PROP_ERROR_TOTAL: u32 = 512;
+const PROP_ERROR_COUNTS: u32 = 513; // Array of Unsigned, one per error kind (code order)
+const PROP_RECENT_ERRORS: u32 = 514; // List of CharacterString, newest first
+
+// Proprietary Network Port statistics (the standard object has no packet
+// counters); Unsigned, wrapping at 2^32 like an SNMP Counter32
+const PROP_PACKETS_SENT: u32 = 515;
+const PROP_PACKETS_RECEIVED: u32 = 516;
+const PROP_PACKET_ERRORS: u32 = 517;
+
+/// Properties the Device object can serve. Property_List is built from the
+/// entries here that actually return a value, so a property only shows up
+/// once a read handler exists for it.
+const DEVICE_PROPERTIES: &[u32] = &[
+    PROP_OBJECT_IDENTIFIER,
+    PROP_OBJECT_NAME,
+    PROP_OBJECT_TYPE,
+    PROP_SYSTEM_STATUS,
+    PROP_VENDOR_NAME,
+    PROP_VENDOR_IDENTIFIER,
+    PROP_MODEL_NAME,
+    PROP_FIRMWARE_REVISION,
+    PROP_APPLICATION_SOFTWARE_VERSION,
+    PROP_PROTOCOL_VERSION,
+    PROP_PROTOCOL_REVISION,
+    PROP_PROTOCOL_SERVICES_SUPPORTED,
+    PROP_PROTOCOL_OBJECT_TYPES_SUPPORTED,
+    PROP_MAX_APDU_LENGTH_ACCEPTED,
+    PROP_SEGMENTATION_SUPPORTED,
+    PROP_APDU_TIMEOUT,
+    PROP_NUMBER_OF_APDU_RETRIES,
+    PROP_DATABASE_REVISION,
+    PROP_OBJECT_LIST,
+    PROP_DESCRIPTION,
+    PROP_LOCATION,
+    PROP_SERIAL_NUMBER,
+    PROP_MAX_INFO_FRAMES,
+    PROP_MAX_MASTER,
+    PROP_DEVICE_ADDRESS_BINDING,
+    PROP_LOCAL_DATE,
+    PROP_LOCAL_TIME,
+    PROP_UTC_OFFSET,
+    PROP_DAYLIGHT_SAVINGS_STATUS,
+];
+
+/// Properties a Network Port object can serve. Link-specific entries
+/// (IP_Address, Max_Master, ...) are left out of Property_List for ports
+/// where they are not set.
+const NETWORK_PORT_PROPERTIES: &[u32] = &[
+    PROP_OBJECT_IDENTIFIER,
+    PROP_OBJECT_NAME,
+    PROP_OBJECT_TYPE,
+    PROP_NETWORK_TYPE,
+    PROP_PROTOCOL_LEVEL,
+    PROP_NETWORK_NUMBER,
+    PROP_NETWORK_NUMBER_QUALITY,
+    PROP_MAC_ADDRESS,
+    PROP_LINK_SPEED,
+    PROP_LINK_SPEEDS,
+    PROP_CHANGES_PENDING,
+    PROP_OUT_OF_SERVICE,
+    PROP_IP_ADDRESS,
+    PROP_SUBNET_MASK,
+    PROP_BIP_MODE,
+    PROP_MAX_MASTER,
+    PROP_MAX_INFO_FRAMES,
+    PROP_PACKETS_SENT,
+    PROP_PACKETS_RECEIVED,
+    PROP_PACKET_ERRORS,
+];
+
+/// Properties of the Gateway Diagnostics object
+const DIAGNOSTICS_PROPERTIES: &[u32] = &[
+    PROP_OBJECT_IDENTIFIER,
+    PROP_OBJECT_NAME,
+    PROP_OBJECT_TYPE,
+    PROP_ERROR_TOTAL,
+    PROP_ERROR_COUNTS,
+    PROP_RECENT_ERRORS,
+];
+
+/// Properties of the WiFi diagnostics Analog Values
+const ANALOG_VALUE_PROPERTIES: &[u32] = &[
+    PROP_OBJECT_IDENTIFIER,
+    PROP_OBJECT_NAME,
+    PROP_OBJECT_TYPE,
+    PROP_PRESENT_VALUE,
+    PROP_DESCRIPTION,
+    PROP_STATUS_FLAGS,
+    PROP_EVENT_STATE,
+    PROP_RELIABILITY,
+    PROP_OUT_OF_SERVICE,
+    PROP_UNITS,
+];
+
+/// Unconfirmed services the gateway initiates without executing them
+/// (I-Am answers, COV and alarm notifications)
+const INITIATED_UNCONFIRMED_SERVICES: &[u8] =
+    &[SERVICE_I_AM, SERVICE_UNCONFIRMED_COV_NOTIFICATION, SERVICE_UNCONFIRMED_EVENT_NOTIFICATION];
+
+/// Network Port properties reported in COV notifications. A change in the
+/// first list triggers one; the traffic counters move with every frame, so
+/// they only ride along.
+const NETWORK_PORT_COV_TRIGGERS: &[u32] = &[PROP_OUT_OF_SERVICE, PROP_NETWORK_NUMBER_QUALITY, PROP_PACKET_ERRORS];
+const NETWORK_PORT_COV_EXTRAS: &[u32] = &[PROP_PACKETS_SENT, PROP_PACKETS_RECEIVED];
+
+/// Gateway Diagnostics properties reported in COV notifications
+const DIAGNOSTICS_COV_TRIGGERS: &[u32] = &[PROP_ERROR_TOTAL];
+const DIAGNOSTICS_COV_EXTRAS: &[u32] = &[PROP_ERROR_COUNTS];
+
+/// Bit position of the first unconfirmed service in BACnetServicesSupported
+const UNCONFIRMED_SERVICES_BIT_OFFSET: u32 = 26;
+
+/// Minimum bit string lengths (Protocol_Revision 14)
+const SERVICES_SUPPORTED_BITS: u32 = 48;
+const OBJECT_TYPES_SUPPORTED_BITS: u32 = 56;
 
 /// Error classes
 const ERROR_CLASS_OBJECT: u32 = 1;
 const ERROR_CLASS_PROPERTY: u32 = 2;
+const ERROR_CLASS_RESOURCES: u32 = 3;
+const ERROR_CLASS_SERVICES: u32 = 5;
 
 /// Error codes
 const ERROR_CODE_UNKNOWN_OBJECT: u32 = 31;
 const ERROR_CODE_UNKNOWN_PROPERTY: u32 = 32;
+const ERROR_CODE_INVALID_DATA_TYPE: u32 = 9;
+const ERROR_CODE_VALUE_OUT_OF_RANGE: u32 = 37;
+const ERROR_CODE_WRITE_ACCESS_DENIED: u32 = 40;
+const ERROR_CODE_CHARACTER_SET_NOT_SUPPORTED: u32 = 41;
+const ERROR_CODE_PROPERTY_IS_NOT_AN_ARRAY: u32 = 50;
+const ERROR_CODE_NO_SPACE_TO_ADD_LIST_ELEMENT: u32 = 19;
+const ERROR_CODE_OPTIONAL_FUNCTIONALITY_NOT_SUPPORTED: u32 = 45;
+
+/// Event notification parameters (Clause 13.9)
+const EVENT_PRIORITY_URGENT: u8 = 64;
+const EVENT_TYPE_CHANGE_OF_STATE: u8 = 1;
+const EVENT_STATE_NORMAL: u8 = 0;
+const EVENT_STATE_OFFNORMAL: u8 = 2;
+const NOTIFY_TYPE_ALARM: u8 = 0;
+
+// BACnetReliability
+const RELIABILITY_NO_FAULT_DETECTED: u32 = 0;
+const RELIABILITY_COMMUNICATION_FAILURE: u32 = 12;
+
+// BACnetStatusFlags bit
+const STATUS_FLAG_FAULT: u32 = 1;
+
+// BACnetEngineeringUnits
+const UNITS_NO_UNITS: u32 = 95;
+
+/// Maximum length of a writable Device string property (fits the 64-byte NVS string buffer)
+pub const MAX_SITE_STRING_LEN: usize = 63;
+
+/// Maximum number of devices kept in the discovery list
+pub const MAX_DISCOVERED_DEVICES: usize = 255;
 
 /// Device status values
 const STATUS_OPERATIONAL: u32 = 0;
@@ -92,18 +270,164 @@ const STATUS_OPERATIONAL: u32 = 0;
 const NETWORK_TYPE_BACNET_IP: u32 = 0;
 const NETWORK_TYPE_MSTP: u32 = 2;
 
-/// Network number quality enumeration
-const NETWORK_NUMBER_QUALITY_CONFIGURED: u32 = 0;
-const NETWORK_NUMBER_QUALITY_LEARNED: u32 = 1;
-const NETWORK_NUMBER_QUALITY_UNKNOWN: u32 = 2;
-
 /// BACnet/IP mode enumeration
 const BIP_MODE_NORMAL: u32 = 0;
 const BIP_MODE_FOREIGN: u32 = 1;
 const BIP_MODE_BBMD: u32 = 2;
 
+/// Network Port instance for the MS/TP port
+pub const MSTP_PORT_INSTANCE: u32 = 1;
+/// Network Port instance for the BACnet/IP port
+pub const IP_PORT_INSTANCE: u32 = 2;
+/// Network Port instance for the configuration hotspot (created when it first comes up)
+pub const AP_PORT_INSTANCE: u32 = 3;
+
+/// Services the local device executes; each can be switched off in the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalService {
+    ReadProperty = 0,
+    ReadPropertyMultiple = 1,
+    WriteProperty = 2,
+    WhoIs = 3,
+    SubscribeCov = 4,
+}
+
+impl LocalService {
+    pub const ALL: [LocalService; 5] = [
+        LocalService::ReadProperty,
+        LocalService::ReadPropertyMultiple,
+        LocalService::WriteProperty,
+        LocalService::WhoIs,
+        LocalService::SubscribeCov,
+    ];
+
+    /// Name used in the configuration list
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LocalService::ReadProperty => "read-property",
+            LocalService::ReadPropertyMultiple => "read-property-multiple",
+            LocalService::WriteProperty => "write-property",
+            LocalService::WhoIs => "who-is",
+            LocalService::SubscribeCov => "subscribe-cov",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|service| service.as_str() == s)
+    }
+
+    /// Service choice within its confirmed or unconfirmed table
+    fn service_choice(self) -> u8 {
+        match self {
+            LocalService::ReadProperty => SERVICE_READ_PROPERTY,
+            LocalService::ReadPropertyMultiple => SERVICE_READ_PROPERTY_MULTIPLE,
+            LocalService::WriteProperty => SERVICE_WRITE_PROPERTY,
+            LocalService::WhoIs => SERVICE_WHO_IS,
+            LocalService::SubscribeCov => SERVICE_SUBSCRIBE_COV,
+        }
+    }
+}
+
+/// Set of enabled local services, stored as a bitmask
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceWhitelist(u8);
+
+impl ServiceWhitelist {
+    /// Every service the device implements
+    pub const ALL: ServiceWhitelist = ServiceWhitelist(0x1F);
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn allows(self, service: LocalService) -> bool {
+        self.0 & (1 << service as u8) != 0
+    }
+
+    /// Comma-separated service names, in table order
+    pub fn format(self) -> String {
+        LocalService::ALL
+            .into_iter()
+            .filter(|service| self.allows(*service))
+            .map(LocalService::as_str)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Parse a comma-separated list of service names (empty = none)
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut bits = 0;
+        for name in s.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let service = LocalService::parse(&name.to_ascii_lowercase())
+                .ok_or_else(|| format!("unknown service '{}'", name))?;
+            bits |= 1 << service as u8;
+        }
+        Ok(Self(bits))
+    }
+}
+
+impl Default for ServiceWhitelist {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Traffic counters served by a Network Port object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortStatistics {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub errors: u64,
+}
+
+/// WiFi link figures mirrored to Analog Value objects (None while unavailable)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WifiDiagnostics {
+    /// Signal strength of the site access point in dBm
+    pub rssi: Option<i8>,
+    /// Channel of the site access point
+    pub channel: Option<u8>,
+    /// Times the station link came back after dropping
+    pub reconnects: Option<u32>,
+    /// Stations connected to the configuration hotspot
+    pub hotspot_clients: Option<u8>,
+}
+
+impl WifiDiagnostics {
+    /// Readings in `WIFI_ANALOG_VALUES` order
+    fn readings(&self) -> [Option<f32>; 4] {
+        [
+            self.rssi.map(f32::from),
+            self.channel.map(f32::from),
+            self.reconnects.map(|n| n as f32),
+            self.hotspot_clients.map(f32::from),
+        ]
+    }
+}
+
+/// WiFi diagnostics Analog Values: (instance, Object_Name, Description).
+/// Units are no-units; the Description names the unit where there is one.
+const WIFI_ANALOG_VALUES: [(u32, &str, &str); 4] = [
+    (1, "WiFi RSSI", "Signal strength of the site WiFi access point in dBm"),
+    (2, "WiFi Channel", "Channel of the site WiFi access point"),
+    (3, "WiFi Reconnects", "Times the site WiFi link came back since startup"),
+    (4, "Hotspot Clients", "Stations connected to the configuration hotspot"),
+];
+
+/// Last value of a WiFi diagnostics Analog Value
+#[derive(Debug, Clone, Copy)]
+struct AnalogReading {
+    value: f32,
+    /// False while the source is unavailable; the last value is kept
+    reliable: bool,
+}
+
 /// Network Port Object representing a communication interface
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct NetworkPort {
     /// Object instance number
     pub instance: u32,
@@ -111,10 +435,8 @@ pub struct NetworkPort {
     pub name: String,
     /// Network type (BACnet/IP, MS/TP, etc.)
     pub network_type: u32,
-    /// BACnet network number
-    pub network_number: u16,
-    /// Network number quality (configured, learned, unknown)
-    pub network_number_quality: u32,
+    /// BACnet network number and its quality (configured, learned, unknown)
+    pub network_number: Mutex<NetworkNumber>,
     /// MAC address (for MS/TP: single byte, for IP: 6 bytes)
     pub mac_address: Vec<u8>,
     /// Link speed in bits/second
@@ -125,18 +447,24 @@ pub struct NetworkPort {
     pub protocol_level: u32,
     /// Changes pending flag
     pub changes_pending: bool,
-    /// Out of service flag
-    pub out_of_service: bool,
-    /// IP address (for BACnet/IP ports only)
-    pub ip_address: Option<[u8; 4]>,
+    /// Out of service flag (set at runtime while the port is administratively disabled)
+    pub out_of_service: AtomicBool,
+    /// Whether the interface behind the port is up; Out_Of_Service while it is not
+    pub interface_up: AtomicBool,
+    /// Whether the object exists (in Object_List and readable)
+    pub present: AtomicBool,
+    /// IP address (for BACnet/IP ports only; follows DHCP lease changes)
+    pub ip_address: Mutex<Option<[u8; 4]>>,
     /// Subnet mask (for BACnet/IP ports only)
-    pub subnet_mask: Option<[u8; 4]>,
+    pub subnet_mask: Mutex<Option<[u8; 4]>>,
     /// BACnet/IP mode (Normal, Foreign, BBMD)
     pub bip_mode: Option<u32>,
     /// Max master (for MS/TP ports only)
     pub max_master: Option<u8>,
     /// Max info frames (for MS/TP ports only)
     pub max_info_frames: Option<u8>,
+    /// Packets sent, received and in error (synced from the driver and gateway stats)
+    pub statistics: Mutex<PortStatistics>,
 }
 
 impl NetworkPort {
@@ -153,19 +481,21 @@ impl NetworkPort {
             instance,
             name,
             network_type: NETWORK_TYPE_BACNET_IP,
-            network_number,
-            network_number_quality: NETWORK_NUMBER_QUALITY_CONFIGURED,
+            network_number: Mutex::new(NetworkNumber::configured(network_number)),
             mac_address: mac_address.to_vec(),
             link_speed: 100_000_000.0, // 100 Mbps default
             link_speeds: vec![10_000_000.0, 100_000_000.0, 1_000_000_000.0],
             protocol_level: 0,
             changes_pending: false,
-            out_of_service: false,
-            ip_address: Some(ip_address),
-            subnet_mask: Some(subnet_mask),
+            out_of_service: AtomicBool::new(false),
+            interface_up: AtomicBool::new(true),
+            present: AtomicBool::new(true),
+            ip_address: Mutex::new(Some(ip_address)),
+            subnet_mask: Mutex::new(Some(subnet_mask)),
             bip_mode: Some(BIP_MODE_NORMAL),
             max_master: None,
             max_info_frames: None,
+            statistics: Mutex::new(PortStatistics::default()),
         }
     }
 
@@ -183,22 +513,39 @@ impl NetworkPort {
             instance,
             name,
             network_type: NETWORK_TYPE_MSTP,
-            network_number,
-            network_number_quality: NETWORK_NUMBER_QUALITY_CONFIGURED,
+            network_number: Mutex::new(NetworkNumber::configured(network_number)),
             mac_address: vec![mac_address],
             link_speed: baud_rate as f32,
             link_speeds: vec![9600.0, 19200.0, 38400.0, 76800.0, 115200.0],
             protocol_level: 0,
             changes_pending: false,
-            out_of_service: false,
-            ip_address: None,
-            subnet_mask: None,
+            out_of_service: AtomicBool::new(false),
+            interface_up: AtomicBool::new(true),
+            present: AtomicBool::new(true),
+            ip_address: Mutex::new(None),
+            subnet_mask: Mutex::new(None),
             bip_mode: None,
             max_master: Some(max_master),
             max_info_frames: Some(max_info_frames),
+            statistics: Mutex::new(PortStatistics::default()),
         }
     }
 
+    /// Current network number and its quality
+    pub fn network_number(&self) -> NetworkNumber {
+        self.network_number.lock().map(|n| *n).unwrap_or_default()
+    }
+
+    /// Administratively disabled, or its interface is down
+    pub fn is_out_of_service(&self) -> bool {
+        self.out_of_service.load(Ordering::Relaxed) || !self.interface_up.load(Ordering::Relaxed)
+    }
+
+    /// Current traffic counters
+    pub fn statistics(&self) -> PortStatistics {
+        self.statistics.lock().map(|s| *s).unwrap_or_default()
+    }
+
     /// Get property value for this Network Port
     pub fn get_property(&self, property_id: u32) -> Option<Vec<u8>> {
         match property_id {
@@ -212,8 +559,9 @@ impl NetworkPort {
             PROP_OBJECT_TYPE => Some(vec![0x91, OBJECT_TYPE_NETWORK_PORT as u8]),
             PROP_NETWORK_TYPE => Some(vec![0x91, self.network_type as u8]),
             PROP_PROTOCOL_LEVEL => Some(vec![0x91, self.protocol_level as u8]),
-            PROP_NETWORK_NUMBER => Some(encode_unsigned(self.network_number as u32)),
-            PROP_NETWORK_NUMBER_QUALITY => Some(vec![0x91, self.network_number_quality as u8]),
+            // 0 while the number is unknown
+            PROP_NETWORK_NUMBER => Some(encode_unsigned(self.network_number().known().unwrap_or(0) as u32)),
+            PROP_NETWORK_NUMBER_QUALITY => Some(vec![0x91, self.network_number().quality as u8]),
             PROP_MAC_ADDRESS => {
                 let len = self.mac_address.len();
                 let mut v = if len < 5 {
@@ -234,11 +582,11 @@ impl NetworkPort {
                 Some(v)
             }
             PROP_CHANGES_PENDING => Some(vec![0x11, if self.changes_pending { 1 } else { 0 }]),
-            PROP_OUT_OF_SERVICE => Some(vec![0x11, if self.out_of_service { 1 } else { 0 }]),
+            PROP_OUT_OF_SERVICE => Some(vec![0x11, self.is_out_of_service() as u8]),
 
             // BACnet/IP specific properties
             PROP_IP_ADDRESS => {
-                if let Some(ip) = self.ip_address {
+                if let Some(ip) = *self.ip_address.lock().unwrap() {
                     let mut v = vec![0x64]; // Application tag 6, length 4
                     v.extend_from_slice(&ip);
                     Some(v)
@@ -247,7 +595,7 @@ impl NetworkPort {
                 }
             }
             PROP_SUBNET_MASK => {
-                if let Some(mask) = self.subnet_mask {
+                if let Some(mask) = *self.subnet_mask.lock().unwrap() {
                     let mut v = vec![0x64]; // Application tag 6, length 4
                     v.extend_from_slice(&mask);
                     Some(v)
@@ -279,11 +627,78 @@ impl NetworkPort {
                 }
             }
 
+            // Statistics
+            PROP_PACKETS_SENT => Some(encode_unsigned(self.statistics().packets_sent as u32)),
+            PROP_PACKETS_RECEIVED => Some(encode_unsigned(self.statistics().packets_received as u32)),
+            PROP_PACKET_ERRORS => Some(encode_unsigned(self.statistics().errors as u32)),
+
+            PROP_PROPERTY_LIST => Some(encode_property_list(NETWORK_PORT_PROPERTIES, |p| {
+                self.get_property(p).is_some()
+            })),
+
             _ => None,
         }
     }
 }
 
+/// Encode Property_List: every property in `candidates` that `has_value`
+/// reports as readable, minus the four the standard excludes
+fn encode_property_list(candidates: &[u32], has_value: impl Fn(u32) -> bool) -> Vec<u8> {
+    let mut v = Vec::new();
+    for &property_id in candidates {
+        if matches!(
+            property_id,
+            PROP_OBJECT_IDENTIFIER | PROP_OBJECT_NAME | PROP_OBJECT_TYPE | PROP_PROPERTY_LIST
+        ) {
+            continue;
+        }
+        if has_value(property_id) {
+            // Enumerated (application tag 9)
+            v.extend_from_slice(&encode_application_enumerated(property_id));
+        }
+    }
+    v
+}
+
+/// Helper function to encode an application-tagged Enumerated
+fn encode_application_enumerated(value: u32) -> Vec<u8> {
+    if value < 0x100 {
+        vec![0x91, value as u8]
+    } else if value < 0x10000 {
+        let mut v = vec![0x92];
+        v.extend_from_slice(&(value as u16).to_be_bytes());
+        v
+    } else {
+        let mut v = vec![0x94];
+        v.extend_from_slice(&value.to_be_bytes());
+        v
+    }
+}
+
+/// Encode an application-tagged Bit String with the given bits set.
+/// The string is at least `min_bits` long and grows to fit the highest bit.
+fn encode_bit_string(set_bits: &[u32], min_bits: u32) -> Vec<u8> {
+    let bit_count = set_bits.iter().map(|b| b + 1).max().unwrap_or(0).max(min_bits);
+    let byte_count = bit_count.div_ceil(8) as usize;
+    let unused_bits = (byte_count * 8) as u32 - bit_count;
+
+    let mut bits = vec![0u8; byte_count];
+    for &bit in set_bits {
+        bits[(bit / 8) as usize] |= 0x80 >> (bit % 8);
+    }
+
+    // Tag 8 (BitString); length covers the unused-bits byte plus the data
+    let len = byte_count + 1;
+    let mut v = if len <= 4 {
+        vec![0x80 | len as u8]
+    } else {
+        vec![0x85, len as u8]
+    };
+    v.push(unused_bits as u8);
+    v.extend_from_slice(&bits);
+    v
+}
+
 /// Helper function to encode a Real (32-bit float)
 fn encode_real(value: f32) -> Vec<u8> {
     let mut v = vec![0x44]; // Application tag 4 (Real), length 4
@@ -309,6 +724,39 @@ fn encode_unsigned(value: u32) -> Vec<u8> {
     }
 }
 
+/// Helper function to encode a signed integer in as few octets as it needs
+fn encode_signed(value: i32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let len = if (-0x80..0x80).contains(&value) {
+        1
+    } else if (-0x8000..0x8000).contains(&value) {
+        2
+    } else if (-0x80_0000..0x80_0000).contains(&value) {
+        3
+    } else {
+        4
+    };
+    let mut v = vec![0x30 | len as u8]; // Application tag 3 (Signed Integer)
+    v.extend_from_slice(&bytes[4 - len..]);
+    v
+}
+
+/// Helper function to encode a Date; unspecified (all 0xFF) until the clock is set
+fn encode_local_date(now: Option<LocalTime>) -> Vec<u8> {
+    match now {
+        Some(t) => vec![0xA4, (t.year - 1900).clamp(0, 254) as u8, t.month as u8, t.day as u8, t.weekday],
+        None => vec![0xA4, 0xFF, 0xFF, 0xFF, 0xFF],
+    }
+}
+
+/// Helper function to encode a Time; unspecified (all 0xFF) until the clock is set
+fn encode_local_time(now: Option<LocalTime>) -> Vec<u8> {
+    match now {
+        Some(t) => vec![0xB4, t.hour, t.minute, t.second, t.hundredths],
+        None => vec![0xB4, 0xFF, 0xFF, 0xFF, 0xFF],
+    }
+}
+
 /// Helper function to encode a character string
 fn encode_character_string(s: &str) -> Vec<u8> {
     let bytes = s.as_bytes();
@@ -335,6 +783,78 @@ fn encode_character_string(s: &str) -> Vec<u8> {
     result
 }
 
+/// Object identifier of the Gateway Diagnostics object
+fn diagnostics_object_id() -> u32 {
+    ((OBJECT_TYPE_GATEWAY_DIAGNOSTICS as u32) << 22) | DIAGNOSTICS_INSTANCE
+}
+
+/// Space for Recent_Errors in a ReadProperty-ACK; older errors that do not
+/// fit are left out rather than segmenting the response
+const RECENT_ERRORS_BUDGET: usize = MAX_APDU_LENGTH as usize - 32;
+
+/// Read a Gateway Diagnostics property from the shared error log
+fn diagnostics_property(property_id: u32) -> Option<Vec<u8>> {
+    match property_id {
+        PROP_OBJECT_IDENTIFIER => {
+            let mut v = vec![0xC4];
+            v.extend_from_slice(&diagnostics_object_id().to_be_bytes());
+            Some(v)
+        }
+        PROP_OBJECT_NAME => Some(encode_character_string("Gateway Diagnostics")),
+        PROP_OBJECT_TYPE => Some(encode_application_enumerated(OBJECT_TYPE_GATEWAY_DIAGNOSTICS as u32)),
+        PROP_PROPERTY_LIST => Some(encode_property_list(DIAGNOSTICS_PROPERTIES, |_| true)),
+        PROP_ERROR_TOTAL => errors::with_log(|log| encode_unsigned(log.total())),
+        PROP_ERROR_COUNTS => errors::with_log(|log| {
+            ErrorKind::ALL.iter().flat_map(|&kind| encode_unsigned(log.count(kind))).collect()
+        }),
+        PROP_RECENT_ERRORS => errors::with_log(|log| {
+            let mut v = Vec::new();
+            for error in log.recent() {
+                let entry = encode_character_string(&format!(
+                    "{}s ago {}/{}: {}",
+                    error.at.elapsed().as_secs(),
+                    error.source.as_str(),
+                    error.kind.as_str(),
+                    error.detail
+                ));
+                if v.len() + entry.len() > RECENT_ERRORS_BUDGET {
+                    break;
+                }
+                v.extend_from_slice(&entry);
+            }
+            v
+        }),
+        _ => None,
+    }
+}
+
+/// Per-site Device properties that can be changed at runtime
+/// (WriteProperty or web UI) and are persisted in NVS
+#[derive(Debug, Clone, Default)]
+pub struct DeviceSiteInfo {
+    pub location: String,
+    pub description: String,
+    pub serial_number: String,
+}
+
+/// A Device_Address_Binding entry: a device and the address it was heard at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressBinding {
+    pub device_instance: u32,
+    pub network: u16,
+    pub mac: Vec<u8>,
+}
+
+/// Space for Device_Address_Binding in a ReadProperty-ACK; entries that do
+/// not fit are left out rather than segmenting the response
+const ADDRESS_BINDING_BUDGET: usize = MAX_APDU_LENGTH as usize - 32;
+
+/// Handler for a confirmed service: (device, requester, invoke_id, service data)
+type ConfirmedServiceHandler = fn(&LocalDevice, &Requester, u8, &[u8]) -> Option<(Vec<u8>, bool)>;
+
+/// Handler for an unconfirmed service: (device, service data)
+type UnconfirmedServiceHandler = fn(&LocalDevice, &[u8]) -> Option<(Vec<u8>, bool)>;
+
 /// Local BACnet Device
 pub struct LocalDevice {
     /// Device instance number
@@ -355,9 +875,40 @@ pub struct LocalDevice {
     pub max_info_frames: u8,
     /// Network Port objects
     pub network_ports: Vec<NetworkPort>,
+    /// Location, Description and Serial_Number (shared with receive threads)
+    site_info: Mutex<DeviceSiteInfo>,
+    /// Set when site info was written over BACnet and needs persisting
+    site_info_written: AtomicBool,
+    /// Device_Address_Binding (synced from the gateway's learned bindings)
+    address_bindings: Mutex<Vec<AddressBinding>>,
+    /// Sequence number for event notification timestamps
+    event_sequence: AtomicU32,
+    /// COV subscriptions to the Network Ports and Gateway Diagnostics
+    cov: Mutex<CovTable>,
+    /// Write-protect jumper fitted: WriteProperty is refused
+    write_protected: AtomicBool,
+    /// WiFi diagnostics Analog Values, each created by its first reading
+    wifi_readings: Mutex<[Option<AnalogReading>; 4]>,
+    /// Services answered; the rest are rejected or ignored and left out of
+    /// Protocol_Services_Supported
+    services: ServiceWhitelist,
 }
 
 impl LocalDevice {
+    /// Confirmed services executed by the device. Dispatch and
+    /// Protocol_Services_Supported are both driven by this table.
+    const CONFIRMED_SERVICES: &'static [(LocalService, ConfirmedServiceHandler)] = &[
+        (LocalService::ReadProperty, Self::handle_read_property),
+        (LocalService::ReadPropertyMultiple, Self::handle_read_property_multiple),
+        (LocalService::WriteProperty, Self::handle_write_property),
+        (LocalService::SubscribeCov, Self::handle_subscribe_cov),
+    ];
+
+    /// Unconfirmed services executed by the device
+    const UNCONFIRMED_SERVICES: &'static [(LocalService, UnconfirmedServiceHandler)] = &[
+        (LocalService::WhoIs, Self::handle_who_is),
+    ];
+
     /// Create a new local device
     pub fn new(device_instance: u32) -> Self {
         Self::new_with_mstp(device_instance, 127, 1)
@@ -376,9 +927,156 @@ impl LocalDevice {
             max_master,
             max_info_frames,
             network_ports: Vec::new(),
+            site_info: Mutex::new(DeviceSiteInfo {
+                description: "BACnet MS/TP to IP Gateway".to_string(),
+                ..Default::default()
+            }),
+            site_info_written: AtomicBool::new(false),
+            address_bindings: Mutex::new(Vec::new()),
+            event_sequence: AtomicU32::new(1),
+            cov: Mutex::new(CovTable::new()),
+            write_protected: AtomicBool::new(false),
+            wifi_readings: Mutex::new([None; 4]),
+            services: ServiceWhitelist::ALL,
         }
     }
 
+    /// Limit the services the device answers (set before the device is shared)
+    pub fn set_services(&mut self, services: ServiceWhitelist) {
+        let disabled: Vec<_> = LocalService::ALL
+            .into_iter()
+            .filter(|service| !services.allows(*service))
+            .map(LocalService::as_str)
+            .collect();
+        if !disabled.is_empty() {
+            info!("Local device services disabled: {}", disabled.join(", "));
+        }
+        self.services = services;
+    }
+
+    /// Replace Location, Description and Serial_Number
+    pub fn set_site_info(&self, site_info: DeviceSiteInfo) {
+        if let Ok(mut info) = self.site_info.lock() {
+            *info = site_info;
+        }
+    }
+
+    /// Get a copy of Location, Description and Serial_Number
+    pub fn site_info(&self) -> DeviceSiteInfo {
+        self.site_info.lock().map(|info| info.clone()).unwrap_or_default()
+    }
+
+    /// Check (and clear) whether site info was changed by WriteProperty since the last call
+    pub fn take_site_info_written(&self) -> bool {
+        self.site_info_written.swap(false, Ordering::SeqCst)
+    }
+
+    /// Refuse WriteProperty while the write-protect jumper is fitted
+    pub fn set_write_protected(&self, write_protected: bool) {
+        self.write_protected.store(write_protected, Ordering::Relaxed);
+    }
+
+    /// Replace the Device_Address_Binding list
+    pub fn set_address_bindings(&self, bindings: Vec<AddressBinding>) {
+        if let Ok(mut current) = self.address_bindings.lock() {
+            *current = bindings;
+        }
+    }
+
+    /// Encode Device_Address_Binding: a list of (Device identifier, network
+    /// number, MAC address), cut short if it would not fit in one APDU
+    fn encode_address_bindings(&self) -> Vec<u8> {
+        let mut v = Vec::new();
+        let Ok(bindings) = self.address_bindings.lock() else { return v };
+        for binding in bindings.iter() {
+            let mut entry = vec![0xC4];
+            entry.extend_from_slice(&(((OBJECT_TYPE_DEVICE as u32) << 22) | binding.device_instance).to_be_bytes());
+            entry.extend_from_slice(&encode_unsigned(binding.network as u32));
+            // Octet string (application tag 6); MACs are at most 6 bytes
+            if binding.mac.len() <= 4 {
+                entry.push(0x60 | binding.mac.len() as u8);
+            } else {
+                entry.extend_from_slice(&[0x65, binding.mac.len() as u8]);
+            }
+            entry.extend_from_slice(&binding.mac);
+
+            if v.len() + entry.len() > ADDRESS_BINDING_BUDGET {
+                debug!("Device_Address_Binding truncated at {} bytes", v.len());
+                break;
+            }
+            v.extend_from_slice(&entry);
+        }
+        v
+    }
+
+    /// Report a Network Port as Out_Of_Service (administratively disabled) or back in service
+    pub fn set_port_out_of_service(&self, instance: u32, out_of_service: bool) {
+        if let Some(port) = self.network_ports.iter().find(|p| p.instance == instance) {
+            port.out_of_service.store(out_of_service, Ordering::Relaxed);
+        }
+    }
+
+    /// Update a Network Port's traffic counters
+    pub fn set_port_statistics(&self, instance: u32, statistics: PortStatistics) {
+        if let Some(port) = self.network_ports.iter().find(|p| p.instance == instance) {
+            if let Ok(mut current) = port.statistics.lock() {
+                *current = statistics;
+            }
+        }
+    }
+
+    /// Update a Network Port's Network_Number and Network_Number_Quality
+    pub fn set_network_number(&self, instance: u32, number: NetworkNumber) {
+        if let Some(port) = self.network_ports.iter().find(|p| p.instance == instance) {
+            if let Ok(mut current) = port.network_number.lock() {
+                *current = number;
+            }
+        }
+    }
+
+    /// Update a BACnet/IP Network Port's IP_Address and IP_Subnet_Mask
+    pub fn set_ip_address(&self, instance: u32, ip_address: [u8; 4], subnet_mask: [u8; 4]) {
+        if let Some(port) = self.network_ports.iter().find(|p| p.instance == instance) {
+            *port.ip_address.lock().unwrap() = Some(ip_address);
+            *port.subnet_mask.lock().unwrap() = Some(subnet_mask);
+        }
+    }
+
+    /// Follow the station interface: its address while it is up, Out_Of_Service
+    /// (keeping the last address) while the gateway runs as a hotspot only
+    pub fn set_station_interface(&self, interface: Option<([u8; 4], [u8; 4])>) {
+        self.set_interface(IP_PORT_INSTANCE, interface);
+    }
+
+    /// Follow the configuration hotspot: its Network Port is created the first
+    /// time the hotspot comes up and is Out_Of_Service while it is down
+    pub fn set_hotspot_interface(&self, interface: Option<([u8; 4], [u8; 4])>) {
+        self.set_interface(AP_PORT_INSTANCE, interface);
+    }
+
+    fn set_interface(&self, instance: u32, interface: Option<([u8; 4], [u8; 4])>) {
+        let Some(port) = self.network_ports.iter().find(|p| p.instance == instance) else { return };
+        if let Some((ip_address, subnet_mask)) = interface {
+            self.set_ip_address(instance, ip_address, subnet_mask);
+            if !port.present.swap(true, Ordering::Relaxed) {
+                info!("Network Port {} created ({})", instance, port.name);
+            }
+        }
+        port.interface_up.store(interface.is_some(), Ordering::Relaxed);
+    }
+
+    /// Network Port by instance, if it exists
+    fn network_port(&self, instance: u32) -> Option<&NetworkPort> {
+        self.network_ports
+            .iter()
+            .find(|p| p.instance == instance && p.present.load(Ordering::Relaxed))
+    }
+
+    /// Network Ports that exist, in instance order
+    fn present_network_ports(&self) -> impl Iterator<Item = &NetworkPort> {
+        self.network_ports.iter().filter(|p| p.present.load(Ordering::Relaxed))
+    }
+
     /// Add a Network Port object to this device
     pub fn add_network_port(&mut self, port: NetworkPort) {
         info!("Adding Network Port: {} (instance {})", port.name, port.instance);
@@ -399,7 +1097,7 @@ impl LocalDevice {
     ) {
         // Create MS/TP Network Port (instance 1)
         let mstp_port = NetworkPort::new_mstp(
-            1,
+            MSTP_PORT_INSTANCE,
             "MS/TP Port".to_string(),
             mstp_network,
             mstp_address,
@@ -411,7 +1109,7 @@ impl LocalDevice {
 
         // Create BACnet/IP Network Port (instance 2)
         let ip_port = NetworkPort::new_bacnet_ip(
-            2,
+            IP_PORT_INSTANCE,
             "BACnet/IP Port".to_string(),
             ip_network,
             mac_address,
@@ -426,9 +1124,94 @@ impl LocalDevice {
         );
     }
 
-    /// Process an APDU and return a response if applicable
+    /// Add the configuration hotspot's BACnet/IP Network Port (instance 3).
+    /// It stays out of Object_List until `set_hotspot_interface` reports the
+    /// hotspot up; the hotspot subnet is part of the same BACnet/IP network.
+    pub fn add_hotspot_network_port(&mut self, ip_network: u16, mac_address: [u8; 6]) {
+        let port = NetworkPort::new_bacnet_ip(
+            AP_PORT_INSTANCE,
+            "Hotspot Port".to_string(),
+            ip_network,
+            mac_address,
+            [0; 4],
+            [0; 4],
+        );
+        *port.ip_address.lock().unwrap() = None;
+        *port.subnet_mask.lock().unwrap() = None;
+        port.interface_up.store(false, Ordering::Relaxed);
+        port.present.store(false, Ordering::Relaxed);
+        self.add_network_port(port);
+    }
+
+    /// Update the WiFi diagnostics Analog Values. An object is created by its
+    /// first reading; when a reading goes missing afterwards the object keeps
+    /// its last value and reports a fault.
+    pub fn set_wifi_diagnostics(&self, diagnostics: WifiDiagnostics) {
+        let Ok(mut readings) = self.wifi_readings.lock() else { return };
+        for ((reading, value), (instance, name, _)) in
+            readings.iter_mut().zip(diagnostics.readings()).zip(WIFI_ANALOG_VALUES)
+        {
+            match (value, reading.as_mut()) {
+                (Some(value), None) => {
+                    info!("Analog Value {} created ({})", instance, name);
+                    *reading = Some(AnalogReading { value, reliable: true });
+                }
+                (Some(value), Some(current)) => *current = AnalogReading { value, reliable: true },
+                (None, Some(current)) => current.reliable = false,
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// Last reading of a WiFi diagnostics Analog Value, if the object exists
+    fn analog_reading(&self, instance: u32) -> Option<AnalogReading> {
+        let index = WIFI_ANALOG_VALUES.iter().position(|(i, _, _)| *i == instance)?;
+        self.wifi_readings.lock().ok()?[index]
+    }
+
+    /// Object identifiers of the Analog Values that exist, in instance order
+    fn present_analog_values(&self) -> Vec<u32> {
+        WIFI_ANALOG_VALUES
+            .iter()
+            .filter(|(instance, _, _)| self.analog_reading(*instance).is_some())
+            .map(|(instance, _, _)| ((OBJECT_TYPE_ANALOG_VALUE as u32) << 22) | instance)
+            .collect()
+    }
+
+    /// Read a property of a WiFi diagnostics Analog Value
+    fn analog_value_property(&self, instance: u32, property_id: u32) -> Option<Vec<u8>> {
+        let (_, name, description) = WIFI_ANALOG_VALUES.iter().find(|(i, _, _)| *i == instance)?;
+        let reading = self.analog_reading(instance)?;
+        match property_id {
+            PROP_OBJECT_IDENTIFIER => {
+                let mut v = vec![0xC4];
+                v.extend_from_slice(&(((OBJECT_TYPE_ANALOG_VALUE as u32) << 22) | instance).to_be_bytes());
+                Some(v)
+            }
+            PROP_OBJECT_NAME => Some(encode_character_string(name)),
+            PROP_OBJECT_TYPE => Some(encode_application_enumerated(OBJECT_TYPE_ANALOG_VALUE as u32)),
+            PROP_PRESENT_VALUE => Some(encode_real(reading.value)),
+            PROP_DESCRIPTION => Some(encode_character_string(description)),
+            PROP_STATUS_FLAGS => {
+                let fault: &[u32] = if reading.reliable { &[] } else { &[STATUS_FLAG_FAULT] };
+                Some(encode_bit_string(fault, 4))
+            }
+            PROP_EVENT_STATE => Some(encode_application_enumerated(EVENT_STATE_NORMAL as u32)),
+            PROP_RELIABILITY => Some(encode_application_enumerated(if reading.reliable {
+                RELIABILITY_NO_FAULT_DETECTED
+            } else {
+                RELIABILITY_COMMUNICATION_FAILURE
+            })),
+            PROP_OUT_OF_SERVICE => Some(vec![0x10]), // Boolean false
+            PROP_UNITS => Some(encode_application_enumerated(UNITS_NO_UNITS)),
+            PROP_PROPERTY_LIST => Some(encode_property_list(ANALOG_VALUE_PROPERTIES, |_| true)),
+            _ => None,
+        }
+    }
+
+    /// Process an APDU from `from` and return a response if applicable
     /// Returns (response_data, is_broadcast_response)
-    pub fn process_apdu(&self, apdu: &[u8]) -> Option<(Vec<u8>, bool)> {
+    pub fn process_apdu(&self, apdu: &[u8], from: &Requester) -> Option<(Vec<u8>, bool)> {
         if apdu.is_empty() {
             return None;
         }
@@ -437,7 +1220,7 @@ impl LocalDevice {
 
         match pdu_type {
             APDU_UNCONFIRMED_REQUEST => self.process_unconfirmed_request(apdu),
-            APDU_CONFIRMED_REQUEST => self.process_confirmed_request(apdu),
+            APDU_CONFIRMED_REQUEST => self.process_confirmed_request(apdu, from),
             _ => {
                 trace!("Ignoring APDU type 0x{:02X}", pdu_type);
                 None
@@ -453,9 +1236,13 @@ impl LocalDevice {
 
         let service_choice = apdu[1];
 
-        match service_choice {
-            SERVICE_WHO_IS => self.handle_who_is(&apdu[2..]),
-            _ => {
+        // Disabled services are ignored like unknown ones: unconfirmed requests get no reply
+        match Self::UNCONFIRMED_SERVICES
+            .iter()
+            .find(|(service, _)| service.service_choice() == service_choice && self.services.allows(*service))
+        {
+            Some((_, handler)) => handler(self, &apdu[2..]),
+            None => {
                 trace!("Ignoring unconfirmed service {}", service_choice);
                 None
             }
@@ -614,33 +1401,8 @@ impl LocalDevice {
         apdu
     }
 
-    /// Build I-Am-Router-To-Network NPDU
-    /// This is a network layer message (not APDU) announcing this router can reach certain networks
-    /// Per BACnet Clause 6.6.3, message type 0x01
-    pub fn build_i_am_router_to_network(networks: &[u16]) -> Vec<u8> {
-        let mut npdu = Vec::with_capacity(4 + networks.len() * 2);
-
-        // NPDU version
-        npdu.push(0x01);
-
-        // Control byte: network layer message (bit 7 = 1)
-        npdu.push(0x80);
-
-        // Message type: I-Am-Router-To-Network = 0x01
-        npdu.push(0x01);
-
-        // List of network numbers this router can reach
-        for &net in networks {
-            npdu.push((net >> 8) as u8);
-            npdu.push((net & 0xFF) as u8);
-        }
-
-        debug!("Built I-Am-Router-To-Network for networks: {:?}", networks);
-        npdu
-    }
-
     /// Process confirmed request (ReadProperty, etc.)
-    fn process_confirmed_request(&self, apdu: &[u8]) -> Option<(Vec<u8>, bool)> {
+    fn process_confirmed_request(&self, apdu: &[u8], from: &Requester) -> Option<(Vec<u8>, bool)> {
         if apdu.len() < 4 {
             return None;
         }
@@ -653,16 +1415,55 @@ impl LocalDevice {
         let invoke_id = apdu[2];
         let service_choice = apdu[3];
 
-        match service_choice {
-            SERVICE_READ_PROPERTY => self.handle_read_property(invoke_id, &apdu[4..]),
-            SERVICE_READ_PROPERTY_MULTIPLE => self.handle_read_property_multiple(invoke_id, &apdu[4..]),
-            _ => {
+        // A disabled service is not in Protocol_Services_Supported, so it is
+        // rejected exactly like one the device never implemented
+        match Self::CONFIRMED_SERVICES
+            .iter()
+            .find(|(service, _)| service.service_choice() == service_choice && self.services.allows(*service))
+        {
+            Some((_, handler)) => handler(self, from, invoke_id, &apdu[4..]),
+            None => {
                 debug!("Unsupported confirmed service {} - sending Reject", service_choice);
                 self.build_reject_response(invoke_id, REJECT_UNRECOGNIZED_SERVICE)
             }
         }
     }
 
+    /// Encode Protocol_Services_Supported from the enabled entries of the service tables
+    fn encode_services_supported(&self) -> Vec<u8> {
+        let mut bits: Vec<u32> = Self::CONFIRMED_SERVICES
+            .iter()
+            .filter(|(service, _)| self.services.allows(*service))
+            .map(|(service, _)| service.service_choice() as u32)
+            .collect();
+        bits.extend(
+            Self::UNCONFIRMED_SERVICES
+                .iter()
+                .filter(|(service, _)| self.services.allows(*service))
+                .map(|(service, _)| service.service_choice())
+                .chain(INITIATED_UNCONFIRMED_SERVICES.iter().copied())
+                .map(|choice| UNCONFIRMED_SERVICES_BIT_OFFSET + choice as u32),
+        );
+        encode_bit_string(&bits, SERVICES_SUPPORTED_BITS)
+    }
+
+    /// Encode Protocol_Object_Types_Supported from the objects in the database
+    fn encode_object_types_supported(&self) -> Vec<u8> {
+        let mut bits = vec![OBJECT_TYPE_DEVICE as u32];
+        if !self.network_ports.is_empty() {
+            bits.push(OBJECT_TYPE_NETWORK_PORT as u32);
+        }
+        if !self.present_analog_values().is_empty() {
+            bits.push(OBJECT_TYPE_ANALOG_VALUE as u32);
+        }
+        encode_bit_string(&bits, OBJECT_TYPES_SUPPORTED_BITS)
+    }
+
+    /// Encode the Device object's Property_List
+    fn encode_device_property_list(&self, object_id: u32) -> Vec<u8> {
+        encode_property_list(DEVICE_PROPERTIES, |p| self.get_property_value(object_id, p).is_some())
+    }
+
     /// Build Reject response for unsupported services
     fn build_reject_response(&self, invoke_id: u8, reject_reason: u8) -> Option<(Vec<u8>, bool)> {
         let mut apdu = Vec::with_capacity(3);
@@ -675,8 +1476,233 @@ impl LocalDevice {
         Some((apdu, false))
     }
 
+    /// Handle WriteProperty request
+    /// Only Location, Description and Serial_Number of the Device object are writable
+    fn handle_write_property(&self, _from: &Requester, invoke_id: u8, data: &[u8]) -> Option<(Vec<u8>, bool)> {
+        // Context tag 0: Object Identifier
+        let (object_id, consumed) = match self.decode_context_unsigned(data, 0, 0) {
+            Some(v) => v,
+            None => return self.build_reject_response(invoke_id, REJECT_MISSING_REQUIRED_PARAMETER),
+        };
+        let mut pos = consumed;
+
+        // Context tag 1: Property Identifier
+        let (property_id, consumed) = match self.decode_context_unsigned(data, pos, 1) {
+            Some(v) => v,
+            None => return self.build_reject_response(invoke_id, REJECT_MISSING_REQUIRED_PARAMETER),
+        };
+        pos += consumed;
+
+        let object_type = (object_id >> 22) as u16;
+        let object_instance = object_id & 0x3FFFFF;
+        if object_id == diagnostics_object_id() {
+            // Error counters are read-only over BACnet
+            return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_WRITE_ACCESS_DENIED);
+        }
+        if object_type != OBJECT_TYPE_DEVICE || object_instance != self.device_instance {
+            debug!("WriteProperty for unknown object: type={}, instance={}", object_type, object_instance);
+            return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_OBJECT, ERROR_CODE_UNKNOWN_OBJECT);
+        }
+
+        match property_id {
+            PROP_LOCATION | PROP_DESCRIPTION | PROP_SERIAL_NUMBER if self.write_protected.load(Ordering::Relaxed) => {
+                info!("WriteProperty: Device:{} property {} refused - write-protected", self.device_instance, property_id);
+                return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_WRITE_ACCESS_DENIED);
+            }
+            PROP_LOCATION | PROP_DESCRIPTION | PROP_SERIAL_NUMBER => {}
+            _ => {
+                if self.get_property_value(object_id, property_id).is_some() {
+                    return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_WRITE_ACCESS_DENIED);
+                }
+                return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_UNKNOWN_PROPERTY);
+            }
+        }
+
+        // Context tag 2: Property Array Index (optional) - none of these are arrays
+        if self.decode_context_unsigned(data, pos, 2).is_some() {
+            return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_PROPERTY_IS_NOT_AN_ARRAY);
+        }
+
+        // Context tag 3: Property Value (opening tag, application-tagged value, closing tag)
+        if pos >= data.len() || data[pos] != 0x3E {
+            return self.build_reject_response(invoke_id, REJECT_MISSING_REQUIRED_PARAMETER);
+        }
+        pos += 1;
+
+        let (value, consumed) = match decode_character_string(&data[pos..]) {
+            Ok(v) => v,
+            Err(error_code) => {
+                return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, error_code);
+            }
+        };
+        pos += consumed;
+
+        if pos >= data.len() || data[pos] != 0x3F {
+            return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_INVALID_DATA_TYPE);
+        }
+
+        if value.len() > MAX_SITE_STRING_LEN {
+            return self.build_error_response(invoke_id, SERVICE_WRITE_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_VALUE_OUT_OF_RANGE);
+        }
+
+        // Context tag 4: Priority is ignored (properties are not commandable)
+        if let Ok(mut info) = self.site_info.lock() {
+            match property_id {
+                PROP_LOCATION => info.location = value,
+                PROP_DESCRIPTION => info.description = value,
+                _ => info.serial_number = value,
+            }
+        }
+        self.site_info_written.store(true, Ordering::SeqCst);
+        info!("WriteProperty: Device:{} property {} updated", self.device_instance, property_id);
+
+        Some((vec![APDU_SIMPLE_ACK, invoke_id, SERVICE_WRITE_PROPERTY], false))
+    }
+
+    /// Handle SubscribeCOV (Clause 13.14) for a Network Port or Gateway
+    /// Diagnostics. Without issueConfirmedNotifications and lifetime it is a
+    /// cancellation. Only unconfirmed notifications are sent, so a request for
+    /// confirmed ones is refused rather than silently downgraded.
+    fn handle_subscribe_cov(&self, from: &Requester, invoke_id: u8, data: &[u8]) -> Option<(Vec<u8>, bool)> {
+        // Context tag 0: Subscriber Process Identifier
+        let Some((process_id, consumed)) = self.decode_context_unsigned(data, 0, 0) else {
+            return self.build_reject_response(invoke_id, REJECT_MISSING_REQUIRED_PARAMETER);
+        };
+        let mut pos = consumed;
+
+        // Context tag 1: Monitored Object Identifier
+        let Some((object_id, consumed)) = self.decode_context_unsigned(data, pos, 1) else {
+            return self.build_reject_response(invoke_id, REJECT_MISSING_REQUIRED_PARAMETER);
+        };
+        pos += consumed;
+
+        // Context tag 2: Issue Confirmed Notifications, context tag 3: Lifetime (both optional)
+        let confirmed = self.decode_context_unsigned(data, pos, 2);
+        if let Some((_, consumed)) = confirmed {
+            pos += consumed;
+        }
+        let lifetime = self.decode_context_unsigned(data, pos, 3).map(|(seconds, _)| seconds);
+
+        let ack = Some((vec![APDU_SIMPLE_ACK, invoke_id, SERVICE_SUBSCRIBE_COV], false));
+        let Ok(mut cov) = self.cov.lock() else { return None };
+        let confirmed = match (confirmed, lifetime) {
+            (None, None) => {
+                if cov.cancel(from, process_id, object_id) {
+                    info!("COV subscription cancelled: {} process {} object 0x{:08X}", from, process_id, object_id);
+                }
+                return ack;
+            }
+            (Some((confirmed, _)), _) => confirmed != 0,
+            (None, Some(_)) => return self.build_reject_response(invoke_id, REJECT_MISSING_REQUIRED_PARAMETER),
+        };
+
+        if self.cov_properties(object_id).is_none() {
+            let known = object_id == (((OBJECT_TYPE_DEVICE as u32) << 22) | self.device_instance)
+                || self.network_ports.iter().any(|p| (((OBJECT_TYPE_NETWORK_PORT as u32) << 22) | p.instance) == object_id)
+                || self.present_analog_values().contains(&object_id);
+            let code = if known { ERROR_CODE_OPTIONAL_FUNCTIONALITY_NOT_SUPPORTED } else { ERROR_CODE_UNKNOWN_OBJECT };
+            return self.build_error_response(invoke_id, SERVICE_SUBSCRIBE_COV, ERROR_CLASS_OBJECT, code);
+        }
+        if confirmed {
+            return self.build_error_response(
+                invoke_id,
+                SERVICE_SUBSCRIBE_COV,
+                ERROR_CLASS_SERVICES,
+                ERROR_CODE_OPTIONAL_FUNCTIONALITY_NOT_SUPPORTED,
+            );
+        }
+
+        let lifetime = lifetime.unwrap_or(0);
+        match cov.subscribe(from.clone(), process_id, object_id, lifetime, Instant::now()) {
+            Some(outcome) => {
+                info!(
+                    "COV subscription {}: {} process {} object 0x{:08X}, lifetime {}s",
+                    if outcome == SubscribeOutcome::New { "added" } else { "renewed" },
+                    from,
+                    process_id,
+                    object_id,
+                    lifetime
+                );
+                ack
+            }
+            None => {
+                debug!("COV subscription table full ({} entries)", MAX_COV_SUBSCRIPTIONS);
+                self.build_error_response(invoke_id, SERVICE_SUBSCRIBE_COV, ERROR_CLASS_RESOURCES, ERROR_CODE_NO_SPACE_TO_ADD_LIST_ELEMENT)
+            }
+        }
+    }
+
+    /// COV trigger and ride-along properties of an object that supports
+    /// subscriptions (a present Network Port or Gateway Diagnostics)
+    fn cov_properties(&self, object_id: u32) -> Option<(&'static [u32], &'static [u32])> {
+        if object_id == diagnostics_object_id() {
+            return Some((DIAGNOSTICS_COV_TRIGGERS, DIAGNOSTICS_COV_EXTRAS));
+        }
+        ((object_id >> 22) as u16 == OBJECT_TYPE_NETWORK_PORT)
+            .then(|| self.network_port(object_id & 0x3FFFFF))
+            .flatten()
+            .map(|_| (NETWORK_PORT_COV_TRIGGERS, NETWORK_PORT_COV_EXTRAS))
+    }
+
+    /// Read a property of a Network Port or Gateway Diagnostics
+    fn cov_property_value(&self, object_id: u32, property_id: u32) -> Option<Vec<u8>> {
+        if object_id == diagnostics_object_id() {
+            diagnostics_property(property_id)
+        } else {
+            self.network_port(object_id & 0x3FFFFF)?.get_property(property_id)
+        }
+    }
+
+    /// Expire COV subscriptions and build the notifications that are due:
+    /// (subscriber, UnconfirmedCOVNotification APDU)
+    pub fn take_cov_notifications(&self, now: Instant) -> Vec<(Requester, Vec<u8>)> {
+        let Ok(mut cov) = self.cov.lock() else { return Vec::new() };
+        let expired = cov.expire(now);
+        if expired > 0 {
+            info!("{} COV subscription(s) expired", expired);
+        }
+        if cov.len() == 0 {
+            return Vec::new();
+        }
+
+        let due = cov.due(now, |object_id| {
+            let (triggers, _) = self.cov_properties(object_id)?;
+            Some(triggers.iter().flat_map(|&p| self.cov_property_value(object_id, p).unwrap_or_default()).collect())
+        });
+        drop(cov);
+
+        let device_id = ((OBJECT_TYPE_DEVICE as u32) << 22) | self.device_instance;
+        due.into_iter()
+            .filter_map(|notification| {
+                let (triggers, extras) = self.cov_properties(notification.object_id)?;
+                let mut apdu = vec![APDU_UNCONFIRMED_REQUEST, SERVICE_UNCONFIRMED_COV_NOTIFICATION];
+                apdu.extend_from_slice(&encode_context_unsigned(0, notification.process_id));
+                apdu.extend_from_slice(&encode_context_value(1, &device_id.to_be_bytes())); // Initiating Device
+                apdu.extend_from_slice(&encode_context_value(2, &notification.object_id.to_be_bytes())); // Monitored Object
+                apdu.extend_from_slice(&encode_context_unsigned(3, notification.time_remaining));
+
+                // List of Values: BACnetPropertyValue { [0] property, [2] value }
+                apdu.push(0x4E);
+                for &property_id in triggers.iter().chain(extras) {
+                    let Some(value) = self.cov_property_value(notification.object_id, property_id) else { continue };
+                    apdu.extend_from_slice(&encode_context_unsigned(0, property_id));
+                    apdu.push(0x2E);
+                    apdu.extend_from_slice(&value);
+                    apdu.push(0x2F);
+                }
+                apdu.push(0x4F);
+                Some((notification.subscriber, apdu))
+            })
+            .collect()
+    }
+
+    /// Number of active COV subscriptions
+    pub fn cov_subscription_count(&self) -> usize {
+        self.cov.lock().map(|cov| cov.len()).unwrap_or(0)
+    }
+
     /// Handle ReadProperty request
-    fn handle_read_property(&self, invoke_id: u8, data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    fn handle_read_property(&self, _from: &Requester, invoke_id: u8, data: &[u8]) -> Option<(Vec<u8>, bool)> {
         // Parse ReadProperty request
         // Context tag 0: Object Identifier (4 bytes)
         // Context tag 1: Property Identifier (1-2 bytes)
@@ -748,7 +1774,7 @@ impl LocalDevice {
         // Now check object type and route to appropriate handler
         if object_type == OBJECT_TYPE_NETWORK_PORT {
             // Find the requested Network Port
-            if let Some(port) = self.network_ports.iter().find(|p| p.instance == object_instance) {
+            if let Some(port) = self.network_port(object_instance) {
                 return self.build_read_property_response_for_network_port(invoke_id, object_id, property_id, port);
             } else {
                 debug!("ReadProperty for unknown Network Port instance: {}", object_instance);
@@ -756,6 +1782,23 @@ impl LocalDevice {
             }
         }
 
+        if object_id == diagnostics_object_id() {
+            return match diagnostics_property(property_id) {
+                Some(value) => self.build_read_property_ack(invoke_id, object_id, property_id, value),
+                None => self.build_error_response(invoke_id, SERVICE_READ_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_UNKNOWN_PROPERTY),
+            };
+        }
+
+        if object_type == OBJECT_TYPE_ANALOG_VALUE {
+            return match self.analog_value_property(object_instance, property_id) {
+                Some(value) => self.build_read_property_ack(invoke_id, object_id, property_id, value),
+                None if self.analog_reading(object_instance).is_some() => {
+                    self.build_error_response(invoke_id, SERVICE_READ_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_UNKNOWN_PROPERTY)
+                }
+                None => self.build_error_response(invoke_id, SERVICE_READ_PROPERTY, ERROR_CLASS_OBJECT, ERROR_CODE_UNKNOWN_OBJECT),
+            };
+        }
+
         // Check if it's our device object
         if object_type != OBJECT_TYPE_DEVICE || object_instance != self.device_instance {
             debug!(
@@ -776,14 +1819,17 @@ impl LocalDevice {
         info!("ReadProperty for Network-Port:{} property {} (0x{:02X})", port.instance, property_id, property_id);
 
         // Get the property value from the Network Port
-        let value_encoded = match port.get_property(property_id) {
-            Some(val) => val,
+        match port.get_property(property_id) {
+            Some(value) => self.build_read_property_ack(invoke_id, object_id, property_id, value),
             None => {
                 debug!("Unknown property {} (0x{:02X}) requested for Network Port", property_id, property_id);
-                return self.build_error_response(invoke_id, SERVICE_READ_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_UNKNOWN_PROPERTY);
+                self.build_error_response(invoke_id, SERVICE_READ_PROPERTY, ERROR_CLASS_PROPERTY, ERROR_CODE_UNKNOWN_PROPERTY)
             }
-        };
+        }
+    }
 
+    /// Build a ReadProperty-ACK carrying an already encoded value
+    fn build_read_property_ack(&self, invoke_id: u8, object_id: u32, property_id: u32, value_encoded: Vec<u8>) -> Option<(Vec<u8>, bool)> {
         let mut apdu = Vec::with_capacity(64);
 
         // PDU type - Complex ACK
@@ -883,35 +1929,16 @@ impl LocalDevice {
                 vec![0x21, 14]
             }
             PROP_PROTOCOL_SERVICES_SUPPORTED => {
-                // Bit string - services we support
-                // We support: I-Am (bit 26), Who-Is (bit 33), ReadProperty (bit 12)
-                // Bit string format: tag, [extended length], unused bits, data bytes
-                // BACnet tag encoding: 0x85 = tag 8 (BitString), extended length (next byte)
-                // 6 bytes of bit data + 1 unused bits byte = 7 bytes total
-                let mut bits = [0u8; 6];
-                // Set bit 12 (ReadProperty) - byte 1, bit 4
-                bits[1] |= 0x08;
-                // Set bit 26 (I-Am) - byte 3, bit 2
-                bits[3] |= 0x20;
-                // Set bit 33 (Who-Is) - byte 4, bit 1
-                bits[4] |= 0x40;
-
-                let mut v = vec![0x85, 0x07, 0x00]; // Tag 8 (BitString), length=7 (extended), 0 unused bits
-                v.extend_from_slice(&bits);
-                v
+                // Bit string built from the registered service handlers
+                self.encode_services_supported()
             }
             PROP_PROTOCOL_OBJECT_TYPES_SUPPORTED => {
-                // Bit string - object types we support
-                // We support: Device (bit 8)
-                // BACnet tag encoding: 0x85 = tag 8 (BitString), extended length (next byte)
-                // 7 bytes of bit data + 1 unused bits byte = 8 bytes total
-                let mut bits = [0u8; 7];
-                // Set bit 8 (Device) - byte 1, bit 0
-                bits[1] |= 0x80;
-
-                let mut v = vec![0x85, 0x08, 0x00]; // Tag 8 (BitString), length=8 (extended), 0 unused bits
-                v.extend_from_slice(&bits);
-                v
+                // Bit string built from the objects in the database
+                self.encode_object_types_supported()
+            }
+            PROP_PROPERTY_LIST => {
+                // Array of Enumerated - every readable property except the required four
+                self.encode_device_property_list(object_id)
             }
             PROP_MAX_APDU_LENGTH_ACCEPTED => {
                 self.encode_unsigned(MAX_APDU_LENGTH)
@@ -941,19 +1968,32 @@ impl LocalDevice {
                 v.extend_from_slice(&object_id.to_be_bytes());
 
                 // Add all Network Port objects
-                for port in &self.network_ports {
+                for port in self.present_network_ports() {
                     let port_obj_id = ((OBJECT_TYPE_NETWORK_PORT as u32) << 22) | port.instance;
                     v.push(0xC4);
                     v.extend_from_slice(&port_obj_id.to_be_bytes());
                 }
 
+                // And the Gateway Diagnostics object
+                v.push(0xC4);
+                v.extend_from_slice(&diagnostics_object_id().to_be_bytes());
+
+                // WiFi diagnostics Analog Values created so far
+                for av_obj_id in self.present_analog_values() {
+                    v.push(0xC4);
+                    v.extend_from_slice(&av_obj_id.to_be_bytes());
+                }
+
                 v
             }
             PROP_DESCRIPTION => {
-                self.encode_character_string("BACnet MS/TP to IP Gateway")
+                self.encode_character_string(&self.site_info().description)
             }
             PROP_LOCATION => {
-                self.encode_character_string("")
+                self.encode_character_string(&self.site_info().location)
+            }
+            PROP_SERIAL_NUMBER => {
+                self.encode_character_string(&self.site_info().serial_number)
             }
             PROP_MAX_INFO_FRAMES => {
                 vec![0x21, self.max_info_frames]
@@ -962,9 +2002,19 @@ impl LocalDevice {
                 vec![0x21, self.max_master]
             }
             PROP_DEVICE_ADDRESS_BINDING => {
-                // Empty list - we don't maintain address bindings
-                // Return empty sequence (no data between opening/closing tags is fine)
-                vec![]
+                self.encode_address_bindings()
+            }
+            PROP_LOCAL_DATE => {
+                encode_local_date(clock::local_now())
+            }
+            PROP_LOCAL_TIME => {
+                encode_local_time(clock::local_now())
+            }
+            PROP_UTC_OFFSET => {
+                encode_signed(i32::from(clock::timezone().utc_offset_minutes()))
+            }
+            PROP_DAYLIGHT_SAVINGS_STATUS => {
+                vec![0x10 | u8::from(clock::local_now().is_some_and(|t| t.dst))]
             }
             _ => {
                 debug!("Unknown property {} (0x{:02X}) requested", property_id, property_id);
@@ -1001,7 +2051,7 @@ impl LocalDevice {
     }
 
     /// Handle ReadPropertyMultiple request
-    fn handle_read_property_multiple(&self, invoke_id: u8, data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    fn handle_read_property_multiple(&self, _from: &Requester, invoke_id: u8, data: &[u8]) -> Option<(Vec<u8>, bool)> {
         debug!("ReadPropertyMultiple request, data len: {}", data.len());
 
         let mut apdu = Vec::with_capacity(256);
@@ -1037,14 +2087,20 @@ impl LocalDevice {
             // Check if it's a Network Port object
             let is_network_port = object_type == OBJECT_TYPE_NETWORK_PORT;
             let network_port = if is_network_port {
-                self.network_ports.iter().find(|p| p.instance == object_instance)
+                self.network_port(object_instance)
             } else {
                 None
             };
 
-            // Check if it's our device object or a valid Network Port
+            let is_diagnostics = object_id == diagnostics_object_id();
+            let is_analog_value =
+                object_type == OBJECT_TYPE_ANALOG_VALUE && self.analog_reading(object_instance).is_some();
+
+            // Check if it's our device object, a valid Network Port, the diagnostics object or an Analog Value
             let is_valid_object = (object_type == OBJECT_TYPE_DEVICE && object_instance == self.device_instance)
-                || (is_network_port && network_port.is_some());
+                || (is_network_port && network_port.is_some())
+                || is_diagnostics
+                || is_analog_value;
 
             if !is_valid_object {
                 debug!("RPM: Unknown object, skipping");
@@ -1124,9 +2180,13 @@ impl LocalDevice {
                     apdu.extend_from_slice(&(property_id as u16).to_be_bytes());
                 }
 
-                // Get property value - from Network Port or Device
+                // Get property value - from Network Port, diagnostics, Analog Value or Device
                 let value_opt = if let Some(port) = network_port {
                     port.get_property(property_id)
+                } else if is_diagnostics {
+                    diagnostics_property(property_id)
+                } else if is_analog_value {
+                    self.analog_value_property(object_instance, property_id)
                 } else {
                     self.get_property_value(object_id, property_id)
                 };
@@ -1179,23 +2239,9 @@ impl LocalDevice {
             PROP_APPLICATION_SOFTWARE_VERSION => Some(self.encode_character_string(&self.application_version)),
             PROP_PROTOCOL_VERSION => Some(vec![0x21, 1]),
             PROP_PROTOCOL_REVISION => Some(vec![0x21, 14]),
-            PROP_PROTOCOL_SERVICES_SUPPORTED => {
-                let mut bits = [0u8; 6];
-                bits[1] |= 0x08; // ReadProperty (bit 12)
-                bits[1] |= 0x02; // ReadPropertyMultiple (bit 14)
-                bits[3] |= 0x20; // I-Am (bit 26)
-                bits[4] |= 0x40; // Who-Is (bit 33)
-                let mut v = vec![0x85, 0x07, 0x00]; // Tag 8 (BitString), length=7 (extended), 0 unused bits
-                v.extend_from_slice(&bits);
-                Some(v)
-            }
-            PROP_PROTOCOL_OBJECT_TYPES_SUPPORTED => {
-                let mut bits = [0u8; 7];
-                bits[1] |= 0x80; // Device (bit 8)
-                let mut v = vec![0x85, 0x08, 0x00]; // Tag 8 (BitString), length=8 (extended), 0 unused bits
-                v.extend_from_slice(&bits);
-                Some(v)
-            }
+            PROP_PROTOCOL_SERVICES_SUPPORTED => Some(self.encode_services_supported()),
+            PROP_PROTOCOL_OBJECT_TYPES_SUPPORTED => Some(self.encode_object_types_supported()),
+            PROP_PROPERTY_LIST => Some(self.encode_device_property_list(object_id)),
             PROP_MAX_APDU_LENGTH_ACCEPTED => Some(self.encode_unsigned(MAX_APDU_LENGTH)),
             PROP_SEGMENTATION_SUPPORTED => Some(vec![0x91, SEGMENTATION_NOT_SUPPORTED as u8]),
             PROP_APDU_TIMEOUT => Some(self.encode_unsigned(3000)),
@@ -1209,19 +2255,34 @@ impl LocalDevice {
                 v.extend_from_slice(&object_id.to_be_bytes());
 
                 // Add all Network Port objects
-                for port in &self.network_ports {
+                for port in self.present_network_ports() {
                     let port_obj_id = ((OBJECT_TYPE_NETWORK_PORT as u32) << 22) | port.instance;
                     v.push(0xC4);
                     v.extend_from_slice(&port_obj_id.to_be_bytes());
                 }
 
+                // And the Gateway Diagnostics object
+                v.push(0xC4);
+                v.extend_from_slice(&diagnostics_object_id().to_be_bytes());
+
+                // WiFi diagnostics Analog Values created so far
+                for av_obj_id in self.present_analog_values() {
+                    v.push(0xC4);
+                    v.extend_from_slice(&av_obj_id.to_be_bytes());
+                }
+
                 Some(v)
             }
-            PROP_DESCRIPTION => Some(self.encode_character_string("BACnet MS/TP to IP Gateway")),
-            PROP_LOCATION => Some(self.encode_character_string("")),
+            PROP_DESCRIPTION => Some(self.encode_character_string(&self.site_info().description)),
+            PROP_LOCATION => Some(self.encode_character_string(&self.site_info().location)),
+            PROP_SERIAL_NUMBER => Some(self.encode_character_string(&self.site_info().serial_number)),
             PROP_MAX_INFO_FRAMES => Some(vec![0x21, self.max_info_frames]),
             PROP_MAX_MASTER => Some(vec![0x21, self.max_master]),
-            PROP_DEVICE_ADDRESS_BINDING => Some(vec![]), // Empty list
+            PROP_DEVICE_ADDRESS_BINDING => Some(self.encode_address_bindings()),
+            PROP_LOCAL_DATE => Some(encode_local_date(clock::local_now())),
+            PROP_LOCAL_TIME => Some(encode_local_time(clock::local_now())),
+            PROP_UTC_OFFSET => Some(encode_signed(i32::from(clock::timezone().utc_offset_minutes()))),
+            PROP_DAYLIGHT_SAVINGS_STATUS => Some(vec![0x10 | u8::from(clock::local_now().is_some_and(|t| t.dst))]),
             _ => None,
         }
     }
@@ -1270,6 +2331,46 @@ impl LocalDevice {
         }
     }
 
+    /// Build an UnconfirmedEventNotification APDU (Clause 13.9) reporting an
+    /// off-normal alarm on the Device object, described by `message`
+    pub fn build_alarm_notification(&self, message: &str) -> Vec<u8> {
+        let object_id = ((OBJECT_TYPE_DEVICE as u32) << 22) | self.device_instance;
+        let sequence = self.event_sequence.fetch_add(1, Ordering::SeqCst).to_be_bytes();
+        let seq_start = sequence.iter().position(|b| *b != 0).unwrap_or(3);
+
+        let mut text = Vec::with_capacity(message.len() + 1);
+        text.push(0); // UTF-8
+        text.extend_from_slice(message.as_bytes());
+
+        let mut apdu = vec![APDU_UNCONFIRMED_REQUEST, SERVICE_UNCONFIRMED_EVENT_NOTIFICATION];
+        apdu.extend_from_slice(&encode_context_value(0, &[0])); // Process Identifier
+        apdu.extend_from_slice(&encode_context_value(1, &object_id.to_be_bytes())); // Initiating Device
+        apdu.extend_from_slice(&encode_context_value(2, &object_id.to_be_bytes())); // Event Object
+        apdu.push(0x3E); // Time Stamp: sequence number choice
+        apdu.extend_from_slice(&encode_context_value(1, &sequence[seq_start..]));
+        apdu.push(0x3F);
+        apdu.extend_from_slice(&encode_context_value(4, &[0])); // Notification Class
+        apdu.extend_from_slice(&encode_context_value(5, &[EVENT_PRIORITY_URGENT]));
+        apdu.extend_from_slice(&encode_context_value(6, &[EVENT_TYPE_CHANGE_OF_STATE]));
+        apdu.extend_from_slice(&encode_context_value(7, &text)); // Message Text
+        apdu.extend_from_slice(&encode_context_value(8, &[NOTIFY_TYPE_ALARM]));
+        apdu.extend_from_slice(&encode_context_value(9, &[0])); // Ack Required = FALSE
+        apdu.extend_from_slice(&encode_context_value(10, &[EVENT_STATE_NORMAL])); // From State
+        apdu.extend_from_slice(&encode_context_value(11, &[EVENT_STATE_OFFNORMAL])); // To State
+
+        // Event Values: change-of-state { new-state = boolean TRUE, status-flags = IN_ALARM }
+        apdu.push(0xCE);
+        apdu.push(0x1E);
+        apdu.push(0x0E);
+        apdu.extend_from_slice(&encode_context_value(0, &[1]));
+        apdu.push(0x0F);
+        apdu.extend_from_slice(&encode_context_value(1, &[0x04, 0x80]));
+        apdu.push(0x1F);
+        apdu.push(0xCF);
+
+        apdu
+    }
+
     /// Build a Who-Is request APDU (broadcast to all devices)
     pub fn build_who_is() -> Vec<u8> {
         vec![
@@ -1301,23 +2402,73 @@ fn encode_context_unsigned(tag: u8, value: u32) -> Vec<u8> {
     let mut result = Vec::new();
 
     if value <= 0xFF {
-        result.push((tag << 4) | 1);  // Context tag with length 1
+        result.push((tag << 4) | 0x08 | 1);  // Context tag with length 1
         result.push(value as u8);
     } else if value <= 0xFFFF {
-        result.push((tag << 4) | 2);  // Context tag with length 2
+        result.push((tag << 4) | 0x08 | 2);  // Context tag with length 2
         result.extend_from_slice(&(value as u16).to_be_bytes());
     } else if value <= 0xFFFFFF {
-        result.push((tag << 4) | 3);  // Context tag with length 3
+        result.push((tag << 4) | 0x08 | 3);  // Context tag with length 3
         let bytes = value.to_be_bytes();
         result.extend_from_slice(&bytes[1..4]);
     } else {
-        result.push((tag << 4) | 4);  // Context tag with length 4
+        result.push((tag << 4) | 0x08 | 4);  // Context tag with length 4
         result.extend_from_slice(&value.to_be_bytes());
     }
 
     result
 }
 
+/// Encode a context-tagged primitive value (tag numbers 0-14)
+fn encode_context_value(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(value.len() + 4);
+
+    if value.len() < 5 {
+        result.push((tag << 4) | 0x08 | value.len() as u8);
+    } else if value.len() < 254 {
+        result.push((tag << 4) | 0x0D);
+        result.push(value.len() as u8);
+    } else {
+        result.push((tag << 4) | 0x0D);
+        result.push(254);
+        result.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    }
+    result.extend_from_slice(value);
+
+    result
+}
+
+/// Decode an application-tagged Character String (UTF-8 only)
+/// Returns the string and bytes consumed, or the BACnet error code to report
+fn decode_character_string(data: &[u8]) -> Result<(String, usize), u32> {
+    if data.is_empty() || (data[0] & 0xF8) != 0x70 {
+        return Err(ERROR_CODE_INVALID_DATA_TYPE);
+    }
+
+    let (length, mut pos) = match data[0] & 0x07 {
+        5 => match data.get(1) {
+            Some(&254) if data.len() >= 4 => (u16::from_be_bytes([data[2], data[3]]) as usize, 4),
+            Some(&len) if len < 254 => (len as usize, 2),
+            _ => return Err(ERROR_CODE_INVALID_DATA_TYPE),
+        },
+        len => (len as usize, 1),
+    };
+
+    if length == 0 || pos + length > data.len() {
+        return Err(ERROR_CODE_INVALID_DATA_TYPE);
+    }
+
+    // Character set 0 = UTF-8 (ANSI X3.4)
+    if data[pos] != 0 {
+        return Err(ERROR_CODE_CHARACTER_SET_NOT_SUPPORTED);
+    }
+    pos += 1;
+
+    let end = pos + length - 1;
+    let s = std::str::from_utf8(&data[pos..end]).map_err(|_| ERROR_CODE_CHARACTER_SET_NOT_SUPPORTED)?;
+    Ok((s.to_string(), end))
+}
+
 /// Discovered device info from I-Am response
 #[derive(Debug, Clone, Default)]
 pub struct DiscoveredDevice {
@@ -1326,9 +2477,24 @@ pub struct DiscoveredDevice {
     pub max_apdu_length: u32,
     pub segmentation: u8,
     pub vendor_id: u16,
+    /// When the first I-Am from this device was heard
+    pub first_seen: Option<std::time::Instant>,
+    /// When the latest I-Am from this device was heard
+    pub last_seen: Option<std::time::Instant>,
+    /// Scan session that first heard this device (0 = outside any scan)
+    pub first_scan_id: u32,
+    /// Scan session that last heard this device
+    pub last_scan_id: u32,
+    /// Number of I-Am responses received
+    pub i_am_count: u32,
 }
 
 impl DiscoveredDevice {
+    /// Human-readable vendor name from the embedded vendor ID table
+    pub fn vendor_name(&self) -> String {
+        crate::vendors::vendor_display_name(self.vendor_id)
+    }
+
     /// Parse an I-Am APDU and extract device info
     pub fn from_i_am(apdu: &[u8], mac_address: u8) -> Option<Self> {
         // Minimum I-Am: PDU type (1) + Service (1) + Object ID (5) + Max APDU (3) + Segmentation (2) + Vendor (3) = 15 bytes
@@ -1410,6 +2576,172 @@ impl DiscoveredDevice {
             max_apdu_length,
             segmentation,
             vendor_id,
+            ..Default::default()
         })
     }
 }
+
+/// Record an I-Am heard at `now` during scan `scan_id` in a discovery list
+///
+/// A different instance answering from a known MAC replaces the old entry
+/// (device swapped), and a known instance answering from a new MAC is
+/// moved (device readdressed). New devices are only added while `may_grow`;
+/// a full list drops the device heard from longest ago to make room.
+/// Returns whether the device was added.
+pub fn record_discovered(
+    devices: &mut Vec<DiscoveredDevice>,
+    device: DiscoveredDevice,
+    scan_id: u32,
+    may_grow: bool,
+    now: Instant,
+) -> bool {
+    devices.retain(|d| d.device_instance == device.device_instance || d.mac_address != device.mac_address);
+
+    if let Some(existing) = devices.iter_mut().find(|d| d.device_instance == device.device_instance) {
+        existing.mac_address = device.mac_address;
+        existing.max_apdu_length = device.max_apdu_length;
+        existing.segmentation = device.segmentation;
+        existing.vendor_id = device.vendor_id;
+        existing.last_seen = Some(now);
+        existing.last_scan_id = scan_id;
+        existing.i_am_count += 1;
+        return false;
+    }
+
+    if !may_grow {
+        return false;
+    }
+
+    if devices.len() >= MAX_DISCOVERED_DEVICES {
+        if let Some(oldest) = devices.iter().enumerate().min_by_key(|(_, d)| d.last_seen).map(|(i, _)| i) {
+            devices.remove(oldest);
+        }
+    }
+
+    devices.push(DiscoveredDevice {
+        first_seen: Some(now),
+        last_seen: Some(now),
+        first_scan_id: scan_id,
+        last_scan_id: scan_id,
+        i_am_count: 1,
+        ..device
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_encode_bit_string() {
+        assert_eq!(encode_bit_string(&[], 4), [0x82, 0x04, 0x00]);
+        assert_eq!(encode_bit_string(&[1, 3], 4), [0x82, 0x04, 0x50]);
+        assert_eq!(encode_bit_string(&[0, 7], 8), [0x82, 0x00, 0x81]);
+        // Grows past min_bits to fit the highest bit
+        assert_eq!(encode_bit_string(&[9], 4), [0x83, 0x06, 0x00, 0x40]);
+        // Five or more content bytes take the extended length form
+        assert_eq!(encode_bit_string(&[31], 32), [0x85, 0x05, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_encode_property_list() {
+        let candidates = [
+            PROP_OBJECT_IDENTIFIER,
+            PROP_OBJECT_NAME,
+            PROP_PRESENT_VALUE,
+            PROP_OBJECT_TYPE,
+            PROP_PROPERTY_LIST,
+            PROP_UNITS,
+            PROP_SERIAL_NUMBER,
+        ];
+        assert_eq!(encode_property_list(&candidates, |_| true), [0x91, 0x55, 0x91, 0x75, 0x92, 0x01, 0x74]);
+        assert_eq!(encode_property_list(&candidates, |p| p != PROP_UNITS), [0x91, 0x55, 0x92, 0x01, 0x74]);
+
+        let device = LocalDevice::new(1234);
+        let object_id = ((OBJECT_TYPE_DEVICE as u32) << 22) | 1234;
+        assert_eq!(
+            device.encode_device_property_list(object_id),
+            [
+                0x91, 0x70, 0x91, 0x79, 0x91, 0x78, 0x91, 0x46, 0x91, 0x2C, 0x91, 0x0C, 0x91, 0x62, 0x91, 0x8B,
+                0x91, 0x61, 0x91, 0x60, 0x91, 0x3E, 0x91, 0x6B, 0x91, 0x0B, 0x91, 0x49, 0x91, 0x9B, 0x91, 0x4C,
+                0x91, 0x1C, 0x91, 0x3A, 0x92, 0x01, 0x74, 0x91, 0x3F, 0x91, 0x40, 0x91, 0x1E, 0x91, 0x38, 0x91,
+                0x39, 0x91, 0x77, 0x91, 0x18,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encode_services_supported() {
+        // SubscribeCOV (5), ReadProperty (12), ReadPropertyMultiple (14), WriteProperty (15),
+        // I-Am (26), UnconfirmedCOVNotification (28), UnconfirmedEventNotification (29), Who-Is (34)
+        let mut device = LocalDevice::new(1234);
+        assert_eq!(device.encode_services_supported(), [0x85, 0x07, 0x00, 0x04, 0x0B, 0x00, 0x2C, 0x20, 0x00]);
+
+        // Who-Is switched off clears its bit; I-Am is still initiated
+        device.set_services(ServiceWhitelist::parse("read-property,write-property").unwrap());
+        assert_eq!(device.encode_services_supported(), [0x85, 0x07, 0x00, 0x00, 0x09, 0x00, 0x2C, 0x00, 0x00]);
+    }
+
+    fn device(device_instance: u32, mac_address: u8) -> DiscoveredDevice {
+        DiscoveredDevice { device_instance, mac_address, vendor_id: 5, ..Default::default() }
+    }
+
+    #[test]
+    fn test_record_discovered() {
+        let start = Instant::now();
+        let mut devices = Vec::new();
+
+        // Learned with first/last seen and scan metadata
+        assert!(record_discovered(&mut devices, device(1001, 5), 1, true, start));
+        assert!(record_discovered(&mut devices, device(1002, 6), 1, true, start));
+        assert_eq!(devices.len(), 2);
+        assert_eq!((devices[0].first_seen, devices[0].last_seen), (Some(start), Some(start)));
+        assert_eq!((devices[0].first_scan_id, devices[0].last_scan_id, devices[0].i_am_count), (1, 1, 1));
+
+        // Heard again in a later scan, with a new vendor
+        let later = start + Duration::from_secs(10);
+        assert!(!record_discovered(&mut devices, DiscoveredDevice { vendor_id: 7, ..device(1001, 5) }, 2, true, later));
+        let d = &devices[0];
+        assert_eq!((d.first_seen, d.last_seen), (Some(start), Some(later)));
+        assert_eq!((d.first_scan_id, d.last_scan_id, d.i_am_count, d.vendor_id), (1, 2, 2, 7));
+
+        // Readdressed: the entry moves with the device
+        assert!(!record_discovered(&mut devices, device(1001, 9), 2, true, later));
+        assert_eq!(devices.len(), 2);
+        assert_eq!((devices[0].mac_address, devices[0].i_am_count), (9, 3));
+
+        // Swapped: another instance at a known MAC replaces the old device
+        assert!(record_discovered(&mut devices, device(2002, 6), 2, true, later));
+        let instances: Vec<_> = devices.iter().map(|d| (d.device_instance, d.mac_address)).collect();
+        assert_eq!(instances, vec![(1001, 9), (2002, 6)]);
+
+        // Not growing: known devices still update, new ones are left out
+        assert!(!record_discovered(&mut devices, device(3003, 20), 3, false, later));
+        assert!(!record_discovered(&mut devices, device(2002, 6), 3, false, later));
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[1].last_scan_id, 3);
+    }
+
+    #[test]
+    fn test_record_discovered_capacity() {
+        let start = Instant::now();
+        let mut devices = Vec::new();
+        for i in 0..MAX_DISCOVERED_DEVICES as u32 {
+            let heard = start + Duration::from_millis(u64::from(i));
+            record_discovered(&mut devices, device(i, i as u8), 1, true, heard);
+        }
+        assert_eq!(devices.len(), MAX_DISCOVERED_DEVICES);
+
+        // The device heard from longest ago (instance 0, refreshed here) is kept;
+        // instance 1 is now the oldest and makes room
+        let late = start + Duration::from_secs(60);
+        record_discovered(&mut devices, device(0, 0), 2, true, late);
+        assert!(record_discovered(&mut devices, device(5000, 255), 2, true, late));
+        assert_eq!(devices.len(), MAX_DISCOVERED_DEVICES);
+        assert!(devices.iter().any(|d| d.device_instance == 0));
+        assert!(!devices.iter().any(|d| d.device_instance == 1));
+        assert!(devices.iter().any(|d| d.device_instance == 5000));
+    }
+}
@@ -0,0 +1,170 @@
+//! Log output: ESP-IDF text or JSON lines
+//!
+//! Wraps the ESP-IDF logger so the serial output can be switched to one JSON
+//! object per line (timestamp, uptime, level, module, message and any
+//! `key=value` pairs in the message) for sites that feed it into a log
+//! collector. A remote console session can also follow the log through a tap,
+//! in whichever format the serial output uses. The tap is bounded and lines
+//! that don't fit are dropped, so a slow client never holds up logging.
+
+use esp_idf_svc::log::EspLogger;
+use log::{Log, Metadata, Record};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime};
+
+use crate::clock;
+use crate::web::json_escape;
+
+/// Lines that can wait for the tap's reader before new ones are dropped
+const TAP_DEPTH: usize = 64;
+
+/// Most `key=value` pairs lifted out of one message
+const MAX_FIELDS: usize = 8;
+
+static ESP_LOGGER: EspLogger = EspLogger::new();
+static LOGGER: GatewayLogger = GatewayLogger;
+static JSON_LINES: AtomicBool = AtomicBool::new(false);
+static TAP: Mutex<Option<SyncSender<String>>> = Mutex::new(None);
+static BOOT: OnceLock<Instant> = OnceLock::new();
+
+struct GatewayLogger;
+
+impl Log for GatewayLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        ESP_LOGGER.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let uptime_ms = BOOT.get().map(|boot| boot.elapsed().as_millis() as u64).unwrap_or(0);
+        let json = JSON_LINES.load(Ordering::Relaxed);
+        let line = json.then(|| {
+            format_json(
+                clock::utc_now(),
+                uptime_ms,
+                record.level().as_str(),
+                record.target(),
+                &record.args().to_string(),
+            )
+        });
+        match line {
+            Some(ref line) => println!("{}", line),
+            None => ESP_LOGGER.log(record),
+        }
+
+        let Ok(tap) = TAP.lock() else { return };
+        if let Some(ref sender) = *tap {
+            let line = line.unwrap_or_else(|| {
+                format!("{} ({}) {}: {}", &record.level().as_str()[..1], uptime_ms, record.target(), record.args())
+            });
+            let _ = sender.try_send(line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger. Called once, first thing in startup.
+pub fn init() {
+    BOOT.get_or_init(Instant::now);
+    log::set_logger(&LOGGER).expect("logger installed twice");
+    ESP_LOGGER.initialize();
+}
+
+/// Switch the serial output (and the tap) between ESP-IDF text and JSON lines
+pub fn set_json(enabled: bool) {
+    JSON_LINES.store(enabled, Ordering::Relaxed);
+}
+
+/// Start copying log lines to a new receiver, replacing any earlier tap
+pub fn tap() -> Receiver<String> {
+    let (sender, receiver) = mpsc::sync_channel(TAP_DEPTH);
+    if let Ok(mut tap) = TAP.lock() {
+        *tap = Some(sender);
+    }
+    receiver
+}
+
+/// Stop copying log lines; the tap's receiver sees the channel close
+pub fn untap() {
+    if let Ok(mut tap) = TAP.lock() {
+        *tap = None;
+    }
+}
+
+/// One log record as a JSON object; `ts` is null until SNTP has set the clock
+fn format_json(utc: Option<SystemTime>, uptime_ms: u64, level: &str, module: &str, message: &str) -> String {
+    let ts = match utc {
+        Some(time) => format!("\"{}\"", clock::format_utc(time)),
+        None => "null".to_string(),
+    };
+    let fields = key_fields(message)
+        .iter()
+        .map(|(key, value)| format!("\"{}\":\"{}\"", key, json_escape(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{\"ts\":{},\"uptime_ms\":{},\"level\":\"{}\",\"module\":\"{}\",\"msg\":\"{}\",\"fields\":{{{}}}}}",
+        ts,
+        uptime_ms,
+        level,
+        json_escape(module),
+        json_escape(message),
+        fields
+    )
+}
+
+/// `key=value` words in a message, e.g. `dest=5` in "Reply timeout dest=5, invoke=12".
+/// Keys are identifiers; trailing punctuation is trimmed off values and a
+/// repeated key keeps its first value.
+fn key_fields(message: &str) -> Vec<(&str, &str)> {
+    let mut fields: Vec<(&str, &str)> = Vec::new();
+    for word in message.split_whitespace() {
+        let Some((key, value)) = word.split_once('=') else { continue };
+        let key = key.trim_start_matches(['(', '[']);
+        let value = value.trim_end_matches([',', ';', ')', ']']);
+        let identifier = key.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if identifier && !value.is_empty() && !fields.iter().any(|(k, _)| *k == key) {
+            fields.push((key, value));
+            if fields.len() == MAX_FIELDS {
+                break;
+            }
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_key_fields() {
+        assert_eq!(
+            key_fields("Reply timeout (dest=5, invoke=12) after retries=3"),
+            vec![("dest", "5"), ("invoke", "12"), ("retries", "3")]
+        );
+        assert_eq!(key_fields("a==b =x 1st=2 x= ok=1 ok=2"), vec![("a", "=b"), ("ok", "1")]);
+        assert!(key_fields("no pairs here").is_empty());
+    }
+
+    #[test]
+    fn test_format_json() {
+        let line = format_json(None, 1234, "WARN", "mstp_ip_gateway::gateway", "Dropped \"frame\" net=5");
+        assert_eq!(
+            line,
+            "{\"ts\":null,\"uptime_ms\":1234,\"level\":\"WARN\",\"module\":\"mstp_ip_gateway::gateway\",\
+             \"msg\":\"Dropped \\\"frame\\\" net=5\",\"fields\":{\"net\":\"5\"}}"
+        );
+        let utc = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let line = format_json(Some(utc), 0, "INFO", "m", "line\nbreak");
+        assert!(line.starts_with("{\"ts\":\"2023-11-14T22:13:20.250Z\",\"uptime_ms\":0,"));
+        assert!(line.contains("\"msg\":\"line\\u000abreak\",\"fields\":{}}"));
+    }
+}
@@ -5,12 +5,65 @@
 //! ## Production Features
 //! - NVS-based configuration persistence
 //! - WiFi auto-reconnection
+//! - B/IP socket re-bound automatically after persistent errors or an address change
+//! - DHCP lease changes followed from IP events and re-announced (I-Am, I-Am-Router-To-Network)
 //! - Watchdog timer for automatic recovery
 //! - Panic handler with automatic restart
 //! - Serial console for runtime configuration
+//! - Graceful shutdown on brown-out or power button long-press
+//! - Configuration hotspot alongside the site WiFi connection (APSTA)
+//! - Optional lifetime statistics kept across reboots
+//! - Event log of link, hotspot, token ring and configuration changes
+//! - Per-subnet compatibility profiles for B/IP clients with known quirks
+//! - Remote router table queries from the diagnostics page
+//! - Webhook notifications on device changes, error bursts and scheduled scans
+//! - MS/TP slave-only mode for serving the gateway's own objects outside the token ring
+//! - Immediate Abort to IP clients while the MS/TP send queue is backed up
+//! - Unified error log served by /api/errors and a proprietary Gateway Diagnostics object
+//! - Secure-BVLL messages counted per source and refused with a BVLC-Result NAK (or dropped)
+//! - Optional read-only status JSON on an alternate port for NOC monitoring
+//! - Max_Master recommendation from the observed ring, applied on confirmation
+//! - CPU budget for web page generation so large pages don't delay routing
+//! - Configuration checked as a whole before it is applied or saved, with a dry-run report
+//! - Per-peer B/IP counters (traffic, malformed frames, unanswered requests) on /api/peers
+//! - WritePropertyMultiple executed as WriteProperty calls for MS/TP devices without WPM
+//! - Blackbox of the seconds before a watchdog reset or panic, shown as a crash report
+//! - Whitelist of the services the gateway's Device object answers
+//! - Hotspot client list with DHCP leases on the AP screen and /api/ap/clients, with kick
+//! - Low-memory mode that sheds frame debugging, discovery growth and top talkers as the heap runs low
+//! - ReadProperty, WriteProperty and Who-Is from the serial console for scripted bench tests
+//! - Per-device response statistics with daily rollups kept in NVS, reported on /api/sla
+//! - Configurable hop count and network priority for gateway-originated NPDUs
+//! - Optional lenient NPDU parsing that repairs known legacy header quirks, counted per quirk
+//! - B/IP broadcasts copied to a list of supervisory stations as Forwarded-NPDU, or sent only to them
+//! - Portal pages rendered from a published snapshot of the web state, not under its lock
+//! - Debug builds: MS/TP driver events marked on spare GPIOs for a logic analyzer (/api/debug/gpio)
+//! - SubscribeCOV on the Network Ports and Gateway Diagnostics, with unconfirmed COV notifications
+//! - One-click pairing with a peer BACman: reciprocal BDT entries and static routes, checked by a test broadcast
+//! - Per-service request counters and a histogram of device Error/Reject/Abort reasons on the diagnostics page
+//! - One-time configuration changes scheduled for a quiet hour, rolled back if the MS/TP ring does not stabilize
+//! - New network numbers and station addresses checked against the live network before they are committed
+//! - IP network number learned from Network-Number-Is, with pass-through routing until it is known
+//! - MS/TP line utilization, bytes per token cycle and a headroom projection for added devices on /capacity
+//! - NVS writes on a background task, coalesced, with their outcome in the event log
+//! - Device Object_Name read through the gateway and cached, shown beside instance numbers in the portal
+//! - Hardware write-protect jumper that locks the configuration against portal, console and BACnet writes
+//! - Received B/IP datagrams queued per source and routed round-robin, with drops counted per peer
+//! - Learned MS/TP/IP address translations on /bindings and /api/bindings, with pin, delete and flush
+//! - One-click support bundle: configuration, statistics, logs, blackbox capture and crash report in a tar archive
+//! - Local_Date, Local_Time, UTC_Offset and Daylight_Savings_Status on the Device object from SNTP and the TZ rule
+//! - Protocol analysis mode flagging nonconformant APDUs per source, with example frames
+//! - Primary/standby redundancy pairs: the standby listens, takes over when the primary is gone, hands back when it returns
+//! - WiFi RSSI, channel, reconnect count and hotspot clients as Analog Value objects on the local device
+//! - Router announcement scope (IP, MS/TP, both or none) and further networks to announce behind static routes
+//! - Token, Poll-For-Master and Reply-Postponed sent ahead of queued data, with a deadline near-miss count
+//! - Serial console over telnet for portal admin accounts, with logins and changes in the event log
+//! - Setup wizard suggests MS/TP and IP network numbers no router on the site announces
+//! - Optional JSON line log output, which remote console sessions can follow
 
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
+    netif::IpEvent,
     hal::{
         gpio::PinDriver,
         peripheral::Peripheral,
@@ -23,59 +76,159 @@ use esp_idf_svc::{
     nvs::EspDefaultNvsPartition,
     wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi, AccessPointConfiguration},
 };
-use log::{error, info, trace, warn};
-use std::net::UdpSocket;
-use std::sync::atomic::{AtomicBool, Ordering};
+use log::{debug, error, info, trace, warn};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+mod analyzer;
+mod ap_clients;
+mod auth;
+mod autoaddr;
+mod backup;
+mod blackbox;
+mod bundle;
+mod capacity;
+mod client;
+mod clock;
+mod compat;
 mod config;
+mod config_check;
+mod console;
+mod cov;
+#[cfg(test)]
+mod crc_tests;
+mod datalink;
 mod display;
+mod errors;
+mod events;
+mod failover;
+mod fair_queue;
+#[cfg(feature = "fault-injection")]
+mod fault_injection;
 mod gateway;
+mod governor;
+#[cfg(feature = "gpio-trace")]
+mod gpio_trace;
+mod health;
+mod heartbeat;
+mod inventory;
+mod lifetime;
 mod local_device;
+mod logging;
+mod maxmaster;
 // Modbus modules - disabled until integration is complete
 // mod modbus_driver;
 // mod modbus_tcp;
 mod mstp_driver;
+mod names;
+mod netnum;
+mod netutil;
+mod notify;
+mod npdu;
+mod nvs_writer;
+mod pairing;
+mod peers;
+mod public_status;
+mod remote_console;
+mod router_query;
+mod rpm_proxy;
+mod scan;
+mod schedule;
+mod service_stats;
+mod shutdown;
+mod sla;
+mod staging;
+mod talkers;
 mod transaction;
+mod tz;
+mod vendors;
 mod web;
-
-use config::GatewayConfig;
+mod web_budget;
+mod web_snapshot;
+mod wpm_proxy;
+mod write_protect;
+
+use blackbox::{CrashReport, FrameSource, FrameSummary};
+use capacity::{CapacityMeter, LineCounters};
+use config::{
+    CrashReportPersistence, EventLogPersistence, GatewayConfig, LifetimeStatsPersistence, RouteNextHop,
+    ScheduledChangePersistence,
+};
+use cov::Requester;
+use datalink::{BipLink, BipSocket, DataLink, LinkAddress, QueuedLink, SharedLink, TxQueueStats};
 // Rs485Protocol will be used when Modbus integration is complete
 // use config::Rs485Protocol;
-use display::{Display, DisplayScreen, GatewayStatus};
-use gateway::BacnetGateway;
-use local_device::LocalDevice;
+use display::{Display, DisplayScreen, DisplayState, GatewayStatus};
+use errors::ErrorSource;
+use events::{EventCategory, Severity};
+use failover::{Failover, FailoverRole, Heartbeat, Transition, HEARTBEAT_INTERVAL, HEARTBEAT_PORT};
+use fair_queue::FairQueue;
+use gateway::{hex_dump, BacnetGateway};
+use governor::{Feature, Governor};
+use lifetime::{LifetimeCounters, LifetimeStats, RebootReason};
+use local_device::{
+    AddressBinding, DeviceSiteInfo, LocalDevice, PortStatistics, WifiDiagnostics, AP_PORT_INSTANCE, IP_PORT_INSTANCE,
+    MSTP_PORT_INSTANCE,
+};
 use mstp_driver::MstpDriver;
-use web::{WebState, start_web_server};
+use npdu::{NetworkAddress, NetworkPriority, Npdu, GLOBAL_BROADCAST_NETWORK};
+use nvs_writer::{NvsWrite, NvsWriter};
+use pairing::{PairingStage, PAIR_MASK};
+use scan::{ScanPlan, ScanProfile, SCAN_REPLY_WINDOW};
+use schedule::{RingVerdict, RingWatch, ScheduleStage, ScheduledChange};
+use shutdown::ShutdownReason;
+use staging::{StagedValidation, StagingStage, STAGING_LISTEN};
+use talkers::TalkerRanking;
+use web::{publish_snapshot, WebState, start_web_server, TOP_TALKERS_SHOWN};
 
 /// Global flag for WiFi connection status (used by reconnection logic)
 static WIFI_CONNECTED: AtomicBool = AtomicBool::new(false);
 
+/// Set by the IP event handler when DHCP assigns the station an address
+static STATION_IP_ASSIGNED: AtomicBool = AtomicBool::new(false);
+
 /// Global flag for AP mode status
 static AP_MODE_ACTIVE: AtomicBool = AtomicBool::new(false);
 
+/// Hot-path counters logged with the periodic stats instead of per packet
+static BIP_RX_DATAGRAMS: AtomicU32 = AtomicU32::new(0);
+static LOCAL_DEVICE_REPLIES: AtomicU32 = AtomicU32::new(0);
+
 /// WiFi reconnection interval in seconds
 const WIFI_RECONNECT_INTERVAL_SECS: u64 = 10;
 
 /// Watchdog timeout in seconds
 const WATCHDOG_TIMEOUT_SECS: u64 = 30;
 
-/// Router announcement interval in loop iterations (30 seconds = 3000 iterations at 10ms)
-const ROUTER_ANNOUNCE_INTERVAL: u64 = 3000;
-
 /// Default AP mode IP address
 const AP_IP_ADDRESS: &str = "192.168.4.1";
 
+/// B/IP frames that can wait for the IP transmit task before new ones are dropped
+const IP_TX_QUEUE_LEN: usize = 32;
+
+/// Stack size for the IP transmit task
+const IP_TX_STACK_SIZE: usize = 6144;
+
+/// Stack size for the NVS writer task
+const NVS_WRITER_STACK_SIZE: usize = 6144;
+
 fn main() -> anyhow::Result<()> {
     // Initialize ESP-IDF
     esp_idf_svc::sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
+    logging::init();
+
+    // Read back the previous boot's blackbox before anything records into it
+    let previous_blackbox = blackbox::init();
 
     // Set up panic handler for automatic restart
     std::panic::set_hook(Box::new(|panic_info| {
         error!("PANIC: {}", panic_info);
+        if let Some(location) = panic_info.location() {
+            blackbox::record_event(EventCategory::System, Severity::Error, &format!("panic at {}", location));
+        }
         error!("Restarting in 3 seconds...");
         thread::sleep(Duration::from_secs(3));
         // SAFETY: esp_restart() is always safe to call on ESP32 - it performs a
@@ -93,9 +246,22 @@ fn main() -> anyhow::Result<()> {
     let sys_loop = EspSystemEventLoop::take()?;
     let nvs = EspDefaultNvsPartition::take()?;
 
+    // DHCP (re)assignments: the main loop moves to a new lease right away
+    // instead of waiting for the periodic WiFi check
+    let _ip_events = sys_loop.subscribe::<IpEvent, _>(|event| {
+        if matches!(event, IpEvent::DhcpIpAssigned(_)) {
+            STATION_IP_ASSIGNED.store(true, Ordering::SeqCst);
+        }
+    })?;
+
     // Clone NVS partition for config loading and console
     let nvs_for_config = nvs.clone();
     let nvs_for_console = nvs.clone();
+    let nvs_for_stats = nvs.clone();
+
+    // Flash writes after boot go through their own task so an erase never
+    // stalls routing while the gateway or portal lock is held
+    let nvs_writer = NvsWriter::spawn(nvs.clone(), NVS_WRITER_STACK_SIZE)?;
 
     // Initialize Task Watchdog Timer (TWDT)
     info!("Initializing watchdog timer...");
@@ -151,7 +317,7 @@ fn main() -> anyhow::Result<()> {
     info!("Buttons initialized (A=GPIO37, B=GPIO39, C=GPIO35)");
 
     // Load configuration from NVS (falls back to defaults if not configured)
-    let config = match GatewayConfig::load_from_nvs(nvs_for_config) {
+    let mut config = match GatewayConfig::load_from_nvs(nvs_for_config) {
         Ok(cfg) => cfg,
         Err(e) => {
             warn!("Failed to load config from NVS: {}, using defaults", e);
@@ -163,12 +329,81 @@ fn main() -> anyhow::Result<()> {
     info!("  MS/TP Network Number: {}", config.mstp_network);
     info!("  IP Network Number: {}", config.ip_network);
     info!("  Device Instance: {}", config.device_instance);
+    clock::apply_timezone(&config.timezone);
+    logging::set_json(config.log_json);
+    npdu::set_originated_defaults(config.npdu_hop_count, config.npdu_priority);
+    if config.npdu_hop_count != npdu::MAX_HOP_COUNT || config.npdu_priority != NetworkPriority::Normal {
+        info!("  Originated NPDUs: hop count {}, priority {}", config.npdu_hop_count, config.npdu_priority.as_str());
+    }
+
+    // SAFETY: esp_reset_reason() only reads the reset cause latched at startup
+    let reset_reason = RebootReason::from_reset_reason(unsafe { esp_idf_svc::sys::esp_reset_reason() });
+
+    // Blackbox: keep what led up to an unexpected reset, otherwise show the last one kept
+    let crash_report = match previous_blackbox {
+        Some(entries) if reset_reason.is_crash() => {
+            let report = CrashReport { reason: reset_reason, entries };
+            warn!("  Unexpected reset ({}), {} blackbox entries kept", reset_reason.as_str(), report.entries.len());
+            if let Err(e) = CrashReportPersistence::save(nvs_for_stats.clone(), &report) {
+                warn!("Failed to save crash report: {}", e);
+            }
+            Some(report)
+        }
+        _ => CrashReportPersistence::load(nvs_for_stats.clone()),
+    };
+
+    // Lifetime statistics: count this boot and record why we restarted
+    let mut lifetime_stats = config.lifetime_stats_enabled.then(|| {
+        let mut stats = LifetimeStatsPersistence::load(nvs_for_stats.clone());
+        stats.record_boot(reset_reason);
+        info!(
+            "  Boot #{} (last reboot: {})",
+            stats.boot_count,
+            stats.last_reboot_reason.map(|r| r.as_str()).unwrap_or("unknown")
+        );
+        if let Err(e) = LifetimeStatsPersistence::save(nvs_for_stats.clone(), &stats) {
+            warn!("Failed to save lifetime stats: {}", e);
+        }
+        stats
+    });
+    let mut lifetime_checkpoint_at = std::time::Instant::now();
+
+    // Event log: bring back warnings spilled by the previous boot
+    if config.event_spill_enabled {
+        events::with_log(|log| EventLogPersistence::load(nvs_for_stats.clone(), log));
+    }
+    let boot_reason = lifetime_stats
+        .as_ref()
+        .and_then(|s| s.last_reboot_reason)
+        .unwrap_or(reset_reason);
+    events::record(EventCategory::System, Severity::Info, &format!("Gateway started ({})", boot_reason.as_str()));
+    let mut event_spill_at = std::time::Instant::now();
+    let mut blackbox_heap_at = std::time::Instant::now();
+
+    // Scheduled configuration change: after the restart that applied it,
+    // the ring has the rollback window to prove the change good
+    let mut scheduled_change = ScheduledChangePersistence::load(nvs_for_stats.clone());
+    let mut ring_watch = None;
+    // Network number or station address change being checked before commit
+    let mut staged: Option<StagedValidation> = None;
+    if let Some(change) = &scheduled_change {
+        match change.stage {
+            ScheduleStage::Pending => info!("  Configuration change scheduled at {}: {}", change.apply_at, change.summary()),
+            ScheduleStage::Applied => {
+                info!("  Scheduled change applied ({}), watching the MS/TP ring", change.summary());
+                ring_watch = Some(RingWatch::new(change.rollback_window(), std::time::Instant::now()));
+            }
+        }
+    }
+    let mut ring_up = false;
+    let mut capacity_meter = CapacityMeter::new();
+    let mut governor = Governor::new();
 
     // Initialize WiFi - check if credentials are configured
     info!("Initializing WiFi...");
 
     // Check if WiFi credentials are empty - if so, start in AP mode automatically
-    let (wifi, ip_info_str, start_in_ap_mode) = if config.wifi_ssid.is_empty() {
+    let (wifi, ip_info_str, start_in_ap_mode, apsta_ip) = if config.wifi_ssid.is_empty() {
         info!("No WiFi credentials configured - starting in AP mode");
         lcd.show_status_message("AP Mode", &format!("SSID: {}", config.ap_ssid))?;
 
@@ -181,16 +416,27 @@ fn main() -> anyhow::Result<()> {
         let ap_ip = switch_to_ap_mode(&mut wifi, &config.ap_ssid, &config.ap_password)?;
         AP_MODE_ACTIVE.store(true, Ordering::SeqCst);
 
-        (wifi, ap_ip, true)
+        (wifi, ap_ip, true, None)
     } else {
         lcd.show_wifi_connecting(&config.wifi_ssid)?;
 
+        // APSTA: bring the configuration hotspot up with the station
+        let soft_ap = if config.ap_with_sta {
+            ap_configuration(&config.ap_ssid, &config.ap_password)
+                .map_err(|e| warn!("Hotspot alongside station disabled: {}", e))
+                .ok()
+        } else {
+            None
+        };
+        let apsta = soft_ap.is_some();
+
         let wifi = init_wifi_with_retry(
             peripherals.modem,
             sys_loop.clone(),
             nvs,
             &config.wifi_ssid,
             &config.wifi_password,
+            soft_ap,
             3, // max retries
         ).unwrap_or_else(|e| {
             error!("WiFi initialization failed after retries: {}", e);
@@ -213,7 +459,13 @@ fn main() -> anyhow::Result<()> {
         info!("  Subnet: {}", ip_info.subnet.mask);
         info!("  Gateway: {}", ip_info.subnet.gateway);
 
-        (wifi, ip_str, false)
+        let apsta_ip = if apsta {
+            soft_ap_ip(&wifi).map_err(|e| warn!("Hotspot IP unavailable: {}", e)).ok()
+        } else {
+            None
+        };
+
+        (wifi, ip_str, false, apsta_ip)
     };
 
     let ip_info = if start_in_ap_mode {
@@ -232,7 +484,8 @@ fn main() -> anyhow::Result<()> {
         .baudrate(Hertz(config.mstp_baud_rate))
         .data_bits(esp_idf_svc::hal::uart::config::DataBits::DataBits8)
         .parity_none()
-        .stop_bits(esp_idf_svc::hal::uart::config::StopBits::STOP1);
+        .stop_bits(esp_idf_svc::hal::uart::config::StopBits::STOP1)
+        .queue_size(16); // Event queue for line-level error diagnostics (framing/parity/overflow)
 
     let uart = UartDriver::new(
         peripherals.uart1,
@@ -245,6 +498,35 @@ fn main() -> anyhow::Result<()> {
 
     info!("RS-485 UART initialized at {} baud", config.mstp_baud_rate);
     info!("Note: M5Stack RS-485 HAT has automatic direction control (SP485EEN)");
+    #[cfg(feature = "gpio-trace")]
+    gpio_trace::init();
+
+    // Write-protect jumper: input with pull-up, fitted = pulled to GND
+    let write_protect_pin = match config.write_protect_pin as i32 {
+        0 => None,
+        // The trace markers were just set up as outputs on the same pins
+        #[cfg(feature = "gpio-trace")]
+        pin if gpio_trace::uses_pin(pin) => {
+            error!("Write-protect jumper on G{} not read: the pin drives a GPIO trace marker in this build", pin);
+            events::record(EventCategory::Config, Severity::Error, &format!("Write-protect jumper on G{} ignored (GPIO trace pin)", pin));
+            None
+        }
+        pin => Some(pin),
+    };
+    let mut write_protect_debounce = write_protect_pin.map(|pin| {
+        // SAFETY: the pin is one of write_protect::WRITE_PROTECT_PINS (checked when the
+        // configuration is loaded). The GPIO trace outputs are the only other users of
+        // those pins, and a clash with them was ruled out above, so no driver owns it.
+        let fitted = unsafe {
+            esp_idf_svc::sys::gpio_reset_pin(pin);
+            esp_idf_svc::sys::gpio_set_direction(pin, esp_idf_svc::sys::gpio_mode_t_GPIO_MODE_INPUT);
+            esp_idf_svc::sys::gpio_set_pull_mode(pin, esp_idf_svc::sys::gpio_pull_mode_t_GPIO_PULLUP_ONLY);
+            esp_idf_svc::sys::gpio_get_level(pin) == 0
+        };
+        write_protect::set_locked(fitted);
+        info!("Write-protect jumper on G{}: {}", pin, if fitted { "fitted, configuration locked" } else { "not fitted" });
+        write_protect::Debouncer::new(fitted)
+    });
 
     // Create MS/TP driver
     // Note: No GPIO direction pin needed - HAT has automatic TX/RX switching
@@ -253,21 +535,53 @@ fn main() -> anyhow::Result<()> {
         config.mstp_address,
         config.mstp_max_master,
     )));
+    mstp_driver.lock().unwrap().set_pfm_aggressiveness(config.mstp_pfm_aggressiveness);
+    if config.mstp_slave_mode {
+        info!("MS/TP slave mode: answering requests at {} without joining the token ring", config.mstp_address);
+        mstp_driver.lock().unwrap().set_slave_mode(true);
+    }
+
+    // Auto-address: find a free station address before anything uses ours
+    // (a slave isn't in the ring, so its address is always the configured one).
+    // With none free, joining on the configured address would clash with the
+    // station already using it, so MS/TP stays listen-only instead.
+    let mut mstp_no_free_address = false;
+    if config.mstp_auto_address && !config.mstp_slave_mode {
+        lcd.show_status_message("MS/TP", "Finding free address...")?;
+        let chosen = mstp_driver.lock().unwrap().survey_station_address(config.mstp_address, || {
+            let _ = watchdog.feed();
+        });
+        match chosen {
+            Some(addr) if addr != config.mstp_address => {
+                info!("MS/TP station address changed {} -> {} by auto-address", config.mstp_address, addr);
+                config.mstp_address = addr;
+                if let Err(e) = config.save_mstp_address(nvs_for_console.clone()) {
+                    warn!("Failed to persist MS/TP station address: {}", e);
+                }
+            }
+            Some(_) => {}
+            None => {
+                error!("Auto-address: no free MS/TP station address - MS/TP is listen-only");
+                events::record(
+                    EventCategory::Token,
+                    Severity::Error,
+                    &format!("Auto-address found no free station address; not joining the ring as {}", config.mstp_address),
+                );
+                mstp_driver.lock().unwrap().set_listen_only(true);
+                mstp_no_free_address = true;
+            }
+        }
+    }
 
-    // Create BACnet/IP UDP socket
+    // Create BACnet/IP UDP socket (shared between threads, and re-bound
+    // in place if it dies - try_clone() doesn't work on ESP-IDF)
     info!("Creating BACnet/IP socket...");
-    let bind_addr = format!("0.0.0.0:{}", config.bacnet_ip_port);
-    let socket = UdpSocket::bind(&bind_addr)?;
-    socket.set_broadcast(true)?;
-    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
-    info!("BACnet/IP socket bound to {}", bind_addr);
+    let bip_socket = Arc::new(BipSocket::bind(config.bacnet_ip_port)?);
+    info!("BACnet/IP socket bound to 0.0.0.0:{}", config.bacnet_ip_port);
 
     // Create gateway - use local IP and subnet mask for routing
     let local_ip: std::net::Ipv4Addr = ip_info.ip.octets().into();
-    // Convert CIDR prefix to subnet mask (e.g., 24 -> 255.255.255.0)
-    let prefix: u8 = ip_info.subnet.mask.0;
-    let mask_bits: u32 = if prefix == 0 { 0 } else { !0u32 << (32 - prefix) };
-    let subnet_mask: std::net::Ipv4Addr = mask_bits.to_be_bytes().into();
+    let subnet_mask = prefix_to_mask(ip_info.subnet.mask.0);
     let gateway = Arc::new(Mutex::new(BacnetGateway::new(
         config.mstp_network,
         config.ip_network,
@@ -302,30 +616,129 @@ fn main() -> anyhow::Result<()> {
         mac_address,
     );
 
+    // The hotspot gets its own Network Port while it is up; the station's
+    // port is Out_Of_Service while the gateway runs as a hotspot only
+    let ap_mac_address = wifi.wifi().ap_netif().get_mac().unwrap_or([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+    local_device.add_hotspot_network_port(config.ip_network, ap_mac_address);
+    if start_in_ap_mode {
+        local_device.set_station_interface(None);
+        local_device.set_hotspot_interface(Some((local_ip.octets(), subnet_mask.octets())));
+    } else if let Some(ap_ip) = apsta_ip.as_deref().and_then(|ip| ip.parse::<Ipv4Addr>().ok()) {
+        local_device.set_hotspot_interface(Some((ap_ip.octets(), [255, 255, 255, 0])));
+    }
+
+    // Site-specific Device properties (Serial_Number defaults to the WiFi MAC)
+    if config.device_serial_number.is_empty() {
+        config.device_serial_number = mac_address.iter().map(|b| format!("{:02X}", b)).collect();
+    }
+    local_device.set_site_info(DeviceSiteInfo {
+        location: config.device_location.clone(),
+        description: config.device_description.clone(),
+        serial_number: config.device_serial_number.clone(),
+    });
+    local_device.set_services(config.device_services);
+    local_device.set_write_protected(write_protect::is_locked());
+    gateway.lock().unwrap().set_write_protected(write_protect::is_locked());
+
+    // Redundancy pair: both units start passive - listening on MS/TP, ignoring
+    // B/IP - until they know what the other one is doing
+    let mut failover_pair = match (config.failover_role, config.failover_peer) {
+        (FailoverRole::Off, _) => None,
+        (role, Some(peer)) => match UdpSocket::bind(("0.0.0.0", HEARTBEAT_PORT)) {
+            Ok(socket) => {
+                let _ = socket.set_nonblocking(true);
+                info!("Redundancy pair: {} unit with peer {}, standing by", role.as_str(), peer);
+                failover::set_standing_by(true);
+                mstp_driver.lock().unwrap().set_listen_only(true);
+                gateway.lock().unwrap().set_standby(true);
+                let now = std::time::Instant::now();
+                Some((Failover::new(role, now), socket, SocketAddr::from((peer, HEARTBEAT_PORT))))
+            }
+            Err(e) => {
+                error!("Redundancy pair: cannot bind heartbeat port {}: {} - running as a single unit", HEARTBEAT_PORT, e);
+                None
+            }
+        },
+        (role, None) => {
+            warn!("Redundancy {} role has no peer address - running as a single unit", role.as_str());
+            None
+        }
+    };
+    let mut failover_heartbeat_at = std::time::Instant::now();
+
     let local_device = Arc::new(local_device);
+    // Transparent router mode: the receive tasks never see the local device
+    let local_device_for_tasks = config.local_device_enabled.then(|| Arc::clone(&local_device));
+    if !config.local_device_enabled {
+        info!("Local device disabled - running as a transparent router");
+    }
 
     // Wrap WiFi in Arc<Mutex> for sharing with main loop (for reconnection)
     let wifi = Arc::new(Mutex::new(wifi));
 
-    // Wrap socket in Arc for sharing between threads
-    // (try_clone() doesn't work on ESP-IDF)
-    let socket = Arc::new(socket);
+    if let Ok(mut gw) = gateway.lock() {
+        gw.set_suppress_on_conflict(config.suppress_on_duplicate_network);
+        gw.set_nvs_partition(nvs_for_stats.clone(), nvs_writer.clone());
+        gw.set_whois_filter(config.whois_filter_enabled);
+        gw.set_ip_network_learning(config.ip_network_learn);
+        gw.set_secure_bvll_drop(config.secure_bvll_drop);
+        gw.set_npdu_lenient(config.npdu_lenient);
+        gw.set_broadcast_form(config.bip_broadcast_form, &config.supervisory_stations);
+        gw.set_router_announcements(config.announce_scope, &config.announce_networks);
+        gw.set_compat_rules(config.compat_rules.clone());
+        gw.set_mstp_max_npdu(config.mstp_max_npdu as usize);
+        gw.set_backpressure(config.backpressure_high as usize, config.backpressure_low as usize);
+        if let Some(ap_ip) = apsta_ip.as_deref().and_then(|ip| ip.parse::<Ipv4Addr>().ok()) {
+            gw.set_ap_interface(Some((ap_ip, Ipv4Addr::new(255, 255, 255, 0))));
+        }
+    }
+
+    // Optional B/IP multicast (Annex J.7): join the group on the WiFi netif
+    // (lwIP sends the IGMP report) and broadcast to the group instead of the subnet.
+    // Falls back to subnet broadcast if the join fails.
+    let mut bip_multicast_iface: Option<Ipv4Addr> = None;
+    if config.bip_multicast_enabled {
+        let joined = bip_socket.current().is_some_and(|socket| {
+            if let Err(e) = socket.set_multicast_loop_v4(false) {
+                warn!("Failed to disable multicast loopback: {}", e);
+            }
+            rejoin_bip_multicast(&socket, config.bip_multicast_group, &mut bip_multicast_iface, local_ip)
+        });
+        if let Ok(mut gw) = gateway.lock() {
+            gw.set_multicast_group(joined.then_some(config.bip_multicast_group));
+        }
+    }
 
-    // Set the IP socket on the gateway so it can send MS/TP->IP traffic
+    // Give the gateway the B/IP data link so it can send MS/TP->IP traffic
     // This is critical - without this, all MS/TP to IP packets are queued but never sent!
+    #[cfg(feature = "fault-injection")]
+    // SAFETY: esp_random() has no preconditions
+    let ip_faults = Arc::new(Mutex::new(fault_injection::FaultInjector::new(unsafe { esp_idf_svc::sys::esp_random() })));
+    let link: Box<dyn DataLink> = Box::new(BipLink::new(Arc::clone(&bip_socket)));
+    #[cfg(feature = "fault-injection")]
+    let link: Box<dyn DataLink> = Box::new(fault_injection::FaultyLink::new(link, Arc::clone(&ip_faults)));
+    // Socket sends run on their own task so a slow send never blocks routing
+    // while the gateway lock is held
+    let (link, ip_tx_stats) = QueuedLink::spawn(link, IP_TX_QUEUE_LEN, IP_TX_STACK_SIZE)?;
     if let Ok(mut gw) = gateway.lock() {
-        gw.set_ip_socket(Arc::clone(&socket));
-        info!("IP socket set on gateway for MS/TP->IP routing");
+        gw.set_ip_link(Box::new(link));
+        info!("B/IP link set on gateway for MS/TP->IP routing");
+        // The MS/TP task polls the same driver for reception
+        gw.set_mstp_link(Box::new(SharedLink::new(Arc::clone(&mstp_driver))));
     }
 
     // Create web server state early so it can be shared with receive tasks
     let web_state = Arc::new(Mutex::new(WebState::new(config.clone(), Some(nvs_for_console))));
+    if let Ok(mut web) = web_state.lock() {
+        web.nvs_writer = Some(nvs_writer.clone());
+        web.mstp_no_free_address = mstp_no_free_address;
+    }
 
     // Spawn MS/TP receive thread
     info!(">>> [MAIN] About to spawn MS/TP receive thread...");
     let mstp_driver_clone = Arc::clone(&mstp_driver);
     let gateway_clone = Arc::clone(&gateway);
-    let local_device_clone = Arc::clone(&local_device);
+    let local_device_clone = local_device_for_tasks.clone();
     let web_state_mstp = Arc::clone(&web_state);
     // Stack size increased from 8KB to 16KB to handle BACnet protocol processing
     // which may require significant stack space for NPDU parsing, routing tables,
@@ -339,10 +752,10 @@ fn main() -> anyhow::Result<()> {
     info!(">>> [MAIN] MS/TP thread spawned successfully!");
 
     // Spawn BACnet/IP receive thread
-    let socket_clone = Arc::clone(&socket);
+    let socket_clone = Arc::clone(&bip_socket);
     let gateway_clone = Arc::clone(&gateway);
     let mstp_driver_clone = Arc::clone(&mstp_driver);
-    let local_device_clone = Arc::clone(&local_device);
+    let local_device_clone = local_device_for_tasks.clone();
     let ip_network_for_thread = config.ip_network;
     let mstp_network_for_ip_thread = config.mstp_network;
     let gateway_mac_for_thread = config.mstp_address;
@@ -388,8 +801,15 @@ fn main() -> anyhow::Result<()> {
         // AP mode fields
         ap_mode_active: start_in_ap_mode,
         ap_ssid: config.ap_ssid.clone(),
-        ap_ip: if start_in_ap_mode { ip_info_str.clone() } else { "192.168.4.1".to_string() },
+        ap_ip: if start_in_ap_mode {
+            ip_info_str.clone()
+        } else {
+            apsta_ip.clone().unwrap_or_else(|| "192.168.4.1".to_string())
+        },
         ap_clients: 0,
+        ap_client_summary: String::new(),
+        network_conflict: None,
+        schedule_countdown: String::new(),
     };
     info!(">>> [MAIN] DEBUG: GatewayStatus created successfully");
 
@@ -398,14 +818,33 @@ fn main() -> anyhow::Result<()> {
     let mut btn_a_was_pressed = false;
     let mut btn_b_was_pressed = false;
     let mut btn_c_was_pressed = false;
+    // When button C went down, for the power-down long-press
+    let mut btn_c_pressed_at: Option<std::time::Instant> = None;
 
     // WiFi reconnection tracking
     let mut wifi_check_counter: u32 = 0;
     const WIFI_CHECK_INTERVAL: u32 = 50; // Check every 5 seconds (50 * 100ms)
+    // For the WiFi diagnostics objects: station link recoveries, and whether
+    // the hotspot has been up (its client count exists from then on)
+    let mut wifi_reconnects: u32 = 0;
+    let mut hotspot_seen = false;
 
-    // Router announcement tracking (I-Am and I-Am-Router-To-Network)
-    // Start at max to trigger immediate announcement on first loop
-    let mut router_announce_counter: u64 = ROUTER_ANNOUNCE_INTERVAL;
+    // When the APSTA hotspot came up, for the auto-off timer
+    let mut soft_ap_since = apsta_ip.is_some().then(std::time::Instant::now);
+
+    // Router announcement tracking: the gateway owns the schedule, main
+    // watches for MS/TP token acquisition to announce immediately on link-up
+    let mut mstp_token_was_active = false;
+
+    // Who-Is sweep being paced out (started from the portal or console)
+    let mut active_scan: Option<ScanPlan> = None;
+    let mut scan_sent_at: Option<std::time::Instant> = None;
+
+    // Scheduled discovery for webhook reports, and the scan it started
+    let scheduled_scan_interval = (config.webhook_enabled && config.webhook_scan_hours > 0)
+        .then(|| Duration::from_secs(u64::from(config.webhook_scan_hours) * 3600));
+    let mut last_scheduled_scan = std::time::Instant::now();
+    let mut scheduled_scan_id: Option<u32> = None;
 
     // Stats logging tracking (log every 60 seconds)
     let mut stats_log_counter: u64 = 0;
@@ -421,9 +860,26 @@ fn main() -> anyhow::Result<()> {
         let mut state = web_state.lock().unwrap();
         state.wifi_connected = !start_in_ap_mode;  // Only connected in Station mode
         state.ip_address = ip_info.ip.to_string();
+        if !start_in_ap_mode {
+            state.default_gateway = Some(ip_info.subnet.gateway.octets().into());
+        }
+        state.crash_report = crash_report;
     }
     info!(">>> [MAIN] web_state updated");
 
+    // SNTP for UTC timestamps on captured frames, events and log lines (Station mode only)
+    let _sntp = if start_in_ap_mode {
+        None
+    } else {
+        match clock::start_sntp(&config.ntp_server) {
+            Ok(sntp) => Some(sntp),
+            Err(e) => {
+                warn!("Failed to start SNTP: {}", e);
+                None
+            }
+        }
+    };
+
     // Start web server for configuration portal
     info!(">>> [MAIN] About to start web server...");
     let web_state_clone = Arc::clone(&web_state);
@@ -437,6 +893,63 @@ fn main() -> anyhow::Result<()> {
             None
         }
     };
+
+    // Read-only status for a NOC on its own port, kept apart from the portal
+    let _public_status_server = if config.public_status_port != 0 {
+        match public_status::start_public_status_server(Arc::clone(&web_state), config.public_status_port) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                error!("Failed to start public status server on port {}: {}", config.public_status_port, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Serial console on the USB UART for headless provisioning
+    if let Err(e) = console::spawn_console_task(Arc::clone(&web_state)) {
+        error!("Failed to spawn serial console task: {:?}", e);
+    }
+
+    // The same console over TCP for portal admins (optional)
+    if config.remote_console_port != 0 {
+        if let Err(e) = remote_console::spawn_remote_console(Arc::clone(&web_state), config.remote_console_port) {
+            error!("Failed to start remote console on port {}: {:?}", config.remote_console_port, e);
+        }
+    }
+
+    // Heartbeat reporting to a fleet monitoring endpoint (optional)
+    if config.heartbeat_enabled {
+        match heartbeat::spawn_heartbeat_task(Arc::clone(&web_state)) {
+            Ok(()) => info!("Heartbeat reporting to {} every {}s", config.heartbeat_url, config.heartbeat_interval_secs),
+            Err(e) => error!("Failed to spawn heartbeat task: {:?}", e),
+        }
+    }
+
+    // Webhook notifications to an integrator endpoint (optional)
+    if config.webhook_enabled {
+        match notify::spawn_notify_task(Arc::clone(&web_state)) {
+            Ok(()) => info!("Webhook notifications to {}", config.webhook_url),
+            Err(e) => error!("Failed to spawn webhook task: {:?}", e),
+        }
+    }
+
+    // Hand the LCD to its own low-priority render task; the main loop only
+    // publishes status snapshots
+    let display_state = Arc::new(Mutex::new(DisplayState {
+        screen: current_screen,
+        status: status.clone(),
+        full_redraw: false,
+        message: None,
+    }));
+    if let Err(e) = display::spawn_render_task(lcd, Arc::clone(&display_state), Arc::clone(&web_state)) {
+        error!("Failed to spawn display render task: {:?}", e);
+    }
+    // A sagging supply raises a shutdown request instead of resetting the chip
+    if let Err(e) = shutdown::install_brown_out_hook() {
+        error!("Failed to arm brown-out detector: {}", e);
+    }
     info!(">>> [MAIN] Web server setup complete, about to enter main loop...");
 
     let mut loop_count: u64 = 0;
@@ -473,23 +986,19 @@ fn main() -> anyhow::Result<()> {
                         gw.active_transaction_count()
                     );
                 }
+            }
+        }
 
-                // Drain MS/TP send queue and transmit retries
-                let retries = gw.drain_mstp_send_queue();
-                if !retries.is_empty() {
-                    drop(gw); // Release gateway lock before acquiring driver lock
-                    if let Ok(mut driver) = mstp_driver.lock() {
-                        for (npdu, dest_mac) in retries {
-                            info!(
-                                "Retransmitting {} bytes to MS/TP MAC {}",
-                                npdu.len(), dest_mac
-                            );
-                            if let Err(e) = driver.send_frame(&npdu, dest_mac, true) {
-                                warn!("Failed to retransmit to MS/TP {}: {}", dest_mac, e);
-                            }
-                        }
-                    }
-                }
+        // Store-and-forward: every 100ms fail frames held past their TTL, then
+        // release the rest to MS/TP once the ring is up (as queue space allows)
+        if loop_count % 10 == 0 {
+            let queue_space = match mstp_driver.try_lock() {
+                Ok(driver) if driver.is_ring_up() => driver.send_queue_space(),
+                _ => 0,
+            };
+            if let Ok(mut gw) = gateway.try_lock() {
+                gw.expire_held_frames();
+                gw.send_held_frames(queue_space);
             }
         }
 
@@ -500,108 +1009,138 @@ fn main() -> anyhow::Result<()> {
             if let Ok(gw) = gateway.try_lock() {
                 info!("\n{}", gw.get_stats_summary());
             }
+            info!(
+                "Receive paths: {} B/IP datagrams, {} local device replies, {} COV subscriptions",
+                BIP_RX_DATAGRAMS.load(Ordering::Relaxed),
+                LOCAL_DEVICE_REPLIES.load(Ordering::Relaxed),
+                local_device.cov_subscription_count()
+            );
         }
 
-        // Check if Who-Is scan was requested from web portal (non-blocking)
-        let scan_requested = {
-            match web_state.try_lock() {
-                Ok(mut web) => {
-                    if web.scan_requested {
-                        info!("Main loop: scan_requested=true, processing...");
-                        web.scan_requested = false;
-                        true
-                    } else {
-                        false
-                    }
+        // Check if Who-Is scan was requested or stopped from the portal (non-blocking)
+        if let Ok(mut web) = web_state.try_lock() {
+            if let Some(interval) = scheduled_scan_interval {
+                if !web.scan_in_progress && last_scheduled_scan.elapsed() >= interval {
+                    last_scheduled_scan = std::time::Instant::now();
+                    let scan_id = web.request_scan(ScanProfile::default());
+                    info!("Scheduled Who-Is scan {} started", scan_id);
+                    scheduled_scan_id = Some(scan_id);
                 }
-                Err(_) => false,  // Skip this iteration if locked
             }
-        };
-
-        // Process scan request with driver lock
-        if scan_requested {
-            info!("Who-Is scan requested - sending broadcasts");
-
-            // Build Who-Is APDU
-            let who_is_apdu = LocalDevice::build_who_is();
-            info!("Who-Is APDU: {:02X?}", who_is_apdu);
-
-            // Send LOCAL broadcast first (simple NPDU, no network layer)
-            // This reaches devices on the local MS/TP segment
-            let mut local_npdu = Vec::with_capacity(who_is_apdu.len() + 2);
-            local_npdu.push(0x01); // NPDU version
-            local_npdu.push(0x00); // Control: no network layer info
-            local_npdu.extend_from_slice(&who_is_apdu);
-            info!("Who-Is NPDU (local): {:02X?}", local_npdu);
-
-            // Also send GLOBAL broadcast (DNET=0xFFFF) for routers
-            // Per Clause 6.2.2, when DNET is present we must include SNET/SADR so routers
-            // know where to return replies. We include our configured MS/TP network and MAC.
-            let mut global_npdu = Vec::with_capacity(who_is_apdu.len() + 12);
-            global_npdu.push(0x01); // NPDU version
-            // Control: destination present + source present (required when DNET is present)
-            global_npdu.push(0x28);
-            global_npdu.push(0xFF); // DNET high byte (0xFFFF = global broadcast)
-            global_npdu.push(0xFF); // DNET low byte
-            global_npdu.push(0x00); // DLEN = 0 (broadcast)
-            // Source specifier (SNET/SADR) so I-Am can be routed back
-            global_npdu.push((config.mstp_network >> 8) as u8); // SNET high
-            global_npdu.push((config.mstp_network & 0xFF) as u8); // SNET low
-            global_npdu.push(0x01); // SLEN = 1 (our MS/TP MAC length)
-            global_npdu.push(config.mstp_address); // SADR = our MAC
-            global_npdu.push(0xFF); // Hop count
-            global_npdu.extend_from_slice(&who_is_apdu);
-            info!("Who-Is NPDU (global): {:02X?}", global_npdu);
-
-            // Now lock driver and queue frames
-            if let Ok(mut driver) = mstp_driver.lock() {
-                match driver.send_frame(&local_npdu, 0xFF, false) {
-                    Ok(_) => info!("Local Who-Is broadcast queued"),
-                    Err(e) => warn!("Failed to queue local Who-Is: {}", e),
+            if web.scan_requested {
+                web.scan_requested = false;
+                scan_sent_at = None;
+                let profile = web.scan_profile;
+                info!("Who-Is scan requested ({}, {} requests)", profile, profile.step_count());
+                active_scan = Some(ScanPlan::new(
+                    profile,
+                    config.mstp_baud_rate,
+                    config.mstp_network,
+                    config.mstp_address,
+                    std::time::Instant::now(),
+                ));
+            } else if !web.scan_in_progress && active_scan.is_some() {
+                info!("Who-Is sweep stopped before completion");
+                active_scan = None;
+            }
+            if let Some(plan) = &active_scan {
+                web.scan_progress = plan.progress();
+                if plan.is_done() {
+                    info!("Who-Is sweep ({}) sent", plan.profile());
+                    active_scan = None;
+                    scan_sent_at = Some(std::time::Instant::now());
                 }
-                match driver.send_frame(&global_npdu, 0xFF, false) {
-                    Ok(_) => info!("Global Who-Is broadcast queued"),
-                    Err(e) => warn!("Failed to queue global Who-Is: {}", e),
+            }
+            // End the scan once late I-Am replies have had time to arrive
+            if scan_sent_at.is_some_and(|t| t.elapsed() >= SCAN_REPLY_WINDOW) {
+                web.scan_in_progress = false;
+                scan_sent_at = None;
+
+                // Judge appeared/disappeared devices against the previous scan;
+                // a ranged sweep covers only part of the instance space
+                let scan_id = web.scan_id;
+                if !matches!(web.scan_profile, ScanProfile::Ranged { .. }) {
+                    let answered: Vec<(u32, u8)> = web.discovered_devices
+                        .iter()
+                        .filter(|d| d.last_scan_id == scan_id)
+                        .map(|d| (d.device_instance, d.mac_address))
+                        .collect();
+                    let scheduled = scheduled_scan_id.take() == Some(scan_id);
+                    web.notifier.scan_finished(scan_id, &answered, scheduled, std::time::Instant::now());
                 }
-            } else {
-                warn!("Could not lock MS/TP driver to send Who-Is");
+            }
+            if config.webhook_enabled && loop_count % 100 == 0 {
+                let errors = web.gateway_stats.routing_errors + web.mstp_stats.crc_errors;
+                web.notifier.check_errors(errors, config.webhook_error_threshold, std::time::Instant::now());
             }
         }
 
-        // Periodic router announcements (I-Am and I-Am-Router-To-Network)
-        // This announces the router's presence on the MS/TP network so devices know we exist
-        router_announce_counter += 1;
-        // Debug: log every 1000 iterations to verify counter is incrementing
-        if router_announce_counter % 1000 == 0 {
-            info!("Announcement counter: {} (threshold: {})", router_announce_counter, ROUTER_ANNOUNCE_INTERVAL);
+        // Send the next Who-Is of the sweep once its pacing interval is up
+        if let Some(plan) = active_scan.as_mut() {
+            if let Some(frames) = plan.poll(std::time::Instant::now()) {
+                if let Ok(mut driver) = mstp_driver.lock() {
+                    for frame in frames {
+                        trace!("Who-Is NPDU to MS/TP {}: {:02X?}", frame.dest, frame.npdu);
+                        if let Err(e) = driver.send_frame(&frame.npdu, frame.dest, false) {
+                            warn!("Failed to queue Who-Is to MS/TP {}: {}", frame.dest, e);
+                        }
+                    }
+                } else {
+                    warn!("Could not lock MS/TP driver to send Who-Is");
+                }
+            }
         }
-        if router_announce_counter >= ROUTER_ANNOUNCE_INTERVAL {
-            router_announce_counter = 0;
-
-            info!("Sending periodic router announcements...");
 
-            // Build I-Am APDU for the gateway device
-            let iam_apdu = local_device.build_i_am();
+        // Router announcements (I-Am and I-Am-Router-To-Network) on both ports.
+        // The gateway schedules them and sends the IP side itself; the MS/TP
+        // side is batched here and queued while we hold the driver lock once.
+        let token_active = mstp_driver.try_lock().ok().map(|d| d.is_token_active());
+        let announcements = match token_active {
+            Some(active) => {
+                let link_up = active && !mstp_token_was_active;
+                if active != mstp_token_was_active {
+                    if active {
+                        events::record(EventCategory::Token, Severity::Info, "MS/TP token ring up");
+                    } else {
+                        events::record(EventCategory::Token, Severity::Warning, "MS/TP token lost");
+                    }
+                }
+                mstp_token_was_active = active;
+                match gateway.try_lock() {
+                    Ok(mut gw) => {
+                        if link_up {
+                            info!("MS/TP token acquired - announcing router");
+                            gw.request_router_announce();
+                        }
+                        gw.announce_router(active)
+                    }
+                    Err(_) => None,
+                }
+            }
+            None => None,
+        };
+        if let Some(announcements) = announcements {
+            info!("Sending router announcements...");
 
-            // Wrap I-Am in NPDU (local broadcast, no network layer info)
-            let mut iam_npdu = Vec::with_capacity(iam_apdu.len() + 2);
-            iam_npdu.push(0x01); // NPDU version
-            iam_npdu.push(0x00); // Control: no network layer info
-            iam_npdu.extend_from_slice(&iam_apdu);
+            // Queue both announcements (no I-Am in transparent router mode)
+            if let Ok(mut driver) = mstp_driver.lock() {
+                if config.local_device_enabled {
+                    // Build I-Am APDU for the gateway device
+                    let iam_apdu = local_device.build_i_am();
 
-            // Build I-Am-Router-To-Network announcing the IP network
-            // This tells MS/TP devices that we can route to the IP network
-            let iartn_npdu = LocalDevice::build_i_am_router_to_network(&[config.ip_network]);
+                    // Wrap I-Am in NPDU (local broadcast, no network layer info)
+                    let iam_npdu = Npdu::local(&iam_apdu).encode();
 
-            // Queue both announcements
-            if let Ok(mut driver) = mstp_driver.lock() {
-                match driver.send_frame(&iam_npdu, 0xFF, false) {
-                    Ok(_) => info!("I-Am broadcast queued"),
-                    Err(e) => warn!("Failed to queue I-Am: {}", e),
+                    match driver.send_frame(&iam_npdu, 0xFF, false) {
+                        Ok(_) => info!("I-Am broadcast queued"),
+                        Err(e) => warn!("Failed to queue I-Am: {}", e),
+                    }
                 }
-                match driver.send_frame(&iartn_npdu, 0xFF, false) {
-                    Ok(_) => info!("I-Am-Router-To-Network broadcast queued (announcing network {})", config.ip_network),
-                    Err(e) => warn!("Failed to queue I-Am-Router-To-Network: {}", e),
+                for npdu in &announcements {
+                    match driver.send_frame(npdu, 0xFF, false) {
+                        Ok(_) => info!("Router announcement broadcast queued on MS/TP"),
+                        Err(e) => warn!("Failed to queue router announcement: {}", e),
+                    }
                 }
             } else {
                 warn!("Could not lock MS/TP driver for router announcements");
@@ -611,6 +1150,11 @@ fn main() -> anyhow::Result<()> {
         // Get MS/TP driver stats (non-blocking to avoid starvation)
         if let Ok(mut driver) = mstp_driver.try_lock() {
             let mstp_stats = driver.get_stats();
+            local_device.set_port_statistics(MSTP_PORT_INSTANCE, PortStatistics {
+                packets_sent: mstp_stats.tx_frames,
+                packets_received: mstp_stats.rx_frames,
+                errors: mstp_stats.crc_errors + mstp_stats.frame_errors,
+            });
             status.rx_frames = mstp_stats.rx_frames;
             status.tx_frames = mstp_stats.tx_frames;
             status.crc_errors = mstp_stats.crc_errors;
@@ -619,198 +1163,1213 @@ fn main() -> anyhow::Result<()> {
             // Connection screen fields
             status.mstp_state = driver.get_state_name().to_string();
             status.has_token = driver.has_token();
+            ring_up = driver.is_link_up();
+            health::set_ring_up(ring_up);
+
+            // Line capacity, sampled about once a second
+            let capacity = (loop_count % 100 == 0).then(|| {
+                capacity_meter.record(std::time::Instant::now(), LineCounters {
+                    line_bytes: mstp_stats.line_bytes,
+                    data_bytes: mstp_stats.data_frame_bytes,
+                    tokens: mstp_stats.tokens_received,
+                });
+                capacity_meter.estimate(
+                    config.mstp_baud_rate,
+                    mstp_stats.master_count,
+                    mstp_stats.token_loop_avg_ms,
+                    config.mstp_max_npdu as usize,
+                )
+            });
 
             // Update web state with MS/TP stats
             if let Ok(mut web) = web_state.try_lock() {
+                if let Some(capacity) = capacity {
+                    web.capacity = capacity;
+                }
                 web.mstp_stats = mstp_stats;
 
                 // Check if stats reset was requested from web portal
                 if web.reset_stats_requested {
                     driver.reset_stats();
+                    web.display_render_max_us = 0;
+                    errors::with_log(|log| log.reset());
+                    web_budget::with_budget(|budget| budget.reset_stats());
                     web.reset_stats_requested = false;
                     info!("Statistics reset completed");
                 }
+
+                // Apply a Max_Master accepted on the diagnostics page
+                if let Some(max_master) = web.max_master_request.take() {
+                    driver.set_max_master(max_master);
+                }
+
+                // Service wiring test requests from web portal
+                if let Some((target, count)) = web.loopback_request.take() {
+                    if let Err(e) = driver.start_loopback_test(target, count) {
+                        warn!("Failed to start wiring test to {}: {}", target, e);
+                    }
+                }
+                if web.loopback_stop_requested {
+                    driver.stop_loopback_test();
+                    web.loopback_stop_requested = false;
+                    info!("Wiring test stopped");
+                }
+                web.loopback_result = driver.get_loopback_result();
+
+                // Apply injected fault rates set from the web portal
+                #[cfg(feature = "fault-injection")]
+                {
+                    driver.set_fault_settings(web.fault_settings);
+                    web.fault_stats_mstp = driver.fault_stats();
+                    if let Ok(mut faults) = ip_faults.lock() {
+                        faults.set_settings(web.fault_settings);
+                        web.fault_stats_ip = faults.stats();
+                    }
+                }
             }
         }
 
         // Get gateway stats for web portal (non-blocking)
         if let Ok(gw) = gateway.try_lock() {
             let gw_stats = gw.get_stats();
+            local_device.set_port_statistics(IP_PORT_INSTANCE, PortStatistics {
+                packets_sent: gw_stats.mstp_to_ip_packets,
+                packets_received: gw_stats.ip_to_mstp_packets,
+                errors: gw_stats.routing_errors + ip_tx_stats.send_errors.load(Ordering::Relaxed),
+            });
+            local_device.set_network_number(IP_PORT_INSTANCE, gw.ip_network_number());
+            local_device.set_network_number(AP_PORT_INSTANCE, gw.ip_network_number());
             if let Ok(mut web) = web_state.try_lock() {
+                web.gateway_stats.ip_network = gw.ip_network_number();
+                web.gateway_stats.router_mode = gw.router_mode();
                 web.gateway_stats.mstp_to_ip_packets = gw_stats.mstp_to_ip_packets;
                 web.gateway_stats.ip_to_mstp_packets = gw_stats.ip_to_mstp_packets;
                 web.gateway_stats.mstp_to_ip_bytes = gw_stats.mstp_to_ip_bytes;
                 web.gateway_stats.ip_to_mstp_bytes = gw_stats.ip_to_mstp_bytes;
                 web.gateway_stats.routing_errors = gw_stats.routing_errors;
                 web.gateway_stats.transaction_timeouts = gw_stats.transaction_timeouts;
+                web.gateway_stats.held_now = gw.held_frame_count();
+                web.gateway_stats.held_frames = gw_stats.held_frames;
+                web.gateway_stats.held_released = gw_stats.held_released;
+                web.gateway_stats.held_expired = gw_stats.held_expired;
+                web.gateway_stats.held_overflows = gw_stats.held_overflows;
+                web.gateway_stats.active_transactions = gw.active_transaction_count();
+                web.gateway_stats.fdt_entries = gw.foreign_device_count();
+                web.gateway_stats.bdt_entries = gw.bdt_count();
+                web.gateway_stats.rejects_received = gw_stats.rejects_received;
+                web.gateway_stats.messages_too_long = gw_stats.messages_too_long;
+                web.gateway_stats.port_disabled_drops = gw_stats.port_disabled_drops;
+                web.gateway_stats.rpm_proxied = gw_stats.rpm_proxied;
+                web.gateway_stats.wpm_proxied = gw_stats.wpm_proxied;
+                web.gateway_stats.whois_suppressed = gw_stats.whois_suppressed;
+                web.gateway_stats.backpressure_aborts = gw_stats.backpressure_aborts;
+                web.gateway_stats.secure_bvll_received = gw_stats.secure_bvll_received;
+                web.gateway_stats.secure_bvll_sources = gw.secure_bvll_sources();
+                web.gateway_stats.npdu_quirks = gw_stats.npdu_quirks;
+                web.gateway_stats.supervisory_stations = gw.supervisory_stations().to_vec();
+                web.gateway_stats.ip_tx_queue_len = ip_tx_stats.depth.load(Ordering::Relaxed);
+                web.gateway_stats.ip_tx_dropped = ip_tx_stats.dropped.load(Ordering::Relaxed);
+                web.gateway_stats.ip_tx_errors = ip_tx_stats.send_errors.load(Ordering::Relaxed);
+                web.gateway_stats.ip_socket_recoveries = bip_socket.recoveries();
+                if loop_count % 100 == 0 {
+                    web.top_talkers_by_packets = gw.top_talkers(TOP_TALKERS_SHOWN, TalkerRanking::Packets);
+                    web.top_talkers_by_bytes = gw.top_talkers(TOP_TALKERS_SHOWN, TalkerRanking::Bytes);
+                    web.ip_peers = Arc::new(gw.peer_stats());
+                    if !web.config.configured {
+                        web.heard_networks = gw.heard_networks().clone();
+                    }
+                }
             }
         }
 
-        // Periodically check WiFi connection and attempt reconnection if needed
-        wifi_check_counter += 1;
-        if wifi_check_counter >= WIFI_CHECK_INTERVAL {
-            wifi_check_counter = 0;
+        // Advance lifetime statistics; checkpoint to NVS on a slow cadence
+        if loop_count % 100 == 0 {
+            if let Some(stats) = lifetime_stats.as_mut() {
+                if let Ok(mut web) = web_state.try_lock() {
+                    stats.update(&LifetimeCounters {
+                        mstp_to_ip_packets: web.gateway_stats.mstp_to_ip_packets,
+                        ip_to_mstp_packets: web.gateway_stats.ip_to_mstp_packets,
+                        routing_errors: web.gateway_stats.routing_errors,
+                        crc_errors: web.mstp_stats.crc_errors,
+                        uptime_secs: web.uptime_secs(),
+                    });
+                    web.lifetime_stats = Some(stats.clone());
+                }
+                if lifetime_checkpoint_at.elapsed() >= lifetime::CHECKPOINT_INTERVAL {
+                    lifetime_checkpoint_at = std::time::Instant::now();
+                    nvs_writer.submit(NvsWrite::LifetimeStats(stats.clone()));
+                }
+            }
+        }
 
-            // In AP mode, update client count; in STA mode, check connection
-            if AP_MODE_ACTIVE.load(Ordering::SeqCst) {
-                // Query AP client count from ESP-IDF using sta_list
-                // SAFETY: wifi_sta_list_t is a simple C struct with no pointers or
-                // invariants that zeroed memory would violate. All fields are integers.
-                let mut sta_list: esp_idf_sys::wifi_sta_list_t = unsafe { std::mem::zeroed() };
-                // SAFETY: esp_wifi_ap_get_sta_list() fills the provided sta_list struct
-                // with current AP client information. We pass a valid mutable reference
-                // and the struct has been properly initialized above.
-                unsafe {
-                    esp_idf_sys::esp_wifi_ap_get_sta_list(&mut sta_list);
-                }
-                status.ap_clients = sta_list.num as u8;
-            } else {
-                if let Ok(mut wifi_guard) = wifi.lock() {
-                    let connected = check_wifi_connection(&mut wifi_guard);
-                    if status.wifi_connected != connected {
-                        status.wifi_connected = connected;
-                        // Force display update when WiFi status changes
-                        if current_screen != DisplayScreen::Splash {
-                            lcd.clear_and_reset().ok();
-                        }
-                        // Update web state (non-blocking)
-                        if let Ok(mut web) = web_state.try_lock() {
-                            web.wifi_connected = connected;
-                        }
-                    }
+        // Roll up per-device response statistics by UTC day and publish them,
+        // with the per-service counters and error histogram
+        if loop_count % 100 == 0 {
+            if let Ok(mut gw) = gateway.try_lock() {
+                gw.advance_sla(clock::utc_day());
+                if let Ok(mut web) = web_state.try_lock() {
+                    web.sla_devices = Arc::new(gw.sla_report());
+                    web.service_counters = gw.service_counters();
+                    let (service_errors, untracked) = gw.top_service_errors(service_stats::MAX_ERROR_REASONS);
+                    web.service_errors = service_errors;
+                    web.service_errors_untracked = untracked;
                 }
             }
         }
 
-        // Handle button A (front big button) - cycle through screens
-        let btn_a_pressed = btn_a.is_low();
-        if !btn_a_pressed && btn_a_was_pressed {
-            // Button released - cycle to next screen
-            current_screen = current_screen.next();
-            info!("Button A - screen: {:?}", current_screen);
-            lcd.clear_and_reset().ok();
-            if current_screen == DisplayScreen::Splash {
-                lcd.show_splash_screen().ok();
+        // Sample the free heap into the blackbox and shed optional features
+        // while it is low
+        if blackbox_heap_at.elapsed() >= blackbox::HEAP_SAMPLE_INTERVAL {
+            blackbox_heap_at = std::time::Instant::now();
+            let free_heap = health::HealthReport::snapshot().free_heap;
+            blackbox::record_heap(free_heap);
+            if governor.update(free_heap) {
+                let shed = governor.shed_features();
+                if shed.is_empty() {
+                    events::record(EventCategory::System, Severity::Info, &format!("Low memory cleared ({} bytes free), all features restored", free_heap));
+                } else {
+                    let names: Vec<&str> = shed.iter().map(|f| f.as_str()).collect();
+                    events::record(EventCategory::System, Severity::Warning, &format!("Low memory ({} bytes free), shed: {}", free_heap, names.join(", ")));
+                }
+                if let Ok(mut gw) = gateway.lock() {
+                    gw.set_top_talkers_enabled(!governor.is_shed(Feature::TopTalkers));
+                }
+                if let Ok(mut web) = web_state.lock() {
+                    if governor.is_shed(Feature::FrameDebug) {
+                        web.last_rx_frames = Arc::default();
+                    }
+                    web.shed_features = shed;
+                }
             }
         }
-        btn_a_was_pressed = btn_a_pressed;
 
-        // Handle button B (side) - toggle AP/Station mode
-        let btn_b_pressed = btn_b.is_low();
-        if btn_b_pressed && !btn_b_was_pressed {
-            info!("Button B pressed - toggling WiFi mode");
+        // Spill new event log warnings to NVS, at most once a minute
+        if loop_count % 100 == 0
+            && config.event_spill_enabled
+            && event_spill_at.elapsed() >= events::SPILL_INTERVAL
+            && events::with_log(|log| log.take_spill_pending()).unwrap_or(false)
+        {
+            event_spill_at = std::time::Instant::now();
+            spill_events(&nvs_for_stats);
+        }
 
-            // Toggle AP mode
-            let new_ap_mode = !AP_MODE_ACTIVE.load(Ordering::SeqCst);
+        // Publish learned device bindings as the local Device_Address_Binding
+        if loop_count % 100 == 0 && config.local_device_enabled {
+            if let Ok(gw) = gateway.try_lock() {
+                let bindings = gw.device_bindings()
+                    .into_iter()
+                    .map(|b| AddressBinding { device_instance: b.device_instance, network: b.network, mac: b.mac })
+                    .collect();
+                drop(gw);
+                local_device.set_address_bindings(bindings);
+            }
+        }
 
-            if new_ap_mode {
-                // Switch to AP mode
-                info!("Switching to AP mode...");
-                if let Ok(mut wifi_guard) = wifi.lock() {
-                    match switch_to_ap_mode(&mut wifi_guard, &config.ap_ssid, &config.ap_password) {
-                        Ok(ap_ip_str) => {
-                            AP_MODE_ACTIVE.store(true, Ordering::SeqCst);
-                            WIFI_CONNECTED.store(false, Ordering::SeqCst);
-                            status.ap_mode_active = true;
-                            status.wifi_connected = false;
-                            status.ip_address = ap_ip_str.clone();
-                            status.ap_ip = ap_ip_str.clone();
-
-                            // Update gateway's local IP for AP mode
-                            if let Ok(mut gw) = gateway.lock() {
-                                if let Ok(ap_ip) = ap_ip_str.parse::<std::net::Ipv4Addr>() {
-                                    let ap_mask = std::net::Ipv4Addr::new(255, 255, 255, 0);
-                                    gw.set_local_ip(ap_ip, ap_mask);
-                                }
+        // COV notifications for subscriptions to the gateway's own objects, sent
+        // back the way the SubscribeCOV came in
+        if loop_count % 10 == 0 && config.local_device_enabled {
+            for (subscriber, apdu) in local_device.take_cov_notifications(std::time::Instant::now()) {
+                let npdu = Npdu { destination: subscriber.source.clone(), ..Npdu::local(&apdu) }.encode();
+                match subscriber.link {
+                    LinkAddress::Mstp(mac) => {
+                        if let Ok(mut driver) = mstp_driver.lock() {
+                            if let Err(e) = driver.send_frame(&npdu, mac, false) {
+                                warn!("Failed to queue COV notification for {}: {}", subscriber, e);
                             }
-
-                            info!("AP mode activated: SSID={}, IP={}", config.ap_ssid, ap_ip_str);
-                        }
-                        Err(e) => {
-                            error!("Failed to switch to AP mode: {}", e);
                         }
                     }
-                }
-            } else {
-                // Switch back to Station mode
-                info!("Switching back to Station mode...");
-                if let Ok(mut wifi_guard) = wifi.lock() {
-                    match switch_to_sta_mode(&mut wifi_guard, &config.wifi_ssid, &config.wifi_password) {
-                        Ok(ip) => {
-                            AP_MODE_ACTIVE.store(false, Ordering::SeqCst);
-                            WIFI_CONNECTED.store(true, Ordering::SeqCst);
-                            status.ap_mode_active = false;
-                            status.wifi_connected = true;
-                            status.ip_address = ip.clone();
-
-                            // Update gateway's local IP for station mode
-                            if let Ok(mut gw) = gateway.lock() {
-                                if let Ok(sta_ip) = ip.parse::<std::net::Ipv4Addr>() {
-                                    let sta_mask = std::net::Ipv4Addr::new(255, 255, 255, 0);
-                                    gw.set_local_ip(sta_ip, sta_mask);
-                                }
+                    LinkAddress::Ip(address) => {
+                        if let Ok(mut gw) = gateway.lock() {
+                            if let Err(e) = gw.send_on_ip(&npdu, address) {
+                                warn!("Failed to send COV notification to {}: {}", subscriber, e);
                             }
-
-                            info!("Station mode activated");
-                        }
-                        Err(e) => {
-                            error!("Failed to switch to Station mode: {}", e);
-                            // Stay in AP mode if switching fails
                         }
                     }
                 }
             }
-
-            // Force display update
-            lcd.clear_and_reset().ok();
         }
-        btn_b_was_pressed = btn_b_pressed;
 
-        // Handle button C (power) - jump to Status screen
-        let btn_c_pressed = btn_c.is_low();
-        if btn_c_pressed && !btn_c_was_pressed {
-            info!("Button C pressed - go to Status screen");
-            current_screen = DisplayScreen::Status;
-            lcd.clear_and_reset().ok();
+        // Write-protect jumper: debounce and propagate changes
+        if loop_count % 10 == 0 {
+            if let Some(debounce) = write_protect_debounce.as_mut() {
+                // SAFETY: reading the level of the input configured at startup
+                let fitted = unsafe { esp_idf_svc::sys::gpio_get_level(config.write_protect_pin as i32) == 0 };
+                if let Some(locked) = debounce.sample(fitted) {
+                    write_protect::set_locked(locked);
+                    gateway.lock().unwrap().set_write_protected(locked);
+                    local_device.set_write_protected(locked);
+                    let message = if locked {
+                        "Write-protect jumper fitted, configuration locked"
+                    } else {
+                        "Write-protect jumper removed, configuration unlocked"
+                    };
+                    info!("{}", message);
+                    events::record(EventCategory::Config, Severity::Info, message);
+                }
+            }
         }
-        btn_c_was_pressed = btn_c_pressed;
 
-        // Update display based on current screen
-        match current_screen {
-            DisplayScreen::Status => {
-                if let Err(e) = lcd.update_status(&status) {
-                    warn!("Failed to update status display: {}", e);
+        // Redundancy pair: heartbeats both ways, watch the shared station on MS/TP,
+        // take over or hand back
+        if loop_count % 10 == 0 {
+            if let Some((pair, socket, peer)) = failover_pair.as_mut() {
+                let now = std::time::Instant::now();
+                let mut buffer = [0u8; 32];
+                while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+                    match Heartbeat::decode(&buffer[..len]) {
+                        Some(heartbeat) if from.ip() == peer.ip() => pair.on_heartbeat(heartbeat, now),
+                        _ => debug!("Ignoring redundancy datagram from {}", from),
+                    }
+                }
+                if let Some(heard) = mstp_driver.lock().ok().and_then(|mut d| d.take_station_heard()) {
+                    pair.on_station_heard(heard);
+                }
+
+                let transition = pair.poll(now);
+                if let Some(transition) = transition {
+                    // Standing down goes quiet before the heartbeat below says so
+                    let active = transition == Transition::TakeOver;
+                    failover::set_standing_by(!active);
+                    mstp_driver.lock().unwrap().set_listen_only(!active || mstp_no_free_address);
+                    gateway.lock().unwrap().set_standby(!active);
+                    let (severity, message) = match (transition, pair.role()) {
+                        (Transition::TakeOver, FailoverRole::Standby) => {
+                            (Severity::Warning, "Redundancy: primary gone, standby took over")
+                        }
+                        (Transition::TakeOver, _) => (Severity::Info, "Redundancy: primary active"),
+                        (Transition::StandDown, _) => (Severity::Info, "Redundancy: standby handed back to the primary"),
+                    };
+                    info!("{}", message);
+                    events::record(EventCategory::System, severity, message);
+                }
+                if transition.is_some() || now.duration_since(failover_heartbeat_at) >= HEARTBEAT_INTERVAL {
+                    failover_heartbeat_at = now;
+                    if let Err(e) = socket.send_to(&pair.heartbeat().encode(), *peer) {
+                        debug!("Redundancy heartbeat to {} failed: {}", peer, e);
+                    }
+                }
+                if let Ok(mut web) = web_state.try_lock() {
+                    web.failover = Some(pair.status(now));
                 }
             }
-            DisplayScreen::Connection => {
-                if let Err(e) = lcd.update_connection(&status) {
-                    warn!("Failed to update connection display: {}", e);
+        }
+
+        // Site inventory: start/stop from the portal, publish progress and the report
+        if loop_count % 10 == 0 {
+            if let Ok(mut gw) = gateway.try_lock() {
+                if let Ok(mut web) = web_state.try_lock() {
+                    if web.inventory_requested {
+                        let targets: Vec<(u32, u8)> = web.discovered_devices
+                            .iter()
+                            .map(|d| (d.device_instance, d.mac_address))
+                            .collect();
+                        if gw.start_inventory(&targets) {
+                            web.inventory_requested = false;
+                        }
+                    }
+                    if web.inventory_cancel_requested {
+                        web.inventory_cancel_requested = false;
+                        web.inventory_requested = false;
+                        gw.cancel_inventory();
+                    }
+                    web.inventory_progress = gw.inventory_progress();
+                    if let Some(devices) = gw.take_finished_inventory() {
+                        web.inventory_report = Arc::new(devices);
+                    }
+                    if web.device_names_refresh_requested {
+                        web.device_names_refresh_requested = false;
+                        gw.refresh_device_names();
+                    }
+                    if loop_count % 100 == 0 {
+                        web.device_names = Arc::new(gw.device_names());
+                        web.device_names_pending = gw.device_names_pending();
+                    }
+                    if let Some((target, kind)) = web.router_query_request.take() {
+                        if let Err(e) = gw.start_router_query(target, kind) {
+                            warn!("Router query to {} failed: {:?}", target, e);
+                        }
+                    }
+                    web.router_query = gw.router_query().cloned();
+                    if let Some(call) = web.client_call_request.take() {
+                        gw.start_client_call(call);
+                    }
+                    if let Some(outcome) = gw.take_client_outcome() {
+                        web.client_outcome = Some(outcome);
+                    }
                 }
             }
-            DisplayScreen::APConfig => {
-                if let Err(e) = lcd.update_ap_config(&status) {
-                    warn!("Failed to update AP config display: {}", e);
+        }
+
+        // Service BDT edits and paired peers from the web portal and keep its view in sync
+        if let Ok(mut gw) = gateway.try_lock() {
+            if let Ok(mut web) = web_state.try_lock() {
+                let mut changed = false;
+                if let Some((address, mask)) = web.bdt_add_request.take() {
+                    gw.add_bdt_entry(address, mask);
+                    changed = true;
+                }
+                if let Some(address) = web.bdt_remove_request.take() {
+                    gw.remove_bdt_entry(address);
+                    changed = true;
+                }
+                if web.bdt_clear_request {
+                    web.bdt_clear_request = false;
+                    gw.clear_bdt();
+                    changed = true;
+                }
+                if let Some((peer, verify)) = web.pair_install_request.take() {
+                    gw.add_bdt_entry(peer.bip_address, PAIR_MASK);
+                    gw.add_static_route(peer.mstp_network, RouteNextHop::Ip(peer.bip_address));
+                    if verify {
+                        if let Err(e) = gw.start_pair_probe(peer.bip_address, peer.mstp_network) {
+                            warn!("Pairing test broadcast failed: {:?}", e);
+                        }
+                    }
+                    web.static_routes = gw.get_static_routes();
+                    changed = true;
+                }
+                if let (Some(pairing), Some(probe)) = (web.pairing.as_mut(), gw.pair_probe()) {
+                    let before = pairing.stage.clone();
+                    pairing.verify(probe, std::time::Instant::now());
+                    if pairing.stage != before {
+                        let severity = if pairing.stage == PairingStage::Paired { Severity::Info } else { Severity::Warning };
+                        info!("Pairing with {}: {}", pairing.peer_ip, pairing.stage);
+                        events::record(EventCategory::Peer, severity, &format!("Pairing with {}: {}", pairing.peer_ip, pairing.stage));
+                    }
+                }
+                if changed || loop_count % 100 == 0 {
+                    web.bdt_entries = gw.get_bdt_entries();
                 }
             }
-            DisplayScreen::Splash => {
-                // Splash screen is static, no updates needed
+        }
+
+        // Service FDT edits from the web portal and keep its view in sync
+        if let Ok(mut gw) = gateway.try_lock() {
+            if let Ok(mut web) = web_state.try_lock() {
+                let mut changed = false;
+                if let Some(address) = web.fdt_remove_request.take() {
+                    gw.remove_foreign_device(address);
+                    changed = true;
+                }
+                if let Some(accept) = web.fd_registrations_request.take() {
+                    gw.set_accept_fd_registrations(accept);
+                    changed = true;
+                }
+                if changed || loop_count % 100 == 0 {
+                    web.foreign_devices = Arc::new(gw.foreign_device_summaries());
+                    web.fd_registrations_enabled = gw.accept_fd_registrations();
+                }
             }
         }
 
-        // Small delay to prevent busy-waiting
-        // Reduced from 100ms to 10ms to be more responsive to scan requests
-        // while still preventing excessive CPU usage
+        // Service address binding edits from the web portal and keep its view in sync
+        if let Ok(mut gw) = gateway.try_lock() {
+            if let Ok(mut web) = web_state.try_lock() {
+                let mut changed = false;
+                if let Some(edit) = web.binding_edit_request.take() {
+                    if !gw.apply_binding_edit(edit) {
+                        info!("Address binding {:?} no longer learned", edit);
+                    }
+                    changed = true;
+                }
+                if changed || loop_count % 100 == 0 {
+                    web.mstp_to_ip_bindings = Arc::new(gw.mstp_to_ip_bindings());
+                    web.ip_to_mstp_bindings = Arc::new(gw.ip_to_mstp_bindings());
+                }
+            }
+        }
+
+        // Service protocol analysis switches from the web portal and keep its view in sync
+        if let Ok(mut gw) = gateway.try_lock() {
+            if let Ok(mut web) = web_state.try_lock() {
+                let mut changed = false;
+                if let Some(enabled) = web.analyzer_request.take() {
+                    gw.set_protocol_analysis(enabled);
+                    changed = true;
+                }
+                if changed || loop_count % 100 == 0 {
+                    web.analyzer_started = gw.protocol_analysis_started();
+                    web.analyzer_reports = Arc::new(gw.analyzer_reports());
+                }
+            }
+        }
+
+        // Service transaction aborts from the web portal and keep its view in sync
+        if let Ok(mut gw) = gateway.try_lock() {
+            if let Ok(mut web) = web_state.try_lock() {
+                let mut changed = false;
+                if let Some((invoke_id, dest_mac)) = web.transaction_abort_request.take() {
+                    if !gw.abort_transaction(invoke_id, dest_mac) {
+                        info!("Transaction invoke_id={} dest={} already finished", invoke_id, dest_mac);
+                    }
+                    changed = true;
+                }
+                if changed || loop_count % 100 == 0 {
+                    web.transactions = gw.transaction_summaries();
+                    web.reassemblies = gw.reassembly_summaries();
+                }
+            }
+        }
+
+        // Service port enable/disable from the web portal; Out_Of_Service follows the port state
+        if let Ok(mut gw) = gateway.try_lock() {
+            if let Ok(mut web) = web_state.try_lock() {
+                if let Some(enabled) = web.mstp_port_enable_request.take() {
+                    gw.set_mstp_port_enabled(enabled);
+                    local_device.set_port_out_of_service(MSTP_PORT_INSTANCE, !enabled);
+                }
+                if let Some(enabled) = web.ip_port_enable_request.take() {
+                    gw.set_ip_port_enabled(enabled);
+                    local_device.set_port_out_of_service(IP_PORT_INSTANCE, !enabled);
+                    local_device.set_port_out_of_service(AP_PORT_INSTANCE, !enabled);
+                }
+                web.mstp_port_enabled = gw.mstp_port_enabled();
+                web.ip_port_enabled = gw.ip_port_enabled();
+            }
+        }
+
+        // Service static route edits from the web portal and keep its view in sync
+        if let Ok(mut gw) = gateway.try_lock() {
+            if let Ok(mut web) = web_state.try_lock() {
+                let mut changed = false;
+                if let Some((network, next_hop)) = web.static_route_add_request.take() {
+                    gw.add_static_route(network, next_hop);
+                    changed = true;
+                }
+                if let Some(network) = web.static_route_remove_request.take() {
+                    gw.remove_static_route(network);
+                    changed = true;
+                }
+                if changed || loop_count % 100 == 0 {
+                    web.static_routes = gw.get_static_routes();
+                    web.unreachable_networks = gw.unreachable_networks();
+                }
+            }
+        }
+
+        // Duplicate network number detection: LCD and web warning, plus a
+        // BACnet alarm on both ports when a conflict is first detected
+        if let Ok(mut gw) = gateway.try_lock() {
+            let new_conflict = gw.take_new_network_conflict();
+            if let Ok(mut web) = web_state.try_lock() {
+                if web.network_conflict_clear_requested {
+                    web.network_conflict_clear_requested = false;
+                    gw.clear_network_conflict();
+                }
+                web.network_conflict = gw.network_conflict().cloned();
+            }
+            status.network_conflict = gw.network_conflict().map(|c| c.network);
+
+            // The alarm comes from the gateway's Device object, so transparent
+            // router mode only shows the LCD and web warning
+            if let Some(conflict) = new_conflict.filter(|_| config.local_device_enabled) {
+                let message = format!(
+                    "Duplicate network number {} also advertised by router {}",
+                    conflict.network, conflict.router
+                );
+                let alarm_apdu = local_device.build_alarm_notification(&message);
+                let alarm_npdu = Npdu::local(&alarm_apdu).encode();
+
+                if let Err(e) = gw.broadcast_on_ip(&alarm_npdu) {
+                    warn!("Failed to send duplicate network alarm on IP: {}", e);
+                }
+                drop(gw);
+                if let Ok(mut driver) = mstp_driver.lock() {
+                    if let Err(e) = driver.send_frame(&alarm_npdu, 0xFF, false) {
+                        warn!("Failed to queue duplicate network alarm on MS/TP: {}", e);
+                    }
+                }
+            }
+        }
+
+        // Sync runtime-writable Device properties between the web portal and BACnet
+        if let Ok(mut web) = web_state.try_lock() {
+            if web.site_info_update_requested {
+                web.site_info_update_requested = false;
+                local_device.set_site_info(DeviceSiteInfo {
+                    location: web.config.device_location.clone(),
+                    description: web.config.device_description.clone(),
+                    serial_number: web.config.device_serial_number.clone(),
+                });
+            } else if local_device.take_site_info_written() {
+                // Written via WriteProperty - reflect in the portal and persist right away
+                let info = local_device.site_info();
+                web.config.device_location = info.location;
+                web.config.device_description = info.description;
+                web.config.device_serial_number = info.serial_number;
+                nvs_writer.submit(NvsWrite::SiteInfo(Box::new(web.config.clone())));
+            }
+        }
+
+        // Mode change requested by button B or the hotspot auto-off
+        let mut wifi_switch: Option<WifiMode> = None;
+
+        // Station address as of this loop, when it was looked up
+        let mut station_address = None;
+
+        // Periodically check WiFi connection and attempt reconnection if needed
+        wifi_check_counter += 1;
+        let wifi_check_due = wifi_check_counter >= WIFI_CHECK_INTERVAL;
+        if wifi_check_due {
+            wifi_check_counter = 0;
+
+            // With the AP up, update client count; in STA mode, check connection
+            let ap_only = AP_MODE_ACTIVE.load(Ordering::SeqCst);
+            if ap_only || soft_ap_since.is_some() {
+                // Stations on the hotspot, joined with their DHCP leases
+                let clients = ap_clients::list();
+                status.ap_clients = clients.len() as u8;
+                status.ap_client_summary = ap_clients::summary(&clients);
+                if let Ok(mut web) = web_state.try_lock() {
+                    web.ap_clients = clients;
+                    web.ap_lease_minutes = ap_clients::lease_minutes();
+                }
+            }
+            if !ap_only {
+                if let Ok(mut wifi_guard) = wifi.lock() {
+                    let connected = check_wifi_connection(&mut wifi_guard);
+                    if connected {
+                        station_address = station_ipv4(&wifi_guard);
+                    }
+                    if status.wifi_connected != connected {
+                        status.wifi_connected = connected;
+                        if connected {
+                            wifi_reconnects += 1;
+                            events::record(EventCategory::Wifi, Severity::Info, &format!("WiFi connected to {}", config.wifi_ssid));
+                        } else {
+                            events::record(EventCategory::Wifi, Severity::Warning, "WiFi connection lost");
+                        }
+                        // Update web state (non-blocking)
+                        if let Ok(mut web) = web_state.try_lock() {
+                            web.wifi_connected = connected;
+                        }
+                        // IP link came back: re-announce on the next loop
+                        if connected {
+                            if let Ok(mut gw) = gateway.lock() {
+                                gw.request_router_announce();
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Mirror the link figures into the local device's Analog Values;
+            // a figure that is unavailable faults its object
+            hotspot_seen |= ap_only || soft_ap_since.is_some();
+            let link = if !ap_only && status.wifi_connected { station_link() } else { None };
+            local_device.set_wifi_diagnostics(WifiDiagnostics {
+                rssi: link.map(|(rssi, _)| rssi),
+                channel: link.map(|(_, channel)| channel),
+                reconnects: (!config.wifi_ssid.is_empty()).then_some(wifi_reconnects),
+                hotspot_clients: hotspot_seen.then_some(status.ap_clients),
+            });
+
+            // APSTA hotspot auto-off, held off while a client is connected
+            let ap_timeout = Duration::from_secs(u64::from(config.ap_timeout_minutes) * 60);
+            if config.ap_timeout_minutes > 0
+                && status.ap_clients == 0
+                && soft_ap_since.is_some_and(|t| t.elapsed() >= ap_timeout)
+            {
+                info!("Hotspot up for {} minutes - turning it off", config.ap_timeout_minutes);
+                wifi_switch = Some(WifiMode::Station);
+            }
+        }
+
+        // DHCP assigned an address: look it up now
+        if STATION_IP_ASSIGNED.swap(false, Ordering::SeqCst) && !AP_MODE_ACTIVE.load(Ordering::SeqCst) {
+            station_address = wifi.lock().ok().and_then(|w| station_ipv4(&w));
+        }
+
+        // B/IP socket supervision: re-bind after persistent errors, or when the
+        // station address changed underneath us (new DHCP lease, netif restart).
+        // A new address goes into the Network Port object and is announced.
+        let moved_to = station_address.filter(|(ip, _)| gateway.lock().is_ok_and(|gw| gw.local_ip() != *ip));
+        if let Some((ip, mask)) = moved_to {
+            info!("Station address changed to {} (mask {})", ip, mask);
+            events::record(EventCategory::Ip, Severity::Info, &format!("Station address changed to {}", ip));
+            local_device.set_ip_address(IP_PORT_INSTANCE, ip.octets(), mask.octets());
+            status.ip_address = ip.to_string();
+            if let Ok(mut web) = web_state.try_lock() {
+                web.ip_address = ip.to_string();
+            }
+        }
+        if moved_to.is_some() || (wifi_check_due && bip_socket.needs_rebind()) {
+            recover_bip_socket(&bip_socket, &gateway, &config, &mut bip_multicast_iface, moved_to);
+        }
+        if moved_to.is_some() && config.local_device_enabled {
+            // I-Am-Router-To-Network follows from the gateway's announce
+            // schedule; the device's own I-Am goes out on IP now
+            let iam_apdu = local_device.build_i_am();
+            let iam_npdu = Npdu::local(&iam_apdu).encode();
+            if let Ok(mut gw) = gateway.lock() {
+                if let Err(e) = gw.broadcast_on_ip(&iam_npdu) {
+                    warn!("Failed to broadcast I-Am on IP after address change: {}", e);
+                }
+            }
+        }
+
+        // Handle button A (front big button) - cycle through screens
+        let btn_a_pressed = btn_a.is_low();
+        if !btn_a_pressed && btn_a_was_pressed {
+            // Button released - cycle to next screen
+            current_screen = current_screen.next();
+            info!("Button A - screen: {:?}", current_screen);
+        }
+        btn_a_was_pressed = btn_a_pressed;
+
+        // Handle button B (side) - toggle AP/Station mode, or the hotspot
+        // alongside the station when APSTA is configured
+        let btn_b_pressed = btn_b.is_low();
+        if btn_b_pressed && !btn_b_was_pressed {
+            info!("Button B pressed - toggling WiFi mode");
+            wifi_switch = Some(if config.ap_with_sta {
+                if soft_ap_since.is_some() { WifiMode::Station } else { WifiMode::StationWithAp }
+            } else if AP_MODE_ACTIVE.load(Ordering::SeqCst) {
+                WifiMode::Station
+            } else {
+                WifiMode::AccessPoint
+            });
+        }
+        btn_b_was_pressed = btn_b_pressed;
+
+        if let Some(mode) = wifi_switch {
+            info!("Switching to {} mode...", mode.as_str());
+            if let Ok(mut wifi_guard) = wifi.lock() {
+                // Primary IP, plus the AP's IP when it runs alongside the station
+                let switched = match mode {
+                    WifiMode::AccessPoint => switch_to_ap_mode(&mut wifi_guard, &config.ap_ssid, &config.ap_password)
+                        .map(|ap_ip| (ap_ip, None)),
+                    WifiMode::Station => switch_to_sta_mode(&mut wifi_guard, &config.wifi_ssid, &config.wifi_password)
+                        .map(|ip| (ip, None)),
+                    WifiMode::StationWithAp => switch_to_apsta_mode(
+                        &mut wifi_guard,
+                        &config.wifi_ssid,
+                        &config.wifi_password,
+                        &config.ap_ssid,
+                        &config.ap_password,
+                    )
+                    .map(|(ip, ap_ip)| (ip, Some(ap_ip))),
+                };
+                match switched {
+                    Ok((ip, apsta_ap_ip)) => {
+                        let ap_only = matches!(mode, WifiMode::AccessPoint);
+                        AP_MODE_ACTIVE.store(ap_only, Ordering::SeqCst);
+                        WIFI_CONNECTED.store(!ap_only, Ordering::SeqCst);
+                        status.ap_mode_active = ap_only;
+                        status.wifi_connected = !ap_only;
+                        status.ip_address = ip.clone();
+                        if ap_only {
+                            status.ap_ip = ip.clone();
+                        }
+                        if let Some(ref ap_ip) = apsta_ap_ip {
+                            status.ap_ip = ap_ip.clone();
+                        }
+                        soft_ap_since = apsta_ap_ip.is_some().then(std::time::Instant::now);
+                        status.ap_clients = 0;
+                        status.ap_client_summary.clear();
+                        if let Ok(mut web) = web_state.lock() {
+                            web.ap_clients.clear();
+                        }
+
+                        // Update gateway's local IP for the new mode; the AP
+                        // subnet is served too while it runs alongside the station
+                        if let Ok(mut gw) = gateway.lock() {
+                            let mask = std::net::Ipv4Addr::new(255, 255, 255, 0);
+                            if let Ok(local_ip) = ip.parse::<std::net::Ipv4Addr>() {
+                                gw.set_local_ip(local_ip, mask);
+                                let interface = Some((local_ip.octets(), mask.octets()));
+                                local_device.set_station_interface(if ap_only { None } else { interface });
+                                if ap_only {
+                                    local_device.set_hotspot_interface(interface);
+                                }
+                                if config.bip_multicast_enabled {
+                                    let joined = bip_socket.current().is_some_and(|socket| {
+                                        rejoin_bip_multicast(&socket, config.bip_multicast_group, &mut bip_multicast_iface, local_ip)
+                                    });
+                                    gw.set_multicast_group(joined.then_some(config.bip_multicast_group));
+                                }
+                            }
+                            let ap_ip = apsta_ap_ip.as_deref().and_then(|ap_ip| ap_ip.parse::<std::net::Ipv4Addr>().ok());
+                            gw.set_ap_interface(ap_ip.map(|ap_ip| (ap_ip, mask)));
+                            if !ap_only {
+                                local_device.set_hotspot_interface(ap_ip.map(|ap_ip| (ap_ip.octets(), mask.octets())));
+                            }
+                        }
+
+                        info!("{} mode activated: IP={}", mode.as_str(), ip);
+                        events::record(
+                            EventCategory::AccessPoint,
+                            Severity::Info,
+                            &format!("Switched to {} mode (IP {})", mode.as_str(), ip),
+                        );
+                    }
+                    Err(e) => {
+                        // Stay in the current mode if switching fails
+                        error!("Failed to switch to {} mode: {}", mode.as_str(), e);
+                        events::record(
+                            EventCategory::AccessPoint,
+                            Severity::Error,
+                            &format!("Failed to switch to {} mode", mode.as_str()),
+                        );
+                    }
+                }
+            }
+        }
+
+        // Scheduled configuration change: store or drop it as the portal
+        // asks, apply it when due, then keep it or roll it back by the ring
+        if loop_count % 100 == 0 {
+            if let Ok(mut web) = web_state.try_lock() {
+                if let Some(change) = web.schedule_request.take() {
+                    match ScheduledChangePersistence::save(nvs_for_stats.clone(), &change) {
+                        Ok(()) => {
+                            events::record(
+                                EventCategory::Config,
+                                Severity::Info,
+                                &format!("Configuration change scheduled: {}", change.summary()),
+                            );
+                            scheduled_change = Some(change);
+                        }
+                        Err(e) => warn!("Failed to save scheduled change: {}", e),
+                    }
+                }
+                if std::mem::take(&mut web.schedule_cancel_requested)
+                    && scheduled_change.as_ref().is_some_and(|c| c.stage == ScheduleStage::Pending)
+                {
+                    if let Err(e) = ScheduledChangePersistence::clear(nvs_for_stats.clone()) {
+                        warn!("Failed to clear scheduled change: {}", e);
+                    }
+                    events::record(EventCategory::Config, Severity::Info, "Scheduled configuration change cancelled");
+                    scheduled_change = None;
+                }
+                web.scheduled_change = scheduled_change.clone();
+                web.schedule_rollback_in = ring_watch.as_ref().map(|w| w.remaining(std::time::Instant::now()).as_secs());
+            }
+
+            let now_utc = clock::utc_secs();
+            // A due change waits while the write-protect jumper is fitted
+            let due = |c: &&mut ScheduledChange| !write_protect::is_locked() && now_utc.is_some_and(|now| c.is_due(now));
+            if let Some(change) = scheduled_change.as_mut().filter(due) {
+                apply_scheduled_change(change, &nvs_for_stats, &nvs_writer, &gateway, &display_state, config.event_spill_enabled);
+                // Still here: the change was dropped
+                scheduled_change = None;
+            }
+            if let Some(watch) = ring_watch.as_mut() {
+                match watch.update(ring_up, std::time::Instant::now()) {
+                    RingVerdict::Waiting => {}
+                    RingVerdict::Stable => {
+                        info!("MS/TP ring stable, keeping the scheduled change");
+                        events::record(EventCategory::Config, Severity::Info, "Scheduled configuration change kept, ring stable");
+                        if let Err(e) = ScheduledChangePersistence::clear(nvs_for_stats.clone()) {
+                            warn!("Failed to clear scheduled change: {}", e);
+                        }
+                        scheduled_change = None;
+                        ring_watch = None;
+                    }
+                    RingVerdict::Failed => {
+                        if let Some(change) = &scheduled_change {
+                            roll_back_scheduled_change(change, &nvs_for_stats, &nvs_writer, &gateway, &display_state, config.event_spill_enabled);
+                        }
+                    }
+                }
+            }
+
+            status.schedule_countdown = match (&scheduled_change, &ring_watch) {
+                (_, Some(watch)) => format!(
+                    "Rollback in {}",
+                    schedule::format_countdown(watch.remaining(std::time::Instant::now()).as_secs())
+                ),
+                (Some(_), None) if write_protect::is_locked() => "Change held by write-protect jumper".to_string(),
+                (Some(change), None) => match now_utc {
+                    Some(now) => format!("Change in {}", schedule::format_countdown(change.apply_at.saturating_sub(now))),
+                    None => "Change waits for clock".to_string(),
+                },
+                (None, None) => String::new(),
+            };
+        }
+
+        // Staged configuration change: keep routing with the running
+        // configuration while listening for conflicts, then commit or report
+        if loop_count % 100 == 0 {
+            if let Some(mut request) = web_state.try_lock().ok().and_then(|mut web| web.staged_request.take()) {
+                info!("Staged configuration change: listening {}s for conflicts", STAGING_LISTEN.as_secs());
+                let queries = match gateway.lock() {
+                    Ok(mut gw) => gw.start_network_probe(&request.check.networks()).unwrap_or_else(|e| {
+                        warn!("Failed to send network probe: {}", e);
+                        Vec::new()
+                    }),
+                    Err(_) => Vec::new(),
+                };
+                if let Ok(mut driver) = mstp_driver.lock() {
+                    for npdu in &queries {
+                        if let Err(e) = driver.send_frame(npdu, 0xFF, false) {
+                            warn!("Failed to queue Who-Is-Router-To-Network on MS/TP: {}", e);
+                        }
+                    }
+                    // Masters already in the ring count as heard
+                    let masters = driver.get_stats().discovered_masters;
+                    let known: Vec<u8> = (0..128u8).filter(|&mac| masters & (1u128 << mac) != 0).collect();
+                    request.record_stations(&known);
+                    driver.take_heard_sources();
+                }
+                staged = Some(request);
+            }
+
+            let stage = staged.as_mut().map(|staging| {
+                if let Ok(mut gw) = gateway.try_lock() {
+                    for (router, networks) in gw.take_network_probe_answers() {
+                        staging.record_router(&router, &networks);
+                    }
+                }
+                if let Ok(mut driver) = mstp_driver.try_lock() {
+                    staging.record_stations(&driver.take_heard_sources());
+                }
+                staging.poll(std::time::Instant::now())
+            });
+            if stage.is_some_and(|stage| stage != StagingStage::Listening) {
+                if let Ok(mut gw) = gateway.lock() {
+                    gw.stop_network_probe();
+                }
+            }
+            if let Some(staging) = &staged {
+                if let Ok(mut web) = web_state.lock() {
+                    web.staged_validation = Some(staging.clone());
+                }
+            }
+            match (stage, staged.take()) {
+                (Some(StagingStage::Passed), Some(staging)) => {
+                    commit_staged_change(&staging, &web_state, &nvs_for_stats, &nvs_writer, &gateway, &display_state, config.event_spill_enabled);
+                }
+                (Some(StagingStage::Refused), Some(staging)) => {
+                    let conflicts: Vec<String> = staging.conflicts.iter().map(|c| c.to_string()).collect();
+                    warn!("Staged configuration change refused: {}", conflicts.join("; "));
+                    events::record(
+                        EventCategory::Config,
+                        Severity::Warning,
+                        &format!("Staged configuration change refused: {}", conflicts.join("; ")),
+                    );
+                }
+                (_, listening) => staged = listening,
+            }
+        }
+
+        // Handle button C (power) - jump to Status screen, hold to power down
+        let btn_c_pressed = btn_c.is_low();
+        if btn_c_pressed && !btn_c_was_pressed {
+            info!("Button C pressed - go to Status screen");
+            current_screen = DisplayScreen::Status;
+            if let Ok(mut shared) = display_state.lock() {
+                shared.screen = current_screen;
+                shared.full_redraw = true;
+            }
+            btn_c_pressed_at = Some(std::time::Instant::now());
+        } else if !btn_c_pressed {
+            btn_c_pressed_at = None;
+        }
+        btn_c_was_pressed = btn_c_pressed;
+        if btn_c_pressed_at.is_some_and(|t| t.elapsed() >= shutdown::POWER_BUTTON_HOLD) {
+            info!("Button C held - powering down");
+            shutdown::request(ShutdownReason::PowerButton);
+        }
+
+        // Brown-out or power button: announce, flush (button only) and stop
+        if let Some(reason) = shutdown::requested() {
+            shut_down(
+                reason,
+                &gateway,
+                &mstp_driver,
+                &display_state,
+                &ip_tx_stats,
+                lifetime_stats.as_mut(),
+                config.event_spill_enabled,
+                &nvs_for_stats,
+                &nvs_writer,
+            );
+        }
+
+        // Publish the display snapshot every 100ms; the render task repaints
+        // whatever changed on its own cadence
+        if loop_count % 10 == 0 {
+            health::set_wifi_up(status.wifi_connected || status.ap_mode_active);
+            if let Ok(mut shared) = display_state.try_lock() {
+                shared.screen = current_screen;
+                if shared.status != status {
+                    shared.status.clone_from(&status);
+                }
+            }
+        }
+
+        // Publish the portal's snapshot every 250ms while someone is reading
+        // it, so page rendering never waits on the state lock
+        if loop_count % 25 == 0 {
+            if let Ok(web) = web_state.try_lock() {
+                publish_snapshot(&web);
+            }
+        }
+
+        // Small delay to prevent busy-waiting
+        // Reduced from 100ms to 10ms to be more responsive to scan requests
+        // while still preventing excessive CPU usage
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Shut down cleanly: tell routing peers we are going away, write the
+/// network tables (plus lifetime statistics and the event log) back to NVS
+/// and say why on the LCD. A power button press ends in deep sleep (button C
+/// wakes the gateway); a brown-out restarts, so the gateway comes back by
+/// itself once the supply recovers. A brown-out skips the NVS writes, since
+/// flash written on a failing supply can be left corrupt.
+fn shut_down(
+    reason: ShutdownReason,
+    gateway: &Mutex<BacnetGateway>,
+    mstp_driver: &Mutex<MstpDriver<'static>>,
+    display_state: &Mutex<DisplayState>,
+    ip_tx_stats: &TxQueueStats,
+    lifetime_stats: Option<&mut LifetimeStats>,
+    spill_event_log: bool,
+    nvs: &EspDefaultNvsPartition,
+    nvs_writer: &NvsWriter,
+) -> ! {
+    warn!("Shutting down ({})", reason.as_str());
+    events::record(EventCategory::System, Severity::Warning, &format!("Shutting down ({})", reason.as_str()));
+
+    // Announcements first: on a brown-out they are what matters most
+    let mstp_busy = gateway.lock().ok().map(|mut gw| gw.announce_going_down());
+    if let Some(npdu) = mstp_busy {
+        if let Ok(mut driver) = mstp_driver.lock() {
+            if let Err(e) = driver.send_frame(&npdu, 0xFF, false) {
+                warn!("Failed to queue Router-Busy-To-Network on MS/TP: {}", e);
+            }
+        }
+    }
+    if reason == ShutdownReason::BrownOut {
+        warn!("Brown-out - skipping NVS writes");
+    } else {
+        if let Ok(mut gw) = gateway.lock() {
+            gw.flush_to_nvs();
+        }
+        if !nvs_writer.flush(nvs_writer::FLUSH_TIMEOUT) {
+            warn!("Queued NVS writes did not finish before shutdown");
+        }
+        if let Some(stats) = lifetime_stats {
+            stats.record_shutdown(RebootReason::PowerButton);
+            if let Err(e) = LifetimeStatsPersistence::save(nvs.clone(), stats) {
+                warn!("Failed to save lifetime stats: {}", e);
+            }
+        }
+        if spill_event_log {
+            spill_events(nvs);
+        }
+    }
+    if let Ok(mut shared) = display_state.lock() {
+        shared.message = Some(match reason {
+            ShutdownReason::BrownOut => ("Power Low", "Restarting..."),
+            ShutdownReason::PowerButton => ("Shutting Down", "Press button C to wake"),
+        });
+    }
+
+    // Give the transmit tasks (and the render task) a moment to finish
+    let started = std::time::Instant::now();
+    while started.elapsed() < shutdown::ANNOUNCE_DRAIN {
+        let mstp_idle = mstp_driver.lock().map(|d| d.get_stats().send_queue_len == 0).unwrap_or(true);
+        if mstp_idle && ip_tx_stats.depth.load(Ordering::Relaxed) == 0 {
+            break;
+        }
         thread::sleep(Duration::from_millis(10));
     }
+
+    match reason {
+        ShutdownReason::BrownOut => {
+            info!("Shutdown complete - restarting");
+            // SAFETY: esp_restart() is always safe to call on ESP32 - it performs a
+            // software reset.
+            unsafe { esp_idf_svc::sys::esp_restart(); }
+        }
+        ShutdownReason::PowerButton => {
+            // Let the LCD show the message, and wait for the button to be
+            // released so it doesn't wake us straight away
+            thread::sleep(display::RENDER_INTERVAL);
+            // SAFETY: GPIO35 is configured as an input for button C; reading
+            // its level and arming ext0 wakeup on it do not disturb the driver.
+            unsafe {
+                while esp_idf_svc::sys::gpio_get_level(esp_idf_svc::sys::gpio_num_t_GPIO_NUM_35) == 0 {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                esp_idf_svc::sys::esp_sleep_enable_ext0_wakeup(esp_idf_svc::sys::gpio_num_t_GPIO_NUM_35, 0);
+                info!("Shutdown complete - entering deep sleep");
+                esp_idf_svc::sys::esp_deep_sleep_start();
+            }
+        }
+    }
+    // Neither call returns
+    #[allow(unreachable_code)]
+    loop { thread::sleep(Duration::from_secs(1)); }
+}
+
+/// Apply a scheduled change that has fallen due: keep the previous values of
+/// the fields it sets, save the changed configuration and restart. Returns
+/// only if the change could not be applied, after forgetting it.
+fn apply_scheduled_change(
+    change: &mut ScheduledChange,
+    nvs: &EspDefaultNvsPartition,
+    nvs_writer: &NvsWriter,
+    gateway: &Mutex<BacnetGateway>,
+    display_state: &Mutex<DisplayState>,
+    spill_event_log: bool,
+) {
+    // Queued writes first, so none of them lands on top of the change
+    nvs_writer.flush(nvs_writer::FLUSH_TIMEOUT);
+    let result = GatewayConfig::load_from_nvs(nvs.clone()).and_then(|mut config| {
+        change.mark_applied(&backup::config_form(&config));
+        let mut issues = web::parse_config_form(&change.change, &mut config);
+        issues.extend(config_check::check(&config, &[]));
+        if let Some(issue) = issues.iter().find(|i| i.is_error()) {
+            anyhow::bail!("{}: {}", issue.field, issue.message);
+        }
+        ScheduledChangePersistence::save(nvs.clone(), change)?;
+        config.save_to_nvs(nvs.clone())
+    });
+    match result {
+        Ok(()) => {
+            warn!("Applying scheduled configuration change ({}), restarting", change.summary());
+            events::record(
+                EventCategory::Config,
+                Severity::Warning,
+                &format!("Scheduled configuration change applied: {}", change.summary()),
+            );
+            restart_with_saved_config(gateway, display_state, nvs, nvs_writer, spill_event_log, "Applying Change");
+        }
+        Err(e) => {
+            error!("Scheduled configuration change dropped: {}", e);
+            events::record(
+                EventCategory::Config,
+                Severity::Error,
+                &format!("Scheduled configuration change dropped: {}", e),
+            );
+            if let Err(e) = ScheduledChangePersistence::clear(nvs.clone()) {
+                warn!("Failed to clear scheduled change: {}", e);
+            }
+        }
+    }
+}
+
+/// Put back the values a scheduled change replaced and restart
+fn roll_back_scheduled_change(
+    change: &ScheduledChange,
+    nvs: &EspDefaultNvsPartition,
+    nvs_writer: &NvsWriter,
+    gateway: &Mutex<BacnetGateway>,
+    display_state: &Mutex<DisplayState>,
+    spill_event_log: bool,
+) -> ! {
+    error!("MS/TP ring not stable after the scheduled change, rolling back ({})", change.previous);
+    nvs_writer.flush(nvs_writer::FLUSH_TIMEOUT);
+    let restored = GatewayConfig::load_from_nvs(nvs.clone()).and_then(|mut config| {
+        web::parse_config_form(&change.previous, &mut config);
+        config.save_to_nvs(nvs.clone())
+    });
+    if let Err(e) = restored {
+        error!("Failed to restore the previous configuration: {}", e);
+    }
+    if let Err(e) = ScheduledChangePersistence::clear(nvs.clone()) {
+        warn!("Failed to clear scheduled change: {}", e);
+    }
+    events::record(
+        EventCategory::Config,
+        Severity::Error,
+        &format!("MS/TP ring not stable after scheduled change ({}), previous settings restored", change.summary()),
+    );
+    restart_with_saved_config(gateway, display_state, nvs, nvs_writer, spill_event_log, "Rolling Back")
+}
+
+/// Commit a staged change that found no conflicts: save it and restart.
+/// Returns only if the configuration could not be saved.
+fn commit_staged_change(
+    staging: &StagedValidation,
+    web_state: &Mutex<WebState>,
+    nvs: &EspDefaultNvsPartition,
+    nvs_writer: &NvsWriter,
+    gateway: &Mutex<BacnetGateway>,
+    display_state: &Mutex<DisplayState>,
+    spill_event_log: bool,
+) {
+    nvs_writer.flush(nvs_writer::FLUSH_TIMEOUT);
+    let Ok(mut web) = web_state.lock() else { return };
+    if let Err(e) = staging.candidate.save_to_nvs(nvs.clone()) {
+        error!("Failed to save staged configuration: {}", e);
+        events::record(EventCategory::Config, Severity::Error, "Staged configuration change passed but could not be saved");
+        return;
+    }
+    web.config = staging.candidate.clone();
+    web.config.configured = true;
+    web.config.commissioning_step = 0;
+    drop(web);
+    warn!("Staged configuration change passed, restarting");
+    events::record(EventCategory::Config, Severity::Info, "Staged configuration change passed without conflicts, saved");
+    restart_with_saved_config(gateway, display_state, nvs, nvs_writer, spill_event_log, "Config Applied");
+}
+
+/// Restart into a configuration saved by a staged or scheduled change (or
+/// its rollback), writing the network tables (and the event log) back to NVS
+/// first
+fn restart_with_saved_config(
+    gateway: &Mutex<BacnetGateway>,
+    display_state: &Mutex<DisplayState>,
+    nvs: &EspDefaultNvsPartition,
+    nvs_writer: &NvsWriter,
+    spill_event_log: bool,
+    title: &'static str,
+) -> ! {
+    if let Ok(mut gw) = gateway.lock() {
+        gw.flush_to_nvs();
+    }
+    if !nvs_writer.flush(nvs_writer::FLUSH_TIMEOUT) {
+        warn!("Queued NVS writes did not finish before restart");
+    }
+    if spill_event_log {
+        spill_events(nvs);
+    }
+    if let Ok(mut shared) = display_state.lock() {
+        shared.message = Some((title, "Restarting..."));
+    }
+    // Let the LCD show the message
+    thread::sleep(display::RENDER_INTERVAL * 2);
+    // SAFETY: esp_restart() is always safe to call on ESP32 - it performs a
+    // software reset.
+    unsafe { esp_idf_svc::sys::esp_restart(); }
+    // esp_restart() does not return
+    #[allow(unreachable_code)]
+    loop { thread::sleep(Duration::from_secs(1)); }
+}
+
+/// Write the event log's latest warnings and errors to NVS, stamped in UTC
+/// when the clock is set
+fn spill_events(nvs: &EspDefaultNvsPartition) {
+    let data = events::with_log(|log| {
+        log.encode_spill(|at| {
+            clock::utc_at(at)
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+        })
+    });
+    if let Some(data) = data {
+        if let Err(e) = EventLogPersistence::save(nvs.clone(), &data) {
+            warn!("Failed to save event log: {}", e);
+        }
+    }
 }
 
 /// Initialize WiFi with retry logic
+///
+/// With `soft_ap` set the configuration hotspot runs alongside the station (APSTA)
 fn init_wifi_with_retry(
     modem: impl Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
     sys_loop: EspSystemEventLoop,
     nvs: EspDefaultNvsPartition,
     ssid: &str,
     password: &str,
+    soft_ap: Option<AccessPointConfiguration>,
     max_retries: u32,
 ) -> anyhow::Result<BlockingWifi<EspWifi<'static>>> {
     let mut wifi = BlockingWifi::wrap(
@@ -818,16 +2377,11 @@ fn init_wifi_with_retry(
         sys_loop,
     )?;
 
-    let wifi_configuration = Configuration::Client(ClientConfiguration {
-        ssid: ssid.try_into()
-            .map_err(|_| anyhow::anyhow!("WiFi SSID exceeds maximum length (32 characters)"))?,
-        bssid: None,
-        auth_method: AuthMethod::WPA2Personal,
-        password: password.try_into()
-            .map_err(|_| anyhow::anyhow!("WiFi password exceeds maximum length (64 characters)"))?,
-        channel: None,
-        ..Default::default()
-    });
+    let sta_config = sta_configuration(ssid, password)?;
+    let wifi_configuration = match soft_ap {
+        Some(ap_config) => Configuration::Mixed(sta_config, ap_config),
+        None => Configuration::Client(sta_config),
+    };
 
     wifi.set_configuration(&wifi_configuration)?;
     wifi.start()?;
@@ -902,21 +2456,44 @@ fn check_wifi_connection(wifi: &mut BlockingWifi<EspWifi<'static>>) -> bool {
     false
 }
 
-/// Switch WiFi to Access Point mode
-/// Returns the AP's IP address string on success
-fn switch_to_ap_mode(
-    wifi: &mut BlockingWifi<EspWifi<'static>>,
-    ap_ssid: &str,
-    ap_password: &str,
-) -> anyhow::Result<String> {
-    info!("Configuring WiFi Access Point mode...");
+/// WiFi mode selected with button B
+#[derive(Debug, Clone, Copy)]
+enum WifiMode {
+    /// Configuration hotspot only
+    AccessPoint,
+    /// Site WiFi only
+    Station,
+    /// Site WiFi with the configuration hotspot alongside (APSTA)
+    StationWithAp,
+}
 
-    // Stop current WiFi operation
-    let _ = wifi.disconnect();
-    let _ = wifi.stop();
+impl WifiMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WifiMode::AccessPoint => "AP",
+            WifiMode::Station => "Station",
+            WifiMode::StationWithAp => "Station + AP",
+        }
+    }
+}
 
-    // Configure as Access Point
-    let ap_config = AccessPointConfiguration {
+/// Station configuration for the site WiFi network
+fn sta_configuration(ssid: &str, password: &str) -> anyhow::Result<ClientConfiguration> {
+    Ok(ClientConfiguration {
+        ssid: ssid.try_into()
+            .map_err(|_| anyhow::anyhow!("WiFi SSID exceeds maximum length (32 characters)"))?,
+        bssid: None,
+        auth_method: AuthMethod::WPA2Personal,
+        password: password.try_into()
+            .map_err(|_| anyhow::anyhow!("WiFi password exceeds maximum length (64 characters)"))?,
+        channel: None,
+        ..Default::default()
+    })
+}
+
+/// Access point configuration for the configuration hotspot
+fn ap_configuration(ap_ssid: &str, ap_password: &str) -> anyhow::Result<AccessPointConfiguration> {
+    Ok(AccessPointConfiguration {
         ssid: ap_ssid.try_into().map_err(|_| anyhow::anyhow!("Invalid AP SSID"))?,
         ssid_hidden: false,
         auth_method: AuthMethod::WPA2Personal,
@@ -924,11 +2501,11 @@ fn switch_to_ap_mode(
         channel: 6,  // Use channel 6 (common, less interference)
         max_connections: 4,
         ..Default::default()
-    };
-
-    wifi.set_configuration(&Configuration::AccessPoint(ap_config))?;
-    wifi.start()?;
+    })
+}
 
+/// Wait for the AP netif to come up and return its IP address string
+fn soft_ap_ip(wifi: &BlockingWifi<EspWifi<'static>>) -> anyhow::Result<String> {
     // Wait for AP interface to be fully initialized
     // The AP netif needs time to start the DHCP server and configure the interface
     info!("Waiting for AP interface to initialize...");
@@ -959,8 +2536,28 @@ fn switch_to_ap_mode(
     // Get the actual AP IP address from netif
     let ip_info = ap_netif.get_ip_info()?;
     let ip_str = format!("{}", ip_info.ip);
+    info!("AP netif IP={}, netif_up={}", ip_str, netif_up);
+    Ok(ip_str)
+}
+
+/// Switch WiFi to Access Point mode
+/// Returns the AP's IP address string on success
+fn switch_to_ap_mode(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    ap_ssid: &str,
+    ap_password: &str,
+) -> anyhow::Result<String> {
+    info!("Configuring WiFi Access Point mode...");
+
+    // Stop current WiFi operation
+    let _ = wifi.disconnect();
+    let _ = wifi.stop();
+
+    wifi.set_configuration(&Configuration::AccessPoint(ap_configuration(ap_ssid, ap_password)?))?;
+    wifi.start()?;
 
-    info!("WiFi AP started: SSID='{}', IP={}, netif_up={}", ap_ssid, ip_str, netif_up);
+    let ip_str = soft_ap_ip(wifi)?;
+    info!("WiFi AP started: SSID='{}', IP={}", ap_ssid, ip_str);
     Ok(ip_str)
 }
 
@@ -975,17 +2572,7 @@ fn switch_to_sta_mode(
     // Stop current WiFi operation
     let _ = wifi.stop();
 
-    // Configure as Station (client)
-    let sta_config = ClientConfiguration {
-        ssid: ssid.try_into().map_err(|_| anyhow::anyhow!("Invalid WiFi SSID"))?,
-        bssid: None,
-        auth_method: AuthMethod::WPA2Personal,
-        password: password.try_into().map_err(|_| anyhow::anyhow!("Invalid WiFi password"))?,
-        channel: None,
-        ..Default::default()
-    };
-
-    wifi.set_configuration(&Configuration::Client(sta_config))?;
+    wifi.set_configuration(&Configuration::Client(sta_configuration(ssid, password)?))?;
     wifi.start()?;
 
     // Connect to the network
@@ -1001,11 +2588,42 @@ fn switch_to_sta_mode(
     Ok(ip_str)
 }
 
+/// Run the configuration hotspot alongside the station connection (APSTA)
+/// Returns the station and AP IP address strings on success
+fn switch_to_apsta_mode(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    ssid: &str,
+    password: &str,
+    ap_ssid: &str,
+    ap_password: &str,
+) -> anyhow::Result<(String, String)> {
+    info!("Configuring WiFi Station + Access Point mode...");
+
+    // Stop current WiFi operation
+    let _ = wifi.stop();
+
+    wifi.set_configuration(&Configuration::Mixed(
+        sta_configuration(ssid, password)?,
+        ap_configuration(ap_ssid, ap_password)?,
+    ))?;
+    wifi.start()?;
+
+    let ap_ip = soft_ap_ip(wifi)?;
+
+    info!("Connecting to WiFi network '{}'...", ssid);
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+    let sta_ip = wifi.wifi().sta_netif().get_ip_info()?.ip.to_string();
+
+    info!("WiFi Station + AP connected: IP={}, AP SSID='{}', AP IP={}", sta_ip, ap_ssid, ap_ip);
+    Ok((sta_ip, ap_ip))
+}
+
 /// MS/TP receive task - reads frames from RS-485 and routes to IP
 fn mstp_receive_task(
     mstp_driver: Arc<Mutex<MstpDriver<'static>>>,
     gateway: Arc<Mutex<BacnetGateway>>,
-    local_device: Arc<LocalDevice>,
+    local_device: Option<Arc<LocalDevice>>,
     web_state: Arc<Mutex<web::WebState>>,
     mstp_network: u16,
 ) {
@@ -1019,343 +2637,271 @@ fn mstp_receive_task(
     loop {
         iteration_counter += 1;
 
-        // Try to receive an MS/TP frame using try_lock()
+        // Poll the MS/TP link using try_lock()
         // This allows main loop to acquire the lock when needed
-        let frame = {
-            match mstp_driver.try_lock() {
-                Ok(mut driver) => {
-                    driver.receive_frame()
-                }
-                Err(_) => {
-                    // Lock contention - yield to let main loop run
-                    // This is critical for preventing mutex starvation!
-                    thread::sleep(Duration::from_millis(1));
-                    continue;
-                }
+        let frames = match mstp_driver.try_lock() {
+            Ok(mut driver) => driver.poll(),
+            Err(_) => {
+                // Lock contention - yield to let main loop run
+                // This is critical for preventing mutex starvation!
+                thread::sleep(Duration::from_millis(1));
+                continue;
             }
         };
+        if frames.is_empty() {
+            // No frame available, small delay
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
 
-        match frame {
-            Ok(Some((data, source_addr))) => {
-                info!("MS/TP RX queue: {} bytes from MAC {}, NPDU: {:02X?}",
-                       data.len(), source_addr, &data[..data.len().min(30)]);
+        for frame in frames {
+            let LinkAddress::Mstp(source_addr) = frame.source else { continue };
+            let data = frame.data;
+            trace!("MS/TP RX from MAC {}: {}", source_addr, hex_dump(&data, 30));
 
-                // Store frame for debug viewing
-                if let Ok(mut web) = web_state.lock() {
-                    web.add_rx_frame(source_addr, &data);
-                }
-
-                // Check if this is an I-Am response (for device discovery)
-                if let Some(apdu) = extract_apdu_from_npdu(&data) {
-                    info!("  -> APDU extracted: {:02X?}", &apdu[..apdu.len().min(20)]);
-                    // Check for I-Am (Unconfirmed Request, Service 0)
-                    if apdu.len() >= 2 && apdu[0] == 0x10 && apdu[1] == 0x00 {
-                        info!("  -> I-Am detected from MAC {}", source_addr);
-                        if let Some(device) = DiscoveredDevice::from_i_am(apdu, source_addr) {
-                            info!("Discovered device: instance {} at MAC {}, vendor {}",
-                                device.device_instance, device.mac_address, device.vendor_id);
-
-                            // Add to discovered devices list (avoid duplicates)
-                            // Always capture I-Am responses - they can arrive anytime
-                            if let Ok(mut web) = web_state.lock() {
-                                // Check if device already exists (by instance or MAC)
-                                let exists = web.discovered_devices.iter()
-                                    .any(|d| d.device_instance == device.device_instance || d.mac_address == device.mac_address);
-                                if !exists {
-                                    web.discovered_devices.push(device);
-                                    info!("Added device to discovered list (total: {})", web.discovered_devices.len());
-                                }
-                            }
+            // Store frame for debug viewing
+            if let Ok(mut web) = web_state.lock() {
+                web.add_rx_frame(source_addr, &data);
+            }
+            blackbox::record_frame(FrameSummary::new(
+                FrameSource::Mstp(source_addr),
+                data.len(),
+                extract_apdu_from_npdu(&data),
+            ));
+
+            // Check if this is an I-Am response (for device discovery)
+            if let Some(apdu) = extract_apdu_from_npdu(&data) {
+                // Check for I-Am (Unconfirmed Request, Service 0)
+                if apdu.len() >= 2 && apdu[0] == 0x10 && apdu[1] == 0x00 {
+                    if let Some(device) = DiscoveredDevice::from_i_am(apdu, source_addr) {
+                        debug!("Discovered device: instance {} at MAC {}, vendor {} ({})",
+                            device.device_instance, device.mac_address, device.vendor_id,
+                            device.vendor_name());
+
+                        // Record against the current scan session
+                        // Always capture I-Am responses - they can arrive anytime
+                        if let Ok(mut web) = web_state.lock() {
+                            web.record_i_am(device);
                         }
                     }
                 }
+            }
 
-                // First, check if this is a message for our local device
-                // Parse NPDU to get to APDU
-                if let Some((response_npdu, is_broadcast, source_info)) = try_process_local_device(&data, &local_device, mstp_network) {
-                    // CRITICAL FIX: Always send responses on MS/TP, not directly to IP!
-                    // When the request came from a remote network (e.g., IP via router at station 2),
-                    // we need to send the response on MS/TP TO THE ROUTER, which will forward it.
-                    // This is how other devices (like JCI controllers) respond.
-
-                    if let Some(ref src) = source_info {
-                        // Request came from a remote network - build NPDU with routing info
-                        // and send on MS/TP to the router that forwarded the request
-                        info!("Local device response for remote request from SNET={}, SADR={:02X?}",
-                              src.source_network, src.source_address);
-
-                        // Build NPDU with destination network info (the original source becomes destination)
-                        let mut routed_npdu = Vec::with_capacity(response_npdu.len() + 12);
-                        routed_npdu.push(0x01); // Version
-
-                        // Control: DNET present (0x20)
-                        routed_npdu.push(0x20);
-
-                        // DNET - original source network (where the request came from)
-                        routed_npdu.extend_from_slice(&src.source_network.to_be_bytes());
-
-                        // DLEN and DADR - original source address
-                        routed_npdu.push(src.source_address.len() as u8);
-                        routed_npdu.extend_from_slice(&src.source_address);
-
-                        // Hop count
-                        routed_npdu.push(0xFF);
-
-                        // Append original APDU (skip version and control from response_npdu)
-                        if response_npdu.len() > 2 {
-                            routed_npdu.extend_from_slice(&response_npdu[2..]);
-                        }
-
-                        // Send on MS/TP to the router (source_addr is the MAC of the router that sent us the request)
-                        // The router will see DNET in the NPDU and forward it to the appropriate network
-                        if let Ok(mut driver) = mstp_driver.lock() {
-                            trace!("Sending I-Am on MS/TP to router MAC {}: {} bytes, NPDU: {:02X?}",
-                                  source_addr, routed_npdu.len(), &routed_npdu[..routed_npdu.len().min(30)]);
-                            if let Err(e) = driver.send_frame(&routed_npdu, source_addr, false) {
-                                warn!("Failed to send I-Am to MS/TP router: {}", e);
-                            } else {
-                                trace!("I-Am queued for MS/TP transmission to router MAC {}", source_addr);
-                            }
-                        }
-                    } else {
-                        // No source network info - send locally on MS/TP (broadcast for I-Am)
-                        if let Ok(mut driver) = mstp_driver.lock() {
-                            let dest = if is_broadcast { 0xFF } else { source_addr };
-                            info!("Sending local device response: {} bytes to MAC {} (broadcast={})",
-                                  response_npdu.len(), dest, is_broadcast);
-                            if let Err(e) = driver.send_frame(&response_npdu, dest, false) {
-                                warn!("Failed to send local device response: {}", e);
-                            }
+            // First, check if this is a message for our local device
+            // Parse NPDU to get to APDU
+            let local_response = local_device
+                .as_deref()
+                .and_then(|device| try_process_local_device(&data, device, mstp_network, LinkAddress::Mstp(source_addr)));
+            if let Some((response_npdu, is_broadcast, source_info)) = local_response {
+                // CRITICAL FIX: Always send responses on MS/TP, not directly to IP!
+                // When the request came from a remote network (e.g., IP via router at station 2),
+                // we need to send the response on MS/TP TO THE ROUTER, which will forward it.
+                // This is how other devices (like JCI controllers) respond.
+
+                if let Some(ref src) = source_info {
+                    // Request came from a remote network - build NPDU with routing info
+                    // and send on MS/TP to the router that forwarded the request
+                    LOCAL_DEVICE_REPLIES.fetch_add(1, Ordering::Relaxed);
+                    debug!("Local device response for remote request from SNET={}, SADR={:02X?}",
+                          src.network, src.address);
+
+                    // Build NPDU with destination network info (the original source becomes destination)
+                    let response_apdu = response_npdu.get(2..).unwrap_or_default();
+                    let routed_npdu = Npdu { destination: Some(src.clone()), ..Npdu::local(response_apdu) }.encode();
+
+                    // Send on MS/TP to the router (source_addr is the MAC of the router that sent us the request)
+                    // The router will see DNET in the NPDU and forward it to the appropriate network
+                    if let Ok(mut driver) = mstp_driver.lock() {
+                        trace!("Sending I-Am on MS/TP to router MAC {}: {}", source_addr, hex_dump(&routed_npdu, 30));
+                        if let Err(e) = driver.send_frame(&routed_npdu, source_addr, false) {
+                            warn!("Failed to send I-Am to MS/TP router: {}", e);
+                        } else {
+                            trace!("I-Am queued for MS/TP transmission to router MAC {}", source_addr);
                         }
                     }
                 } else {
-                    // Route the frame through the gateway
-                    if let Ok(mut gw) = gateway.lock() {
-                        match gw.route_from_mstp(&data, source_addr) {
-                            Ok(Some((reject_npdu, reject_dest))) => {
-                                // Send reject message back to MS/TP source
-                                drop(gw); // Release gateway lock before acquiring driver lock
-                                if let Ok(mut driver) = mstp_driver.lock() {
-                                    if let Err(e) = driver.send_frame(&reject_npdu, reject_dest, false) {
-                                        warn!("Failed to send reject to MS/TP: {}", e);
-                                    }
-                                }
-                            }
-                            Ok(None) => {
-                                // Successfully routed, nothing more to do
-                            }
-                            Err(e) => {
-                                warn!("Failed to route MS/TP frame: {}", e);
-                            }
+                    // No source network info - send locally on MS/TP (broadcast for I-Am)
+                    if let Ok(mut driver) = mstp_driver.lock() {
+                        let dest = if is_broadcast { 0xFF } else { source_addr };
+                        LOCAL_DEVICE_REPLIES.fetch_add(1, Ordering::Relaxed);
+                        debug!("Sending local device response: {} bytes to MAC {} (broadcast={})",
+                              response_npdu.len(), dest, is_broadcast);
+                        if let Err(e) = driver.send_frame(&response_npdu, dest, false) {
+                            warn!("Failed to send local device response: {}", e);
                         }
                     }
                 }
-            }
-            Ok(None) => {
-                // No frame available, small delay
-                thread::sleep(Duration::from_millis(1));
-            }
-            Err(e) => {
-                warn!("MS/TP receive error: {}", e);
-                thread::sleep(Duration::from_millis(10));
+            } else {
+                // Route the frame through the gateway; a reject (or RPM proxy
+                // ReadProperty) goes back out through its MS/TP link
+                if let Ok(mut gw) = gateway.lock() {
+                    if let Err(e) = gw.receive_from_mstp(&data, source_addr) {
+                        warn!("Failed to route MS/TP frame: {}", e);
+                        errors::record_error(ErrorSource::Routing, &e);
+                    }
+                }
             }
         }
     }
 }
 
-/// Extract APDU from NPDU data
-fn extract_apdu_from_npdu(data: &[u8]) -> Option<&[u8]> {
-    if data.len() < 2 {
-        return None;
+/// NPDU carried by a B/IP datagram (Original-Unicast/Broadcast or Forwarded-NPDU)
+fn bip_npdu(data: &[u8]) -> Option<&[u8]> {
+    match data {
+        [0x81, 0x0A | 0x0B, ..] => data.get(4..),
+        [0x81, 0x04, ..] => data.get(10..),
+        _ => None,
     }
+}
 
-    let version = data[0];
-    if version != 0x01 {
+/// Extract APDU from NPDU data
+fn extract_apdu_from_npdu(data: &[u8]) -> Option<&[u8]> {
+    let npdu = Npdu::decode(data).ok()?;
+    if npdu.network_message || npdu.payload.is_empty() {
         return None;
     }
+    Some(npdu.payload)
+}
 
-    let control = data[1];
-    let mut pos = 2;
+/// Try to process a message with the local device, returns response if applicable
+/// Returns: (response_npdu, is_broadcast, optional SNET/SADR of the requester)
+/// `local_network` is the network number where this local device resides (IP network for IP side, MS/TP network for MS/TP side)
+/// and `link` the station the frame came from
+fn try_process_local_device(
+    data: &[u8],
+    local_device: &LocalDevice,
+    local_network: u16,
+    link: LinkAddress,
+) -> Option<(Vec<u8>, bool, Option<NetworkAddress>)> {
+    trace!("try_process_local_device: {}", hex_dump(data, 20));
 
-    // Check for destination network (bit 5)
-    if (control & 0x20) != 0 {
-        if pos + 3 > data.len() {
+    let npdu = match Npdu::decode(data) {
+        Ok(npdu) => npdu,
+        Err(e) => {
+            trace!("Not a BACnet NPDU: {}", e);
             return None;
         }
-        pos += 2; // DNET
-        let dlen = data[pos] as usize;
-        pos += 1 + dlen;
-    }
+    };
 
-    // Check for source network (bit 3)
-    if (control & 0x08) != 0 {
-        if pos + 3 > data.len() {
+    // If DNET is not 0xFFFF (global broadcast) and not our local network,
+    // this message should be routed, not processed locally
+    if let Some(ref dest) = npdu.destination {
+        if dest.network != GLOBAL_BROADCAST_NETWORK && dest.network != local_network {
+            trace!("DNET {} not for the local device (network {})", dest.network, local_network);
             return None;
         }
-        pos += 2; // SNET
-        let slen = data[pos] as usize;
-        pos += 1 + slen;
     }
 
-    // Skip hop count if destination was present
-    if (control & 0x20) != 0 {
-        pos += 1;
-    }
-
-    // If network layer message, no APDU
-    if (control & 0x80) != 0 {
+    // Network layer messages are not for the local device
+    if npdu.network_message || npdu.payload.is_empty() {
         return None;
     }
 
-    if pos < data.len() {
-        Some(&data[pos..])
-    } else {
-        None
+    // Process with local device
+    let from = Requester { link, source: npdu.source.clone() };
+    if let Some((response_apdu, is_broadcast)) = local_device.process_apdu(npdu.payload, &from) {
+        trace!("Local device response: {} bytes, is_broadcast={}", response_apdu.len(), is_broadcast);
+        // Local response: no network layer addressing (I-Am broadcasts and
+        // unicast replies alike); the caller adds DNET/DADR for remote requesters
+        let response_npdu = Npdu::local(&response_apdu).encode();
+        return Some((response_npdu, is_broadcast, npdu.source));
     }
-}
 
-/// Source routing information parsed from NPDU
-#[derive(Debug, Clone)]
-struct SourceRouteInfo {
-    /// Source network number (SNET)
-    pub source_network: u16,
-    /// Source address (SADR)
-    pub source_address: Vec<u8>,
+    None
 }
 
-/// Try to process a message with the local device, returns response if applicable
-/// Returns: (response_npdu, is_broadcast, optional_source_route)
-/// `local_network` is the network number where this local device resides (IP network for IP side, MS/TP network for MS/TP side)
-fn try_process_local_device(data: &[u8], local_device: &LocalDevice, local_network: u16) -> Option<(Vec<u8>, bool, Option<SourceRouteInfo>)> {
-    // The data should be NPDU (network layer)
-    // NPDU format: version (1) + control (1) + [optional dest/source] + APDU
-    info!(">>> try_process_local_device: {} bytes, NPDU: {:02X?}", data.len(), &data[..data.len().min(20)]);
-
-    if data.len() < 2 {
-        info!(">>> NPDU too short");
-        return None;
+/// Re-bind the B/IP socket, rejoin the multicast group on the new socket
+/// and, when the station address changed, move the gateway to it
+fn recover_bip_socket(
+    bip_socket: &BipSocket,
+    gateway: &Mutex<BacnetGateway>,
+    config: &GatewayConfig,
+    multicast_iface: &mut Option<Ipv4Addr>,
+    new_address: Option<(Ipv4Addr, Ipv4Addr)>,
+) {
+    match new_address {
+        Some((ip, _)) => warn!("Station address changed to {} - re-binding B/IP socket", ip),
+        None => warn!("B/IP socket failing - re-binding"),
     }
-
-    let version = data[0];
-    if version != 0x01 {
-        info!(">>> Not BACnet NPDU (version=0x{:02X})", version);
-        return None; // Not BACnet NPDU
+    if let Err(e) = bip_socket.rebind() {
+        error!("Failed to re-bind B/IP socket: {}", e);
+        events::record(EventCategory::Ip, Severity::Error, &format!("B/IP socket re-bind failed: {}", e));
+        return;
     }
+    events::record(
+        EventCategory::Ip,
+        Severity::Warning,
+        &format!("B/IP socket re-bound (recovery #{})", bip_socket.recoveries()),
+    );
 
-    let control = data[1];
-    let mut pos = 2;
-    info!(">>> NPDU: version=0x{:02X}, control=0x{:02X}", version, control);
-
-    // Check for destination network (bit 5)
-    let has_dest = (control & 0x20) != 0;
-    // Check for source network (bit 3)
-    let has_source = (control & 0x08) != 0;
-    // Network layer message (bit 7)
-    let is_network_msg = (control & 0x80) != 0;
-
-    // Skip destination if present
-    if has_dest {
-        if pos + 3 > data.len() {
-            info!(">>> DNET parse: pos+3 > len ({} > {})", pos + 3, data.len());
-            return None;
-        }
-        let dnet = u16::from_be_bytes([data[pos], data[pos + 1]]);
-        pos += 2;
-        let dlen = data[pos] as usize;
-        pos += 1;
-        info!(">>> DNET=0x{:04X}, DLEN={}, local_network={}", dnet, dlen, local_network);
-
-        // If DNET is not 0xFFFF (global broadcast) and not our local network,
-        // this message should be routed, not processed locally
-        if dnet != 0xFFFF && dnet != local_network {
-            // This is targeted at a different network - let routing handle it
-            info!(">>> DNET not for us (not 0xFFFF and not local network {})", local_network);
-            return None;
-        }
-
-        pos += dlen;
+    let Ok(mut gw) = gateway.lock() else { return };
+    match new_address {
+        Some((ip, mask)) => gw.set_local_ip(ip, mask),
+        None => gw.request_router_announce(),
     }
-
-    // Extract source network info if present
-    let source_info = if has_source {
-        if pos + 3 > data.len() {
-            return None;
-        }
-        let snet = u16::from_be_bytes([data[pos], data[pos + 1]]);
-        pos += 2;
-        let slen = data[pos] as usize;
-        pos += 1;
-        if pos + slen > data.len() {
-            return None;
-        }
-        let sadr = data[pos..pos + slen].to_vec();
-        pos += slen;
-        Some(SourceRouteInfo {
-            source_network: snet,
-            source_address: sadr,
-        })
-    } else {
-        None
-    };
-
-    // Skip hop count if destination was present
-    if has_dest {
-        if pos >= data.len() {
-            return None;
-        }
-        pos += 1;
+    // The old membership went away with the old socket
+    *multicast_iface = None;
+    if config.bip_multicast_enabled {
+        let iface = gw.local_ip();
+        let joined = bip_socket.current().is_some_and(|socket| {
+            if let Err(e) = socket.set_multicast_loop_v4(false) {
+                warn!("Failed to disable multicast loopback: {}", e);
+            }
+            rejoin_bip_multicast(&socket, config.bip_multicast_group, multicast_iface, iface)
+        });
+        gw.set_multicast_group(joined.then_some(config.bip_multicast_group));
     }
+}
 
-    // If this is a network layer message, don't process with local device
-    if is_network_msg {
-        return None;
-    }
+/// Station interface address and subnet mask, once DHCP has assigned one
+fn station_ipv4(wifi: &BlockingWifi<EspWifi<'static>>) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    let ip_info = wifi.wifi().sta_netif().get_ip_info().ok()?;
+    let ip: Ipv4Addr = ip_info.ip.octets().into();
+    (!ip.is_unspecified()).then(|| (ip, prefix_to_mask(ip_info.subnet.mask.0)))
+}
 
-    // Now we have APDU at data[pos..]
-    if pos >= data.len() {
-        info!(">>> No APDU: pos={} >= len={}", pos, data.len());
-        return None;
-    }
+/// Signal strength (dBm) and channel of the access point the station is associated with
+fn station_link() -> Option<(i8, u8)> {
+    // SAFETY: wifi_ap_record_t is plain integers, byte arrays and bitfields; zeroed is valid
+    let mut record: esp_idf_svc::sys::wifi_ap_record_t = unsafe { std::mem::zeroed() };
+    // SAFETY: fills the struct we own; fails harmlessly when not associated
+    (unsafe { esp_idf_svc::sys::esp_wifi_sta_get_ap_info(&mut record) } == esp_idf_svc::sys::ESP_OK)
+        .then_some((record.rssi, record.primary))
+}
 
-    let apdu = &data[pos..];
-    info!(">>> APDU at pos={}: {:02X?}", pos, &apdu[..apdu.len().min(20)]);
+/// Convert a CIDR prefix to a subnet mask (e.g. 24 -> 255.255.255.0)
+fn prefix_to_mask(prefix: u8) -> Ipv4Addr {
+    let mask_bits: u32 = if prefix == 0 { 0 } else { !0u32 << (32 - prefix) };
+    mask_bits.to_be_bytes().into()
+}
 
-    // Process with local device
-    info!(">>> Calling local_device.process_apdu()...");
-    if let Some((response_apdu, is_broadcast)) = local_device.process_apdu(apdu) {
-        info!(">>> Got response from local_device: {} bytes, is_broadcast={}", response_apdu.len(), is_broadcast);
-        // Build NPDU wrapper for response
-        // For I-Am (broadcast), use global broadcast
-        // For ReadProperty response (unicast), use source routing if available
-        let mut npdu = Vec::with_capacity(response_apdu.len() + 10);
-
-        // NPDU Version
-        npdu.push(0x01);
-
-        if is_broadcast {
-            // Broadcast response (I-Am)
-            // Control: no destination/source network info, APDU present
-            npdu.push(0x00);
-        } else {
-            // Unicast response - no network layer addressing needed for local response
-            npdu.push(0x00);
+/// Move the B/IP multicast group membership (Annex J.7) to a new interface address.
+/// Returns true if the group is now joined on `iface`.
+fn rejoin_bip_multicast(socket: &UdpSocket, group: Ipv4Addr, joined_on: &mut Option<Ipv4Addr>, iface: Ipv4Addr) -> bool {
+    if let Some(old) = joined_on.take() {
+        if let Err(e) = socket.leave_multicast_v4(&group, &old) {
+            warn!("Failed to leave multicast group {} on {}: {}", group, old, e);
+        }
+    }
+    match socket.join_multicast_v4(&group, &iface) {
+        Ok(()) => {
+            info!("Joined B/IP multicast group {} on {}", group, iface);
+            *joined_on = Some(iface);
+            true
+        }
+        Err(e) => {
+            error!("Failed to join B/IP multicast group {} on {}: {} - using subnet broadcast", group, iface, e);
+            false
         }
-
-        // Append APDU
-        npdu.extend_from_slice(&response_apdu);
-
-        return Some((npdu, is_broadcast, source_info));
     }
-
-    None
 }
 
 /// BACnet/IP receive task - reads UDP packets and routes to MS/TP
 fn ip_receive_task(
-    socket: Arc<UdpSocket>,
+    bip_socket: Arc<BipSocket>,
     gateway: Arc<Mutex<BacnetGateway>>,
     mstp_driver: Arc<Mutex<MstpDriver<'static>>>,
-    local_device: Arc<LocalDevice>,
+    local_device: Option<Arc<LocalDevice>>,
     ip_network: u16,
     mstp_network: u16,
     gateway_mac: u8,
@@ -1363,114 +2909,131 @@ fn ip_receive_task(
     info!("BACnet/IP receive task started (gateway MAC {} on networks {} and {})",
           gateway_mac, ip_network, mstp_network);
 
-    let mut buffer = [0u8; 1500];
+    let mut link = BipLink::new(Arc::clone(&bip_socket));
     let mut poll_count: u32 = 0;
+    let mut fair_queue = FairQueue::new();
 
     loop {
         poll_count += 1;
         // Log heartbeat every 1000 polls (~10 seconds at 100ms timeout)
         if poll_count % 1000 == 0 {
-            info!("BIP thread alive: {} polls, waiting for UDP on port 47808", poll_count);
+            debug!(
+                "BIP thread alive: {} polls, {} datagrams received",
+                poll_count,
+                BIP_RX_DATAGRAMS.load(Ordering::Relaxed)
+            );
         }
 
-        match socket.recv_from(&mut buffer) {
-            Ok((len, source_addr)) => {
-                let data = &buffer[..len];
+        // Take the socket afresh each time so a re-bound one is picked up
+        let Some(socket) = bip_socket.current() else {
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        };
+        // Wait for the next datagram only when nothing is queued, then queue
+        // whatever else is waiting per source, so a chatty client takes its
+        // turn with the others instead of going first. Persistent receive
+        // errors get the socket re-bound by the main loop.
+        let received = link.receive(fair_queue.is_empty());
+        if received.is_empty() && fair_queue.is_empty() {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+        for frame in received {
+            let LinkAddress::Ip(source_addr) = frame.source else { continue };
+            BIP_RX_DATAGRAMS.fetch_add(1, Ordering::Relaxed);
+            fair_queue.push(source_addr, &frame.data);
+        }
+        let drops = fair_queue.take_drops();
+        if !drops.is_empty() {
+            if let Ok(mut gw) = gateway.lock() {
+                for (addr, count) in drops {
+                    gw.record_ip_queue_drops(addr, count);
+                }
+            }
+        }
+
+        // One round: a datagram from each queued source, then back to the socket
+        for _ in 0..fair_queue.peer_count() {
+            let Some((source_addr, datagram)) = fair_queue.pop() else { break };
+            let data = &datagram[..];
+            let len = data.len();
+            trace!("BIP RX from {}: {}", source_addr, hex_dump(data, 20));
+            if let SocketAddr::V4(source) = source_addr {
+                let apdu = bip_npdu(data).and_then(extract_apdu_from_npdu);
+                blackbox::record_frame(FrameSummary::new(FrameSource::Ip(source), len, apdu));
+            }
 
-                // Log ALL received IP packets for debugging
-                info!("BIP RX: {} bytes from {} BVLC: {:02X?}",
-                      len, source_addr, &data[..data.len().min(20)]);
+            // The passive unit of a redundancy pair leaves B/IP to the active one
+            if failover::is_standing_by() {
+                continue;
+            }
 
-                // Debug: Log NPDU destination for routing decisions
-                if len > 8 {
-                    let npdu_start = if data[1] == 0x04 { 10 } else { 4 };  // Forwarded or Original
-                    if len > npdu_start + 4 {
-                        let control = data[npdu_start + 1];
-                        if (control & 0x20) != 0 {  // DNET present
-                            let dnet = ((data[npdu_start + 2] as u16) << 8) | (data[npdu_start + 3] as u16);
-                            info!("BIP RX DNET: {} (mstp_network={})", dnet, mstp_network);
-                        }
-                    }
+            // Debug: Log NPDU destination for routing decisions
+            if log::log_enabled!(log::Level::Trace) {
+                if let Some(dest) = bip_npdu(data).and_then(|npdu| Npdu::decode(npdu).ok()?.destination) {
+                    trace!("BIP RX DNET: {} (mstp_network={})", dest.network, mstp_network);
                 }
+            }
 
-                // Try to process with local device first (for Who-Is from IP side)
-                // Also check for requests addressed to gateway via MS/TP routing (DNET=mstp_network, DADR=gateway_mac)
-                if let Some((response_npdu, is_broadcast)) = try_process_ip_local_device(data, &local_device, ip_network, mstp_network, gateway_mac) {
-                    // Wrap in BVLC and send back
-                    let mut bvlc = Vec::with_capacity(response_npdu.len() + 4);
-                    bvlc.push(0x81); // BVLC type
-                    if is_broadcast {
-                        bvlc.push(0x0B); // Original-Broadcast-NPDU
-                    } else {
-                        bvlc.push(0x0A); // Original-Unicast-NPDU
-                    }
-                    let total_len = (response_npdu.len() + 4) as u16;
-                    bvlc.extend_from_slice(&total_len.to_be_bytes());
-                    bvlc.extend_from_slice(&response_npdu);
-
-                    // Send response
-                    if is_broadcast {
-                        // Send to broadcast address for network discovery
-                        let broadcast_addr = "255.255.255.255:47808";
-                        if let Err(e) = socket.send_to(&bvlc, broadcast_addr) {
+            // Try to process with local device first (for Who-Is from IP side)
+            // Also check for requests addressed to gateway via MS/TP routing (DNET=mstp_network, DADR=gateway_mac)
+            let local_response = local_device
+                .as_deref()
+                .and_then(|device| try_process_ip_local_device(data, source_addr, device, ip_network, mstp_network, gateway_mac));
+            if let Some((response_npdu, is_broadcast)) = local_response {
+                LOCAL_DEVICE_REPLIES.fetch_add(1, Ordering::Relaxed);
+                // Wrap in BVLC and send back
+                let mut bvlc = Vec::with_capacity(response_npdu.len() + 4);
+                bvlc.push(0x81); // BVLC type
+                if is_broadcast {
+                    bvlc.push(0x0B); // Original-Broadcast-NPDU
+                } else {
+                    bvlc.push(0x0A); // Original-Unicast-NPDU
+                }
+                let total_len = (response_npdu.len() + 4) as u16;
+                bvlc.extend_from_slice(&total_len.to_be_bytes());
+                bvlc.extend_from_slice(&response_npdu);
+
+                // Send response
+                if is_broadcast {
+                    // Broadcast for network discovery the way the gateway broadcasts
+                    // everything else: the configured form(s) or the multicast group,
+                    // the AP subnet and the supervisory stations
+                    if let Ok(mut gw) = gateway.lock() {
+                        if let Err(e) = gw.broadcast_on_ip(&response_npdu) {
                             warn!("Failed to send I-Am broadcast: {}", e);
                         }
-                        // Also send directly to the requester (common BACnet practice)
-                        // This ensures the requester gets our I-Am even if broadcast fails
+                    }
+                    // Also send directly to the requester (common BACnet practice)
+                    // This ensures the requester gets our I-Am even if broadcast fails,
+                    // unless its compatibility profile lists devices twice that way
+                    let unicast_i_am = gateway.lock().map(|gw| gw.compat_quirks(source_addr).unicast_i_am).unwrap_or(true);
+                    if unicast_i_am {
                         if let Err(e) = socket.send_to(&bvlc, source_addr) {
                             warn!("Failed to send I-Am unicast to {}: {}", source_addr, e);
                         }
-                    } else {
-                        if let Err(e) = socket.send_to(&bvlc, source_addr) {
-                            warn!("Failed to send response to {}: {}", source_addr, e);
-                        }
-                    }
-                }
-
-                // Route the frame through the gateway
-                info!("BIP->routing: calling gateway.lock()...");
-                if let Ok(mut gw) = gateway.lock() {
-                    info!("BIP->routing: calling route_from_ip...");
-                    match gw.route_from_ip(data, source_addr) {
-                        Ok(Some((mstp_data, mstp_dest))) => {
-                            // Check NPDU control byte for expecting-reply bit (bit 2 = 0x04)
-                            // NPDU format: [version, control, ...]
-                            // Control bit 2 indicates "data expecting reply"
-                            let expecting_reply = if mstp_data.len() >= 2 {
-                                (mstp_data[1] & 0x04) != 0
-                            } else {
-                                false
-                            };
-
-                            // Send to MS/TP
-                            info!("IP->MS/TP routing: {} bytes to MS/TP dest={} expecting_reply={} NPDU: {:02X?}",
-                                  mstp_data.len(), mstp_dest, expecting_reply, &mstp_data[..mstp_data.len().min(20)]);
-                            if let Ok(mut driver) = mstp_driver.lock() {
-                                match driver.send_frame(&mstp_data, mstp_dest, expecting_reply) {
-                                    Ok(_) => trace!("IP->MS/TP frame queued successfully"),
-                                    Err(e) => warn!("Failed to send to MS/TP: {}", e),
-                                }
-                            }
-                        }
-                        Ok(None) => {
-                            // Frame handled internally (e.g., BVLC control) or not for MS/TP
-                            info!("BIP->routing: route_from_ip returned None (BVLC control or not for MS/TP)");
-                        }
-                        Err(e) => {
-                            warn!("BIP->routing: route_from_ip error: {}", e);
-                        }
                     }
                 } else {
-                    warn!("BIP->routing: gateway.lock() failed!");
+                    if let Err(e) = socket.send_to(&bvlc, source_addr) {
+                        warn!("Failed to send response to {}: {}", source_addr, e);
+                    }
                 }
             }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // Timeout, no data available
-                thread::sleep(Duration::from_millis(1));
-            }
-            Err(e) => {
-                warn!("UDP receive error: {}", e);
-                thread::sleep(Duration::from_millis(10));
+
+            // Route the frame through the gateway
+            if let Ok(mut gw) = gateway.lock() {
+                // Backpressure decisions use the MS/TP queue depth as of this frame
+                if let Ok(driver) = mstp_driver.try_lock() {
+                    gw.set_mstp_queue_depth(driver.send_queue_len());
+                }
+                // Frames for MS/TP go out through the gateway's MS/TP link,
+                // or are held (store-and-forward) while the ring is down
+                if let Err(e) = gw.receive_from_ip(data, source_addr) {
+                    warn!("BIP->routing: route_from_ip error: {}", e);
+                    errors::record_error(ErrorSource::Routing, &e);
+                }
+            } else {
+                warn!("BIP->routing: gateway.lock() failed!");
             }
         }
     }
@@ -1485,6 +3048,7 @@ fn ip_receive_task(
 /// - Routed requests to gateway's MS/TP address (DNET=mstp_network, DADR=gateway_mac)
 fn try_process_ip_local_device(
     data: &[u8],
+    source_addr: SocketAddr,
     local_device: &LocalDevice,
     ip_network: u16,
     mstp_network: u16,
@@ -1510,32 +3074,20 @@ fn try_process_ip_local_device(
     let npdu_data = &data[4..];
 
     // Check if this is addressed to gateway's MS/TP address (routed request)
-    // NPDU: version (1) + control (1) + [DNET (2) + DLEN (1) + DADR (DLEN) + hop_count (1)] + ...
-    if npdu_data.len() >= 6 {
-        let control = npdu_data[1];
-        let has_dest = (control & 0x20) != 0;
-
-        if has_dest {
-            let dnet = u16::from_be_bytes([npdu_data[2], npdu_data[3]]);
-            let dlen = npdu_data[4] as usize;
-
-            // Check if addressed to gateway's MS/TP address
-            if dnet == mstp_network && dlen == 1 && npdu_data.len() > 5 {
-                let dadr = npdu_data[5];
-                if dadr == gateway_mac {
-                    info!(">>> Routed request to gateway's MS/TP address (DNET={}, DADR={})",
-                          dnet, dadr);
-                    // Process as local device request, using mstp_network as local_network
-                    // so the DNET check passes
-                    return try_process_local_device(npdu_data, local_device, mstp_network)
-                        .map(|(npdu, is_broadcast, _source_info)| (npdu, is_broadcast));
-                }
-            }
-        }
+    let to_gateway_mac = Npdu::decode(npdu_data)
+        .ok()
+        .and_then(|npdu| npdu.destination)
+        .is_some_and(|dest| dest.network == mstp_network && dest.address == [gateway_mac]);
+    if to_gateway_mac {
+        trace!("Routed request to gateway's MS/TP address (DNET={}, DADR={})", mstp_network, gateway_mac);
+        // Process as local device request, using mstp_network as local_network
+        // so the DNET check passes
+        return try_process_local_device(npdu_data, local_device, mstp_network, LinkAddress::Ip(source_addr))
+            .map(|(npdu, is_broadcast, _source_info)| (npdu, is_broadcast));
     }
 
     // Standard processing - check for direct requests (no DNET or DNET=ip_network)
-    try_process_local_device(npdu_data, local_device, ip_network)
+    try_process_local_device(npdu_data, local_device, ip_network, LinkAddress::Ip(source_addr))
         .map(|(npdu, is_broadcast, _source_info)| (npdu, is_broadcast))
 }
 
@@ -0,0 +1,109 @@
+//! Max_Master tuning recommendation
+//!
+//! A master polls every address between itself and the next master, wrapping
+//! at Max_Master, so unused addresses above the highest master are swept with
+//! unanswered Poll-For-Master frames forever. From the masters seen on a
+//! stable ring this suggests the lowest Max_Master that still leaves
+//! `HEADROOM` spare addresses above the highest master, with a rough estimate
+//! of how much sooner each full poll sweep completes.
+
+use std::time::Duration;
+
+/// Spare addresses kept above the highest master for stations added later
+pub const HEADROOM: u8 = 4;
+
+/// Highest Max_Master allowed by ASHRAE 135 Clause 9
+const MAX_MASTER_LIMIT: u8 = 127;
+
+/// A suggested Max_Master and what it is expected to save
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxMasterAdvice {
+    pub current: u8,
+    pub highest_master: u8,
+    pub recommended: u8,
+    /// Unused addresses polled at the current and the recommended setting
+    pub empty_now: u8,
+    pub empty_after: u8,
+    /// Approximate time for one full Poll-For-Master sweep before and after
+    pub sweep_now: Duration,
+    pub sweep_after: Duration,
+}
+
+impl MaxMasterAdvice {
+    /// Percentage by which a full poll sweep gets shorter
+    pub fn sweep_saving_pct(&self) -> u32 {
+        let now = self.sweep_now.as_millis();
+        if now == 0 {
+            return 0;
+        }
+        ((now - self.sweep_after.as_millis()) * 100 / now) as u32
+    }
+}
+
+/// Recommend a lower Max_Master, or None when the current one is already
+/// tight. `masters` is the bitmap of stations seen (including ourselves);
+/// one unused address is polled every `pfm_interval` token loops.
+pub fn recommend(masters: u128, current: u8, pfm_interval: u16, token_loop_ms: u32) -> Option<MaxMasterAdvice> {
+    if masters == 0 {
+        return None;
+    }
+    let highest_master = (127 - masters.leading_zeros()) as u8;
+    let recommended = highest_master.saturating_add(HEADROOM).min(MAX_MASTER_LIMIT);
+    if recommended >= current {
+        return None;
+    }
+
+    let empty = |max: u8| -> u8 {
+        let span = if max >= 127 { u128::MAX } else { (1u128 << (max + 1)) - 1 };
+        (max as u32 + 1 - (masters & span).count_ones()) as u8
+    };
+    let sweep = |empty: u8| Duration::from_millis(empty as u64 * pfm_interval as u64 * token_loop_ms as u64);
+    let (empty_now, empty_after) = (empty(current), empty(recommended));
+
+    Some(MaxMasterAdvice {
+        current,
+        highest_master,
+        recommended,
+        empty_now,
+        empty_after,
+        sweep_now: sweep(empty_now),
+        sweep_after: sweep(empty_after),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitmap(addresses: &[u8]) -> u128 {
+        addresses.iter().fold(0, |map, &a| map | (1u128 << a))
+    }
+
+    #[test]
+    fn test_recommends_headroom_above_highest_master() {
+        let advice = recommend(bitmap(&[1, 3, 7, 20]), 127, 255, 40).unwrap();
+        assert_eq!(advice.highest_master, 20);
+        assert_eq!(advice.recommended, 24);
+        assert_eq!(advice.empty_now, 124);
+        assert_eq!(advice.empty_after, 21);
+        assert_eq!(advice.sweep_now, Duration::from_millis(124 * 255 * 40));
+        assert_eq!(advice.sweep_after, Duration::from_millis(21 * 255 * 40));
+        assert_eq!(advice.sweep_saving_pct(), 83);
+    }
+
+    #[test]
+    fn test_no_advice_when_already_tight() {
+        assert_eq!(recommend(bitmap(&[3, 20]), 24, 255, 40), None);
+        assert_eq!(recommend(bitmap(&[3, 125]), 127, 255, 40), None);
+        assert_eq!(recommend(0, 127, 255, 40), None);
+    }
+
+    #[test]
+    fn test_headroom_capped_at_127() {
+        let advice = recommend(bitmap(&[124]), 127, 255, 40);
+        assert_eq!(advice, None);
+        let advice = recommend(bitmap(&[0, 122]), 127, 255, 0).unwrap();
+        assert_eq!(advice.recommended, 126);
+        assert_eq!(advice.sweep_saving_pct(), 0);
+    }
+}
@@ -6,11 +6,20 @@
 //! Note: The M5Stack RS-485 HAT uses automatic direction control via the SP485EEN
 //! chip's built-in transceiver circuit - no manual GPIO direction pin needed.
 
-use esp_idf_svc::hal::uart::UartDriver;
+use esp_idf_svc::hal::uart::{UartDriver, UartEventPayload};
 use log::{debug, info, trace, warn};
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
+use crate::autoaddr::{AddressSurvey, SurveyPhase};
+use crate::errors::{self, Classify, ErrorKind, ErrorSource};
+use crate::npdu::Npdu;
+
+#[cfg(feature = "fault-injection")]
+use crate::fault_injection::{FaultInjector, FaultSettings, FaultStats, MAX_DELAYED_FRAMES};
+#[cfg(feature = "gpio-trace")]
+use crate::gpio_trace;
+
 // MS/TP frame constants
 const MSTP_PREAMBLE_55: u8 = 0x55;
 const MSTP_PREAMBLE_FF: u8 = 0xFF;
@@ -21,6 +30,29 @@ const MSTP_BROADCAST_ADDRESS: u8 = 255;
 // Polling configuration
 const NPOLL: u8 = 255; // Poll for new masters every 255 tokens (reduced frequency for debugging)
 const MAX_RETRY: u8 = 3; // Maximum retries for failed transmissions
+pub const MAX_SEND_QUEUE: usize = 16; // Frames waiting for the token
+const SLAVE_REPLY_DELAY_MS: u64 = 250; // Treply_delay: a slave's window to answer a request
+
+// Control frame deadlines
+// Token, Poll-For-Master and Reply-Postponed frames are sent by the driver
+// itself as soon as they are due, never from the data send queue. A control
+// frame sent after three quarters of its deadline counts as a near-miss.
+const T_USAGE_DELAY: Duration = Duration::from_millis(15); // Tusage_delay: max silence before we transmit
+const T_REPLY_DELAY: Duration = Duration::from_millis(SLAVE_REPLY_DELAY_MS); // Treply_delay
+const REPLY_POSTPONE_AFTER: Duration = Duration::from_millis(150); // Stop waiting for a reply, well inside Treply_delay
+
+// Adaptive Poll-For-Master configuration
+// Once the ring has been unchanged for PFM_STABLE_AFTER, polls are spaced
+// NPOLL * (1 + aggressiveness) tokens apart and every other poll is spent on
+// an address adjacent to a known master. Worst-case discovery of a station in
+// an unused range is therefore bounded by 2 * (1 + aggressiveness) * NPOLL
+// tokens per address swept.
+const PFM_STABLE_AFTER: Duration = Duration::from_secs(60);
+pub const PFM_MAX_AGGRESSIVENESS: u8 = 3;
+
+// Loopback/wiring test configuration
+const LOOPBACK_PAYLOAD_LEN: usize = 32;
+pub const LOOPBACK_MAX_PROBES: u16 = 1000;
 
 /// MS/TP frame types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -77,6 +109,7 @@ pub enum MstpError {
     Timeout,
     BufferFull,
     BusyMedium, // Medium is busy (another station is transmitting)
+    FrameTooLong, // Data exceeds the 501-octet MS/TP data field
 }
 
 impl std::fmt::Display for MstpError {
@@ -85,6 +118,7 @@ impl std::fmt::Display for MstpError {
             MstpError::IoError(s) => write!(f, "I/O error: {}", s),
             MstpError::InvalidFrame => write!(f, "Invalid frame"),
             MstpError::CrcError => write!(f, "CRC error"),
+            MstpError::FrameTooLong => write!(f, "Frame too long for MS/TP"),
             MstpError::Timeout => write!(f, "Timeout"),
             MstpError::BufferFull => write!(f, "Buffer full"),
             MstpError::BusyMedium => write!(f, "Medium busy"),
@@ -94,6 +128,20 @@ impl std::fmt::Display for MstpError {
 
 impl std::error::Error for MstpError {}
 
+impl Classify for MstpError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            MstpError::IoError(_) => ErrorKind::Io,
+            MstpError::InvalidFrame => ErrorKind::InvalidFrame,
+            MstpError::CrcError => ErrorKind::Crc,
+            MstpError::Timeout => ErrorKind::Timeout,
+            MstpError::BufferFull => ErrorKind::QueueFull,
+            MstpError::BusyMedium => ErrorKind::BusyMedium,
+            MstpError::FrameTooLong => ErrorKind::FrameTooLong,
+        }
+    }
+}
+
 /// MS/TP Driver for ESP32
 /// Uses M5Stack RS-485 HAT with automatic direction control (no GPIO needed)
 #[allow(dead_code)]
@@ -107,7 +155,7 @@ pub struct MstpDriver<'a> {
 
     // State machine
     state: MstpState,
-    token_count: u8,
+    token_count: u16,
     frame_count: u8,
 
     // Debug counters
@@ -125,6 +173,37 @@ pub struct MstpDriver<'a> {
     token_loop_time_ms: u32,
     discovered_masters: u128, // Bitmap of discovered master addresses (0-127)
 
+    // Adaptive Poll-For-Master state
+    pfm_aggressiveness: u8,       // 0 = standard fixed NPOLL sweep
+    ring_changed_at: Instant,     // Last time the set of masters (or pass failures) changed
+    ring_snapshot: (u128, u64),   // (discovered_masters, token_pass_failures) at last check
+    pfm_adjacent_turn: bool,      // Alternates sweep / adjacent polls when stable
+    pfm_adjacent_cursor: u8,      // Round-robin position for adjacent-address polls
+    pfm_adjacent_poll: bool,      // Current PFM targets an adjacent address (don't advance sweep)
+
+    // Listen-only address survey (auto-address mode, before joining the ring)
+    address_survey: Option<AddressSurvey>,
+
+    // Stations heard as a frame source since last taken (bit per MAC 0-255)
+    heard_sources: [u128; 2],
+
+    // Redundancy standby: transmit nothing, only note frames from our own station
+    // address (the active unit of the pair)
+    listen_only: bool,
+    station_heard_at: Option<Instant>,
+
+    // Slave-only mode: never take part in the token ring, only answer requests
+    slave_mode: bool,
+    slave_reply_to: Option<(u8, Option<u8>, Instant)>, // (requester, request's invoke ID, received at)
+    slave_discarded_frames: u64,           // Frames a slave could not send (no token to initiate)
+
+    // Test_Request frames addressed to us, and the Test_Responses sent back
+    test_requests_received: u64,
+    test_responses_sent: u64,
+
+    // Test_Request / Test_Response wiring test
+    loopback: LoopbackTest,
+
     // Error counters
     crc_errors: u64,
     frame_errors: u64,
@@ -132,21 +211,48 @@ pub struct MstpDriver<'a> {
     tokens_received: u64,
     token_pass_failures: u64,
 
+    // UART line-level error counters (wiring/termination, not BACnet protocol)
+    uart_parity_errors: u64,
+    uart_framing_errors: u64,
+    uart_fifo_overflows: u64,
+    uart_buffer_overflows: u64,
+    uart_breaks: u64,
+    overrun_bytes: u64,         // Buffered bytes of frames cut short by an overrun
+    noise_bytes: u64,           // Bytes discarded while hunting for a preamble
+    line_bytes: u64,            // Bytes of valid frames heard or sent (line utilization)
+    data_frame_bytes: u64,      // Part of line_bytes in frames carrying data
+
     // Token loop timing (for min/max/avg calculation)
     token_loop_min_ms: u32,
     token_loop_max_ms: u32,
     token_loop_sum_ms: u64,      // Sum for calculating average
     token_loop_count: u64,       // Count for calculating average
 
+    // Data CRC computation time (RX and TX data frames)
+    data_crc_time_sum_ns: u64,
+    data_crc_time_count: u64,
+    data_crc_time_max_ns: u32,
+
+    // Time between polls from the receive task, and its jitter
+    poll_timing: PollTiming,
+
     // Queues
     send_queue: VecDeque<(Vec<u8>, u8, bool)>, // (data, destination, expecting_reply)
     receive_queue: VecDeque<(Vec<u8>, u8)>, // (data, source)
 
+    // Fault injection (debug builds): injector and frames held back by the delay fault
+    #[cfg(feature = "fault-injection")]
+    faults: FaultInjector,
+    #[cfg(feature = "fault-injection")]
+    delayed_frames: VecDeque<(Instant, Vec<u8>, u8, bool)>, // (due, data, destination, expecting_reply)
+
     // Receive buffer
     rx_buffer: Vec<u8>,
 
     // Pending request for AnswerDataRequest state
     pending_request: Option<(Vec<u8>, u8)>, // (data, source)
+    reply_to: Option<(u8, Option<u8>, Instant)>, // (requester, request's invoke ID, received at)
+    deadline_near_misses: u64,              // Control frames sent late in their deadline
 
     // Timing
     silence_timer: Instant,
@@ -170,6 +276,8 @@ impl<'a> MstpDriver<'a> {
     pub fn new(uart: UartDriver<'a>, station_address: u8, max_master: u8) -> Self {
         let next_station = (station_address + 1) % (max_master + 1);
         let now = Instant::now();
+        // Slave addresses (128-254) are not in the master bitmap
+        let own_bit = if station_address <= 127 { 1u128 << station_address } else { 0 };
 
         Self {
             uart,
@@ -189,20 +297,56 @@ impl<'a> MstpDriver<'a> {
             sole_master: false,
             last_token_time: None,
             token_loop_time_ms: 0,
-            discovered_masters: 1u128 << station_address, // Include ourselves
+            discovered_masters: own_bit, // Include ourselves
+            pfm_aggressiveness: 0,
+            ring_changed_at: now,
+            ring_snapshot: (own_bit, 0),
+            pfm_adjacent_turn: false,
+            pfm_adjacent_cursor: 0,
+            pfm_adjacent_poll: false,
+            address_survey: None,
+            heard_sources: [0; 2],
+            listen_only: false,
+            station_heard_at: None,
+            slave_mode: false,
+            slave_reply_to: None,
+            slave_discarded_frames: 0,
+            test_requests_received: 0,
+            test_responses_sent: 0,
+            loopback: LoopbackTest::default(),
             crc_errors: 0,
             frame_errors: 0,
             reply_timeouts: 0,
             tokens_received: 0,
             token_pass_failures: 0,
+            uart_parity_errors: 0,
+            uart_framing_errors: 0,
+            uart_fifo_overflows: 0,
+            uart_buffer_overflows: 0,
+            overrun_bytes: 0,
+            uart_breaks: 0,
+            noise_bytes: 0,
+            line_bytes: 0,
+            data_frame_bytes: 0,
             token_loop_min_ms: u32::MAX,
             token_loop_max_ms: 0,
             token_loop_sum_ms: 0,
             token_loop_count: 0,
+            data_crc_time_sum_ns: 0,
+            data_crc_time_count: 0,
+            data_crc_time_max_ns: 0,
+            poll_timing: PollTiming::default(),
             send_queue: VecDeque::new(),
             receive_queue: VecDeque::new(),
+            #[cfg(feature = "fault-injection")]
+            // SAFETY: esp_random() has no preconditions
+            faults: FaultInjector::new(unsafe { esp_idf_svc::sys::esp_random() }),
+            #[cfg(feature = "fault-injection")]
+            delayed_frames: VecDeque::new(),
             rx_buffer: Vec::with_capacity(MSTP_HEADER_SIZE + MSTP_MAX_DATA_LENGTH + 2),
             pending_request: None,
+            reply_to: None,
+            deadline_near_misses: 0,
             silence_timer: now,
             reply_timer: None,
             usage_timer: None,
@@ -219,23 +363,101 @@ impl<'a> MstpDriver<'a> {
     /// Queue a frame for transmission
     /// expecting_reply: true if this is a confirmed request expecting a response
     pub fn send_frame(&mut self, data: &[u8], destination: u8, expecting_reply: bool) -> Result<(), MstpError> {
-        if self.send_queue.len() >= 16 {
+        if data.len() > MSTP_MAX_DATA_LENGTH {
+            // The length field would describe a frame no station accepts
+            warn!("Refusing {} byte frame to {}: exceeds MS/TP max {}", data.len(), destination, MSTP_MAX_DATA_LENGTH);
+            return Err(MstpError::FrameTooLong);
+        }
+        if self.send_queue.len() >= MAX_SEND_QUEUE {
             return Err(MstpError::BufferFull);
         }
 
+        #[cfg(feature = "fault-injection")]
+        {
+            let action = self.faults.decide();
+            if action.drop {
+                debug!("FAULT: dropped {} byte frame to {}", data.len(), destination);
+                return Ok(());
+            }
+            let copies = if action.duplicate { 2 } else { 1 };
+            if let Some(delay) = action.delay {
+                if self.delayed_frames.len() + copies > MAX_DELAYED_FRAMES {
+                    return Err(MstpError::BufferFull);
+                }
+                for _ in 0..copies {
+                    self.delayed_frames.push_back((Instant::now() + delay, data.to_vec(), destination, expecting_reply));
+                }
+                return Ok(());
+            }
+            if action.duplicate && self.send_queue.len() + 1 < MAX_SEND_QUEUE {
+                self.send_queue.push_back((data.to_vec(), destination, expecting_reply));
+            }
+        }
+
         trace!("QUEUE: Adding {} bytes to send_queue for dest={}, queue_len_after={}, state={:?}",
               data.len(), destination, self.send_queue.len() + 1, self.state);
         self.send_queue.push_back((data.to_vec(), destination, expecting_reply));
         Ok(())
     }
 
+    /// Set the injected fault rates for frames sent on MS/TP
+    #[cfg(feature = "fault-injection")]
+    pub fn set_fault_settings(&mut self, settings: FaultSettings) {
+        self.faults.set_settings(settings);
+        if settings.delay_ms == 0 {
+            // Send anything still held back right away
+            for (_, data, dest, expecting_reply) in self.delayed_frames.drain(..) {
+                if self.send_queue.len() < MAX_SEND_QUEUE {
+                    self.send_queue.push_back((data, dest, expecting_reply));
+                }
+            }
+        }
+    }
+
+    /// Faults injected on MS/TP so far
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_stats(&self) -> FaultStats {
+        self.faults.stats()
+    }
+
+    /// Move delayed frames whose time has come onto the send queue
+    #[cfg(feature = "fault-injection")]
+    fn release_delayed_frames(&mut self) {
+        let now = Instant::now();
+        while self.send_queue.len() < MAX_SEND_QUEUE
+            && self.delayed_frames.front().is_some_and(|(due, ..)| *due <= now)
+        {
+            if let Some((_, data, dest, expecting_reply)) = self.delayed_frames.pop_front() {
+                self.send_queue.push_back((data, dest, expecting_reply));
+            }
+        }
+    }
+
+    /// Number of frames waiting in the send queue
+    pub fn send_queue_len(&self) -> usize {
+        self.send_queue.len()
+    }
+
+    /// Number of frames that can still be queued with `send_frame`
+    pub fn send_queue_space(&self) -> usize {
+        MAX_SEND_QUEUE.saturating_sub(self.send_queue.len())
+    }
+
     /// Queue a frame for transmission (backwards compatibility - assumes not expecting reply)
     pub fn send_frame_simple(&mut self, data: &[u8], destination: u8) -> Result<(), MstpError> {
         self.send_frame(data, destination, false)
     }
 
+    /// Note the start of a poll from the receive task, for the loop timing stats
+    pub fn record_poll(&mut self) {
+        self.poll_timing.record(Instant::now());
+    }
+
     /// Receive a frame (returns None if no frame available)
     pub fn receive_frame(&mut self) -> Result<Option<(Vec<u8>, u8)>, MstpError> {
+        #[cfg(feature = "fault-injection")]
+        self.release_delayed_frames();
+
         // Process incoming bytes
         self.process_uart_rx()?;
 
@@ -251,8 +473,45 @@ impl<'a> MstpDriver<'a> {
         Ok(result)
     }
 
+    /// Drain the ESP-IDF UART event queue and count line-level errors
+    /// These come from the UART peripheral itself and point at wiring, termination
+    /// or baud mismatch problems rather than MS/TP protocol problems.
+    fn process_uart_events(&mut self) {
+        let mut overflowed = false;
+
+        if let Some(queue) = self.uart.event_queue() {
+            while let Some((event, _)) = queue.recv_front(0) {
+                match event.payload() {
+                    UartEventPayload::ParityError => self.uart_parity_errors += 1,
+                    UartEventPayload::FrameError => self.uart_framing_errors += 1,
+                    UartEventPayload::RxFifoOverflow => {
+                        self.uart_fifo_overflows += 1;
+                        overflowed = true;
+                    }
+                    UartEventPayload::RxBufferFull => {
+                        self.uart_buffer_overflows += 1;
+                        overflowed = true;
+                    }
+                    UartEventPayload::Break | UartEventPayload::DataBreak => self.uart_breaks += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        if overflowed {
+            // Bytes were lost, so the frame buffered so far can't be completed.
+            // Whatever the UART still holds is read as usual; the parser skips
+            // the rest of the cut frame while hunting for the next preamble.
+            let dropped = discard_partial_frame(&mut self.rx_buffer);
+            self.overrun_bytes += dropped as u64;
+            trace!("UART RX overrun: {} buffered bytes dropped", dropped);
+        }
+    }
+
     /// Process incoming UART bytes
     fn process_uart_rx(&mut self) -> Result<(), MstpError> {
+        self.process_uart_events();
+
         let mut buf = [0u8; 256];
         let mut total_read = 0usize;
 
@@ -262,7 +521,9 @@ impl<'a> MstpDriver<'a> {
                 Ok(n) => {
                     // CRITICAL: Minimize logging overhead in RX path!
                     // Only log broadcast/data frames which we're debugging
-                    if n > 0 {
+                    // Scanning every read for data frames costs more than the
+                    // read itself at high baud rates, so only do it when tracing
+                    if n > 0 && log::log_enabled!(log::Level::Trace) {
                         // Check for BROADCAST data frame: 55 FF 06 FF (type=6, dest=255)
                         let has_broadcast = buf[..n].windows(4).any(|w| {
                             w[0] == 0x55 && w[1] == 0xFF && w[2] == 0x06 && w[3] == 0xFF
@@ -273,12 +534,10 @@ impl<'a> MstpDriver<'a> {
                         });
 
                         if has_broadcast {
-                            warn!("**** BROADCAST DATA FRAME (Who-Is?) **** UART_RX {} bytes: {:02X?}", n, &buf[..n.min(64)]);
+                            trace!("Broadcast data frame in UART_RX {} bytes: {:02X?}", n, &buf[..n.min(64)]);
                         } else if has_data_frame {
-                            warn!("!!!! DATA FRAME DETECTED !!!! UART_RX {} bytes: {:02X?}", n, &buf[..n.min(64)]);
+                            trace!("Data frame in UART_RX {} bytes: {:02X?}", n, &buf[..n.min(64)]);
                         }
-                        // Token/control frames: use trace!() to minimize overhead
-                        // trace!("UART_RX {} bytes: {:02X?}", n, &buf[..n.min(32)]);
                     }
                     self.rx_buffer.extend_from_slice(&buf[..n]);
                     self.silence_timer = Instant::now();
@@ -301,9 +560,7 @@ impl<'a> MstpDriver<'a> {
     fn parse_frames(&mut self) -> Result<(), MstpError> {
         loop {
             // Look for preamble
-            let preamble_pos = self.rx_buffer
-                .windows(2)
-                .position(|w| w[0] == MSTP_PREAMBLE_55 && w[1] == MSTP_PREAMBLE_FF);
+            let preamble_pos = find_preamble(&self.rx_buffer);
 
             let _start = match preamble_pos {
                 Some(pos) => {
@@ -311,6 +568,7 @@ impl<'a> MstpDriver<'a> {
                     if pos > 0 {
                         trace!("RX_DISCARD: {} bytes before preamble: {:02X?}",
                                pos, &self.rx_buffer[..pos.min(16)]);
+                        self.noise_bytes += pos as u64;
                         self.rx_buffer.drain(..pos);
                     }
                     0
@@ -324,6 +582,7 @@ impl<'a> MstpDriver<'a> {
                             trace!("RX_DISCARD: No preamble, discarding {} bytes: {:02X?}",
                                    keep, &self.rx_buffer[..keep.min(16)]);
                         }
+                        self.noise_bytes += keep as u64;
                         self.rx_buffer.drain(..keep);
                     }
                     return Ok(());
@@ -347,6 +606,9 @@ impl<'a> MstpDriver<'a> {
 
             if calculated_crc != header_crc {
                 self.crc_errors += 1;
+                #[cfg(feature = "gpio-trace")]
+                gpio_trace::crc_error();
+                errors::record(ErrorSource::Mstp, ErrorKind::Crc, &format!("header CRC, frame from {}", source));
                 // Show full header bytes for debugging
                 let hdr_bytes = &self.rx_buffer[..MSTP_HEADER_SIZE.min(self.rx_buffer.len())];
                 warn!("Header CRC error: calc=0x{:02X} recv=0x{:02X} type={} dest={} src={} len={}",
@@ -365,6 +627,7 @@ impl<'a> MstpDriver<'a> {
             // Check for oversized frames
             if data_len > MSTP_MAX_DATA_LENGTH {
                 self.frame_errors += 1;
+                errors::record(ErrorSource::Mstp, ErrorKind::FrameTooLong, &format!("{} byte frame from {}", data_len, source));
                 warn!("Oversized frame: data_len={} > max={}", data_len, MSTP_MAX_DATA_LENGTH);
                 self.rx_buffer.drain(..2); // Skip preamble and try again
                 continue;
@@ -409,10 +672,13 @@ impl<'a> MstpDriver<'a> {
                 let crc_low = self.rx_buffer[data_end];
                 let crc_high = self.rx_buffer[data_end + 1];
                 let received_crc = (crc_high as u16) << 8 | crc_low as u16;
-                let calculated_crc = calculate_data_crc(&data);
+                let calculated_crc = self.timed_data_crc(&data);
 
                 if received_crc != calculated_crc {
                     self.crc_errors += 1;
+                    #[cfg(feature = "gpio-trace")]
+                    gpio_trace::crc_error();
+                    errors::record(ErrorSource::Mstp, ErrorKind::Crc, &format!("data CRC, {} byte frame from {}", data_len, source));
                     // Verbose debug: show raw frame bytes for CRC debugging
                     let frame_bytes: Vec<u8> = self.rx_buffer[..frame_size].to_vec();
                     warn!("Data CRC error: calc=0x{:04X} recv=0x{:04X} (type={}, src={}, len={})",
@@ -431,6 +697,10 @@ impl<'a> MstpDriver<'a> {
 
             // Remove frame from buffer
             self.rx_buffer.drain(..frame_size);
+            self.line_bytes += frame_size as u64;
+            if data_len > 0 {
+                self.data_frame_bytes += frame_size as u64;
+            }
 
             // Process frame FIRST - logging can wait!
             // PollForMaster (0x01) requires immediate response within Tslot (10ms)
@@ -454,6 +724,24 @@ impl<'a> MstpDriver<'a> {
         source: u8,
         data: Vec<u8>,
     ) -> Result<(), MstpError> {
+        // Auto-address survey: only listen, never answer or take part in the ring
+        if let Some(survey) = self.address_survey.as_mut() {
+            survey.observe(frame_type, dest, source, Instant::now());
+            return Ok(());
+        }
+
+        // Redundancy standby: the other unit is alive while it sends from our address
+        if self.listen_only {
+            if source == self.station_address {
+                self.station_heard_at = Some(Instant::now());
+            }
+            return Ok(());
+        }
+
+        if source != self.station_address {
+            self.heard_sources[(source / 128) as usize] |= 1u128 << (source % 128);
+        }
+
         let ftype = MstpFrameType::from_u8(frame_type);
 
         // Log data frames at info level for debugging
@@ -469,6 +757,10 @@ impl<'a> MstpDriver<'a> {
             );
         }
 
+        if self.slave_mode {
+            return self.handle_frame_as_slave(ftype, dest, source, data);
+        }
+
         // If we're in Initialize and receive a valid frame, transition to Idle
         // This means the bus is active and we should join the network
         if self.state == MstpState::Initialize {
@@ -510,6 +802,8 @@ impl<'a> MstpDriver<'a> {
                 if dest == self.station_address {
                     // We received the token - transition to UseToken
                     debug!("Received Token from station {} (in Idle)", source);
+                    #[cfg(feature = "gpio-trace")]
+                    gpio_trace::token_received();
                     self.token_count += 1;
                     self.tokens_received += 1;
                     self.frame_count = 0;
@@ -595,6 +889,7 @@ impl<'a> MstpDriver<'a> {
                 if dest == self.station_address {
                     // Transition to AnswerDataRequest
                     trace!("Received BACnet data (expecting reply) from station {}, {} bytes", source, data.len());
+                    self.reply_to = Some((source, request_invoke_id(&data), Instant::now()));
                     self.pending_request = Some((data, source));
                     self.reply_delay_timer = Some(Instant::now());
                     self.state = MstpState::AnswerDataRequest;
@@ -620,6 +915,9 @@ impl<'a> MstpDriver<'a> {
                     }
                 }
             }
+            Some(MstpFrameType::TestRequest) if dest == self.station_address => {
+                self.answer_test_request(source, &data)?;
+            }
             _ => {
                 // Other frame types ignored in Idle
             }
@@ -660,11 +958,19 @@ impl<'a> MstpDriver<'a> {
             Some(MstpFrameType::TestRequest) => {
                 // ReceivedUnexpectedFrame event
                 warn!("Unexpected frame type {:?} in WaitForReply state", ftype);
+                self.loopback.complete(ProbeOutcome::NoReply, Instant::now());
                 self.reply_timer = None;
                 self.state = MstpState::Idle;
                 self.no_token_timer = Instant::now();
             }
 
+            // Test_Response to our own wiring test probe - consumed here, never queued upward
+            Some(MstpFrameType::TestResponse) if self.loopback.awaiting(source) => {
+                self.loopback.complete(ProbeOutcome::Response(&data), Instant::now());
+                self.reply_timer = None;
+                self.state = MstpState::DoneWithToken;
+            }
+
             // ALL OTHER frame types are accepted as valid replies
             // This includes:
             // - BacnetDataNotExpectingReply
@@ -675,6 +981,7 @@ impl<'a> MstpDriver<'a> {
             _ => {
                 // ReceivedReply event - valid reply received
                 debug!("Valid reply received in WaitForReply: {:?}", ftype);
+                self.loopback.complete(ProbeOutcome::WrongReply, Instant::now());
 
                 // Queue for upper layer
                 if self.receive_queue.len() < 16 {
@@ -729,10 +1036,14 @@ impl<'a> MstpDriver<'a> {
                     self.sole_master = false;
 
                     // Advance poll_station past the discovered master for next poll cycle
-                    self.poll_station = (source + 1) % (self.max_master + 1);
-                    if self.poll_station == self.station_address {
-                        self.poll_station = (self.poll_station + 1) % (self.max_master + 1);
+                    // (adjacent-address polls leave the sweep position untouched)
+                    if !self.pfm_adjacent_poll {
+                        self.poll_station = (source + 1) % (self.max_master + 1);
+                        if self.poll_station == self.station_address {
+                            self.poll_station = (self.poll_station + 1) % (self.max_master + 1);
+                        }
                     }
+                    self.pfm_adjacent_poll = false;
 
                     // We generated the token via polling, so we should use it first
                     // before passing to the newly discovered master
@@ -753,7 +1064,7 @@ impl<'a> MstpDriver<'a> {
             Some(MstpFrameType::BacnetDataNotExpectingReply) => {
                 if dest == self.station_address || dest == MSTP_BROADCAST_ADDRESS {
                     if self.receive_queue.len() < 16 {
-                        info!("Queuing DNER in PollForMaster state: {} bytes from {}", data.len(), source);
+                        debug!("Queuing DNER in PollForMaster state: {} bytes from {}", data.len(), source);
                         self.receive_queue.push_back((data, source));
                     }
                 }
@@ -776,6 +1087,10 @@ impl<'a> MstpDriver<'a> {
             Some(MstpFrameType::Token) => {
                 if dest == self.station_address {
                     // Unexpected token - go to UseToken anyway
+                    #[cfg(feature = "gpio-trace")]
+                    gpio_trace::token_received();
+                    // A reply still owed goes out with the token like any queued frame
+                    self.reply_to = None;
                     self.token_count += 1;
                     self.frame_count = 0;
                     self.state = MstpState::UseToken;
@@ -794,13 +1109,116 @@ impl<'a> MstpDriver<'a> {
                     }
                 }
             }
+            Some(MstpFrameType::TestRequest) if dest == self.station_address => {
+                self.answer_test_request(source, &data)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Frame handling for a slave node (ASHRAE 135 Clause 9.5.7)
+    /// Tokens and Poll-For-Master are never answered, so the ring's masters
+    /// don't see us; only requests addressed to us get a reply.
+    fn handle_frame_as_slave(
+        &mut self,
+        ftype: Option<MstpFrameType>,
+        dest: u8,
+        source: u8,
+        data: Vec<u8>,
+    ) -> Result<(), MstpError> {
+        // Any valid frame shows the trunk is alive, so the link comes up
+        // whether or not a request for us ever arrives
+        self.no_token_timer = Instant::now();
+        if self.state == MstpState::Initialize {
+            info!("Slave: frames heard on the trunk, leaving Initialize");
+            self.state = MstpState::Idle;
+        }
+
+        match ftype {
+            Some(MstpFrameType::BacnetDataExpectingReply) if dest == self.station_address => {
+                if self.state == MstpState::AnswerDataRequest {
+                    // A new request supersedes one we never answered
+                    debug!("Slave: request from {} replaces unanswered request", source);
+                }
+                if self.receive_queue.len() < 16 {
+                    self.slave_reply_to = Some((source, request_invoke_id(&data), Instant::now()));
+                    self.receive_queue.push_back((data, source));
+                    self.state = MstpState::AnswerDataRequest;
+                }
+            }
+            Some(MstpFrameType::BacnetDataExpectingReply) |
+            Some(MstpFrameType::BacnetDataNotExpectingReply) => {
+                if (dest == self.station_address || dest == MSTP_BROADCAST_ADDRESS)
+                    && self.receive_queue.len() < 16
+                {
+                    self.receive_queue.push_back((data, source));
+                }
+            }
+            Some(MstpFrameType::TestRequest) if dest == self.station_address => {
+                // Test_Request is answered like any other request (Clause 9.5.7)
+                self.answer_test_request(source, &data)?;
+            }
             _ => {}
         }
         Ok(())
     }
 
+    /// Slave node state machine: reply to the pending request or stay silent
+    fn run_slave_state_machine(&mut self) -> Result<(), MstpError> {
+        let Some((requester, invoke_id, received_at)) = self.slave_reply_to else {
+            // Nothing to answer: a slave can't initiate, so queued frames can never go out
+            if !self.send_queue.is_empty() {
+                trace!("Slave: discarding {} queued frame(s), no request to answer", self.send_queue.len());
+                self.slave_discarded_frames += self.send_queue.len() as u64;
+                self.send_queue.clear();
+            }
+            return Ok(());
+        };
+
+        match choose_reply(&self.send_queue, requester, invoke_id, received_at.elapsed(), T_REPLY_DELAY) {
+            ReplyChoice::Send(index) => {
+                if let Some((data, dest, _)) = self.send_queue.remove(index) {
+                    self.send_data_frame(&data, dest, false)?;
+                }
+            }
+            ReplyChoice::Wait => return Ok(()),
+            ReplyChoice::GiveUp => {
+                // CannotReply: the requester times out and retries
+                debug!("Slave: no reply for {} within {}ms", requester, SLAVE_REPLY_DELAY_MS);
+                self.reply_timeouts += 1;
+            }
+        }
+        self.slave_reply_to = None;
+        self.state = MstpState::Idle;
+        Ok(())
+    }
+
     /// Run the MS/TP state machine - implements ASHRAE 135 Clause 9
     fn run_state_machine(&mut self) -> Result<(), MstpError> {
+        // However WaitForReply ended, a probe still in flight got no answer
+        if self.state != MstpState::WaitForReply {
+            self.loopback.complete(ProbeOutcome::NoReply, Instant::now());
+        }
+        if self.listen_only {
+            self.send_queue.clear();
+            return Ok(());
+        }
+        if self.slave_mode {
+            return self.run_slave_state_machine();
+        }
+
+        self.step_master()?;
+        // Once we are done with the token it goes out in this same call rather
+        // than waiting behind whatever the caller routes before polling again
+        while matches!(self.state, MstpState::DoneWithToken | MstpState::PassToken) {
+            self.step_master()?;
+        }
+        Ok(())
+    }
+
+    /// One transition of the master node state machine
+    fn step_master(&mut self) -> Result<(), MstpError> {
         match self.state {
             MstpState::Initialize => {
                 // Wait for silence then go to idle
@@ -817,7 +1235,8 @@ impl<'a> MstpDriver<'a> {
                     // No token received, try to generate one via polling
                     info!("Idle: No token timeout ({}ms), starting PollForMaster", self.t_no_token);
                     self.poll_station = (self.station_address + 1) % (self.max_master + 1);
-                    self.send_poll_for_master(self.poll_station)?;
+                    // Recovery after a long silence has no deadline to meet
+                    self.send_raw_frame(MstpFrameType::PollForMaster, self.poll_station, &[])?;
                     self.state = MstpState::PollForMaster;
                     self.silence_timer = Instant::now();
                 }
@@ -833,6 +1252,15 @@ impl<'a> MstpDriver<'a> {
                     }
                 }
 
+                // Wiring test probes take one of our info frames while a test is running
+                if self.loopback.wants_probe() && self.frame_count < self.max_info_frames {
+                    self.send_loopback_probe()?;
+                    self.frame_count += 1;
+                    self.reply_timer = Some(Instant::now());
+                    self.state = MstpState::WaitForReply;
+                    return Ok(());
+                }
+
                 // We have the token, send data if available
                 if self.frame_count < self.max_info_frames {
                     if let Some((data, dest, expecting_reply)) = self.send_queue.pop_front() {
@@ -879,10 +1307,19 @@ impl<'a> MstpDriver<'a> {
                 // Check for reply timeout
                 if let Some(timer) = self.reply_timer {
                     if timer.elapsed() > Duration::from_millis(self.t_reply_timeout) {
-                        warn!("Reply timeout in WaitForReply state");
                         self.reply_timer = None;
+
+                        // An unanswered wiring test probe is a test result, not a protocol error
+                        if self.loopback.probe_in_flight() {
+                            self.loopback.complete(ProbeOutcome::NoReply, Instant::now());
+                            self.state = MstpState::DoneWithToken;
+                            return Ok(());
+                        }
+
+                        warn!("Reply timeout in WaitForReply state");
                         self.retry_count += 1;
                         self.reply_timeouts += 1;
+                        errors::record(ErrorSource::Mstp, ErrorKind::Timeout, "no reply to data frame");
 
                         if self.retry_count >= MAX_RETRY {
                             // Max retries exceeded
@@ -913,27 +1350,42 @@ impl<'a> MstpDriver<'a> {
                                 self.receive_queue.push_back((request_data, source));
                             }
                         }
-
-                        // IMPORTANT: Transition to UseToken, NOT Idle!
-                        // We have the implicit right to reply - the sender is waiting.
-                        // Go to UseToken to give the application layer time to queue a response.
                         self.reply_delay_timer = None;
-                        self.frame_count = 0;
-                        self.usage_timer = Some(Instant::now());
-                        self.state = MstpState::UseToken;
-                        info!("AnswerDataRequest -> UseToken: Waiting for response to be queued");
                     }
-                } else {
-                    // No timer set, something went wrong
+                    return Ok(());
+                }
+
+                let Some((requester, invoke_id, received_at)) = self.reply_to else {
+                    // No request to answer, something went wrong
                     self.state = MstpState::Idle;
                     self.no_token_timer = Instant::now();
+                    return Ok(());
+                };
+
+                // The reply goes out ahead of any data queued for the token;
+                // we have no token, so nothing else may be sent from here
+                match choose_reply(&self.send_queue, requester, invoke_id, received_at.elapsed(), REPLY_POSTPONE_AFTER) {
+                    ReplyChoice::Send(index) => {
+                        if let Some((data, dest, _)) = self.send_queue.remove(index) {
+                            self.send_data_frame(&data, dest, false)?;
+                        }
+                    }
+                    ReplyChoice::Wait => return Ok(()),
+                    ReplyChoice::GiveUp => {
+                        // The reply will follow when we next hold the token
+                        debug!("AnswerDataRequest: no reply for {} yet, sending ReplyPostponed", requester);
+                        self.send_control_frame(MstpFrameType::ReplyPostponed, requester, received_at, T_REPLY_DELAY)?;
+                    }
                 }
+                self.reply_to = None;
+                self.state = MstpState::Idle;
+                self.no_token_timer = Instant::now();
             }
 
             MstpState::DoneWithToken => {
                 // Check if we should poll for new masters
-                if self.token_count >= NPOLL {
-                    debug!("Poll interval reached, polling station {}", self.poll_station);
+                self.update_ring_stability();
+                if self.token_count >= self.pfm_interval() {
                     self.token_count = 0;
 
                     // Skip our own address
@@ -941,8 +1393,11 @@ impl<'a> MstpDriver<'a> {
                         self.poll_station = (self.poll_station + 1) % (self.max_master + 1);
                     }
 
-                    // Send poll to current poll_station (incremented by PollForMaster state)
-                    self.send_poll_for_master(self.poll_station)?;
+                    // Send poll to current poll_station (incremented by PollForMaster state),
+                    // or to an address adjacent to a known master when adaptive polling is active
+                    let target = self.next_poll_target();
+                    debug!("Poll interval reached, polling station {}", target);
+                    self.send_poll_for_master(target)?;
                     self.state = MstpState::PollForMaster;
                     self.silence_timer = Instant::now();
                 } else {
@@ -965,7 +1420,8 @@ impl<'a> MstpDriver<'a> {
                 if self.silence_timer.elapsed() > Duration::from_millis(self.t_no_token) {
                     debug!("NoToken state: attempting recovery via PollForMaster");
                     self.poll_station = (self.station_address + 1) % (self.max_master + 1);
-                    self.send_poll_for_master(self.poll_station)?;
+                    // Recovery after a long silence has no deadline to meet
+                    self.send_raw_frame(MstpFrameType::PollForMaster, self.poll_station, &[])?;
                     self.state = MstpState::PollForMaster;
                     self.silence_timer = Instant::now();
                 }
@@ -977,12 +1433,16 @@ impl<'a> MstpDriver<'a> {
                 // not a full sweep. Increment poll_station for the NEXT poll cycle.
                 if self.silence_timer.elapsed() > Duration::from_millis(self.t_slot) {
                     // No reply from this station - increment poll_station for next time
-                    self.poll_station = (self.poll_station + 1) % (self.max_master + 1);
-
-                    // Skip our own address
-                    if self.poll_station == self.station_address {
+                    // (an unanswered adjacent-address poll doesn't move the sweep)
+                    if !self.pfm_adjacent_poll {
                         self.poll_station = (self.poll_station + 1) % (self.max_master + 1);
+
+                        // Skip our own address
+                        if self.poll_station == self.station_address {
+                            self.poll_station = (self.poll_station + 1) % (self.max_master + 1);
+                        }
                     }
+                    self.pfm_adjacent_poll = false;
 
                     debug!("PollForMaster: no reply, next poll will be station {}",
                            self.poll_station);
@@ -1011,17 +1471,35 @@ impl<'a> MstpDriver<'a> {
 
     /// Send a token frame
     fn send_token(&mut self, dest: u8) -> Result<(), MstpError> {
-        self.send_raw_frame(MstpFrameType::Token, dest, &[])
+        self.send_control_frame(MstpFrameType::Token, dest, self.silence_timer, T_USAGE_DELAY)
     }
 
     /// Send poll for master
     fn send_poll_for_master(&mut self, dest: u8) -> Result<(), MstpError> {
-        self.send_raw_frame(MstpFrameType::PollForMaster, dest, &[])
+        self.send_control_frame(MstpFrameType::PollForMaster, dest, self.silence_timer, T_USAGE_DELAY)
     }
 
     /// Send reply to poll
     fn send_reply_to_poll(&mut self, dest: u8) -> Result<(), MstpError> {
-        self.send_raw_frame(MstpFrameType::ReplyToPollForMaster, dest, &[])
+        self.send_control_frame(MstpFrameType::ReplyToPollForMaster, dest, self.silence_timer, T_USAGE_DELAY)
+    }
+
+    /// Send a frame that must go out within `deadline` of `since`.
+    /// Control frames bypass the send queue; one sent in the last quarter of
+    /// its deadline is counted as a near-miss (no logging - the other station
+    /// may already be waiting on it).
+    fn send_control_frame(
+        &mut self,
+        ftype: MstpFrameType,
+        dest: u8,
+        since: Instant,
+        deadline: Duration,
+    ) -> Result<(), MstpError> {
+        let latency = since.elapsed();
+        if latency * 4 >= deadline * 3 {
+            self.deadline_near_misses += 1;
+        }
+        self.send_raw_frame(ftype, dest, &[])
     }
 
     /// Send a data frame
@@ -1034,8 +1512,36 @@ impl<'a> MstpDriver<'a> {
         self.send_raw_frame(ftype, dest, data)
     }
 
+    /// Data CRC, with its computation time added to the stats
+    fn timed_data_crc(&mut self, data: &[u8]) -> u16 {
+        let started = Instant::now();
+        let crc = calculate_data_crc(data);
+        let elapsed_ns = started.elapsed().as_nanos().min(u32::MAX as u128) as u32;
+        self.data_crc_time_sum_ns += elapsed_ns as u64;
+        self.data_crc_time_count += 1;
+        self.data_crc_time_max_ns = self.data_crc_time_max_ns.max(elapsed_ns);
+        crc
+    }
+
+    /// Echo a Test_Request back as a Test_Response (Clause 9.3)
+    ///
+    /// Sent straight away rather than through AnswerDataRequest: the reply
+    /// carries the request's own data, so there is nothing to wait for.
+    /// Test_Requests to the broadcast address are never answered.
+    fn answer_test_request(&mut self, source: u8, data: &[u8]) -> Result<(), MstpError> {
+        self.test_requests_received += 1;
+        self.send_raw_frame(MstpFrameType::TestResponse, source, data)?;
+        self.test_responses_sent += 1;
+        trace!("Test_Response sent to {} ({} bytes)", source, data.len());
+        Ok(())
+    }
+
     /// Send a raw MS/TP frame
     fn send_raw_frame(&mut self, ftype: MstpFrameType, dest: u8, data: &[u8]) -> Result<(), MstpError> {
+        if self.listen_only {
+            trace!("Listen-only: not sending {:?} to {}", ftype, dest);
+            return Ok(());
+        }
         let data_len = data.len();
 
         // Build frame
@@ -1059,7 +1565,9 @@ impl<'a> MstpDriver<'a> {
         // Data and CRC if present
         if !data.is_empty() {
             frame.extend_from_slice(data);
-            let data_crc = calculate_data_crc(data);
+            let data_crc = self.timed_data_crc(data);
+            #[cfg(feature = "fault-injection")]
+            let data_crc = if (ftype as u8) >= 5 && self.faults.corrupt_crc() { !data_crc } else { data_crc };
             frame.push((data_crc & 0xFF) as u8);
             frame.push((data_crc >> 8) as u8);
         }
@@ -1160,7 +1668,17 @@ impl<'a> MstpDriver<'a> {
         // Send the frame
         // Note: M5Stack RS-485 HAT has automatic direction control via SP485EEN chip
         // The TX line controls DE/RE automatically - no GPIO needed
-        self.uart.write(&frame).map_err(|e| MstpError::IoError(format!("{:?}", e)))?;
+        #[cfg(feature = "gpio-trace")]
+        gpio_trace::tx_start();
+        self.uart.write(&frame).map_err(|e| {
+            #[cfg(feature = "gpio-trace")]
+            gpio_trace::tx_end();
+            MstpError::IoError(format!("{:?}", e))
+        })?;
+        self.line_bytes += frame.len() as u64;
+        if data_len > 0 {
+            self.data_frame_bytes += frame.len() as u64;
+        }
 
         // Wait for TX to complete
         // At 38400 baud: each byte = 10 bits = ~260us
@@ -1176,6 +1694,8 @@ impl<'a> MstpDriver<'a> {
         let extra_margin_us = if is_time_critical { 200 } else { 1000 };
         let tx_time_us = (frame.len() as u64) * 260 + extra_margin_us;
         std::thread::sleep(std::time::Duration::from_micros(tx_time_us));
+        #[cfg(feature = "gpio-trace")]
+        gpio_trace::tx_end();
 
         // Note: M5Stack RS-485 HAT uses SP485EEN with automatic direction control
         // This chip should NOT echo our TX back to RX (DE/RE tied together, controlled by TX)
@@ -1260,6 +1780,102 @@ impl<'a> MstpDriver<'a> {
         (self.station_address + 1) % (self.max_master + 1)
     }
 
+    /// Start a Test_Request/Test_Response wiring test against a station
+    /// Probes are sent one per token hold until `count` have been sent.
+    pub fn start_loopback_test(&mut self, target: u8, count: u16) -> Result<(), MstpError> {
+        if target == self.station_address || target > 127 {
+            return Err(MstpError::InvalidFrame);
+        }
+        let count = count.clamp(1, LOOPBACK_MAX_PROBES);
+        info!("Starting MS/TP wiring test: {} Test_Request frames to station {}", count, target);
+        self.loopback.start(target, count);
+        Ok(())
+    }
+
+    /// Abort a running wiring test (results so far are kept)
+    pub fn stop_loopback_test(&mut self) {
+        self.loopback.stop();
+    }
+
+    /// Get the current/last wiring test result
+    pub fn get_loopback_result(&self) -> LoopbackTestResult {
+        self.loopback.result.clone()
+    }
+
+    /// Transmit the next Test_Request probe
+    fn send_loopback_probe(&mut self) -> Result<(), MstpError> {
+        let payload = self.loopback.next_payload();
+        self.send_raw_frame(MstpFrameType::TestRequest, self.loopback.result.target, &payload)?;
+        self.loopback.probe_sent(payload, Instant::now());
+        Ok(())
+    }
+
+    /// Set adaptive Poll-For-Master aggressiveness (0 = standard, up to PFM_MAX_AGGRESSIVENESS)
+    pub fn set_pfm_aggressiveness(&mut self, level: u8) {
+        self.pfm_aggressiveness = level.min(PFM_MAX_AGGRESSIVENESS);
+        info!("Poll-For-Master aggressiveness set to {}", self.pfm_aggressiveness);
+    }
+
+    /// Check whether the set of masters has been unchanged long enough to back off polling
+    pub fn is_ring_stable(&self) -> bool {
+        self.ring_changed_at.elapsed() >= PFM_STABLE_AFTER
+    }
+
+    /// Restart the stability window whenever a master appears or a token pass fails
+    fn update_ring_stability(&mut self) {
+        let snapshot = (self.discovered_masters, self.token_pass_failures);
+        if snapshot != self.ring_snapshot {
+            self.ring_snapshot = snapshot;
+            self.ring_changed_at = Instant::now();
+        }
+    }
+
+    /// Number of tokens between Poll-For-Master cycles
+    fn pfm_interval(&self) -> u16 {
+        if self.pfm_aggressiveness > 0 && self.is_ring_stable() {
+            NPOLL as u16 * (1 + self.pfm_aggressiveness as u16)
+        } else {
+            NPOLL as u16
+        }
+    }
+
+    /// Choose the station for the next Poll-For-Master
+    /// When adaptive polling is active, alternates between the regular sweep and
+    /// addresses adjacent to known masters (where new devices are most likely added).
+    fn next_poll_target(&mut self) -> u8 {
+        self.pfm_adjacent_poll = false;
+
+        if self.pfm_aggressiveness > 0 && self.is_ring_stable() {
+            self.pfm_adjacent_turn = !self.pfm_adjacent_turn;
+            if self.pfm_adjacent_turn {
+                if let Some(addr) = self.next_adjacent_address() {
+                    self.pfm_adjacent_poll = true;
+                    return addr;
+                }
+            }
+        }
+
+        self.poll_station
+    }
+
+    /// Find the next unused address next to a known master, round-robin from the cursor
+    fn next_adjacent_address(&mut self) -> Option<u8> {
+        let span = self.max_master as u16 + 1;
+        for offset in 0..span {
+            let addr = ((self.pfm_adjacent_cursor as u16 + offset) % span) as u8;
+            if addr == self.station_address || (self.discovered_masters >> addr) & 1 == 1 {
+                continue;
+            }
+            let below = addr > 0 && (self.discovered_masters >> (addr - 1)) & 1 == 1;
+            let above = addr < 127 && (self.discovered_masters >> (addr + 1)) & 1 == 1;
+            if below || above {
+                self.pfm_adjacent_cursor = ((addr as u16 + 1) % span) as u8;
+                return Some(addr);
+            }
+        }
+        None
+    }
+
     /// Get comprehensive MS/TP statistics
     pub fn get_stats(&self) -> MstpStats {
         // Calculate average token loop time
@@ -1276,6 +1892,12 @@ impl<'a> MstpDriver<'a> {
             self.token_loop_min_ms
         };
 
+        let data_crc_avg_ns = if self.data_crc_time_count > 0 {
+            (self.data_crc_time_sum_ns / self.data_crc_time_count) as u32
+        } else {
+            0
+        };
+
         MstpStats {
             rx_frames: self.rx_frame_count,
             tx_frames: self.tx_frame_count,
@@ -1284,10 +1906,25 @@ impl<'a> MstpDriver<'a> {
             reply_timeouts: self.reply_timeouts,
             tokens_received: self.tokens_received,
             token_pass_failures: self.token_pass_failures,
+            deadline_near_misses: self.deadline_near_misses,
+            uart_parity_errors: self.uart_parity_errors,
+            uart_framing_errors: self.uart_framing_errors,
+            uart_fifo_overflows: self.uart_fifo_overflows,
+            uart_buffer_overflows: self.uart_buffer_overflows,
+            overrun_bytes: self.overrun_bytes,
+            uart_breaks: self.uart_breaks,
+            noise_bytes: self.noise_bytes,
+            line_bytes: self.line_bytes,
+            data_frame_bytes: self.data_frame_bytes,
             token_loop_time_ms: self.token_loop_time_ms,
             token_loop_min_ms,
             token_loop_max_ms: self.token_loop_max_ms,
             token_loop_avg_ms,
+            data_crc_avg_ns,
+            data_crc_max_ns: self.data_crc_time_max_ns,
+            poll_interval_avg_us: self.poll_timing.avg_us(),
+            poll_interval_max_us: self.poll_timing.max_us,
+            poll_jitter_us: self.poll_timing.jitter_us,
             master_count: self.discovered_masters.count_ones() as u8,
             discovered_masters: self.discovered_masters,
             current_state: self.state as u8,
@@ -1295,9 +1932,16 @@ impl<'a> MstpDriver<'a> {
             poll_station: self.poll_station,
             silence_ms: self.silence_timer.elapsed().as_millis() as u32,
             station_address: self.station_address,
+            max_master: self.max_master,
             sole_master: self.sole_master,
             send_queue_len: self.send_queue.len() as u8,
             receive_queue_len: self.receive_queue.len() as u8,
+            ring_stable: self.is_ring_stable(),
+            pfm_interval: self.pfm_interval(),
+            slave_mode: self.slave_mode,
+            slave_discarded_frames: self.slave_discarded_frames,
+            test_requests_received: self.test_requests_received,
+            test_responses_sent: self.test_responses_sent,
         }
     }
 
@@ -1325,13 +1969,30 @@ impl<'a> MstpDriver<'a> {
         self.tokens_received = 0;
         self.frame_errors = 0;
         self.token_pass_failures = 0;
+        self.deadline_near_misses = 0;
         self.rx_poll_count = 0;
+        self.uart_parity_errors = 0;
+        self.uart_framing_errors = 0;
+        self.uart_fifo_overflows = 0;
+        self.uart_buffer_overflows = 0;
+        self.overrun_bytes = 0;
+        self.uart_breaks = 0;
+        self.noise_bytes = 0;
+        self.line_bytes = 0;
+        self.data_frame_bytes = 0;
+        self.slave_discarded_frames = 0;
+        self.test_requests_received = 0;
+        self.test_responses_sent = 0;
         // Reset token loop timing stats
         self.token_loop_time_ms = 0;
         self.token_loop_min_ms = u32::MAX;
         self.token_loop_max_ms = 0;
         self.token_loop_sum_ms = 0;
         self.token_loop_count = 0;
+        self.data_crc_time_sum_ns = 0;
+        self.data_crc_time_count = 0;
+        self.data_crc_time_max_ns = 0;
+        self.poll_timing.reset();
         // Keep discovered_masters bitmap - don't clear device knowledge
     }
 
@@ -1345,6 +2006,79 @@ impl<'a> MstpDriver<'a> {
         )
     }
 
+    /// Check whether the token is circulating through this station
+    /// (held now, or seen within the no-token timeout)
+    pub fn is_token_active(&self) -> bool {
+        if self.state == MstpState::Initialize || self.slave_mode {
+            return false;
+        }
+        self.has_token() || self.no_token_timer.elapsed() < Duration::from_millis(self.t_no_token)
+    }
+
+    /// Check whether frames can reach other stations: the token is
+    /// circulating and at least one other master is in the ring
+    pub fn is_ring_up(&self) -> bool {
+        self.is_token_active() && !self.sole_master
+    }
+
+    /// Check whether the MS/TP link is working for this station's role:
+    /// the ring is up for a master, frames are being heard for a slave
+    /// (any valid frame takes a slave out of Initialize)
+    pub fn is_link_up(&self) -> bool {
+        if self.slave_mode {
+            self.state != MstpState::Initialize
+                && self.no_token_timer.elapsed() < Duration::from_millis(self.t_no_token)
+        } else {
+            self.is_ring_up()
+        }
+    }
+
+    /// Operate as an MS/TP slave: no token, no Poll-For-Master replies,
+    /// only replies to requests addressed to this station
+    pub fn set_slave_mode(&mut self, enabled: bool) {
+        self.slave_mode = enabled;
+        self.slave_reply_to = None;
+        if enabled {
+            self.sole_master = false;
+            self.discovered_masters &= !(1u128.checked_shl(self.station_address as u32).unwrap_or(0));
+        }
+        self.state = MstpState::Initialize;
+        self.silence_timer = Instant::now();
+    }
+
+    /// Whether the driver runs as an MS/TP slave
+    pub fn is_slave(&self) -> bool {
+        self.slave_mode
+    }
+
+    /// Stand by for the other unit of a redundancy pair: transmit nothing and
+    /// pass nothing up, only note frames sent from our station address.
+    /// Leaving listen-only joins the ring (or answers as a slave) from scratch.
+    pub fn set_listen_only(&mut self, enabled: bool) {
+        if self.listen_only == enabled {
+            return;
+        }
+        self.listen_only = enabled;
+        self.station_heard_at = None;
+        self.send_queue.clear();
+        self.slave_reply_to = None;
+        self.sole_master = false;
+        self.state = MstpState::Initialize;
+        self.silence_timer = Instant::now();
+        info!("MS/TP {}", if enabled { "listen-only (standby)" } else { "active" });
+    }
+
+    /// Whether the driver is standing by in listen-only mode
+    pub fn is_listen_only(&self) -> bool {
+        self.listen_only
+    }
+
+    /// When a frame from our station address was last heard in listen-only mode,
+    /// cleared by the call
+    pub fn take_station_heard(&mut self) -> Option<Instant> {
+        self.station_heard_at.take()
+    }
+
     /// Get the station address
     pub fn get_station_address(&self) -> u8 {
         self.station_address
@@ -1354,6 +2088,79 @@ impl<'a> MstpDriver<'a> {
     pub fn get_max_master(&self) -> u8 {
         self.max_master
     }
+
+    /// Change Max_Master at runtime. Masters above the new value are
+    /// forgotten; values below our own station address are ignored.
+    pub fn set_max_master(&mut self, max_master: u8) {
+        if max_master > 127 || max_master < self.station_address {
+            warn!("Ignoring Max_Master {} (station address {})", max_master, self.station_address);
+            return;
+        }
+        self.max_master = max_master;
+        if max_master < 127 {
+            self.discovered_masters &= (1u128 << (max_master + 1)) - 1;
+        }
+        if self.poll_station > max_master {
+            self.poll_station = (self.station_address + 1) % (max_master + 1);
+        }
+        self.next_station = self.find_next_master();
+        info!("Max_Master set to {}", max_master);
+    }
+
+    /// Stations heard as a frame source since the last call
+    pub fn take_heard_sources(&mut self) -> Vec<u8> {
+        let heard = std::mem::take(&mut self.heard_sources);
+        (0..=255u8).filter(|&mac| heard[(mac / 128) as usize] & (1u128 << (mac % 128)) != 0).collect()
+    }
+
+    /// Listen without transmitting and pick a free station address
+    ///
+    /// Blocks until a full token rotation and PFM activity have been heard
+    /// and the chosen address stayed silent (or the survey times out).
+    /// `on_wait` runs between polls, e.g. to feed the watchdog. On success the
+    /// driver takes the address and starts from the masters it heard.
+    pub fn survey_station_address(&mut self, preferred: u8, mut on_wait: impl FnMut()) -> Option<u8> {
+        info!("Auto-address: listening for a free station address (0-{})", self.max_master);
+        self.address_survey = Some(AddressSurvey::new(self.max_master, preferred, Instant::now()));
+
+        let chosen = loop {
+            if let Err(e) = self.process_uart_rx() {
+                debug!("Auto-address: receive error: {}", e);
+            }
+            let phase = match self.address_survey.as_mut() {
+                Some(survey) => survey.poll(Instant::now()),
+                None => break None,
+            };
+            match phase {
+                SurveyPhase::Done(addr) => break Some(addr),
+                SurveyPhase::NoFreeAddress => break None,
+                SurveyPhase::Listening | SurveyPhase::Verifying { .. } => {}
+            }
+            on_wait();
+            std::thread::sleep(Duration::from_millis(5));
+        };
+
+        let heard = self.address_survey.take().map(|s| s.used_addresses()).unwrap_or(0);
+        match chosen {
+            Some(addr) => {
+                info!("Auto-address: using station address {} ({} other master(s) heard)", addr, heard.count_ones());
+                self.set_station_address(addr, heard);
+            }
+            None => warn!("Auto-address: no free station address up to {}", self.max_master),
+        }
+        chosen
+    }
+
+    /// Change our station address before joining the ring
+    fn set_station_address(&mut self, addr: u8, known_masters: u128) {
+        self.station_address = addr;
+        self.poll_station = addr;
+        self.discovered_masters = known_masters | (1u128 << addr);
+        self.ring_snapshot = (self.discovered_masters, self.token_pass_failures);
+        self.next_station = self.find_next_master();
+        self.state = MstpState::Initialize;
+        self.silence_timer = Instant::now();
+    }
 }
 
 /// MS/TP Statistics
@@ -1367,10 +2174,25 @@ pub struct MstpStats {
     pub reply_timeouts: u64,
     pub tokens_received: u64,
     pub token_pass_failures: u64,   // Times we failed to pass token (max retries)
+    pub deadline_near_misses: u64,  // Token/PFM/Reply-Postponed sent late in their deadline
+    pub uart_parity_errors: u64,    // UART parity errors (line noise)
+    pub uart_framing_errors: u64,   // UART framing errors (bad stop bit, baud mismatch, reflections)
+    pub uart_fifo_overflows: u64,   // Hardware RX FIFO overflows (bytes lost)
+    pub uart_buffer_overflows: u64, // Driver ring buffer full (bytes lost)
+    pub overrun_bytes: u64,         // Buffered bytes dropped because an overrun cut their frame short
+    pub uart_breaks: u64,           // Break conditions (line held low, e.g. A/B swapped or open bus)
+    pub noise_bytes: u64,           // Bytes discarded outside any frame
+    pub line_bytes: u64,            // Bytes of valid frames heard or sent
+    pub data_frame_bytes: u64,      // Part of line_bytes in frames carrying data
     pub token_loop_time_ms: u32,
     pub token_loop_min_ms: u32,     // Minimum observed token loop time
     pub token_loop_max_ms: u32,     // Maximum observed token loop time
     pub token_loop_avg_ms: u32,     // Rolling average token loop time
+    pub data_crc_avg_ns: u32,       // Average data CRC computation time per frame
+    pub data_crc_max_ns: u32,       // Longest data CRC computation time
+    pub poll_interval_avg_us: u32,  // Average time between polls of the state machine
+    pub poll_interval_max_us: u32,  // Longest time between polls
+    pub poll_jitter_us: u32,        // Smoothed change in poll interval from one poll to the next
     pub master_count: u8,
     pub discovered_masters: u128,
     pub current_state: u8,          // MstpState as u8
@@ -1378,23 +2200,248 @@ pub struct MstpStats {
     pub poll_station: u8,
     pub silence_ms: u32,            // Time since last valid frame
     pub station_address: u8,        // Our station address
+    pub max_master: u8,             // Highest address polled for masters
     pub sole_master: bool,          // Operating as sole master on bus
     pub send_queue_len: u8,         // Current send queue depth
     pub receive_queue_len: u8,      // Current receive queue depth
+    pub ring_stable: bool,          // Set of masters unchanged long enough for adaptive polling
+    pub pfm_interval: u16,          // Current tokens between Poll-For-Master cycles
+    pub slave_mode: bool,           // Operating as an MS/TP slave (no token)
+    pub slave_discarded_frames: u64, // Frames dropped because a slave can't initiate
+    pub test_requests_received: u64, // Test_Request frames addressed to us
+    pub test_responses_sent: u64,    // Test_Response frames sent back
 }
 
-/// Calculate MS/TP header CRC-8 per ASHRAE 135 Annex G.1
-/// Uses polynomial X^8 + X^7 + 1
-/// This is the PARALLEL algorithm from the ASHRAE spec - NOT the standard bit-by-bit CRC!
-fn calculate_header_crc(header: &[u8]) -> u8 {
-    let mut crc = 0xFFu8;
+/// Result of a Test_Request/Test_Response wiring test
+#[derive(Debug, Clone, Default)]
+pub struct LoopbackTestResult {
+    pub target: u8,
+    pub requested: u16,
+    pub sent: u16,
+    pub passed: u16,                // Test_Response received with identical payload
+    pub mismatched: u16,            // Test_Response received with corrupted/different payload
+    pub timeouts: u16,              // No Test_Response within Treply_timeout
+    pub rtt_min_us: u32,
+    pub rtt_max_us: u32,
+    pub rtt_sum_us: u64,
+    pub in_progress: bool,
+}
 
-    for &byte in header {
-        // XOR C7..C0 with D7..D0
-        let mut temp = (crc ^ byte) as u16;
+impl LoopbackTestResult {
+    /// Average round-trip time of passed probes in microseconds
+    pub fn rtt_avg_us(&self) -> u32 {
+        if self.passed > 0 {
+            (self.rtt_sum_us / self.passed as u64) as u32
+        } else {
+            0
+        }
+    }
+
+    /// Percentage of completed probes that failed (mismatch or timeout)
+    pub fn error_rate_pct(&self) -> f32 {
+        let completed = self.passed + self.mismatched + self.timeouts;
+        if completed > 0 {
+            (self.mismatched + self.timeouts) as f32 * 100.0 / completed as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+/// How a wiring test probe ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProbeOutcome<'a> {
+    /// Test_Response from the target, with its payload
+    Response(&'a [u8]),
+    /// The target's answer was some other frame
+    WrongReply,
+    /// No answer within Treply_timeout, or WaitForReply ended another way
+    NoReply,
+}
+
+/// Probe bookkeeping for a Test_Request/Test_Response wiring test
+#[derive(Debug, Default)]
+struct LoopbackTest {
+    result: LoopbackTestResult,
+    remaining: u16,
+    /// The probe awaiting its answer: when it was sent and its payload
+    in_flight: Option<(Instant, Vec<u8>)>,
+}
+
+impl LoopbackTest {
+    fn start(&mut self, target: u8, count: u16) {
+        self.result = LoopbackTestResult {
+            target,
+            requested: count,
+            in_progress: true,
+            ..Default::default()
+        };
+        self.remaining = count;
+        self.in_flight = None;
+    }
+
+    /// Send no more probes. A probe in flight still has its answer consumed
+    /// when it arrives, so it never reaches the upper layer, but it isn't
+    /// counted.
+    fn stop(&mut self) {
+        self.remaining = 0;
+        self.result.in_progress = false;
+    }
+
+    /// Another probe is due
+    fn wants_probe(&self) -> bool {
+        self.remaining > 0
+    }
+
+    fn probe_in_flight(&self) -> bool {
+        self.in_flight.is_some()
+    }
+
+    /// A probe is waiting for an answer from `source`
+    fn awaiting(&self, source: u8) -> bool {
+        self.in_flight.is_some() && source == self.result.target
+    }
+
+    /// Sequence-dependent payload for the next probe
+    fn next_payload(&self) -> Vec<u8> {
+        let seq = self.result.sent;
+        let mut payload = Vec::with_capacity(LOOPBACK_PAYLOAD_LEN);
+        payload.extend_from_slice(&seq.to_be_bytes());
+        // Alternating bit patterns plus an embedded preamble to stress the receiver
+        payload.extend_from_slice(&[0x55, 0xAA, 0x00, 0xFF, 0x55, 0xFF]);
+        for i in payload.len()..LOOPBACK_PAYLOAD_LEN {
+            payload.push((i as u8).wrapping_mul(37).wrapping_add(seq as u8));
+        }
+        payload
+    }
+
+    fn probe_sent(&mut self, payload: Vec<u8>, now: Instant) {
+        self.in_flight = Some((now, payload));
+        self.remaining = self.remaining.saturating_sub(1);
+        self.result.sent += 1;
+    }
+
+    /// Record how the probe in flight ended; does nothing when none is
+    fn complete(&mut self, outcome: ProbeOutcome, now: Instant) {
+        let Some((sent_at, payload)) = self.in_flight.take() else {
+            return;
+        };
+        if !self.result.in_progress {
+            return;
+        }
+
+        match outcome {
+            ProbeOutcome::Response(data) if data == payload.as_slice() => {
+                let rtt_us = now.saturating_duration_since(sent_at).as_micros() as u32;
+                self.result.passed += 1;
+                self.result.rtt_min_us = if self.result.passed == 1 {
+                    rtt_us
+                } else {
+                    self.result.rtt_min_us.min(rtt_us)
+                };
+                self.result.rtt_max_us = self.result.rtt_max_us.max(rtt_us);
+                self.result.rtt_sum_us = self.result.rtt_sum_us.saturating_add(rtt_us as u64);
+            }
+            ProbeOutcome::Response(data) => {
+                debug!("Test_Response payload mismatch ({} bytes, expected {})", data.len(), payload.len());
+                self.result.mismatched += 1;
+            }
+            ProbeOutcome::WrongReply => {
+                debug!("Wiring test probe answered with another frame type");
+                self.result.mismatched += 1;
+            }
+            ProbeOutcome::NoReply => self.result.timeouts += 1,
+        }
+
+        if self.remaining == 0 {
+            self.result.in_progress = false;
+            info!("MS/TP wiring test to {} complete: {}/{} passed, {} mismatched, {} timeouts",
+                  self.result.target, self.result.passed, self.result.sent,
+                  self.result.mismatched, self.result.timeouts);
+        }
+    }
+}
+
+/// Offset of the first frame preamble (55 FF) in `buffer`
+fn find_preamble(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(2).position(|w| w[0] == MSTP_PREAMBLE_55 && w[1] == MSTP_PREAMBLE_FF)
+}
+
+/// Drop the partly received frame after a UART overrun and return its length
+fn discard_partial_frame(rx_buffer: &mut Vec<u8>) -> usize {
+    let dropped = rx_buffer.len();
+    rx_buffer.clear();
+    dropped
+}
+
+/// What a station answering a DataExpectingReply frame does next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReplyChoice {
+    /// Send the queued frame at this index, the reply to the request
+    Send(usize),
+    /// No reply queued yet, keep waiting
+    Wait,
+    /// Waited too long: ReplyPostponed for a master, CannotReply for a slave
+    GiveUp,
+}
+
+/// Find the reply to `requester`'s request in the send queue. Only an answer
+/// carrying the request's invoke ID counts, so other frames queued for the
+/// requester never take the reply slot; a request without an invoke ID can
+/// only be answered with the token.
+fn choose_reply(
+    send_queue: &VecDeque<(Vec<u8>, u8, bool)>,
+    requester: u8,
+    invoke_id: Option<u8>,
+    waited: Duration,
+    give_up_after: Duration,
+) -> ReplyChoice {
+    let reply = invoke_id.and_then(|id| {
+        send_queue.iter().position(|(data, dest, expecting_reply)| {
+            *dest == requester && !*expecting_reply && answered_invoke_id(data) == Some(id)
+        })
+    });
+    match reply {
+        Some(index) => ReplyChoice::Send(index),
+        None if waited < give_up_after => ReplyChoice::Wait,
+        None => ReplyChoice::GiveUp,
+    }
+}
+
+/// Invoke ID of a BACnet-Confirmed-Request NPDU
+fn request_invoke_id(npdu: &[u8]) -> Option<u8> {
+    let npdu = Npdu::decode(npdu).ok().filter(|npdu| !npdu.network_message)?;
+    match *npdu.payload {
+        [pdu_type, _, invoke_id, ..] if pdu_type >> 4 == 0 => Some(invoke_id),
+        _ => None,
+    }
+}
+
+/// Invoke ID of the request an NPDU answers (SimpleACK, ComplexACK,
+/// SegmentACK, Error, Reject or Abort)
+fn answered_invoke_id(npdu: &[u8]) -> Option<u8> {
+    let npdu = Npdu::decode(npdu).ok().filter(|npdu| !npdu.network_message)?;
+    match *npdu.payload {
+        [pdu_type, invoke_id, ..] if (2..=7).contains(&(pdu_type >> 4)) => Some(invoke_id),
+        _ => None,
+    }
+}
+
+/// Header CRC-8 step for each value of (CRC ^ data byte), per ASHRAE 135 Annex G.1
+static HEADER_CRC_TABLE: [u8; 256] = build_header_crc_table();
+
+/// Data CRC-16 step for each value of the low byte of (CRC ^ data byte), per Annex G.2
+static DATA_CRC_TABLE: [u16; 256] = build_data_crc_table();
+
+/// Build the header CRC table from the PARALLEL algorithm in the ASHRAE spec
+/// (polynomial X^8 + X^7 + 1) - NOT the standard bit-by-bit CRC!
+const fn build_header_crc_table() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut temp = i as u16;
 
         // Exclusive OR the terms in the table (top down)
-        // This implements the polynomial X^8 + X^7 + 1
         temp = temp
             ^ (temp << 1)
             ^ (temp << 2)
@@ -1405,28 +2452,256 @@ fn calculate_header_crc(header: &[u8]) -> u8 {
             ^ (temp << 7);
 
         // Combine bits shifted out left hand end
-        crc = ((temp & 0xfe) ^ ((temp >> 8) & 1)) as u8;
+        table[i] = ((temp & 0xfe) ^ ((temp >> 8) & 1)) as u8;
+        i += 1;
     }
+    table
+}
 
+/// Build the data CRC table: eight bit-by-bit steps of CRC-CCITT
+/// (x^16 + x^12 + x^5 + 1, reflected form 0x8408) per entry
+const fn build_data_crc_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x0001 != 0 { (crc >> 1) ^ 0x8408 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Calculate MS/TP header CRC-8 per ASHRAE 135 Annex G.1 (one table lookup per byte)
+pub fn calculate_header_crc(header: &[u8]) -> u8 {
+    let mut crc = 0xFFu8;
+    for &byte in header {
+        crc = HEADER_CRC_TABLE[(crc ^ byte) as usize];
+    }
     !crc
 }
 
-/// Calculate MS/TP data CRC-16 per ASHRAE 135 Annex G.2
-/// Uses CRC-CCITT polynomial: x^16 + x^12 + x^5 + 1 (reflected form: 0x8408)
+/// Calculate MS/TP data CRC-16 per ASHRAE 135 Annex G.2 (one table lookup per byte)
 /// NOT the same as MODBUS/CRC-16-IBM (0xA001)!
-fn calculate_data_crc(data: &[u8]) -> u16 {
+pub fn calculate_data_crc(data: &[u8]) -> u16 {
     let mut crc = 0xFFFFu16;
-
     for &byte in data {
-        crc ^= byte as u16;
-        for _ in 0..8 {
-            if crc & 0x0001 != 0 {
-                crc = (crc >> 1) ^ 0x8408;  // CRC-CCITT reflected polynomial
-            } else {
-                crc >>= 1;
-            }
+        crc = (crc >> 8) ^ DATA_CRC_TABLE[((crc ^ byte as u16) & 0xFF) as usize];
+    }
+    !crc
+}
+
+/// Time between polls of the driver. A poll that comes late (a busy
+/// receive task, lock contention) eats into the MS/TP reply and token
+/// deadlines, so the spread matters as much as the average.
+#[derive(Debug, Clone, Copy, Default)]
+struct PollTiming {
+    last_poll: Option<Instant>,
+    last_interval_us: u32,
+    sum_us: u64,
+    count: u64,
+    max_us: u32,
+    /// Change in interval from one poll to the next, smoothed with a gain
+    /// of 1/16 like RTP interarrival jitter (RFC 3550 A.8)
+    jitter_us: u32,
+}
+
+impl PollTiming {
+    fn record(&mut self, now: Instant) {
+        let Some(last) = self.last_poll.replace(now) else { return };
+        let interval_us = now.duration_since(last).as_micros().min(u32::MAX as u128) as u32;
+        if self.count > 0 {
+            let change = i64::from(interval_us.abs_diff(self.last_interval_us));
+            let jitter = i64::from(self.jitter_us);
+            self.jitter_us = (jitter + (change - jitter) / 16) as u32;
         }
+        self.last_interval_us = interval_us;
+        self.sum_us += u64::from(interval_us);
+        self.count += 1;
+        self.max_us = self.max_us.max(interval_us);
     }
 
-    !crc
+    fn avg_us(&self) -> u32 {
+        self.sum_us.checked_div(self.count).unwrap_or(0) as u32
+    }
+
+    /// Start over, timing the next interval from the last poll
+    fn reset(&mut self) {
+        *self = Self { last_poll: self.last_poll, ..Self::default() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_timing() {
+        let start = Instant::now();
+        let at = |us: u64| start + Duration::from_micros(us);
+        let mut timing = PollTiming::default();
+
+        // Steady 1 ms polls: no jitter
+        for i in 0..5 {
+            timing.record(at(i * 1000));
+        }
+        assert_eq!((timing.avg_us(), timing.max_us, timing.jitter_us), (1000, 1000, 0));
+
+        // A late poll and the catch-up after it move jitter by 1/16 of each change
+        timing.record(at(4000 + 2600));
+        assert_eq!((timing.max_us, timing.jitter_us), (2600, 100));
+        timing.record(at(6600 + 1000));
+        assert_eq!(timing.jitter_us, 100 + (1600 - 100) / 16);
+        assert_eq!(timing.avg_us(), 7600 / 6);
+
+        // Reset keeps timing from the last poll
+        timing.reset();
+        assert_eq!((timing.avg_us(), timing.max_us, timing.jitter_us), (0, 0, 0));
+        timing.record(at(7600 + 500));
+        assert_eq!((timing.avg_us(), timing.max_us, timing.jitter_us), (500, 500, 0));
+    }
+
+    /// A test against station 7 with its first probe sent at `sent_at`
+    fn started_test(count: u16, sent_at: Instant) -> (LoopbackTest, Vec<u8>) {
+        let mut test = LoopbackTest::default();
+        test.start(7, count);
+        let payload = test.next_payload();
+        test.probe_sent(payload.clone(), sent_at);
+        (test, payload)
+    }
+
+    #[test]
+    fn test_loopback_probe_match() {
+        let sent_at = Instant::now();
+        let (mut test, payload) = started_test(1, sent_at);
+        assert!(test.awaiting(7));
+        assert!(!test.awaiting(8));
+        assert!(!test.wants_probe());
+
+        test.complete(ProbeOutcome::Response(&payload), sent_at + Duration::from_micros(1500));
+        assert!(!test.probe_in_flight());
+        assert_eq!(test.result.passed, 1);
+        assert_eq!((test.result.rtt_min_us, test.result.rtt_max_us, test.result.rtt_avg_us()), (1500, 1500, 1500));
+        assert!(!test.result.in_progress);
+        assert_eq!(test.result.error_rate_pct(), 0.0);
+    }
+
+    #[test]
+    fn test_loopback_probe_mismatch() {
+        let sent_at = Instant::now();
+        let (mut test, mut payload) = started_test(3, sent_at);
+        payload[4] ^= 0x01;
+        test.complete(ProbeOutcome::Response(&payload), sent_at);
+        assert_eq!((test.result.passed, test.result.mismatched), (0, 1));
+        assert!(test.result.in_progress);
+
+        // Any other frame answering the next probe fails it as well
+        let next = test.next_payload();
+        assert_ne!(next, payload);
+        test.probe_sent(next, sent_at);
+        test.complete(ProbeOutcome::WrongReply, sent_at);
+        assert_eq!(test.result.mismatched, 2);
+        assert!(!test.probe_in_flight());
+    }
+
+    #[test]
+    fn test_loopback_probe_timeout() {
+        let sent_at = Instant::now();
+        let (mut test, payload) = started_test(1, sent_at);
+        test.complete(ProbeOutcome::NoReply, sent_at);
+        assert_eq!(test.result.timeouts, 1);
+        assert!(!test.result.in_progress);
+        assert_eq!(test.result.error_rate_pct(), 100.0);
+
+        // A late Test_Response finds nothing in flight
+        assert!(!test.awaiting(7));
+        test.complete(ProbeOutcome::Response(&payload), sent_at);
+        assert_eq!((test.result.passed, test.result.timeouts), (0, 1));
+    }
+
+    #[test]
+    fn test_loopback_stop_with_probe_in_flight() {
+        let sent_at = Instant::now();
+        let (mut test, payload) = started_test(5, sent_at);
+        test.stop();
+        assert!(!test.wants_probe());
+        assert!(!test.result.in_progress);
+
+        // The answer is still consumed, but not counted
+        assert!(test.awaiting(7));
+        test.complete(ProbeOutcome::Response(&payload), sent_at);
+        assert!(!test.probe_in_flight());
+        assert_eq!((test.result.sent, test.result.passed, test.result.timeouts), (1, 0, 0));
+    }
+
+    #[test]
+    fn test_reply_matched_by_invoke_id() {
+        // ReadProperty request from station 9 with invoke ID 0x2A
+        let request = [0x01, 0x04, 0x00, 0x05, 0x2A, 0x0C, 0x0C, 0x02, 0x00, 0x00, 0x01, 0x19, 0x4D];
+        let invoke_id = request_invoke_id(&request);
+        assert_eq!(invoke_id, Some(0x2A));
+
+        let mut queue = VecDeque::new();
+        // An I-Am and an answer to another request, both for the requester
+        queue.push_back((vec![0x01, 0x00, 0x10, 0x00, 0xC4, 0x02, 0x00, 0x00, 0x05], 9, false));
+        queue.push_back((vec![0x01, 0x00, 0x20, 0x11, 0x0F], 9, false));
+        // The ComplexACK for our request, queued for someone else
+        queue.push_back((vec![0x01, 0x00, 0x30, 0x2A, 0x0C], 4, false));
+        assert_eq!(choose_reply(&queue, 9, invoke_id, Duration::ZERO, REPLY_POSTPONE_AFTER), ReplyChoice::Wait);
+
+        queue.push_back((vec![0x01, 0x00, 0x30, 0x2A, 0x0C], 9, false));
+        assert_eq!(choose_reply(&queue, 9, invoke_id, Duration::ZERO, REPLY_POSTPONE_AFTER), ReplyChoice::Send(3));
+
+        // A routed reply (DNET present) carries the same invoke ID
+        let routed = vec![0x01, 0x20, 0x00, 0x05, 0x01, 0x07, 0xFF, 0x50, 0x2A, 0x05];
+        assert_eq!(answered_invoke_id(&routed), Some(0x2A));
+        assert_eq!(answered_invoke_id(&[0x01, 0x80, 0x01, 0x00, 0x05]), None);
+    }
+
+    #[test]
+    fn test_reply_postponed_after_150ms() {
+        let queue = VecDeque::from([(vec![0x01, 0x00, 0x10, 0x08], 9, false)]);
+        let waited = |ms| choose_reply(&queue, 9, Some(0x2A), Duration::from_millis(ms), REPLY_POSTPONE_AFTER);
+        assert_eq!(waited(0), ReplyChoice::Wait);
+        assert_eq!(waited(149), ReplyChoice::Wait);
+        assert_eq!(waited(150), ReplyChoice::GiveUp);
+
+        // A request without an invoke ID is only answered with the token
+        let reply = VecDeque::from([(vec![0x01, 0x00, 0x20, 0x2A, 0x0F], 9, false)]);
+        assert_eq!(choose_reply(&reply, 9, None, Duration::ZERO, REPLY_POSTPONE_AFTER), ReplyChoice::Wait);
+        assert_eq!(choose_reply(&reply, 9, None, REPLY_POSTPONE_AFTER, REPLY_POSTPONE_AFTER), ReplyChoice::GiveUp);
+    }
+
+    /// Frame header from `source` to 3 with a valid header CRC
+    fn header(frame_type: u8, source: u8, data_len: u16) -> Vec<u8> {
+        let mut header = vec![0x55, 0xFF, frame_type, 0x03, source];
+        header.extend_from_slice(&data_len.to_be_bytes());
+        header.push(calculate_header_crc(&header[2..7]));
+        header
+    }
+
+    #[test]
+    fn test_overrun_recovery() {
+        // A 20 byte data frame from 5 had its header and three data bytes buffered
+        let mut rx_buffer = header(0x06, 5, 20);
+        rx_buffer.extend_from_slice(&[0x01, 0x00, 0x10]);
+        assert_eq!(discard_partial_frame(&mut rx_buffer), 11);
+        assert!(rx_buffer.is_empty());
+
+        // Then come the bytes after the gap, and a token from 5
+        rx_buffer.extend_from_slice(&[0x08, 0x00, 0x05, 0xAA, 0xBB]);
+        let token = header(0x00, 5, 0);
+        rx_buffer.extend_from_slice(&token);
+
+        // The parser skips the tail of the cut frame and finds the token,
+        // instead of reading the token as the cut frame's data
+        let start = find_preamble(&rx_buffer).unwrap();
+        assert_eq!(start, 5);
+        assert_eq!(rx_buffer[start..], token[..]);
+        assert_eq!(calculate_header_crc(&rx_buffer[start + 2..start + 7]), rx_buffer[start + 7]);
+    }
 }
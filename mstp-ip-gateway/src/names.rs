@@ -0,0 +1,269 @@
+//! Device names for the portal
+//!
+//! Technicians think in names, not instance numbers. The gateway reads the
+//! Object_Name of every device it has a binding for, over the port the device
+//! announced itself on, one ReadProperty at a time (sharing the proxy invoke
+//! IDs), and keeps the answer for `NAME_TTL`. A device heard at a new address
+//! is read again; one that did not answer is tried again after
+//! `NAME_RETRY_AFTER`.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::inventory::{decode_application, decode_character_string};
+use crate::rpm_proxy::{encode_read_property, parse_read_property_result, PropertyRef, ReadResult};
+
+/// How long a name read from a device is shown before it is read again
+pub const NAME_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before reading the name of a device that did not answer
+pub const NAME_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait for each answer
+const NAME_READ_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Retransmissions of an unanswered read before it is given up
+const NAME_READ_MAX_RETRIES: u8 = 1;
+
+const OBJECT_TYPE_DEVICE: u32 = 8;
+const PROP_OBJECT_NAME: u32 = 77;
+const TAG_CHARACTER_STRING: u8 = 7;
+
+/// Where a device's name is read: its MS/TP MAC or its B/IP address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameTarget {
+    Mstp(u8),
+    Ip(SocketAddr),
+}
+
+#[derive(Debug, Clone)]
+struct NameEntry {
+    target: NameTarget,
+    name: Option<String>,
+    /// When the device last answered or gave up (None = read as soon as possible)
+    read_at: Option<Instant>,
+    answered: bool,
+}
+
+impl NameEntry {
+    fn is_due(&self, now: Instant) -> bool {
+        match self.read_at {
+            None => true,
+            Some(at) => now.duration_since(at) >= if self.answered { NAME_TTL } else { NAME_RETRY_AFTER },
+        }
+    }
+}
+
+/// The read waiting for an answer
+#[derive(Debug, Clone)]
+struct Outstanding {
+    device_instance: u32,
+    invoke_id: u8,
+    sent_at: Instant,
+    retries: u8,
+}
+
+/// Object_Name by device instance, kept fresh by the gateway
+#[derive(Debug, Default)]
+pub struct NameCache {
+    entries: HashMap<u32, NameEntry>,
+    outstanding: Option<Outstanding>,
+}
+
+impl NameCache {
+    /// Note where a device is; a new device, or one at a new address, is read
+    pub fn observe(&mut self, device_instance: u32, target: NameTarget) {
+        match self.entries.get_mut(&device_instance) {
+            Some(entry) if entry.target == target => {}
+            Some(entry) => {
+                entry.target = target;
+                entry.read_at = None;
+            }
+            None => {
+                self.entries.insert(device_instance, NameEntry { target, name: None, read_at: None, answered: false });
+            }
+        }
+    }
+
+    /// Drop a device that is no longer heard
+    pub fn forget(&mut self, device_instance: u32) {
+        self.entries.remove(&device_instance);
+    }
+
+    /// Read every name again, e.g. after devices were renamed
+    pub fn invalidate_all(&mut self) {
+        for entry in self.entries.values_mut() {
+            entry.read_at = None;
+        }
+    }
+
+    /// Known names by device instance
+    pub fn names(&self) -> HashMap<u32, String> {
+        self.entries
+            .iter()
+            .filter_map(|(instance, entry)| entry.name.clone().map(|name| (*instance, name)))
+            .collect()
+    }
+
+    /// Devices whose name has not been read yet
+    pub fn pending(&self) -> usize {
+        self.entries.values().filter(|e| e.read_at.is_none()).count()
+    }
+
+    /// The ReadProperty to send now, as (APDU, target)
+    ///
+    /// Only targets `ready` accepts are read, so the gateway can hold MS/TP
+    /// reads back while the ring is busy. A new read takes `next_invoke_id`
+    /// and advances it; a retransmission reuses its own.
+    pub fn poll(
+        &mut self,
+        now: Instant,
+        next_invoke_id: &mut u8,
+        ready: impl Fn(NameTarget) -> bool,
+    ) -> Option<(Vec<u8>, NameTarget)> {
+        if let Some(outstanding) = self.outstanding.as_mut() {
+            if now.duration_since(outstanding.sent_at) < NAME_READ_TIMEOUT {
+                return None;
+            }
+            let Some(entry) = self.entries.get_mut(&outstanding.device_instance) else {
+                self.outstanding = None;
+                return None;
+            };
+            if outstanding.retries >= NAME_READ_MAX_RETRIES {
+                entry.read_at = Some(now);
+                entry.answered = false;
+                self.outstanding = None;
+                return None;
+            }
+            if !ready(entry.target) {
+                return None;
+            }
+            outstanding.retries += 1;
+            outstanding.sent_at = now;
+            return Some((request(outstanding.invoke_id, outstanding.device_instance), entry.target));
+        }
+
+        let (&device_instance, entry) = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.is_due(now) && ready(e.target))
+            .min_by_key(|(instance, e)| (e.read_at, **instance))?;
+        let invoke_id = *next_invoke_id;
+        *next_invoke_id = invoke_id.wrapping_add(1);
+        self.outstanding = Some(Outstanding { device_instance, invoke_id, sent_at: now, retries: 0 });
+        Some((request(invoke_id, device_instance), entry.target))
+    }
+
+    /// Whether an APDU from `source` answers the outstanding read
+    pub fn matches(&self, source: NameTarget, apdu: &[u8]) -> bool {
+        let Some(outstanding) = &self.outstanding else { return false };
+        self.entries.get(&outstanding.device_instance).is_some_and(|e| e.target == source)
+            && apdu.get(1) == Some(&outstanding.invoke_id)
+            && matches!(apdu[0] >> 4, 3 | 5 | 6 | 7)
+    }
+
+    /// Record the device's answer; an Error, Reject or Abort clears the name
+    pub fn record(&mut self, apdu: &[u8], now: Instant) {
+        let Some(outstanding) = self.outstanding.take() else { return };
+        let Some(entry) = self.entries.get_mut(&outstanding.device_instance) else { return };
+        entry.read_at = Some(now);
+        entry.answered = true;
+        entry.name = match parse_read_property_result(apdu) {
+            Some(ReadResult::Value(value)) => {
+                decode_application(&value, TAG_CHARACTER_STRING).map(decode_character_string)
+            }
+            _ => None,
+        };
+    }
+}
+
+/// ReadProperty of the Device object's Object_Name
+fn request(invoke_id: u8, device_instance: u32) -> Vec<u8> {
+    encode_read_property(
+        invoke_id,
+        &PropertyRef {
+            object_id: (OBJECT_TYPE_DEVICE << 22) | device_instance,
+            property_id: PROP_OBJECT_NAME,
+            array_index: None,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ReadProperty-ACK for Device `instance` Object_Name with an ANSI string
+    fn name_ack(invoke_id: u8, instance: u32, name: &str) -> Vec<u8> {
+        let mut apdu = vec![0x30, invoke_id, 0x0C, 0x0C];
+        apdu.extend_from_slice(&((OBJECT_TYPE_DEVICE << 22) | instance).to_be_bytes());
+        apdu.extend_from_slice(&[0x19, PROP_OBJECT_NAME as u8, 0x3E, 0x75, name.len() as u8 + 1, 0x00]);
+        apdu.extend_from_slice(name.as_bytes());
+        apdu.push(0x3F);
+        apdu
+    }
+
+    #[test]
+    fn test_name_read_over_the_device_port_and_cached() {
+        let mut cache = NameCache::default();
+        let now = Instant::now();
+        let mut invoke_id = 7;
+        cache.observe(1234, NameTarget::Mstp(5));
+
+        let (apdu, target) = cache.poll(now, &mut invoke_id, |_| true).unwrap();
+        assert_eq!(target, NameTarget::Mstp(5));
+        assert_eq!(apdu[2], 7);
+        assert_eq!(invoke_id, 8);
+        assert!(!cache.matches(NameTarget::Mstp(6), &name_ack(7, 1234, "AHU-1")));
+        assert!(cache.matches(NameTarget::Mstp(5), &name_ack(7, 1234, "AHU-1")));
+        cache.record(&name_ack(7, 1234, "AHU-1"), now);
+        assert_eq!(cache.names().get(&1234).map(String::as_str), Some("AHU-1"));
+
+        // Fresh until the TTL runs out, or the device moves
+        assert!(cache.poll(now + NAME_TTL / 2, &mut invoke_id, |_| true).is_none());
+        assert!(cache.poll(now + NAME_TTL, &mut invoke_id, |_| true).is_some());
+    }
+
+    #[test]
+    fn test_new_address_and_invalidate_trigger_reads() {
+        let mut cache = NameCache::default();
+        let now = Instant::now();
+        let mut invoke_id = 0;
+        cache.observe(10, NameTarget::Mstp(1));
+        cache.poll(now, &mut invoke_id, |_| true).unwrap();
+        cache.record(&name_ack(0, 10, "VAV-10"), now);
+        assert_eq!(cache.pending(), 0);
+
+        cache.observe(10, NameTarget::Mstp(1));
+        assert_eq!(cache.pending(), 0, "same address keeps the name fresh");
+        cache.observe(10, NameTarget::Mstp(2));
+        assert_eq!(cache.pending(), 1);
+        assert_eq!(cache.names().get(&10).map(String::as_str), Some("VAV-10"), "old name shown until re-read");
+
+        let (_, target) = cache.poll(now, &mut invoke_id, |_| true).unwrap();
+        assert_eq!(target, NameTarget::Mstp(2));
+        cache.record(&name_ack(1, 10, "VAV-10b"), now);
+        cache.invalidate_all();
+        assert_eq!(cache.pending(), 1);
+    }
+
+    #[test]
+    fn test_unanswered_read_is_retried_later_and_held_while_not_ready() {
+        let mut cache = NameCache::default();
+        let now = Instant::now();
+        let mut invoke_id = 0;
+        cache.observe(20, NameTarget::Mstp(3));
+
+        assert!(cache.poll(now, &mut invoke_id, |_| false).is_none(), "MS/TP busy");
+        let (first, _) = cache.poll(now, &mut invoke_id, |_| true).unwrap();
+        let (retry, _) = cache.poll(now + NAME_READ_TIMEOUT, &mut invoke_id, |_| true).unwrap();
+        assert_eq!(first, retry, "retransmission reuses the invoke ID");
+        let given_up = now + NAME_READ_TIMEOUT * 2;
+        assert!(cache.poll(given_up, &mut invoke_id, |_| true).is_none());
+        assert!(cache.names().is_empty());
+
+        assert!(cache.poll(given_up + NAME_RETRY_AFTER / 2, &mut invoke_id, |_| true).is_none());
+        assert!(cache.poll(given_up + NAME_RETRY_AFTER, &mut invoke_id, |_| true).is_some());
+    }
+}
@@ -0,0 +1,243 @@
+//! Network number quality and learning
+//!
+//! A port's network number is either configured or learned from a
+//! Network-Number-Is sent by a router whose number is configured (Clause
+//! 6.4.19-20). Until it is known the gateway runs pass-through: traffic is
+//! still carried between the ports, but no announcement claims the unknown
+//! network, so other routers never learn a route to a made-up number. The
+//! quality is shown as Network_Number_Quality on the Network Port objects.
+//!
+//! Network numbers other routers announce on the IP side are also noted, so
+//! the setup wizard can suggest numbers nobody on the site is using yet.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+
+pub const NL_WHAT_IS_NETWORK_NUMBER: u8 = 0x12;
+pub const NL_NETWORK_NUMBER_IS: u8 = 0x13;
+
+/// Most announced networks remembered; further ones are ignored
+pub const MAX_HEARD_NETWORKS: usize = 64;
+
+/// BACnetNetworkNumberQuality
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NetworkNumberQuality {
+    #[default]
+    Unknown = 0,
+    Learned = 1,
+    LearnedConfigured = 2,
+    Configured = 3,
+}
+
+impl NetworkNumberQuality {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetworkNumberQuality::Unknown => "unknown",
+            NetworkNumberQuality::Learned => "learned",
+            NetworkNumberQuality::LearnedConfigured => "learned-configured",
+            NetworkNumberQuality::Configured => "configured",
+        }
+    }
+}
+
+/// A port's network number and where it came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NetworkNumber {
+    /// Placeholder while the quality is Unknown
+    pub number: u16,
+    pub quality: NetworkNumberQuality,
+}
+
+/// What a Network-Number-Is did to a port's number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LearnOutcome {
+    Unchanged,
+    Learned(u16),
+    /// A router with a configured number disagrees with ours
+    Conflict(u16),
+}
+
+impl NetworkNumber {
+    pub fn configured(number: u16) -> Self {
+        Self { number, quality: NetworkNumberQuality::Configured }
+    }
+
+    /// Not known yet; `placeholder` carries traffic until it is learned
+    pub fn unknown(placeholder: u16) -> Self {
+        Self { number: placeholder, quality: NetworkNumberQuality::Unknown }
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.quality != NetworkNumberQuality::Unknown
+    }
+
+    /// The number, if it may be announced
+    pub fn known(&self) -> Option<u16> {
+        self.is_known().then_some(self.number)
+    }
+
+    /// Apply a Network-Number-Is heard on this port. Only a sender with a
+    /// configured number is learned from; a configured port never changes.
+    pub fn learn(&mut self, number: u16, sender_configured: bool) -> LearnOutcome {
+        if !sender_configured || (number == self.number && self.is_known()) {
+            return LearnOutcome::Unchanged;
+        }
+        if self.quality == NetworkNumberQuality::Configured {
+            return LearnOutcome::Conflict(number);
+        }
+        *self = Self { number, quality: NetworkNumberQuality::Learned };
+        LearnOutcome::Learned(number)
+    }
+}
+
+impl fmt::Display for NetworkNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.known() {
+            Some(number) => write!(f, "{} ({})", number, self.quality.as_str()),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+/// How the gateway routes between its ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RouterMode {
+    #[default]
+    Routing,
+    /// A port's network number is not known yet
+    PassThrough,
+}
+
+impl RouterMode {
+    pub fn of(ports: &[NetworkNumber]) -> Self {
+        if ports.iter().all(NetworkNumber::is_known) {
+            RouterMode::Routing
+        } else {
+            RouterMode::PassThrough
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouterMode::Routing => "routing",
+            RouterMode::PassThrough => "pass-through",
+        }
+    }
+}
+
+/// Network numbers heard from other routers on the IP side
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeardNetworks {
+    /// Networks in I-Am-Router-To-Network, with the router that announced each last
+    pub reachable: BTreeMap<u16, SocketAddr>,
+    /// The IP network's own number, from a router with a configured number
+    pub ip_network: Option<u16>,
+}
+
+impl HeardNetworks {
+    /// Note the networks listed in an I-Am-Router-To-Network body (after the message type)
+    pub fn note_announcement(&mut self, router: SocketAddr, body: &[u8]) {
+        for pair in body.chunks_exact(2) {
+            let network = u16::from_be_bytes([pair[0], pair[1]]);
+            if self.reachable.len() < MAX_HEARD_NETWORKS || self.reachable.contains_key(&network) {
+                self.reachable.insert(network, router);
+            }
+        }
+    }
+
+    /// Network numbers for our ports that clash with nothing heard: the
+    /// current ones where they are free, otherwise the next free numbers.
+    /// The IP port takes the number its routers announce.
+    pub fn suggest(&self, mstp_network: u16, ip_network: u16) -> (u16, u16) {
+        let ip = match self.ip_network {
+            Some(number) => number,
+            None => self.next_free(ip_network, None),
+        };
+        (self.next_free(mstp_network, Some(ip)), ip)
+    }
+
+    /// `start` if nobody announces it, else the next number nobody announces
+    fn next_free(&self, start: u16, also_taken: Option<u16>) -> u16 {
+        let taken = |n: u16| self.reachable.contains_key(&n) || Some(n) == also_taken;
+        let mut number = start.clamp(1, 65534);
+        // At most MAX_HEARD_NETWORKS + 1 numbers are taken, so this ends quickly
+        while taken(number) {
+            number = if number >= 65534 { 1 } else { number + 1 };
+        }
+        number
+    }
+}
+
+/// Network-Number-Is message body: the number and whether it is configured
+pub fn network_number_is(number: NetworkNumber) -> Vec<u8> {
+    let [high, low] = number.number.to_be_bytes();
+    let configured = number.quality == NetworkNumberQuality::Configured;
+    vec![NL_NETWORK_NUMBER_IS, high, low, configured as u8]
+}
+
+/// Parse a Network-Number-Is message body (after the message type)
+pub fn parse_network_number_is(body: &[u8]) -> Option<(u16, bool)> {
+    match body {
+        [high, low, flag, ..] => Some((u16::from_be_bytes([*high, *low]), *flag == 1)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_learns_only_from_configured_senders() {
+        let mut port = NetworkNumber::unknown(10001);
+        assert_eq!(port.known(), None);
+        assert_eq!(port.learn(7, false), LearnOutcome::Unchanged);
+        assert_eq!(port.learn(7, true), LearnOutcome::Learned(7));
+        assert_eq!(port, NetworkNumber { number: 7, quality: NetworkNumberQuality::Learned });
+        assert_eq!(port.learn(7, true), LearnOutcome::Unchanged);
+        // The network was renumbered
+        assert_eq!(port.learn(8, true), LearnOutcome::Learned(8));
+    }
+
+    #[test]
+    fn test_configured_number_reports_conflicts() {
+        let mut port = NetworkNumber::configured(2);
+        assert_eq!(port.learn(2, true), LearnOutcome::Unchanged);
+        assert_eq!(port.learn(3, false), LearnOutcome::Unchanged);
+        assert_eq!(port.learn(3, true), LearnOutcome::Conflict(3));
+        assert_eq!(port.known(), Some(2));
+    }
+
+    #[test]
+    fn test_router_mode_and_message() {
+        let mstp = NetworkNumber::configured(1);
+        let mut ip = NetworkNumber::unknown(10001);
+        assert_eq!(RouterMode::of(&[mstp, ip]), RouterMode::PassThrough);
+        ip.learn(2001, true);
+        assert_eq!(RouterMode::of(&[mstp, ip]), RouterMode::Routing);
+
+        let message = network_number_is(mstp);
+        assert_eq!(message, vec![NL_NETWORK_NUMBER_IS, 0x00, 0x01, 1]);
+        assert_eq!(parse_network_number_is(&message[1..]), Some((1, true)));
+        assert_eq!(network_number_is(ip)[3], 0);
+        assert_eq!(parse_network_number_is(&[0x00]), None);
+    }
+
+    #[test]
+    fn test_suggests_numbers_nobody_announces() {
+        let router: SocketAddr = "10.0.0.9:47808".parse().unwrap();
+        let mut heard = HeardNetworks::default();
+        assert_eq!(heard.suggest(65001, 10001), (65001, 10001));
+
+        // Another gateway left at the defaults, and the usual 1/2001 router
+        heard.note_announcement(router, &[0xFD, 0xE9, 0xFD, 0xEA, 0x00, 0x01]);
+        assert_eq!(heard.suggest(65001, 10001), (65003, 10001));
+        assert_eq!(heard.suggest(1, 65001), (2, 65003));
+        assert_eq!(heard.reachable.get(&1), Some(&router));
+
+        // The IP port follows the number its routers give it
+        heard.ip_network = Some(2001);
+        assert_eq!(heard.suggest(2001, 10001), (2002, 2001));
+    }
+}
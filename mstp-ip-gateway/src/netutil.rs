@@ -0,0 +1,297 @@
+//! Network utilities for troubleshooting the BACnet/IP side
+//!
+//! ICMP ping (esp_ping) to the hosts the B/IP port depends on: BDT peers,
+//! the default gateway and an optionally configured BBMD. A peer that
+//! answers ping but not BACnet points at BBMD/BDT configuration; one that
+//! does not answer at all points at IP routing, firewalls or wiring.
+//!
+//! Also runs the HTTP side of pairing with a peer BACman (see `pairing`).
+
+use embedded_svc::http::client::Client;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::client::{Configuration as HttpClientConfig, EspHttpConnection};
+use esp_idf_svc::ping::{Configuration as PingConfig, EspPing, Reply};
+use log::{info, warn};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::events::{self, EventCategory, Severity};
+use crate::pairing::{Pairing, PeerInfo, PAIR_API_PATH};
+use crate::web::{local_pair_info, WebState};
+
+/// Echo requests sent to each host per check
+pub const PING_COUNT: u32 = 4;
+
+/// Time to wait for each echo reply
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Spacing between echo requests to one host
+const PING_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Stack size for the reachability check thread
+const CHECK_STACK_SIZE: usize = 6144;
+
+/// Time allowed for the peer to answer a pairing request
+const PAIR_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest pairing answer read from the peer
+const MAX_PAIR_ANSWER_LEN: usize = 256;
+
+/// Stack size for the pairing thread (plain HTTP, no TLS)
+const PAIR_STACK_SIZE: usize = 8192;
+
+/// Why a host is being checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingRole {
+    BdtPeer,
+    DefaultGateway,
+    Bbmd,
+}
+
+impl std::fmt::Display for PingRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PingRole::BdtPeer => write!(f, "BDT peer"),
+            PingRole::DefaultGateway => write!(f, "Default gateway"),
+            PingRole::Bbmd => write!(f, "BBMD"),
+        }
+    }
+}
+
+/// Outcome of pinging one host
+#[derive(Debug, Clone)]
+pub struct PingResult {
+    pub role: PingRole,
+    pub addr: Ipv4Addr,
+    pub transmitted: u32,
+    pub received: u32,
+    pub min_ms: u32,
+    pub avg_ms: u32,
+    pub max_ms: u32,
+    pub checked_at: Instant,
+    /// Set when the ping session could not be run at all
+    pub error: Option<String>,
+}
+
+impl PingResult {
+    pub fn reachable(&self) -> bool {
+        self.received > 0
+    }
+
+    pub fn loss_pct(&self) -> u32 {
+        if self.transmitted == 0 {
+            return 100;
+        }
+        (self.transmitted - self.received.min(self.transmitted)) * 100 / self.transmitted
+    }
+
+    /// One-line summary for the portal
+    pub fn summary(&self) -> String {
+        match &self.error {
+            Some(e) => format!("error: {}", e),
+            None if self.reachable() => format!(
+                "{}/{} replies, {}/{}/{} ms",
+                self.received, self.transmitted, self.min_ms, self.avg_ms, self.max_ms
+            ),
+            None => format!("no reply ({} sent)", self.transmitted),
+        }
+    }
+}
+
+/// Ping one host, blocking for up to `PING_COUNT` timeouts
+pub fn ping_host(role: PingRole, addr: Ipv4Addr) -> PingResult {
+    let conf = PingConfig {
+        count: PING_COUNT,
+        interval: PING_INTERVAL,
+        timeout: PING_TIMEOUT,
+        ..Default::default()
+    };
+    let rtts = Mutex::new(Vec::new());
+
+    let mut result = PingResult {
+        role,
+        addr,
+        transmitted: 0,
+        received: 0,
+        min_ms: 0,
+        avg_ms: 0,
+        max_ms: 0,
+        checked_at: Instant::now(),
+        error: None,
+    };
+
+    let outcome = EspPing::default().ping_details(addr.octets().into(), &conf, |_summary, reply| {
+        if let Reply::Success(info) = reply {
+            if let Ok(mut rtts) = rtts.lock() {
+                rtts.push(info.elapsed_time.as_millis() as u32);
+            }
+        }
+    });
+    match outcome {
+        Ok(summary) => {
+            result.transmitted = summary.transmitted;
+            result.received = summary.received;
+            let rtts = rtts.into_inner().unwrap_or_default();
+            if !rtts.is_empty() {
+                result.min_ms = rtts.iter().copied().min().unwrap_or(0);
+                result.max_ms = rtts.iter().copied().max().unwrap_or(0);
+                result.avg_ms = rtts.iter().sum::<u32>() / rtts.len() as u32;
+            }
+        }
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result
+}
+
+/// Hosts to check: every BDT peer, the default gateway and the configured BBMD
+pub fn reachability_targets(state: &WebState) -> Vec<(PingRole, Ipv4Addr)> {
+    let mut targets: Vec<(PingRole, Ipv4Addr)> = Vec::new();
+    let mut add = |role, addr: Ipv4Addr| {
+        if !addr.is_unspecified() && !targets.iter().any(|(_, a)| *a == addr) {
+            targets.push((role, addr));
+        }
+    };
+    if let Some(gateway) = state.default_gateway {
+        add(PingRole::DefaultGateway, gateway);
+    }
+    if let Some(bbmd) = state.config.bbmd_address {
+        add(PingRole::Bbmd, bbmd);
+    }
+    for (peer, _mask) in &state.bdt_entries {
+        if let std::net::IpAddr::V4(ip) = peer.ip() {
+            add(PingRole::BdtPeer, ip);
+        }
+    }
+    targets
+}
+
+/// Ping all reachability targets on a background thread, storing each
+/// result in the web state as it completes
+pub fn spawn_reachability_check(web_state: Arc<Mutex<WebState>>) -> anyhow::Result<()> {
+    let targets = {
+        let mut state = web_state.lock().unwrap();
+        state.reachability_in_progress = true;
+        reachability_targets(&state)
+    };
+    info!("Reachability check started for {} host(s)", targets.len());
+
+    let spawned = thread::Builder::new()
+        .stack_size(CHECK_STACK_SIZE)
+        .spawn({
+            let web_state = Arc::clone(&web_state);
+            move || {
+                for (role, addr) in targets {
+                    let result = ping_host(role, addr);
+                    if !result.reachable() {
+                        warn!("{} {} unreachable: {}", role, addr, result.summary());
+                        events::record(
+                            EventCategory::Peer,
+                            Severity::Warning,
+                            &format!("{} {} unreachable: {}", role, addr, result.summary()),
+                        );
+                    }
+                    if let Ok(mut state) = web_state.lock() {
+                        state.reachability.retain(|r| r.addr != addr);
+                        state.reachability.push(result);
+                    }
+                }
+                if let Ok(mut state) = web_state.lock() {
+                    state.reachability_in_progress = false;
+                }
+                info!("Reachability check finished");
+            }
+        });
+    if let Err(e) = spawned {
+        web_state.lock().unwrap().reachability_in_progress = false;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Pair with the BACman at `peer_ip` on a background thread: send it this
+/// gateway's details, and once it accepts, queue its BDT entry and static
+/// route for the main loop, which follows up with the test broadcast
+pub fn spawn_pairing(web_state: Arc<Mutex<WebState>>, peer_ip: Ipv4Addr, authorization: String) -> anyhow::Result<()> {
+    let local = {
+        let mut state = web_state.lock().unwrap();
+        let Some(local) = local_pair_info(&state) else { anyhow::bail!("no IP address yet") };
+        state.pairing = Some(Pairing::new(peer_ip, Instant::now()));
+        local
+    };
+    info!("Pairing with {} started", peer_ip);
+
+    let spawned = thread::Builder::new()
+        .stack_size(PAIR_STACK_SIZE)
+        .spawn({
+            let web_state = Arc::clone(&web_state);
+            move || {
+                let answer = request_pairing(peer_ip, &authorization, &local.encode());
+                let mut guard = web_state.lock().unwrap();
+                let state = &mut *guard;
+                let Some(pairing) = state.pairing.as_mut() else { return };
+                let outcome = answer.and_then(|peer| {
+                    pairing.accepted(&local, peer.clone()).map_err(anyhow::Error::msg)?;
+                    Ok(peer)
+                });
+                match outcome {
+                    Ok(peer) => {
+                        info!("Peer {} accepted pairing, MS/TP network {}", peer.bip_address, peer.mstp_network);
+                        state.pair_install_request = Some((peer, true));
+                    }
+                    Err(e) => {
+                        warn!("Pairing with {} failed: {}", peer_ip, e);
+                        events::record(EventCategory::Peer, Severity::Warning, &format!("Pairing with {} failed: {}", peer_ip, e));
+                        pairing.fail(e.to_string());
+                    }
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        if let Some(pairing) = web_state.lock().unwrap().pairing.as_mut() {
+            pairing.fail("could not start the pairing task");
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// POST this gateway's details to the peer's pairing endpoint and parse its answer
+fn request_pairing(peer_ip: Ipv4Addr, authorization: &str, body: &str) -> anyhow::Result<PeerInfo> {
+    let connection = EspHttpConnection::new(&HttpClientConfig {
+        timeout: Some(PAIR_REQUEST_TIMEOUT),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+
+    let url = format!("http://{}{}", peer_ip, PAIR_API_PATH);
+    let content_length = body.len().to_string();
+    let headers = [
+        ("Authorization", authorization),
+        ("Content-Type", "application/x-www-form-urlencoded"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    let mut request = client.post(&url, &headers)?;
+    request.write_all(body.as_bytes())?;
+    request.flush()?;
+    let mut response = request.submit()?;
+
+    let status = response.status();
+    let mut answer = [0u8; MAX_PAIR_ANSWER_LEN];
+    let mut len = 0;
+    while len < answer.len() {
+        match response.read(&mut answer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let answer = String::from_utf8_lossy(&answer[..len]);
+    match status {
+        200 => PeerInfo::parse(&answer).ok_or_else(|| anyhow::anyhow!("peer sent an unreadable answer")),
+        401 | 403 => anyhow::bail!("peer refused the credentials"),
+        404 => anyhow::bail!("peer does not support pairing"),
+        409 => anyhow::bail!("peer refused: {}", answer.trim()),
+        _ => anyhow::bail!("peer answered HTTP {}", status),
+    }
+}
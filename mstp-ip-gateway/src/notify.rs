@@ -0,0 +1,337 @@
+//! Webhook notifications for integrators
+//!
+//! POSTs a JSON document to a configured URL when something changes on the
+//! trunk: a device answering a Who-Is scan for the first time, a known
+//! device no longer answering, routing and CRC errors crossing a threshold
+//! within `ERROR_WINDOW`, and the result of scheduled discovery scans.
+//! Device changes are judged scan against scan (ranged sweeps cover only
+//! part of the instance space and are left out), so the first scan after
+//! boot only sets the baseline. Events queue in the web state (oldest
+//! dropped beyond `MAX_PENDING_NOTIFICATIONS`) and a background thread
+//! delivers them one at a time, backing off like the heartbeat on failure.
+
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::auth;
+use crate::clock;
+use crate::heartbeat;
+use crate::web::{json_escape, WebState};
+
+/// Events held while the endpoint is unreachable
+pub const MAX_PENDING_NOTIFICATIONS: usize = 16;
+
+/// Window over which errors are counted against the threshold
+pub const ERROR_WINDOW: Duration = Duration::from_secs(600);
+
+/// Longest scheduled discovery interval (one week)
+pub const MAX_SCAN_INTERVAL_HOURS: u32 = 168;
+
+/// Stack size for the notification thread (TLS handshake needs headroom)
+const NOTIFY_STACK_SIZE: usize = 12288;
+
+/// Something worth telling the integrator about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyEvent {
+    /// A device answered a scan for the first time
+    DeviceAppeared { device_instance: u32, mac_address: u8 },
+    /// A device from the previous scan did not answer this one
+    DeviceDisappeared { device_instance: u32, mac_address: u8 },
+    /// Routing and CRC errors within `ERROR_WINDOW` reached the threshold
+    ErrorThreshold { errors: u64, threshold: u32 },
+    /// Result of a scheduled discovery scan
+    DiscoveryReport { scan_id: u32, devices: usize, appeared: usize, disappeared: usize },
+}
+
+impl NotifyEvent {
+    /// Event name in the payload
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotifyEvent::DeviceAppeared { .. } => "device_appeared",
+            NotifyEvent::DeviceDisappeared { .. } => "device_disappeared",
+            NotifyEvent::ErrorThreshold { .. } => "error_threshold",
+            NotifyEvent::DiscoveryReport { .. } => "discovery_report",
+        }
+    }
+
+    /// Event-specific JSON members, without braces
+    pub fn details_json(&self) -> String {
+        match self {
+            NotifyEvent::DeviceAppeared { device_instance, mac_address }
+            | NotifyEvent::DeviceDisappeared { device_instance, mac_address } => {
+                format!(r#""device_instance":{},"mac_address":{}"#, device_instance, mac_address)
+            }
+            NotifyEvent::ErrorThreshold { errors, threshold } => format!(
+                r#""errors":{},"threshold":{},"window_secs":{}"#,
+                errors,
+                threshold,
+                ERROR_WINDOW.as_secs()
+            ),
+            NotifyEvent::DiscoveryReport { scan_id, devices, appeared, disappeared } => format!(
+                r#""scan_id":{},"devices":{},"appeared":{},"disappeared":{}"#,
+                scan_id, devices, appeared, disappeared
+            ),
+        }
+    }
+}
+
+/// A queued event and when it happened
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: NotifyEvent,
+    pub at: Instant,
+}
+
+/// Change detection, the outgoing queue and delivery status
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    pending: VecDeque<Notification>,
+    /// (instance, MAC) pairs that answered the last finished scan
+    roster: Option<Vec<(u32, u8)>>,
+    /// Start of the current error window and the error total at that time
+    error_window: Option<(Instant, u64)>,
+    error_alarm_sent: bool,
+    /// Notifications accepted by the endpoint
+    pub sent: u64,
+    /// Notifications dropped because the queue was full
+    pub dropped: u64,
+    /// Failed attempts since the last success
+    pub consecutive_failures: u32,
+    /// When the endpoint last accepted a notification
+    pub last_success: Option<Instant>,
+    /// Description of the most recent failure (empty after a success)
+    pub last_error: String,
+}
+
+impl Notifier {
+    /// Queue an event, dropping the oldest when full
+    pub fn push(&mut self, event: NotifyEvent, now: Instant) {
+        if self.pending.len() >= MAX_PENDING_NOTIFICATIONS {
+            self.pending.pop_front();
+            self.dropped += 1;
+        }
+        self.pending.push_back(Notification { event, at: now });
+    }
+
+    /// Compare the devices that answered a finished scan with the previous
+    /// one; `report` adds a discovery summary (scheduled scans)
+    pub fn scan_finished(&mut self, scan_id: u32, answered: &[(u32, u8)], report: bool, now: Instant) {
+        let (mut appeared, mut disappeared) = (0, 0);
+        if let Some(previous) = self.roster.take() {
+            for &(device_instance, mac_address) in answered {
+                if !previous.iter().any(|(i, _)| *i == device_instance) {
+                    appeared += 1;
+                    self.push(NotifyEvent::DeviceAppeared { device_instance, mac_address }, now);
+                }
+            }
+            for &(device_instance, mac_address) in &previous {
+                if !answered.iter().any(|(i, _)| *i == device_instance) {
+                    disappeared += 1;
+                    self.push(NotifyEvent::DeviceDisappeared { device_instance, mac_address }, now);
+                }
+            }
+        }
+        self.roster = Some(answered.to_vec());
+        if report {
+            self.push(
+                NotifyEvent::DiscoveryReport { scan_id, devices: answered.len(), appeared, disappeared },
+                now,
+            );
+        }
+    }
+
+    /// Raise one alarm per window once `total` errors have grown by
+    /// `threshold` since the window started (0 = off)
+    pub fn check_errors(&mut self, total: u64, threshold: u32, now: Instant) {
+        let (started, base) = match self.error_window {
+            Some((started, base)) if now.duration_since(started) < ERROR_WINDOW && total >= base => (started, base),
+            _ => {
+                self.error_alarm_sent = false;
+                (now, total)
+            }
+        };
+        self.error_window = Some((started, base));
+        if threshold > 0 && !self.error_alarm_sent && total - base >= u64::from(threshold) {
+            self.error_alarm_sent = true;
+            self.push(NotifyEvent::ErrorThreshold { errors: total - base, threshold }, now);
+        }
+    }
+
+    /// Oldest undelivered notification
+    pub fn peek(&self) -> Option<&Notification> {
+        self.pending.front()
+    }
+
+    /// Remove the oldest notification after delivery
+    pub fn pop_delivered(&mut self) {
+        self.pending.pop_front();
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Discard undelivered notifications (webhook switched off)
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Spawn the notification thread. Settings are read from the web state on
+/// each cycle, so the task idles while the webhook is off or WiFi is down.
+pub fn spawn_notify_task(web_state: Arc<Mutex<WebState>>) -> anyhow::Result<()> {
+    thread::Builder::new()
+        .stack_size(NOTIFY_STACK_SIZE)
+        .spawn(move || notify_task(web_state))?;
+    Ok(())
+}
+
+fn notify_task(web_state: Arc<Mutex<WebState>>) {
+    info!("Webhook notification task started");
+    let mut next_attempt = Instant::now();
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        if Instant::now() < next_attempt {
+            continue;
+        }
+
+        // Snapshot the next notification without holding the lock during I/O
+        let (url, payload, failures) = {
+            let mut state = match web_state.lock() {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            if !state.config.webhook_enabled || state.config.webhook_url.is_empty() {
+                state.notifier.clear_pending();
+                continue;
+            }
+            if !state.wifi_connected {
+                continue;
+            }
+            let Some(notification) = state.notifier.peek() else { continue };
+            (
+                state.config.webhook_url.clone(),
+                build_payload(&state, notification),
+                state.notifier.consecutive_failures,
+            )
+        };
+
+        let result = heartbeat::post_json(&url, &payload);
+
+        let mut state = match web_state.lock() {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let notifier = &mut state.notifier;
+        match result {
+            Ok(()) => {
+                if failures > 0 {
+                    info!("Webhook delivered after {} failed attempts", failures);
+                }
+                notifier.pop_delivered();
+                notifier.sent += 1;
+                notifier.consecutive_failures = 0;
+                notifier.last_success = Some(Instant::now());
+                notifier.last_error.clear();
+            }
+            Err(e) => {
+                let failures = failures.saturating_add(1);
+                let delay = heartbeat::backoff_delay(failures, u32::from_le_bytes(auth::random_bytes()));
+                warn!("Webhook to {} failed ({}), retrying in {}s", url, e, delay.as_secs());
+                notifier.consecutive_failures = failures;
+                notifier.last_error = e.to_string();
+                next_attempt = Instant::now() + delay;
+            }
+        }
+    }
+}
+
+/// Build the webhook JSON document for one notification
+fn build_payload(state: &WebState, notification: &Notification) -> String {
+    format!(
+        r#"{{"event":"{}",{},"site_name":"{}","device_name":"{}","device_instance":{},"ip_address":"{}","mstp_network":{},"uptime_secs":{},"timestamp":{}}}"#,
+        notification.event.as_str(),
+        notification.event.details_json(),
+        json_escape(&state.config.site_name),
+        json_escape(&state.config.device_name),
+        state.config.device_instance,
+        state.ip_address,
+        state.config.mstp_network,
+        state.uptime_secs(),
+        clock::utc_at(notification.at)
+            .map(|t| format!(r#""{}""#, clock::format_utc(t)))
+            .unwrap_or_else(|| "null".to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(notifier: &mut Notifier) -> Vec<NotifyEvent> {
+        let mut events = Vec::new();
+        while let Some(n) = notifier.peek() {
+            events.push(n.event.clone());
+            notifier.pop_delivered();
+        }
+        events
+    }
+
+    #[test]
+    fn test_scan_changes_against_previous_scan() {
+        let now = Instant::now();
+        let mut notifier = Notifier::default();
+
+        // The first scan only sets the baseline
+        notifier.scan_finished(1, &[(100, 5), (200, 6)], false, now);
+        assert_eq!(notifier.pending_len(), 0);
+
+        notifier.scan_finished(2, &[(100, 5), (300, 7)], true, now);
+        assert_eq!(
+            events(&mut notifier),
+            vec![
+                NotifyEvent::DeviceAppeared { device_instance: 300, mac_address: 7 },
+                NotifyEvent::DeviceDisappeared { device_instance: 200, mac_address: 6 },
+                NotifyEvent::DiscoveryReport { scan_id: 2, devices: 2, appeared: 1, disappeared: 1 },
+            ]
+        );
+
+        // Queue full: the oldest are dropped
+        for _ in 0..MAX_PENDING_NOTIFICATIONS + 2 {
+            notifier.push(NotifyEvent::ErrorThreshold { errors: 1, threshold: 1 }, now);
+        }
+        assert_eq!(notifier.pending_len(), MAX_PENDING_NOTIFICATIONS);
+        assert_eq!(notifier.dropped, 2);
+    }
+
+    #[test]
+    fn test_error_threshold_fires_once_per_window() {
+        let start = Instant::now();
+        let mut notifier = Notifier::default();
+
+        notifier.check_errors(1000, 10, start);
+        notifier.check_errors(1009, 10, start + Duration::from_secs(60));
+        assert_eq!(notifier.pending_len(), 0);
+        notifier.check_errors(1012, 10, start + Duration::from_secs(120));
+        notifier.check_errors(1030, 10, start + Duration::from_secs(180));
+        assert_eq!(events(&mut notifier), vec![NotifyEvent::ErrorThreshold { errors: 12, threshold: 10 }]);
+
+        // A new window starts from the current total
+        notifier.check_errors(1035, 10, start + ERROR_WINDOW);
+        notifier.check_errors(1044, 10, start + ERROR_WINDOW + Duration::from_secs(1));
+        assert_eq!(notifier.pending_len(), 0);
+        notifier.check_errors(1045, 10, start + ERROR_WINDOW + Duration::from_secs(2));
+        assert_eq!(notifier.pending_len(), 1);
+
+        // Threshold 0 is off; a counter reset restarts the window
+        let mut off = Notifier::default();
+        off.check_errors(0, 0, start);
+        off.check_errors(500, 0, start);
+        off.check_errors(10, 0, start);
+        assert_eq!(off.pending_len(), 0);
+    }
+}
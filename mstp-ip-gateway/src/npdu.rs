@@ -0,0 +1,461 @@
+//! BACnet NPDU (network layer header)
+//!
+//! One typed representation of the NPDU header (ASHRAE 135 Clause 6.2) for
+//! both directions: `decode` splits a received NPDU into its control flags,
+//! DNET/DADR, SNET/SADR, hop count and payload, and `encode` writes one back
+//! out. The gateway, the receive tasks and the scan/router-query builders all
+//! go through it instead of pushing header bytes by hand.
+//!
+//! NPDUs the gateway originates itself (announcements, proxy replies, scans)
+//! start from `Npdu::local`, which takes its hop count and priority from the
+//! site settings applied once at boot via `set_originated_defaults`.
+//!
+//! `decode` is strict. `decode_lenient` additionally accepts the header
+//! deviations some legacy devices emit (`NpduQuirk`) and reports which one it
+//! tolerated, so the gateway can re-encode a clean NPDU and count how often
+//! each shows up.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Protocol version carried in every NPDU
+pub const NPDU_VERSION: u8 = 0x01;
+
+/// Largest hop count, and the default for NPDUs the gateway originates
+pub const MAX_HOP_COUNT: u8 = 255;
+
+/// Hop count written on originated NPDUs with a destination specifier
+static ORIGINATED_HOP_COUNT: AtomicU8 = AtomicU8::new(MAX_HOP_COUNT);
+/// Priority bits written on originated NPDUs
+static ORIGINATED_PRIORITY: AtomicU8 = AtomicU8::new(NetworkPriority::Normal as u8);
+
+/// Apply the configured hop count and priority to every NPDU built with `Npdu::local`
+pub fn set_originated_defaults(hop_count: u8, priority: NetworkPriority) {
+    ORIGINATED_HOP_COUNT.store(hop_count.max(1), Ordering::Relaxed);
+    ORIGINATED_PRIORITY.store(priority as u8, Ordering::Relaxed);
+}
+
+/// Network priority carried in the low two control bits (Clause 6.2.2)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum NetworkPriority {
+    #[default]
+    Normal = 0,
+    Urgent = 1,
+    CriticalEquipment = 2,
+    LifeSafety = 3,
+}
+
+impl NetworkPriority {
+    /// Decode the control bits (or NVS value); only the low two bits count
+    pub fn from_u8(value: u8) -> Self {
+        match value & CONTROL_PRIORITY {
+            1 => NetworkPriority::Urgent,
+            2 => NetworkPriority::CriticalEquipment,
+            3 => NetworkPriority::LifeSafety,
+            _ => NetworkPriority::Normal,
+        }
+    }
+
+    /// Form value used by the portal and console
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetworkPriority::Normal => "normal",
+            NetworkPriority::Urgent => "urgent",
+            NetworkPriority::CriticalEquipment => "critical-equipment",
+            NetworkPriority::LifeSafety => "life-safety",
+        }
+    }
+
+    /// Parse a form value back
+    pub fn parse(s: &str) -> Option<Self> {
+        [Self::Normal, Self::Urgent, Self::CriticalEquipment, Self::LifeSafety]
+            .into_iter()
+            .find(|p| p.as_str() == s)
+    }
+}
+
+/// Global broadcast DNET
+pub const GLOBAL_BROADCAST_NETWORK: u16 = 0xFFFF;
+
+const CONTROL_NETWORK_MESSAGE: u8 = 0x80;
+const CONTROL_DESTINATION: u8 = 0x20;
+const CONTROL_SOURCE: u8 = 0x08;
+const CONTROL_EXPECTING_REPLY: u8 = 0x04;
+const CONTROL_PRIORITY: u8 = 0x03;
+/// Control bits 6 and 4, which shall be zero
+const CONTROL_RESERVED: u8 = 0x50;
+
+/// Most filler octets skipped ahead of an APDU in lenient mode
+const MAX_PADDING: usize = 4;
+
+/// Network number and MAC address (DNET/DADR or SNET/SADR)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkAddress {
+    pub network: u16,
+    /// Empty for a broadcast on `network`
+    pub address: Vec<u8>,
+}
+
+impl NetworkAddress {
+    pub fn new(network: u16, address: &[u8]) -> Self {
+        Self { network, address: address.to_vec() }
+    }
+
+    /// Broadcast on `network` (empty DADR)
+    pub fn broadcast(network: u16) -> Self {
+        Self { network, address: Vec::new() }
+    }
+}
+
+/// Why an NPDU could not be decoded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NpduError {
+    /// Fewer than the two fixed octets
+    TooShort(usize),
+    InvalidVersion(u8),
+    /// A field runs past the end of the data (field, bytes needed, bytes present)
+    Truncated(&'static str, usize, usize),
+}
+
+impl fmt::Display for NpduError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NpduError::TooShort(len) => write!(f, "NPDU too short: {} bytes (minimum 2)", len),
+            NpduError::InvalidVersion(v) => write!(f, "Invalid NPDU version: expected 1, got {}", v),
+            NpduError::Truncated(field, need, have) => {
+                write!(f, "NPDU {} truncated: need {} bytes, have {}", field, need, have)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NpduError {}
+
+/// A known deviation from Clause 6.2 that lenient decoding tolerates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NpduQuirk {
+    /// DNET/DADR present but the frame ends where the hop count should be
+    MissingHopCount = 0,
+    /// A hop count octet ahead of the APDU with no destination specifier
+    StrayHopCount = 1,
+    /// Several filler octets between the header and the APDU
+    Padding = 2,
+    /// Reserved control bits set
+    ReservedBits = 3,
+}
+
+impl NpduQuirk {
+    pub const ALL: [NpduQuirk; 4] =
+        [NpduQuirk::MissingHopCount, NpduQuirk::StrayHopCount, NpduQuirk::Padding, NpduQuirk::ReservedBits];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NpduQuirk::MissingHopCount => "missing-hop-count",
+            NpduQuirk::StrayHopCount => "stray-hop-count",
+            NpduQuirk::Padding => "padding",
+            NpduQuirk::ReservedBits => "reserved-bits",
+        }
+    }
+}
+
+/// An NPDU header and the payload (APDU, or network message type and data) it carries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Npdu<'a> {
+    pub network_message: bool,
+    pub expecting_reply: bool,
+    pub priority: u8,
+    pub destination: Option<NetworkAddress>,
+    pub source: Option<NetworkAddress>,
+    /// Only on the wire when a destination is present; `MAX_HOP_COUNT` otherwise
+    pub hop_count: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Npdu<'a> {
+    /// Local APDU with no network layer addressing, at the configured
+    /// priority and hop count for gateway-originated traffic
+    pub fn local(apdu: &'a [u8]) -> Self {
+        Self {
+            network_message: false,
+            expecting_reply: false,
+            priority: ORIGINATED_PRIORITY.load(Ordering::Relaxed),
+            destination: None,
+            source: None,
+            hop_count: ORIGINATED_HOP_COUNT.load(Ordering::Relaxed),
+            payload: apdu,
+        }
+    }
+
+    /// Network layer message (`message` starts with the message type)
+    pub fn network_message(message: &'a [u8]) -> Self {
+        Self { network_message: true, ..Self::local(message) }
+    }
+
+    /// Split `data` into header fields and payload
+    pub fn decode(data: &'a [u8]) -> Result<Self, NpduError> {
+        Self::parse(data, false).map(|(npdu, _)| npdu)
+    }
+
+    /// `decode`, but tolerating the deviations in `NpduQuirk`; returns the
+    /// first one found alongside the NPDU. `encode` on the result gives the
+    /// header as it should have been sent.
+    pub fn decode_lenient(data: &'a [u8]) -> Result<(Self, Option<NpduQuirk>), NpduError> {
+        Self::parse(data, true)
+    }
+
+    fn parse(data: &'a [u8], lenient: bool) -> Result<(Self, Option<NpduQuirk>), NpduError> {
+        if data.len() < 2 {
+            return Err(NpduError::TooShort(data.len()));
+        }
+        if data[0] != NPDU_VERSION {
+            return Err(NpduError::InvalidVersion(data[0]));
+        }
+
+        let control = data[1];
+        let mut pos = 2;
+        let mut quirk = None;
+        let destination = if control & CONTROL_DESTINATION != 0 {
+            Some(read_address(data, &mut pos, ["destination", "destination address"])?)
+        } else {
+            None
+        };
+        let source = if control & CONTROL_SOURCE != 0 {
+            Some(read_address(data, &mut pos, ["source", "source address"])?)
+        } else {
+            None
+        };
+        let network_message = control & CONTROL_NETWORK_MESSAGE != 0;
+        let hop_count = if destination.is_some() {
+            match data.get(pos) {
+                Some(&hop_count) => {
+                    pos += 1;
+                    hop_count
+                }
+                None if lenient => {
+                    quirk = Some(NpduQuirk::MissingHopCount);
+                    MAX_HOP_COUNT
+                }
+                None => return Err(NpduError::Truncated("hop count", pos + 1, data.len())),
+            }
+        } else {
+            MAX_HOP_COUNT
+        };
+
+        if lenient && destination.is_none() && !network_message {
+            // No APDU starts with a PDU type above 7, so leading octets that
+            // would are a misplaced hop count or filler
+            let stray = data[pos..].iter().take(MAX_PADDING).take_while(|&&b| b >> 4 > 7).count();
+            if stray > 0 && pos + stray < data.len() {
+                quirk = Some(if stray == 1 { NpduQuirk::StrayHopCount } else { NpduQuirk::Padding });
+                pos += stray;
+            }
+        }
+        if lenient && quirk.is_none() && control & CONTROL_RESERVED != 0 {
+            quirk = Some(NpduQuirk::ReservedBits);
+        }
+
+        let npdu = Self {
+            network_message,
+            expecting_reply: control & CONTROL_EXPECTING_REPLY != 0,
+            priority: control & CONTROL_PRIORITY,
+            destination,
+            source,
+            hop_count,
+            payload: &data[pos..],
+        };
+        Ok((npdu, quirk))
+    }
+
+    /// Header length on the wire (everything before the payload)
+    pub fn header_len(&self) -> usize {
+        let address_len = |a: &Option<NetworkAddress>| a.as_ref().map_or(0, |a| 3 + a.address.len());
+        2 + address_len(&self.destination) + address_len(&self.source) + usize::from(self.destination.is_some())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.header_len() + self.payload.len());
+        let mut control = self.priority & CONTROL_PRIORITY;
+        if self.network_message {
+            control |= CONTROL_NETWORK_MESSAGE;
+        }
+        if self.destination.is_some() {
+            control |= CONTROL_DESTINATION;
+        }
+        if self.source.is_some() {
+            control |= CONTROL_SOURCE;
+        }
+        if self.expecting_reply {
+            control |= CONTROL_EXPECTING_REPLY;
+        }
+        out.push(NPDU_VERSION);
+        out.push(control);
+        for address in [&self.destination, &self.source].into_iter().flatten() {
+            out.extend_from_slice(&address.network.to_be_bytes());
+            out.push(address.address.len() as u8);
+            out.extend_from_slice(&address.address);
+        }
+        if self.destination.is_some() {
+            out.push(self.hop_count);
+        }
+        out.extend_from_slice(self.payload);
+        out
+    }
+
+    /// The NPDU as this router forwards it: SNET/SADR set to where it came
+    /// from and the hop count decremented.
+    ///
+    /// Per ASHRAE 135 Clause 6.2.2: When delivering to the final destination network,
+    /// the DNET/DADR fields must be stripped from the NPDU. Set `final_delivery` to true
+    /// when the destination network matches the local network being delivered to.
+    pub fn routed(&self, source_network: u16, source_address: &[u8], final_delivery: bool) -> Self {
+        let destination = self.destination.clone().filter(|_| !final_delivery);
+        Self {
+            destination,
+            source: Some(NetworkAddress::new(source_network, source_address)),
+            hop_count: self.hop_count.saturating_sub(1),
+            ..self.clone()
+        }
+    }
+}
+
+/// Read a network number, address length and address at `pos`
+/// (`fields` names the specifier and its address for errors)
+fn read_address(data: &[u8], pos: &mut usize, fields: [&'static str; 2]) -> Result<NetworkAddress, NpduError> {
+    let header = data.get(*pos..*pos + 3).ok_or(NpduError::Truncated(fields[0], *pos + 3, data.len()))?;
+    let network = u16::from_be_bytes([header[0], header[1]]);
+    let start = *pos + 3;
+    let end = start + usize::from(header[2]);
+    let address = data.get(start..end).ok_or(NpduError::Truncated(fields[1], end, data.len()))?;
+    *pos = end;
+    Ok(NetworkAddress::new(network, address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_rejects_malformed_headers() {
+        let err = Npdu::decode(&[0x01]).unwrap_err();
+        assert_eq!(err, NpduError::TooShort(1));
+        assert!(err.to_string().contains("too short"));
+        assert!(err.to_string().contains("minimum 2"));
+
+        let err = Npdu::decode(&[0x02, 0x00]).unwrap_err();
+        assert!(err.to_string().contains("Invalid NPDU version"));
+        assert!(err.to_string().contains("expected 1, got 2"));
+
+        // Destination flag set, DLEN 5 but no address follows
+        let err = Npdu::decode(&[0x01, 0x20, 0x00, 0x01, 0x05]).unwrap_err();
+        assert!(err.to_string().contains("destination address truncated"));
+
+        // DNET/DADR complete, hop count missing
+        let err = Npdu::decode(&[0x01, 0x20, 0x00, 0x01, 0x01, 0x05]).unwrap_err();
+        assert_eq!(err, NpduError::Truncated("hop count", 7, 6));
+
+        let err = Npdu::decode(&[0x01, 0x08, 0x00]).unwrap_err();
+        assert_eq!(err, NpduError::Truncated("source", 5, 3));
+    }
+
+    #[test]
+    fn test_decode_then_encode_is_identity() {
+        let simple = [0x01, 0x00];
+        let npdu = Npdu::decode(&simple).unwrap();
+        assert_eq!(npdu, Npdu::local(&[]));
+        assert_eq!(npdu.header_len(), 2);
+
+        let frames: [&[u8]; 4] = [
+            // Confirmed ReadProperty to MAC 5 on network 1, expecting reply, priority 1
+            &[0x01, 0x25, 0x00, 0x01, 0x01, 0x05, 0xFE, 0x00, 0x05, 0x07, 0x0C],
+            // Routed I-Am from 10.0.5.17:47808 on network 2, global broadcast
+            &[0x01, 0x28, 0xFF, 0xFF, 0x00, 0x00, 0x02, 0x06, 10, 0, 5, 17, 0xBA, 0xC0, 0xFF, 0x10, 0x00],
+            // Who-Is-Router-To-Network
+            &[0x01, 0x80, 0x00],
+            // Reply to SNET/SADR without a destination
+            &[0x01, 0x08, 0x00, 0x01, 0x01, 0x0A, 0x30, 0x01, 0x0C],
+        ];
+        for frame in frames {
+            let npdu = Npdu::decode(frame).unwrap();
+            assert_eq!(npdu.header_len() + npdu.payload.len(), frame.len());
+            assert_eq!(npdu.encode(), frame);
+        }
+
+        let npdu = Npdu::decode(frames[0]).unwrap();
+        assert!(npdu.expecting_reply);
+        assert_eq!(npdu.priority, 1);
+        assert_eq!(npdu.destination, Some(NetworkAddress::new(1, &[5])));
+        assert_eq!(npdu.hop_count, 0xFE);
+        assert_eq!(npdu.payload, [0x00, 0x05, 0x07, 0x0C]);
+    }
+
+    #[test]
+    fn test_routed_adds_source_and_strips_destination_on_final_delivery() {
+        let request = [0x01, 0x24, 0x00, 0x01, 0x01, 0x05, 0xFF, 0x00, 0x05, 0x07, 0x0C];
+        let npdu = Npdu::decode(&request).unwrap();
+
+        // Forwarded onward: DNET/DADR kept, hop count decremented, SNET/SADR added
+        let onward = npdu.routed(2, &[192, 168, 1, 50, 0xBA, 0xC0], false).encode();
+        assert_eq!(
+            onward,
+            [0x01, 0x2C, 0x00, 0x01, 0x01, 0x05, 0x00, 0x02, 0x06, 192, 168, 1, 50, 0xBA, 0xC0, 0xFE, 0x00, 0x05, 0x07, 0x0C]
+        );
+
+        // Delivered: destination specifier and hop count dropped
+        let delivered = npdu.routed(2, &[192, 168, 1, 50, 0xBA, 0xC0], true).encode();
+        assert_eq!(delivered, [0x01, 0x0C, 0x00, 0x02, 0x06, 192, 168, 1, 50, 0xBA, 0xC0, 0x00, 0x05, 0x07, 0x0C]);
+
+        let who_is_router = Npdu::network_message(&[0x00]);
+        assert_eq!(who_is_router.encode(), [0x01, 0x80, 0x00]);
+        let broadcast = Npdu { destination: Some(NetworkAddress::broadcast(GLOBAL_BROADCAST_NETWORK)), ..Npdu::local(&[0x10, 0x08]) };
+        assert_eq!(broadcast.encode(), [0x01, 0x20, 0xFF, 0xFF, 0x00, 0xFF, 0x10, 0x08]);
+    }
+
+    #[test]
+    fn test_decode_lenient_repairs_known_quirks() {
+        // DNET/DADR with the hop count left off
+        let missing_hop = [0x01, 0x20, 0x00, 0x01, 0x01, 0x05];
+        assert!(Npdu::decode(&missing_hop).is_err());
+        let (npdu, quirk) = Npdu::decode_lenient(&missing_hop).unwrap();
+        assert_eq!(quirk, Some(NpduQuirk::MissingHopCount));
+        assert_eq!(npdu.hop_count, MAX_HOP_COUNT);
+        assert_eq!(npdu.encode(), [0x01, 0x20, 0x00, 0x01, 0x01, 0x05, 0xFF]);
+
+        // Hop count written without a destination, then an I-Am
+        let stray_hop = [0x01, 0x00, 0xFF, 0x10, 0x00, 0xC4];
+        assert_eq!(Npdu::decode(&stray_hop).unwrap().payload[0], 0xFF);
+        let (npdu, quirk) = Npdu::decode_lenient(&stray_hop).unwrap();
+        assert_eq!(quirk, Some(NpduQuirk::StrayHopCount));
+        assert_eq!(npdu.encode(), [0x01, 0x00, 0x10, 0x00, 0xC4]);
+
+        let padded = [0x01, 0x04, 0xFF, 0xFF, 0x00, 0x05, 0x07, 0x0C];
+        let (npdu, quirk) = Npdu::decode_lenient(&padded).unwrap();
+        assert_eq!(quirk, Some(NpduQuirk::Padding));
+        assert_eq!(npdu.payload, [0x00, 0x05, 0x07, 0x0C]);
+
+        let reserved = [0x01, 0x50, 0x10, 0x08];
+        let (npdu, quirk) = Npdu::decode_lenient(&reserved).unwrap();
+        assert_eq!(quirk, Some(NpduQuirk::ReservedBits));
+        assert_eq!(npdu.encode(), [0x01, 0x00, 0x10, 0x08]);
+
+        // Clean frames and network messages come back untouched
+        let clean = [0x01, 0x25, 0x00, 0x01, 0x01, 0x05, 0xFE, 0x00, 0x05, 0x07, 0x0C];
+        assert_eq!(Npdu::decode_lenient(&clean).unwrap(), (Npdu::decode(&clean).unwrap(), None));
+        let network_message = [0x01, 0x80, 0x12, 0x00, 0x05];
+        assert_eq!(Npdu::decode_lenient(&network_message).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_network_priority_round_trips() {
+        for bits in 0..4 {
+            let priority = NetworkPriority::from_u8(bits);
+            assert_eq!(priority as u8, bits);
+            assert_eq!(NetworkPriority::parse(priority.as_str()), Some(priority));
+        }
+        assert_eq!(NetworkPriority::from_u8(0x26), NetworkPriority::CriticalEquipment);
+        assert_eq!(NetworkPriority::parse("high"), None);
+
+        let npdu = Npdu { priority: NetworkPriority::LifeSafety as u8, ..Npdu::local(&[0x10, 0x08]) };
+        assert_eq!(npdu.encode(), [0x01, 0x03, 0x10, 0x08]);
+    }
+}
@@ -0,0 +1,245 @@
+//! Background NVS writer
+//!
+//! A flash erase can take tens of milliseconds, long enough to stall routing
+//! when it happens under the gateway or portal lock. Persistence goes through
+//! this task instead: callers hand over a copy of what to store and carry on.
+//! Writes of the same item that arrive within `COALESCE_WINDOW` of each other
+//! are merged, so only the newest copy is written. Outcomes are recorded in
+//! the event log; periodic checkpoints only report failures.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::nvs::{EspNvsPartition, NvsDefault};
+use log::{debug, info, warn};
+
+use crate::auth::{ApiKey, UserAccount};
+use crate::config::{
+    ApiKeyPersistence, BdtEntryConfig, DeviceLabel, DeviceLabelPersistence, GatewayConfig,
+    LifetimeStatsPersistence, NetworkTablePersistence, RoutingTableEntryConfig, SlaPersistence,
+    StaticRouteConfig, UserAccountPersistence,
+};
+use crate::events::{self, EventCategory, Severity};
+use crate::lifetime::LifetimeStats;
+use crate::sla::SlaTable;
+
+/// How long to wait for more writes before touching flash
+pub const COALESCE_WINDOW: Duration = Duration::from_millis(250);
+
+/// How long a restart waits for queued writes to reach flash
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Something to store, with the data to store
+#[derive(Debug, Clone)]
+pub enum NvsWrite {
+    Config(Box<GatewayConfig>),
+    /// Values entered so far in the setup wizard and the step to resume at
+    CommissioningProgress(Box<GatewayConfig>),
+    /// Location, description and serial number only
+    SiteInfo(Box<GatewayConfig>),
+    Bdt(Vec<BdtEntryConfig>),
+    RoutingTable(Vec<RoutingTableEntryConfig>),
+    StaticRoutes(Vec<StaticRouteConfig>),
+    Users(Vec<UserAccount>),
+    ApiKeys(Vec<ApiKey>),
+    DeviceLabels(Vec<DeviceLabel>),
+    Sla(SlaTable),
+    LifetimeStats(LifetimeStats),
+}
+
+impl NvsWrite {
+    /// What is written, for log and event messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            NvsWrite::Config(_) => "configuration",
+            NvsWrite::CommissioningProgress(_) => "commissioning progress",
+            NvsWrite::SiteInfo(_) => "device site info",
+            NvsWrite::Bdt(_) => "BDT",
+            NvsWrite::RoutingTable(_) => "routing table",
+            NvsWrite::StaticRoutes(_) => "static routes",
+            NvsWrite::Users(_) => "portal accounts",
+            NvsWrite::ApiKeys(_) => "API keys",
+            NvsWrite::DeviceLabels(_) => "device labels",
+            NvsWrite::Sla(_) => "response statistics",
+            NvsWrite::LifetimeStats(_) => "lifetime statistics",
+        }
+    }
+
+    /// Checkpoints and wizard progress are only worth an event when they fail
+    pub fn is_checkpoint(&self) -> bool {
+        matches!(self, NvsWrite::CommissioningProgress(_) | NvsWrite::Sla(_) | NvsWrite::LifetimeStats(_))
+    }
+
+    /// Whether `other` stores the same item, so only the newer one is needed
+    fn replaces(&self, other: &NvsWrite) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    fn store(&self, nvs: &EspNvsPartition<NvsDefault>) -> anyhow::Result<()> {
+        match self {
+            NvsWrite::Config(config) => config.save_to_nvs(nvs.clone()),
+            NvsWrite::CommissioningProgress(config) => config.save_commissioning_progress(nvs.clone()),
+            NvsWrite::SiteInfo(config) => config.save_site_info(nvs.clone()),
+            NvsWrite::Bdt(entries) => NetworkTablePersistence::save_bdt(nvs.clone(), entries),
+            NvsWrite::RoutingTable(entries) => NetworkTablePersistence::save_routing_table(nvs.clone(), entries),
+            NvsWrite::StaticRoutes(entries) => NetworkTablePersistence::save_static_routes(nvs.clone(), entries),
+            NvsWrite::Users(users) => UserAccountPersistence::save_users(nvs.clone(), users),
+            NvsWrite::ApiKeys(keys) => ApiKeyPersistence::save_keys(nvs.clone(), keys),
+            NvsWrite::DeviceLabels(labels) => DeviceLabelPersistence::save_labels(nvs.clone(), labels),
+            NvsWrite::Sla(table) => SlaPersistence::save(nvs.clone(), table),
+            NvsWrite::LifetimeStats(stats) => LifetimeStatsPersistence::save(nvs.clone(), stats),
+        }
+    }
+}
+
+/// Writes waiting for flash, at most one per item, oldest item first
+#[derive(Debug, Default)]
+pub struct WriteQueue {
+    pending: Vec<NvsWrite>,
+}
+
+impl WriteQueue {
+    /// Queue a write, replacing a waiting write of the same item
+    pub fn push(&mut self, write: NvsWrite) {
+        match self.pending.iter_mut().find(|w| w.replaces(&write)) {
+            Some(waiting) => *waiting = write,
+            None => self.pending.push(write),
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<NvsWrite> {
+        std::mem::take(&mut self.pending)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+enum Job {
+    Write(NvsWrite),
+    /// Answered once everything queued before it is written
+    Flush(Sender<()>),
+}
+
+/// Handle to the writer task; clones share the task
+#[derive(Clone)]
+pub struct NvsWriter {
+    tx: Sender<Job>,
+}
+
+impl NvsWriter {
+    /// Start the writer task for `nvs`
+    pub fn spawn(nvs: EspNvsPartition<NvsDefault>, stack_size: usize) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("NVS writer".to_string())
+            .stack_size(stack_size)
+            .spawn(move || run(nvs, rx))?;
+        Ok(Self { tx })
+    }
+
+    /// Queue a write; never blocks
+    pub fn submit(&self, write: NvsWrite) {
+        let name = write.name();
+        if self.tx.send(Job::Write(write)).is_err() {
+            warn!("NVS writer stopped, {} not saved", name);
+        }
+    }
+
+    /// Wait until everything submitted so far is written, up to `timeout`.
+    /// Used before a restart so no queued write is lost or lands late.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let (done_tx, done_rx) = mpsc::channel();
+        self.tx.send(Job::Flush(done_tx)).is_ok() && done_rx.recv_timeout(timeout).is_ok()
+    }
+}
+
+fn run(nvs: EspNvsPartition<NvsDefault>, rx: Receiver<Job>) {
+    info!("NVS writer task started");
+    let mut queue = WriteQueue::default();
+    let mut flushes = Vec::new();
+    while let Ok(first) = rx.recv() {
+        // Gather what else arrives within the window; a flush cuts it short
+        let deadline = Instant::now() + COALESCE_WINDOW;
+        let mut next = Ok(first);
+        while let Ok(job) = next {
+            match job {
+                Job::Write(write) => queue.push(write),
+                Job::Flush(done) => flushes.push(done),
+            }
+            next = if flushes.is_empty() {
+                rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            } else {
+                rx.try_recv().map_err(|_| RecvTimeoutError::Timeout)
+            };
+        }
+        write_all(&nvs, queue.take());
+        for done in flushes.drain(..) {
+            let _ = done.send(());
+        }
+    }
+    info!("NVS writer task stopped");
+}
+
+/// Write a batch and report it: one event for what was saved, one per failure
+fn write_all(nvs: &EspNvsPartition<NvsDefault>, writes: Vec<NvsWrite>) {
+    let mut saved = Vec::new();
+    for write in &writes {
+        let started = Instant::now();
+        match write.store(nvs) {
+            Ok(()) => {
+                debug!("Saved {} to NVS in {} ms", write.name(), started.elapsed().as_millis());
+                if !write.is_checkpoint() {
+                    saved.push(write.name());
+                }
+            }
+            Err(e) => {
+                warn!("Failed to save {} to NVS: {}", write.name(), e);
+                events::record(
+                    EventCategory::Config,
+                    Severity::Error,
+                    &format!("Failed to save {} to NVS: {}", write.name(), e),
+                );
+            }
+        }
+    }
+    if !saved.is_empty() {
+        events::record(EventCategory::Config, Severity::Info, &format!("Saved to NVS: {}", saved.join(", ")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_keeps_newest_write_per_item() {
+        let mut queue = WriteQueue::default();
+        queue.push(NvsWrite::StaticRoutes(Vec::new()));
+        queue.push(NvsWrite::Bdt(Vec::new()));
+        queue.push(NvsWrite::StaticRoutes(vec![StaticRouteConfig {
+            network: 5,
+            next_hop: crate::config::RouteNextHop::Mstp(3),
+        }]));
+
+        let writes = queue.take();
+        assert!(queue.is_empty());
+        assert_eq!(writes.iter().map(NvsWrite::name).collect::<Vec<_>>(), vec!["static routes", "BDT"]);
+        assert!(matches!(&writes[0], NvsWrite::StaticRoutes(routes) if routes.len() == 1));
+    }
+
+    #[test]
+    fn test_config_and_site_info_are_separate_items() {
+        let mut queue = WriteQueue::default();
+        let config = Box::new(GatewayConfig::default());
+        queue.push(NvsWrite::Config(config.clone()));
+        queue.push(NvsWrite::SiteInfo(config));
+        queue.push(NvsWrite::LifetimeStats(LifetimeStats::default()));
+        let writes = queue.take();
+        assert_eq!(writes.len(), 3);
+        assert!(!writes[0].is_checkpoint());
+        assert!(writes[2].is_checkpoint());
+    }
+}
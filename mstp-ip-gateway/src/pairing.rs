@@ -0,0 +1,208 @@
+//! Pairing two BACman units as peer BBMDs
+//!
+//! Replaces entering matching BDT entries and static routes by hand at both
+//! sites. The initiating gateway POSTs its B/IP address and network numbers
+//! to the peer's `/api/pair` with the peer's admin credentials; the peer
+//! installs a BDT entry and a static route back to it and answers with its
+//! own details, and the initiator installs the reciprocal pair. A
+//! Who-Is-Router-To-Network broadcast then checks that broadcasts cross
+//! between the sites: the peer only hears it through the initiator's BDT.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Instant;
+
+use crate::router_query::RouterQuery;
+
+/// Peer endpoint answering pairing requests
+pub const PAIR_API_PATH: &str = "/api/pair";
+
+/// Mask installed with each paired BDT entry: the peer forwards to its own
+/// subnet itself (two-hop distribution), which works through routers that
+/// drop directed broadcasts
+pub const PAIR_MASK: Ipv4Addr = Ipv4Addr::BROADCAST;
+
+/// What each side of a pairing tells the other about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub bip_address: SocketAddr,
+    pub ip_network: u16,
+    pub mstp_network: u16,
+    pub site_name: String,
+}
+
+impl PeerInfo {
+    /// Form-encoded body, used for both the request and the answer
+    pub fn encode(&self) -> String {
+        format!(
+            "bip={}&ip_net={}&mstp_net={}&site={}",
+            self.bip_address,
+            self.ip_network,
+            self.mstp_network,
+            urlencoding::encode(&self.site_name)
+        )
+    }
+
+    /// Parse a body written by `encode`
+    pub fn parse(body: &str) -> Option<Self> {
+        let value = |key: &str| {
+            body.split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| urlencoding::decode(v).map(|v| v.into_owned()).unwrap_or_default())
+        };
+        let bip_address = value("bip")?.parse::<SocketAddr>().ok().filter(SocketAddr::is_ipv4)?;
+        Some(Self {
+            bip_address,
+            ip_network: value("ip_net")?.parse().ok()?,
+            mstp_network: value("mstp_net")?.parse().ok()?,
+            site_name: value("site").unwrap_or_default(),
+        })
+    }
+}
+
+/// Why two gateways cannot be paired
+pub fn check_peer(local: &PeerInfo, peer: &PeerInfo) -> Result<(), &'static str> {
+    if peer.bip_address.ip() == local.bip_address.ip() {
+        Err("peer is this gateway")
+    } else if peer.bip_address.ip().is_unspecified() || peer.bip_address.ip().is_multicast() {
+        Err("peer has no usable B/IP address")
+    } else if peer.ip_network != local.ip_network {
+        // BBMD peers extend one B/IP network; different numbers mean a site mismatch
+        Err("peer uses a different IP network number")
+    } else if peer.mstp_network == local.mstp_network {
+        Err("peer uses the same MS/TP network number")
+    } else if peer.mstp_network == local.ip_network {
+        Err("peer's MS/TP network number is this gateway's IP network")
+    } else {
+        Ok(())
+    }
+}
+
+/// Where a pairing started from this gateway stands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairingStage {
+    /// Waiting for the peer's `/api/pair` answer
+    Contacting,
+    /// Entries installed on both ends, test broadcast sent
+    Verifying,
+    /// The peer answered the test broadcast
+    Paired,
+    Failed(String),
+}
+
+impl PairingStage {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, PairingStage::Paired | PairingStage::Failed(_))
+    }
+}
+
+impl fmt::Display for PairingStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PairingStage::Contacting => f.write_str("contacting peer"),
+            PairingStage::Verifying => f.write_str("verifying with a test broadcast"),
+            PairingStage::Paired => f.write_str("paired"),
+            PairingStage::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// A pairing started from this gateway
+#[derive(Debug, Clone)]
+pub struct Pairing {
+    /// Peer portal address as entered
+    pub peer_ip: Ipv4Addr,
+    /// The peer's details, once it has answered
+    pub peer: Option<PeerInfo>,
+    pub stage: PairingStage,
+    pub started_at: Instant,
+}
+
+impl Pairing {
+    pub fn new(peer_ip: Ipv4Addr, now: Instant) -> Self {
+        Self { peer_ip, peer: None, stage: PairingStage::Contacting, started_at: now }
+    }
+
+    /// The peer accepted and sent its details; check them before anything is installed
+    pub fn accepted(&mut self, local: &PeerInfo, peer: PeerInfo) -> Result<(), &'static str> {
+        let checked = check_peer(local, &peer);
+        self.stage = match checked {
+            Ok(()) => PairingStage::Verifying,
+            Err(reason) => PairingStage::Failed(reason.to_string()),
+        };
+        self.peer = Some(peer);
+        checked
+    }
+
+    pub fn fail(&mut self, reason: impl Into<String>) {
+        self.stage = PairingStage::Failed(reason.into());
+    }
+
+    /// Follow the test broadcast: paired once the peer announces its MS/TP
+    /// network, failed if the answer window passes without it
+    pub fn verify(&mut self, probe: &RouterQuery, now: Instant) {
+        let Some(peer) = self.peer.as_ref().filter(|_| self.stage == PairingStage::Verifying) else { return };
+        if probe.target != peer.bip_address {
+            return;
+        }
+        if probe.networks.contains(&peer.mstp_network) {
+            self.stage = PairingStage::Paired;
+        } else if probe.is_finished(now) {
+            self.fail("peer did not answer the test broadcast - check that UDP port 47808 passes between the sites");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router_query::{RouterQueryKind, ROUTER_QUERY_WINDOW};
+
+    fn info(bip: &str, ip_network: u16, mstp_network: u16) -> PeerInfo {
+        PeerInfo { bip_address: bip.parse().unwrap(), ip_network, mstp_network, site_name: String::new() }
+    }
+
+    #[test]
+    fn test_peer_info_round_trips() {
+        let peer = PeerInfo { site_name: "North plant & annex".to_string(), ..info("10.1.2.3:47809", 10001, 2) };
+        assert_eq!(PeerInfo::parse(&peer.encode()), Some(peer));
+        assert_eq!(PeerInfo::parse("bip=10.1.2.3:47808&ip_net=1"), None);
+        assert_eq!(PeerInfo::parse("bip=[::1]:47808&ip_net=1&mstp_net=2"), None);
+    }
+
+    #[test]
+    fn test_check_peer_refuses_conflicting_numbers() {
+        let local = info("10.0.0.5:47808", 10001, 1);
+        assert_eq!(check_peer(&local, &info("10.1.0.5:47808", 10001, 2)), Ok(()));
+        assert_eq!(check_peer(&local, &info("10.0.0.5:47808", 10001, 2)), Err("peer is this gateway"));
+        assert!(check_peer(&local, &info("10.1.0.5:47808", 10002, 2)).is_err());
+        assert!(check_peer(&local, &info("10.1.0.5:47808", 10001, 1)).is_err());
+        assert!(check_peer(&local, &info("10.1.0.5:47808", 10001, 10001)).is_err());
+    }
+
+    #[test]
+    fn test_verify_waits_for_the_peer_network() {
+        let now = Instant::now();
+        let local = info("10.0.0.5:47808", 10001, 1);
+        let peer = info("10.1.0.5:47808", 10001, 2);
+        let mut pairing = Pairing::new(Ipv4Addr::new(10, 1, 0, 5), now);
+        pairing.accepted(&local, peer.clone()).unwrap();
+        assert_eq!(pairing.stage, PairingStage::Verifying);
+
+        let mut probe = RouterQuery::new(peer.bip_address, RouterQueryKind::WhoIsRouter, now);
+        pairing.verify(&probe, now);
+        assert_eq!(pairing.stage, PairingStage::Verifying);
+
+        // I-Am-Router-To-Network 2, 10001 from the peer
+        probe.record_reply(peer.bip_address, &[0x01, 0x00, 0x02, 0x27, 0x11], now);
+        pairing.verify(&probe, now);
+        assert_eq!(pairing.stage, PairingStage::Paired);
+
+        let mut silent = Pairing::new(Ipv4Addr::new(10, 1, 0, 5), now);
+        silent.accepted(&local, peer.clone()).unwrap();
+        let probe = RouterQuery::new(peer.bip_address, RouterQueryKind::WhoIsRouter, now);
+        silent.verify(&probe, now + ROUTER_QUERY_WINDOW);
+        assert!(matches!(silent.stage, PairingStage::Failed(_)));
+    }
+}
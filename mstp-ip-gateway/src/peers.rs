@@ -0,0 +1,213 @@
+//! Per-peer statistics on the IP side
+//!
+//! Counts what each B/IP endpoint sends the gateway and how it is answered:
+//! packets and bytes both ways, malformed frames, messages refused (too long,
+//! port disabled, backpressure aborts) and confirmed requests that were never
+//! answered. Unlike the top talkers, the counts are not windowed - they point
+//! at the workstation producing bad frames or more requests than the trunk
+//! can serve.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Peers tracked at once; the one heard from least recently is evicted
+pub const MAX_TRACKED_PEERS: usize = 32;
+
+/// Counters for one B/IP endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerStats {
+    pub addr: SocketAddr,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    /// Frames that failed BVLC/NPDU parsing
+    pub errors: u64,
+    /// Messages refused with a Reject-Message-To-Network or an Abort
+    pub rejected: u64,
+    /// Confirmed requests routed onto MS/TP
+    pub confirmed_requests: u64,
+    /// Confirmed requests that ran out of retries
+    pub unanswered: u64,
+    /// Datagrams dropped by the receive fair queue before routing
+    pub dropped: u64,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+}
+
+impl PeerStats {
+    fn new(addr: SocketAddr, now: Instant) -> Self {
+        Self {
+            addr,
+            packets_received: 0,
+            bytes_received: 0,
+            packets_sent: 0,
+            bytes_sent: 0,
+            errors: 0,
+            rejected: 0,
+            confirmed_requests: 0,
+            unanswered: 0,
+            dropped: 0,
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+
+    /// Share of confirmed requests left unanswered, in percent
+    pub fn unanswered_pct(&self) -> Option<f32> {
+        if self.confirmed_requests == 0 {
+            return None;
+        }
+        Some(self.unanswered as f32 * 100.0 / self.confirmed_requests as f32)
+    }
+}
+
+/// Bounded table of peer counters
+pub struct PeerTable {
+    peers: HashMap<SocketAddr, PeerStats>,
+}
+
+impl PeerTable {
+    pub fn new() -> Self {
+        Self { peers: HashMap::new() }
+    }
+
+    /// A peer's counters, adding it to the table (evicting the stalest) if needed
+    fn entry(&mut self, addr: SocketAddr, now: Instant) -> &mut PeerStats {
+        if !self.peers.contains_key(&addr) && self.peers.len() >= MAX_TRACKED_PEERS {
+            if let Some(stalest) = self.peers.values().min_by_key(|p| p.last_seen).map(|p| p.addr) {
+                self.peers.remove(&stalest);
+            }
+        }
+        self.peers.entry(addr).or_insert_with(|| PeerStats::new(addr, now))
+    }
+
+    /// Count a frame received from a peer, adding it to the table if needed
+    pub fn record_received(&mut self, addr: SocketAddr, bytes: usize, now: Instant) {
+        let peer = self.entry(addr, now);
+        peer.packets_received += 1;
+        peer.bytes_received += bytes as u64;
+        peer.last_seen = now;
+    }
+
+    /// Count datagrams from a peer dropped before routing, adding it to the table if needed
+    pub fn record_dropped(&mut self, addr: SocketAddr, count: u64, now: Instant) {
+        let peer = self.entry(addr, now);
+        peer.dropped += count;
+        peer.last_seen = now;
+    }
+
+    /// Count a frame sent to a peer. Destinations never heard from are not tracked.
+    pub fn record_sent(&mut self, addr: SocketAddr, bytes: usize) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.packets_sent += 1;
+            peer.bytes_sent += bytes as u64;
+        }
+    }
+
+    pub fn record_error(&mut self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.errors += 1;
+        }
+    }
+
+    pub fn record_rejected(&mut self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.rejected += 1;
+        }
+    }
+
+    pub fn record_confirmed_request(&mut self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.confirmed_requests += 1;
+        }
+    }
+
+    pub fn record_unanswered(&mut self, addr: SocketAddr) {
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.unanswered += 1;
+        }
+    }
+
+    /// All tracked peers, busiest first
+    pub fn snapshot(&self) -> Vec<PeerStats> {
+        let mut peers: Vec<PeerStats> = self.peers.values().copied().collect();
+        peers.sort_by(|a, b| b.packets_received.cmp(&a.packets_received).then(a.addr.cmp(&b.addr)));
+        peers
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+}
+
+impl Default for PeerTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    fn peer(last: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, last)), 47808)
+    }
+
+    #[test]
+    fn test_counts_per_peer() {
+        let now = Instant::now();
+        let mut table = PeerTable::new();
+        table.record_received(peer(10), 100, now);
+        table.record_received(peer(10), 50, now);
+        table.record_received(peer(20), 30, now);
+        table.record_sent(peer(10), 40);
+        table.record_error(peer(20));
+        table.record_confirmed_request(peer(10));
+        table.record_confirmed_request(peer(10));
+        table.record_unanswered(peer(10));
+        table.record_rejected(peer(10));
+        table.record_dropped(peer(20), 3, now);
+
+        let peers = table.snapshot();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].addr, peer(10));
+        assert_eq!((peers[0].packets_received, peers[0].bytes_received), (2, 150));
+        assert_eq!((peers[0].packets_sent, peers[0].bytes_sent), (1, 40));
+        assert_eq!(peers[0].rejected, 1);
+        assert_eq!(peers[0].unanswered_pct(), Some(50.0));
+        assert_eq!(peers[1].errors, 1);
+        assert_eq!(peers[1].dropped, 3);
+        assert_eq!(peers[1].unanswered_pct(), None);
+    }
+
+    #[test]
+    fn test_unknown_destinations_not_tracked() {
+        let mut table = PeerTable::new();
+        table.record_sent(peer(30), 10);
+        table.record_error(peer(30));
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn test_stalest_peer_evicted_when_full() {
+        let start = Instant::now();
+        let mut table = PeerTable::new();
+        for i in 0..MAX_TRACKED_PEERS {
+            table.record_received(peer(i as u8), 10, start + Duration::from_secs(i as u64));
+        }
+        // Peer 0 is heard again, so peer 1 is now the stalest
+        table.record_received(peer(0), 10, start + Duration::from_secs(100));
+        table.record_received(peer(200), 10, start + Duration::from_secs(101));
+
+        assert_eq!(table.len(), MAX_TRACKED_PEERS);
+        let addrs: Vec<SocketAddr> = table.snapshot().iter().map(|p| p.addr).collect();
+        assert!(addrs.contains(&peer(0)));
+        assert!(addrs.contains(&peer(200)));
+        assert!(!addrs.contains(&peer(1)));
+    }
+}
@@ -0,0 +1,86 @@
+//! Read-only public status endpoint on an alternate port
+//!
+//! A second HTTP server that answers a single GET with a stripped-down JSON
+//! status document (health, uptime, ring and routing counters). No portal,
+//! configuration or control routes are registered on it, so site IT can
+//! expose this port to a NOC while the admin portal stays firewalled.
+//! Disabled unless a port is configured.
+
+use embedded_svc::io::Write;
+use esp_idf_svc::http::server::{Configuration as HttpConfig, EspHttpServer};
+use log::info;
+use std::sync::{Arc, Mutex};
+
+use crate::clock;
+use crate::health::HealthReport;
+use crate::web::{json_escape, snapshot, WebState};
+
+/// The only path served on the public port
+pub const PUBLIC_STATUS_PATH: &str = "/status.json";
+
+/// Lowest configurable public port (privileged ports are left to the portal)
+pub const MIN_PUBLIC_PORT: u16 = 1024;
+
+/// Control socket for this server; the portal uses the default (32768)
+const CTRL_PORT: u16 = 32769;
+
+/// Start the public status server on `port`
+pub fn start_public_status_server(
+    state: Arc<Mutex<WebState>>,
+    port: u16,
+) -> anyhow::Result<EspHttpServer<'static>> {
+    let http_config = HttpConfig {
+        http_port: port,
+        ctrl_port: CTRL_PORT,
+        max_open_sockets: 2,
+        max_uri_handlers: 1,
+        ..Default::default()
+    };
+
+    let mut server = EspHttpServer::new(&http_config)?;
+    info!("Public status server starting on port {}", port);
+
+    server.fn_handler(PUBLIC_STATUS_PATH, embedded_svc::http::Method::Get, move |req| {
+        let health = HealthReport::snapshot();
+        let json = build_status(&snapshot(&state), &health);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+            ("Cache-Control", "no-store"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    Ok(server)
+}
+
+/// Build the public status document. Leaves out addresses, SSIDs, serial
+/// numbers and anything else not needed to tell whether the gateway is well.
+fn build_status(state: &WebState, health: &HealthReport) -> String {
+    format!(
+        r#"{{"status":"{}","site_name":"{}","device_name":"{}","device_instance":{},"firmware":"{}","uptime_secs":{},"wifi_up":{},"ring_up":{},"heap_ok":{},"master_count":{},"rx_frames":{},"tx_frames":{},"crc_errors":{},"token_pass_failures":{},"mstp_to_ip":{},"ip_to_mstp":{},"routing_errors":{},"transaction_timeouts":{},"network_conflict":{},"timestamp":{}}}"#,
+        if health.healthy() { "ok" } else { "unhealthy" },
+        json_escape(&state.config.site_name),
+        json_escape(&state.config.device_name),
+        state.config.device_instance,
+        env!("CARGO_PKG_VERSION"),
+        state.uptime_secs(),
+        health.wifi_up,
+        health.ring_up,
+        health.heap_ok(),
+        state.mstp_stats.master_count,
+        state.mstp_stats.rx_frames,
+        state.mstp_stats.tx_frames,
+        state.mstp_stats.crc_errors,
+        state.mstp_stats.token_pass_failures,
+        state.gateway_stats.mstp_to_ip_packets,
+        state.gateway_stats.ip_to_mstp_packets,
+        state.gateway_stats.routing_errors,
+        state.gateway_stats.transaction_timeouts,
+        state.network_conflict.is_some(),
+        clock::utc_now()
+            .map(|t| format!(r#""{}""#, clock::format_utc(t)))
+            .unwrap_or_else(|| "null".to_string()),
+    )
+}
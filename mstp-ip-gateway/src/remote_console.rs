@@ -0,0 +1,575 @@
+//! Serial console over TCP
+//!
+//! The serial console's commands, reachable with a telnet client for field
+//! engineers who can get onto the site network but not to the USB port.
+//! Disabled unless a port is configured. Sessions log in with a portal admin
+//! account, so nobody can log in until one exists. One session at a time;
+//! it is dropped after a few failed logins, when the login is not finished
+//! within `LOGIN_TIMEOUT` or when left idle. An address that keeps failing
+//! or timing out at the login is turned away for a while that doubles with
+//! each round of failures, so it can neither guess on nor hold the session.
+//! Logins, changes and logouts go to the event log. `logs on` follows the
+//! gateway log in the session, in the serial output's format. Like any telnet session the
+//! traffic is not encrypted.
+
+use log::{info, warn};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::auth::Role;
+use crate::console::{self, Origin, MAX_LINE_LEN};
+use crate::events::{self, EventCategory, Severity};
+use crate::logging;
+use crate::web::WebState;
+
+/// Stack size for the listener and session threads
+const SESSION_STACK_SIZE: usize = 8192;
+
+/// A session with no input for this long is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often log lines are passed on while following the log
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Failed logins before the connection is dropped
+const MAX_LOGIN_ATTEMPTS: u8 = 3;
+
+/// Pause after a failed login to slow down guessing
+const LOGIN_FAILURE_DELAY: Duration = Duration::from_secs(2);
+
+/// Time from connecting to logging in before the connection is dropped
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long an address is turned away after its first MAX_LOGIN_ATTEMPTS
+/// failures; doubled for each further round, up to MAX_LOGIN_BACKOFF
+const LOGIN_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_LOGIN_BACKOFF: Duration = Duration::from_secs(900);
+
+/// Failures are forgotten once an address has been quiet this long
+const LOGIN_FAILURE_MEMORY: Duration = Duration::from_secs(3600);
+
+/// Addresses whose failed logins are remembered; the longest quiet one
+/// makes room for a new one
+const MAX_FAILING_ADDRESSES: usize = 16;
+
+// Telnet commands (RFC 854) and the echo option (RFC 857)
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const ECHO: u8 = 1;
+
+/// Set while a session is open
+static SESSION_OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Failed logins per client address
+static LOGIN_FAILURES: Mutex<LoginFailures> = Mutex::new(LoginFailures::new());
+
+/// Listen for console sessions on `port`
+pub fn spawn_remote_console(web_state: Arc<Mutex<WebState>>, port: u16) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    info!("Remote console listening on TCP port {}", port);
+    thread::Builder::new()
+        .stack_size(SESSION_STACK_SIZE)
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => accept(stream, &web_state),
+                    Err(e) => warn!("Remote console accept failed: {}", e),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Start a session thread for a new connection, or turn it away if a session is open
+fn accept(mut stream: TcpStream, web_state: &Arc<Mutex<WebState>>) {
+    let Ok(peer) = stream.peer_addr() else { return };
+    if let Some(wait) = LOGIN_FAILURES.lock().unwrap().lockout(peer.ip(), Instant::now()) {
+        let notice = format!("Too many failed logins - try again in {}s\r\n", wait.as_secs() + 1);
+        let _ = stream.write_all(notice.as_bytes());
+        return;
+    }
+    if SESSION_OPEN.swap(true, Ordering::SeqCst) {
+        let _ = stream.write_all(b"Another console session is open\r\n");
+        return;
+    }
+    let web_state = Arc::clone(web_state);
+    let spawned = thread::Builder::new()
+        .stack_size(SESSION_STACK_SIZE)
+        .spawn(move || {
+            session(Connection::new(stream), peer.ip(), &web_state);
+            SESSION_OPEN.store(false, Ordering::SeqCst);
+        });
+    if let Err(e) = spawned {
+        warn!("Failed to spawn remote console session: {}", e);
+        SESSION_OPEN.store(false, Ordering::SeqCst);
+    }
+}
+
+fn session(mut conn: Connection, ip: IpAddr, web_state: &Mutex<WebState>) {
+    let login_deadline = Instant::now() + LOGIN_TIMEOUT;
+    conn.deadline = Some(login_deadline);
+    let has_admin = web_state.lock().unwrap().users.iter().any(|u| u.role == Role::Admin);
+    if !has_admin {
+        let _ = conn.write("No portal admin account - create one in the web portal first\n");
+        events::record(EventCategory::Console, Severity::Warning, &format!("Login from {} refused: no admin account", ip));
+        return;
+    }
+    let Some(username) = login(&mut conn, ip, web_state) else {
+        if Instant::now() >= login_deadline {
+            info!("Remote console: login from {} timed out", ip);
+            events::record(EventCategory::Console, Severity::Warning, &format!("Login from {} timed out", ip));
+            // Holding the session without logging in counts as a failure
+            LOGIN_FAILURES.lock().unwrap().failed(ip, Instant::now());
+        }
+        return;
+    };
+    conn.deadline = None;
+    if conn.stream.set_read_timeout(Some(IDLE_TIMEOUT)).is_err() {
+        return;
+    }
+    info!("Remote console: {} logged in from {}", username, ip);
+    events::record(EventCategory::Console, Severity::Info, &format!("{:.32} logged in from {}", username, ip));
+
+    let _ = conn.write("Type 'help' for commands, 'logs on' to follow the log, 'exit' to log out\n");
+    let mut commands = 0u32;
+    // While following the log, reads time out every LOG_POLL_INTERVAL to pass
+    // lines on, so idleness is timed here rather than by the socket
+    let mut follow: Option<Receiver<String>> = None;
+    let mut last_input = Instant::now();
+    let mut prompt = true;
+    let reason = loop {
+        if prompt && conn.write("> ").is_err() {
+            break "connection lost";
+        }
+        prompt = true;
+        let command = match conn.read_input() {
+            Ok(Some(Input::Line(line))) => line.trim().to_string(),
+            Ok(Some(Input::TooLong)) => {
+                let _ = conn.write("Line too long\n");
+                continue;
+            }
+            Ok(None) => break "closed by client",
+            Err(ref e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => match follow {
+                Some(ref lines) if last_input.elapsed() < IDLE_TIMEOUT => {
+                    if lines.try_iter().try_for_each(|line| conn.write(&line).and_then(|_| conn.write("\n"))).is_err() {
+                        break "connection lost";
+                    }
+                    prompt = false;
+                    continue;
+                }
+                _ => break "idle timeout",
+            },
+            Err(_) => break "connection lost",
+        };
+        last_input = Instant::now();
+        match command.as_str() {
+            "" => continue,
+            "exit" | "quit" | "logout" => break "logged out",
+            "logs on" | "logs off" => {
+                let on = command == "logs on";
+                follow = on.then(logging::tap);
+                if !on {
+                    logging::untap();
+                }
+                let timeout = if on { LOG_POLL_INTERVAL } else { IDLE_TIMEOUT };
+                if conn.stream.set_read_timeout(Some(timeout)).is_err() {
+                    break "connection lost";
+                }
+                let _ = conn.write(if on { "Following the log - 'logs off' to stop\n" } else { "Stopped following the log\n" });
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(entry) = audit_entry(&command) {
+            events::record(EventCategory::Console, Severity::Info, &format!("{:.32}: {}", username, entry));
+        }
+        commands += 1;
+        let reply = console::execute(&command, web_state, Origin::Remote);
+        if conn.write(&reply).and_then(|_| conn.write("\n")).is_err() {
+            break "connection lost";
+        }
+    };
+    if follow.is_some() {
+        logging::untap();
+    }
+    info!("Remote console: {} from {} ended ({})", username, ip, reason);
+    events::record(
+        EventCategory::Console,
+        Severity::Info,
+        &format!("{:.32} from {} ended: {}, {} commands", username, ip, reason, commands),
+    );
+}
+
+/// Prompt for a portal admin account. Returns the username once logged in.
+fn login(conn: &mut Connection, ip: IpAddr, web_state: &Mutex<WebState>) -> Option<String> {
+    conn.write("BACman gateway console - log in with a portal admin account\n").ok()?;
+    for _ in 0..MAX_LOGIN_ATTEMPTS {
+        conn.write("login: ").ok()?;
+        let username = conn.read_line()?.trim().to_string();
+        // The client stops echoing while we claim to echo, hiding the password
+        conn.write_raw(&[IAC, WILL, ECHO]).ok()?;
+        conn.write("password: ").ok()?;
+        let password = conn.read_line()?;
+        conn.write_raw(&[IAC, WONT, ECHO]).ok()?;
+        conn.write("\n").ok()?;
+
+        let account = web_state
+            .lock()
+            .unwrap()
+            .users
+            .iter()
+            .find(|u| u.username == username && u.role == Role::Admin)
+            .cloned();
+        // Hash outside the lock so the portal is not held up
+        match account.map(|a| a.verify(&password)) {
+            Some(Ok(true)) => {
+                LOGIN_FAILURES.lock().unwrap().succeeded(ip);
+                return Some(username);
+            }
+            Some(Err(e)) => warn!("Remote console: login check for '{}' failed: {}", username, e),
+            _ => {}
+        }
+        warn!("Remote console: failed login for '{}' from {}", username, ip);
+        events::record(EventCategory::Console, Severity::Warning, &format!("Login failed for '{:.32}' from {}", username, ip));
+        let lockout = {
+            let mut failures = LOGIN_FAILURES.lock().unwrap();
+            failures.failed(ip, Instant::now());
+            failures.lockout(ip, Instant::now())
+        };
+        if let Some(wait) = lockout {
+            warn!("Remote console: turning {} away for {}s after repeated failed logins", ip, wait.as_secs());
+            events::record(
+                EventCategory::Console,
+                Severity::Warning,
+                &format!("{} turned away for {}s after repeated failed logins", ip, wait.as_secs()),
+            );
+            let _ = conn.write("Too many failed logins\n");
+            return None;
+        }
+        thread::sleep(LOGIN_FAILURE_DELAY);
+        conn.write("Login incorrect\n").ok()?;
+    }
+    None
+}
+
+/// Failed logins of one client address
+#[derive(Debug, Clone, Copy)]
+struct FailingAddress {
+    ip: IpAddr,
+    count: u32,
+    last: Instant,
+}
+
+/// Failed logins per client address, to turn away an address that keeps failing
+#[derive(Debug)]
+struct LoginFailures {
+    addresses: Vec<FailingAddress>,
+}
+
+impl LoginFailures {
+    const fn new() -> Self {
+        Self { addresses: Vec::new() }
+    }
+
+    /// Count a failed login from `ip`
+    fn failed(&mut self, ip: IpAddr, now: Instant) {
+        self.forget_quiet(now);
+        if let Some(address) = self.addresses.iter_mut().find(|a| a.ip == ip) {
+            address.count += 1;
+            address.last = now;
+            return;
+        }
+        if self.addresses.len() >= MAX_FAILING_ADDRESSES {
+            if let Some(quietest) = self.addresses.iter().enumerate().min_by_key(|(_, a)| a.last).map(|(i, _)| i) {
+                self.addresses.remove(quietest);
+            }
+        }
+        self.addresses.push(FailingAddress { ip, count: 1, last: now });
+    }
+
+    /// Forget the failures of `ip` once it has logged in
+    fn succeeded(&mut self, ip: IpAddr) {
+        self.addresses.retain(|a| a.ip != ip);
+    }
+
+    /// How much longer `ip` is turned away, if it is
+    fn lockout(&mut self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        self.forget_quiet(now);
+        let address = self.addresses.iter().find(|a| a.ip == ip)?;
+        let rounds = address.count / u32::from(MAX_LOGIN_ATTEMPTS);
+        if rounds == 0 {
+            return None;
+        }
+        let period = LOGIN_BACKOFF.saturating_mul(1 << (rounds - 1).min(16)).min(MAX_LOGIN_BACKOFF);
+        let wait = (address.last + period).saturating_duration_since(now);
+        (!wait.is_zero()).then_some(wait)
+    }
+
+    fn forget_quiet(&mut self, now: Instant) {
+        self.addresses.retain(|a| now.saturating_duration_since(a.last) < LOGIN_FAILURE_MEMORY);
+    }
+}
+
+/// Audit entry for a command that changes something. Values given to `set`
+/// are left out since they may be passwords.
+fn audit_entry(command: &str) -> Option<String> {
+    let mut words = command.split_whitespace();
+    let verb = words.next()?;
+    match verb {
+        "set" => Some(format!("set {}", words.next().unwrap_or(""))),
+        "save" | "reboot" | "factory-reset" => Some(verb.to_string()),
+        "writeprop" => Some(command.to_string()),
+        _ => None,
+    }
+}
+
+/// A telnet connection: lines in through a `LineDecoder`, text out with CR LF line ends
+struct Connection {
+    stream: TcpStream,
+    /// Reads fail with TimedOut from here on (used while logging in)
+    deadline: Option<Instant>,
+    decoder: LineDecoder,
+    buf: [u8; 64],
+    start: usize,
+    end: usize,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Self {
+        Self { stream, deadline: None, decoder: LineDecoder::default(), buf: [0; 64], start: 0, end: 0 }
+    }
+
+    /// Next line from the client; Ok(None) once it has closed the connection
+    fn read_input(&mut self) -> std::io::Result<Option<Input>> {
+        loop {
+            while self.start < self.end {
+                let byte = self.buf[self.start];
+                self.start += 1;
+                if let Some(input) = self.decoder.feed(byte) {
+                    return Ok(Some(input));
+                }
+            }
+            if let Some(deadline) = self.deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(ErrorKind::TimedOut.into());
+                }
+                self.stream.set_read_timeout(Some(remaining))?;
+            }
+            let n = self.stream.read(&mut self.buf)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.start = 0;
+            self.end = n;
+        }
+    }
+
+    /// Next line during login; None on any error, an overlong line or a closed connection
+    fn read_line(&mut self) -> Option<String> {
+        match self.read_input() {
+            Ok(Some(Input::Line(line))) => Some(line),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, text: &str) -> std::io::Result<()> {
+        self.stream.write_all(text.replace('\n', "\r\n").as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.stream.write_all(bytes)
+    }
+}
+
+/// What the decoder made of the bytes up to a line end
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    Line(String),
+    /// More than MAX_LINE_LEN bytes; the line is discarded
+    TooLong,
+}
+
+/// Position in a telnet command sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Telnet {
+    #[default]
+    Data,
+    /// After IAC
+    Command,
+    /// After IAC WILL/WONT/DO/DONT, waiting for the option byte
+    Option,
+    /// Inside IAC SB ... IAC SE
+    Sub,
+    /// IAC inside a subnegotiation
+    SubIac,
+}
+
+/// Assembles lines typed into a telnet client, dropping option negotiation
+/// and accepting CR LF, CR NUL or a bare LF as the line end
+#[derive(Debug, Default)]
+struct LineDecoder {
+    line: Vec<u8>,
+    telnet: Telnet,
+    after_cr: bool,
+    too_long: bool,
+}
+
+impl LineDecoder {
+    fn feed(&mut self, byte: u8) -> Option<Input> {
+        match self.telnet {
+            Telnet::Data => {}
+            Telnet::Command => {
+                self.telnet = match byte {
+                    WILL | WONT | DO | DONT => Telnet::Option,
+                    SB => Telnet::Sub,
+                    _ => Telnet::Data,
+                };
+                return None;
+            }
+            Telnet::Option => {
+                self.telnet = Telnet::Data;
+                return None;
+            }
+            Telnet::Sub => {
+                if byte == IAC {
+                    self.telnet = Telnet::SubIac;
+                }
+                return None;
+            }
+            Telnet::SubIac => {
+                self.telnet = if byte == SE { Telnet::Data } else { Telnet::Sub };
+                return None;
+            }
+        }
+
+        let after_cr = std::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            IAC => self.telnet = Telnet::Command,
+            b'\n' | 0 if after_cr => {}
+            b'\r' | b'\n' => {
+                let line = std::mem::take(&mut self.line);
+                if std::mem::take(&mut self.too_long) {
+                    return Some(Input::TooLong);
+                }
+                return Some(Input::Line(String::from_utf8_lossy(&line).into_owned()));
+            }
+            // Backspace and DEL
+            0x08 | 0x7f => {
+                self.line.pop();
+            }
+            b if b < 0x20 => {}
+            b if self.line.len() < MAX_LINE_LEN => self.line.push(b),
+            _ => self.too_long = true,
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> Vec<Input> {
+        let mut decoder = LineDecoder::default();
+        bytes.iter().filter_map(|&b| decoder.feed(b)).collect()
+    }
+
+    fn line(s: &str) -> Input {
+        Input::Line(s.to_string())
+    }
+
+    #[test]
+    fn test_decoder_drops_negotiation_and_handles_line_ends() {
+        // What a telnet client sends on connect, then a line ended CR LF
+        let mut bytes = vec![IAC, DO, ECHO, IAC, WILL, 31, IAC, SB, 31, 0, 80, 0, 24, IAC, SE];
+        bytes.extend_from_slice(b"show stats\r\n");
+        bytes.extend_from_slice(b"admin\r\0");
+        bytes.extend_from_slice(b"save\n\r\n");
+        assert_eq!(decode(&bytes), vec![line("show stats"), line("admin"), line("save"), line("")]);
+    }
+
+    #[test]
+    fn test_decoder_editing_and_overlong_lines() {
+        assert_eq!(decode(b"sax\x08ve\x7f\x7fve\x1b\r\n"), vec![line("save")]);
+
+        let mut bytes = vec![b'x'; MAX_LINE_LEN + 1];
+        bytes.extend_from_slice(b"\r\nhelp\r\n");
+        assert_eq!(decode(&bytes), vec![Input::TooLong, line("help")]);
+    }
+
+    #[test]
+    fn test_audit_entry_leaves_out_set_values() {
+        assert_eq!(audit_entry("set wifi_pass hunter22"), Some("set wifi_pass".to_string()));
+        assert_eq!(audit_entry("save"), Some("save".to_string()));
+        assert_eq!(
+            audit_entry("writeprop 5 analog-value:3 present-value 72.5"),
+            Some("writeprop 5 analog-value:3 present-value 72.5".to_string())
+        );
+        assert_eq!(audit_entry("show config"), None);
+        assert_eq!(audit_entry(""), None);
+    }
+
+    #[test]
+    fn test_login_failures_back_off_per_address() {
+        let start = Instant::now();
+        let guesser: IpAddr = "192.168.1.50".parse().unwrap();
+        let other: IpAddr = "192.168.1.51".parse().unwrap();
+        let mut failures = LoginFailures::new();
+
+        // A round of failed logins turns the address away; others can still log in
+        for _ in 0..MAX_LOGIN_ATTEMPTS - 1 {
+            failures.failed(guesser, start);
+        }
+        assert_eq!(failures.lockout(guesser, start), None);
+        failures.failed(guesser, start);
+        assert_eq!(failures.lockout(guesser, start), Some(LOGIN_BACKOFF));
+        assert_eq!(failures.lockout(other, start), None);
+        assert_eq!(failures.lockout(guesser, start + LOGIN_BACKOFF), None);
+
+        // Each further round doubles the wait, up to the cap
+        let later = start + LOGIN_BACKOFF;
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            failures.failed(guesser, later);
+        }
+        assert_eq!(failures.lockout(guesser, later), Some(LOGIN_BACKOFF * 2));
+        for _ in 0..10 * MAX_LOGIN_ATTEMPTS {
+            failures.failed(guesser, later);
+        }
+        assert_eq!(failures.lockout(guesser, later), Some(MAX_LOGIN_BACKOFF));
+
+        // Forgotten after a quiet spell, or on a successful login
+        assert_eq!(failures.lockout(guesser, later + LOGIN_FAILURE_MEMORY), None);
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            failures.failed(other, later);
+        }
+        failures.succeeded(other);
+        assert_eq!(failures.lockout(other, later), None);
+    }
+
+    #[test]
+    fn test_login_failures_bounded() {
+        let start = Instant::now();
+        let mut failures = LoginFailures::new();
+        for i in 0..MAX_FAILING_ADDRESSES as u8 + 1 {
+            let ip = IpAddr::from([10, 0, 0, i]);
+            for _ in 0..MAX_LOGIN_ATTEMPTS {
+                failures.failed(ip, start + Duration::from_secs(u64::from(i)));
+            }
+        }
+        assert_eq!(failures.addresses.len(), MAX_FAILING_ADDRESSES);
+        let now = start + Duration::from_secs(MAX_FAILING_ADDRESSES as u64);
+        assert_eq!(failures.lockout(IpAddr::from([10, 0, 0, 0]), now), None);
+        assert!(failures.lockout(IpAddr::from([10, 0, 0, MAX_FAILING_ADDRESSES as u8]), now).is_some());
+    }
+}
@@ -0,0 +1,190 @@
+//! Remote router table queries
+//!
+//! Maps an unfamiliar internetwork from the gateway itself: an
+//! Initialize-Routing-Table with no ports asks a router for its whole routing
+//! table (Clause 6.4.7, answered with an Initialize-Routing-Table-Ack), and a
+//! Who-Is-Router-To-Network with no network asks which networks it reaches.
+//! One query runs at a time; answers from the queried address are collected
+//! for a short window and shown on the diagnostics page.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::npdu::Npdu;
+
+/// How long answers to a query are collected
+pub const ROUTER_QUERY_WINDOW: Duration = Duration::from_secs(3);
+
+/// Routing table entries kept from one answer
+pub const MAX_REMOTE_ROUTES: usize = 64;
+
+const NL_WHO_IS_ROUTER_TO_NETWORK: u8 = 0x00;
+const NL_I_AM_ROUTER_TO_NETWORK: u8 = 0x01;
+const NL_INITIALIZE_ROUTING_TABLE: u8 = 0x06;
+const NL_INITIALIZE_ROUTING_TABLE_ACK: u8 = 0x07;
+
+/// Which question to ask the router
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterQueryKind {
+    /// Initialize-Routing-Table with zero ports (read the routing table)
+    RoutingTable,
+    /// Who-Is-Router-To-Network for every network
+    WhoIsRouter,
+}
+
+impl RouterQueryKind {
+    /// Form value and JSON name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouterQueryKind::RoutingTable => "routing-table",
+            RouterQueryKind::WhoIsRouter => "who-is-router",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "routing-table" => Some(RouterQueryKind::RoutingTable),
+            "who-is-router" => Some(RouterQueryKind::WhoIsRouter),
+            _ => None,
+        }
+    }
+
+    /// Local network-layer NPDU carrying the query
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            RouterQueryKind::RoutingTable => Npdu::network_message(&[NL_INITIALIZE_ROUTING_TABLE, 0x00]).encode(),
+            RouterQueryKind::WhoIsRouter => Npdu::network_message(&[NL_WHO_IS_ROUTER_TO_NETWORK]).encode(),
+        }
+    }
+}
+
+impl fmt::Display for RouterQueryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RouterQueryKind::RoutingTable => "Initialize-Routing-Table",
+            RouterQueryKind::WhoIsRouter => "Who-Is-Router-To-Network",
+        })
+    }
+}
+
+/// One routing table entry reported by a remote router
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRoute {
+    pub network: u16,
+    pub port_id: u8,
+    pub port_info: Vec<u8>,
+}
+
+/// A query and the answers collected so far
+#[derive(Debug, Clone)]
+pub struct RouterQuery {
+    pub target: SocketAddr,
+    pub kind: RouterQueryKind,
+    pub sent_at: Instant,
+    /// Entries of an Initialize-Routing-Table-Ack
+    pub routes: Vec<RemoteRoute>,
+    /// Networks announced in I-Am-Router-To-Network
+    pub networks: Vec<u16>,
+    /// Whether any answer arrived
+    pub answered: bool,
+}
+
+impl RouterQuery {
+    pub fn new(target: SocketAddr, kind: RouterQueryKind, now: Instant) -> Self {
+        Self { target, kind, sent_at: now, routes: Vec::new(), networks: Vec::new(), answered: false }
+    }
+
+    /// Answers stop being collected once the window has passed
+    pub fn is_finished(&self, now: Instant) -> bool {
+        now.duration_since(self.sent_at) >= ROUTER_QUERY_WINDOW
+    }
+
+    /// Record a network-layer message from `source` (message type first).
+    /// Returns true when it answered this query.
+    pub fn record_reply(&mut self, source: SocketAddr, message: &[u8], now: Instant) -> bool {
+        if source != self.target || self.is_finished(now) {
+            return false;
+        }
+        let Some((&msg_type, body)) = message.split_first() else { return false };
+        match msg_type {
+            NL_INITIALIZE_ROUTING_TABLE_ACK => {
+                for route in parse_routing_table(body) {
+                    if self.routes.len() >= MAX_REMOTE_ROUTES {
+                        break;
+                    }
+                    if !self.routes.contains(&route) {
+                        self.routes.push(route);
+                    }
+                }
+            }
+            NL_I_AM_ROUTER_TO_NETWORK => {
+                for network in body.chunks_exact(2).map(|n| u16::from_be_bytes([n[0], n[1]])) {
+                    if !self.networks.contains(&network) {
+                        self.networks.push(network);
+                    }
+                }
+            }
+            _ => return false,
+        }
+        self.answered = true;
+        true
+    }
+}
+
+/// Entries of an Initialize-Routing-Table(-Ack) body: port count, then per
+/// port DNET, port ID, port info length and port info. A truncated entry
+/// ends the list.
+pub fn parse_routing_table(body: &[u8]) -> Vec<RemoteRoute> {
+    let mut routes = Vec::new();
+    let Some((&count, mut rest)) = body.split_first() else { return routes };
+    for _ in 0..count {
+        let [hi, lo, port_id, info_len, tail @ ..] = rest else { break };
+        let info_len = *info_len as usize;
+        if tail.len() < info_len {
+            break;
+        }
+        routes.push(RemoteRoute {
+            network: u16::from_be_bytes([*hi, *lo]),
+            port_id: *port_id,
+            port_info: tail[..info_len].to_vec(),
+        });
+        rest = &tail[info_len..];
+    }
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_routing_table_ack() {
+        let body = [0x03, 0x00, 0x05, 0x01, 0x00, 0x07, 0xD1, 0x02, 0x01, 0xAA, 0x00, 0x09];
+        let routes = parse_routing_table(&body);
+        assert_eq!(routes.len(), 2, "truncated third entry is dropped");
+        assert_eq!(routes[0], RemoteRoute { network: 5, port_id: 1, port_info: Vec::new() });
+        assert_eq!(routes[1], RemoteRoute { network: 2001, port_id: 2, port_info: vec![0xAA] });
+        assert!(parse_routing_table(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_query_collects_answers_from_target_only() {
+        let now = Instant::now();
+        let target: SocketAddr = "10.0.0.9:47808".parse().unwrap();
+        let other: SocketAddr = "10.0.0.10:47808".parse().unwrap();
+        let mut query = RouterQuery::new(target, RouterQueryKind::WhoIsRouter, now);
+
+        assert!(!query.record_reply(other, &[0x01, 0x00, 0x05], now));
+        assert!(query.record_reply(target, &[0x01, 0x00, 0x05, 0x00, 0x06], now));
+        assert!(query.record_reply(target, &[0x01, 0x00, 0x06, 0x00, 0x07], now));
+        assert_eq!(query.networks, vec![5, 6, 7]);
+        assert!(!query.record_reply(target, &[0x03, 0x00, 0x05], now), "not an answer");
+
+        let late = now + ROUTER_QUERY_WINDOW;
+        assert!(query.is_finished(late));
+        assert!(!query.record_reply(target, &[0x07, 0x01, 0x00, 0x08, 0x01, 0x00], late));
+        assert!(query.routes.is_empty());
+        assert!(query.answered);
+    }
+}
@@ -0,0 +1,459 @@
+//! ReadPropertyMultiple proxying for MS/TP devices without RPM
+//!
+//! Many low-cost MS/TP devices only execute ReadProperty, so a head-end
+//! that polls with ReadPropertyMultiple gets a Reject back. Once a device
+//! has rejected RPM as an unrecognized service, the gateway answers RPM for
+//! it instead: each property reference becomes one ReadProperty on the
+//! MS/TP side, and the values (or per-property errors) are assembled into
+//! a ReadPropertyMultiple-ACK for the IP client.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Confirmed service choices
+pub const SERVICE_READ_PROPERTY: u8 = 12;
+pub const SERVICE_READ_PROPERTY_MULTIPLE: u8 = 14;
+
+/// Reject reason sent by devices that don't execute a service
+pub const REJECT_UNRECOGNIZED_SERVICE: u8 = 9;
+
+/// Most RPM translations in flight at once
+pub const MAX_RPM_PROXIES: usize = 8;
+
+/// Most property references a single proxied RPM may carry
+pub const MAX_PROXIED_PROPERTIES: usize = 64;
+
+/// How long to wait for each ReadProperty answer
+pub const RPM_PROXY_READ_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Retransmissions of an unanswered ReadProperty before the client is sent an Abort
+pub const RPM_PROXY_MAX_RETRIES: u8 = 2;
+
+/// Properties that expand to a device-defined list and can't be read one by one
+const PROP_ALL: u32 = 8;
+const PROP_OPTIONAL: u32 = 80;
+const PROP_REQUIRED: u32 = 105;
+
+/// Max APDU code sent in proxied requests (480 octets, the MS/TP limit)
+pub const PROXY_MAX_APDU_CODE: u8 = 0x03;
+
+/// One property reference from a ReadAccessSpecification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PropertyRef {
+    pub object_id: u32,
+    pub property_id: u32,
+    pub array_index: Option<u32>,
+}
+
+/// Outcome of one proxied ReadProperty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadResult {
+    /// Encoded property value (contents of the ReadProperty-ACK value tag)
+    Value(Vec<u8>),
+    /// Encoded error class and error code from the Error PDU
+    Error(Vec<u8>),
+}
+
+/// What the gateway should do after a ReadProperty answer
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProxyStep {
+    /// Send this ReadProperty APDU to the device next
+    Next(Vec<u8>),
+    /// All properties read: send this ReadPropertyMultiple-ACK APDU to the client
+    Complete(Vec<u8>),
+    /// The assembled ACK is larger than the client accepts (and cannot be segmented)
+    TooLong,
+    /// The device answered with something other than an ACK or Error
+    Failed,
+}
+
+/// An RPM from an IP client being answered with ReadProperty calls
+#[derive(Debug)]
+pub struct RpmProxy {
+    /// IP client that sent the RPM
+    pub client: SocketAddr,
+    /// Invoke ID of the client's RPM
+    pub client_invoke_id: u8,
+    /// Largest APDU the client accepts
+    pub client_max_apdu: usize,
+    /// MS/TP device being read
+    pub dest_mac: u8,
+    /// Invoke ID of the outstanding ReadProperty
+    pub invoke_id: u8,
+    /// When the outstanding ReadProperty was sent
+    pub sent_at: Instant,
+    /// Retransmissions of the outstanding ReadProperty
+    pub retries: u8,
+    refs: Vec<PropertyRef>,
+    results: Vec<ReadResult>,
+}
+
+impl RpmProxy {
+    /// Start proxying a complete, unsegmented RPM request APDU.
+    ///
+    /// Returns `None` when the request can't be translated: malformed, too
+    /// many references, or using ALL/REQUIRED/OPTIONAL.
+    pub fn new(rpm_apdu: &[u8], client: SocketAddr, dest_mac: u8, invoke_id: u8, now: Instant) -> Option<Self> {
+        // [type/flags, max segs/max APDU, invoke ID, service, service data...]
+        if rpm_apdu.len() < 4
+            || rpm_apdu[0] & 0xF8 != 0x00
+            || rpm_apdu[3] != SERVICE_READ_PROPERTY_MULTIPLE
+        {
+            return None;
+        }
+        let refs = parse_rpm_request(&rpm_apdu[4..])?;
+        Some(Self {
+            client,
+            client_invoke_id: rpm_apdu[2],
+            client_max_apdu: max_apdu_octets(rpm_apdu[1] & 0x0F),
+            dest_mac,
+            invoke_id,
+            sent_at: now,
+            retries: 0,
+            results: Vec::with_capacity(refs.len()),
+            refs,
+        })
+    }
+
+    /// ReadProperty request APDU for the property currently being read
+    pub fn request(&self) -> Vec<u8> {
+        encode_read_property(self.invoke_id, &self.refs[self.results.len()])
+    }
+
+    /// Properties read so far and in total
+    pub fn progress(&self) -> (usize, usize) {
+        (self.results.len(), self.refs.len())
+    }
+
+    /// Whether an APDU from `mac` answers the outstanding ReadProperty
+    pub fn matches(&self, mac: u8, apdu: &[u8]) -> bool {
+        mac == self.dest_mac && apdu.get(1) == Some(&self.invoke_id) && matches!(apdu[0] >> 4, 3 | 5 | 6 | 7)
+    }
+
+    /// Record the device's answer; the next ReadProperty (if any) uses `next_invoke_id`
+    pub fn record(&mut self, apdu: &[u8], next_invoke_id: u8, now: Instant) -> ProxyStep {
+        let Some(result) = parse_read_property_result(apdu) else {
+            return ProxyStep::Failed;
+        };
+        self.results.push(result);
+
+        if self.results.len() < self.refs.len() {
+            self.invoke_id = next_invoke_id;
+            self.sent_at = now;
+            self.retries = 0;
+            return ProxyStep::Next(self.request());
+        }
+
+        let ack = encode_rpm_ack(self.client_invoke_id, &self.refs, &self.results);
+        if ack.len() > self.client_max_apdu {
+            ProxyStep::TooLong
+        } else {
+            ProxyStep::Complete(ack)
+        }
+    }
+}
+
+/// Octets for a Max_APDU_Length_Accepted code (Clause 20.1.2.5)
+fn max_apdu_octets(code: u8) -> usize {
+    match code {
+        0 => 50,
+        1 => 128,
+        2 => 206,
+        3 => 480,
+        4 => 1024,
+        _ => 1476,
+    }
+}
+
+/// A decoded tag header
+pub struct Tag {
+    pub number: u8,
+    pub context: bool,
+    /// Length/value/type bits of the first octet (6 and 7 mark opening and closing tags)
+    pub raw_lvt: u8,
+    /// Length (or value), after any extended length octets
+    pub lvt: u32,
+    pub header_len: usize,
+}
+
+impl Tag {
+    pub fn is_context(&self, number: u8) -> bool {
+        self.context && self.number == number && self.raw_lvt <= 4
+    }
+
+    pub fn is_opening(&self, number: u8) -> bool {
+        self.context && self.number == number && self.raw_lvt == 6
+    }
+
+    pub fn is_closing(&self, number: u8) -> bool {
+        self.context && self.number == number && self.raw_lvt == 7
+    }
+}
+
+/// Decode the tag header at `pos`
+pub fn read_tag(data: &[u8], pos: usize) -> Option<Tag> {
+    let first = *data.get(pos)?;
+    let mut header_len = 1;
+    let mut number = first >> 4;
+    if number == 0x0F {
+        number = *data.get(pos + header_len)?;
+        header_len += 1;
+    }
+    let context = first & 0x08 != 0;
+    let raw_lvt = first & 0x07;
+    let mut lvt = raw_lvt as u32;
+    if raw_lvt == 5 {
+        let ext = *data.get(pos + header_len)?;
+        header_len += 1;
+        lvt = match ext {
+            254 => {
+                let v = read_unsigned(data, pos + header_len, 2)?;
+                header_len += 2;
+                v
+            }
+            255 => {
+                let v = read_unsigned(data, pos + header_len, 4)?;
+                header_len += 4;
+                v
+            }
+            n => n as u32,
+        };
+    }
+    Some(Tag { number, context, raw_lvt, lvt, header_len })
+}
+
+/// Decode a big-endian unsigned of `len` (1-4) octets
+fn read_unsigned(data: &[u8], pos: usize, len: usize) -> Option<u32> {
+    if !(1..=4).contains(&len) {
+        return None;
+    }
+    let bytes = data.get(pos..pos + len)?;
+    Some(bytes.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32))
+}
+
+/// Read a context-tagged unsigned with the given tag number at `pos`
+pub fn read_context_unsigned(data: &[u8], pos: &mut usize, number: u8) -> Option<u32> {
+    let tag = read_tag(data, *pos)?;
+    if !tag.is_context(number) {
+        return None;
+    }
+    let value = read_unsigned(data, *pos + tag.header_len, tag.lvt as usize)?;
+    *pos += tag.header_len + tag.lvt as usize;
+    Some(value)
+}
+
+/// Encode a context-tagged unsigned (tag numbers 0-14)
+pub fn encode_context_unsigned(number: u8, value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|&&b| b == 0).count();
+    let mut v = vec![(number << 4) | 0x08 | (4 - skip) as u8];
+    v.extend_from_slice(&bytes[skip..]);
+    v
+}
+
+/// Parse the list of ReadAccessSpecifications from RPM service data
+fn parse_rpm_request(data: &[u8]) -> Option<Vec<PropertyRef>> {
+    let mut refs = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        // [0] objectIdentifier
+        let tag = read_tag(data, pos)?;
+        if !tag.is_context(0) || tag.lvt != 4 {
+            return None;
+        }
+        let object_id = read_context_unsigned(data, &mut pos, 0)?;
+
+        // [1] listOfPropertyReferences
+        let tag = read_tag(data, pos)?;
+        if !tag.is_opening(1) {
+            return None;
+        }
+        pos += tag.header_len;
+        loop {
+            let tag = read_tag(data, pos)?;
+            if tag.is_closing(1) {
+                pos += tag.header_len;
+                break;
+            }
+            let property_id = read_context_unsigned(data, &mut pos, 0)?;
+            if matches!(property_id, PROP_ALL | PROP_REQUIRED | PROP_OPTIONAL) {
+                return None;
+            }
+            let array_index = match read_tag(data, pos) {
+                Some(tag) if tag.is_context(1) => Some(read_context_unsigned(data, &mut pos, 1)?),
+                _ => None,
+            };
+            refs.push(PropertyRef { object_id, property_id, array_index });
+            if refs.len() > MAX_PROXIED_PROPERTIES {
+                return None;
+            }
+        }
+    }
+    (!refs.is_empty()).then_some(refs)
+}
+
+/// ReadProperty-Request APDU for one property reference
+pub fn encode_read_property(invoke_id: u8, r: &PropertyRef) -> Vec<u8> {
+    let mut apdu = vec![0x00, PROXY_MAX_APDU_CODE, invoke_id, SERVICE_READ_PROPERTY, 0x0C];
+    apdu.extend_from_slice(&r.object_id.to_be_bytes());
+    apdu.extend_from_slice(&encode_context_unsigned(1, r.property_id));
+    if let Some(index) = r.array_index {
+        apdu.extend_from_slice(&encode_context_unsigned(2, index));
+    }
+    apdu
+}
+
+/// Extract the value or error from a ReadProperty-ACK or Error PDU
+pub fn parse_read_property_result(apdu: &[u8]) -> Option<ReadResult> {
+    if apdu.len() < 3 || apdu[2] != SERVICE_READ_PROPERTY {
+        return None;
+    }
+    match apdu[0] {
+        // Unsegmented ComplexAck: [0] object, [1] property, [2] index (optional), [3] value
+        0x30 => {
+            let mut pos = 3;
+            loop {
+                let tag = read_tag(apdu, pos)?;
+                if tag.is_opening(3) {
+                    pos += tag.header_len;
+                    break;
+                }
+                if !tag.context || tag.raw_lvt > 5 {
+                    return None;
+                }
+                pos += tag.header_len + tag.lvt as usize;
+            }
+            let (&last, _) = apdu.split_last()?;
+            if last != 0x3F || pos >= apdu.len() {
+                return None;
+            }
+            Some(ReadResult::Value(apdu[pos..apdu.len() - 1].to_vec()))
+        }
+        // Error: [invoke ID, service, error class, error code]
+        0x50 => Some(ReadResult::Error(apdu[3..].to_vec())),
+        _ => None,
+    }
+}
+
+/// ReadPropertyMultiple-ACK APDU from the collected results
+fn encode_rpm_ack(invoke_id: u8, refs: &[PropertyRef], results: &[ReadResult]) -> Vec<u8> {
+    let mut apdu = vec![0x30, invoke_id, SERVICE_READ_PROPERTY_MULTIPLE];
+    let mut current_object = None;
+    for (r, result) in refs.iter().zip(results) {
+        if current_object != Some(r.object_id) {
+            if current_object.is_some() {
+                apdu.push(0x1F);
+            }
+            apdu.push(0x0C);
+            apdu.extend_from_slice(&r.object_id.to_be_bytes());
+            apdu.push(0x1E);
+            current_object = Some(r.object_id);
+        }
+        apdu.extend_from_slice(&encode_context_unsigned(2, r.property_id));
+        if let Some(index) = r.array_index {
+            apdu.extend_from_slice(&encode_context_unsigned(3, index));
+        }
+        match result {
+            ReadResult::Value(value) => {
+                apdu.push(0x4E);
+                apdu.extend_from_slice(value);
+                apdu.push(0x4F);
+            }
+            ReadResult::Error(error) => {
+                apdu.push(0x5E);
+                apdu.extend_from_slice(error);
+                apdu.push(0x5F);
+            }
+        }
+    }
+    if current_object.is_some() {
+        apdu.push(0x1F);
+    }
+    apdu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> SocketAddr {
+        "192.168.1.50:47808".parse().unwrap()
+    }
+
+    /// RPM for AI-1 Present_Value + Status_Flags and AV-2 Priority_Array[3]
+    fn rpm_request() -> Vec<u8> {
+        vec![
+            0x00, 0x05, 0x21, SERVICE_READ_PROPERTY_MULTIPLE,
+            0x0C, 0x00, 0x00, 0x00, 0x01, 0x1E, 0x09, 0x55, 0x09, 0x6F, 0x1F,
+            0x0C, 0x00, 0x80, 0x00, 0x02, 0x1E, 0x09, 0x57, 0x19, 0x03, 0x1F,
+        ]
+    }
+
+    #[test]
+    fn test_rpm_translated_to_read_property_sequence() {
+        let now = Instant::now();
+        let mut proxy = RpmProxy::new(&rpm_request(), client(), 5, 100, now).unwrap();
+        assert_eq!(proxy.client_invoke_id, 0x21);
+        assert_eq!(proxy.client_max_apdu, 1476);
+        assert_eq!(proxy.progress(), (0, 3));
+        assert_eq!(proxy.request(), vec![0x00, 0x03, 100, 12, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x55]);
+
+        // Present_Value = 72.5
+        let ack = [0x30, 100, 12, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x55, 0x3E, 0x44, 0x42, 0x91, 0x00, 0x00, 0x3F];
+        assert!(proxy.matches(5, &ack));
+        assert!(!proxy.matches(6, &ack));
+        let ProxyStep::Next(next) = proxy.record(&ack, 101, now) else { panic!("expected next read") };
+        assert_eq!(next, vec![0x00, 0x03, 101, 12, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x6F]);
+
+        // Status_Flags
+        let ack = [0x30, 101, 12, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x6F, 0x3E, 0x82, 0x04, 0x00, 0x3F];
+        let ProxyStep::Next(next) = proxy.record(&ack, 102, now) else { panic!("expected next read") };
+        assert_eq!(next, vec![0x00, 0x03, 102, 12, 0x0C, 0x00, 0x80, 0x00, 0x02, 0x19, 0x57, 0x29, 0x03]);
+
+        // Priority_Array[3] fails with property / invalid-array-index
+        let error = [0x50, 102, 12, 0x91, 0x02, 0x91, 0x2A];
+        let ProxyStep::Complete(rpm_ack) = proxy.record(&error, 103, now) else { panic!("expected ack") };
+        assert_eq!(
+            rpm_ack,
+            vec![
+                0x30, 0x21, SERVICE_READ_PROPERTY_MULTIPLE,
+                0x0C, 0x00, 0x00, 0x00, 0x01, 0x1E,
+                0x29, 0x55, 0x4E, 0x44, 0x42, 0x91, 0x00, 0x00, 0x4F,
+                0x29, 0x6F, 0x4E, 0x82, 0x04, 0x00, 0x4F,
+                0x1F,
+                0x0C, 0x00, 0x80, 0x00, 0x02, 0x1E,
+                0x29, 0x57, 0x39, 0x03, 0x5E, 0x91, 0x02, 0x91, 0x2A, 0x5F,
+                0x1F,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_untranslatable_requests_are_refused() {
+        let now = Instant::now();
+        // Property ALL can only be answered by the device itself
+        let all = [0x00, 0x05, 0x01, SERVICE_READ_PROPERTY_MULTIPLE, 0x0C, 0x02, 0x00, 0x00, 0x05, 0x1E, 0x09, 0x08, 0x1F];
+        assert!(RpmProxy::new(&all, client(), 5, 1, now).is_none());
+        // Truncated specification
+        let truncated = [0x00, 0x05, 0x01, SERVICE_READ_PROPERTY_MULTIPLE, 0x0C, 0x02, 0x00, 0x00, 0x05, 0x1E, 0x09, 0x55];
+        assert!(RpmProxy::new(&truncated, client(), 5, 1, now).is_none());
+        // Not an RPM
+        let rp = [0x00, 0x05, 0x01, SERVICE_READ_PROPERTY, 0x0C, 0x02, 0x00, 0x00, 0x05, 0x19, 0x55];
+        assert!(RpmProxy::new(&rp, client(), 5, 1, now).is_none());
+    }
+
+    #[test]
+    fn test_reject_or_oversized_ack_fails_proxy() {
+        let now = Instant::now();
+        let mut proxy = RpmProxy::new(&rpm_request(), client(), 5, 100, now).unwrap();
+        assert_eq!(proxy.record(&[0x60, 100, 0x04], 101, now), ProxyStep::Failed);
+
+        // Client accepts only 50 octets: a long string value can't be returned
+        let request = [0x00, 0x00, 0x01, SERVICE_READ_PROPERTY_MULTIPLE, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x1E, 0x09, 0x55, 0x1F];
+        let mut proxy = RpmProxy::new(&request, client(), 5, 100, now).unwrap();
+        let mut ack = vec![0x30, 100, 12, 0x0C, 0x00, 0x00, 0x00, 0x01, 0x19, 0x55, 0x3E, 0x75, 60, 0x00];
+        ack.extend_from_slice(&[b'x'; 59]);
+        ack.push(0x3F);
+        assert_eq!(proxy.record(&ack, 101, now), ProxyStep::TooLong);
+    }
+}
@@ -0,0 +1,279 @@
+//! Who-Is scan profiles
+//!
+//! A single global Who-Is makes every device answer at once, which on a
+//! slow trunk collides with normal traffic and loses I-Am replies. The
+//! profiles here spread the discovery out: Who-Is sweeps over windows of
+//! device instances, or a directed Who-Is to each MS/TP MAC in turn, paced
+//! from the configured baud rate so replies have room on the wire.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::local_device::LocalDevice;
+use crate::npdu::{NetworkAddress, Npdu, GLOBAL_BROADCAST_NETWORK};
+
+/// Highest device instance a ranged sweep can cover
+pub const MAX_SCAN_INSTANCE: u32 = 4_194_302;
+
+/// Most Who-Is requests a single sweep may send
+pub const MAX_SCAN_STEPS: u32 = 512;
+
+/// Bits on the wire for one I-Am reply (MS/TP header, NPDU, APDU, CRC)
+const I_AM_FRAME_BITS: u64 = 300;
+
+/// Reply slots left between directed Who-Is requests (one answer expected)
+const DIRECTED_REPLY_SLOTS: u64 = 4;
+
+/// Reply slots left between ranged Who-Is requests (a window may hold many devices)
+const RANGED_REPLY_SLOTS: u64 = 16;
+
+/// Shortest spacing between two Who-Is requests of a sweep
+const MIN_PACING_MS: u64 = 20;
+
+/// How long a scan stays open for I-Am replies after its last Who-Is
+pub const SCAN_REPLY_WINDOW: Duration = Duration::from_secs(5);
+
+/// How to discover devices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanProfile {
+    /// One local and one global Who-Is broadcast
+    #[default]
+    Quick,
+    /// Who-Is broadcasts for consecutive instance windows of `window` devices
+    Ranged { low: u32, high: u32, window: u32 },
+    /// A Who-Is sent to each MS/TP MAC 0-127 in turn
+    Directed,
+}
+
+impl fmt::Display for ScanProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanProfile::Quick => write!(f, "quick"),
+            ScanProfile::Ranged { low, high, window } => {
+                write!(f, "ranged {}-{} by {}", low, high, window)
+            }
+            ScanProfile::Directed => write!(f, "directed"),
+        }
+    }
+}
+
+impl ScanProfile {
+    /// Build a ranged profile, rejecting empty ranges and sweeps too long to run
+    pub fn ranged(low: u32, high: u32, window: u32) -> Result<Self, String> {
+        if low > high || high > MAX_SCAN_INSTANCE {
+            return Err(format!("Instance range must be 0-{} with low <= high", MAX_SCAN_INSTANCE));
+        }
+        if window == 0 {
+            return Err("Window size must be at least 1".to_string());
+        }
+        let steps = (high - low) / window + 1;
+        if steps > MAX_SCAN_STEPS {
+            return Err(format!(
+                "Range needs {} requests; widen the window to stay within {}",
+                steps, MAX_SCAN_STEPS
+            ));
+        }
+        Ok(ScanProfile::Ranged { low, high, window })
+    }
+
+    /// Number of Who-Is requests the sweep sends
+    pub fn step_count(&self) -> u32 {
+        match *self {
+            ScanProfile::Quick => 1,
+            ScanProfile::Ranged { low, high, window } => (high - low) / window + 1,
+            ScanProfile::Directed => 128,
+        }
+    }
+
+    /// Spacing between requests at the given baud rate
+    pub fn pacing(&self, baud_rate: u32) -> Duration {
+        let slots = match self {
+            ScanProfile::Quick => return Duration::ZERO,
+            ScanProfile::Ranged { .. } => RANGED_REPLY_SLOTS,
+            ScanProfile::Directed => DIRECTED_REPLY_SLOTS,
+        };
+        let reply_ms = I_AM_FRAME_BITS * 1000 / u64::from(baud_rate.max(1));
+        Duration::from_millis((reply_ms * slots).max(MIN_PACING_MS))
+    }
+
+    /// Rough time for the whole sweep to be sent
+    pub fn duration(&self, baud_rate: u32) -> Duration {
+        self.pacing(baud_rate) * self.step_count().saturating_sub(1)
+    }
+}
+
+/// One Who-Is to queue on the MS/TP driver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanFrame {
+    pub npdu: Vec<u8>,
+    /// MS/TP destination (255 = broadcast)
+    pub dest: u8,
+}
+
+/// A sweep in progress, handing out requests as they fall due
+#[derive(Debug)]
+pub struct ScanPlan {
+    profile: ScanProfile,
+    pacing: Duration,
+    next_step: u32,
+    next_due: Instant,
+    mstp_network: u16,
+    station_address: u8,
+}
+
+impl ScanPlan {
+    pub fn new(profile: ScanProfile, baud_rate: u32, mstp_network: u16, station_address: u8, now: Instant) -> Self {
+        Self {
+            profile,
+            pacing: profile.pacing(baud_rate),
+            next_step: 0,
+            next_due: now,
+            mstp_network,
+            station_address,
+        }
+    }
+
+    pub fn profile(&self) -> ScanProfile {
+        self.profile
+    }
+
+    /// Requests sent so far and in total
+    pub fn progress(&self) -> (u32, u32) {
+        (self.next_step, self.profile.step_count())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next_step >= self.profile.step_count()
+    }
+
+    /// Frames for the next step if it is due, advancing the sweep
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<ScanFrame>> {
+        if self.is_done() || now < self.next_due {
+            return None;
+        }
+        let step = self.next_step;
+        self.next_step += 1;
+        self.next_due = now + self.pacing;
+
+        let frames = match self.profile {
+            ScanProfile::Quick => {
+                let apdu = LocalDevice::build_who_is();
+                vec![
+                    ScanFrame { npdu: local_npdu(&apdu), dest: 0xFF },
+                    ScanFrame { npdu: self.global_npdu(&apdu), dest: 0xFF },
+                ]
+            }
+            ScanProfile::Ranged { low, high, window } => {
+                let start = low + step * window;
+                let end = start.saturating_add(window - 1).min(high);
+                let apdu = LocalDevice::build_who_is_range(start, end);
+                vec![ScanFrame { npdu: local_npdu(&apdu), dest: 0xFF }]
+            }
+            ScanProfile::Directed => {
+                let mac = step as u8;
+                if mac == self.station_address {
+                    // Nothing to ask ourselves; let the next MAC go out on time
+                    self.next_due = now;
+                    return Some(Vec::new());
+                }
+                let apdu = LocalDevice::build_who_is();
+                vec![ScanFrame { npdu: local_npdu(&apdu), dest: mac }]
+            }
+        };
+        Some(frames)
+    }
+
+    /// Global broadcast (DNET=0xFFFF) for devices behind other routers.
+    /// Per Clause 6.2.2, SNET/SADR are included so replies find their way back.
+    fn global_npdu(&self, apdu: &[u8]) -> Vec<u8> {
+        Npdu {
+            destination: Some(NetworkAddress::broadcast(GLOBAL_BROADCAST_NETWORK)),
+            source: Some(NetworkAddress::new(self.mstp_network, &[self.station_address])),
+            ..Npdu::local(apdu)
+        }
+        .encode()
+    }
+}
+
+/// Local NPDU (no network layer information) for the MS/TP segment
+fn local_npdu(apdu: &[u8]) -> Vec<u8> {
+    Npdu::local(apdu).encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quick_scan_sends_local_and_global_once() {
+        let now = Instant::now();
+        let mut plan = ScanPlan::new(ScanProfile::Quick, 38400, 5, 3, now);
+        let frames = plan.poll(now).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].npdu, vec![0x01, 0x00, 0x10, 0x08]);
+        assert_eq!(
+            frames[1].npdu,
+            vec![0x01, 0x28, 0xFF, 0xFF, 0x00, 0x00, 0x05, 0x01, 0x03, 0xFF, 0x10, 0x08]
+        );
+        assert!(plan.is_done());
+        assert!(plan.poll(now).is_none());
+    }
+
+    #[test]
+    fn test_ranged_scan_covers_windows() {
+        let profile = ScanProfile::ranged(100, 349, 100).unwrap();
+        assert_eq!(profile.step_count(), 3);
+
+        let now = Instant::now();
+        let mut plan = ScanPlan::new(profile, 9600, 1, 0, now);
+        let pacing = profile.pacing(9600);
+        let mut requests = Vec::new();
+        let mut t = now;
+        while let Some(frames) = plan.poll(t) {
+            requests.push(frames[0].npdu.clone());
+            assert!(plan.poll(t).is_none(), "next window must wait for the pacing interval");
+            t += pacing;
+        }
+        assert_eq!(requests.len(), 3);
+        // Who-Is 100-199, 200-299, 300-349
+        assert_eq!(requests[0][4..], [0x09, 100, 0x19, 199]);
+        assert_eq!(requests[1][4..], [0x09, 200, 0x1A, 0x01, 0x2B]);
+        assert_eq!(requests[2][4..], [0x0A, 0x01, 0x2C, 0x1A, 0x01, 0x5D]);
+    }
+
+    #[test]
+    fn test_ranged_scan_rejects_bad_ranges() {
+        assert!(ScanProfile::ranged(10, 5, 1).is_err());
+        assert!(ScanProfile::ranged(0, 10, 0).is_err());
+        assert!(ScanProfile::ranged(0, MAX_SCAN_INSTANCE + 1, 100_000).is_err());
+        assert!(ScanProfile::ranged(0, MAX_SCAN_INSTANCE, 1000).is_err());
+        assert!(ScanProfile::ranged(0, MAX_SCAN_INSTANCE, 10_000).is_ok());
+    }
+
+    #[test]
+    fn test_directed_scan_skips_own_mac() {
+        let now = Instant::now();
+        let mut plan = ScanPlan::new(ScanProfile::Directed, 115200, 1, 2, now);
+        let mut dests = Vec::new();
+        let mut t = now;
+        while !plan.is_done() {
+            if let Some(frames) = plan.poll(t) {
+                dests.extend(frames.iter().map(|f| f.dest));
+            }
+            t += Duration::from_millis(MIN_PACING_MS);
+        }
+        assert_eq!(dests.len(), 127);
+        assert!(!dests.contains(&2));
+        assert_eq!(plan.progress(), (128, 128));
+    }
+
+    #[test]
+    fn test_pacing_follows_baud_rate() {
+        let slow = ScanProfile::Directed.pacing(9600);
+        let fast = ScanProfile::Directed.pacing(76800);
+        assert_eq!(slow, Duration::from_millis(31 * DIRECTED_REPLY_SLOTS));
+        assert!(fast < slow);
+        assert_eq!(ScanProfile::Directed.pacing(115200), Duration::from_millis(MIN_PACING_MS));
+        assert!(ScanProfile::ranged(0, 99, 10).unwrap().pacing(9600) > slow);
+    }
+}
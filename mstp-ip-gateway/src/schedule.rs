@@ -0,0 +1,335 @@
+//! One-time configuration change scheduled for a quiet hour
+//!
+//! A change is a set of config page form fields (e.g. `mstp_baud=76800`)
+//! applied at a UTC time, typically overnight when nobody is on site. When it
+//! falls due the main loop keeps the previous values of just those fields,
+//! saves the changed configuration to NVS and restarts. After the restart the
+//! MS/TP ring has the rollback window to come up and stay up for
+//! `RING_STABLE_HOLD`; if it does not, the previous values are put back and
+//! the gateway restarts again. The schedule is kept in NVS across both
+//! restarts, and the wall clock comes from SNTP, so nothing falls due until
+//! the clock has been set.
+
+use std::time::{Duration, Instant};
+
+/// Longest stored schedule (the change and the previous values, form-encoded)
+pub const MAX_SCHEDULE_LEN: usize = 2048;
+
+/// Rollback window used when the form leaves it out
+pub const DEFAULT_ROLLBACK_WINDOW_MINUTES: u32 = 10;
+
+/// Longest rollback window that can be asked for
+pub const MAX_ROLLBACK_WINDOW_MINUTES: u32 = 120;
+
+/// How long the ring must stay up before a change is kept
+pub const RING_STABLE_HOLD: Duration = Duration::from_secs(60);
+
+/// Form fields that describe the schedule itself rather than the change
+pub const SCHEDULE_FIELDS: [&str; 2] = ["at", "window"];
+
+/// Where a scheduled change is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleStage {
+    /// Waiting for its time
+    Pending,
+    /// Saved and restarted; the ring is being watched
+    Applied,
+}
+
+impl ScheduleStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleStage::Pending => "pending",
+            ScheduleStage::Applied => "applied",
+        }
+    }
+}
+
+/// A configuration change waiting for its time, or for the ring to settle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledChange {
+    /// UTC seconds since the epoch
+    pub apply_at: u64,
+    pub window_minutes: u32,
+    /// Fields to apply, in the config page's form encoding
+    pub change: String,
+    /// Values those fields had before; empty until applied
+    pub previous: String,
+    pub stage: ScheduleStage,
+}
+
+impl ScheduledChange {
+    pub fn new(apply_at: u64, window_minutes: u32, change: String) -> Self {
+        Self {
+            apply_at,
+            window_minutes,
+            change,
+            previous: String::new(),
+            stage: ScheduleStage::Pending,
+        }
+    }
+
+    /// Build a schedule from a form holding `at`, `window` and the fields to
+    /// change. `at` is a UTC time as "YYYY-MM-DDTHH:MM" (what a datetime-local
+    /// input sends) or seconds since the epoch.
+    pub fn from_form(body: &str, now_utc: u64) -> Result<Self, &'static str> {
+        let mut apply_at = None;
+        let mut window_minutes = DEFAULT_ROLLBACK_WINDOW_MINUTES;
+        let mut change = Vec::new();
+        for pair in body.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "at" => {
+                    let value = urlencoding::decode(value).unwrap_or_default();
+                    apply_at = Some(parse_time(&value).ok_or("time must be YYYY-MM-DDTHH:MM (UTC)")?);
+                }
+                "window" => {
+                    window_minutes = match value.parse() {
+                        Ok(m) if (1..=MAX_ROLLBACK_WINDOW_MINUTES).contains(&m) => m,
+                        _ => return Err("rollback window must be 1-120 minutes"),
+                    };
+                }
+                _ => change.push(pair),
+            }
+        }
+        let apply_at = apply_at.ok_or("no time given")?;
+        if apply_at <= now_utc {
+            return Err("time is in the past");
+        }
+        if change.is_empty() {
+            return Err("no settings to change");
+        }
+        let schedule = Self::new(apply_at, window_minutes, change.join("&"));
+        if schedule.encode().len() > MAX_SCHEDULE_LEN {
+            return Err("change is too long");
+        }
+        Ok(schedule)
+    }
+
+    /// Pending and its time has come
+    pub fn is_due(&self, now_utc: u64) -> bool {
+        self.stage == ScheduleStage::Pending && now_utc >= self.apply_at
+    }
+
+    /// Names of the fields the change sets
+    pub fn fields(&self) -> Vec<&str> {
+        form_keys(&self.change)
+    }
+
+    /// The change as "key=value" pairs for display, passwords masked
+    pub fn summary(&self) -> String {
+        self.change
+            .split('&')
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                if key.ends_with("_pass") {
+                    format!("{}=****", key)
+                } else {
+                    format!("{}={}", key, urlencoding::decode(value).unwrap_or_default())
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn rollback_window(&self) -> Duration {
+        Duration::from_secs(self.window_minutes as u64 * 60)
+    }
+
+    /// Remember the current values of the changed fields (taken from the full
+    /// configuration form) and move on to watching the ring
+    pub fn mark_applied(&mut self, current_form: &str) {
+        let fields = self.fields();
+        self.previous = current_form
+            .split('&')
+            .filter(|pair| fields.contains(&pair.split('=').next().unwrap_or("")))
+            .collect::<Vec<_>>()
+            .join("&");
+        self.stage = ScheduleStage::Applied;
+    }
+
+    /// Stored form: the schedule's own fields, then the change and the
+    /// previous values, each form-encoded again as a single value
+    pub fn encode(&self) -> Vec<u8> {
+        format!(
+            "at={}&window={}&stage={}&change={}&previous={}",
+            self.apply_at,
+            self.window_minutes,
+            self.stage.as_str(),
+            urlencoding::encode(&self.change),
+            urlencoding::encode(&self.previous)
+        )
+        .into_bytes()
+    }
+
+    /// Parse a stored schedule; anything unreadable is treated as none
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(data).ok()?;
+        let mut schedule = Self::new(0, DEFAULT_ROLLBACK_WINDOW_MINUTES, String::new());
+        for pair in text.split('&') {
+            let (key, value) = pair.split_once('=')?;
+            match key {
+                "at" => schedule.apply_at = value.parse().ok()?,
+                "window" => schedule.window_minutes = value.parse().ok()?,
+                "stage" => {
+                    schedule.stage = match value {
+                        "pending" => ScheduleStage::Pending,
+                        "applied" => ScheduleStage::Applied,
+                        _ => return None,
+                    }
+                }
+                "change" => schedule.change = urlencoding::decode(value).ok()?.into_owned(),
+                "previous" => schedule.previous = urlencoding::decode(value).ok()?.into_owned(),
+                _ => {}
+            }
+        }
+        (schedule.apply_at != 0 && !schedule.change.is_empty()).then_some(schedule)
+    }
+}
+
+/// Keys of a form, in order
+fn form_keys(form: &str) -> Vec<&str> {
+    form.split('&').filter(|p| !p.is_empty()).map(|p| p.split('=').next().unwrap_or("")).collect()
+}
+
+/// What the ring has shown since an applied change restarted the gateway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RingVerdict {
+    Waiting,
+    /// Up for `RING_STABLE_HOLD` without a break: keep the change
+    Stable,
+    /// Window over without that: roll back
+    Failed,
+}
+
+/// Watches the MS/TP ring after an applied change
+#[derive(Debug, Clone)]
+pub struct RingWatch {
+    deadline: Instant,
+    up_since: Option<Instant>,
+}
+
+impl RingWatch {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self { deadline: now + window, up_since: None }
+    }
+
+    pub fn update(&mut self, ring_up: bool, now: Instant) -> RingVerdict {
+        if !ring_up {
+            self.up_since = None;
+        } else if now.duration_since(*self.up_since.get_or_insert(now)) >= RING_STABLE_HOLD {
+            return RingVerdict::Stable;
+        }
+        if now >= self.deadline {
+            RingVerdict::Failed
+        } else {
+            RingVerdict::Waiting
+        }
+    }
+
+    /// Time left before the change is rolled back
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.deadline.saturating_duration_since(now)
+    }
+}
+
+/// Countdown for the LCD and the portal, e.g. "2h05m" or "4m10s"
+pub fn format_countdown(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs / 60 % 60)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+/// Seconds since the epoch of a UTC "YYYY-MM-DDTHH:MM[:SS]" (a space may
+/// replace the 'T'), or of a plain number of seconds
+pub fn parse_time(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if let Ok(secs) = text.parse::<u64>() {
+        return Some(secs);
+    }
+    let (date, time) = text.split_once(['T', ' '])?;
+    let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<u32>().ok());
+    let (year, month, day) = (date_parts.next()??, date_parts.next()??, date_parts.next()??);
+    let mut time_parts = time.splitn(3, ':').map(|p| p.parse::<u32>().ok());
+    let (hour, minute) = (time_parts.next()??, time_parts.next()??);
+    let second = time_parts.next().flatten().unwrap_or(0);
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month, day);
+    Some(days as u64 * 86_400 + hour as u64 * 3600 + minute as u64 * 60 + second as u64)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule_from_form_and_round_trip() {
+        let now = parse_time("2026-10-18T09:30").unwrap();
+        let mut schedule = ScheduledChange::from_form("at=2026-10-19T02%3A00&window=15&mstp_baud=76800&mstp_addr=3", now).unwrap();
+        assert_eq!(schedule.apply_at, 1_792_375_200);
+        assert_eq!(schedule.window_minutes, 15);
+        assert_eq!(schedule.fields(), vec!["mstp_baud", "mstp_addr"]);
+        assert_eq!(schedule.summary(), "mstp_baud=76800, mstp_addr=3");
+        assert!(!schedule.is_due(now));
+        assert!(schedule.is_due(schedule.apply_at));
+
+        schedule.mark_applied("mstp_addr=1&mstp_max=127&mstp_baud=38400&site_name=Plant%20Room");
+        assert_eq!(schedule.previous, "mstp_addr=1&mstp_baud=38400");
+        assert_eq!(schedule.stage, ScheduleStage::Applied);
+        assert!(!schedule.is_due(schedule.apply_at));
+        assert_eq!(ScheduledChange::decode(&schedule.encode()), Some(schedule));
+
+        assert_eq!(ScheduledChange::from_form("at=2026-10-18T09:00&mstp_baud=76800", now), Err("time is in the past"));
+        assert_eq!(ScheduledChange::from_form("at=2026-10-19T02:00", now), Err("no settings to change"));
+        assert_eq!(ScheduledChange::from_form("at=2026-10-19T02:00&window=0&mstp_baud=76800", now), Err("rollback window must be 1-120 minutes"));
+        assert_eq!(ScheduledChange::decode(b"garbage"), None);
+        assert_eq!(ScheduledChange::new(1, 10, "wifi_pass=secret&site_name=A%20B".into()).summary(), "wifi_pass=****, site_name=A B");
+    }
+
+    #[test]
+    fn test_ring_watch_needs_an_unbroken_hold() {
+        let start = Instant::now();
+        let mut watch = RingWatch::new(Duration::from_secs(300), start);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(watch.update(false, at(10)), RingVerdict::Waiting);
+        assert_eq!(watch.update(true, at(20)), RingVerdict::Waiting);
+        // A drop restarts the hold
+        assert_eq!(watch.update(false, at(70)), RingVerdict::Waiting);
+        assert_eq!(watch.update(true, at(90)), RingVerdict::Waiting);
+        assert_eq!(watch.update(true, at(150)), RingVerdict::Stable);
+
+        let mut watch = RingWatch::new(Duration::from_secs(300), start);
+        assert_eq!(watch.update(true, at(280)), RingVerdict::Waiting);
+        assert_eq!(watch.update(true, at(300)), RingVerdict::Failed);
+        assert_eq!(watch.remaining(at(240)), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_countdown_and_time_formats() {
+        assert_eq!(format_countdown(2 * 3600 + 5 * 60 + 9), "2h05m");
+        assert_eq!(format_countdown(250), "4m10s");
+        assert_eq!(parse_time("1970-01-01 00:00"), Some(0));
+        assert_eq!(parse_time("2000-03-01T12:34:56"), Some(951_914_096));
+        assert_eq!(parse_time("2026-13-01T00:00"), None);
+        assert_eq!(parse_time("1792375200"), Some(1_792_375_200));
+    }
+}
@@ -0,0 +1,263 @@
+//! Per-service translation statistics
+//!
+//! Counts the confirmed requests routed from B/IP onto MS/TP by service and
+//! how the devices answered them, with a histogram of the Error, Reject and
+//! Abort reasons per service. A head-end polling a property the controller
+//! does not have shows up here as a climbing
+//! "read-property - property: unknown-property" line, at the router rather
+//! than buried in the head-end's own logs.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+
+use crate::client::{decode_error_pdu, error_name};
+
+/// Distinct (service, reason) pairs tracked; later new ones are only counted
+pub const MAX_ERROR_REASONS: usize = 32;
+
+/// Confirmed service names (Clause 21, BACnetConfirmedServiceChoice)
+const SERVICE_NAMES: [(u8, &str); 20] = [
+    (0, "acknowledge-alarm"),
+    (2, "confirmed-event-notification"),
+    (3, "get-alarm-summary"),
+    (4, "get-enrollment-summary"),
+    (5, "subscribe-cov"),
+    (6, "atomic-read-file"),
+    (7, "atomic-write-file"),
+    (8, "add-list-element"),
+    (9, "remove-list-element"),
+    (10, "create-object"),
+    (11, "delete-object"),
+    (12, "read-property"),
+    (14, "read-property-multiple"),
+    (15, "write-property"),
+    (16, "write-property-multiple"),
+    (17, "device-communication-control"),
+    (20, "reinitialize-device"),
+    (26, "read-range"),
+    (28, "subscribe-cov-property"),
+    (29, "get-event-information"),
+];
+
+/// Reject reasons (Clause 21, BACnetRejectReason)
+const REJECT_REASON_NAMES: [&str; 10] = [
+    "other",
+    "buffer-overflow",
+    "inconsistent-parameters",
+    "invalid-parameter-data-type",
+    "invalid-tag",
+    "missing-required-parameter",
+    "parameter-out-of-range",
+    "too-many-arguments",
+    "undefined-enumeration",
+    "unrecognized-service",
+];
+
+/// Abort reasons (Clause 21, BACnetAbortReason)
+const ABORT_REASON_NAMES: [&str; 12] = [
+    "other",
+    "buffer-overflow",
+    "invalid-apdu-in-this-state",
+    "preempted-by-higher-priority-task",
+    "segmentation-not-supported",
+    "security-error",
+    "insufficient-security",
+    "window-size-out-of-range",
+    "application-exceeded-reply-time",
+    "out-of-resources",
+    "tsm-timeout",
+    "apdu-too-long",
+];
+
+/// Service name by number, or the number itself
+pub fn service_name(service: u8) -> String {
+    SERVICE_NAMES
+        .iter()
+        .find(|(s, _)| *s == service)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("service {}", service))
+}
+
+/// Why a device refused a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorReason {
+    /// Error PDU (class, code)
+    Error(u32, u32),
+    Reject(u8),
+    Abort(u8),
+}
+
+impl ErrorReason {
+    /// Reason carried by an Error, Reject or Abort APDU; None for anything else
+    pub fn from_apdu(apdu: &[u8]) -> Option<Self> {
+        match apdu.first()? >> 4 {
+            5 => Some(match decode_error_pdu(apdu) {
+                Some((class, code)) => ErrorReason::Error(class, code),
+                None => ErrorReason::Error(0, 0),
+            }),
+            6 => Some(ErrorReason::Reject(*apdu.get(2)?)),
+            7 => Some(ErrorReason::Abort(*apdu.get(2)?)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |names: &[&str], reason: u8| names.get(reason as usize).map(|n| n.to_string()).unwrap_or_else(|| reason.to_string());
+        match self {
+            ErrorReason::Error(class, code) => write!(f, "error {}", error_name(*class, *code)),
+            ErrorReason::Reject(reason) => write!(f, "reject {}", name(&REJECT_REASON_NAMES, *reason)),
+            ErrorReason::Abort(reason) => write!(f, "abort {}", name(&ABORT_REASON_NAMES, *reason)),
+        }
+    }
+}
+
+/// How the requests of one service were answered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServiceCounters {
+    /// Confirmed requests routed onto MS/TP
+    pub requests: u64,
+    /// Simple-ACK or Complex-ACK
+    pub acks: u64,
+    pub errors: u64,
+    pub rejects: u64,
+    pub aborts: u64,
+    /// Ran out of retries without an answer
+    pub timeouts: u64,
+}
+
+/// One line of the error histogram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorCount {
+    pub service: u8,
+    pub reason: ErrorReason,
+    pub count: u64,
+    /// MS/TP station that answered this way most recently
+    pub last_mac: u8,
+    pub last_seen: Instant,
+}
+
+/// Counters per service and the error reason histogram
+#[derive(Debug, Default)]
+pub struct ServiceStats {
+    services: BTreeMap<u8, ServiceCounters>,
+    reasons: Vec<ErrorCount>,
+    /// Errors whose (service, reason) pair did not fit the histogram
+    untracked: u64,
+}
+
+impl ServiceStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A confirmed request for `service` was routed onto MS/TP
+    pub fn record_request(&mut self, service: u8) {
+        self.services.entry(service).or_default().requests += 1;
+    }
+
+    /// The final answer to a `service` request arrived from `mac`
+    pub fn record_answer(&mut self, service: u8, apdu: &[u8], mac: u8, now: Instant) {
+        let counters = self.services.entry(service).or_default();
+        let Some(reason) = ErrorReason::from_apdu(apdu) else {
+            counters.acks += 1;
+            return;
+        };
+        match reason {
+            ErrorReason::Error(..) => counters.errors += 1,
+            ErrorReason::Reject(_) => counters.rejects += 1,
+            ErrorReason::Abort(_) => counters.aborts += 1,
+        }
+        if let Some(entry) = self.reasons.iter_mut().find(|e| e.service == service && e.reason == reason) {
+            entry.count += 1;
+            entry.last_mac = mac;
+            entry.last_seen = now;
+        } else if self.reasons.len() < MAX_ERROR_REASONS {
+            self.reasons.push(ErrorCount { service, reason, count: 1, last_mac: mac, last_seen: now });
+        } else {
+            self.untracked += 1;
+        }
+    }
+
+    /// A `service` request ran out of retries
+    pub fn record_timeout(&mut self, service: u8) {
+        self.services.entry(service).or_default().timeouts += 1;
+    }
+
+    /// Counters by service number
+    pub fn counters(&self) -> Vec<(u8, ServiceCounters)> {
+        self.services.iter().map(|(&service, &counters)| (service, counters)).collect()
+    }
+
+    /// The `n` most frequent error reasons, most frequent first
+    pub fn top_errors(&self, n: usize) -> Vec<ErrorCount> {
+        let mut reasons = self.reasons.clone();
+        reasons.sort_by(|a, b| b.count.cmp(&a.count).then(b.last_seen.cmp(&a.last_seen)));
+        reasons.truncate(n);
+        reasons
+    }
+
+    pub fn untracked(&self) -> u64 {
+        self.untracked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reasons_decoded_and_named() {
+        // Error: property / unknown-property
+        let error = ErrorReason::from_apdu(&[0x50, 0x01, 0x0C, 0x91, 0x02, 0x91, 0x20]).unwrap();
+        assert_eq!(error, ErrorReason::Error(2, 32));
+        assert_eq!(error.to_string(), "error property: unknown-property");
+        assert_eq!(ErrorReason::from_apdu(&[0x60, 0x01, 0x09]).unwrap().to_string(), "reject unrecognized-service");
+        assert_eq!(ErrorReason::from_apdu(&[0x70, 0x01, 0x04]).unwrap().to_string(), "abort segmentation-not-supported");
+        assert_eq!(ErrorReason::from_apdu(&[0x70, 0x01, 0x40]).unwrap().to_string(), "abort 64");
+        assert_eq!(ErrorReason::from_apdu(&[0x20, 0x01, 0x0F]), None);
+        assert_eq!(service_name(12), "read-property");
+        assert_eq!(service_name(99), "service 99");
+    }
+
+    #[test]
+    fn test_histogram_counts_per_service_and_reason() {
+        let now = Instant::now();
+        let mut stats = ServiceStats::new();
+        let unknown_property = [0x50, 0x01, 0x0C, 0x91, 0x02, 0x91, 0x20];
+        for _ in 0..3 {
+            stats.record_request(12);
+            stats.record_answer(12, &unknown_property, 5, now);
+        }
+        stats.record_request(15);
+        stats.record_answer(15, &[0x20, 0x02, 0x0F], 5, now);
+        stats.record_request(15);
+        stats.record_answer(15, &[0x60, 0x03, 0x06], 7, now);
+        stats.record_request(14);
+        stats.record_timeout(14);
+
+        let counters: BTreeMap<u8, ServiceCounters> = stats.counters().into_iter().collect();
+        assert_eq!(counters[&12], ServiceCounters { requests: 3, errors: 3, ..Default::default() });
+        assert_eq!(counters[&15], ServiceCounters { requests: 2, acks: 1, rejects: 1, ..Default::default() });
+        assert_eq!(counters[&14].timeouts, 1);
+
+        let top = stats.top_errors(10);
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].service, top[0].reason, top[0].count), (12, ErrorReason::Error(2, 32), 3));
+        assert_eq!((top[1].reason, top[1].last_mac), (ErrorReason::Reject(6), 7));
+    }
+
+    #[test]
+    fn test_histogram_is_bounded() {
+        let now = Instant::now();
+        let mut stats = ServiceStats::new();
+        for reason in 0..MAX_ERROR_REASONS as u8 + 3 {
+            stats.record_answer(12, &[0x60, 0x01, reason], 1, now);
+        }
+        assert_eq!(stats.top_errors(usize::MAX).len(), MAX_ERROR_REASONS);
+        assert_eq!(stats.untracked(), 3);
+        assert_eq!(stats.counters()[0].1.rejects, MAX_ERROR_REASONS as u64 + 3);
+    }
+}
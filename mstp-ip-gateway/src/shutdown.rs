@@ -0,0 +1,136 @@
+//! Graceful shutdown on brown-out or a power button long-press
+//!
+//! The brown-out interrupt and the button only raise a request here. The
+//! main loop picks it up, broadcasts Router-Busy-To-Network on both ports
+//! and puts a shutdown message on the LCD before powering down or
+//! restarting. Only a button shutdown writes the network tables back to
+//! NVS: a flash write started on a failing supply can corrupt the partition.
+//!
+//! ESP-IDF's own brown-out handler resets the chip from inside the
+//! interrupt, leaving no time for the announcements, so it is disabled in
+//! sdkconfig and the detector is armed here to interrupt first. Its
+//! hardware reset stays on, delayed by the longest reset wait, as the
+//! backstop if the supply keeps falling.
+
+use esp_idf_svc::sys::{esp_err_t, EspError};
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+/// How long the power button is held before the gateway powers down
+pub const POWER_BUTTON_HOLD: Duration = Duration::from_secs(3);
+
+/// Longest wait for the final announcements to leave the transmit queues
+pub const ANNOUNCE_DRAIN: Duration = Duration::from_millis(300);
+
+/// Why the gateway is shutting down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Supply voltage fell below the brown-out threshold
+    BrownOut,
+    /// Power button held for `POWER_BUTTON_HOLD`
+    PowerButton,
+}
+
+impl ShutdownReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShutdownReason::BrownOut => "brown-out",
+            ShutdownReason::PowerButton => "power button",
+        }
+    }
+}
+
+const NOT_REQUESTED: u8 = 0;
+const BROWN_OUT: u8 = 1;
+const POWER_BUTTON: u8 = 2;
+
+/// Pending shutdown request; the first reason raised wins
+static REQUESTED: AtomicU8 = AtomicU8::new(NOT_REQUESTED);
+
+/// Ask the main loop to shut down. Safe to call from an interrupt.
+pub fn request(reason: ShutdownReason) {
+    let code = match reason {
+        ShutdownReason::BrownOut => BROWN_OUT,
+        ShutdownReason::PowerButton => POWER_BUTTON,
+    };
+    let _ = REQUESTED.compare_exchange(NOT_REQUESTED, code, Ordering::SeqCst, Ordering::SeqCst);
+}
+
+/// The pending shutdown request, if any
+pub fn requested() -> Option<ShutdownReason> {
+    match REQUESTED.load(Ordering::SeqCst) {
+        BROWN_OUT => Some(ShutdownReason::BrownOut),
+        POWER_BUTTON => Some(ShutdownReason::PowerButton),
+        _ => None,
+    }
+}
+
+// ESP32 RTC controller registers (ESP32 TRM, Low-Power Management chapter)
+const RTC_CNTL_BASE: usize = 0x3FF4_8000;
+const RTC_CNTL_INT_ENA_REG: usize = RTC_CNTL_BASE + 0x3C;
+const RTC_CNTL_INT_CLR_REG: usize = RTC_CNTL_BASE + 0x48;
+const RTC_CNTL_BROWN_OUT_REG: usize = RTC_CNTL_BASE + 0xD4;
+
+/// Brown-out bit in the RTC interrupt enable/clear registers
+const BROWN_OUT_INT: u32 = 1 << 7;
+/// RTC_CNTL_BROWN_OUT_REG fields
+const BROWN_OUT_ENA: u32 = 1 << 30;
+const BROWN_OUT_THRES_SHIFT: u32 = 27;
+const BROWN_OUT_THRES_MASK: u32 = 0x7 << BROWN_OUT_THRES_SHIFT;
+const BROWN_OUT_RST_ENA: u32 = 1 << 26;
+const BROWN_OUT_RST_WAIT_SHIFT: u32 = 16;
+const BROWN_OUT_RST_WAIT_MASK: u32 = 0x3FF << BROWN_OUT_RST_WAIT_SHIFT;
+
+/// Hardware reset delay after the detector trips, in RTC slow clock cycles:
+/// the longest the field holds, about 7 ms at 150 kHz
+const BROWN_OUT_RST_WAIT: u32 = 0x3FF;
+
+/// Detector level 5 (about 2.70 V) trips early enough on a sagging supply
+/// to leave time for the flush before the 3.3 V rail collapses
+const BROWN_OUT_LEVEL: u32 = 5;
+
+extern "C" {
+    // esp_private/rtc_ctrl.h, not covered by the generated bindings
+    fn rtc_isr_register(
+        handler: unsafe extern "C" fn(*mut c_void),
+        handler_arg: *mut c_void,
+        rtc_intr_mask: u32,
+        flags: u32,
+    ) -> esp_err_t;
+}
+
+/// Brown-out interrupt: latch the request and mask the source, which stays
+/// asserted for as long as the supply is low
+unsafe extern "C" fn on_brown_out(_arg: *mut c_void) {
+    let ena = RTC_CNTL_INT_ENA_REG as *mut u32;
+    ena.write_volatile(ena.read_volatile() & !BROWN_OUT_INT);
+    (RTC_CNTL_INT_CLR_REG as *mut u32).write_volatile(BROWN_OUT_INT);
+    request(ShutdownReason::BrownOut);
+}
+
+/// Arm the brown-out detector to interrupt ahead of its delayed hardware
+/// reset. The reset is armed first, so the detector still protects the
+/// chip if the interrupt cannot be registered.
+pub fn install_brown_out_hook() -> Result<(), EspError> {
+    // SAFETY: the RTC brown-out and interrupt registers are only touched here
+    // and in `on_brown_out`; ESP-IDF's brown-out driver is disabled in
+    // sdkconfig, so nothing else configures them.
+    unsafe {
+        let reg = RTC_CNTL_BROWN_OUT_REG as *mut u32;
+        let value = reg.read_volatile() & !(BROWN_OUT_THRES_MASK | BROWN_OUT_RST_WAIT_MASK);
+        reg.write_volatile(
+            value
+                | BROWN_OUT_ENA
+                | BROWN_OUT_RST_ENA
+                | (BROWN_OUT_LEVEL << BROWN_OUT_THRES_SHIFT)
+                | (BROWN_OUT_RST_WAIT << BROWN_OUT_RST_WAIT_SHIFT),
+        );
+
+        (RTC_CNTL_INT_CLR_REG as *mut u32).write_volatile(BROWN_OUT_INT);
+        EspError::convert(rtc_isr_register(on_brown_out, std::ptr::null_mut(), BROWN_OUT_INT, 0))?;
+        let ena = RTC_CNTL_INT_ENA_REG as *mut u32;
+        ena.write_volatile(ena.read_volatile() | BROWN_OUT_INT);
+    }
+    Ok(())
+}
@@ -0,0 +1,282 @@
+//! Per-device response statistics for SLA reports
+//!
+//! For every MS/TP device that confirmed requests are routed to, counts the
+//! answers (success or error), the requests that ran out of retries, and the
+//! response time from the last transmission to the answer. Counts roll up
+//! per UTC day; the last `SLA_HISTORY_DAYS` completed days are kept and the
+//! whole table is checkpointed to NVS, so a flaky field device can be shown
+//! with numbers covering the past week.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How often the table is written to NVS between day rollovers
+pub const SLA_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Devices tracked at once; the one with the fewest requests is evicted
+pub const MAX_SLA_DEVICES: usize = 32;
+
+/// Completed days kept per device
+pub const SLA_HISTORY_DAYS: usize = 7;
+
+/// Stored bucket: MAC, day, then six counters
+const SLA_BUCKET_LEN: usize = 1 + 4 + 6 * 4;
+
+/// Largest stored table: current day, then one bucket per device and day
+pub const MAX_SLA_RECORD_LEN: usize = 4 + MAX_SLA_DEVICES * (SLA_HISTORY_DAYS + 1) * SLA_BUCKET_LEN;
+
+/// Response counters for one device over one day
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResponseCounters {
+    /// Simple-ACK or Complex-ACK answers
+    pub success: u32,
+    /// Error, Reject or Abort answers
+    pub errors: u32,
+    /// Requests that ran out of retries
+    pub timeouts: u32,
+    pub min_ms: u32,
+    pub max_ms: u32,
+    /// Sum of all response times, for the average
+    pub total_ms: u32,
+}
+
+impl ResponseCounters {
+    fn record_answer(&mut self, response_ms: u32, success: bool) {
+        if self.answered() == 0 || response_ms < self.min_ms {
+            self.min_ms = response_ms;
+        }
+        self.max_ms = self.max_ms.max(response_ms);
+        self.total_ms = self.total_ms.saturating_add(response_ms);
+        if success {
+            self.success += 1;
+        } else {
+            self.errors += 1;
+        }
+    }
+
+    pub fn answered(&self) -> u32 {
+        self.success + self.errors
+    }
+
+    pub fn requests(&self) -> u32 {
+        self.answered() + self.timeouts
+    }
+
+    /// Mean response time over the answered requests
+    pub fn avg_ms(&self) -> Option<u32> {
+        (self.answered() > 0).then(|| self.total_ms / self.answered())
+    }
+
+    /// Share of requests answered with success, in percent
+    pub fn success_pct(&self) -> Option<f32> {
+        (self.requests() > 0).then(|| self.success as f32 * 100.0 / self.requests() as f32)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.requests() == 0
+    }
+}
+
+/// Counters for one MS/TP device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceSla {
+    pub mac: u8,
+    pub today: ResponseCounters,
+    /// Completed days as (days since 1970-01-01, counters), newest first
+    pub history: Vec<(u32, ResponseCounters)>,
+}
+
+impl DeviceSla {
+    fn new(mac: u8) -> Self {
+        Self { mac, today: ResponseCounters::default(), history: Vec::new() }
+    }
+
+    /// Requests over today and the kept history
+    pub fn total_requests(&self) -> u32 {
+        self.today.requests() + self.history.iter().map(|(_, c)| c.requests()).sum::<u32>()
+    }
+}
+
+/// Response statistics for all tracked devices
+#[derive(Debug, Clone, Default)]
+pub struct SlaTable {
+    devices: BTreeMap<u8, DeviceSla>,
+    /// Day the `today` counters belong to (0 until the clock is known)
+    day: u32,
+}
+
+impl SlaTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an answer from `mac` that took `response_time`
+    pub fn record_answer(&mut self, mac: u8, response_time: Duration, success: bool) {
+        let response_ms = response_time.as_millis().min(u32::MAX as u128) as u32;
+        self.device(mac).today.record_answer(response_ms, success);
+    }
+
+    /// Count a request to `mac` that ran out of retries
+    pub fn record_timeout(&mut self, mac: u8) {
+        self.device(mac).today.timeouts += 1;
+    }
+
+    /// Move to `day` (days since 1970-01-01, UTC). Returns true when today's
+    /// counters were rolled into the history.
+    pub fn set_day(&mut self, day: u32) -> bool {
+        if self.day == 0 {
+            self.day = day;
+            return false;
+        }
+        if day <= self.day {
+            return false;
+        }
+        for device in self.devices.values_mut() {
+            if !device.today.is_empty() {
+                device.history.insert(0, (self.day, device.today));
+            }
+            device.history.retain(|(d, _)| day - d <= SLA_HISTORY_DAYS as u32);
+            device.today = ResponseCounters::default();
+        }
+        // Devices with nothing left in the window are dropped
+        self.devices.retain(|_, d| d.total_requests() > 0);
+        self.day = day;
+        true
+    }
+
+    /// All tracked devices, by MAC
+    pub fn snapshot(&self) -> Vec<DeviceSla> {
+        self.devices.values().cloned().collect()
+    }
+
+    /// Serialize for NVS: the current day, then one bucket per device and day
+    /// (day 0 marks today's counters)
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(MAX_SLA_RECORD_LEN);
+        buf.extend_from_slice(&self.day.to_be_bytes());
+        for device in self.devices.values() {
+            let today = (!device.today.is_empty()).then_some((0, device.today));
+            for (day, counters) in today.iter().chain(device.history.iter()) {
+                buf.push(device.mac);
+                buf.extend_from_slice(&day.to_be_bytes());
+                for value in [counters.success, counters.errors, counters.timeouts, counters.min_ms, counters.max_ms, counters.total_ms] {
+                    buf.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+        buf
+    }
+
+    /// Parse a stored table; a truncated record keeps the buckets read so far
+    pub fn decode(data: &[u8]) -> Self {
+        let mut table = Self::new();
+        let Some((day, buckets)) = data.split_first_chunk::<4>() else {
+            return table;
+        };
+        table.day = u32::from_be_bytes(*day);
+        for bucket in buckets.chunks_exact(SLA_BUCKET_LEN) {
+            let word = |i: usize| u32::from_be_bytes([bucket[i], bucket[i + 1], bucket[i + 2], bucket[i + 3]]);
+            let counters = ResponseCounters {
+                success: word(5),
+                errors: word(9),
+                timeouts: word(13),
+                min_ms: word(17),
+                max_ms: word(21),
+                total_ms: word(25),
+            };
+            let device = table.device(bucket[0]);
+            match word(1) {
+                0 => device.today = counters,
+                day if device.history.len() < SLA_HISTORY_DAYS => device.history.push((day, counters)),
+                _ => {}
+            }
+        }
+        table
+    }
+
+    fn device(&mut self, mac: u8) -> &mut DeviceSla {
+        if !self.devices.contains_key(&mac) && self.devices.len() >= MAX_SLA_DEVICES {
+            let quietest = self.devices.values().min_by_key(|d| d.total_requests()).map(|d| d.mac);
+            if let Some(quietest) = quietest {
+                self.devices.remove(&quietest);
+            }
+        }
+        self.devices.entry(mac).or_insert_with(|| DeviceSla::new(mac))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_answers_and_timeouts() {
+        let mut table = SlaTable::new();
+        table.record_answer(5, Duration::from_millis(40), true);
+        table.record_answer(5, Duration::from_millis(120), true);
+        table.record_answer(5, Duration::from_millis(20), false);
+        table.record_timeout(5);
+        table.record_timeout(9);
+
+        let devices = table.snapshot();
+        assert_eq!(devices.len(), 2);
+        let today = devices[0].today;
+        assert_eq!((today.success, today.errors, today.timeouts), (2, 1, 1));
+        assert_eq!((today.min_ms, today.max_ms, today.avg_ms()), (20, 120, Some(60)));
+        assert_eq!(today.success_pct(), Some(50.0));
+        assert_eq!(devices[1].today.avg_ms(), None);
+    }
+
+    #[test]
+    fn test_day_rollover_keeps_history() {
+        let mut table = SlaTable::new();
+        assert!(!table.set_day(20_000));
+        table.record_answer(5, Duration::from_millis(10), true);
+        assert!(!table.set_day(20_000));
+        assert!(table.set_day(20_001));
+        table.record_timeout(5);
+
+        let device = &table.snapshot()[0];
+        assert_eq!(device.today.timeouts, 1);
+        assert_eq!(device.history, [(20_000, ResponseCounters { success: 1, min_ms: 10, max_ms: 10, total_ms: 10, ..Default::default() })]);
+
+        // Only the last week is kept, and a device quiet for all of it is forgotten
+        table.set_day(20_001 + SLA_HISTORY_DAYS as u32);
+        let history: Vec<u32> = table.snapshot()[0].history.iter().map(|(day, _)| *day).collect();
+        assert_eq!(history, [20_001]);
+        table.set_day(20_100);
+        assert!(table.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut table = SlaTable::new();
+        table.set_day(20_000);
+        table.record_answer(5, Duration::from_millis(30), true);
+        table.set_day(20_001);
+        table.record_answer(5, Duration::from_millis(50), false);
+        table.record_timeout(7);
+
+        let encoded = table.encode();
+        assert!(encoded.len() <= MAX_SLA_RECORD_LEN);
+        let decoded = SlaTable::decode(&encoded);
+        assert_eq!(decoded.day, 20_001);
+        assert_eq!(decoded.snapshot(), table.snapshot());
+        assert!(SlaTable::decode(&[]).snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_quietest_device_evicted_when_full() {
+        let mut table = SlaTable::new();
+        for mac in 0..MAX_SLA_DEVICES as u8 {
+            table.record_timeout(mac);
+            table.record_timeout(mac);
+        }
+        table.record_timeout(3);
+        table.record_timeout(200);
+        let macs: Vec<u8> = table.snapshot().iter().map(|d| d.mac).collect();
+        assert_eq!(macs.len(), MAX_SLA_DEVICES);
+        assert!(macs.contains(&200));
+        assert!(macs.contains(&3));
+    }
+}
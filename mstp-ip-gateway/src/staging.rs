@@ -0,0 +1,201 @@
+//! Staged validation of network number and station address changes
+//!
+//! A new MS/TP or IP network number, or a new station address, breaks the
+//! internetwork if something out there already uses it. Such a change is not
+//! applied straight away: for `STAGING_LISTEN` the gateway keeps routing with
+//! the running configuration and listens. Who-Is-Router-To-Network for each
+//! new network number goes out on both ports, and any router answering for
+//! it is a conflict, as is any frame heard from the new station address.
+//! Only a change that passes is saved and the gateway restarted with it;
+//! otherwise the conflicts are reported and nothing changes.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::GatewayConfig;
+
+/// How long to listen for conflicts before committing
+pub const STAGING_LISTEN: Duration = Duration::from_secs(15);
+
+/// The settings a change touches that need checking against the live network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StagedCheck {
+    pub mstp_network: Option<u16>,
+    pub ip_network: Option<u16>,
+    pub mstp_address: Option<u8>,
+}
+
+impl StagedCheck {
+    /// What `candidate` changes against the running configuration, or None
+    /// when it leaves network numbers and station address alone
+    pub fn between(running: &GatewayConfig, candidate: &GatewayConfig) -> Option<Self> {
+        let check = Self {
+            mstp_network: changed(running.mstp_network, candidate.mstp_network),
+            ip_network: changed(running.ip_network, candidate.ip_network),
+            // A slave or self-addressing station does not keep this address
+            mstp_address: changed(running.mstp_address, candidate.mstp_address)
+                .filter(|_| !candidate.mstp_slave_mode && !candidate.mstp_auto_address),
+        };
+        (check != Self::default()).then_some(check)
+    }
+
+    /// New network numbers to ask routers about
+    pub fn networks(&self) -> Vec<u16> {
+        self.mstp_network.iter().chain(self.ip_network.iter()).copied().collect()
+    }
+}
+
+/// The new value, if it differs from the old one
+fn changed<T: PartialEq>(old: T, new: T) -> Option<T> {
+    (old != new).then_some(new)
+}
+
+/// Something on the live network already using a new setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// A router answered for the network number
+    Network { network: u16, router: String },
+    /// A frame was heard from the station address
+    Station(u8),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Network { network, router } => write!(f, "network {} is already reached through router {}", network, router),
+            Conflict::Station(mac) => write!(f, "MS/TP station {} is already in use", mac),
+        }
+    }
+}
+
+/// Where a staged validation has got to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StagingStage {
+    Listening,
+    /// No conflicts: the change is committed
+    Passed,
+    /// Conflicts found: the change is dropped
+    Refused,
+}
+
+impl StagingStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StagingStage::Listening => "listening",
+            StagingStage::Passed => "passed",
+            StagingStage::Refused => "refused",
+        }
+    }
+}
+
+/// A configuration change held back while the network is checked
+#[derive(Debug, Clone)]
+pub struct StagedValidation {
+    /// The configuration to commit if the check passes
+    pub candidate: GatewayConfig,
+    pub check: StagedCheck,
+    pub started: Instant,
+    pub conflicts: Vec<Conflict>,
+    pub stage: StagingStage,
+}
+
+impl StagedValidation {
+    pub fn new(candidate: GatewayConfig, check: StagedCheck, now: Instant) -> Self {
+        Self {
+            candidate,
+            check,
+            started: now,
+            conflicts: Vec::new(),
+            stage: StagingStage::Listening,
+        }
+    }
+
+    /// A router announced reaching `networks` (from I-Am-Router-To-Network)
+    pub fn record_router(&mut self, router: &str, networks: &[u16]) {
+        for &network in networks.iter().filter(|n| self.check.networks().contains(n)) {
+            let conflict = Conflict::Network { network, router: router.to_string() };
+            if !self.conflicts.contains(&conflict) {
+                self.conflicts.push(conflict);
+            }
+        }
+    }
+
+    /// MS/TP stations heard as a frame source
+    pub fn record_stations(&mut self, heard: &[u8]) {
+        if let Some(mac) = self.check.mstp_address.filter(|mac| heard.contains(mac)) {
+            if !self.conflicts.contains(&Conflict::Station(mac)) {
+                self.conflicts.push(Conflict::Station(mac));
+            }
+        }
+    }
+
+    /// Finish once the listening time is over
+    pub fn poll(&mut self, now: Instant) -> StagingStage {
+        if self.stage == StagingStage::Listening && now.duration_since(self.started) >= STAGING_LISTEN {
+            self.stage = if self.conflicts.is_empty() { StagingStage::Passed } else { StagingStage::Refused };
+        }
+        self.stage
+    }
+
+    /// Listening time left
+    pub fn remaining(&self, now: Instant) -> Duration {
+        (self.started + STAGING_LISTEN).saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_covers_only_changed_settings() {
+        let running = GatewayConfig::default();
+        assert_eq!(StagedCheck::between(&running, &running), None);
+
+        let mut candidate = running.clone();
+        candidate.ip_network = running.ip_network + 100;
+        candidate.mstp_address = 9;
+        let check = StagedCheck::between(&running, &candidate).unwrap();
+        assert_eq!(check.mstp_network, None);
+        assert_eq!(check.networks(), vec![running.ip_network + 100]);
+        assert_eq!(check.mstp_address, Some(9));
+
+        // A self-addressing station is not tied to the configured address
+        candidate.mstp_auto_address = true;
+        assert_eq!(StagedCheck::between(&running, &candidate).unwrap().mstp_address, None);
+    }
+
+    #[test]
+    fn test_validation_refused_on_conflicts() {
+        let start = Instant::now();
+        let check = StagedCheck { mstp_network: Some(5), ip_network: None, mstp_address: Some(3) };
+        let mut staging = StagedValidation::new(GatewayConfig::default(), check, start);
+
+        staging.record_router("192.168.1.20:47808", &[4, 6]);
+        staging.record_stations(&[2]);
+        assert!(staging.conflicts.is_empty());
+
+        staging.record_router("192.168.1.20:47808", &[5]);
+        staging.record_router("192.168.1.20:47808", &[5]);
+        staging.record_stations(&[2, 3]);
+        assert_eq!(staging.poll(start + Duration::from_secs(1)), StagingStage::Listening);
+        assert_eq!(staging.poll(start + STAGING_LISTEN), StagingStage::Refused);
+        assert_eq!(
+            staging.conflicts.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            vec![
+                "network 5 is already reached through router 192.168.1.20:47808".to_string(),
+                "MS/TP station 3 is already in use".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validation_passes_on_a_quiet_network() {
+        let start = Instant::now();
+        let check = StagedCheck { mstp_network: None, ip_network: Some(2001), mstp_address: None };
+        let mut staging = StagedValidation::new(GatewayConfig::default(), check, start);
+        staging.record_stations(&[0, 1, 2, 3, 200]);
+        assert_eq!(staging.remaining(start + Duration::from_secs(5)), STAGING_LISTEN - Duration::from_secs(5));
+        assert_eq!(staging.poll(start + STAGING_LISTEN), StagingStage::Passed);
+    }
+}
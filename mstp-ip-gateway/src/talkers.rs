@@ -0,0 +1,289 @@
+//! Top talkers across the router
+//!
+//! Counts packets and bytes routed from and to each link-layer address (MS/TP
+//! MAC or B/IP endpoint) over a rolling window, so the one controller or
+//! workstation flooding the trunk stands out. Two windows are kept - the
+//! current one and the one before it - so the ranking never empties right
+//! after a rollover.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Length of one counting window
+pub const TALKER_WINDOW: Duration = Duration::from_secs(60);
+
+/// Addresses tracked at once; the quietest is evicted to make room
+pub const MAX_TRACKED_TALKERS: usize = 64;
+
+/// A routed endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TalkerAddress {
+    /// MS/TP MAC (255 = MS/TP broadcast)
+    Mstp(u8),
+    /// B/IP endpoint (broadcast and multicast addresses included)
+    Ip(SocketAddr),
+}
+
+impl fmt::Display for TalkerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TalkerAddress::Mstp(255) => write!(f, "MS/TP broadcast"),
+            TalkerAddress::Mstp(mac) => write!(f, "MS/TP {}", mac),
+            TalkerAddress::Ip(addr) => write!(f, "IP {}", addr),
+        }
+    }
+}
+
+/// What to rank talkers by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TalkerRanking {
+    #[default]
+    Packets,
+    Bytes,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counters {
+    packets_sent: u64,
+    bytes_sent: u64,
+    packets_received: u64,
+    bytes_received: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.packets_sent += other.packets_sent;
+        self.bytes_sent += other.bytes_sent;
+        self.packets_received += other.packets_received;
+        self.bytes_received += other.bytes_received;
+    }
+
+    fn packets(&self) -> u64 {
+        self.packets_sent + self.packets_received
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TalkerEntry {
+    current: Counters,
+    previous: Counters,
+}
+
+impl TalkerEntry {
+    fn total(&self) -> Counters {
+        let mut total = self.current;
+        total.add(&self.previous);
+        total
+    }
+}
+
+/// One row of the top-talkers table (covering the last one to two windows)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopTalker {
+    pub address: TalkerAddress,
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+}
+
+impl TopTalker {
+    pub fn packets(&self) -> u64 {
+        self.packets_sent + self.packets_received
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes_sent + self.bytes_received
+    }
+}
+
+/// Rolling per-address traffic counters
+#[derive(Debug)]
+pub struct TalkerTable {
+    entries: HashMap<TalkerAddress, TalkerEntry>,
+    window: Duration,
+    window_start: Instant,
+    /// Off while the low-memory governor has shed the table
+    enabled: bool,
+}
+
+impl TalkerTable {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            entries: HashMap::new(),
+            window,
+            window_start: now,
+            enabled: true,
+        }
+    }
+
+    /// Stop or resume counting; stopping drops the table to free its memory
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.entries = HashMap::new();
+        }
+    }
+
+    /// Count one routed packet from `source` to `dest`
+    pub fn record(&mut self, source: TalkerAddress, dest: TalkerAddress, bytes: usize, now: Instant) {
+        if !self.enabled {
+            return;
+        }
+        self.rotate(now);
+        let bytes = bytes as u64;
+        let counters = &mut self.entry(source).current;
+        counters.packets_sent += 1;
+        counters.bytes_sent += bytes;
+        let counters = &mut self.entry(dest).current;
+        counters.packets_received += 1;
+        counters.bytes_received += bytes;
+    }
+
+    /// Start a new window once the current one has run out, forgetting
+    /// addresses that were silent for two windows
+    pub fn rotate(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < self.window {
+            return;
+        }
+        if elapsed >= self.window * 2 {
+            // Idle for more than a whole window: nothing recent to keep
+            self.entries.clear();
+        } else {
+            for entry in self.entries.values_mut() {
+                entry.previous = entry.current;
+                entry.current = Counters::default();
+            }
+            self.entries.retain(|_, e| e.previous.packets() > 0);
+        }
+        self.window_start = now;
+    }
+
+    /// The `n` busiest addresses, busiest first
+    pub fn top(&self, n: usize, ranking: TalkerRanking) -> Vec<TopTalker> {
+        let mut talkers: Vec<TopTalker> = self
+            .entries
+            .iter()
+            .map(|(address, entry)| {
+                let total = entry.total();
+                TopTalker {
+                    address: *address,
+                    packets_sent: total.packets_sent,
+                    bytes_sent: total.bytes_sent,
+                    packets_received: total.packets_received,
+                    bytes_received: total.bytes_received,
+                }
+            })
+            .collect();
+        talkers.sort_by(|a, b| {
+            let (ka, kb) = match ranking {
+                TalkerRanking::Packets => ((a.packets(), a.bytes()), (b.packets(), b.bytes())),
+                TalkerRanking::Bytes => ((a.bytes(), a.packets()), (b.bytes(), b.packets())),
+            };
+            kb.cmp(&ka)
+        });
+        talkers.truncate(n);
+        talkers
+    }
+
+    fn entry(&mut self, address: TalkerAddress) -> &mut TalkerEntry {
+        if !self.entries.contains_key(&address) && self.entries.len() >= MAX_TRACKED_TALKERS {
+            let quietest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.total().packets())
+                .map(|(a, _)| *a);
+            if let Some(quietest) = quietest {
+                self.entries.remove(&quietest);
+            }
+        }
+        self.entries.entry(address).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> TalkerAddress {
+        TalkerAddress::Ip(SocketAddr::from(([192, 168, 1, last], 47808)))
+    }
+
+    #[test]
+    fn test_ranks_by_packets_and_bytes() {
+        let now = Instant::now();
+        let mut table = TalkerTable::new(TALKER_WINDOW, now);
+        for _ in 0..10 {
+            table.record(TalkerAddress::Mstp(5), ip(20), 20, now);
+        }
+        table.record(TalkerAddress::Mstp(7), ip(20), 1400, now);
+
+        let by_packets = table.top(2, TalkerRanking::Packets);
+        assert_eq!(by_packets[0].address, ip(20));
+        assert_eq!(by_packets[0].packets_received, 11);
+        assert_eq!(by_packets[0].bytes_received, 1600);
+        assert_eq!(by_packets[1].address, TalkerAddress::Mstp(5));
+        assert_eq!(by_packets[1].packets_sent, 10);
+
+        let by_bytes = table.top(3, TalkerRanking::Bytes);
+        assert_eq!(by_bytes[1].address, TalkerAddress::Mstp(7));
+        assert_eq!(by_bytes[2].address, TalkerAddress::Mstp(5));
+    }
+
+    #[test]
+    fn test_rolling_window_forgets_silent_addresses() {
+        let start = Instant::now();
+        let mut table = TalkerTable::new(TALKER_WINDOW, start);
+        table.record(TalkerAddress::Mstp(1), TalkerAddress::Mstp(255), 50, start);
+
+        // Next window: the old counts are still reported alongside new traffic
+        let next = start + TALKER_WINDOW;
+        table.record(TalkerAddress::Mstp(2), ip(9), 50, next);
+        let top = table.top(10, TalkerRanking::Packets);
+        assert!(top.iter().any(|t| t.address == TalkerAddress::Mstp(1)));
+        assert_eq!(table.entries.len(), 4);
+
+        // A window later, only addresses heard in the previous window remain
+        table.rotate(next + TALKER_WINDOW);
+        let top = table.top(10, TalkerRanking::Packets);
+        assert!(top.iter().all(|t| t.address != TalkerAddress::Mstp(1)));
+        assert_eq!(table.entries.len(), 2);
+
+        // Long idle period clears everything
+        table.rotate(next + TALKER_WINDOW * 5);
+        assert_eq!(table.entries.len(), 0);
+    }
+
+    #[test]
+    fn test_capacity_evicts_quietest() {
+        let now = Instant::now();
+        let mut table = TalkerTable::new(TALKER_WINDOW, now);
+        for _ in 0..5 {
+            table.record(TalkerAddress::Mstp(0), TalkerAddress::Mstp(1), 10, now);
+        }
+        for last in 0..=(MAX_TRACKED_TALKERS as u8) {
+            table.record(ip(last), TalkerAddress::Mstp(1), 10, now);
+        }
+        assert_eq!(table.entries.len(), MAX_TRACKED_TALKERS);
+        let top = table.top(2, TalkerRanking::Packets);
+        assert_eq!(top[0].address, TalkerAddress::Mstp(1));
+        assert_eq!(top[1].address, TalkerAddress::Mstp(0));
+    }
+
+    #[test]
+    fn test_disabled_table_is_emptied_and_stays_empty() {
+        let now = Instant::now();
+        let mut table = TalkerTable::new(TALKER_WINDOW, now);
+        table.record(TalkerAddress::Mstp(0), ip(1), 10, now);
+        table.set_enabled(false);
+        table.record(TalkerAddress::Mstp(0), ip(1), 10, now);
+        assert!(table.top(10, TalkerRanking::Packets).is_empty());
+
+        table.set_enabled(true);
+        table.record(TalkerAddress::Mstp(0), ip(1), 10, now);
+        assert_eq!(table.top(10, TalkerRanking::Packets).len(), 2);
+    }
+}
@@ -217,6 +217,8 @@ pub struct TransactionStats {
     pub total_timed_out: u64,
     /// Total retries attempted
     pub total_retries: u64,
+    /// Total transactions aborted by the operator
+    pub total_aborted: u64,
     /// Current number of active transactions
     pub active_count: usize,
 }
@@ -327,6 +329,59 @@ impl TransactionTable {
         Some(transaction)
     }
 
+    /// Remove a stuck transaction on operator request
+    ///
+    /// Unlike `remove`, this is not counted as a completion.
+    pub fn abort(&mut self, invoke_id: u8, dest_mac: u8) -> Option<PendingTransaction> {
+        let key = TransactionKey::new(invoke_id, dest_mac);
+        let transaction = self.transactions.remove(&key)?;
+
+        self.stats.total_aborted += 1;
+        self.stats.active_count = self.transactions.len();
+
+        warn!(
+            "Aborted transaction: invoke_id={} service={:?} dest={}:{} age={:.1}s retries={}",
+            transaction.invoke_id,
+            transaction.service,
+            transaction.dest_network,
+            transaction.dest_mac,
+            transaction.created_at.elapsed().as_secs_f32(),
+            transaction.retries
+        );
+
+        Some(transaction)
+    }
+
+    /// Iterate over active transactions (in no particular order)
+    pub fn iter(&self) -> impl Iterator<Item = &PendingTransaction> {
+        self.transactions.values()
+    }
+
+    /// Remove and return all transactions toward a destination network
+    ///
+    /// Used when a router reports the network unreachable so the clients can
+    /// be aborted instead of waiting for a timeout.
+    pub fn remove_for_network(&mut self, network: u16) -> Vec<PendingTransaction> {
+        let keys: Vec<TransactionKey> = self
+            .transactions
+            .iter()
+            .filter(|(_, tx)| tx.dest_network == network)
+            .map(|(key, _)| *key)
+            .collect();
+
+        let removed: Vec<PendingTransaction> = keys
+            .into_iter()
+            .filter_map(|key| self.transactions.remove(&key))
+            .collect();
+
+        if !removed.is_empty() {
+            debug!("Removed {} transaction(s) toward network {}", removed.len(), network);
+            self.stats.active_count = self.transactions.len();
+        }
+
+        removed
+    }
+
     /// Check for timed-out transactions and return them
     ///
     /// This should be called periodically (e.g., every 1 second) to detect timeouts.
@@ -708,4 +763,63 @@ mod tests {
         assert_eq!(table.stats().total_completed, 1);
         assert_eq!(table.stats().active_count, 0);
     }
+
+    #[test]
+    fn test_remove_for_network() {
+        let mut table = TransactionTable::new();
+
+        for (invoke_id, dest_network) in [(1u8, 1u16), (2, 500), (3, 500)] {
+            let tx = PendingTransaction::new(
+                invoke_id,
+                "192.168.1.100:47808".parse().unwrap(),
+                Some(2),
+                vec![192, 168, 1, 100, 0xBA, 0xC0],
+                dest_network,
+                10,
+                ConfirmedServiceChoice::ReadProperty,
+                false,
+                vec![0x01, 0x08, 0x00, 0x01, 0x01, 0x0A], // Mock NPDU
+            );
+            table.add(tx).unwrap();
+        }
+
+        let removed = table.remove_for_network(500);
+        assert_eq!(removed.len(), 2);
+        assert!(removed.iter().all(|tx| tx.dest_network == 500));
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.stats().active_count, 1);
+        assert!(table.remove_for_network(500).is_empty());
+    }
+
+    #[test]
+    fn test_abort_transaction() {
+        let mut table = TransactionTable::new();
+
+        for invoke_id in [7u8, 8] {
+            let tx = PendingTransaction::new(
+                invoke_id,
+                "192.168.1.100:47808".parse().unwrap(),
+                Some(2),
+                vec![192, 168, 1, 100, 0xBA, 0xC0],
+                1,
+                10,
+                ConfirmedServiceChoice::ReadProperty,
+                false,
+                vec![0x01, 0x08, 0x00, 0x01, 0x01, 0x0A], // Mock NPDU
+            );
+            table.add(tx).unwrap();
+        }
+
+        let mut invoke_ids: Vec<u8> = table.iter().map(|tx| tx.invoke_id).collect();
+        invoke_ids.sort();
+        assert_eq!(invoke_ids, vec![7, 8]);
+
+        let aborted = table.abort(7, 10).unwrap();
+        assert_eq!(aborted.invoke_id, 7);
+        assert!(table.abort(7, 10).is_none());
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.stats().total_aborted, 1);
+        assert_eq!(table.stats().total_completed, 0);
+        assert_eq!(table.stats().active_count, 1);
+    }
 }
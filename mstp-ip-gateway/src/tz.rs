@@ -0,0 +1,320 @@
+//! POSIX TZ rules for the Device object's date and time properties
+//!
+//! Log lines and the portal take local time from the C library, but
+//! Local_Date, Local_Time, UTC_Offset and Daylight_Savings_Status need the
+//! pieces - the standard offset and whether daylight saving is in effect -
+//! which newlib does not expose. This parses the POSIX TZ string from the
+//! configuration (e.g. "CET-1CEST,M3.5.0,M10.5.0/3") and evaluates its DST
+//! rule itself, so a string the portal accepts means the same thing to both.
+
+/// DST rule assumed when a TZ string names a DST zone without one (US rules)
+const DEFAULT_DST_RULE: &str = "M3.2.0,M11.1.0";
+
+/// Transition time of day when a rule gives none (02:00 local)
+const DEFAULT_TRANSITION_SECS: i32 = 7200;
+
+/// Day a DST transition falls on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleDate {
+    /// `Jn`: day 1..=365, February 29 never counted
+    Julian(u16),
+    /// `n`: day 0..=365, February 29 counted
+    DayOfYear(u16),
+    /// `Mm.w.d`: weekday d (0 = Sunday) of week w (5 = last) of month m
+    MonthWeek { month: u8, week: u8, weekday: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Transition {
+    date: RuleDate,
+    /// Local time of day in seconds, in the offset in force before the transition
+    time: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Dst {
+    /// Seconds east of UTC while DST is in effect
+    offset: i32,
+    start: Transition,
+    end: Transition,
+}
+
+/// Parsed POSIX TZ string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Seconds east of UTC for standard time
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+/// Broken-down local time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    /// 1 = Monday .. 7 = Sunday, as BACnet Date encodes it
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub hundredths: u8,
+    /// Daylight saving time in effect
+    pub dst: bool,
+}
+
+impl TimeZone {
+    pub const UTC: TimeZone = TimeZone { std_offset: 0, dst: None };
+
+    /// Parse a POSIX TZ string; None if it is malformed
+    pub fn parse(tz: &str) -> Option<Self> {
+        let rest = zone_name(tz)?;
+        let (std_west, rest) = hms(rest, true)?;
+        let std_offset = -std_west;
+        if rest.is_empty() {
+            return Some(Self { std_offset, dst: None });
+        }
+
+        let rest = zone_name(rest)?;
+        let (offset, rest) = match rest.bytes().next() {
+            None | Some(b',') => (std_offset + 3600, rest),
+            Some(_) => {
+                let (west, rest) = hms(rest, true)?;
+                (-west, rest)
+            }
+        };
+        let rules = if rest.is_empty() { DEFAULT_DST_RULE } else { rest.strip_prefix(',')? };
+        let (start, rules) = transition(rules)?;
+        let (end, rules) = transition(rules.strip_prefix(',')?)?;
+        if !rules.is_empty() {
+            return None;
+        }
+        Some(Self { std_offset, dst: Some(Dst { offset, start, end }) })
+    }
+
+    /// Standard-time offset in minutes as BACnet UTC_Offset has it: zones
+    /// west of Greenwich positive
+    pub fn utc_offset_minutes(&self) -> i16 {
+        (-self.std_offset / 60) as i16
+    }
+
+    /// Whether daylight saving time is in effect at `utc_secs`
+    pub fn is_dst(&self, utc_secs: i64) -> bool {
+        let Some(dst) = self.dst else { return false };
+        let (year, _, _) = civil_from_days((utc_secs + i64::from(self.std_offset)).div_euclid(86_400));
+        let start = dst.start.local_secs(year) - i64::from(self.std_offset);
+        let end = dst.end.local_secs(year) - i64::from(dst.offset);
+        if start < end {
+            (start..end).contains(&utc_secs)
+        } else {
+            // Southern hemisphere: DST spans the new year
+            !(end..start).contains(&utc_secs)
+        }
+    }
+
+    /// Local time for a UTC time in milliseconds since the epoch
+    pub fn local(&self, utc_millis: i64) -> LocalTime {
+        let utc_secs = utc_millis.div_euclid(1000);
+        let dst = self.is_dst(utc_secs);
+        let offset = match self.dst {
+            Some(d) if dst => d.offset,
+            _ => self.std_offset,
+        };
+        let local_secs = utc_secs + i64::from(offset);
+        let days = local_secs.div_euclid(86_400);
+        let secs_of_day = local_secs.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        LocalTime {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday
+            weekday: ((days + 3).rem_euclid(7) + 1) as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+            hundredths: (utc_millis.rem_euclid(1000) / 10) as u8,
+            dst,
+        }
+    }
+}
+
+impl Transition {
+    /// Local seconds since the epoch at which the transition happens in `year`
+    fn local_secs(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        let leap = days_from_civil(year, 3, 1) - days_from_civil(year, 2, 28) == 2;
+        let day = match self.date {
+            RuleDate::Julian(n) => jan1 + i64::from(n) - 1 + i64::from(leap && n >= 60),
+            RuleDate::DayOfYear(n) => jan1 + i64::from(n),
+            RuleDate::MonthWeek { month, week, weekday } => {
+                let first = days_from_civil(year, u32::from(month), 1);
+                let next = if month == 12 { days_from_civil(year + 1, 1, 1) } else { days_from_civil(year, u32::from(month) + 1, 1) };
+                // Weekday of the 1st, 0 = Sunday
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (i64::from(weekday) - first_weekday).rem_euclid(7) + 7 * (i64::from(week) - 1);
+                while day >= next {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        day * 86_400 + i64::from(self.time)
+    }
+}
+
+/// Skip a zone name: three or more letters, or anything between `<` and `>`
+fn zone_name(s: &str) -> Option<&str> {
+    if let Some(quoted) = s.strip_prefix('<') {
+        let end = quoted.find('>')?;
+        return (end >= 3).then(|| &quoted[end + 1..]);
+    }
+    let len = s.bytes().take_while(u8::is_ascii_alphabetic).count();
+    (len >= 3).then(|| &s[len..])
+}
+
+/// `[+|-]hh[:mm[:ss]]` in seconds; the sign is only accepted when `signed`
+fn hms(s: &str, signed: bool) -> Option<(i32, &str)> {
+    let (sign, mut rest) = match s.bytes().next() {
+        Some(b'-') if signed => (-1, &s[1..]),
+        Some(b'+') if signed => (1, &s[1..]),
+        _ => (1, s),
+    };
+    let mut secs = 0;
+    for (i, scale) in [3600, 60, 1].into_iter().enumerate() {
+        if i > 0 {
+            match rest.strip_prefix(':') {
+                Some(r) => rest = r,
+                None => break,
+            }
+        }
+        let (value, r) = number(rest)?;
+        if (i == 0 && value > 167) || (i > 0 && value > 59) {
+            return None;
+        }
+        secs += value as i32 * scale;
+        rest = r;
+    }
+    Some((sign * secs, rest))
+}
+
+/// Leading decimal digits (at most three)
+fn number(s: &str) -> Option<(u16, &str)> {
+    let len = s.bytes().take_while(u8::is_ascii_digit).count();
+    if len == 0 || len > 3 {
+        return None;
+    }
+    Some((s[..len].parse().ok()?, &s[len..]))
+}
+
+/// `date[/time]`
+fn transition(s: &str) -> Option<(Transition, &str)> {
+    let (date, rest) = if let Some(rest) = s.strip_prefix('J') {
+        let (n, rest) = number(rest)?;
+        ((1..=365).contains(&n).then_some(RuleDate::Julian(n))?, rest)
+    } else if let Some(rest) = s.strip_prefix('M') {
+        let (month, rest) = number(rest)?;
+        let (week, rest) = number(rest.strip_prefix('.')?)?;
+        let (weekday, rest) = number(rest.strip_prefix('.')?)?;
+        if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+            return None;
+        }
+        (RuleDate::MonthWeek { month: month as u8, week: week as u8, weekday: weekday as u8 }, rest)
+    } else {
+        let (n, rest) = number(s)?;
+        ((n <= 365).then_some(RuleDate::DayOfYear(n))?, rest)
+    };
+    let (time, rest) = match rest.strip_prefix('/') {
+        Some(rest) => hms(rest, true)?,
+        None => (DEFAULT_TRANSITION_SECS, rest),
+    };
+    Some((Transition { date, time }, rest))
+}
+
+/// Proleptic Gregorian date for a day count since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's days-to-civil algorithm (eras of 400 years)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Day count since 1970-01-01 for a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        for days in (-800_000..800_000).step_by(97) {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn test_parse_offsets_and_rejects_malformed() {
+        assert_eq!(TimeZone::parse("UTC0"), Some(TimeZone::UTC));
+        assert_eq!(TimeZone::parse("EST5EDT").unwrap().utc_offset_minutes(), 300);
+        assert_eq!(TimeZone::parse("<+0530>-5:30").unwrap().utc_offset_minutes(), -330);
+        assert_eq!(TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap().utc_offset_minutes(), -60);
+        for bad in ["", "X1", "CET", "CET-1CEST,M13.1.0,M10.5.0", "CET-1CEST,M3.5.0", "CET-1CEST,M3.5.0,M10.5.0/3x", "UTC 0"] {
+            assert_eq!(TimeZone::parse(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_dst_transitions() {
+        let cet = TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2026-03-29 01:00 UTC and 2026-10-25 01:00 UTC
+        assert!(!cet.is_dst(1_774_746_000 - 1));
+        assert!(cet.is_dst(1_774_746_000));
+        assert!(cet.is_dst(1_792_890_000 - 1));
+        assert!(!cet.is_dst(1_792_890_000));
+
+        // US default rule: 2026-03-08 07:00 UTC
+        let eastern = TimeZone::parse("EST5EDT").unwrap();
+        assert!(!eastern.is_dst(1_772_953_200 - 1));
+        assert!(eastern.is_dst(1_772_953_200));
+
+        // Southern hemisphere: January is summer, 2026-01-15 and 2026-07-15
+        let sydney = TimeZone::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert!(sydney.is_dst(1_768_435_200));
+        assert!(!sydney.is_dst(1_784_073_600));
+        assert!(!TimeZone::UTC.is_dst(1_784_073_600));
+    }
+
+    #[test]
+    fn test_local_time() {
+        let cet = TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2026-10-18T09:30:05.123Z, a Sunday
+        let local = cet.local(1_792_315_805_123);
+        assert_eq!((local.year, local.month, local.day, local.weekday), (2026, 10, 18, 7));
+        assert_eq!((local.hour, local.minute, local.second, local.hundredths), (11, 30, 5, 12));
+        assert!(local.dst);
+
+        let utc = TimeZone::UTC.local(1_792_315_805_123);
+        assert_eq!((utc.hour, utc.dst), (9, false));
+    }
+}
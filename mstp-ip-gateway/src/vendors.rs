@@ -0,0 +1,131 @@
+//! BACnet Vendor Identifier lookup
+//!
+//! Compact table of ASHRAE-assigned vendor IDs for the manufacturers most
+//! commonly found on MS/TP trunks. Used to show readable names for devices
+//! discovered via I-Am instead of bare numeric IDs.
+//!
+//! The full list is maintained by ASHRAE at bacnet.org/assigned-vendor-ids;
+//! unknown IDs are simply shown as numbers.
+
+/// Vendor ID to name table - MUST stay sorted by ID (binary search)
+const VENDOR_NAMES: &[(u16, &str)] = &[
+    (0, "ASHRAE"),
+    (1, "NIST"),
+    (2, "Trane"),
+    (3, "Daikin Applied"),
+    (4, "PolarSoft"),
+    (5, "Johnson Controls"),
+    (6, "American Auto-Matrix"),
+    (7, "Siemens (Landis & Staefa)"),
+    (8, "Delta Controls"),
+    (9, "Siemens Schweiz"),
+    (10, "Schneider Electric"),
+    (11, "TAC"),
+    (12, "Orion Analysis"),
+    (13, "Teletrol Systems"),
+    (14, "Cimetrics"),
+    (15, "Cornell University"),
+    (16, "Carrier"),
+    (17, "Honeywell"),
+    (18, "Alerton"),
+    (19, "TAC AB"),
+    (20, "Hewlett-Packard"),
+    (21, "Dorsette's"),
+    (22, "Siemens (Cerberus)"),
+    (23, "York Controls"),
+    (24, "Automated Logic"),
+    (25, "CSI Control Systems"),
+    (26, "Phoenix Controls"),
+    (27, "Innovex Technologies"),
+    (28, "KMC Controls"),
+    (29, "Xn Technologies"),
+    (30, "Hyundai Information Technology"),
+    (31, "Tokimec"),
+    (32, "Simplex"),
+    (33, "North Building Technologies"),
+    (34, "Notifier"),
+    (35, "Reliable Controls"),
+    (36, "Tridium"),
+    (37, "Sierra Monitor / FieldServer"),
+    (38, "Silicon Energy"),
+    (39, "Kieback & Peter"),
+    (40, "Anacon Systems"),
+    (41, "Systems Controls & Instruments"),
+    (42, "Acuity Brands Lighting"),
+    (43, "Micropower Manufacturing"),
+    (44, "Matrix Controls"),
+    (45, "Metalaire"),
+    (46, "ESS Engineering"),
+    (47, "Sphere Systems"),
+    (48, "Walker Technologies"),
+    (49, "H I Solutions"),
+    (50, "MBS GmbH"),
+    (51, "SAMSON AG"),
+    (52, "Badger Meter"),
+    (53, "Daikin Industries"),
+    (54, "NARA Controls"),
+    (55, "Mammoth"),
+    (56, "Liebert"),
+    (57, "SEMCO"),
+    (58, "Air Monitor"),
+    (59, "Triatek"),
+    (60, "NexLight"),
+    (61, "Multistack"),
+    (62, "TSI"),
+    (63, "Weather-Rite"),
+    (64, "Dunham-Bush"),
+    (65, "Reliance Electric"),
+    (66, "LCS"),
+    (67, "Regulator Australia"),
+    (68, "Touch-Plate Lighting Controls"),
+    (69, "Amann GmbH"),
+    (70, "RLE Technologies"),
+    (71, "Cardkey Systems"),
+    (72, "SECOM"),
+    (73, "ABB Gebaudetechnik"),
+    (74, "KNX Association"),
+    (75, "IEIEJ"),
+    (76, "Nohmi Bosai"),
+    (77, "Carel"),
+    (78, "UTC Fire & Security Espana"),
+    (79, "Hochiki"),
+    (80, "Fr. Sauter AG"),
+    (178, "LOYTEC"),
+    (245, "Contemporary Controls"),
+    (260, "BACnet Stack (SourceForge)"),
+    (364, "Distech Controls"),
+];
+
+/// Look up the vendor name for a BACnet Vendor_Identifier
+pub fn vendor_name(vendor_id: u16) -> Option<&'static str> {
+    VENDOR_NAMES
+        .binary_search_by_key(&vendor_id, |&(id, _)| id)
+        .ok()
+        .map(|i| VENDOR_NAMES[i].1)
+}
+
+/// Vendor name for display, falling back to "Vendor <id>" for unknown IDs
+pub fn vendor_display_name(vendor_id: u16) -> String {
+    match vendor_name(vendor_id) {
+        Some(name) => name.to_string(),
+        None => format!("Vendor {}", vendor_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_sorted() {
+        assert!(VENDOR_NAMES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(vendor_name(5), Some("Johnson Controls"));
+        assert_eq!(vendor_name(364), Some("Distech Controls"));
+        assert_eq!(vendor_name(65535), None);
+        assert_eq!(vendor_display_name(9999), "Vendor 9999");
+    }
+}
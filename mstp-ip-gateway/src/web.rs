@@ -5,36 +5,128 @@
 //! - Configuration page for all settings
 //! - Save/reset configuration to NVS
 //! - Reboot functionality
-
+//! - Friendly names and notes for discovered devices
+//! - Site inventory (object lists and names) download as JSON or CSV
+//! - Ping reachability checks for BDT peers, the default gateway and BBMD
+//! - Foreign Device Table view with delete and a registration accept/reject toggle
+//! - Diagnostics page with the routed traffic top talkers and remote router queries
+//! - Event timeline (WiFi, hotspot, token ring, config saves, unreachable peers)
+//! - Optional admin/viewer accounts (HTTP Basic authentication)
+//! - Scoped API keys (read, config, control) for scripts calling /api endpoints
+
+use embedded_svc::http::server::Request;
 use embedded_svc::io::Write;
-use esp_idf_svc::http::server::{Configuration as HttpConfig, EspHttpServer};
+use esp_idf_svc::http::server::{Configuration as HttpConfig, EspHttpConnection, EspHttpServer};
 use esp_idf_svc::nvs::{EspNvsPartition, NvsDefault};
 use log::{error, info};
-use std::net::{Ipv4Addr, SocketAddr};
-use std::sync::{Arc, Mutex};
-
-use crate::config::GatewayConfig;
-use crate::local_device::DiscoveredDevice;
-use crate::mstp_driver::MstpStats;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use crate::analyzer::{SourceReport, Violation, EXAMPLES_PER_SOURCE};
+use crate::ap_clients::{self, ApClient};
+use crate::auth::{self, parse_basic_auth, ApiKey, ApiScope, ApiScopes, Role, UserAccount};
+use crate::backup::{self, BACKUP_FILE_NAME, MIN_PASSPHRASE_LEN};
+use crate::blackbox::{self, CrashReport, Entry, Record};
+use crate::bundle::{HeapStats, Tar, BUNDLE_FILE_NAME};
+use crate::capacity::{CapacityEstimate, PROJECTED_DEVICES, TYPICAL_ROUTED_NPDU, UTILIZATION_LIMIT_PCT};
+use crate::client::{ClientCall, ClientOutcome};
+use crate::clock;
+use crate::compat;
+use crate::config_check::{self, ConfigIssue, IssueLevel};
+use crate::config::{
+    format_network_list, format_supervisory_stations, parse_network_list, parse_supervisory_stations, AnnounceScope,
+    ApiKeyPersistence, BroadcastForm, CrashReportPersistence, DeviceLabel, DeviceLabelPersistence, GatewayConfig,
+    RouteNextHop, UserAccountPersistence, MAX_ANNOUNCED_NETWORKS, MAX_DEVICE_LABELS, MAX_LABEL_NAME_LEN,
+    MAX_LABEL_NOTES_LEN, MAX_SUPERVISORY_STATIONS,
+};
+use crate::failover::{FailoverRole, FailoverStatus, FALLBACK_HOLD, HEARTBEAT_PORT, PEER_TIMEOUT};
+#[cfg(feature = "fault-injection")]
+use crate::fault_injection::{FaultSettings, FaultStats, MAX_DELAY_MS};
+use crate::gateway::{
+    AddressBindingSummary, BindingEdit, BindingKey, ForeignDeviceSummary, NetworkConflict, ReassemblySummary, SupervisoryStation, TransactionSummary, UnreachableNetwork, MIN_MSTP_MAX_NPDU, MSTP_MAX_NPDU,
+};
+use crate::governor::Feature;
+#[cfg(feature = "gpio-trace")]
+use crate::gpio_trace;
+use crate::health::HealthReport;
+use crate::inventory::{InventoryDevice, InventoryProgress};
+use crate::errors::{self, ErrorKind, ErrorSource};
+use crate::events::{self, Event, EventCategory, Severity};
+use crate::heartbeat::{self, HeartbeatStatus};
+use crate::lifetime::{self, LifetimeStats};
+use crate::local_device::{record_discovered, DiscoveredDevice, ServiceWhitelist, MAX_SITE_STRING_LEN};
+use crate::maxmaster::{self, MaxMasterAdvice};
+use crate::mstp_driver::{LoopbackTestResult, MstpStats, LOOPBACK_MAX_PROBES, MAX_SEND_QUEUE, PFM_MAX_AGGRESSIVENESS};
+use crate::netnum::{HeardNetworks, NetworkNumber, RouterMode};
+use crate::netutil::{self, PingResult, PingRole, PING_COUNT};
+use crate::npdu::{NetworkPriority, NpduQuirk};
+use crate::nvs_writer::{self, NvsWrite, NvsWriter};
+use crate::notify::{self, Notifier};
+use crate::pairing::{check_peer, Pairing, PairingStage, PeerInfo, PAIR_API_PATH};
+use crate::peers::{PeerStats, MAX_TRACKED_PEERS};
+use crate::public_status::{MIN_PUBLIC_PORT, PUBLIC_STATUS_PATH};
+use crate::router_query::{RouterQuery, RouterQueryKind, ROUTER_QUERY_WINDOW};
+use crate::scan::{ScanProfile, MAX_SCAN_INSTANCE, SCAN_REPLY_WINDOW};
+use crate::schedule::{self, ScheduleStage, ScheduledChange, MAX_ROLLBACK_WINDOW_MINUTES, RING_STABLE_HOLD};
+use crate::staging::{StagedCheck, StagedValidation, StagingStage, STAGING_LISTEN};
+use crate::service_stats::{self, ErrorCount, ServiceCounters, MAX_ERROR_REASONS};
+use crate::sla::{DeviceSla, ResponseCounters, MAX_SLA_DEVICES, SLA_HISTORY_DAYS};
+use crate::talkers::{TalkerAddress, TopTalker, TALKER_WINDOW};
+use crate::tz::TimeZone;
+use crate::web_budget::{self, BUDGET_PER_SEC};
+use crate::web_snapshot::Snapshot;
+use crate::write_protect;
 
 /// Web server port
 const WEB_PORT: u16 = 80;
 
+/// Rows shown in each top-talkers ranking
+pub const TOP_TALKERS_SHOWN: usize = 10;
+
+/// Error reasons listed on the diagnostics page (/api/service-errors has them all)
+const DIAGNOSTICS_SERVICE_ERRORS: usize = 10;
+
 /// Shared state for web handlers
+///
+/// The larger tables sit behind `Arc`s so a published snapshot shares them
+/// instead of copying every row. Writers replace a table with a new `Arc`,
+/// or change it through `Arc::make_mut`, which copies it only while a
+/// snapshot still holds the old one.
+#[derive(Clone)]
 pub struct WebState {
     pub config: GatewayConfig,
     pub nvs_partition: Option<EspNvsPartition<NvsDefault>>,
+    /// Background writer all saves go through (set by the main task)
+    pub nvs_writer: Option<NvsWriter>,
     pub mstp_stats: MstpStats,
     pub gateway_stats: GatewayStats,
     pub wifi_connected: bool,
     pub ip_address: String,
+    /// Stations on the configuration hotspot (empty while it is down)
+    pub ap_clients: Vec<ApClient>,
+    /// Lease time of the hotspot's DHCP server in minutes
+    pub ap_lease_minutes: Option<u32>,
     pub reset_stats_requested: bool,
     pub scan_requested: bool,
-    pub discovered_devices: Vec<DiscoveredDevice>,
+    pub discovered_devices: Arc<Vec<DiscoveredDevice>>,
     pub scan_in_progress: bool,
+    /// Current Who-Is scan session (0 = no scan run yet)
+    pub scan_id: u32,
+    /// When the current scan session started
+    pub scan_started: Option<std::time::Instant>,
+    /// Sweep used by the requested scan
+    pub scan_profile: ScanProfile,
+    /// Who-Is requests sent and total for the current sweep (synced from main loop)
+    pub scan_progress: (u32, u32),
     pub start_time: std::time::Instant,
-    /// Last few received BACnet data frames for debugging (source_mac, hex_data)
-    pub last_rx_frames: std::collections::VecDeque<(u8, String)>,
+    /// Last few received BACnet data frames for debugging (source_mac, data, received),
+    /// kept raw so the receive path doesn't format hex for every frame
+    pub last_rx_frames: Arc<std::collections::VecDeque<(u8, Vec<u8>, std::time::Instant)>>,
+    /// Optional features switched off by the low-memory governor (synced from main loop)
+    pub shed_features: Vec<Feature>,
     /// BDT entries for display and management (synced from gateway)
     pub bdt_entries: Vec<(SocketAddr, Ipv4Addr)>,
     /// Request to add BDT entry (IP:port, mask)
@@ -43,6 +135,150 @@ pub struct WebState {
     pub bdt_remove_request: Option<SocketAddr>,
     /// Request to clear all BDT entries
     pub bdt_clear_request: bool,
+    /// Registered foreign devices for display (synced from gateway)
+    pub foreign_devices: Arc<Vec<ForeignDeviceSummary>>,
+    /// Request to delete a foreign device registration by address
+    pub fdt_remove_request: Option<SocketAddr>,
+    /// Whether foreign device registrations are accepted (synced from gateway)
+    pub fd_registrations_enabled: bool,
+    /// Request to accept or reject foreign device registrations
+    pub fd_registrations_request: Option<bool>,
+    /// Learned MS/TP to IP address translations (synced from gateway)
+    pub mstp_to_ip_bindings: Arc<Vec<AddressBindingSummary>>,
+    /// Learned IP to MS/TP address translations (synced from gateway)
+    pub ip_to_mstp_bindings: Arc<Vec<AddressBindingSummary>>,
+    /// Request to pin, delete or flush address translations
+    pub binding_edit_request: Option<BindingEdit>,
+    /// Default IPv4 gateway of the station interface (None in AP mode)
+    pub default_gateway: Option<Ipv4Addr>,
+    /// Redundancy pair state, None for a single unit (synced from main loop)
+    pub failover: Option<FailoverStatus>,
+    /// When protocol analysis was switched on, None while off (synced from gateway)
+    pub analyzer_started: Option<Instant>,
+    /// Conformance counters per source, most nonconformant first (synced from gateway)
+    pub analyzer_reports: Arc<Vec<SourceReport>>,
+    /// Request to switch protocol analysis on or off
+    pub analyzer_request: Option<bool>,
+    /// Busiest routed addresses by packets and by bytes (synced from gateway)
+    pub top_talkers_by_packets: Vec<TopTalker>,
+    pub top_talkers_by_bytes: Vec<TopTalker>,
+    /// Per-endpoint counters for B/IP peers, busiest first (synced from gateway)
+    pub ip_peers: Arc<Vec<PeerStats>>,
+    /// Response statistics per MS/TP device, by MAC (synced from gateway)
+    pub sla_devices: Arc<Vec<DeviceSla>>,
+    /// Requests and answers per confirmed service (synced from gateway)
+    pub service_counters: Vec<(u8, ServiceCounters)>,
+    /// Most frequent Error/Reject/Abort answers by service (synced from gateway)
+    pub service_errors: Vec<ErrorCount>,
+    /// Error answers whose reason did not fit the histogram
+    pub service_errors_untracked: u64,
+    /// MS/TP line utilization and headroom over the last window (synced from the main loop)
+    pub capacity: Option<CapacityEstimate>,
+    /// Latest ping result per checked host (updated by the reachability task)
+    pub reachability: Vec<PingResult>,
+    pub reachability_in_progress: bool,
+    /// Static routes for display (synced from gateway)
+    pub static_routes: Vec<(u16, RouteNextHop)>,
+    /// Request to add or replace a static route (network, next hop)
+    pub static_route_add_request: Option<(u16, RouteNextHop)>,
+    /// Request to remove the static route to a network
+    pub static_route_remove_request: Option<u16>,
+    /// Networks backed off after a Reject-Message-To-Network (synced from gateway)
+    pub unreachable_networks: Vec<UnreachableNetwork>,
+    /// Network numbers other routers announce on IP, for the setup wizard (synced from gateway)
+    pub heard_networks: HeardNetworks,
+    /// Pending confirmed requests and segmented requests being reassembled (synced from gateway)
+    pub transactions: Vec<TransactionSummary>,
+    pub reassemblies: Vec<ReassemblySummary>,
+    /// Request to abort a stuck transaction (invoke id, MS/TP destination)
+    pub transaction_abort_request: Option<(u8, u8)>,
+    /// Administrative port state (synced from gateway)
+    pub mstp_port_enabled: bool,
+    pub ip_port_enabled: bool,
+    /// Requests to enable or disable a port for maintenance
+    pub mstp_port_enable_request: Option<bool>,
+    pub ip_port_enable_request: Option<bool>,
+    /// Request to change Max_Master at runtime (accepted recommendation)
+    pub max_master_request: Option<u8>,
+    /// LCD render time, last and worst (written by the display render task)
+    pub display_render_us: u32,
+    pub display_render_max_us: u32,
+    /// Totals across reboots (None while lifetime statistics are disabled)
+    pub lifetime_stats: Option<LifetimeStats>,
+    /// Blackbox recording of the last unexpected reset (kept in NVS until cleared)
+    pub crash_report: Option<CrashReport>,
+    /// Request to start an MS/TP wiring test (target MAC, probe count)
+    pub loopback_request: Option<(u8, u16)>,
+    /// Request to abort a running wiring test
+    pub loopback_stop_requested: bool,
+    /// Latest wiring test result (synced from MS/TP driver)
+    pub loopback_result: LoopbackTestResult,
+    /// Request to inventory the discovered MS/TP devices
+    pub inventory_requested: bool,
+    /// Request to stop the running inventory
+    pub inventory_cancel_requested: bool,
+    /// Progress of the running inventory (synced from gateway)
+    pub inventory_progress: Option<InventoryProgress>,
+    /// Devices of the last finished inventory
+    pub inventory_report: Arc<Vec<InventoryDevice>>,
+    /// Object_Name of bound devices by instance (synced from gateway)
+    pub device_names: Arc<HashMap<u32, String>>,
+    /// Bound devices whose name has not been read yet (synced from gateway)
+    pub device_names_pending: usize,
+    /// Request to read every device name again
+    pub device_names_refresh_requested: bool,
+    /// Request to query a remote router (address, question)
+    pub router_query_request: Option<(SocketAddr, RouterQueryKind)>,
+    /// Running or last router query with its answers (synced from gateway)
+    pub router_query: Option<RouterQuery>,
+    /// Running or last pairing with a peer BACman started from this portal
+    pub pairing: Option<Pairing>,
+    /// Request to install a paired peer's BDT entry and static route (peer, send test broadcast)
+    pub pair_install_request: Option<(PeerInfo, bool)>,
+    /// Scheduled configuration change (synced from the main loop)
+    pub scheduled_change: Option<ScheduledChange>,
+    /// Time left before an applied change is rolled back, while the ring is watched
+    pub schedule_rollback_in: Option<u64>,
+    /// Request to store a new scheduled change, replacing any pending one
+    pub schedule_request: Option<ScheduledChange>,
+    /// Request to drop the pending scheduled change
+    pub schedule_cancel_requested: bool,
+    /// Request to check a network number or station address change against the live network
+    pub staged_request: Option<StagedValidation>,
+    /// Running or last staged validation (synced from the main loop)
+    pub staged_validation: Option<StagedValidation>,
+    /// ReadProperty/WriteProperty requested from the serial console
+    pub client_call_request: Option<ClientCall>,
+    /// Outcome of the last console client call (synced from gateway)
+    pub client_outcome: Option<ClientOutcome>,
+    /// Request to push Location/Description/Serial_Number to the local device
+    pub site_info_update_requested: bool,
+    /// Duplicate MS/TP network number seen on the IP side (synced from gateway)
+    pub network_conflict: Option<NetworkConflict>,
+    /// Request to acknowledge and clear the network conflict
+    pub network_conflict_clear_requested: bool,
+    /// Auto-address found no free MS/TP station address, so MS/TP stays listen-only
+    pub mstp_no_free_address: bool,
+    /// Heartbeat delivery status (updated by the heartbeat task)
+    pub heartbeat: HeartbeatStatus,
+    /// Webhook queue, change detection and delivery status
+    pub notifier: Notifier,
+    /// Portal accounts (empty = portal open to everyone)
+    pub users: Vec<UserAccount>,
+    /// API keys accepted on /api endpoints
+    pub api_keys: Vec<ApiKey>,
+    /// Portal logins verified recently, so PBKDF2 runs once per login rather than per request
+    pub login_cache: auth::LoginCache,
+    /// Friendly names and notes keyed by device instance (persisted in NVS)
+    pub device_labels: Arc<Vec<DeviceLabel>>,
+    /// Injected fault rates (applied to the MS/TP driver and IP link by the main loop)
+    #[cfg(feature = "fault-injection")]
+    pub fault_settings: FaultSettings,
+    /// Injected fault counts (synced from the MS/TP driver and IP link)
+    #[cfg(feature = "fault-injection")]
+    pub fault_stats_mstp: FaultStats,
+    #[cfg(feature = "fault-injection")]
+    pub fault_stats_ip: FaultStats,
 }
 
 /// Gateway stats snapshot for web display
@@ -54,39 +290,239 @@ pub struct GatewayStats {
     pub ip_to_mstp_bytes: u64,
     pub routing_errors: u64,
     pub transaction_timeouts: u64,
+    /// Store-and-forward queue (IP->MS/TP frames held while the token is lost)
+    pub held_now: usize,
+    pub held_frames: u64,
+    pub held_released: u64,
+    pub held_expired: u64,
+    pub held_overflows: u64,
+    pub active_transactions: usize,
+    pub fdt_entries: usize,
+    pub bdt_entries: usize,
+    pub rejects_received: u64,
+    /// Messages rejected because they exceed the destination port's max NPDU
+    pub messages_too_long: u64,
+    /// Messages dropped or rejected because a port is administratively disabled
+    pub port_disabled_drops: u64,
+    /// RPM requests answered with ReadProperty calls for devices without RPM
+    pub rpm_proxied: u64,
+    /// WPM requests executed as WriteProperty calls for devices without WPM
+    pub wpm_proxied: u64,
+    /// Ranged Who-Is from IP not forwarded because every device in range is on IP
+    pub whois_suppressed: u64,
+    /// Confirmed requests from IP aborted while the MS/TP send queue was backed up
+    pub backpressure_aborts: u64,
+    /// Secure-BVLL messages received, and per source (most frequent first)
+    pub secure_bvll_received: u64,
+    pub secure_bvll_sources: Vec<(IpAddr, u32)>,
+    /// NPDUs repaired in lenient mode, indexed by NpduQuirk
+    pub npdu_quirks: [u64; NpduQuirk::ALL.len()],
+    /// Broadcast copies sent to each supervisory station
+    pub supervisory_stations: Vec<SupervisoryStation>,
+    /// IP transmit task queue (frames waiting, dropped on overflow, failed sends)
+    pub ip_tx_queue_len: usize,
+    pub ip_tx_dropped: u64,
+    pub ip_tx_errors: u64,
+    /// Times the B/IP socket was re-bound after failing or an address change
+    pub ip_socket_recoveries: u64,
+    /// IP network number in use and its quality, and whether the gateway routes or passes through
+    pub ip_network: NetworkNumber,
+    pub router_mode: RouterMode,
 }
 
 impl WebState {
     pub fn new(config: GatewayConfig, nvs_partition: Option<EspNvsPartition<NvsDefault>>) -> Self {
+        let users = nvs_partition
+            .clone()
+            .and_then(|nvs| UserAccountPersistence::load_users(nvs).ok())
+            .unwrap_or_default();
+        let api_keys = nvs_partition
+            .clone()
+            .and_then(|nvs| ApiKeyPersistence::load_keys(nvs).ok())
+            .unwrap_or_default();
+        let device_labels = nvs_partition
+            .clone()
+            .and_then(|nvs| DeviceLabelPersistence::load_labels(nvs).ok())
+            .unwrap_or_default();
         Self {
             config,
             nvs_partition,
+            nvs_writer: None,
             mstp_stats: MstpStats::default(),
             gateway_stats: GatewayStats::default(),
             wifi_connected: false,
             ip_address: String::new(),
+            ap_clients: Vec::new(),
+            ap_lease_minutes: None,
             reset_stats_requested: false,
             scan_requested: false,
-            discovered_devices: Vec::new(),
+            discovered_devices: Arc::default(),
             scan_in_progress: false,
+            scan_id: 0,
+            scan_started: None,
+            scan_profile: ScanProfile::default(),
+            scan_progress: (0, 0),
             start_time: std::time::Instant::now(),
-            last_rx_frames: std::collections::VecDeque::new(),
+            last_rx_frames: Arc::default(),
+            shed_features: Vec::new(),
             bdt_entries: Vec::new(),
             bdt_add_request: None,
             bdt_remove_request: None,
             bdt_clear_request: false,
+            foreign_devices: Arc::default(),
+            fdt_remove_request: None,
+            fd_registrations_enabled: true,
+            fd_registrations_request: None,
+            mstp_to_ip_bindings: Arc::default(),
+            ip_to_mstp_bindings: Arc::default(),
+            binding_edit_request: None,
+            default_gateway: None,
+            failover: None,
+            analyzer_started: None,
+            analyzer_reports: Arc::default(),
+            analyzer_request: None,
+            top_talkers_by_packets: Vec::new(),
+            top_talkers_by_bytes: Vec::new(),
+            ip_peers: Arc::default(),
+            sla_devices: Arc::default(),
+            service_counters: Vec::new(),
+            service_errors: Vec::new(),
+            service_errors_untracked: 0,
+            capacity: None,
+            reachability: Vec::new(),
+            reachability_in_progress: false,
+            static_routes: Vec::new(),
+            static_route_add_request: None,
+            static_route_remove_request: None,
+            unreachable_networks: Vec::new(),
+            heard_networks: HeardNetworks::default(),
+            transactions: Vec::new(),
+            reassemblies: Vec::new(),
+            transaction_abort_request: None,
+            mstp_port_enabled: true,
+            ip_port_enabled: true,
+            mstp_port_enable_request: None,
+            ip_port_enable_request: None,
+            max_master_request: None,
+            display_render_us: 0,
+            display_render_max_us: 0,
+            lifetime_stats: None,
+            crash_report: None,
+            loopback_request: None,
+            loopback_stop_requested: false,
+            loopback_result: LoopbackTestResult::default(),
+            inventory_requested: false,
+            inventory_cancel_requested: false,
+            inventory_progress: None,
+            inventory_report: Arc::default(),
+            device_names: Arc::default(),
+            device_names_pending: 0,
+            device_names_refresh_requested: false,
+            router_query_request: None,
+            router_query: None,
+            pairing: None,
+            pair_install_request: None,
+            scheduled_change: None,
+            schedule_rollback_in: None,
+            schedule_request: None,
+            schedule_cancel_requested: false,
+            staged_request: None,
+            staged_validation: None,
+            client_call_request: None,
+            client_outcome: None,
+            site_info_update_requested: false,
+            network_conflict: None,
+            network_conflict_clear_requested: false,
+            mstp_no_free_address: false,
+            heartbeat: HeartbeatStatus::default(),
+            notifier: Notifier::default(),
+            users,
+            api_keys,
+            login_cache: auth::LoginCache::new(),
+            device_labels: Arc::new(device_labels),
+            #[cfg(feature = "fault-injection")]
+            fault_settings: FaultSettings::default(),
+            #[cfg(feature = "fault-injection")]
+            fault_stats_mstp: FaultStats::default(),
+            #[cfg(feature = "fault-injection")]
+            fault_stats_ip: FaultStats::default(),
+        }
+    }
+
+    /// Hand a write to the NVS writer; false if NVS is not available
+    pub fn queue_nvs_write(&self, write: NvsWrite) -> bool {
+        match self.nvs_writer {
+            Some(ref writer) => {
+                writer.submit(write);
+                true
+            }
+            None => false,
         }
     }
 
     /// Add a received frame to the debug buffer (keeps last 10)
     pub fn add_rx_frame(&mut self, source_mac: u8, data: &[u8]) {
-        let hex = data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
-        self.last_rx_frames.push_back((source_mac, hex));
-        while self.last_rx_frames.len() > 10 {
-            self.last_rx_frames.pop_front();
+        if self.shed_features.contains(&Feature::FrameDebug) {
+            return;
+        }
+        let frames = Arc::make_mut(&mut self.last_rx_frames);
+        frames.push_back((source_mac, data.to_vec(), std::time::Instant::now()));
+        while frames.len() > 10 {
+            frames.pop_front();
+        }
+    }
+
+    /// Object_Name the device reports, once read
+    pub fn device_name(&self, device_instance: u32) -> Option<&str> {
+        self.device_names.get(&device_instance).map(String::as_str)
+    }
+
+    /// Look up the operator label for a device instance
+    pub fn device_label(&self, device_instance: u32) -> Option<&DeviceLabel> {
+        self.device_labels.iter().find(|l| l.device_instance == device_instance)
+    }
+
+    /// Start a new Who-Is scan session and return its ID
+    ///
+    /// Devices from earlier scans are kept; ones that do not answer this
+    /// scan keep their old `last_scan_id` and can be aged out.
+    pub fn begin_scan(&mut self) -> u32 {
+        self.scan_id = self.scan_id.wrapping_add(1).max(1);
+        self.scan_started = Some(std::time::Instant::now());
+        self.scan_id
+    }
+
+    /// Ask the main loop to run a Who-Is sweep and return the new scan ID
+    pub fn request_scan(&mut self, profile: ScanProfile) -> u32 {
+        self.scan_requested = true;
+        self.scan_in_progress = true;
+        self.scan_profile = profile;
+        self.scan_progress = (0, profile.step_count());
+        self.begin_scan()
+    }
+
+    /// Record an I-Am against the current scan session (see `record_discovered`)
+    pub fn record_i_am(&mut self, device: DiscoveredDevice) {
+        // Known devices keep updating, but the list stops growing while memory is low
+        let may_grow = !self.shed_features.contains(&Feature::DiscoveryGrowth);
+        let devices = Arc::make_mut(&mut self.discovered_devices);
+        if record_discovered(devices, device, self.scan_id, may_grow, Instant::now()) {
+            info!("Added device to discovered list (total: {})", devices.len());
         }
     }
 
+    /// Remove discovered devices not heard from within `max_age`
+    ///
+    /// Returns the number of devices removed.
+    pub fn age_discovered_devices(&mut self, max_age: std::time::Duration) -> usize {
+        let devices = Arc::make_mut(&mut self.discovered_devices);
+        let before = devices.len();
+        devices.retain(|d| {
+            d.last_seen.map(|t| t.elapsed() <= max_age).unwrap_or(false)
+        });
+        before - devices.len()
+    }
+
     /// Get uptime in seconds
     pub fn uptime_secs(&self) -> u64 {
         self.start_time.elapsed().as_secs()
@@ -94,18 +530,67 @@ impl WebState {
 
     /// Get formatted uptime string (e.g., "2d 5h 30m")
     pub fn uptime_formatted(&self) -> String {
-        let secs = self.uptime_secs();
-        let days = secs / 86400;
-        let hours = (secs % 86400) / 3600;
-        let mins = (secs % 3600) / 60;
-
-        if days > 0 {
-            format!("{}d {}h {}m", days, hours, mins)
-        } else if hours > 0 {
-            format!("{}h {}m", hours, mins)
-        } else {
-            format!("{}m", mins)
-        }
+        format_uptime(self.uptime_secs())
+    }
+}
+
+/// Copy of the web state that pages and JSON render from (see `web_snapshot`)
+static SNAPSHOT: Snapshot<WebState> = Snapshot::new();
+
+/// Current snapshot of `state`, to render from with no lock held
+pub fn snapshot(state: &Mutex<WebState>) -> Arc<WebState> {
+    SNAPSHOT.load(state, Instant::now())
+}
+
+/// Publish a copy of `state` while portal clients are reading (the caller holds the lock)
+pub fn publish_snapshot(state: &WebState) {
+    let now = Instant::now();
+    if SNAPSHOT.wanted(now) {
+        SNAPSHOT.publish(state, now);
+    }
+}
+
+/// `WebState` locked by a handler that changes it; dropping it invalidates
+/// the snapshot so the next page shows the change
+struct StateWriteGuard<'a>(MutexGuard<'a, WebState>);
+
+impl Deref for StateWriteGuard<'_> {
+    type Target = WebState;
+
+    fn deref(&self) -> &WebState {
+        &self.0
+    }
+}
+
+impl DerefMut for StateWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut WebState {
+        &mut self.0
+    }
+}
+
+impl Drop for StateWriteGuard<'_> {
+    fn drop(&mut self) {
+        SNAPSHOT.invalidate();
+    }
+}
+
+/// Lock the state to change it
+fn lock_for_write(state: &Mutex<WebState>) -> StateWriteGuard<'_> {
+    StateWriteGuard(state.lock().unwrap())
+}
+
+/// Format a duration in seconds as e.g. "2d 5h 30m"
+fn format_uptime(secs: u64) -> String {
+    let days = secs / 86400;
+    let hours = (secs % 86400) / 3600;
+    let mins = (secs % 3600) / 60;
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, mins)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else {
+        format!("{}m", mins)
     }
 }
 
@@ -133,17 +618,21 @@ pub fn start_web_server(
     let state_scan = Arc::clone(&state);
     let state_devices = Arc::clone(&state);
 
-    // Index page - redirect to status
-    server.fn_handler("/", embedded_svc::http::Method::Get, |req| {
+    // Index page - setup wizard until commissioned, then status
+    let state_index = Arc::clone(&state);
+    server.fn_handler("/", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_index, Role::Viewer)? else { return Ok(()) };
+        let configured = state_index.lock().unwrap().config.configured;
+        let html = if configured { HTML_REDIRECT_STATUS } else { HTML_REDIRECT_WIZARD };
         let mut resp = req.into_ok_response()?;
-        resp.write_all(HTML_REDIRECT_STATUS.as_bytes())?;
+        resp.write_all(html.as_bytes())?;
         Ok::<(), anyhow::Error>(())
     })?;
 
     // Status page
     server.fn_handler("/status", embedded_svc::http::Method::Get, move |req| {
-        let state = state_status.lock().unwrap();
-        let html = generate_status_page(&state);
+        let Some(req) = authorize(req, &state_status, Role::Viewer)? else { return Ok(()) };
+        let html = web_budget::run("/status", || generate_status_page(&snapshot(&state_status)));
         let mut resp = req.into_ok_response()?;
         resp.write_all(html.as_bytes())?;
         Ok::<(), anyhow::Error>(())
@@ -151,26 +640,53 @@ pub fn start_web_server(
 
     // Configuration page (GET)
     server.fn_handler("/config", embedded_svc::http::Method::Get, move |req| {
-        let state = state_config.lock().unwrap();
-        let html = generate_config_page(&state);
+        let Some(req) = authorize(req, &state_config, Role::Admin)? else { return Ok(()) };
+        let html = web_budget::run("/config", || generate_config_page(&snapshot(&state_config)));
         let mut resp = req.into_ok_response()?;
         resp.write_all(html.as_bytes())?;
         Ok::<(), anyhow::Error>(())
     })?;
 
     // Configuration form submit (POST)
-    server.fn_handler("/config", embedded_svc::http::Method::Post, move |mut req| {
+    server.fn_handler("/config", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_config_post, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
         // Read POST body
         let mut body = [0u8; 1024];
         let len = req.read(&mut body).unwrap_or(0);
         let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
 
-        // Parse form data
-        let mut state = state_config_post.lock().unwrap();
-        parse_config_form(body_str, &mut state.config);
+        // Parse into a copy; nothing is applied if the result has errors
+        let mut state = lock_for_write(&state_config_post);
+        let (candidate, issues) = validate_config_form(body_str, &state);
+        let message = if form_value(body_str, "check").as_deref() == Some("1") {
+            if issues.is_empty() {
+                "Check only - no problems found, nothing changed.".to_string()
+            } else {
+                format!("Check only - nothing changed.{}", config_issues_html(&issues))
+            }
+        } else if config_check::has_errors(&issues) {
+            format!("Configuration not updated.{}", config_issues_html(&issues))
+        } else if staging_busy(&state) {
+            "Configuration not updated - a staged change is still being checked.".to_string()
+        } else if let Some(check) = StagedCheck::between(&state.config, &candidate) {
+            state.staged_request = Some(StagedValidation::new(candidate, check, std::time::Instant::now()));
+            format!(
+                "Network numbers or station address changed - listening {}s for conflicts before applying. Without conflicts the configuration is saved and the gateway restarts.{}",
+                STAGING_LISTEN.as_secs(),
+                config_issues_html(&issues)
+            )
+        } else {
+            state.config = candidate;
+            // Location/Description/Serial_Number apply at runtime, no reboot needed
+            state.site_info_update_requested = true;
+            format!(
+                "Configuration updated. Click 'Save to NVS' to persist changes.{}",
+                config_issues_html(&issues)
+            )
+        };
 
-        // Redirect back to config page with success message
-        let html = generate_config_page_with_message(&state, "Configuration updated. Click 'Save to NVS' to persist changes.");
+        let html = generate_config_page_with_message(&state, &message);
         let mut resp = req.into_ok_response()?;
         resp.write_all(html.as_bytes())?;
         Ok::<(), anyhow::Error>(())
@@ -178,23 +694,23 @@ pub fn start_web_server(
 
     // Save configuration to NVS
     server.fn_handler("/save", embedded_svc::http::Method::Post, move |req| {
-        let state = state_save.lock().unwrap();
-        let message = if let Some(ref nvs) = state.nvs_partition {
-            match state.config.save_to_nvs(nvs.clone()) {
-                Ok(_) => {
-                    info!("Configuration saved to NVS via web portal");
-                    "Configuration saved successfully! Reboot to apply changes."
-                }
-                Err(e) => {
-                    error!("Failed to save config: {}", e);
-                    "Error saving configuration!"
-                }
-            }
+        let Some(req) = authorize(req, &state_save, Role::Admin)? else { return Ok(()) };
+        let Some(req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_save);
+        let issues = config_check::check(&state.config, &discovered_instances(&state));
+        let message = if config_check::has_errors(&issues) {
+            format!("Configuration not saved.{}", config_issues_html(&issues))
+        } else if state.queue_nvs_write(NvsWrite::Config(Box::new(state.config.clone()))) {
+            info!("Configuration save queued via web portal");
+            events::record(EventCategory::Config, Severity::Info, "Configuration saved via web portal");
+            state.config.configured = true;
+            state.config.commissioning_step = 0;
+            "Configuration saved! Reboot to apply changes. The event log confirms the write to NVS.".to_string()
         } else {
-            "NVS not available"
+            "NVS not available".to_string()
         };
 
-        let html = generate_config_page_with_message(&state, message);
+        let html = generate_config_page_with_message(&state, &message);
         let mut resp = req.into_ok_response()?;
         resp.write_all(html.as_bytes())?;
         Ok::<(), anyhow::Error>(())
@@ -202,7 +718,13 @@ pub fn start_web_server(
 
     // Reset configuration to defaults
     server.fn_handler("/reset", embedded_svc::http::Method::Post, move |req| {
-        let mut state = state_reset.lock().unwrap();
+        let Some(req) = authorize(req, &state_reset, Role::Admin)? else { return Ok(()) };
+        let Some(req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_reset);
+        // Nothing still queued may land after the erase
+        if let Some(ref writer) = state.nvs_writer {
+            writer.flush(nvs_writer::FLUSH_TIMEOUT);
+        }
         if let Some(ref nvs) = state.nvs_partition {
             let _ = GatewayConfig::clear_nvs(nvs.clone());
         }
@@ -216,8 +738,17 @@ pub fn start_web_server(
     })?;
 
     // Reboot device
-    server.fn_handler("/reboot", embedded_svc::http::Method::Post, |req| {
+    let state_reboot = Arc::clone(&state);
+    server.fn_handler("/reboot", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_reboot, Role::Admin)? else { return Ok(()) };
         info!("Reboot requested via web portal");
+        // Saves still in the writer's queue must reach flash first
+        let writer = state_reboot.lock().ok().and_then(|state| state.nvs_writer.clone());
+        if let Some(writer) = writer {
+            if !writer.flush(nvs_writer::FLUSH_TIMEOUT) {
+                error!("Queued NVS writes did not finish before reboot");
+            }
+        }
         let html = HTML_REBOOT_PAGE;
         let mut resp = req.into_ok_response()?;
         resp.write_all(html.as_bytes())?;
@@ -233,10 +764,209 @@ pub fn start_web_server(
         Ok::<(), anyhow::Error>(())
     })?;
 
+    // Encrypted configuration backup, secrets included (download)
+    let state_backup_export = Arc::clone(&state);
+    server.fn_handler("/backup/export", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_backup_export, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 256];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+        // Form encoding turns spaces into '+'
+        let passphrase = form_value(&body_str.replace('+', "%20"), "passphrase").unwrap_or_default();
+
+        // Key derivation takes about a second; do it outside the lock
+        let config = state_backup_export.lock().unwrap().config.clone();
+        match backup::export_config(&config, &passphrase) {
+            Ok(blob) => {
+                info!("Encrypted configuration backup exported via web portal");
+                let disposition = format!("attachment; filename=\"{}\"", BACKUP_FILE_NAME);
+                let mut resp = req.into_response(200, Some("OK"), &[
+                    ("Content-Type", "application/octet-stream"),
+                    ("Content-Disposition", &disposition),
+                ])?;
+                resp.write_all(blob.as_bytes())?;
+            }
+            Err(e) => {
+                let state = state_backup_export.lock().unwrap();
+                let html = generate_config_page_with_message(&state, &format!("Backup failed: {}", e));
+                let mut resp = req.into_ok_response()?;
+                resp.write_all(html.as_bytes())?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Restore an encrypted configuration backup and save it to NVS
+    let state_backup_import = Arc::clone(&state);
+    server.fn_handler("/backup/import", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_backup_import, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        // A backup is under 2 KB of base64; the body may arrive in several reads
+        let mut body = vec![0u8; 8192];
+        let mut len = 0;
+        while len < body.len() {
+            match req.read(&mut body[len..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+        }
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+        let passphrase = form_value(&body_str.replace('+', "%20"), "passphrase").unwrap_or_default();
+        let blob = form_value(body_str, "backup").unwrap_or_default();
+
+        let restored = backup::import_config(&blob, &passphrase);
+        let mut state = lock_for_write(&state_backup_import);
+        let message = match restored {
+            Ok(form) => {
+                let (candidate, issues) = validate_config_form(&form, &state);
+                if config_check::has_errors(&issues) {
+                    info!("Configuration restore refused: backup does not validate");
+                    format!("Restore refused - the backup does not validate.{}", config_issues_html(&issues))
+                } else {
+                    state.config = candidate;
+                    state.site_info_update_requested = true;
+                    if state.queue_nvs_write(NvsWrite::Config(Box::new(state.config.clone()))) {
+                        info!("Configuration restored from encrypted backup via web portal");
+                        events::record(EventCategory::Config, Severity::Info, "Configuration restored from backup");
+                        state.config.configured = true;
+                        state.config.commissioning_step = 0;
+                        "Configuration restored and saved. Reboot to apply changes.".to_string()
+                    } else {
+                        "Configuration restored but NVS is not available".to_string()
+                    }
+                }
+            }
+            Err(e) => {
+                info!("Configuration restore failed: {}", e);
+                errors::record(ErrorSource::Web, ErrorKind::BadRequest, &format!("restore: {}", e));
+                format!("Restore failed: {}", e)
+            }
+        };
+
+        let html = generate_config_page_with_message(&state, &message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Schedule a one-time configuration change from the config page
+    let state_schedule = Arc::clone(&state);
+    server.fn_handler("/config/schedule", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_schedule, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 1024];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_schedule);
+        let message = match validate_schedule_form(&schedule_page_form(body_str), &state) {
+            Ok(change) => {
+                let message = format!(
+                    "Change scheduled for {} UTC: {}",
+                    schedule_time(&change),
+                    html_escape(&change.summary())
+                );
+                state.schedule_request = Some(change);
+                message
+            }
+            Err(issues) => format!("Change not scheduled.{}", config_issues_html(&issues)),
+        };
+
+        let html = generate_config_page_with_message(&state, &message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Drop the pending scheduled change
+    let state_schedule_cancel = Arc::clone(&state);
+    server.fn_handler("/config/schedule/cancel", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_schedule_cancel, Role::Admin)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_schedule_cancel);
+        state.schedule_cancel_requested = true;
+        let html = generate_config_page_with_message(&state, "Scheduled change cancelled.");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint for the scheduled configuration change
+    let state_schedule_api = Arc::clone(&state);
+    server.fn_handler("/api/config/schedule", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_schedule_api, Role::Viewer)? else { return Ok(()) };
+        let json = generate_schedule_json(&snapshot(&state_schedule_api));
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to schedule a change: at, window and the config fields to set
+    let state_schedule_api_post = Arc::clone(&state);
+    server.fn_handler("/api/config/schedule", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_schedule_api_post, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 1024];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_schedule_api_post);
+        let (code, reason, json) = match validate_schedule_form(body_str, &state) {
+            Ok(change) => {
+                info!("Configuration change scheduled via API for {}", schedule_time(&change));
+                let json = format!(
+                    r#"{{"scheduled":true,"apply_at":"{}","window_minutes":{}}}"#,
+                    schedule_time(&change),
+                    change.window_minutes
+                );
+                state.schedule_request = Some(change);
+                (200, "OK", json)
+            }
+            Err(issues) => (422, "Unprocessable Entity", generate_config_issues_json(false, false, false, &issues)),
+        };
+        drop(state);
+
+        let mut resp = req.into_response(code, Some(reason), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to drop the pending scheduled change
+    let state_schedule_api_cancel = Arc::clone(&state);
+    server.fn_handler("/api/config/schedule/cancel", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_schedule_api_cancel, Role::Admin)? else { return Ok(()) };
+        lock_for_write(&state_schedule_api_cancel).schedule_cancel_requested = true;
+        let json = r#"{"status":"ok","message":"Scheduled change cancel requested"}"#;
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Health check for load balancers and uptime monitors. Unauthenticated
+    // and lock-free: it reads only the cached flags in `health`.
+    server.fn_handler("/healthz", embedded_svc::http::Method::Get, move |req| {
+        let report = HealthReport::snapshot();
+        let (code, reason) = if report.healthy() { (200, "OK") } else { (503, "Service Unavailable") };
+        let mut resp = req.into_response(code, Some(reason), &[
+            ("Content-Type", "application/json"),
+            ("Cache-Control", "no-store"),
+        ])?;
+        resp.write_all(report.to_json().as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
     // API endpoint for status JSON (for AJAX updates)
     server.fn_handler("/api/status", embedded_svc::http::Method::Get, move |req| {
-        let state = state_api_status.lock().unwrap();
-        let json = generate_status_json(&state);
+        let Some(req) = authorize(req, &state_api_status, Role::Viewer)? else { return Ok(()) };
+        let json = web_budget::run("/api/status", || generate_status_json(&snapshot(&state_api_status)));
         let mut resp = req.into_response(200, Some("OK"), &[
             ("Content-Type", "application/json"),
             ("Access-Control-Allow-Origin", "*"),
@@ -247,7 +977,8 @@ pub fn start_web_server(
 
     // API endpoint to reset statistics
     server.fn_handler("/api/reset-stats", embedded_svc::http::Method::Post, move |req| {
-        let mut state = state_reset_stats.lock().unwrap();
+        let Some(req) = authorize(req, &state_reset_stats, Role::Admin)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_reset_stats);
         state.reset_stats_requested = true;
         info!("Statistics reset requested via web portal");
         let json = r#"{"status":"ok","message":"Statistics reset requested"}"#;
@@ -259,10 +990,67 @@ pub fn start_web_server(
         Ok::<(), anyhow::Error>(())
     })?;
 
+    // API endpoint to apply configuration fields, or only check them with dry_run=1
+    let state_config_api = Arc::clone(&state);
+    server.fn_handler("/api/config", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_config_api, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 1024];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_config_api);
+        let (candidate, issues) = validate_config_form(body_str, &state);
+        let dry_run = form_value(body_str, "dry_run").as_deref() == Some("1");
+        let valid = !config_check::has_errors(&issues);
+        let busy = valid && !dry_run && staging_busy(&state);
+        let staged = if valid && !dry_run && !busy { StagedCheck::between(&state.config, &candidate) } else { None };
+        let applied = valid && !dry_run && !busy && staged.is_none();
+        if let Some(check) = staged {
+            state.staged_request = Some(StagedValidation::new(candidate, check, std::time::Instant::now()));
+            info!("Configuration change staged via API");
+        } else if applied {
+            state.config = candidate;
+            state.site_info_update_requested = true;
+            info!("Configuration updated via API");
+        }
+        drop(state);
+
+        let json = generate_config_issues_json(valid, applied, staged.is_some(), &issues);
+        let (code, reason) = if !valid {
+            (422, "Unprocessable Entity")
+        } else if busy {
+            (409, "Conflict")
+        } else if staged.is_some() {
+            (202, "Accepted")
+        } else {
+            (200, "OK")
+        };
+        let mut resp = req.into_response(code, Some(reason), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint for the running or last staged validation
+    let state_staged_api = Arc::clone(&state);
+    server.fn_handler("/api/config/staged", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_staged_api, Role::Viewer)? else { return Ok(()) };
+        let json = generate_staged_json(&snapshot(&state_staged_api));
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
     // API endpoint to export all data as JSON
     server.fn_handler("/api/export", embedded_svc::http::Method::Get, move |req| {
-        let state = state_export.lock().unwrap();
-        let json = generate_export_json(&state);
+        let Some(req) = authorize(req, &state_export, Role::Viewer)? else { return Ok(()) };
+        let json = web_budget::run("/api/export", || generate_export_json(&snapshot(&state_export)));
         let mut resp = req.into_response(200, Some("OK"), &[
             ("Content-Type", "application/json"),
             ("Content-Disposition", "attachment; filename=\"bacman-export.json\""),
@@ -272,35 +1060,108 @@ pub fn start_web_server(
         Ok::<(), anyhow::Error>(())
     })?;
 
+    // Support bundle: configuration without passwords, statistics, logs,
+    // blackbox capture and crash report in one archive
+    let state_bundle = Arc::clone(&state);
+    server.fn_handler("/api/support-bundle", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_bundle, Role::Viewer)? else { return Ok(()) };
+        let bundle = web_budget::run("/api/support-bundle", || generate_support_bundle(&snapshot(&state_bundle)));
+        let disposition = format!("attachment; filename=\"{}\"", BUNDLE_FILE_NAME);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/x-tar"),
+            ("Content-Disposition", &disposition),
+        ])?;
+        resp.write_all(&bundle)?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to acknowledge a duplicate network conflict
+    let state_clear_conflict = Arc::clone(&state);
+    server.fn_handler("/api/clear-conflict", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_clear_conflict, Role::Admin)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_clear_conflict);
+        state.network_conflict_clear_requested = true;
+        info!("Network conflict clear requested via web portal");
+        let json = r#"{"status":"ok","message":"Network conflict cleared"}"#;
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
     // API endpoint to start a Who-Is scan
+    // Body selects the sweep: profile=quick|ranged|directed (ranged adds low, high, window)
     server.fn_handler("/api/scan", embedded_svc::http::Method::Post, move |req| {
-        let mut state = state_scan.lock().unwrap();
-        if state.scan_in_progress {
-            let json = r#"{"status":"busy","message":"Scan already in progress"}"#;
-            let mut resp = req.into_response(200, Some("OK"), &[
-                ("Content-Type", "application/json"),
-                ("Access-Control-Allow-Origin", "*"),
-            ])?;
-            resp.write_all(json.as_bytes())?;
+        let Some(mut req) = authorize(req, &state_scan, Role::Viewer)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_scan);
+        let json = if state.scan_in_progress {
+            r#"{"status":"busy","message":"Scan already in progress"}"#.to_string()
         } else {
-            state.scan_requested = true;
-            state.scan_in_progress = true;
-            state.discovered_devices.clear();
-            info!("Who-Is scan requested via web portal");
-            let json = r#"{"status":"ok","message":"Scan started"}"#;
-            let mut resp = req.into_response(200, Some("OK"), &[
-                ("Content-Type", "application/json"),
-                ("Access-Control-Allow-Origin", "*"),
-            ])?;
-            resp.write_all(json.as_bytes())?;
-        }
+            match parse_scan_form(body_str) {
+                Ok(profile) => {
+                    let scan_id = state.request_scan(profile);
+                    info!("Who-Is scan {} ({}) requested via web portal", scan_id, profile);
+                    format!(
+                        r#"{{"status":"ok","message":"Scan started","scan_id":{},"requests":{},"duration_ms":{}}}"#,
+                        scan_id,
+                        profile.step_count(),
+                        profile.duration(state.config.mstp_baud_rate).as_millis()
+                    )
+                }
+                Err(e) => format!(r#"{{"status":"error","message":"{}"}}"#, json_escape(&e)),
+            }
+        };
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
         Ok::<(), anyhow::Error>(())
     })?;
 
-    // API endpoint to get discovered devices
+    // API endpoint to get discovered devices (optionally only those heard in scan `?scan=N`)
     server.fn_handler("/api/devices", embedded_svc::http::Method::Get, move |req| {
-        let state = state_devices.lock().unwrap();
-        let json = generate_devices_json(&state);
+        let Some(req) = authorize(req, &state_devices, Role::Viewer)? else { return Ok(()) };
+        let scan_filter = req.uri()
+            .split_once('?')
+            .and_then(|(_, query)| form_value(query, "scan"))
+            .and_then(|v| v.parse::<u32>().ok());
+        let state = snapshot(&state_devices);
+        let json = generate_devices_json(&state, scan_filter);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to clear the discovery list, or age out entries with `older_than=SECS`
+    let state_clear_devices = Arc::clone(&state);
+    server.fn_handler("/api/devices/clear", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_clear_devices, Role::Viewer)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+        let older_than = form_value(body_str, "older_than").and_then(|v| v.parse::<u64>().ok());
+
+        let mut state = lock_for_write(&state_clear_devices);
+        let removed = match older_than {
+            Some(secs) => state.age_discovered_devices(std::time::Duration::from_secs(secs)),
+            None => {
+                let count = state.discovered_devices.len();
+                state.discovered_devices = Arc::default();
+                count
+            }
+        };
+        info!("Removed {} discovered device(s) via web portal", removed);
+        let json = format!(r#"{{"status":"ok","removed":{}}}"#, removed);
         let mut resp = req.into_response(200, Some("OK"), &[
             ("Content-Type", "application/json"),
             ("Access-Control-Allow-Origin", "*"),
@@ -312,7 +1173,8 @@ pub fn start_web_server(
     // API endpoint to stop scan
     let state_stop_scan = Arc::clone(&state);
     server.fn_handler("/api/stop-scan", embedded_svc::http::Method::Post, move |req| {
-        let mut state = state_stop_scan.lock().unwrap();
+        let Some(req) = authorize(req, &state_stop_scan, Role::Viewer)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_stop_scan);
         state.scan_in_progress = false;
         info!("Scan stopped via web portal");
         let json = r#"{"status":"ok","message":"Scan stopped"}"#;
@@ -327,11 +1189,8 @@ pub fn start_web_server(
     // API endpoint to get last received frames (debug)
     let state_debug = Arc::clone(&state);
     server.fn_handler("/api/debug/frames", embedded_svc::http::Method::Get, move |req| {
-        let state = state_debug.lock().unwrap();
-        let frames: Vec<String> = state.last_rx_frames.iter()
-            .map(|(mac, hex)| format!("{{\"mac\":{},\"data\":\"{}\"}}", mac, hex))
-            .collect();
-        let json = format!("{{\"frames\":[{}]}}", frames.join(","));
+        let Some(req) = authorize(req, &state_debug, Role::Viewer)? else { return Ok(()) };
+        let json = generate_debug_frames_json(&snapshot(&state_debug));
         let mut resp = req.into_response(200, Some("OK"), &[
             ("Content-Type", "application/json"),
             ("Access-Control-Allow-Origin", "*"),
@@ -340,10 +1199,96 @@ pub fn start_web_server(
         Ok::<(), anyhow::Error>(())
     })?;
 
+    // Fault injection settings and counters (debug builds only)
+    #[cfg(feature = "fault-injection")]
+    {
+        let state_inject = Arc::clone(&state);
+        server.fn_handler("/api/debug/inject", embedded_svc::http::Method::Get, move |req| {
+            let Some(req) = authorize(req, &state_inject, Role::Admin)? else { return Ok(()) };
+            let state = snapshot(&state_inject);
+            let json = generate_fault_injection_json(&state, "");
+            let mut resp = req.into_response(200, Some("OK"), &[
+                ("Content-Type", "application/json"),
+                ("Access-Control-Allow-Origin", "*"),
+            ])?;
+            resp.write_all(json.as_bytes())?;
+            Ok::<(), anyhow::Error>(())
+        })?;
+
+        // POST drop=N&corrupt=N&duplicate=N&delay_ms=N (percentages 0-100; omitted = unchanged)
+        let state_inject_set = Arc::clone(&state);
+        server.fn_handler("/api/debug/inject", embedded_svc::http::Method::Post, move |req| {
+            let Some(mut req) = authorize(req, &state_inject_set, Role::Admin)? else { return Ok(()) };
+            let mut body = [0u8; 128];
+            let len = req.read(&mut body).unwrap_or(0);
+            let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+            let mut state = lock_for_write(&state_inject_set);
+            let (status, reason, json) = match parse_fault_injection_form(body_str, state.fault_settings) {
+                Ok(settings) => {
+                    state.fault_settings = settings;
+                    info!("Fault injection set via web portal: {:?}", settings);
+                    (200, "OK", generate_fault_injection_json(&state, "ok"))
+                }
+                Err(msg) => (400, "Bad Request", generate_fault_injection_json(&state, msg)),
+            };
+            let mut resp = req.into_response(status, Some(reason), &[
+                ("Content-Type", "application/json"),
+                ("Access-Control-Allow-Origin", "*"),
+            ])?;
+            resp.write_all(json.as_bytes())?;
+            Ok::<(), anyhow::Error>(())
+        })?;
+    }
+
+    // GPIO trace markers for a logic analyzer (debug builds only)
+    #[cfg(feature = "gpio-trace")]
+    {
+        let state_gpio = Arc::clone(&state);
+        server.fn_handler("/api/debug/gpio", embedded_svc::http::Method::Get, move |req| {
+            let Some(req) = authorize(req, &state_gpio, Role::Admin)? else { return Ok(()) };
+            let json = generate_gpio_trace_json("");
+            let mut resp = req.into_response(200, Some("OK"), &[
+                ("Content-Type", "application/json"),
+                ("Access-Control-Allow-Origin", "*"),
+            ])?;
+            resp.write_all(json.as_bytes())?;
+            Ok::<(), anyhow::Error>(())
+        })?;
+
+        // POST enabled=1|0
+        let state_gpio_set = Arc::clone(&state);
+        server.fn_handler("/api/debug/gpio", embedded_svc::http::Method::Post, move |req| {
+            let Some(mut req) = authorize(req, &state_gpio_set, Role::Admin)? else { return Ok(()) };
+            let mut body = [0u8; 64];
+            let len = req.read(&mut body).unwrap_or(0);
+            let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+            let (status, reason, json) = match form_value(body_str, "enabled").as_deref() {
+                Some("1") => {
+                    gpio_trace::set_enabled(true);
+                    (200, "OK", generate_gpio_trace_json("ok"))
+                }
+                Some("0") => {
+                    gpio_trace::set_enabled(false);
+                    (200, "OK", generate_gpio_trace_json("ok"))
+                }
+                _ => (400, "Bad Request", generate_gpio_trace_json("enabled must be 1 or 0")),
+            };
+            let mut resp = req.into_response(status, Some(reason), &[
+                ("Content-Type", "application/json"),
+                ("Access-Control-Allow-Origin", "*"),
+            ])?;
+            resp.write_all(json.as_bytes())?;
+            Ok::<(), anyhow::Error>(())
+        })?;
+    }
+
     // BDT page (GET)
     let state_bdt = Arc::clone(&state);
     server.fn_handler("/bdt", embedded_svc::http::Method::Get, move |req| {
-        let state = state_bdt.lock().unwrap();
+        let Some(req) = authorize(req, &state_bdt, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_bdt);
         let html = generate_bdt_page(&state);
         let mut resp = req.into_ok_response()?;
         resp.write_all(html.as_bytes())?;
@@ -352,12 +1297,14 @@ pub fn start_web_server(
 
     // BDT add entry (POST)
     let state_bdt_add = Arc::clone(&state);
-    server.fn_handler("/bdt/add", embedded_svc::http::Method::Post, move |mut req| {
+    server.fn_handler("/bdt/add", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_bdt_add, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
         let mut body = [0u8; 256];
         let len = req.read(&mut body).unwrap_or(0);
         let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
 
-        let mut state = state_bdt_add.lock().unwrap();
+        let mut state = lock_for_write(&state_bdt_add);
         let message = parse_bdt_add_form(body_str, &mut state);
 
         let html = generate_bdt_page_with_message(&state, message);
@@ -368,12 +1315,14 @@ pub fn start_web_server(
 
     // BDT remove entry (POST)
     let state_bdt_remove = Arc::clone(&state);
-    server.fn_handler("/bdt/remove", embedded_svc::http::Method::Post, move |mut req| {
+    server.fn_handler("/bdt/remove", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_bdt_remove, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
         let mut body = [0u8; 128];
         let len = req.read(&mut body).unwrap_or(0);
         let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
 
-        let mut state = state_bdt_remove.lock().unwrap();
+        let mut state = lock_for_write(&state_bdt_remove);
         let message = parse_bdt_remove_form(body_str, &mut state);
 
         let html = generate_bdt_page_with_message(&state, message);
@@ -385,7 +1334,9 @@ pub fn start_web_server(
     // BDT clear all (POST)
     let state_bdt_clear = Arc::clone(&state);
     server.fn_handler("/bdt/clear", embedded_svc::http::Method::Post, move |req| {
-        let mut state = state_bdt_clear.lock().unwrap();
+        let Some(req) = authorize(req, &state_bdt_clear, Role::Admin)? else { return Ok(()) };
+        let Some(req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_bdt_clear);
         state.bdt_clear_request = true;
         info!("BDT clear requested via web portal");
 
@@ -395,1169 +1346,7125 @@ pub fn start_web_server(
         Ok::<(), anyhow::Error>(())
     })?;
 
-    // API endpoint to get BDT entries as JSON
-    let state_bdt_api = Arc::clone(&state);
-    server.fn_handler("/api/bdt", embedded_svc::http::Method::Get, move |req| {
-        let state = state_bdt_api.lock().unwrap();
-        let json = generate_bdt_json(&state);
-        let mut resp = req.into_response(200, Some("OK"), &[
-            ("Content-Type", "application/json"),
-            ("Access-Control-Allow-Origin", "*"),
-        ])?;
-        resp.write_all(json.as_bytes())?;
+    // BDT reachability check (POST) - pings peers, default gateway and BBMD
+    let state_bdt_ping = Arc::clone(&state);
+    server.fn_handler("/bdt/ping", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_bdt_ping, Role::Viewer)? else { return Ok(()) };
+        let busy = state_bdt_ping.lock().unwrap().reachability_in_progress;
+        let message = if busy {
+            "Reachability check already running."
+        } else {
+            match netutil::spawn_reachability_check(Arc::clone(&state_bdt_ping)) {
+                Ok(()) => "Reachability check started. Refresh for results.",
+                Err(e) => {
+                    error!("Failed to start reachability check: {:?}", e);
+                    "Failed to start reachability check."
+                }
+            }
+        };
+
+        let state = state_bdt_ping.lock().unwrap();
+        let html = generate_bdt_page_with_message(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
         Ok::<(), anyhow::Error>(())
     })?;
 
-    info!("Web server started successfully");
-    Ok(server)
-}
-
-/// Valid MS/TP baud rates per ASHRAE 135
-const VALID_MSTP_BAUD_RATES: [u32; 5] = [9600, 19200, 38400, 76800, 115200];
-
-/// Maximum BACnet device instance (2^22 - 2)
-const MAX_DEVICE_INSTANCE: u32 = 4194302;
-
-/// Parse URL-encoded form data with validation
-fn parse_config_form(body: &str, config: &mut GatewayConfig) {
-    for pair in body.split('&') {
-        let mut parts = pair.splitn(2, '=');
-        let key = parts.next().unwrap_or("");
-        let value = parts.next().unwrap_or("");
-        let value = urlencoding::decode(value).unwrap_or_default();
+    // Pair with a peer BACman (POST) - exchanges BDT entries and static routes in the background
+    let state_bdt_pair = Arc::clone(&state);
+    server.fn_handler("/bdt/pair", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_bdt_pair, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 256];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
 
-        match key {
-            "wifi_ssid" => {
-                // SSID max 32 characters
-                if value.len() <= 32 {
-                    config.wifi_ssid = value.to_string();
-                }
-            }
-            "wifi_pass" => {
-                // Only update if not empty (allows keeping existing password)
-                // WPA2 requires 8-63 characters
-                if !value.is_empty() && value.len() >= 8 && value.len() <= 63 {
-                    config.wifi_password = value.to_string();
-                }
-            }
-            "ap_ssid" => {
-                // SSID max 32 characters
-                if value.len() <= 32 && !value.is_empty() {
-                    config.ap_ssid = value.to_string();
-                }
-            }
-            "ap_pass" => {
-                // Only update if not empty (allows keeping existing password)
-                // WPA2 requires 8-63 characters
-                if !value.is_empty() && value.len() >= 8 && value.len() <= 63 {
-                    config.ap_password = value.to_string();
-                }
-            }
-            "mstp_addr" => {
-                // MS/TP master address: 0-127
-                if let Ok(v) = value.parse::<u8>() {
-                    if v <= 127 {
-                        config.mstp_address = v;
-                    }
-                }
-            }
-            "mstp_max" => {
-                // MS/TP max master: 0-127, must be >= mstp_address
-                if let Ok(v) = value.parse::<u8>() {
-                    if v <= 127 && v >= config.mstp_address {
-                        config.mstp_max_master = v;
-                    }
-                }
-            }
-            "mstp_baud" => {
-                // Only accept valid MS/TP baud rates
-                if let Ok(v) = value.parse::<u32>() {
-                    if VALID_MSTP_BAUD_RATES.contains(&v) {
-                        config.mstp_baud_rate = v;
+        let busy = state_bdt_pair.lock().unwrap().pairing.as_ref().is_some_and(|p| !p.stage.is_finished());
+        let peer_ip = form_value(body_str, "peer").and_then(|p| p.trim().parse::<Ipv4Addr>().ok());
+        let user = form_value(body_str, "user").unwrap_or_default();
+        let pass = form_value(body_str, "pass").unwrap_or_default();
+        let message = match peer_ip {
+            _ if busy => "Pairing already running.",
+            None => "Invalid peer IP address",
+            Some(_) if pass.is_empty() => "Enter the peer's admin password or an API key with the config scope",
+            Some(peer_ip) => {
+                // A username means portal credentials; a secret alone is taken as an API key
+                let authorization = if user.is_empty() {
+                    format!("Bearer {}", pass)
+                } else {
+                    format!("Basic {}", auth::base64_encode(format!("{}:{}", user, pass).as_bytes()))
+                };
+                match netutil::spawn_pairing(Arc::clone(&state_bdt_pair), peer_ip, authorization) {
+                    Ok(()) => "Pairing started. Refresh for progress.",
+                    Err(e) => {
+                        error!("Failed to start pairing: {:?}", e);
+                        "Failed to start pairing."
                     }
                 }
             }
-            "mstp_net" => {
-                // BACnet network number: 1-65534 (0 and 65535 reserved)
-                if let Ok(v) = value.parse::<u16>() {
-                    if v >= 1 && v <= 65534 {
-                        config.mstp_network = v;
-                    }
-                }
-            }
-            "ip_port" => {
-                // Port must be > 0
-                if let Ok(v) = value.parse::<u16>() {
-                    if v > 0 {
-                        config.bacnet_ip_port = v;
-                    }
-                }
-            }
-            "ip_net" => {
-                // BACnet network number: 1-65534 (0 and 65535 reserved)
-                if let Ok(v) = value.parse::<u16>() {
-                    if v >= 1 && v <= 65534 {
-                        config.ip_network = v;
-                    }
-                }
+        };
+
+        let state = state_bdt_pair.lock().unwrap();
+        let html = generate_bdt_page_with_message(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Pairing request from another BACman (POST) - installs a BDT entry and a
+    // static route back to it and answers with this gateway's details
+    let state_pair_api = Arc::clone(&state);
+    server.fn_handler(PAIR_API_PATH, embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_pair_api, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 256];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let answer = {
+            let mut state = lock_for_write(&state_pair_api);
+            match (local_pair_info(&state), PeerInfo::parse(body_str)) {
+                (None, _) => Err("gateway has no IP address yet"),
+                (_, None) => Err("malformed pairing request"),
+                (Some(local), Some(peer)) => check_peer(&local, &peer).map(|()| {
+                    info!("Pairing requested by {} ({})", peer.bip_address, peer.site_name);
+                    events::record(
+                        EventCategory::Peer,
+                        Severity::Info,
+                        &format!("Paired with {} (MS/TP network {}) at its request", peer.bip_address, peer.mstp_network),
+                    );
+                    state.pair_install_request = Some((peer, false));
+                    local.encode()
+                }),
             }
-            "dev_inst" => {
-                // Device instance: 0-4194302 (max per ASHRAE 135)
-                if let Ok(v) = value.parse::<u32>() {
-                    if v <= MAX_DEVICE_INSTANCE {
-                        config.device_instance = v;
-                    }
-                }
+        };
+        match answer {
+            Ok(body) => {
+                let mut resp = req.into_response(200, Some("OK"), &[("Content-Type", "application/x-www-form-urlencoded")])?;
+                resp.write_all(body.as_bytes())?;
             }
-            "dev_name" => {
-                // Device name max 64 characters
-                if value.len() <= 64 && !value.is_empty() {
-                    config.device_name = value.to_string();
-                }
+            Err(reason) => {
+                info!("Pairing request refused: {}", reason);
+                let mut resp = req.into_response(409, Some("Conflict"), &[("Content-Type", "text/plain")])?;
+                resp.write_all(reason.as_bytes())?;
             }
-            _ => {}
         }
-    }
-}
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-/// Generate status page HTML
-fn generate_status_page(state: &WebState) -> String {
-    // Convert discovered_masters bitmap to hex string
-    let masters_hex = format!("{:032x}", state.mstp_stats.discovered_masters);
+    // API endpoint to get BDT entries as JSON
+    let state_bdt_api = Arc::clone(&state);
+    server.fn_handler("/api/bdt", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_bdt_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_bdt_api);
+        let json = generate_bdt_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-    format!(r#"<!DOCTYPE html>
-<html>
-<head>
-    <title>BACman Gateway - Status</title>
-    <meta name="viewport" content="width=device-width, initial-scale=1">
-    <style>{}</style>
-    <script>
-        const STATE_NAMES = ['Init', 'Idle', 'UseToken', 'WaitReply', 'PassToken', 'NoToken', 'PollMaster', 'AnswerReq', 'DoneToken'];
+    // FDT page (GET)
+    let state_fdt = Arc::clone(&state);
+    server.fn_handler("/fdt", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_fdt, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_fdt);
+        let html = generate_fdt_page(&state, "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-        function updateDeviceGrid(hexStr, stationAddr) {{
-            const grid = document.getElementById('device-grid');
-            if (!grid) return;
+    // FDT delete entry (POST)
+    let state_fdt_remove = Arc::clone(&state);
+    server.fn_handler("/fdt/remove", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_fdt_remove, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
 
-            // Parse hex string to BigInt
-            let bitmap = BigInt('0x' + hexStr);
+        let mut state = lock_for_write(&state_fdt_remove);
+        let message = match form_value(body_str, "addr").and_then(|a| a.parse::<SocketAddr>().ok()) {
+            Some(addr) => {
+                state.fdt_remove_request = Some(addr);
+                info!("FDT delete requested via web portal: {}", addr);
+                "Foreign device delete requested. Entry will be removed."
+            }
+            None => "Invalid address format (expected IP:port)",
+        };
 
-            for (let i = 0; i < 128; i++) {{
-                const cell = document.getElementById('dev-' + i);
-                if (cell) {{
-                    const isPresent = (bitmap >> BigInt(i)) & BigInt(1);
-                    cell.className = 'grid-cell';
-                    if (i === stationAddr) {{
-                        cell.className += ' self';
-                    }} else if (isPresent) {{
-                        cell.className += ' active';
-                    }}
-                }}
-            }}
-        }}
+        let html = generate_fdt_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-        function updateStatus() {{
-            fetch('/api/status')
-                .then(r => r.json())
-                .then(data => {{
-                    // Frame counters
-                    document.getElementById('rx_frames').textContent = data.rx_frames;
-                    document.getElementById('tx_frames').textContent = data.tx_frames;
-                    document.getElementById('tokens_received').textContent = data.tokens_received;
+    // Accept or reject foreign device registrations (POST)
+    let state_fdt_accept = Arc::clone(&state);
+    server.fn_handler("/fdt/registrations", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_fdt_accept, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
 
-                    // Error counters with highlighting
-                    const crcEl = document.getElementById('crc_errors');
-                    crcEl.textContent = data.crc_errors;
-                    crcEl.className = data.crc_errors > 0 ? 'value error' : 'value';
+        let mut state = lock_for_write(&state_fdt_accept);
+        let message = match form_value(body_str, "enabled").as_deref() {
+            Some("1") => {
+                state.fd_registrations_request = Some(true);
+                info!("Foreign device registrations accepted via web portal");
+                "Foreign device registrations accepted."
+            }
+            Some("0") => {
+                state.fd_registrations_request = Some(false);
+                info!("Foreign device registrations rejected via web portal");
+                "Foreign device registrations rejected. Registered devices stay until their TTL runs out."
+            }
+            _ => "Invalid setting",
+        };
 
-                    const frameErrEl = document.getElementById('frame_errors');
-                    frameErrEl.textContent = data.frame_errors;
-                    frameErrEl.className = data.frame_errors > 0 ? 'value error' : 'value';
+        let html = generate_fdt_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    const replyTOEl = document.getElementById('reply_timeouts');
-                    replyTOEl.textContent = data.reply_timeouts;
-                    replyTOEl.className = data.reply_timeouts > 0 ? 'value error' : 'value';
+    // API endpoint to get FDT entries as JSON
+    let state_fdt_api = Arc::clone(&state);
+    server.fn_handler("/api/fdt", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_fdt_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_fdt_api);
+        let json = generate_fdt_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    const passFailEl = document.getElementById('token_pass_failures');
-                    passFailEl.textContent = data.token_pass_failures;
-                    passFailEl.className = data.token_pass_failures > 0 ? 'value error' : 'value';
+    // Event timeline page (GET, optional `?category=wifi&severity=warning`)
+    let state_events = Arc::clone(&state);
+    server.fn_handler("/events", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_events, Role::Viewer)? else { return Ok(()) };
+        let filter = EventFilter::from_uri(req.uri());
+        let html = web_budget::run("/events", || generate_events_page(&snapshot(&state_events), &filter));
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    // Token loop timing
-                    document.getElementById('token_loop').textContent = data.token_loop_ms + ' ms';
-                    document.getElementById('token_loop_min').textContent = data.token_loop_min_ms + ' ms';
-                    document.getElementById('token_loop_max').textContent = data.token_loop_max_ms + ' ms';
-                    document.getElementById('token_loop_avg').textContent = data.token_loop_avg_ms + ' ms';
+    // Crash report page: the blackbox recording of the last unexpected reset
+    let state_crash = Arc::clone(&state);
+    server.fn_handler("/crash", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_crash, Role::Viewer)? else { return Ok(()) };
+        let html = generate_crash_page(&snapshot(&state_crash), "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    // State machine
-                    document.getElementById('masters').textContent = data.master_count;
-                    document.getElementById('state').textContent = STATE_NAMES[data.current_state] || 'Unknown';
-                    document.getElementById('next_station').textContent = data.next_station;
-                    document.getElementById('poll_station').textContent = data.poll_station;
+    // Forget the crash report (POST)
+    let state_crash_clear = Arc::clone(&state);
+    server.fn_handler("/crash/clear", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_crash_clear, Role::Admin)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_crash_clear);
+        let message = match state.nvs_partition.clone().map(CrashReportPersistence::clear) {
+            Some(Ok(())) => {
+                state.crash_report = None;
+                info!("Crash report cleared via web portal");
+                "Crash report cleared."
+            }
+            Some(Err(e)) => {
+                error!("Failed to clear crash report: {}", e);
+                "Crash report could not be removed from NVS!"
+            }
+            None => "NVS is not available",
+        };
 
-                    const silenceEl = document.getElementById('silence');
-                    silenceEl.textContent = data.silence_ms + ' ms';
-                    silenceEl.className = data.silence_ms > 500 ? 'value warning' : 'value';
+        let html = generate_crash_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    const soleMasterEl = document.getElementById('sole_master');
-                    soleMasterEl.textContent = data.sole_master ? 'Yes' : 'No';
-                    soleMasterEl.className = data.sole_master ? 'value warning' : 'value';
+    // API endpoint for the event log (`?since=SEQ` returns only newer events)
+    let state_events_api = Arc::clone(&state);
+    server.fn_handler("/api/events", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_events_api, Role::Viewer)? else { return Ok(()) };
+        let filter = EventFilter::from_uri(req.uri());
+        let state = snapshot(&state_events_api);
+        let json = generate_events_json(&state, &filter);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    // Queue depths
-                    document.getElementById('send_queue').textContent = data.send_queue_len;
-                    document.getElementById('receive_queue').textContent = data.receive_queue_len;
+    // API endpoint for the unified error log
+    let state_errors_api = Arc::clone(&state);
+    server.fn_handler("/api/errors", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_errors_api, Role::Viewer)? else { return Ok(()) };
+        let json = generate_errors_json();
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    // Gateway stats
-                    document.getElementById('mstp_to_ip').textContent = data.mstp_to_ip;
-                    document.getElementById('ip_to_mstp').textContent = data.ip_to_mstp;
+    // Address bindings page (GET)
+    let state_bindings = Arc::clone(&state);
+    server.fn_handler("/bindings", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_bindings, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_bindings);
+        let html = generate_bindings_page(&state, "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
 
-                    // Uptime
-                    document.getElementById('uptime').textContent = data.uptime;
+    // Pin, unpin, delete or flush address bindings (POST)
+    let state_bindings_edit = Arc::clone(&state);
+    server.fn_handler("/bindings/edit", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_bindings_edit, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
 
-                    // Device count chip
-                    document.getElementById('device-count').textContent = data.master_count + ' found';
+        let mut state = lock_for_write(&state_bindings_edit);
+        let message = match parse_binding_edit(body_str) {
+            Some(edit) => {
+                state.binding_edit_request = Some(edit);
+                info!("Address binding edit requested via web portal: {:?}", edit);
+                "Binding change requested. The tables update within a second."
+            }
+            None => "Invalid binding request",
+        };
 
-                    updateDeviceGrid(data.discovered_masters, data.station_address);
-                }})
-                .catch(e => console.error('Update failed:', e));
-        }}
-        function resetStats() {{
-            fetch('/api/reset-stats', {{ method: 'POST' }})
-                .then(r => r.json())
-                .then(data => {{ if(data.status === 'ok') updateStatus(); }})
-                .catch(e => console.error('Reset failed:', e));
-        }}
-        function exportData() {{
-            window.location.href = '/api/export';
-        }}
+        let html = generate_bindings_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the address bindings as JSON
+    let state_bindings_api = Arc::clone(&state);
+    server.fn_handler("/api/bindings", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_bindings_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_bindings_api);
+        let json = generate_bindings_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to pin, unpin, delete or flush address bindings
+    let state_bindings_api_edit = Arc::clone(&state);
+    server.fn_handler("/api/bindings", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_bindings_api_edit, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let (status, reason, json) = match parse_binding_edit(body_str) {
+            Some(edit) => {
+                lock_for_write(&state_bindings_api_edit).binding_edit_request = Some(edit);
+                info!("Address binding edit requested via API: {:?}", edit);
+                (202, "Accepted", r#"{"status":"requested"}"#)
+            }
+            None => (
+                400,
+                "Bad Request",
+                r#"{"status":"error","message":"action must be pin, unpin, remove or flush; table mstp or ip with its key"}"#,
+            ),
+        };
+        let mut resp = req.into_response(status, Some(reason), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Static routes page (GET)
+    let state_routes = Arc::clone(&state);
+    server.fn_handler("/routes", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_routes, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_routes);
+        let html = generate_routes_page(&state, "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Static route add (POST)
+    let state_routes_add = Arc::clone(&state);
+    server.fn_handler("/routes/add", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_routes_add, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 256];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_routes_add);
+        let message = parse_route_add_form(body_str, &mut state);
+
+        let html = generate_routes_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Static route remove (POST)
+    let state_routes_remove = Arc::clone(&state);
+    server.fn_handler("/routes/remove", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_routes_remove, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_routes_remove);
+        let message = match form_value(body_str, "network").and_then(|v| v.parse::<u16>().ok()) {
+            Some(network) => {
+                state.static_route_remove_request = Some(network);
+                info!("Static route remove requested via web portal: network {}", network);
+                "Static route remove requested. Route will be removed."
+            }
+            None => "Invalid network number",
+        };
+
+        let html = generate_routes_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get static routes as JSON
+    let state_routes_api = Arc::clone(&state);
+    server.fn_handler("/api/routes", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_routes_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_routes_api);
+        let json = generate_routes_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // MS/TP wiring test page (GET)
+    let state_test = Arc::clone(&state);
+    server.fn_handler("/test", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_test, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_test);
+        let html = generate_loopback_page(&state, "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // MS/TP wiring test start (POST)
+    let state_test_start = Arc::clone(&state);
+    server.fn_handler("/test/start", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_test_start, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_test_start);
+        let message = parse_loopback_form(body_str, &mut state);
+
+        let html = generate_loopback_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // MS/TP wiring test stop (POST)
+    let state_test_stop = Arc::clone(&state);
+    server.fn_handler("/test/stop", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_test_stop, Role::Admin)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_test_stop);
+        state.loopback_stop_requested = true;
+        info!("Wiring test stop requested via web portal");
+
+        let html = generate_loopback_page(&state, "Test stop requested.");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get wiring test result as JSON
+    let state_test_api = Arc::clone(&state);
+    server.fn_handler("/api/loopback", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_test_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_test_api);
+        let json = generate_loopback_json(&state.loopback_result);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Commissioning wizard (GET) - resumes at the saved step
+    let state_wizard = Arc::clone(&state);
+    server.fn_handler("/wizard", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_wizard, Role::Admin)? else { return Ok(()) };
+        let state = snapshot(&state_wizard);
+        let step = state.config.commissioning_step.clamp(1, WIZARD_LAST_STEP);
+        let html = generate_wizard_page(&state, step, "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Commissioning wizard step submit (POST)
+    let state_wizard_post = Arc::clone(&state);
+    server.fn_handler("/wizard", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_wizard_post, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 1024];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_wizard_post);
+        match handle_wizard_form(body_str, &mut state) {
+            Some((step, message)) => {
+                let html = generate_wizard_page(&state, step, &message);
+                let mut resp = req.into_ok_response()?;
+                resp.write_all(html.as_bytes())?;
+            }
+            None => {
+                // The wizard's configuration save must reach flash first
+                if let Some(ref writer) = state.nvs_writer {
+                    if !writer.flush(nvs_writer::FLUSH_TIMEOUT) {
+                        error!("Queued NVS writes did not finish before reboot");
+                    }
+                }
+                let mut resp = req.into_ok_response()?;
+                resp.write_all(HTML_REBOOT_PAGE.as_bytes())?;
+
+                // Reboot onto the site network once the response is sent
+                std::thread::spawn(|| {
+                    std::thread::sleep(std::time::Duration::from_secs(2));
+                    // SAFETY: esp_restart() is always safe to call on ESP32 - it performs a
+                    // software reset. The 2-second delay ensures the HTTP response is sent.
+                    unsafe { esp_idf_svc::sys::esp_restart(); }
+                });
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint for the wizard's live MS/TP address check
+    let state_wizard_check = Arc::clone(&state);
+    server.fn_handler("/api/wizard/check-address", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_wizard_check, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+        let addr = form_value(body_str, "addr").and_then(|v| v.parse::<u8>().ok()).unwrap_or(255);
+
+        let state = state_wizard_check.lock().unwrap();
+        let json = generate_address_check_json(&state, addr);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Device labels page (GET)
+    let state_labels = Arc::clone(&state);
+    server.fn_handler("/devices", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_labels, Role::Viewer)? else { return Ok(()) };
+        let html = web_budget::run("/devices", || generate_device_labels_page(&snapshot(&state_labels), ""));
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Add, update or remove one device label (POST)
+    let state_label_set = Arc::clone(&state);
+    server.fn_handler("/devices/label", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_label_set, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 512];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_label_set);
+        let message = parse_device_label_form(body_str, &mut state);
+
+        let html = generate_device_labels_page(&state, &message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Bulk label import, one "instance,name,notes" line per device (POST)
+    let state_label_import = Arc::clone(&state);
+    server.fn_handler("/devices/import", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_label_import, Role::Admin)? else { return Ok(()) };
+        // Large enough for a full label table; the body may arrive in several reads
+        let mut body = vec![0u8; 8192];
+        let mut len = 0;
+        while len < body.len() {
+            match req.read(&mut body[len..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+        }
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_label_import);
+        let message = parse_device_import_form(body_str, &mut state);
+
+        let html = generate_device_labels_page(&state, &message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Read every device name again
+    let state_names = Arc::clone(&state);
+    server.fn_handler("/devices/names", embedded_svc::http::Method::Post, move |req| {
+        let Some(req) = authorize(req, &state_names, Role::Viewer)? else { return Ok(()) };
+        let mut state = lock_for_write(&state_names);
+        state.device_names_refresh_requested = true;
+        info!("Device name refresh requested via web portal");
+
+        let html = generate_device_labels_page(&state, "Device names will be read again in the background.");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint for device names by instance
+    let state_names_api = Arc::clone(&state);
+    server.fn_handler("/api/names", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_names_api, Role::Viewer)? else { return Ok(()) };
+        let json = generate_device_names_json(&snapshot(&state_names_api));
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Start or cancel a site inventory (POST action=start|cancel)
+    let state_inventory = Arc::clone(&state);
+    server.fn_handler("/devices/inventory", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_inventory, Role::Viewer)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_inventory);
+        let message = parse_inventory_form(body_str, &mut state);
+
+        let html = generate_device_labels_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint for site inventory progress
+    let state_inventory_api = Arc::clone(&state);
+    server.fn_handler("/api/inventory", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_inventory_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_inventory_api);
+        let json = generate_inventory_status_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Site inventory downloads
+    let state_inventory_json = Arc::clone(&state);
+    server.fn_handler("/inventory.json", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_inventory_json, Role::Viewer)? else { return Ok(()) };
+        let json = generate_inventory_json(&snapshot(&state_inventory_json).inventory_report);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Content-Disposition", "attachment; filename=\"bacman-inventory.json\""),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    let state_inventory_csv = Arc::clone(&state);
+    server.fn_handler("/inventory.csv", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_inventory_csv, Role::Viewer)? else { return Ok(()) };
+        let csv = generate_inventory_csv(&snapshot(&state_inventory_csv).inventory_report);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "text/csv"),
+            ("Content-Disposition", "attachment; filename=\"bacman-inventory.csv\""),
+        ])?;
+        resp.write_all(csv.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Diagnostics page (GET)
+    let state_diag = Arc::clone(&state);
+    server.fn_handler("/diagnostics", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_diag, Role::Viewer)? else { return Ok(()) };
+        let html = web_budget::run("/diagnostics", || generate_diagnostics_page(&snapshot(&state_diag), ""));
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Protocol analyzer page (GET)
+    let state_analyzer = Arc::clone(&state);
+    server.fn_handler("/analyzer", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_analyzer, Role::Viewer)? else { return Ok(()) };
+        let html = generate_analyzer_page(&snapshot(&state_analyzer), "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Switch protocol analysis on or off (POST)
+    let state_analyzer_set = Arc::clone(&state);
+    server.fn_handler("/analyzer", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_analyzer_set, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_analyzer_set);
+        let message = match form_value(body_str, "enabled").as_deref() {
+            Some("1") => {
+                state.analyzer_request = Some(true);
+                info!("Protocol analysis enable requested via web portal");
+                "Protocol analysis starting. Counters fill in as frames are routed."
+            }
+            Some("0") => {
+                state.analyzer_request = Some(false);
+                info!("Protocol analysis disable requested via web portal");
+                "Protocol analysis stopping. Its counters are discarded."
+            }
+            _ => "Invalid analyzer request",
+        };
+
+        let html = generate_analyzer_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the protocol analyzer counters as JSON
+    let state_analyzer_api = Arc::clone(&state);
+    server.fn_handler("/api/analyzer", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_analyzer_api, Role::Viewer)? else { return Ok(()) };
+        let json = generate_analyzer_json(&snapshot(&state_analyzer_api));
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to switch protocol analysis on or off
+    let state_analyzer_api_set = Arc::clone(&state);
+    server.fn_handler("/api/analyzer", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_analyzer_api_set, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let (status, reason, json) = match form_value(body_str, "enabled").as_deref() {
+            Some(value @ ("1" | "0")) => {
+                lock_for_write(&state_analyzer_api_set).analyzer_request = Some(value == "1");
+                info!("Protocol analysis {} requested via API", if value == "1" { "enable" } else { "disable" });
+                (202, "Accepted", r#"{"status":"requested"}"#)
+            }
+            _ => (400, "Bad Request", r#"{"status":"error","message":"enabled must be 1 or 0"}"#),
+        };
+        let mut resp = req.into_response(status, Some(reason), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the redundancy pair state as JSON
+    let state_failover_api = Arc::clone(&state);
+    server.fn_handler("/api/failover", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_failover_api, Role::Viewer)? else { return Ok(()) };
+        let json = generate_failover_json(&snapshot(&state_failover_api));
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Abort a stuck transaction (POST)
+    let state_tx_abort = Arc::clone(&state);
+    server.fn_handler("/transactions/abort", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_tx_abort, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_tx_abort);
+        let invoke_id = form_value(body_str, "invoke_id").and_then(|v| v.parse::<u8>().ok());
+        let dest_mac = form_value(body_str, "dest_mac").and_then(|v| v.parse::<u8>().ok());
+        let message = match (invoke_id, dest_mac) {
+            (Some(invoke_id), Some(dest_mac)) => {
+                state.transaction_abort_request = Some((invoke_id, dest_mac));
+                info!("Transaction abort requested via web portal: invoke_id={} dest={}", invoke_id, dest_mac);
+                "Abort requested. The client will be sent an Abort PDU."
+            }
+            _ => "Invalid transaction",
+        };
+
+        let html = generate_diagnostics_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Enable or disable a port for maintenance (POST)
+    let state_port = Arc::clone(&state);
+    server.fn_handler("/ports", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_port, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_port);
+        let enabled = match form_value(body_str, "enabled").as_deref() {
+            Some("1") => Some(true),
+            Some("0") => Some(false),
+            _ => None,
+        };
+        let message = match (form_value(body_str, "port").as_deref(), enabled) {
+            (Some("mstp"), Some(enabled)) => {
+                state.mstp_port_enable_request = Some(enabled);
+                info!("MS/TP port {} via web portal", if enabled { "enabled" } else { "disabled" });
+                if enabled { "MS/TP port enabled." } else { "MS/TP port disabled. Traffic to and from MS/TP is no longer routed." }
+            }
+            (Some("ip"), Some(enabled)) => {
+                state.ip_port_enable_request = Some(enabled);
+                info!("IP port {} via web portal", if enabled { "enabled" } else { "disabled" });
+                if enabled { "IP port enabled." } else { "IP port disabled. Traffic to and from B/IP is no longer routed." }
+            }
+            _ => "Invalid port",
+        };
+
+        let html = generate_diagnostics_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Apply the recommended Max_Master (POST)
+    let state_max_master = Arc::clone(&state);
+    server.fn_handler("/diagnostics/max-master", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_max_master, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_max_master);
+        let value = form_value(body_str, "max_master").and_then(|v| v.parse::<u8>().ok());
+        // Only the value currently recommended is accepted, so a stale page can't apply an old one
+        let message = match (value, max_master_advice(&state)) {
+            (Some(value), Some(advice)) if value == advice.recommended => {
+                state.config.mstp_max_master = value;
+                state.max_master_request = Some(value);
+                info!("Max_Master lowered to {} via web portal", value);
+                events::record(EventCategory::Config, Severity::Info, &format!("Max_Master lowered to {}", value));
+                if state.queue_nvs_write(NvsWrite::Config(Box::new(state.config.clone()))) {
+                    "Max_Master applied and saved."
+                } else {
+                    "Max_Master applied but NVS is not available"
+                }
+            }
+            _ => "The recommendation has changed. Review it and try again.",
+        };
+
+        let html = generate_diagnostics_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Query a remote router's routing table (POST)
+    let state_router_query = Arc::clone(&state);
+    server.fn_handler("/diagnostics/router-query", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_router_query, Role::Viewer)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_router_query);
+        let kind = form_value(body_str, "kind").and_then(|v| RouterQueryKind::parse(&v));
+        let target = form_value(body_str, "target").and_then(|v| {
+            v.parse::<SocketAddr>()
+                .ok()
+                .or_else(|| v.parse::<Ipv4Addr>().ok().map(|ip| SocketAddr::new(std::net::IpAddr::V4(ip), 47808)))
+        });
+        let message = match (target, kind) {
+            (Some(target), Some(kind)) => {
+                state.router_query_request = Some((target, kind));
+                info!("Router query via web portal: {} to {}", kind, target);
+                "Query sent. Answers are collected for a few seconds."
+            }
+            (None, _) => "Invalid router address",
+            (_, None) => "Invalid query",
+        };
+
+        let html = generate_diagnostics_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint for the last router query and its answers
+    let state_router_query_api = Arc::clone(&state);
+    server.fn_handler("/api/router-query", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_router_query_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_router_query_api);
+        let json = generate_router_query_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get active transactions and reassemblies as JSON
+    let state_tx_api = Arc::clone(&state);
+    server.fn_handler("/api/transactions", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_tx_api, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_tx_api);
+        let json = generate_transactions_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the top talkers as JSON
+    let state_talkers = Arc::clone(&state);
+    server.fn_handler("/api/talkers", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_talkers, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_talkers);
+        let json = generate_talkers_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the per-peer IP statistics as JSON
+    let state_peers = Arc::clone(&state);
+    server.fn_handler("/api/peers", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_peers, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_peers);
+        let json = generate_peers_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the per-device response statistics (SLA report) as JSON
+    let state_sla = Arc::clone(&state);
+    server.fn_handler("/api/sla", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_sla, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_sla);
+        let json = generate_sla_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // MS/TP capacity page: line utilization and what more devices would add
+    let state_capacity = Arc::clone(&state);
+    server.fn_handler("/capacity", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_capacity, Role::Viewer)? else { return Ok(()) };
+        let html = generate_capacity_page(&snapshot(&state_capacity));
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the MS/TP capacity estimate as JSON
+    let state_capacity_api = Arc::clone(&state);
+    server.fn_handler("/api/capacity", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_capacity_api, Role::Viewer)? else { return Ok(()) };
+        let json = generate_capacity_json(&snapshot(&state_capacity_api));
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to get the per-service request counters and error histogram as JSON
+    let state_service_errors = Arc::clone(&state);
+    server.fn_handler("/api/service-errors", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_service_errors, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_service_errors);
+        let json = generate_service_errors_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to list the stations on the configuration hotspot
+    let state_ap_clients = Arc::clone(&state);
+    server.fn_handler("/api/ap/clients", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_ap_clients, Role::Viewer)? else { return Ok(()) };
+        let state = snapshot(&state_ap_clients);
+        let json = generate_ap_clients_json(&state);
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // API endpoint to deauthenticate a hotspot client (body: mac=AA:BB:CC:DD:EE:FF)
+    let state_ap_kick = Arc::clone(&state);
+    server.fn_handler("/api/ap/clients/kick", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_ap_kick, Role::Admin)? else { return Ok(()) };
+        let mut body = [0u8; 64];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let json = match form_value(body_str, "mac").and_then(|v| ap_clients::parse_mac(&v)) {
+            None => r#"{"status":"error","message":"mac must be AA:BB:CC:DD:EE:FF"}"#.to_string(),
+            Some(mac) => match ap_clients::kick(&mac) {
+                Ok(()) => {
+                    lock_for_write(&state_ap_kick).ap_clients.retain(|c| c.mac != mac);
+                    events::record(
+                        EventCategory::Wifi,
+                        Severity::Info,
+                        &format!("Hotspot client {} kicked via web portal", ap_clients::format_mac(&mac)),
+                    );
+                    r#"{"status":"ok","message":"Client disconnected"}"#.to_string()
+                }
+                Err(e) => format!(r#"{{"status":"error","message":"{}"}}"#, json_escape(&e.to_string())),
+            },
+        };
+        let mut resp = req.into_response(200, Some("OK"), &[
+            ("Content-Type", "application/json"),
+            ("Access-Control-Allow-Origin", "*"),
+        ])?;
+        resp.write_all(json.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Portal account management page (GET)
+    let state_users = Arc::clone(&state);
+    server.fn_handler("/users", embedded_svc::http::Method::Get, move |req| {
+        let Some(req) = authorize(req, &state_users, Role::Admin)? else { return Ok(()) };
+        let state = snapshot(&state_users);
+        let html = generate_users_page(&state, "");
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Add or update a portal account (POST)
+    let state_users_add = Arc::clone(&state);
+    server.fn_handler("/users/add", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_users_add, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 256];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_users_add);
+        let message = parse_user_add_form(body_str, &mut state);
+
+        let html = generate_users_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Remove a portal account (POST)
+    let state_users_remove = Arc::clone(&state);
+    server.fn_handler("/users/remove", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_users_remove, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_users_remove);
+        let message = match form_value(body_str, "username") {
+            Some(username) => remove_user(&mut state, &username),
+            None => "Invalid username",
+        };
+
+        let html = generate_users_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Create an API key (POST); the key is shown once in the page message
+    let state_keys_add = Arc::clone(&state);
+    server.fn_handler("/users/keys/add", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_keys_add, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_keys_add);
+        let message = parse_api_key_form(body_str, &mut state);
+
+        let html = generate_users_page(&state, &message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    // Revoke an API key (POST)
+    let state_keys_remove = Arc::clone(&state);
+    server.fn_handler("/users/keys/remove", embedded_svc::http::Method::Post, move |req| {
+        let Some(mut req) = authorize(req, &state_keys_remove, Role::Admin)? else { return Ok(()) };
+        let Some(mut req) = refuse_if_write_protected(req)? else { return Ok(()) };
+        let mut body = [0u8; 128];
+        let len = req.read(&mut body).unwrap_or(0);
+        let body_str = std::str::from_utf8(&body[..len]).unwrap_or("");
+
+        let mut state = lock_for_write(&state_keys_remove);
+        let message = match form_value(body_str, "name") {
+            Some(name) => remove_api_key(&mut state, &name),
+            None => "Invalid key name",
+        };
+
+        let html = generate_users_page(&state, message);
+        let mut resp = req.into_ok_response()?;
+        resp.write_all(html.as_bytes())?;
+        Ok::<(), anyhow::Error>(())
+    })?;
+
+    info!("Web server started successfully");
+    Ok(server)
+}
+
+/// Check HTTP Basic credentials against the portal accounts, or an API key
+/// (`Authorization: Bearer`) on /api endpoints against the scope the request needs.
+/// Returns the request when access is granted; otherwise answers it with
+/// 401 (no or bad credentials) or 403 (role or scope too low) and returns None.
+/// A presented API key is checked even while no accounts exist, so a revoked
+/// or mistyped key never gets through the open portal.
+fn authorize<'r, 'c>(
+    req: Request<&'r mut EspHttpConnection<'c>>,
+    state: &Mutex<WebState>,
+    required: Role,
+) -> anyhow::Result<Option<Request<&'r mut EspHttpConnection<'c>>>> {
+    let supplied = req.header("Authorization").is_some();
+    let api_secret = req.header("Authorization")
+        .and_then(auth::parse_bearer_token)
+        .filter(|_| req.uri().starts_with("/api/"))
+        .map(str::to_string);
+    let credentials = {
+        let state = state.lock().unwrap();
+        if let Some(secret) = api_secret {
+            // A single SHA-256 per key, cheap enough to check under the lock
+            let key = state.api_keys.iter().find(|k| k.verify(&secret)).cloned();
+            drop(state);
+            return authorize_api_key(req, key, required);
+        }
+        if state.users.is_empty() {
+            return Ok(Some(req));
+        }
+        let credentials = req.header("Authorization")
+            .and_then(parse_basic_auth)
+            .and_then(|(username, password)| {
+                state.users.iter().find(|u| u.username == username).cloned().map(|u| (u, password))
+            });
+        match credentials {
+            Some((user, password)) if state.login_cache.contains(&user, &password, Instant::now()) => {
+                drop(state);
+                return authorize_role(req, &user, required);
+            }
+            other => other,
+        }
+    };
+
+    // Hash outside the lock so other handlers are not held up
+    let verified = match credentials {
+        Some((user, password)) => match user.verify(&password) {
+            Ok(true) => Some((user, password)),
+            Ok(false) => None,
+            Err(e) => {
+                error!("Login check for '{}' failed: {}", user.username, e);
+                let mut resp = req.into_response(500, Some("Internal Server Error"), &[("Content-Type", "text/plain")])?;
+                resp.write_all(b"Password check failed, try again")?;
+                return Ok(None);
+            }
+        },
+        None => None,
+    };
+    match verified {
+        Some((user, password)) => {
+            let mut state = state.lock().unwrap();
+            state.login_cache.insert(&user, &password, Instant::now());
+            drop(state);
+            authorize_role(req, &user, required)
+        }
+        None => {
+            // A browser's first request carries no credentials; only count failed attempts
+            if supplied {
+                errors::record(ErrorSource::Web, ErrorKind::Unauthorized, &format!("bad credentials for {}", req.uri()));
+            }
+            let mut resp = req.into_response(401, Some("Unauthorized"), &[
+                ("WWW-Authenticate", "Basic realm=\"BACman Gateway\""),
+                ("Content-Type", "text/plain"),
+            ])?;
+            resp.write_all(b"Authentication required")?;
+            Ok(None)
+        }
+    }
+}
+
+/// Let a verified account through if its role allows `required`, else answer 403
+fn authorize_role<'r, 'c>(
+    req: Request<&'r mut EspHttpConnection<'c>>,
+    user: &UserAccount,
+    required: Role,
+) -> anyhow::Result<Option<Request<&'r mut EspHttpConnection<'c>>>> {
+    if user.role.allows(required) {
+        return Ok(Some(req));
+    }
+    errors::record(ErrorSource::Web, ErrorKind::Unauthorized, &format!("{} lacks access to {}", user.username, req.uri()));
+    let mut resp = req.into_response(403, Some("Forbidden"), &[("Content-Type", "text/plain")])?;
+    resp.write_all(b"This account has read-only access")?;
+    Ok(None)
+}
+
+/// Answer a configuration change with 423 while the write-protect jumper is fitted
+fn refuse_if_write_protected<'r, 'c>(
+    req: Request<&'r mut EspHttpConnection<'c>>,
+) -> anyhow::Result<Option<Request<&'r mut EspHttpConnection<'c>>>> {
+    if !write_protect::is_locked() {
+        return Ok(Some(req));
+    }
+    info!("Refused {} - configuration is write-protected", req.uri());
+    let mut resp = req.into_response(423, Some("Locked"), &[("Content-Type", "text/plain")])?;
+    resp.write_all(write_protect::LOCKED_MESSAGE.as_bytes())?;
+    Ok(None)
+}
+
+/// Admit an /api request that presented an API key, or answer it with 401/403
+fn authorize_api_key<'r, 'c>(
+    req: Request<&'r mut EspHttpConnection<'c>>,
+    key: Option<ApiKey>,
+    required: Role,
+) -> anyhow::Result<Option<Request<&'r mut EspHttpConnection<'c>>>> {
+    let path = req.uri().split('?').next().unwrap_or("");
+    let scope = ApiScope::required(req.method() == embedded_svc::http::Method::Get, path, required);
+    match key {
+        Some(key) if key.scopes.allows(scope) => Ok(Some(req)),
+        Some(key) => {
+            errors::record(
+                ErrorSource::Web,
+                ErrorKind::Unauthorized,
+                &format!("API key {} lacks the {} scope for {}", key.name, scope.as_str(), req.uri()),
+            );
+            let mut resp = req.into_response(403, Some("Forbidden"), &[("Content-Type", "text/plain")])?;
+            resp.write_all(format!("This API key lacks the {} scope", scope.as_str()).as_bytes())?;
+            Ok(None)
+        }
+        None => {
+            errors::record(ErrorSource::Web, ErrorKind::Unauthorized, &format!("bad API key for {}", req.uri()));
+            let mut resp = req.into_response(401, Some("Unauthorized"), &[
+                ("WWW-Authenticate", "Bearer realm=\"BACman Gateway\""),
+                ("Content-Type", "text/plain"),
+            ])?;
+            resp.write_all(b"Invalid API key")?;
+            Ok(None)
+        }
+    }
+}
+
+/// Maximum BACnet device instance (2^22 - 2)
+const MAX_DEVICE_INSTANCE: u32 = 4194302;
+
+/// Device instances heard on the network, for the uniqueness check
+fn discovered_instances(state: &WebState) -> Vec<u32> {
+    state.discovered_devices.iter().map(|d| d.device_instance).collect()
+}
+
+/// Parse a form into a copy of the current configuration and check the result
+/// as a whole. The copy is only worth applying if no issue is an error.
+pub fn validate_config_form(body: &str, state: &WebState) -> (GatewayConfig, Vec<ConfigIssue>) {
+    let mut candidate = state.config.clone();
+    let mut issues = parse_config_form(body, &mut candidate);
+    issues.extend(config_check::check(&candidate, &discovered_instances(state)));
+    (candidate, issues)
+}
+
+/// Render validation issues as a list under a page message
+fn config_issues_html(issues: &[ConfigIssue]) -> String {
+    if issues.is_empty() {
+        return String::new();
+    }
+    let items: String = issues
+        .iter()
+        .map(|i| {
+            let label = if i.is_error() { "Error" } else { "Warning" };
+            format!("<li><b>{}</b> ({}): {}</li>", label, html_escape(&i.field), html_escape(&i.message))
+        })
+        .collect();
+    format!("<ul>{}</ul>", items)
+}
+
+/// Check a schedule form: the time and rollback window first, then the
+/// settings it changes against the current configuration
+fn validate_schedule_form(body: &str, state: &WebState) -> Result<ScheduledChange, Vec<ConfigIssue>> {
+    let Some(now) = clock::utc_secs() else {
+        return Err(vec![ConfigIssue::error("at", "clock not synced yet")]);
+    };
+    if state.scheduled_change.as_ref().is_some_and(|c| c.stage == ScheduleStage::Applied) {
+        return Err(vec![ConfigIssue::error("at", "the last change is still being watched")]);
+    }
+    let change = ScheduledChange::from_form(body, now).map_err(|e| vec![ConfigIssue::error("at", e)])?;
+    let (_, issues) = validate_config_form(&change.change, state);
+    if config_check::has_errors(&issues) {
+        return Err(issues);
+    }
+    Ok(change)
+}
+
+/// The config page's schedule card as a schedule form: unchanged (empty)
+/// fields dropped and the free-form settings spliced in
+fn schedule_page_form(body: &str) -> String {
+    let mut fields = Vec::new();
+    for pair in body.split('&') {
+        match pair.split_once('=') {
+            Some(("other", value)) => {
+                let other = urlencoding::decode(&value.replace('+', "%20")).unwrap_or_default().into_owned();
+                fields.extend(other.split('&').map(str::trim).filter(|p| !p.is_empty()).map(str::to_string));
+            }
+            Some((_, "")) | None => {}
+            Some(_) => fields.push(pair.to_string()),
+        }
+    }
+    fields.join("&")
+}
+
+/// When a scheduled change falls due, as UTC
+fn schedule_time(change: &ScheduledChange) -> String {
+    let at = std::time::UNIX_EPOCH + std::time::Duration::from_secs(change.apply_at);
+    clock::format_utc(at)
+}
+
+/// Generate the scheduled change JSON
+fn generate_schedule_json(state: &WebState) -> String {
+    let Some(change) = &state.scheduled_change else {
+        return r#"{"scheduled":false}"#.to_string();
+    };
+    let fields: Vec<String> = change.fields().iter().map(|f| format!(r#""{}""#, json_escape(f))).collect();
+    format!(
+        r#"{{"scheduled":true,"stage":"{}","apply_at":"{}","starts_in":{},"window_minutes":{},"rollback_in":{},"fields":[{}]}}"#,
+        change.stage.as_str(),
+        schedule_time(change),
+        clock::utc_secs().map(|now| change.apply_at.saturating_sub(now).to_string()).unwrap_or_else(|| "null".to_string()),
+        change.window_minutes,
+        state.schedule_rollback_in.map(|s| s.to_string()).unwrap_or_else(|| "null".to_string()),
+        fields.join(",")
+    )
+}
+
+/// Scheduled change card for the config page: the pending or watched
+/// change, or the form to schedule one
+fn schedule_card_html(state: &WebState) -> String {
+    let body = match &state.scheduled_change {
+        Some(change) if change.stage == ScheduleStage::Applied => format!(
+            "<p>Applied {}. Rolled back in {} unless the MS/TP ring stays up for {}s.</p>",
+            html_escape(&change.summary()),
+            schedule::format_countdown(state.schedule_rollback_in.unwrap_or(0)),
+            RING_STABLE_HOLD.as_secs()
+        ),
+        Some(change) => format!(
+            r#"<p>{} at {} UTC (in {}), rolled back if the ring is not stable within {} min.</p>
+            <form method="POST" action="/config/schedule/cancel" onsubmit="return confirm('Cancel the scheduled change?')">
+                <button type="submit" class="btn btn-warning">Cancel Change</button>
+            </form>"#,
+            html_escape(&change.summary()),
+            schedule_time(change),
+            schedule::format_countdown(clock::utc_secs().map(|now| change.apply_at.saturating_sub(now)).unwrap_or(0)),
+            change.window_minutes
+        ),
+        None => format!(
+            r#"<form method="POST" action="/config/schedule">
+                <div class="form-group">
+                    <label for="sched_at">Apply At (UTC)</label>
+                    <input type="datetime-local" id="sched_at" name="at" required>
+                </div>
+                <div class="form-group">
+                    <label for="sched_window">Rollback Window (minutes)</label>
+                    <input type="number" id="sched_window" name="window" value="{}" min="1" max="{}">
+                </div>
+                <div class="form-group">
+                    <label for="sched_baud">New Baud Rate</label>
+                    <select id="sched_baud" name="mstp_baud">
+                        <option value="">(unchanged)</option>
+                        <option value="9600">9600</option>
+                        <option value="19200">19200</option>
+                        <option value="38400">38400</option>
+                        <option value="57600">57600</option>
+                        <option value="76800">76800</option>
+                        <option value="115200">115200</option>
+                    </select>
+                </div>
+                <div class="form-group">
+                    <label for="sched_addr">New Station Address</label>
+                    <input type="number" id="sched_addr" name="mstp_addr" min="0" max="254" placeholder="(unchanged)">
+                </div>
+                <div class="form-group">
+                    <label for="sched_other">Other Settings</label>
+                    <input type="text" id="sched_other" name="other" placeholder="e.g. mstp_max=64&amp;mstp_net=2">
+                </div>
+                <button type="submit" class="btn">Schedule Change</button>
+            </form>"#,
+            schedule::DEFAULT_ROLLBACK_WINDOW_MINUTES,
+            MAX_ROLLBACK_WINDOW_MINUTES
+        ),
+    };
+    format!(
+        r#"<div class="card">
+            <h2>Scheduled Change</h2>
+            <p class="hint">Apply settings once at a quiet hour: they are saved and the gateway restarts at that time. If the MS/TP ring is not stable within the rollback window, the previous values come back. {}</p>
+            {}
+        </div>"#,
+        clock_summary(),
+        body
+    )
+}
+
+/// Generate the JSON reply for a configuration update or dry run
+fn generate_config_issues_json(valid: bool, applied: bool, staged: bool, issues: &[ConfigIssue]) -> String {
+    let items: Vec<String> = issues
+        .iter()
+        .map(|i| {
+            format!(
+                r#"{{"level":"{}","field":"{}","message":"{}"}}"#,
+                i.level.as_str(),
+                json_escape(&i.field),
+                json_escape(&i.message)
+            )
+        })
+        .collect();
+    let errors = issues.iter().filter(|i| i.level == IssueLevel::Error).count();
+    format!(
+        r#"{{"valid":{},"applied":{},"staged":{},"errors":{},"warnings":{},"issues":[{}]}}"#,
+        valid,
+        applied,
+        staged,
+        errors,
+        issues.len() - errors,
+        items.join(",")
+    )
+}
+
+/// Whether a staged change is still waiting to be checked or being checked
+fn staging_busy(state: &WebState) -> bool {
+    state.staged_request.is_some()
+        || state.staged_validation.as_ref().is_some_and(|s| s.stage == StagingStage::Listening)
+}
+
+/// Generate the staged validation JSON
+fn generate_staged_json(state: &WebState) -> String {
+    let Some(staging) = state.staged_request.as_ref().or(state.staged_validation.as_ref()) else {
+        return r#"{"staged":false}"#.to_string();
+    };
+    let conflicts: Vec<String> = staging.conflicts.iter().map(|c| format!(r#""{}""#, json_escape(&c.to_string()))).collect();
+    let opt = |v: Option<String>| v.unwrap_or_else(|| "null".to_string());
+    format!(
+        r#"{{"staged":true,"stage":"{}","remaining":{},"mstp_network":{},"ip_network":{},"mstp_address":{},"conflicts":[{}]}}"#,
+        staging.stage.as_str(),
+        staging.remaining(std::time::Instant::now()).as_secs(),
+        opt(staging.check.mstp_network.map(|n| n.to_string())),
+        opt(staging.check.ip_network.map(|n| n.to_string())),
+        opt(staging.check.mstp_address.map(|a| a.to_string())),
+        conflicts.join(",")
+    )
+}
+
+/// Staged validation card for the config page, while a change is checked
+/// or after one was refused
+fn staged_card_html(state: &WebState) -> String {
+    let Some(staging) = state.staged_request.as_ref().or(state.staged_validation.as_ref()) else {
+        return String::new();
+    };
+    let body = match staging.stage {
+        StagingStage::Listening => format!(
+            "<p>Listening for conflicts, {}s left. Reload for the outcome.</p>",
+            staging.remaining(std::time::Instant::now()).as_secs()
+        ),
+        StagingStage::Passed => "<p>No conflicts found. The configuration is saved and the gateway restarts.</p>".to_string(),
+        StagingStage::Refused => {
+            let items: String = staging.conflicts.iter().map(|c| format!("<li>{}</li>", html_escape(&c.to_string()))).collect();
+            format!("<p>Not applied, the running configuration is unchanged:</p><ul>{}</ul>", items)
+        }
+    };
+    let mut checked = Vec::new();
+    if let Some(n) = staging.check.mstp_network {
+        checked.push(format!("MS/TP network {}", n));
+    }
+    if let Some(n) = staging.check.ip_network {
+        checked.push(format!("IP network {}", n));
+    }
+    if let Some(a) = staging.check.mstp_address {
+        checked.push(format!("station address {}", a));
+    }
+    format!(
+        r#"<div class="card">
+            <h2>Staged Change</h2>
+            <p class="hint">Checking {} against the live network.</p>
+            {}
+        </div>"#,
+        checked.join(", "),
+        body
+    )
+}
+
+/// Parse URL-encoded form data with validation
+///
+/// Values that fail their own checks are left unchanged and reported, one
+/// issue per field. Constraints between fields (station address against
+/// Max_Master, distinct network numbers, ...) are left to `config_check::check`.
+pub fn parse_config_form(body: &str, config: &mut GatewayConfig) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    for pair in body.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+        let value = urlencoding::decode(value).unwrap_or_default();
+        let mut refused: Option<&str> = None;
+
+        match key {
+            "wifi_ssid" => {
+                // SSID max 32 characters
+                if value.len() <= 32 {
+                    config.wifi_ssid = value.to_string();
+                } else {
+                    refused = Some("SSID is limited to 32 characters");
+                }
+            }
+            "wifi_pass" => {
+                // Only update if not empty (allows keeping existing password)
+                // WPA2 requires 8-63 characters
+                if value.len() >= 8 && value.len() <= 63 {
+                    config.wifi_password = value.to_string();
+                } else if !value.is_empty() {
+                    refused = Some("WPA2 password must be 8-63 characters");
+                }
+            }
+            "ap_ssid" => {
+                // SSID max 32 characters
+                if value.len() <= 32 && !value.is_empty() {
+                    config.ap_ssid = value.to_string();
+                } else {
+                    refused = Some("hotspot SSID must be 1-32 characters");
+                }
+            }
+            "ap_pass" => {
+                // Only update if not empty (allows keeping existing password)
+                // WPA2 requires 8-63 characters
+                if value.len() >= 8 && value.len() <= 63 {
+                    config.ap_password = value.to_string();
+                } else if !value.is_empty() {
+                    refused = Some("WPA2 password must be 8-63 characters");
+                }
+            }
+            "ap_sta" => {
+                config.ap_with_sta = value == "1";
+            }
+            "ap_timeout" => {
+                // Minutes, 0 = keep the hotspot up
+                match value.parse::<u16>() {
+                    Ok(v) => config.ap_timeout_minutes = v,
+                    Err(_) => refused = Some("expected a number of minutes"),
+                }
+            }
+            "mstp_addr" => {
+                // MS/TP address: 0-127 for a master, up to 254 for a slave
+                match value.parse::<u8>() {
+                    Ok(v) if v <= 254 => config.mstp_address = v,
+                    _ => refused = Some("station address must be 0-254"),
+                }
+            }
+            "mstp_max" => {
+                // MS/TP max master: 0-127
+                match value.parse::<u8>() {
+                    Ok(v) if v <= 127 => config.mstp_max_master = v,
+                    _ => refused = Some("Max Master must be 0-127"),
+                }
+            }
+            "mstp_baud" => {
+                // Support for the rate is checked with the rest of the configuration
+                match value.parse::<u32>() {
+                    Ok(v) => config.mstp_baud_rate = v,
+                    Err(_) => refused = Some("expected a baud rate"),
+                }
+            }
+            "mstp_net" => {
+                // BACnet network number: 1-65534 (0 and 65535 reserved)
+                match value.parse::<u16>() {
+                    Ok(v) if (1..=65534).contains(&v) => config.mstp_network = v,
+                    _ => refused = Some("network number must be 1-65534"),
+                }
+            }
+            "mstp_auto" => {
+                config.mstp_auto_address = value == "1";
+            }
+            "mstp_slave" => {
+                config.mstp_slave_mode = value == "1";
+            }
+            "mstp_pfm" => {
+                // Adaptive Poll-For-Master aggressiveness: 0 (standard) to PFM_MAX_AGGRESSIVENESS
+                match value.parse::<u8>() {
+                    Ok(v) if v <= PFM_MAX_AGGRESSIVENESS => config.mstp_pfm_aggressiveness = v,
+                    _ => refused = Some("aggressiveness level out of range"),
+                }
+            }
+            "mstp_npdu" => {
+                // Max NPDU routed onto MS/TP: MIN_MSTP_MAX_NPDU to 501 (classic frame)
+                match value.parse::<u16>() {
+                    Ok(v) if (MIN_MSTP_MAX_NPDU..=MSTP_MAX_NPDU).contains(&(v as usize)) => {
+                        config.mstp_max_npdu = v;
+                    }
+                    _ => refused = Some("max NPDU out of range for MS/TP"),
+                }
+            }
+            "bp_high" => {
+                // Backpressure high mark: 0 (off) up to the MS/TP send queue size
+                match value.parse::<u8>() {
+                    Ok(v) if v as usize <= MAX_SEND_QUEUE => config.backpressure_high = v,
+                    _ => refused = Some("high mark exceeds the MS/TP send queue"),
+                }
+            }
+            "bp_low" => {
+                // Backpressure low mark: compared with the high mark by the cross-field check
+                match value.parse::<u8>() {
+                    Ok(v) => config.backpressure_low = v,
+                    Err(_) => refused = Some("expected a queue depth"),
+                }
+            }
+            "ip_port" => {
+                // Port must be > 0
+                match value.parse::<u16>() {
+                    Ok(v) if v > 0 => config.bacnet_ip_port = v,
+                    _ => refused = Some("UDP port must be 1-65535"),
+                }
+            }
+            "ip_net" => {
+                // BACnet network number: 1-65534 (0 and 65535 reserved)
+                match value.parse::<u16>() {
+                    Ok(v) if (1..=65534).contains(&v) => config.ip_network = v,
+                    _ => refused = Some("network number must be 1-65534"),
+                }
+            }
+            "ip_learn" => {
+                config.ip_network_learn = value == "1";
+            }
+            "bip_mode" => {
+                match &*value {
+                    "broadcast" => config.bip_multicast_enabled = false,
+                    "multicast" => config.bip_multicast_enabled = true,
+                    _ => refused = Some("expected broadcast or multicast"),
+                }
+            }
+            "bip_group" => {
+                // Must be an IPv4 multicast address (224.0.0.0/4)
+                match value.parse::<Ipv4Addr>() {
+                    Ok(v) if v.is_multicast() => config.bip_multicast_group = v,
+                    _ => refused = Some("expected an IPv4 multicast address"),
+                }
+            }
+            "bcast_form" => {
+                match &*value {
+                    "directed" => config.bip_broadcast_form = BroadcastForm::Directed,
+                    "limited" => config.bip_broadcast_form = BroadcastForm::Limited,
+                    "both" => config.bip_broadcast_form = BroadcastForm::Both,
+                    "none" => config.bip_broadcast_form = BroadcastForm::Disabled,
+                    _ => refused = Some("expected directed, limited, both or none"),
+                }
+            }
+            "sup_station" => {
+                // Empty clears the list; otherwise comma separated IP or IP:port (default 47808)
+                match parse_supervisory_stations(&value) {
+                    Ok(stations) => config.supervisory_stations = stations,
+                    Err(e) => refused = Some(e),
+                }
+            }
+            "ann_scope" => {
+                match AnnounceScope::parse(&value) {
+                    Some(scope) => config.announce_scope = scope,
+                    None => refused = Some("expected both, ip, mstp or none"),
+                }
+            }
+            "ann_nets" => {
+                // Empty clears the list; otherwise comma separated network numbers
+                match parse_network_list(&value) {
+                    Ok(networks) => config.announce_networks = networks,
+                    Err(e) => refused = Some(e),
+                }
+            }
+            "compat" => {
+                // Rules that don't parse leave the current set in place
+                match compat::parse_rules(&value) {
+                    Ok(rules) => config.compat_rules = rules,
+                    Err(_) => refused = Some("compatibility rules did not parse"),
+                }
+            }
+            "dup_suppress" => {
+                config.suppress_on_duplicate_network = value == "1";
+            }
+            "whois_filter" => {
+                config.whois_filter_enabled = value == "1";
+            }
+            "npdu_hops" => {
+                // 1 to 255; 0 would be dropped by the first router
+                match value.parse::<u8>() {
+                    Ok(v) if v > 0 => config.npdu_hop_count = v,
+                    _ => refused = Some("hop count must be 1 to 255"),
+                }
+            }
+            "npdu_prio" => {
+                match NetworkPriority::parse(&value) {
+                    Some(v) => config.npdu_priority = v,
+                    None => refused = Some("expected normal, urgent, critical-equipment or life-safety"),
+                }
+            }
+            "bbmd_addr" => {
+                // Empty clears the BBMD; otherwise a unicast IPv4 address
+                if value.is_empty() {
+                    config.bbmd_address = None;
+                } else {
+                    match value.parse::<Ipv4Addr>() {
+                        Ok(v) if !v.is_unspecified() && !v.is_multicast() && !v.is_broadcast() => {
+                            config.bbmd_address = Some(v);
+                        }
+                        _ => refused = Some("expected a unicast IPv4 address"),
+                    }
+                }
+            }
+            "sbvll_drop" => {
+                config.secure_bvll_drop = value == "1";
+            }
+            "npdu_lenient" => {
+                config.npdu_lenient = value == "1";
+            }
+            "hb_enabled" => {
+                config.heartbeat_enabled = value == "1";
+            }
+            "hb_url" => {
+                // Empty clears the endpoint; otherwise TLS is required
+                if value.is_empty()
+                    || (value.starts_with("https://") && value.len() <= heartbeat::MAX_URL_LEN)
+                {
+                    config.heartbeat_url = value.to_string();
+                } else {
+                    refused = Some("expected an https:// URL");
+                }
+            }
+            "hb_interval" => {
+                match value.parse::<u32>() {
+                    Ok(v) if (heartbeat::MIN_INTERVAL_SECS..=heartbeat::MAX_INTERVAL_SECS).contains(&v) => {
+                        config.heartbeat_interval_secs = v;
+                    }
+                    _ => refused = Some("interval out of range"),
+                }
+            }
+            "pub_port" => {
+                // 0 disables; never the portal's own port
+                match value.parse::<u16>() {
+                    Ok(v) if v == 0 || (v >= MIN_PUBLIC_PORT && v != WEB_PORT) => config.public_status_port = v,
+                    _ => refused = Some("expected 0 or an unprivileged port other than the portal's"),
+                }
+            }
+            "con_port" => {
+                // 0 disables; never the portal's own port
+                match value.parse::<u16>() {
+                    Ok(v) if v != WEB_PORT => config.remote_console_port = v,
+                    _ => refused = Some("expected 0 or a port other than the portal's"),
+                }
+            }
+            "wh_enabled" => {
+                config.webhook_enabled = value == "1";
+            }
+            "wh_url" => {
+                // Empty clears the endpoint; plain HTTP is allowed for on-site integrations
+                if value.is_empty()
+                    || ((value.starts_with("https://") || value.starts_with("http://"))
+                        && value.len() <= heartbeat::MAX_URL_LEN)
+                {
+                    config.webhook_url = value.to_string();
+                } else {
+                    refused = Some("expected an http:// or https:// URL");
+                }
+            }
+            "wh_scan_h" => {
+                match value.parse::<u32>() {
+                    Ok(v) if v <= notify::MAX_SCAN_INTERVAL_HOURS => config.webhook_scan_hours = v,
+                    _ => refused = Some("scan interval out of range"),
+                }
+            }
+            "wh_err_thr" => {
+                match value.parse::<u32>() {
+                    Ok(v) => config.webhook_error_threshold = v,
+                    Err(_) => refused = Some("expected a number"),
+                }
+            }
+            "site_name" => {
+                if value.len() <= MAX_SITE_STRING_LEN {
+                    config.site_name = value.to_string();
+                } else {
+                    refused = Some("text is too long");
+                }
+            }
+            "ntp_server" => {
+                // Empty falls back to the default pool
+                if value.len() <= clock::MAX_CLOCK_STRING_LEN && !value.contains(char::is_whitespace) {
+                    config.ntp_server = value.to_string();
+                } else {
+                    refused = Some("expected a host name without spaces");
+                }
+            }
+            "timezone" => {
+                // POSIX TZ string with its DST rule, e.g. "EST5EDT,M3.2.0,M11.1.0";
+                // empty falls back to UTC
+                if value.is_empty() || (value.len() <= clock::MAX_CLOCK_STRING_LEN && TimeZone::parse(&value).is_some()) {
+                    config.timezone = value.to_string();
+                } else {
+                    refused = Some("expected a POSIX TZ string such as CET-1CEST,M3.5.0,M10.5.0/3");
+                }
+            }
+            "life_stats" => {
+                config.lifetime_stats_enabled = value == "1";
+            }
+            "evt_spill" => {
+                config.event_spill_enabled = value == "1";
+            }
+            "log_json" => {
+                config.log_json = value == "1";
+            }
+            "wp_pin" => {
+                match value.parse::<u8>() {
+                    Ok(pin) if write_protect::valid_pin(pin) => config.write_protect_pin = pin,
+                    _ => refused = Some("write-protect pin must be 0 (none), 25, 32 or 33"),
+                }
+            }
+            "fo_role" => {
+                match FailoverRole::parse(&value) {
+                    Some(role) => config.failover_role = role,
+                    None => refused = Some("expected off, primary or standby"),
+                }
+            }
+            "fo_peer" => {
+                // Empty clears the peer; otherwise a unicast IPv4 address
+                if value.is_empty() {
+                    config.failover_peer = None;
+                } else {
+                    match value.parse::<Ipv4Addr>() {
+                        Ok(v) if !v.is_unspecified() && !v.is_multicast() && !v.is_broadcast() => {
+                            config.failover_peer = Some(v);
+                        }
+                        _ => refused = Some("expected a unicast IPv4 address"),
+                    }
+                }
+            }
+            "local_dev" => {
+                config.local_device_enabled = value == "1";
+            }
+            "dev_inst" => {
+                // Device instance: 0-4194302 (max per ASHRAE 135)
+                match value.parse::<u32>() {
+                    Ok(v) if v <= MAX_DEVICE_INSTANCE => config.device_instance = v,
+                    _ => refused = Some("device instance must be 0-4194302"),
+                }
+            }
+            "dev_name" => {
+                // Device name max 64 characters
+                if value.len() <= 64 && !value.is_empty() {
+                    config.device_name = value.to_string();
+                } else {
+                    refused = Some("device name must be 1-64 characters");
+                }
+            }
+            "dev_loc" => {
+                if value.len() <= MAX_SITE_STRING_LEN {
+                    config.device_location = value.to_string();
+                } else {
+                    refused = Some("text is too long");
+                }
+            }
+            "dev_desc" => {
+                if value.len() <= MAX_SITE_STRING_LEN {
+                    config.device_description = value.to_string();
+                } else {
+                    refused = Some("text is too long");
+                }
+            }
+            "dev_serial" => {
+                if value.len() <= MAX_SITE_STRING_LEN && !value.is_empty() {
+                    config.device_serial_number = value.to_string();
+                } else {
+                    refused = Some("serial number must not be empty or too long");
+                }
+            }
+            "dev_services" => {
+                match ServiceWhitelist::parse(&value) {
+                    Ok(services) => config.device_services = services,
+                    Err(_) => refused = Some("unknown service in the answered services list"),
+                }
+            }
+            _ => {}
+        }
+        if let Some(reason) = refused {
+            issues.push(ConfigIssue::error(key, format!("'{}' not accepted: {}", value, reason)));
+        }
+    }
+    issues
+}
+
+/// Escape free-text values for use inside an HTML attribute
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Escape free-text values for use inside a JSON string
+pub fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Generate status page HTML
+fn generate_status_page(state: &WebState) -> String {
+    // Convert discovered_masters bitmap to hex string
+    let masters_hex = format!("{:032x}", state.mstp_stats.discovered_masters);
+    let uart_overflows = state.mstp_stats.uart_fifo_overflows + state.mstp_stats.uart_buffer_overflows;
+    // Friendly names for the device map tooltips
+    let device_names: Vec<(u8, &str)> = state.discovered_devices.iter()
+        .filter_map(|d| state.device_label(d.device_instance).map(|l| (d.mac_address, l.name.as_str())))
+        .filter(|(_, name)| !name.is_empty())
+        .collect();
+    // Scan options (captured by name in the template)
+    let max_instance = MAX_SCAN_INSTANCE;
+    let baud = state.config.mstp_baud_rate;
+    let reply_window_ms = SCAN_REPLY_WINDOW.as_millis();
+    let lifetime_html = lifetime_stats_html(state.lifetime_stats.as_ref());
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Status</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <script>
+        const STATE_NAMES = ['Init', 'Idle', 'UseToken', 'WaitReply', 'PassToken', 'NoToken', 'PollMaster', 'AnswerReq', 'DoneToken'];
+
+        function updateDeviceGrid(hexStr, stationAddr) {{
+            const grid = document.getElementById('device-grid');
+            if (!grid) return;
+
+            // Parse hex string to BigInt
+            let bitmap = BigInt('0x' + hexStr);
+
+            for (let i = 0; i < 128; i++) {{
+                const cell = document.getElementById('dev-' + i);
+                if (cell) {{
+                    const isPresent = (bitmap >> BigInt(i)) & BigInt(1);
+                    cell.className = 'grid-cell';
+                    if (i === stationAddr) {{
+                        cell.className += ' self';
+                    }} else if (isPresent) {{
+                        cell.className += ' active';
+                    }}
+                }}
+            }}
+        }}
+
+        function formatBytes(n) {{
+            if (n >= 1048576) return (n / 1048576).toFixed(1) + ' MB';
+            if (n >= 1024) return (n / 1024).toFixed(1) + ' KB';
+            return n + ' B';
+        }}
+
+        function updateStatus() {{
+            fetch('/api/status')
+                .then(r => r.json())
+                .then(data => {{
+                    // Frame counters
+                    document.getElementById('rx_frames').textContent = data.rx_frames;
+                    document.getElementById('tx_frames').textContent = data.tx_frames;
+                    document.getElementById('tokens_received').textContent = data.tokens_received;
+
+                    // Error counters with highlighting
+                    const crcEl = document.getElementById('crc_errors');
+                    crcEl.textContent = data.crc_errors;
+                    crcEl.className = data.crc_errors > 0 ? 'value error' : 'value';
+
+                    const frameErrEl = document.getElementById('frame_errors');
+                    frameErrEl.textContent = data.frame_errors;
+                    frameErrEl.className = data.frame_errors > 0 ? 'value error' : 'value';
+
+                    const replyTOEl = document.getElementById('reply_timeouts');
+                    replyTOEl.textContent = data.reply_timeouts;
+                    replyTOEl.className = data.reply_timeouts > 0 ? 'value error' : 'value';
+
+                    const passFailEl = document.getElementById('token_pass_failures');
+                    passFailEl.textContent = data.token_pass_failures;
+                    passFailEl.className = data.token_pass_failures > 0 ? 'value error' : 'value';
+
+                    const nearMissEl = document.getElementById('deadline_near_misses');
+                    nearMissEl.textContent = data.deadline_near_misses;
+                    nearMissEl.className = data.deadline_near_misses > 0 ? 'value warning' : 'value';
+
+                    // RS-485 line errors (UART level, separate from BACnet CRC errors)
+                    ['uart_framing_errors', 'uart_parity_errors', 'uart_overflows', 'uart_breaks'].forEach(id => {{
+                        const el = document.getElementById(id);
+                        el.textContent = data[id];
+                        el.className = data[id] > 0 ? 'value error' : 'value';
+                    }});
+                    document.getElementById('noise_bytes').textContent = data.noise_bytes;
+
+                    // Token loop timing
+                    document.getElementById('token_loop').textContent = data.token_loop_ms + ' ms';
+                    document.getElementById('token_loop_min').textContent = data.token_loop_min_ms + ' ms';
+                    document.getElementById('token_loop_max').textContent = data.token_loop_max_ms + ' ms';
+                    document.getElementById('token_loop_avg').textContent = data.token_loop_avg_ms + ' ms';
+                    document.getElementById('data_crc_avg').textContent = (data.data_crc_avg_ns / 1000).toFixed(1) + ' us';
+                    document.getElementById('data_crc_max').textContent = (data.data_crc_max_ns / 1000).toFixed(1) + ' us';
+                    document.getElementById('poll_interval_avg').textContent = (data.poll_interval_avg_us / 1000).toFixed(1) + ' ms';
+                    document.getElementById('poll_interval_max').textContent = (data.poll_interval_max_us / 1000).toFixed(1) + ' ms';
+                    document.getElementById('poll_jitter').textContent = (data.poll_jitter_us / 1000).toFixed(1) + ' ms';
+                    document.getElementById('display_render').textContent = (data.display_render_us / 1000).toFixed(1) + ' ms';
+                    document.getElementById('display_render_max').textContent = (data.display_render_max_us / 1000).toFixed(1) + ' ms';
+
+                    // State machine
+                    document.getElementById('masters').textContent = data.master_count;
+                    document.getElementById('state').textContent = (STATE_NAMES[data.current_state] || 'Unknown') + (data.slave_mode ? ' (slave)' : '');
+                    document.getElementById('next_station').textContent = data.next_station;
+                    document.getElementById('poll_station').textContent = data.poll_station;
+
+                    const silenceEl = document.getElementById('silence');
+                    silenceEl.textContent = data.silence_ms + ' ms';
+                    silenceEl.className = data.silence_ms > 500 ? 'value warning' : 'value';
+
+                    const soleMasterEl = document.getElementById('sole_master');
+                    soleMasterEl.textContent = data.sole_master ? 'Yes' : 'No';
+                    soleMasterEl.className = data.sole_master ? 'value warning' : 'value';
+
+                    // Queue depths
+                    document.getElementById('send_queue').textContent = data.send_queue_len;
+                    document.getElementById('receive_queue').textContent = data.receive_queue_len;
+
+                    // Gateway stats
+                    document.getElementById('mstp_to_ip').textContent = data.mstp_to_ip;
+                    document.getElementById('ip_to_mstp').textContent = data.ip_to_mstp;
+                    document.getElementById('held_now').textContent = data.held_now;
+                    const heldExpEl = document.getElementById('held_expired');
+                    heldExpEl.textContent = data.held_expired;
+                    heldExpEl.className = data.held_expired > 0 ? 'value error' : 'value';
+                    updateConflictBanner(data.network_conflict);
+
+                    // Gateway traffic
+                    document.getElementById('mstp_to_ip_bytes').textContent = formatBytes(data.mstp_to_ip_bytes);
+                    document.getElementById('ip_to_mstp_bytes').textContent = formatBytes(data.ip_to_mstp_bytes);
+                    ['routing_errors', 'transaction_timeouts', 'rejects_received', 'messages_too_long', 'backpressure_aborts', 'ip_tx_dropped'].forEach(id => {{
+                        const el = document.getElementById(id);
+                        el.textContent = data[id];
+                        el.className = data[id] > 0 ? 'value error' : 'value';
+                    }});
+                    ['active_transactions', 'fdt_entries', 'bdt_entries', 'ip_tx_queue_len', 'ip_socket_recoveries'].forEach(id => {{
+                        document.getElementById(id).textContent = data[id];
+                    }});
+
+                    // Uptime
+                    document.getElementById('uptime').textContent = data.uptime;
+
+                    // Device count chip
+                    document.getElementById('device-count').textContent = data.master_count + ' found';
+
+                    updateDeviceGrid(data.discovered_masters, data.station_address);
+                }})
+                .catch(e => console.error('Update failed:', e));
+        }}
+        function resetStats() {{
+            fetch('/api/reset-stats', {{ method: 'POST' }})
+                .then(r => r.json())
+                .then(data => {{ if(data.status === 'ok') updateStatus(); }})
+                .catch(e => console.error('Reset failed:', e));
+        }}
+        function exportData() {{
+            window.location.href = '/api/export';
+        }}
         let scanPollInterval = null;
+        let currentScanId = 0;
         function startScan() {{
             document.getElementById('scanBtn').disabled = true;
             document.getElementById('scanBtn').textContent = 'Scanning...';
             document.getElementById('scan-results').style.display = 'block';
-            document.getElementById('scan-status').textContent = 'Sending Who-Is broadcast...';
+            document.getElementById('scan-status').textContent = 'Sending Who-Is...';
             document.getElementById('device-list').innerHTML = '';
 
-            fetch('/api/scan', {{ method: 'POST' }})
-                .then(r => r.json())
-                .then(data => {{
-                    if (data.status === 'ok') {{
-                        scanPollInterval = setInterval(pollScanResults, 1000);
-                        setTimeout(stopScan, 5000);
-                    }} else {{
-                        document.getElementById('scan-status').textContent = data.message;
-                        document.getElementById('scanBtn').disabled = false;
-                        document.getElementById('scanBtn').textContent = 'Scan Devices (Who-Is)';
-                    }}
-                }});
-        }}
-        function pollScanResults() {{
-            fetch('/api/devices')
-                .then(r => r.json())
-                .then(data => {{
-                    const list = document.getElementById('device-list');
-                    list.innerHTML = '';
-                    if (data.devices.length === 0) {{
-                        document.getElementById('scan-status').textContent = 'Waiting for I-Am responses...';
-                    }} else {{
-                        document.getElementById('scan-status').textContent = 'Found ' + data.devices.length + ' device(s):';
-                        data.devices.forEach(dev => {{
-                            const div = document.createElement('div');
-                            div.className = 'device-row';
-                            div.innerHTML = '<span>MAC ' + dev.mac + '</span><span>Instance ' + dev.instance + '</span><span>Vendor ' + dev.vendor + '</span>';
-                            div.onclick = () => showDeviceInfo(dev);
-                            list.appendChild(div);
-                        }});
-                    }}
-                }});
-        }}
-        function stopScan() {{
-            if (scanPollInterval) clearInterval(scanPollInterval);
-            scanPollInterval = null;
-            document.getElementById('scanBtn').disabled = false;
-            document.getElementById('scanBtn').textContent = 'Scan Devices (Who-Is)';
-            fetch('/api/stop-scan', {{ method: 'POST' }});
-            pollScanResults();
-        }}
-        function showDeviceInfo(dev) {{
-            const modal = document.getElementById('device-modal');
-            const body = document.getElementById('modal-body');
-            body.innerHTML = '<p><b>MAC Address:</b> ' + dev.mac + '</p>' +
-                '<p><b>Device Instance:</b> ' + dev.instance + '</p>' +
-                '<p><b>Vendor ID:</b> ' + dev.vendor + '</p>' +
-                '<p><b>Max APDU:</b> ' + dev.max_apdu + '</p>' +
-                '<p><b>Segmentation:</b> ' + ['Both', 'Transmit', 'Receive', 'None'][dev.segmentation] + '</p>';
-            modal.style.display = 'flex';
-        }}
-        function closeModal(e) {{
-            if (!e || e.target.id === 'device-modal') {{
-                document.getElementById('device-modal').style.display = 'none';
-            }}
-        }}
-        function showGridDeviceInfo(mac) {{
-            fetch('/api/devices')
-                .then(r => r.json())
-                .then(data => {{
-                    const dev = data.devices.find(d => d.mac === mac);
-                    if (dev) {{
-                        showDeviceInfo(dev);
-                    }} else {{
-                        const modal = document.getElementById('device-modal');
-                        const body = document.getElementById('modal-body');
-                        body.innerHTML = '<p><b>MAC Address:</b> ' + mac + '</p><p>No I-Am received. Run a scan first.</p>';
-                        modal.style.display = 'flex';
-                    }}
-                }});
-        }}
-        setInterval(updateStatus, 2000);
-        document.addEventListener('DOMContentLoaded', () => updateDeviceGrid('{}', {}));
-    </script>
+            const params = new URLSearchParams();
+            ['profile', 'low', 'high', 'window'].forEach(k => params.append(k, document.getElementById('scan-' + k).value));
+            fetch('/api/scan', {{ method: 'POST', body: params }})
+                .then(r => r.json())
+                .then(data => {{
+                    if (data.status === 'ok') {{
+                        currentScanId = data.scan_id;
+                        scanPollInterval = setInterval(pollScanResults, 1000);
+                        // Leave time for late I-Am replies after the last request of the sweep
+                        setTimeout(stopScan, data.duration_ms + {reply_window_ms});
+                    }} else {{
+                        document.getElementById('scan-status').textContent = data.message;
+                        document.getElementById('scanBtn').disabled = false;
+                        document.getElementById('scanBtn').textContent = 'Scan Devices (Who-Is)';
+                    }}
+                }});
+        }}
+        function pollScanResults() {{
+            fetch('/api/devices?scan=' + currentScanId)
+                .then(r => r.json())
+                .then(data => {{
+                    const list = document.getElementById('device-list');
+                    list.innerHTML = '';
+                    const sweep = data.scan_total > 1 ? ' (Who-Is ' + data.scan_sent + '/' + data.scan_total + ')' : '';
+                    if (data.devices.length === 0) {{
+                        document.getElementById('scan-status').textContent = 'Waiting for I-Am responses...' + sweep;
+                    }} else {{
+                        document.getElementById('scan-status').textContent = 'Found ' + data.devices.length + ' device(s)' + sweep + ':';
+                        data.devices.forEach(dev => {{
+                            const div = document.createElement('div');
+                            div.className = 'device-row';
+                            div.innerHTML = '<span>MAC ' + dev.mac + '</span><span>Instance ' + dev.instance + '</span><span>' + escapeHtml(dev.name || dev.object_name || dev.vendor_name) + '</span>';
+                            div.onclick = () => showDeviceInfo(dev);
+                            list.appendChild(div);
+                        }});
+                    }}
+                }});
+        }}
+        function stopScan() {{
+            if (scanPollInterval) clearInterval(scanPollInterval);
+            scanPollInterval = null;
+            document.getElementById('scanBtn').disabled = false;
+            document.getElementById('scanBtn').textContent = 'Scan Devices (Who-Is)';
+            fetch('/api/stop-scan', {{ method: 'POST' }});
+            pollScanResults();
+        }}
+        function updateScanProfile() {{
+            const ranged = document.getElementById('scan-profile').value === 'ranged';
+            document.getElementById('scan-range').style.display = ranged ? 'inline' : 'none';
+        }}
+        function showDeviceInfo(dev) {{
+            const modal = document.getElementById('device-modal');
+            const body = document.getElementById('modal-body');
+            body.innerHTML = '<p><b>MAC Address:</b> ' + dev.mac + '</p>' +
+                '<p><b>Device Instance:</b> ' + dev.instance + '</p>' +
+                (dev.object_name ? '<p><b>Object Name:</b> ' + escapeHtml(dev.object_name) + '</p>' : '') +
+                (dev.name ? '<p><b>Label:</b> ' + escapeHtml(dev.name) + '</p>' : '') +
+                (dev.notes ? '<p><b>Notes:</b> ' + escapeHtml(dev.notes) + '</p>' : '') +
+                '<p><b>Vendor:</b> ' + dev.vendor_name + ' (' + dev.vendor + ')</p>' +
+                '<p><b>Max APDU:</b> ' + dev.max_apdu + '</p>' +
+                '<p><b>Segmentation:</b> ' + ['Both', 'Transmit', 'Receive', 'None'][dev.segmentation] + '</p>';
+            modal.style.display = 'flex';
+        }}
+        function escapeHtml(s) {{
+            const d = document.createElement('div');
+            d.textContent = s;
+            return d.innerHTML;
+        }}
+        function closeModal(e) {{
+            if (!e || e.target.id === 'device-modal') {{
+                document.getElementById('device-modal').style.display = 'none';
+            }}
+        }}
+        function showGridDeviceInfo(mac) {{
+            fetch('/api/devices')
+                .then(r => r.json())
+                .then(data => {{
+                    const dev = data.devices.find(d => d.mac === mac);
+                    if (dev) {{
+                        showDeviceInfo(dev);
+                    }} else {{
+                        const modal = document.getElementById('device-modal');
+                        const body = document.getElementById('modal-body');
+                        body.innerHTML = '<p><b>MAC Address:</b> ' + mac + '</p><p>No I-Am received. Run a scan first.</p>';
+                        modal.style.display = 'flex';
+                    }}
+                }});
+        }}
+        function updateConflictBanner(conflict) {{
+            const banner = document.getElementById('conflict-banner');
+            if (!banner) return;
+            if (!conflict) {{
+                banner.style.display = 'none';
+                return;
+            }}
+            document.getElementById('conflict-network').textContent = conflict.network;
+            document.getElementById('conflict-router').textContent = conflict.router;
+            document.getElementById('conflict-count').textContent = conflict.count;
+            banner.style.display = '';
+        }}
+        function clearConflict() {{
+            fetch('/api/clear-conflict', {{ method: 'POST' }})
+                .then(() => updateConflictBanner(null));
+        }}
+        setInterval(updateStatus, 2000);
+        document.addEventListener('DOMContentLoaded', () => updateDeviceGrid('{}', {}));
+    </script>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status" class="active">Status</a>
+            <a href="/config">Configuration</a>
+            <a href="/devices">Devices</a>
+            <a href="/diagnostics">Diagnostics</a>
+            <a href="/events">Events</a>
+        </nav>
+
+        {}
+
+        <div class="card">
+            <div class="card-header">
+                <h2>MS/TP Device Map <span class="chip" id="device-count">{} found</span></h2>
+                <button class="btn btn-sm" id="scanBtn" onclick="startScan()">Scan (Who-Is)</button>
+            </div>
+            <div class="device-grid" id="device-grid">{}</div>
+            <div class="grid-legend">
+                <span><span class="legend-box self"></span> This Device</span>
+                <span><span class="legend-box active"></span> Active Master</span>
+                <span><span class="legend-box"></span> Not Found</span>
+            </div>
+            <div class="scan-options" style="margin-top:12px;font-size:0.8em;color:#666;">
+                Sweep
+                <select id="scan-profile" onchange="updateScanProfile()">
+                    <option value="quick">Quick broadcast</option>
+                    <option value="ranged">Instance windows</option>
+                    <option value="directed">Each MAC 0-127</option>
+                </select>
+                <span id="scan-range" style="display:none;">
+                    <input type="number" id="scan-low" value="0" min="0" max="{max_instance}" style="width:90px"> to
+                    <input type="number" id="scan-high" value="{max_instance}" min="0" max="{max_instance}" style="width:90px"> in windows of
+                    <input type="number" id="scan-window" value="10000" min="1" max="{max_instance}" style="width:80px">
+                </span>
+                <span>Requests are paced for {baud} baud.</span>
+            </div>
+            <div id="scan-results" style="margin-top:12px;display:none;">
+                <div class="scan-status" id="scan-status"></div>
+                <div id="device-list"></div>
+            </div>
+        </div>
+
+        <div class="card">
+            <h2>State Machine</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">State</span>
+                    <span class="value" id="state">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Sole Master</span>
+                    <span class="value {}" id="sole_master">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Next Station</span>
+                    <span class="value" id="next_station">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Poll Station</span>
+                    <span class="value" id="poll_station">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Silence</span>
+                    <span class="value" id="silence">{} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Masters Found</span>
+                    <span class="value" id="masters">{}</span>
+                </div>
+            </div>
+        </div>
+
+        <div class="card">
+            <h2>MS/TP Statistics</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">RX Frames</span>
+                    <span class="value" id="rx_frames">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">TX Frames</span>
+                    <span class="value" id="tx_frames">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Tokens Received</span>
+                    <span class="value" id="tokens_received">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Send Queue</span>
+                    <span class="value" id="send_queue">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Receive Queue</span>
+                    <span class="value" id="receive_queue">{}</span>
+                </div>
+            </div>
+        </div>
+
+        <div class="card">
+            <h2>Token Loop Timing</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">Current</span>
+                    <span class="value" id="token_loop">{} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Min</span>
+                    <span class="value" id="token_loop_min">{} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Max</span>
+                    <span class="value" id="token_loop_max">{} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Average</span>
+                    <span class="value" id="token_loop_avg">{} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Data CRC Avg</span>
+                    <span class="value" id="data_crc_avg">{:.1} us</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Data CRC Max</span>
+                    <span class="value" id="data_crc_max">{:.1} us</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Poll Interval Avg</span>
+                    <span class="value" id="poll_interval_avg">{:.1} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Poll Interval Max</span>
+                    <span class="value" id="poll_interval_max">{:.1} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Poll Jitter</span>
+                    <span class="value" id="poll_jitter">{:.1} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Display Render</span>
+                    <span class="value" id="display_render">{:.1} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Display Render Max</span>
+                    <span class="value" id="display_render_max">{:.1} ms</span>
+                </div>
+            </div>
+            <p style="color: #555; font-size: 0.8em; margin-top: 16px;"><a href="/capacity">Line capacity and headroom</a></p>
+        </div>
+
+        <div class="card">
+            <h2>Errors</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">CRC Errors</span>
+                    <span class="value {}" id="crc_errors">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Frame Errors</span>
+                    <span class="value {}" id="frame_errors">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Reply Timeouts</span>
+                    <span class="value {}" id="reply_timeouts">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Token Pass Fail</span>
+                    <span class="value {}" id="token_pass_failures">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Deadline Near-Misses</span>
+                    <span class="value {}" id="deadline_near_misses">{}</span>
+                </div>
+            </div>
+        </div>
+
+        <div class="card">
+            <h2>RS-485 Line Quality</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">Framing Errors</span>
+                    <span class="value {}" id="uart_framing_errors">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Parity Errors</span>
+                    <span class="value {}" id="uart_parity_errors">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">RX Overflows</span>
+                    <span class="value {}" id="uart_overflows">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Breaks</span>
+                    <span class="value {}" id="uart_breaks">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Noise Bytes</span>
+                    <span class="value" id="noise_bytes">{}</span>
+                </div>
+            </div>
+        </div>
+
+        <div class="card">
+            <h2>Gateway Routing</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">WiFi</span>
+                    <span class="value {}">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">IP Address</span>
+                    <span class="value auto-size">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">MS/TP to IP</span>
+                    <span class="value" id="mstp_to_ip">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">IP to MS/TP</span>
+                    <span class="value" id="ip_to_mstp">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Held for MS/TP</span>
+                    <span class="value" id="held_now">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Held Expired</span>
+                    <span class="value {}" id="held_expired">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Uptime</span>
+                    <span class="value" id="uptime">{}</span>
+                </div>
+            </div>
+        </div>
+
+        <div class="card">
+            <h2>Gateway Traffic</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">Bytes MS/TP to IP</span>
+                    <span class="value" id="mstp_to_ip_bytes">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Bytes IP to MS/TP</span>
+                    <span class="value" id="ip_to_mstp_bytes">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Routing Errors</span>
+                    <span class="value {}" id="routing_errors">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Txn Timeouts</span>
+                    <span class="value {}" id="transaction_timeouts">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Active Txns</span>
+                    <span class="value" id="active_transactions">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Foreign Devices</span>
+                    <span class="value" id="fdt_entries">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">BDT Entries</span>
+                    <span class="value" id="bdt_entries">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Rejects Rcvd</span>
+                    <span class="value {}" id="rejects_received">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Too Long</span>
+                    <span class="value {}" id="messages_too_long">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Overload Aborts</span>
+                    <span class="value {}" id="backpressure_aborts">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">IP TX Queue</span>
+                    <span class="value" id="ip_tx_queue_len">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">IP TX Drops</span>
+                    <span class="value {}" id="ip_tx_dropped">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Socket Recoveries</span>
+                    <span class="value" id="ip_socket_recoveries">{}</span>
+                </div>
+            </div>
+        </div>
+
+        {}
+
+        <div class="card">
+            <h2>Network Configuration</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">MS/TP Network</span>
+                    <span class="value">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">IP Network</span>
+                    <span class="value">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Router Mode</span>
+                    <span class="value">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Station Address</span>
+                    <span class="value">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Device Instance</span>
+                    <span class="value">{}</span>
+                </div>
+            </div>
+        </div>
+
+        <div class="card">
+            <h2>Tools</h2>
+            <div class="button-row">
+                <button class="btn" onclick="resetStats()">Reset Statistics</button>
+                <button class="btn" onclick="exportData()">Export JSON</button>
+                <button class="btn" onclick="window.location.href='/api/support-bundle'">Generate Support Bundle</button>
+            </div>
+        </div>
+
+        <div id="device-modal" class="modal" onclick="closeModal(event)">
+            <div class="modal-content" onclick="event.stopPropagation()">
+                <h3>Device Info</h3>
+                <div id="modal-body"></div>
+                <button class="btn" onclick="closeModal()">Close</button>
+            </div>
+        </div>
+
+        <p class="footer">BACman v0.1.0</p>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        masters_hex,
+        state.mstp_stats.station_address,
+        format!(
+            "{}{}",
+            generate_no_free_address_banner_html(state.mstp_no_free_address, state.config.mstp_address),
+            generate_conflict_banner_html(state.network_conflict.as_ref())
+        ),
+        // Device Map card
+        state.mstp_stats.master_count,
+        generate_device_grid_html(state.mstp_stats.discovered_masters, state.mstp_stats.station_address, &device_names),
+        // State Machine card
+        format!("{}{}", get_state_name(state.mstp_stats.current_state), if state.mstp_stats.slave_mode { " (slave)" } else { "" }),
+        if state.mstp_stats.sole_master { "warning" } else { "" },
+        if state.mstp_stats.sole_master { "Yes" } else { "No" },
+        state.mstp_stats.next_station,
+        state.mstp_stats.poll_station,
+        state.mstp_stats.silence_ms,
+        state.mstp_stats.master_count,
+        // MS/TP Statistics card
+        state.mstp_stats.rx_frames,
+        state.mstp_stats.tx_frames,
+        state.mstp_stats.tokens_received,
+        state.mstp_stats.send_queue_len,
+        state.mstp_stats.receive_queue_len,
+        // Token Loop Timing card
+        state.mstp_stats.token_loop_time_ms,
+        state.mstp_stats.token_loop_min_ms,
+        state.mstp_stats.token_loop_max_ms,
+        state.mstp_stats.token_loop_avg_ms,
+        state.mstp_stats.data_crc_avg_ns as f32 / 1000.0,
+        state.mstp_stats.data_crc_max_ns as f32 / 1000.0,
+        state.mstp_stats.poll_interval_avg_us as f32 / 1000.0,
+        state.mstp_stats.poll_interval_max_us as f32 / 1000.0,
+        state.mstp_stats.poll_jitter_us as f32 / 1000.0,
+        state.display_render_us as f32 / 1000.0,
+        state.display_render_max_us as f32 / 1000.0,
+        // Errors card
+        if state.mstp_stats.crc_errors > 0 { "error" } else { "" },
+        state.mstp_stats.crc_errors,
+        if state.mstp_stats.frame_errors > 0 { "error" } else { "" },
+        state.mstp_stats.frame_errors,
+        if state.mstp_stats.reply_timeouts > 0 { "error" } else { "" },
+        state.mstp_stats.reply_timeouts,
+        if state.mstp_stats.token_pass_failures > 0 { "error" } else { "" },
+        state.mstp_stats.token_pass_failures,
+        if state.mstp_stats.deadline_near_misses > 0 { "warning" } else { "" },
+        state.mstp_stats.deadline_near_misses,
+        // RS-485 Line Quality card
+        if state.mstp_stats.uart_framing_errors > 0 { "error" } else { "" },
+        state.mstp_stats.uart_framing_errors,
+        if state.mstp_stats.uart_parity_errors > 0 { "error" } else { "" },
+        state.mstp_stats.uart_parity_errors,
+        if uart_overflows > 0 { "error" } else { "" },
+        uart_overflows,
+        if state.mstp_stats.uart_breaks > 0 { "error" } else { "" },
+        state.mstp_stats.uart_breaks,
+        state.mstp_stats.noise_bytes,
+        // Gateway Routing card
+        if state.wifi_connected { "ok" } else { "error" },
+        if state.wifi_connected { "Connected" } else { "Disconnected" },
+        state.ip_address,
+        state.gateway_stats.mstp_to_ip_packets,
+        state.gateway_stats.ip_to_mstp_packets,
+        state.gateway_stats.held_now,
+        if state.gateway_stats.held_expired > 0 { "error" } else { "" },
+        state.gateway_stats.held_expired,
+        state.uptime_formatted(),
+        // Gateway Traffic card
+        format_bytes(state.gateway_stats.mstp_to_ip_bytes),
+        format_bytes(state.gateway_stats.ip_to_mstp_bytes),
+        if state.gateway_stats.routing_errors > 0 { "error" } else { "" },
+        state.gateway_stats.routing_errors,
+        if state.gateway_stats.transaction_timeouts > 0 { "error" } else { "" },
+        state.gateway_stats.transaction_timeouts,
+        state.gateway_stats.active_transactions,
+        state.gateway_stats.fdt_entries,
+        state.gateway_stats.bdt_entries,
+        if state.gateway_stats.rejects_received > 0 { "error" } else { "" },
+        state.gateway_stats.rejects_received,
+        if state.gateway_stats.messages_too_long > 0 { "error" } else { "" },
+        state.gateway_stats.messages_too_long,
+        if state.gateway_stats.backpressure_aborts > 0 { "error" } else { "" },
+        state.gateway_stats.backpressure_aborts,
+        state.gateway_stats.ip_tx_queue_len,
+        if state.gateway_stats.ip_tx_dropped > 0 { "error" } else { "" },
+        state.gateway_stats.ip_tx_dropped,
+        state.gateway_stats.ip_socket_recoveries,
+        lifetime_html,
+        // Network Configuration card
+        state.config.mstp_network,
+        state.gateway_stats.ip_network,
+        state.gateway_stats.router_mode.as_str(),
+        state.config.mstp_address,
+        state.config.device_instance,
+    )
+}
+
+/// Generate the duplicate network warning banner (hidden when no conflict is active)
+fn generate_conflict_banner_html(conflict: Option<&NetworkConflict>) -> String {
+    let (display, network, router, count) = match conflict {
+        Some(c) => ("", c.network.to_string(), c.router.to_string(), c.count),
+        None => ("none", String::new(), String::new(), 0),
+    };
+    format!(r#"<div class="alert-banner" id="conflict-banner" style="display:{}">
+            <strong>Duplicate network number</strong>
+            <p>Router <span id="conflict-router">{}</span> is advertising network <span id="conflict-network">{}</span>, which this gateway routes to MS/TP (<span id="conflict-count">{}</span> announcements). Renumber one of the networks.</p>
+            <button class="btn btn-sm" onclick="clearConflict()">Clear</button>
+        </div>"#,
+        display, router, network, count)
+}
+
+/// Warning banner for an auto-address survey that found no free station address
+fn generate_no_free_address_banner_html(no_free_address: bool, configured: u8) -> String {
+    if !no_free_address {
+        return String::new();
+    }
+    format!(r#"<div class="alert-banner">
+            <strong>No free MS/TP station address</strong>
+            <p>Auto-address heard every address in use, so the gateway did not join the token ring with its configured address {} and is only listening on MS/TP. Raise Max_Master or free an address, then restart.</p>
+        </div>
+"#, configured)
+}
+
+/// Format the active network conflict as a JSON value (null when none)
+fn network_conflict_json(conflict: Option<&NetworkConflict>) -> String {
+    match conflict {
+        Some(c) => format!(r#"{{"network":{},"router":"{}","count":{},"first_seen_secs":{},"last_seen_secs":{},"first_seen":{},"last_seen":{}}}"#,
+            c.network, c.router, c.count,
+            c.first_seen.elapsed().as_secs(), c.last_seen.elapsed().as_secs(),
+            utc_json(c.first_seen), utc_json(c.last_seen)),
+        None => "null".to_string(),
+    }
+}
+
+/// Generate HTML for the device grid (128 cells for addresses 0-127)
+fn generate_device_grid_html(discovered_masters: u128, station_address: u8, device_names: &[(u8, &str)]) -> String {
+    let mut html = String::with_capacity(8192);
+    for i in 0..128u8 {
+        let title = match device_names.iter().find(|(mac, _)| *mac == i) {
+            Some((_, name)) => format!("Address {} - {}", i, html_escape(name)),
+            None => format!("Address {}", i),
+        };
+        let is_present = (discovered_masters >> i) & 1 == 1;
+        let is_self = i == station_address;
+        let class = if is_self {
+            "grid-cell self"
+        } else if is_present {
+            "grid-cell active"
+        } else {
+            "grid-cell"
+        };
+        // Make active and self cells clickable to show device info
+        if is_present || is_self {
+            html.push_str(&format!(r#"<div class="{}" id="dev-{}" title="{}" onclick="showGridDeviceInfo({})">{}</div>"#, class, i, title, i, i));
+        } else {
+            html.push_str(&format!(r#"<div class="{}" id="dev-{}" title="{}">{}</div>"#, class, i, title, i));
+        }
+    }
+    html
+}
+
+/// Lifetime totals for the JSON export (null while disabled)
+fn lifetime_stats_json(stats: Option<&LifetimeStats>) -> String {
+    match stats {
+        Some(s) => format!(
+            r#"{{"boot_count":{},"uptime_secs":{},"mstp_to_ip_packets":{},"ip_to_mstp_packets":{},"routing_errors":{},"crc_errors":{},"last_reboot_reason":"{}"}}"#,
+            s.boot_count,
+            s.totals.uptime_secs,
+            s.totals.mstp_to_ip_packets,
+            s.totals.ip_to_mstp_packets,
+            s.totals.routing_errors,
+            s.totals.crc_errors,
+            s.last_reboot_reason.map(|r| r.as_str()).unwrap_or("unknown")
+        ),
+        None => "null".to_string(),
+    }
+}
+
+/// Lifetime statistics card for the status page
+fn lifetime_stats_html(stats: Option<&LifetimeStats>) -> String {
+    let Some(stats) = stats else {
+        return r#"<div class="card">
+            <h2>Lifetime Statistics</h2>
+            <p class="hint">Disabled. Enable on the Config page to keep totals across reboots.</p>
+        </div>"#.to_string();
+    };
+    let item = |label: &str, value: String| {
+        format!(
+            r#"<div class="status-item">
+                    <span class="label">{}</span>
+                    <span class="value">{}</span>
+                </div>"#,
+            label, value
+        )
+    };
+    let items = [
+        item("Boots", stats.boot_count.to_string()),
+        item("Total Uptime", format_uptime(stats.totals.uptime_secs)),
+        item("MS/TP to IP", stats.totals.mstp_to_ip_packets.to_string()),
+        item("IP to MS/TP", stats.totals.ip_to_mstp_packets.to_string()),
+        item("Routing Errors", stats.totals.routing_errors.to_string()),
+        item("CRC Errors", stats.totals.crc_errors.to_string()),
+        item("Last Reboot", stats.last_reboot_reason.map(|r| r.as_str()).unwrap_or("unknown").to_string()),
+    ];
+    format!(
+        r#"<div class="card">
+            <h2>Lifetime Statistics</h2>
+            <div class="status-grid">
+                {}
+            </div>
+            <p class="hint">Since first boot, checkpointed every {} minutes and at shutdown</p>
+        </div>"#,
+        items.join("\n                "),
+        lifetime::CHECKPOINT_INTERVAL.as_secs() / 60
+    )
+}
+
+/// Format a byte count for display (B, KB, MB)
+fn format_bytes(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Get state name from state number
+fn get_state_name(state: u8) -> &'static str {
+    match state {
+        0 => "Initialize",
+        1 => "Idle",
+        2 => "UseToken",
+        3 => "WaitForReply",
+        4 => "PassToken",
+        5 => "NoToken",
+        6 => "PollForMaster",
+        7 => "AnswerDataRequest",
+        8 => "DoneWithToken",
+        _ => "Unknown",
+    }
+}
+
+/// Generate configuration page HTML
+fn generate_config_page(state: &WebState) -> String {
+    generate_config_page_with_message(state, "")
+}
+
+/// Generate configuration page with message
+fn generate_config_page_with_message(state: &WebState, message: &str) -> String {
+    let message_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+    let staged_card = staged_card_html(state);
+    // Backup passphrase minimum (captured by name in the template)
+    let min_pass = MIN_PASSPHRASE_LEN;
+    let schedule_card = schedule_card_html(state);
+
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Configuration</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/config" class="active">Configuration</a>
+            <a href="/users">Users</a>
+        </nav>
+
+        {}
+        {staged_card}
+
+        <form method="POST" action="/config">
+            <div class="card">
+                <h2>WiFi Station Mode</h2>
+                <p class="hint">Connect to an existing WiFi network</p>
+                <div class="form-group">
+                    <label for="wifi_ssid">SSID</label>
+                    <input type="text" id="wifi_ssid" name="wifi_ssid" value="{}" maxlength="32">
+                </div>
+                <div class="form-group">
+                    <label for="wifi_pass">Password</label>
+                    <input type="password" id="wifi_pass" name="wifi_pass" placeholder="(leave blank to keep current)" maxlength="64">
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>WiFi Access Point Mode</h2>
+                <p class="hint">Create a WiFi hotspot (activate via long-press on APConfig screen)</p>
+                <div class="form-group">
+                    <label for="ap_ssid">AP SSID</label>
+                    <input type="text" id="ap_ssid" name="ap_ssid" value="{}" maxlength="32">
+                </div>
+                <div class="form-group">
+                    <label for="ap_pass">AP Password (min 8 chars)</label>
+                    <input type="password" id="ap_pass" name="ap_pass" placeholder="(leave blank to keep current)" maxlength="64" minlength="8">
+                </div>
+                <div class="form-group">
+                    <label for="ap_sta">Hotspot While Connected</label>
+                    <select id="ap_sta" name="ap_sta">
+                        <option value="0" {}>Off (button B toggles AP/Station)</option>
+                        <option value="1" {}>On (AP and Station together)</option>
+                    </select>
+                    <p class="hint">Keeps the configuration hotspot reachable while on site WiFi</p>
+                </div>
+                <div class="form-group">
+                    <label for="ap_timeout">Hotspot Auto-Off (minutes, 0 = never)</label>
+                    <input type="number" id="ap_timeout" name="ap_timeout" value="{}" min="0" max="65535">
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>MS/TP Settings</h2>
+                <div class="form-group">
+                    <label for="mstp_addr">Station Address (0-127, slave 0-254)</label>
+                    <input type="number" id="mstp_addr" name="mstp_addr" value="{}" min="0" max="254">
+                </div>
+                <div class="form-group">
+                    <label for="mstp_slave">Station Role</label>
+                    <select id="mstp_slave" name="mstp_slave">
+                        <option value="0" {}>Master (joins the token ring)</option>
+                        <option value="1" {}>Slave (answers requests only)</option>
+                    </select>
+                    <p class="hint">A slave never holds the token, so it adds no master to a crowded ring. The BMS can still read and write the gateway's own objects, but the gateway can't route to or announce itself on MS/TP (no Who-Is answers; bind the device statically). Slaves usually take addresses 128-254</p>
+                </div>
+                <div class="form-group">
+                    <label for="mstp_auto">Address Selection</label>
+                    <select id="mstp_auto" name="mstp_auto">
+                        <option value="0" {}>Fixed</option>
+                        <option value="1" {}>Automatic (find a free address)</option>
+                    </select>
+                    <p class="hint">Automatic listens to the trunk at startup and takes a free address up to Max Master, keeping the station address above if it is free</p>
+                </div>
+                <div class="form-group">
+                    <label for="mstp_max">Max Master (0-127)</label>
+                    <input type="number" id="mstp_max" name="mstp_max" value="{}" min="0" max="127">
+                </div>
+                <div class="form-group">
+                    <label for="mstp_baud">Baud Rate</label>
+                    <select id="mstp_baud" name="mstp_baud">
+                        <option value="9600" {}>9600</option>
+                        <option value="19200" {}>19200</option>
+                        <option value="38400" {}>38400</option>
+                        <option value="57600" {}>57600</option>
+                        <option value="76800" {}>76800</option>
+                        <option value="115200" {}>115200</option>
+                    </select>
+                </div>
+                <div class="form-group">
+                    <label for="mstp_net">MS/TP Network Number</label>
+                    <input type="number" id="mstp_net" name="mstp_net" value="{}" min="1" max="65534">
+                </div>
+                <div class="form-group">
+                    <label for="mstp_pfm">Poll-For-Master Mode</label>
+                    <select id="mstp_pfm" name="mstp_pfm">
+                        <option value="0" {}>Standard (fixed sweep)</option>
+                        <option value="1" {}>Adaptive - mild</option>
+                        <option value="2" {}>Adaptive - moderate</option>
+                        <option value="3" {}>Adaptive - aggressive</option>
+                    </select>
+                    <p class="hint">Adaptive modes poll less often once the ring is stable and favour addresses next to known masters</p>
+                </div>
+                <div class="form-group">
+                    <label for="mstp_npdu">Max NPDU Length (bytes)</label>
+                    <input type="number" id="mstp_npdu" name="mstp_npdu" value="{}" min="{}" max="{}">
+                    <p class="hint">Longer messages from the IP side are rejected with Reject-Message-To-Network (message too long); B/IP carries up to 1497</p>
+                </div>
+                <div class="form-group">
+                    <label for="bp_high">Backpressure Start (queued frames, 0-{}, 0 = off)</label>
+                    <input type="number" id="bp_high" name="bp_high" value="{}" min="0" max="{}">
+                </div>
+                <div class="form-group">
+                    <label for="bp_low">Backpressure Stop (queued frames)</label>
+                    <input type="number" id="bp_low" name="bp_low" value="{}" min="0" max="{}">
+                    <p class="hint">Once this many frames wait for the token, new confirmed requests from IP get an immediate Abort (buffer overflow) instead of timing out, until the queue drains to the stop level</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>BACnet/IP Settings</h2>
+                <div class="form-group">
+                    <label for="ip_port">UDP Port</label>
+                    <input type="number" id="ip_port" name="ip_port" value="{}" min="1" max="65535">
+                </div>
+                <div class="form-group">
+                    <label for="ip_net">IP Network Number</label>
+                    <input type="number" id="ip_net" name="ip_net" value="{}" min="1" max="65534">
+                </div>
+                <div class="form-group">
+                    <label for="ip_learn">IP Network Number Source</label>
+                    <select id="ip_learn" name="ip_learn">
+                        <option value="0" {}>Configured</option>
+                        <option value="1" {}>Learn from Network-Number-Is</option>
+                    </select>
+                    <p class="hint">When learning, the number above only carries traffic until another router announces the real one; the gateway passes traffic through without announcing the IP network until then</p>
+                </div>
+                <div class="form-group">
+                    <label for="bip_mode">Broadcast Mode</label>
+                    <select id="bip_mode" name="bip_mode">
+                        <option value="broadcast" {}>Subnet broadcast</option>
+                        <option value="multicast" {}>Multicast group (Annex J.7)</option>
+                    </select>
+                </div>
+                <div class="form-group">
+                    <label for="bip_group">Multicast Group</label>
+                    <input type="text" id="bip_group" name="bip_group" value="{}" maxlength="15" pattern="\d+\.\d+\.\d+\.\d+">
+                    <p class="hint">Use where managed switches block subnet broadcasts but permit a BACnet multicast group</p>
+                </div>
+                <div class="form-group">
+                    <label for="bcast_form">Broadcast Address</label>
+                    <select id="bcast_form" name="bcast_form">
+                        <option value="directed" {}>Directed subnet broadcast</option>
+                        <option value="limited" {}>Limited broadcast (255.255.255.255)</option>
+                        <option value="both" {}>Both</option>
+                        <option value="none" {}>None (supervisory stations only)</option>
+                    </select>
+                    <p class="hint">Form used for B/IP broadcasts when multicast is off; some firewalls drop one or the other, and some sites block both</p>
+                </div>
+                <div class="form-group">
+                    <label for="sup_station">Supervisory Stations</label>
+                    <input type="text" id="sup_station" name="sup_station" value="{}" maxlength="90" placeholder="optional IP or IP:port, comma separated">
+                    <p class="hint">Each gets a Forwarded-NPDU unicast copy of every B/IP broadcast (up to {})</p>
+                </div>
+                <div class="form-group">
+                    <label for="compat">Client Compatibility Profiles</label>
+                    <input type="text" id="compat" name="compat" value="{}" maxlength="190" placeholder="e.g. 10.0.5.0/24=niagara, 10.0.9.4=jci-cct">
+                    <p class="hint">Source subnet=profile, comma separated (up to {}). Profiles: jci-cct (no SADR, window 1), niagara (Forwarded-NPDU, no unicast I-Am), yabe (window 4), default</p>
+                </div>
+                <div class="form-group">
+                    <label for="dup_suppress">Duplicate Network Number</label>
+                    <select id="dup_suppress" name="dup_suppress">
+                        <option value="0" {}>Warn only</option>
+                        <option value="1" {}>Warn and suppress MS/TP routing</option>
+                    </select>
+                    <p class="hint">Action when another router on the IP side advertises the MS/TP network number</p>
+                </div>
+                <div class="form-group">
+                    <label for="whois_filter">Ranged Who-Is from IP</label>
+                    <select id="whois_filter" name="whois_filter">
+                        <option value="0" {}>Always forward to MS/TP</option>
+                        <option value="1" {}>Skip when all devices in range are on IP</option>
+                    </select>
+                    <p class="hint">Saves token time on Who-Is for devices already bound on the IP side</p>
+                </div>
+                <div class="form-group">
+                    <label for="ann_scope">Router Announcements</label>
+                    <select id="ann_scope" name="ann_scope">
+                        <option value="both" {}>IP and MS/TP</option>
+                        <option value="ip" {}>IP only</option>
+                        <option value="mstp" {}>MS/TP only</option>
+                        <option value="none" {}>None (answer Who-Is-Router only)</option>
+                    </select>
+                    <p class="hint">Ports the periodic I-Am-Router-To-Network goes out on; nothing is announced while a port is disabled</p>
+                </div>
+                <div class="form-group">
+                    <label for="ann_nets">Announced Networks</label>
+                    <input type="text" id="ann_nets" name="ann_nets" value="{}" maxlength="100" placeholder="optional, e.g. 300, 400">
+                    <p class="hint">Further networks to announce (up to {}); each goes out on the side away from its static route, and only while that route is up</p>
+                </div>
+                <div class="form-group">
+                    <label for="npdu_hops">Originated Hop Count</label>
+                    <input type="number" id="npdu_hops" name="npdu_hops" value="{}" min="1" max="255">
+                    <p class="hint">Hop count on routed NPDUs the gateway sends itself (announcements, proxy replies)</p>
+                </div>
+                <div class="form-group">
+                    <label for="npdu_prio">Originated NPDU Priority</label>
+                    <select id="npdu_prio" name="npdu_prio">
+                        <option value="normal" {}>Normal</option>
+                        <option value="urgent" {}>Urgent</option>
+                        <option value="critical-equipment" {}>Critical equipment</option>
+                        <option value="life-safety" {}>Life safety</option>
+                    </select>
+                    <p class="hint">Network priority on gateway-originated traffic; forwarded traffic keeps its own</p>
+                </div>
+                <div class="form-group">
+                    <label for="npdu_lenient">NPDU Parsing</label>
+                    <select id="npdu_lenient" name="npdu_lenient">
+                        <option value="0" {}>Strict</option>
+                        <option value="1" {}>Lenient</option>
+                    </select>
+                    <p class="hint">Lenient repairs known legacy header quirks (missing or stray hop count, padding) and routes the frame; each is logged once per source and counted</p>
+                </div>
+                <div class="form-group">
+                    <label for="bbmd_addr">BBMD Address</label>
+                    <input type="text" id="bbmd_addr" name="bbmd_addr" value="{}" maxlength="15" placeholder="optional">
+                    <p class="hint">Site BBMD checked by the reachability tool on the BDT page</p>
+                </div>
+                <div class="form-group">
+                    <label for="sbvll_drop">Secure-BVLL Messages</label>
+                    <select id="sbvll_drop" name="sbvll_drop">
+                        <option value="0" {}>Refuse with BVLC-Result NAK (non-standard code 0x0070)</option>
+                        <option value="1" {}>Drop silently (recommended)</option>
+                    </select>
+                    <p class="hint">B/IP security is not supported; these messages are counted per source either way</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Device Settings</h2>
+                <div class="form-group">
+                    <label for="local_dev">Gateway Device Object</label>
+                    <select id="local_dev" name="local_dev">
+                        <option value="1" {}>Enabled</option>
+                        <option value="0" {}>Disabled (transparent router)</option>
+                    </select>
+                    <p class="hint">Disabled: the gateway only routes - no I-Am, no Device object, nothing counted against device licensing. Applies after reboot.</p>
+                </div>
+                <div class="form-group">
+                    <label for="dev_inst">Device Instance (0-4194303)</label>
+                    <input type="number" id="dev_inst" name="dev_inst" value="{}" min="0" max="4194303">
+                </div>
+                <div class="form-group">
+                    <label for="dev_name">Device Name</label>
+                    <input type="text" id="dev_name" name="dev_name" value="{}" maxlength="64">
+                </div>
+                <div class="form-group">
+                    <label for="dev_loc">Location</label>
+                    <input type="text" id="dev_loc" name="dev_loc" value="{}" maxlength="63">
+                </div>
+                <div class="form-group">
+                    <label for="dev_desc">Description</label>
+                    <input type="text" id="dev_desc" name="dev_desc" value="{}" maxlength="63">
+                </div>
+                <div class="form-group">
+                    <label for="dev_serial">Serial Number</label>
+                    <input type="text" id="dev_serial" name="dev_serial" value="{}" maxlength="63">
+                    <p class="hint">Location, Description and Serial Number apply immediately and are also writable over BACnet</p>
+                </div>
+                <div class="form-group">
+                    <label for="dev_services">Answered Services</label>
+                    <input type="text" id="dev_services" name="dev_services" value="{}" placeholder="read-property,write-property">
+                    <p class="hint">Comma-separated: read-property, read-property-multiple, write-property, who-is, subscribe-cov. Other confirmed requests get a Reject (unrecognized-service), other unconfirmed ones are ignored, and Protocol_Services_Supported lists only these. Applies after reboot.</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Fleet Monitoring</h2>
+                <div class="form-group">
+                    <label for="hb_enabled">Heartbeat</label>
+                    <select id="hb_enabled" name="hb_enabled">
+                        <option value="0" {}>Disabled</option>
+                        <option value="1" {}>Enabled</option>
+                    </select>
+                </div>
+                <div class="form-group">
+                    <label for="hb_url">Endpoint URL</label>
+                    <input type="url" id="hb_url" name="hb_url" value="{}" maxlength="128" placeholder="https://">
+                </div>
+                <div class="form-group">
+                    <label for="hb_interval">Interval (seconds, 30-86400)</label>
+                    <input type="number" id="hb_interval" name="hb_interval" value="{}" min="30" max="86400">
+                </div>
+                <div class="form-group">
+                    <label for="site_name">Site Name</label>
+                    <input type="text" id="site_name" name="site_name" value="{}" maxlength="63">
+                    <p class="hint">{}</p>
+                </div>
+                <div class="form-group">
+                    <label for="pub_port">Public Status Port ({}-65535, 0 = off)</label>
+                    <input type="number" id="pub_port" name="pub_port" value="{}" min="0" max="65535">
+                    <p class="hint">Serves read-only status JSON at {} on this port with no configuration pages, for exposing to a NOC. Applies after reboot.</p>
+                </div>
+                <div class="form-group">
+                    <label for="con_port">Remote Console Port (0 = off)</label>
+                    <input type="number" id="con_port" name="con_port" value="{}" min="0" max="65535">
+                    <p class="hint">Serial console commands over telnet for portal admin accounts. Sessions are not encrypted - use on trusted networks only. Factory reset still needs the USB port. Applies after reboot.</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Webhook Notifications</h2>
+                <div class="form-group">
+                    <label for="wh_enabled">Webhook</label>
+                    <select id="wh_enabled" name="wh_enabled">
+                        <option value="0" {}>Disabled</option>
+                        <option value="1" {}>Enabled</option>
+                    </select>
+                    <p class="hint">POSTs JSON when a device appears in or drops out of a scan, when errors pass the threshold, and after scheduled scans</p>
+                </div>
+                <div class="form-group">
+                    <label for="wh_url">Webhook URL</label>
+                    <input type="url" id="wh_url" name="wh_url" value="{}" maxlength="128" placeholder="https://">
+                </div>
+                <div class="form-group">
+                    <label for="wh_scan_h">Scheduled Discovery (hours, 0 = off)</label>
+                    <input type="number" id="wh_scan_h" name="wh_scan_h" value="{}" min="0" max="{}">
+                </div>
+                <div class="form-group">
+                    <label for="wh_err_thr">Error Threshold (per {} minutes, 0 = off)</label>
+                    <input type="number" id="wh_err_thr" name="wh_err_thr" value="{}" min="0">
+                    <p class="hint">{}</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Time</h2>
+                <div class="form-group">
+                    <label for="ntp_server">NTP Server</label>
+                    <input type="text" id="ntp_server" name="ntp_server" value="{}" maxlength="63" placeholder="pool.ntp.org">
+                </div>
+                <div class="form-group">
+                    <label for="timezone">Timezone (POSIX TZ)</label>
+                    <input type="text" id="timezone" name="timezone" value="{}" maxlength="63" placeholder="UTC0">
+                    <p class="hint">{}. Captures and events are stamped in UTC; log lines and the Device object's Local_Date, Local_Time, UTC_Offset and Daylight_Savings_Status use local time, with DST by the rule after the zone names (e.g. CET-1CEST,M3.5.0,M10.5.0/3).</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Lifetime Statistics</h2>
+                <div class="form-group">
+                    <label for="life_stats">Keep Totals Across Reboots</label>
+                    <select id="life_stats" name="life_stats">
+                        <option value="0" {}>Off (since-boot counters only)</option>
+                        <option value="1" {}>On (checkpoint to flash)</option>
+                    </select>
+                    <p class="hint">Routed frames, errors, uptime, boot count and last reboot reason, saved every few minutes and at shutdown</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Event Log</h2>
+                <div class="form-group">
+                    <label for="evt_spill">Keep Warnings Across Reboots</label>
+                    <select id="evt_spill" name="evt_spill">
+                        <option value="0" {}>Off (RAM only)</option>
+                        <option value="1" {}>On (save to flash)</option>
+                    </select>
+                    <p class="hint">The last {} warnings and errors, saved at most once a minute and at shutdown</p>
+                </div>
+                <div class="form-group">
+                    <label for="log_json">Serial Log Format</label>
+                    <select id="log_json" name="log_json">
+                        <option value="0" {}>Text (ESP-IDF)</option>
+                        <option value="1" {}>JSON lines</option>
+                    </select>
+                    <p class="hint">One JSON object per line with timestamp, level, module, message and key=value fields, for log collectors. Also used by 'logs on' in the remote console</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Write Protect</h2>
+                <div class="form-group">
+                    <label for="wp_pin">Jumper Pin</label>
+                    <select id="wp_pin" name="wp_pin">
+                        <option value="0" {}>None</option>
+                        <option value="25" {}>G25 (HAT header)</option>
+                        <option value="32" {}>G32 (Grove)</option>
+                        <option value="33" {}>G33 (Grove)</option>
+                    </select>
+                    <p class="hint">A jumper from this pin to GND refuses every configuration change (portal, console, BACnet writes, Write-BDT) until it is removed. Jumper {}. Applies after reboot.</p>
+                </div>
+            </div>
+
+            <div class="card">
+                <h2>Redundancy</h2>
+                <div class="form-group">
+                    <label for="fo_role">Pair Role</label>
+                    <select id="fo_role" name="fo_role">
+                        <option value="off" {}>Off (single unit)</option>
+                        <option value="primary" {}>Primary</option>
+                        <option value="standby" {}>Standby</option>
+                    </select>
+                    <p class="hint">Two units with the same station address, networks and device instance. The standby stays silent and takes over when the primary is gone from both IP and MS/TP for {}s, and hands back {}s after the primary returns. {}. Applies after reboot.</p>
+                </div>
+                <div class="form-group">
+                    <label for="fo_peer">Other Unit</label>
+                    <input type="text" id="fo_peer" name="fo_peer" value="{}" maxlength="15" placeholder="IP address">
+                    <p class="hint">Heartbeats are exchanged on UDP port {}</p>
+                </div>
+            </div>
+
+            <div class="button-row">
+                <button type="submit" class="btn btn-primary">Apply Changes</button>
+                <button type="submit" name="check" value="1" class="btn">Check Only</button>
+            </div>
+        </form>
+
+        <div class="card">
+            <h2>Persist Settings</h2>
+            <p>Save configuration to flash memory (NVS) for persistence across reboots.</p>
+            <div class="button-row">
+                <form method="POST" action="/save" style="display:inline">
+                    <button type="submit" class="btn btn-success">Save to NVS</button>
+                </form>
+                <form method="POST" action="/reset" style="display:inline" onsubmit="return confirm('Reset all settings to defaults?')">
+                    <button type="submit" class="btn btn-warning">Reset Defaults</button>
+                </form>
+                <form method="POST" action="/reboot" style="display:inline" onsubmit="return confirm('Reboot the gateway?')">
+                    <button type="submit" class="btn btn-danger">Reboot</button>
+                </form>
+            </div>
+        </div>
+
+        {schedule_card}
+
+        <div class="card">
+            <h2>Encrypted Backup</h2>
+            <p class="hint">A full copy of the configuration, including WiFi and AP passwords, encrypted with a passphrase (AES-256-GCM). Use it to move settings to another unit; the JSON export leaves secrets out.</p>
+            <form method="POST" action="/backup/export">
+                <div class="form-group">
+                    <label for="backup_pass">Passphrase</label>
+                    <input type="password" id="backup_pass" name="passphrase" minlength="{min_pass}" required>
+                </div>
+                <button type="submit" class="btn">Download Backup</button>
+            </form>
+            <form method="POST" action="/backup/import" onsubmit="return confirm('Replace the current configuration with this backup?')">
+                <div class="form-group">
+                    <label for="backup_file">Backup File</label>
+                    <input type="file" id="backup_file" accept=".bak,.txt" onchange="const f = this.files[0]; if (f) f.text().then(t => document.getElementById('backup_blob').value = t.trim());">
+                    <textarea id="backup_blob" name="backup" rows="3" placeholder="or paste the backup text" required></textarea>
+                </div>
+                <div class="form-group">
+                    <label for="restore_pass">Passphrase</label>
+                    <input type="password" id="restore_pass" name="passphrase" minlength="{min_pass}" required>
+                </div>
+                <button type="submit" class="btn btn-warning">Restore Backup</button>
+            </form>
+        </div>
+
+        <p class="footer">BACman v0.1.0 | Changes take effect after reboot</p>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        message_html,
+        state.config.wifi_ssid,
+        state.config.ap_ssid,
+        if state.config.ap_with_sta { "" } else { "selected" },
+        if state.config.ap_with_sta { "selected" } else { "" },
+        state.config.ap_timeout_minutes,
+        state.config.mstp_address,
+        if state.config.mstp_slave_mode { "" } else { "selected" },
+        if state.config.mstp_slave_mode { "selected" } else { "" },
+        if state.config.mstp_auto_address { "" } else { "selected" },
+        if state.config.mstp_auto_address { "selected" } else { "" },
+        state.config.mstp_max_master,
+        if state.config.mstp_baud_rate == 9600 { "selected" } else { "" },
+        if state.config.mstp_baud_rate == 19200 { "selected" } else { "" },
+        if state.config.mstp_baud_rate == 38400 { "selected" } else { "" },
+        if state.config.mstp_baud_rate == 57600 { "selected" } else { "" },
+        if state.config.mstp_baud_rate == 76800 { "selected" } else { "" },
+        if state.config.mstp_baud_rate == 115200 { "selected" } else { "" },
+        state.config.mstp_network,
+        if state.config.mstp_pfm_aggressiveness == 0 { "selected" } else { "" },
+        if state.config.mstp_pfm_aggressiveness == 1 { "selected" } else { "" },
+        if state.config.mstp_pfm_aggressiveness == 2 { "selected" } else { "" },
+        if state.config.mstp_pfm_aggressiveness == 3 { "selected" } else { "" },
+        state.config.mstp_max_npdu,
+        MIN_MSTP_MAX_NPDU,
+        MSTP_MAX_NPDU,
+        MAX_SEND_QUEUE,
+        state.config.backpressure_high,
+        MAX_SEND_QUEUE,
+        state.config.backpressure_low,
+        MAX_SEND_QUEUE,
+        state.config.bacnet_ip_port,
+        state.config.ip_network,
+        if state.config.ip_network_learn { "" } else { "selected" },
+        if state.config.ip_network_learn { "selected" } else { "" },
+        if state.config.bip_multicast_enabled { "" } else { "selected" },
+        if state.config.bip_multicast_enabled { "selected" } else { "" },
+        state.config.bip_multicast_group,
+        if state.config.bip_broadcast_form == BroadcastForm::Directed { "selected" } else { "" },
+        if state.config.bip_broadcast_form == BroadcastForm::Limited { "selected" } else { "" },
+        if state.config.bip_broadcast_form == BroadcastForm::Both { "selected" } else { "" },
+        if state.config.bip_broadcast_form == BroadcastForm::Disabled { "selected" } else { "" },
+        format_supervisory_stations(&state.config.supervisory_stations),
+        MAX_SUPERVISORY_STATIONS,
+        compat::format_rules(&state.config.compat_rules),
+        compat::MAX_COMPAT_RULES,
+        if state.config.suppress_on_duplicate_network { "" } else { "selected" },
+        if state.config.suppress_on_duplicate_network { "selected" } else { "" },
+        if state.config.whois_filter_enabled { "" } else { "selected" },
+        if state.config.whois_filter_enabled { "selected" } else { "" },
+        if state.config.announce_scope == AnnounceScope::Both { "selected" } else { "" },
+        if state.config.announce_scope == AnnounceScope::Ip { "selected" } else { "" },
+        if state.config.announce_scope == AnnounceScope::Mstp { "selected" } else { "" },
+        if state.config.announce_scope == AnnounceScope::Off { "selected" } else { "" },
+        format_network_list(&state.config.announce_networks),
+        MAX_ANNOUNCED_NETWORKS,
+        state.config.npdu_hop_count,
+        if state.config.npdu_priority == NetworkPriority::Normal { "selected" } else { "" },
+        if state.config.npdu_priority == NetworkPriority::Urgent { "selected" } else { "" },
+        if state.config.npdu_priority == NetworkPriority::CriticalEquipment { "selected" } else { "" },
+        if state.config.npdu_priority == NetworkPriority::LifeSafety { "selected" } else { "" },
+        if state.config.npdu_lenient { "" } else { "selected" },
+        if state.config.npdu_lenient { "selected" } else { "" },
+        state.config.bbmd_address.map(|a| a.to_string()).unwrap_or_default(),
+        if state.config.secure_bvll_drop { "" } else { "selected" },
+        if state.config.secure_bvll_drop { "selected" } else { "" },
+        if state.config.local_device_enabled { "selected" } else { "" },
+        if state.config.local_device_enabled { "" } else { "selected" },
+        state.config.device_instance,
+        state.config.device_name,
+        html_escape(&state.config.device_location),
+        html_escape(&state.config.device_description),
+        html_escape(&state.config.device_serial_number),
+        state.config.device_services.format(),
+        if state.config.heartbeat_enabled { "" } else { "selected" },
+        if state.config.heartbeat_enabled { "selected" } else { "" },
+        html_escape(&state.config.heartbeat_url),
+        state.config.heartbeat_interval_secs,
+        html_escape(&state.config.site_name),
+        heartbeat_summary(&state.heartbeat),
+        MIN_PUBLIC_PORT,
+        state.config.public_status_port,
+        PUBLIC_STATUS_PATH,
+        state.config.remote_console_port,
+        if state.config.webhook_enabled { "" } else { "selected" },
+        if state.config.webhook_enabled { "selected" } else { "" },
+        html_escape(&state.config.webhook_url),
+        state.config.webhook_scan_hours,
+        notify::MAX_SCAN_INTERVAL_HOURS,
+        notify::ERROR_WINDOW.as_secs() / 60,
+        state.config.webhook_error_threshold,
+        webhook_summary(&state.notifier),
+        html_escape(&state.config.ntp_server),
+        html_escape(&state.config.timezone),
+        clock_summary(),
+        if state.config.lifetime_stats_enabled { "" } else { "selected" },
+        if state.config.lifetime_stats_enabled { "selected" } else { "" },
+        if state.config.event_spill_enabled { "" } else { "selected" },
+        if state.config.event_spill_enabled { "selected" } else { "" },
+        events::MAX_SPILLED_EVENTS,
+        if state.config.log_json { "" } else { "selected" },
+        if state.config.log_json { "selected" } else { "" },
+        if state.config.write_protect_pin == 0 { "selected" } else { "" },
+        if state.config.write_protect_pin == 25 { "selected" } else { "" },
+        if state.config.write_protect_pin == 32 { "selected" } else { "" },
+        if state.config.write_protect_pin == 33 { "selected" } else { "" },
+        if write_protect::is_locked() { "fitted - configuration locked" } else { "not fitted" },
+        if state.config.failover_role == FailoverRole::Off { "selected" } else { "" },
+        if state.config.failover_role == FailoverRole::Primary { "selected" } else { "" },
+        if state.config.failover_role == FailoverRole::Standby { "selected" } else { "" },
+        PEER_TIMEOUT.as_secs(),
+        FALLBACK_HOLD.as_secs(),
+        failover_summary(state),
+        state.config.failover_peer.map(|a| a.to_string()).unwrap_or_default(),
+        HEARTBEAT_PORT,
+    )
+}
+
+/// One-line redundancy state for the config page
+fn failover_summary(state: &WebState) -> String {
+    let Some(status) = &state.failover else { return "Not paired".to_string() };
+    let peer = match status.peer_heard_secs {
+        Some(secs) => format!("other unit {} {}s ago", if status.peer_active { "active" } else { "standing by" }, secs),
+        None => "other unit not heard".to_string(),
+    };
+    format!(
+        "This unit is {}, {}, {} takeover(s)",
+        if status.active { "active" } else { "standing by" },
+        peer,
+        status.takeovers
+    )
+}
+
+/// One-line wall clock summary for the config page
+fn clock_summary() -> String {
+    match clock::utc_now() {
+        Some(now) => format!("Clock synced: {} ({})", html_escape(&clock::format_local(now)), clock::format_utc(now)),
+        None => "Clock not synced yet (needs Station mode and NTP access)".to_string(),
+    }
+}
+
+/// One-line heartbeat delivery summary for the config page
+fn heartbeat_summary(hb: &HeartbeatStatus) -> String {
+    if hb.consecutive_failures > 0 {
+        format!("{} failed attempts, last error: {}", hb.consecutive_failures, html_escape(&hb.last_error))
+    } else if let Some(t) = hb.last_success {
+        format!("{} heartbeats sent, last {}s ago", hb.sent, t.elapsed().as_secs())
+    } else {
+        "No heartbeat sent yet".to_string()
+    }
+}
+
+/// One-line webhook delivery summary for the config page
+fn webhook_summary(notifier: &Notifier) -> String {
+    if notifier.consecutive_failures > 0 {
+        format!(
+            "{} failed attempts, {} waiting, last error: {}",
+            notifier.consecutive_failures,
+            notifier.pending_len(),
+            html_escape(&notifier.last_error)
+        )
+    } else if let Some(t) = notifier.last_success {
+        format!("{} notifications sent, last {}s ago", notifier.sent, t.elapsed().as_secs())
+    } else {
+        "No notification sent yet".to_string()
+    }
+}
+
+/// Generate status JSON for API endpoint
+fn generate_status_json(state: &WebState) -> String {
+    // Convert discovered_masters bitmap to hex string for the device grid
+    let masters_hex = format!("{:032x}", state.mstp_stats.discovered_masters);
+
+    format!(r#"{{"rx_frames":{},"tx_frames":{},"crc_errors":{},"frame_errors":{},"reply_timeouts":{},"tokens_received":{},"token_pass_failures":{},"deadline_near_misses":{},"token_loop_ms":{},"token_loop_min_ms":{},"token_loop_max_ms":{},"token_loop_avg_ms":{},"master_count":{},"mstp_to_ip":{},"ip_to_mstp":{},"wifi_connected":{},"discovered_masters":"{}","current_state":{},"next_station":{},"poll_station":{},"silence_ms":{},"station_address":{},"sole_master":{},"send_queue_len":{},"receive_queue_len":{},"uart_framing_errors":{},"uart_parity_errors":{},"uart_overflows":{},"uart_breaks":{},"noise_bytes":{},"uptime_secs":{},"uptime":"{}","network_conflict":{},"held_now":{},"held_frames":{},"held_released":{},"held_expired":{},"held_overflows":{},"mstp_to_ip_bytes":{},"ip_to_mstp_bytes":{},"routing_errors":{},"transaction_timeouts":{},"active_transactions":{},"fdt_entries":{},"bdt_entries":{},"rejects_received":{},"ip_tx_queue_len":{},"ip_tx_dropped":{},"ip_tx_errors":{},"ip_socket_recoveries":{},"data_crc_avg_ns":{},"data_crc_max_ns":{},"poll_interval_avg_us":{},"poll_interval_max_us":{},"poll_jitter_us":{},"messages_too_long":{},"port_disabled_drops":{},"mstp_port_enabled":{},"ip_port_enabled":{},"rpm_proxied":{},"wpm_proxied":{},"whois_suppressed":{},"backpressure_aborts":{},"secure_bvll_received":{},"secure_bvll_sources":"{}","npdu_quirks":{{{}}},"supervisory_stations":[{}],"display_render_us":{},"display_render_max_us":{},"slave_mode":{},"slave_discarded_frames":{},"test_requests_received":{},"test_responses_sent":{},"shed_features":[{}],"ip_network":{},"ip_network_quality":"{}","router_mode":"{}"}}"#,
+        state.mstp_stats.rx_frames,
+        state.mstp_stats.tx_frames,
+        state.mstp_stats.crc_errors,
+        state.mstp_stats.frame_errors,
+        state.mstp_stats.reply_timeouts,
+        state.mstp_stats.tokens_received,
+        state.mstp_stats.token_pass_failures,
+        state.mstp_stats.deadline_near_misses,
+        state.mstp_stats.token_loop_time_ms,
+        state.mstp_stats.token_loop_min_ms,
+        state.mstp_stats.token_loop_max_ms,
+        state.mstp_stats.token_loop_avg_ms,
+        state.mstp_stats.master_count,
+        state.gateway_stats.mstp_to_ip_packets,
+        state.gateway_stats.ip_to_mstp_packets,
+        state.wifi_connected,
+        masters_hex,
+        state.mstp_stats.current_state,
+        state.mstp_stats.next_station,
+        state.mstp_stats.poll_station,
+        state.mstp_stats.silence_ms,
+        state.mstp_stats.station_address,
+        state.mstp_stats.sole_master,
+        state.mstp_stats.send_queue_len,
+        state.mstp_stats.receive_queue_len,
+        state.mstp_stats.uart_framing_errors,
+        state.mstp_stats.uart_parity_errors,
+        state.mstp_stats.uart_fifo_overflows + state.mstp_stats.uart_buffer_overflows,
+        state.mstp_stats.uart_breaks,
+        state.mstp_stats.noise_bytes,
+        state.uptime_secs(),
+        state.uptime_formatted(),
+        network_conflict_json(state.network_conflict.as_ref()),
+        state.gateway_stats.held_now,
+        state.gateway_stats.held_frames,
+        state.gateway_stats.held_released,
+        state.gateway_stats.held_expired,
+        state.gateway_stats.held_overflows,
+        state.gateway_stats.mstp_to_ip_bytes,
+        state.gateway_stats.ip_to_mstp_bytes,
+        state.gateway_stats.routing_errors,
+        state.gateway_stats.transaction_timeouts,
+        state.gateway_stats.active_transactions,
+        state.gateway_stats.fdt_entries,
+        state.gateway_stats.bdt_entries,
+        state.gateway_stats.rejects_received,
+        state.gateway_stats.ip_tx_queue_len,
+        state.gateway_stats.ip_tx_dropped,
+        state.gateway_stats.ip_tx_errors,
+        state.gateway_stats.ip_socket_recoveries,
+        state.mstp_stats.data_crc_avg_ns,
+        state.mstp_stats.data_crc_max_ns,
+        state.mstp_stats.poll_interval_avg_us,
+        state.mstp_stats.poll_interval_max_us,
+        state.mstp_stats.poll_jitter_us,
+        state.gateway_stats.messages_too_long,
+        state.gateway_stats.port_disabled_drops,
+        state.mstp_port_enabled,
+        state.ip_port_enabled,
+        state.gateway_stats.rpm_proxied,
+        state.gateway_stats.wpm_proxied,
+        state.gateway_stats.whois_suppressed,
+        state.gateway_stats.backpressure_aborts,
+        state.gateway_stats.secure_bvll_received,
+        state.gateway_stats.secure_bvll_sources.iter().map(|(ip, n)| format!("{} ({})", ip, n)).collect::<Vec<_>>().join(", "),
+        NpduQuirk::ALL.iter().map(|q| format!("\"{}\":{}", q.as_str(), state.gateway_stats.npdu_quirks[*q as usize])).collect::<Vec<_>>().join(","),
+        state.gateway_stats.supervisory_stations.iter().map(|s| format!(r#"{{"address":"{}","sent":{},"failed":{}}}"#, s.address, s.sent, s.failed)).collect::<Vec<_>>().join(","),
+        state.display_render_us,
+        state.display_render_max_us,
+        state.mstp_stats.slave_mode,
+        state.mstp_stats.slave_discarded_frames,
+        state.mstp_stats.test_requests_received,
+        state.mstp_stats.test_responses_sent,
+        state.shed_features.iter().map(|f| format!("\"{}\"", f.as_str())).collect::<Vec<_>>().join(","),
+        state.gateway_stats.ip_network.known().unwrap_or(0),
+        state.gateway_stats.ip_network.quality.as_str(),
+        state.gateway_stats.router_mode.as_str(),
+    )
+}
+
+/// Generate JSON for the last frames received on MS/TP
+fn generate_debug_frames_json(state: &WebState) -> String {
+    let frames: Vec<String> = state.last_rx_frames.iter()
+        .map(|(mac, data, at)| {
+            let hex = data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+            format!("{{\"mac\":{},\"time\":{},\"data\":\"{}\"}}", mac, utc_json(*at), hex)
+        })
+        .collect();
+    format!("{{\"frames\":[{}]}}", frames.join(","))
+}
+
+/// Blackbox entries as a JSON array, times in milliseconds since boot
+fn blackbox_entries_json(entries: &[Entry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| match &entry.record {
+            Record::Event { category, severity, text } => format!(
+                r#"{{"uptime_ms":{},"kind":"event","category":"{}","severity":"{}","text":"{}"}}"#,
+                entry.uptime_ms,
+                category.as_str(),
+                severity.as_str(),
+                json_escape(text)
+            ),
+            Record::Frame(frame) => format!(
+                r#"{{"uptime_ms":{},"kind":"frame","len":{},"text":"{}"}}"#,
+                entry.uptime_ms,
+                frame.len,
+                json_escape(&frame.to_string())
+            ),
+            Record::Heap { free } => format!(r#"{{"uptime_ms":{},"kind":"heap","free":{}}}"#, entry.uptime_ms, free),
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+/// Assemble the support bundle archive from the web state
+fn generate_support_bundle(state: &WebState) -> Vec<u8> {
+    let heap = HeapStats::read();
+    let mut tar = Tar::new(clock::utc_secs().unwrap_or(0));
+
+    let system = format!(
+        r#"{{"firmware":"{}","generated":{},"uptime_secs":{},"free_heap":{},"min_free_heap":{},"largest_free_block":{}}}"#,
+        env!("CARGO_PKG_VERSION"),
+        utc_json(std::time::Instant::now()),
+        state.uptime_secs(),
+        heap.free,
+        heap.min_free,
+        heap.largest_block
+    );
+    tar.add_file("system.json", system.as_bytes());
+    // Configuration (passwords left out), statistics and the event log
+    tar.add_file("export.json", generate_export_json(state).as_bytes());
+    tar.add_file("status.json", generate_status_json(state).as_bytes());
+    tar.add_file("errors.json", generate_errors_json().as_bytes());
+    tar.add_file("peers.json", generate_peers_json(state).as_bytes());
+
+    let capture = format!(
+        r#"{{"blackbox":{},"mstp_frames":{}}}"#,
+        blackbox_entries_json(&blackbox::current().unwrap_or_default()),
+        generate_debug_frames_json(state)
+    );
+    tar.add_file("capture.json", capture.as_bytes());
+    if let Some(report) = &state.crash_report {
+        let crash = format!(
+            r#"{{"reason":"{}","entries":{}}}"#,
+            report.reason.as_str(),
+            blackbox_entries_json(&report.entries)
+        );
+        tar.add_file("crash.json", crash.as_bytes());
+    }
+    tar.finish()
+}
+
+/// Generate export JSON with all diagnostic data
+fn generate_export_json(state: &WebState) -> String {
+    let masters_hex = format!("{:032x}", state.mstp_stats.discovered_masters);
+
+    // Build list of discovered device addresses
+    let mut devices = Vec::new();
+    for i in 0..128u8 {
+        if (state.mstp_stats.discovered_masters >> i) & 1 == 1 {
+            devices.push(i);
+        }
+    }
+    let devices_str: Vec<String> = devices.iter().map(|d| d.to_string()).collect();
+
+    // I-Am discovered devices with vendor names resolved
+    let devices_json: Vec<String> = state.discovered_devices.iter()
+        .map(|d| {
+            let label = state.device_label(d.device_instance);
+            format!(
+                r#"{{"mac":{},"instance":{},"object_name":"{}","name":"{}","notes":"{}","vendor_id":{},"vendor_name":"{}","last_scan":{},"last_seen_secs":{}}}"#,
+                d.mac_address, d.device_instance,
+                json_escape(state.device_name(d.device_instance).unwrap_or("")),
+                json_escape(label.map(|l| l.name.as_str()).unwrap_or("")),
+                json_escape(label.map(|l| l.notes.as_str()).unwrap_or("")),
+                d.vendor_id, d.vendor_name(),
+                d.last_scan_id, d.last_seen.map(|t| t.elapsed().as_secs()).unwrap_or(0)
+            )
+        })
+        .collect();
+    let devices_json = devices_json.join(",");
+
+    // All labels, including devices not heard from since boot
+    let labels_json: Vec<String> = state.device_labels.iter()
+        .map(|l| format!(
+            r#"{{"instance":{},"name":"{}","notes":"{}"}}"#,
+            l.device_instance, json_escape(&l.name), json_escape(&l.notes)
+        ))
+        .collect();
+    let labels_json = labels_json.join(",");
+
+    format!(r#"{{
+  "export_time": "{}",
+  "uptime_secs": {},
+  "uptime": "{}",
+  "device": {{
+    "name": "{}",
+    "instance": {},
+    "station_address": {},
+    "ip_address": "{}",
+    "location": "{}",
+    "description": "{}",
+    "serial_number": "{}",
+    "services": "{}"
+  }},
+  "networks": {{
+    "mstp_network": {},
+    "ip_network": {},
+    "ip_network_learn": {},
+    "baud_rate": {},
+    "bip_multicast_enabled": {},
+    "bip_multicast_group": "{}",
+    "bip_broadcast_form": "{}",
+    "supervisory_stations": "{}",
+    "compat_profiles": "{}",
+    "suppress_on_duplicate_network": {},
+    "whois_filter_enabled": {},
+    "announce_scope": "{}",
+    "announce_networks": "{}",
+    "npdu_hop_count": {},
+    "npdu_priority": "{}",
+    "npdu_lenient": {},
+    "secure_bvll_drop": {},
+    "backpressure_high": {},
+    "backpressure_low": {},
+    "network_conflict": {}
+  }},
+  "mstp_stats": {{
+    "rx_frames": {},
+    "tx_frames": {},
+    "tokens_received": {},
+    "crc_errors": {},
+    "frame_errors": {},
+    "reply_timeouts": {},
+    "token_pass_failures": {},
+    "deadline_near_misses": {},
+    "master_count": {},
+    "discovered_masters_hex": "{}",
+    "discovered_addresses": [{}]
+  }},
+  "token_loop_timing": {{
+    "current_ms": {},
+    "min_ms": {},
+    "max_ms": {},
+    "avg_ms": {}
+  }},
+  "line_quality": {{
+    "uart_framing_errors": {},
+    "uart_parity_errors": {},
+    "uart_fifo_overflows": {},
+    "uart_buffer_overflows": {},
+    "overrun_bytes": {},
+    "uart_breaks": {},
+    "noise_bytes": {}
+  }},
+  "queues": {{
+    "send_queue_len": {},
+    "receive_queue_len": {}
+  }},
+  "state_machine": {{
+    "current_state": "{}",
+    "sole_master": {},
+    "next_station": {},
+    "poll_station": {},
+    "silence_ms": {},
+    "ring_stable": {},
+    "pfm_interval": {},
+    "slave_mode": {},
+    "slave_discarded_frames": {},
+    "test_requests_received": {},
+    "test_responses_sent": {}
+  }},
+  "gateway_stats": {{
+    "mstp_to_ip_packets": {},
+    "ip_to_mstp_packets": {},
+    "held_frames": {},
+    "held_released": {},
+    "held_expired": {},
+    "held_overflows": {},
+    "mstp_to_ip_bytes": {},
+    "ip_to_mstp_bytes": {},
+    "routing_errors": {},
+    "transaction_timeouts": {},
+    "active_transactions": {},
+    "fdt_entries": {},
+    "bdt_entries": {},
+    "ip_tx_dropped": {},
+    "ip_tx_errors": {},
+    "ip_socket_recoveries": {}
+  }},
+  "wifi": {{
+    "connected": {},
+    "ssid": "{}",
+    "ap_with_sta": {},
+    "ap_timeout_minutes": {}
+  }},
+  "lifetime_stats": {},
+  "write_protect": {{
+    "pin": {},
+    "locked": {}
+  }},
+  "events": {},
+  "heartbeat": {{
+    "enabled": {},
+    "url": "{}",
+    "interval_secs": {},
+    "site_name": "{}",
+    "public_status_port": {},
+    "remote_console_port": {},
+    "sent": {},
+    "consecutive_failures": {},
+    "last_success_secs": {},
+    "last_error": "{}"
+  }},
+  "webhook": {{
+    "enabled": {},
+    "url": "{}",
+    "scan_hours": {},
+    "error_threshold": {},
+    "sent": {},
+    "pending": {},
+    "dropped": {},
+    "consecutive_failures": {},
+    "last_error": "{}"
+  }},
+  "discovered_devices": [{}],
+  "device_labels": [{}]
+}}"#,
+        chrono_lite_timestamp(),
+        state.uptime_secs(),
+        state.uptime_formatted(),
+        state.config.device_name,
+        state.config.device_instance,
+        state.mstp_stats.station_address,
+        state.ip_address,
+        json_escape(&state.config.device_location),
+        json_escape(&state.config.device_description),
+        json_escape(&state.config.device_serial_number),
+        state.config.device_services.format(),
+        state.config.mstp_network,
+        state.config.ip_network,
+        state.config.ip_network_learn,
+        state.config.mstp_baud_rate,
+        state.config.bip_multicast_enabled,
+        state.config.bip_multicast_group,
+        state.config.bip_broadcast_form.as_str(),
+        format_supervisory_stations(&state.config.supervisory_stations),
+        compat::format_rules(&state.config.compat_rules),
+        state.config.suppress_on_duplicate_network,
+        state.config.whois_filter_enabled,
+        state.config.announce_scope.as_str(),
+        format_network_list(&state.config.announce_networks),
+        state.config.npdu_hop_count,
+        state.config.npdu_priority.as_str(),
+        state.config.npdu_lenient,
+        state.config.secure_bvll_drop,
+        state.config.backpressure_high,
+        state.config.backpressure_low,
+        network_conflict_json(state.network_conflict.as_ref()),
+        state.mstp_stats.rx_frames,
+        state.mstp_stats.tx_frames,
+        state.mstp_stats.tokens_received,
+        state.mstp_stats.crc_errors,
+        state.mstp_stats.frame_errors,
+        state.mstp_stats.reply_timeouts,
+        state.mstp_stats.token_pass_failures,
+        state.mstp_stats.deadline_near_misses,
+        state.mstp_stats.master_count,
+        masters_hex,
+        devices_str.join(","),
+        state.mstp_stats.token_loop_time_ms,
+        state.mstp_stats.token_loop_min_ms,
+        state.mstp_stats.token_loop_max_ms,
+        state.mstp_stats.token_loop_avg_ms,
+        state.mstp_stats.uart_framing_errors,
+        state.mstp_stats.uart_parity_errors,
+        state.mstp_stats.uart_fifo_overflows,
+        state.mstp_stats.uart_buffer_overflows,
+        state.mstp_stats.overrun_bytes,
+        state.mstp_stats.uart_breaks,
+        state.mstp_stats.noise_bytes,
+        state.mstp_stats.send_queue_len,
+        state.mstp_stats.receive_queue_len,
+        get_state_name(state.mstp_stats.current_state),
+        state.mstp_stats.sole_master,
+        state.mstp_stats.next_station,
+        state.mstp_stats.poll_station,
+        state.mstp_stats.silence_ms,
+        state.mstp_stats.ring_stable,
+        state.mstp_stats.pfm_interval,
+        state.mstp_stats.slave_mode,
+        state.mstp_stats.slave_discarded_frames,
+        state.mstp_stats.test_requests_received,
+        state.mstp_stats.test_responses_sent,
+        state.gateway_stats.mstp_to_ip_packets,
+        state.gateway_stats.ip_to_mstp_packets,
+        state.gateway_stats.held_frames,
+        state.gateway_stats.held_released,
+        state.gateway_stats.held_expired,
+        state.gateway_stats.held_overflows,
+        state.gateway_stats.mstp_to_ip_bytes,
+        state.gateway_stats.ip_to_mstp_bytes,
+        state.gateway_stats.routing_errors,
+        state.gateway_stats.transaction_timeouts,
+        state.gateway_stats.active_transactions,
+        state.gateway_stats.fdt_entries,
+        state.gateway_stats.bdt_entries,
+        state.gateway_stats.ip_tx_dropped,
+        state.gateway_stats.ip_tx_errors,
+        state.gateway_stats.ip_socket_recoveries,
+        state.wifi_connected,
+        state.config.wifi_ssid,
+        state.config.ap_with_sta,
+        state.config.ap_timeout_minutes,
+        lifetime_stats_json(state.lifetime_stats.as_ref()),
+        state.config.write_protect_pin,
+        write_protect::is_locked(),
+        events_json_array(state, &EventFilter { since: 0, category: None, min_severity: Severity::Info }),
+        state.config.heartbeat_enabled,
+        json_escape(&state.config.heartbeat_url),
+        state.config.heartbeat_interval_secs,
+        json_escape(&state.config.site_name),
+        state.config.public_status_port,
+        state.config.remote_console_port,
+        state.heartbeat.sent,
+        state.heartbeat.consecutive_failures,
+        state.heartbeat.last_success.map(|t| t.elapsed().as_secs().to_string()).unwrap_or_else(|| "null".to_string()),
+        json_escape(&state.heartbeat.last_error),
+        state.config.webhook_enabled,
+        json_escape(&state.config.webhook_url),
+        state.config.webhook_scan_hours,
+        state.config.webhook_error_threshold,
+        state.notifier.sent,
+        state.notifier.pending_len(),
+        state.notifier.dropped,
+        state.notifier.consecutive_failures,
+        json_escape(&state.notifier.last_error),
+        devices_json,
+        labels_json,
+    )
+}
+
+/// Simple timestamp (uptime in seconds since no RTC)
+fn chrono_lite_timestamp() -> String {
+    match clock::utc_now() {
+        Some(now) => clock::format_utc(now),
+        None => {
+            // Clock not set by SNTP yet: seconds since boot
+            let uptime = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!("uptime_{}s", uptime)
+        }
+    }
+}
+
+/// JSON UTC timestamp for an event, or null until SNTP has set the clock
+fn utc_json(at: std::time::Instant) -> String {
+    clock::utc_at(at)
+        .map(|t| format!(r#""{}""#, clock::format_utc(t)))
+        .unwrap_or_else(|| "null".to_string())
+}
+
+/// Generate JSON for discovered devices, optionally only those heard in one scan
+fn generate_devices_json(state: &WebState, scan_filter: Option<u32>) -> String {
+    let mut json = String::from(r#"{"scan_in_progress":"#);
+    json.push_str(if state.scan_in_progress { "true" } else { "false" });
+    json.push_str(&format!(
+        r#","scan_id":{},"scan_sent":{},"scan_total":{},"devices":["#,
+        state.scan_id, state.scan_progress.0, state.scan_progress.1
+    ));
+
+    let secs_ago = |t: Option<std::time::Instant>| t.map(|t| t.elapsed().as_secs()).unwrap_or(0);
+    let devices = state.discovered_devices.iter()
+        .filter(|d| scan_filter.map(|id| d.last_scan_id == id).unwrap_or(true));
+    for (i, device) in devices.enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let label = state.device_label(device.device_instance);
+        json.push_str(&format!(
+            r#"{{"mac":{},"instance":{},"object_name":"{}","name":"{}","notes":"{}","vendor":{},"vendor_name":"{}","max_apdu":{},"segmentation":{},"first_seen_secs":{},"last_seen_secs":{},"first_scan":{},"last_scan":{},"i_am_count":{},"stale":{}}}"#,
+            device.mac_address,
+            device.device_instance,
+            json_escape(state.device_name(device.device_instance).unwrap_or("")),
+            json_escape(label.map(|l| l.name.as_str()).unwrap_or("")),
+            json_escape(label.map(|l| l.notes.as_str()).unwrap_or("")),
+            device.vendor_id,
+            device.vendor_name(),
+            device.max_apdu_length,
+            device.segmentation,
+            secs_ago(device.first_seen),
+            secs_ago(device.last_seen),
+            device.first_scan_id,
+            device.last_scan_id,
+            device.i_am_count,
+            device.last_scan_id != state.scan_id
+        ));
+    }
+
+    json.push_str("]}");
+    json
+}
+
+/// CSS styles - Modern monochrome design
+const CSS_STYLES: &str = r#"
+* { box-sizing: border-box; margin: 0; padding: 0; }
+body { font-family: 'SF Mono', 'Fira Code', 'Consolas', monospace; background: #0a0a0a; color: #e0e0e0; line-height: 1.6; }
+.container { max-width: 800px; margin: 0 auto; padding: 24px; }
+h1 { color: #fff; text-align: center; margin-bottom: 24px; font-size: 1.5em; font-weight: 600; letter-spacing: 2px; text-transform: uppercase; }
+h2 { color: #fff; margin-bottom: 10px; font-size: 0.8em; font-weight: 500; letter-spacing: 1px; text-transform: uppercase; border-bottom: 1px solid #2a2a2a; padding-bottom: 6px; }
+nav { display: flex; justify-content: center; gap: 4px; margin-bottom: 24px; }
+nav a { color: #666; text-decoration: none; padding: 10px 24px; font-size: 0.85em; letter-spacing: 1px; text-transform: uppercase; border: 1px solid #222; transition: all 0.2s; }
+nav a:hover { color: #fff; border-color: #444; }
+nav a.active { color: #fff; background: #1a1a1a; border-color: #333; }
+.card { background: #111; border: 1px solid #222; padding: 16px; margin-bottom: 12px; }
+.card-header { display: flex; justify-content: space-between; align-items: center; margin-bottom: 10px; border-bottom: 1px solid #2a2a2a; padding-bottom: 6px; }
+.card-header h2 { margin-bottom: 0; border-bottom: none; padding-bottom: 0; }
+.status-grid { display: grid; grid-template-columns: repeat(auto-fit, minmax(120px, 1fr)); gap: 6px; }
+.status-item { background: #0a0a0a; border: 1px solid #1a1a1a; padding: 8px 10px; text-align: center; }
+.status-item .label { display: block; color: #555; font-size: 0.65em; letter-spacing: 1px; text-transform: uppercase; margin-bottom: 2px; }
+.status-item .value { display: block; font-size: 1.1em; font-weight: 600; color: #fff; font-variant-numeric: tabular-nums; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
+.status-item .value.auto-size { font-size: clamp(0.7em, 2.5vw, 1.1em); }
+.chip { display: inline-block; background: #333; color: #fff; padding: 2px 8px; font-size: 0.7em; font-weight: 400; margin-left: 8px; vertical-align: middle; }
+.status-item .value.ok { color: #888; }
+.status-item .value.error { color: #fff; background: #333; padding: 2px 8px; }
+.status-item .value.warning { color: #000; background: #fff; padding: 2px 8px; animation: blink 1s infinite; }
+@keyframes blink { 50% { opacity: 0.5; } }
+.alert-banner { background: #fff; color: #000; border: 1px solid #fff; padding: 12px 16px; margin-bottom: 12px; }
+.alert-banner p { font-size: 0.85em; margin: 4px 0 8px; }
+.device-grid { display: grid; grid-template-columns: repeat(16, 1fr); gap: 2px; margin-bottom: 12px; }
+.grid-cell { aspect-ratio: 1; background: #1a1a1a; border: 1px solid #222; display: flex; align-items: center; justify-content: center; font-size: 0.55em; color: #333; transition: all 0.2s; cursor: default; }
+.grid-cell.active { background: #333; color: #fff; border-color: #444; }
+.grid-cell.self { background: #fff; color: #000; border-color: #fff; font-weight: bold; }
+.grid-legend { display: flex; gap: 16px; justify-content: center; font-size: 0.75em; color: #666; }
+.legend-box { display: inline-block; width: 12px; height: 12px; border: 1px solid #333; margin-right: 4px; vertical-align: middle; }
+.legend-box.active { background: #333; }
+.legend-box.self { background: #fff; }
+.form-group { margin-bottom: 16px; }
+.form-group label { display: block; margin-bottom: 6px; color: #666; font-size: 0.75em; letter-spacing: 1px; text-transform: uppercase; }
+.hint { color: #555; font-size: 0.8em; margin: -8px 0 12px 0; font-style: italic; }
+.form-group input, .form-group select { width: 100%; padding: 12px; border: 1px solid #222; background: #0a0a0a; color: #fff; font-size: 0.95em; font-family: inherit; transition: border-color 0.2s; }
+.form-group input:focus, .form-group select:focus { outline: none; border-color: #444; }
+.form-group input::placeholder { color: #444; }
+.button-row { display: flex; gap: 6px; flex-wrap: wrap; margin-top: 12px; }
+.btn { padding: 8px 16px; border: 1px solid #333; background: transparent; color: #fff; cursor: pointer; font-size: 0.75em; font-family: inherit; letter-spacing: 1px; text-transform: uppercase; transition: all 0.2s; }
+.btn:hover { background: #1a1a1a; border-color: #444; }
+.btn-sm { padding: 4px 10px; font-size: 0.65em; }
+.btn-primary { background: #fff; color: #000; border-color: #fff; }
+.btn-primary:hover { background: #ccc; border-color: #ccc; }
+.btn-success { background: #333; border-color: #444; }
+.btn-success:hover { background: #444; }
+.btn-warning { background: #222; border-color: #333; }
+.btn-warning:hover { background: #333; }
+.btn-danger { background: #1a1a1a; border-color: #333; color: #888; }
+.btn-danger:hover { background: #2a2a2a; color: #fff; }
+.message { background: #111; border-left: 2px solid #444; padding: 16px; margin-bottom: 20px; font-size: 0.9em; }
+.footer { text-align: center; color: #333; margin-top: 32px; font-size: 0.75em; letter-spacing: 1px; }
+.footer a { color: #555; text-decoration: none; }
+.footer a:hover { color: #888; }
+.modal { display: none; position: fixed; top: 0; left: 0; width: 100%; height: 100%; background: rgba(0,0,0,0.8); justify-content: center; align-items: center; z-index: 1000; }
+.modal-content { background: #111; border: 1px solid #333; padding: 24px; max-width: 400px; width: 90%; }
+.modal-content h3 { margin-bottom: 16px; font-size: 1em; letter-spacing: 1px; text-transform: uppercase; border-bottom: 1px solid #222; padding-bottom: 8px; }
+.modal-content p { margin: 8px 0; font-size: 0.9em; }
+.modal-content p b { color: #888; }
+.device-row { display: flex; justify-content: space-between; padding: 12px; margin: 4px 0; background: #0a0a0a; border: 1px solid #1a1a1a; cursor: pointer; font-size: 0.85em; transition: all 0.2s; }
+.device-row:hover { background: #1a1a1a; border-color: #333; }
+.device-row span { color: #888; }
+.scan-status { color: #666; font-size: 0.85em; margin-bottom: 8px; }
+.grid-cell.active { cursor: pointer; }
+.grid-cell.active:hover { background: #444; transform: scale(1.1); }
+@media (max-width: 600px) { .container { padding: 16px; } .card { padding: 16px; } .btn { padding: 10px 16px; } .device-grid { grid-template-columns: repeat(8, 1fr); } .grid-cell { font-size: 0.5em; } }
+"#;
+
+/// HTML redirect to status page
+const HTML_REDIRECT_STATUS: &str = r#"<!DOCTYPE html>
+<html><head><meta http-equiv="refresh" content="0;url=/status"></head>
+<body>Redirecting to <a href="/status">status page</a>...</body></html>"#;
+
+/// HTML redirect to the commissioning wizard
+const HTML_REDIRECT_WIZARD: &str = r#"<!DOCTYPE html>
+<html><head><meta http-equiv="refresh" content="0;url=/wizard"></head>
+<body>Redirecting to <a href="/wizard">setup wizard</a>...</body></html>"#;
+
+/// HTML reboot page
+const HTML_REBOOT_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Rebooting</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>
+        body { font-family: 'SF Mono', 'Fira Code', 'Consolas', monospace; background: #0a0a0a; color: #e0e0e0; display: flex; justify-content: center; align-items: center; min-height: 100vh; }
+        .message { text-align: center; }
+        h1 { color: #fff; font-size: 1.2em; font-weight: 500; letter-spacing: 2px; text-transform: uppercase; }
+        .spinner { width: 40px; height: 40px; border: 2px solid #222; border-top: 2px solid #fff; border-radius: 50%; animation: spin 1s linear infinite; margin: 24px auto; }
+        @keyframes spin { 0% { transform: rotate(0deg); } 100% { transform: rotate(360deg); } }
+        p { color: #555; font-size: 0.85em; letter-spacing: 1px; }
+    </style>
+    <script>setTimeout(() => location.href = '/status', 10000);</script>
+</head>
+<body>
+    <div class="message">
+        <h1>Rebooting</h1>
+        <div class="spinner"></div>
+        <p>The gateway is restarting. You will be redirected automatically.</p>
+    </div>
+</body>
+</html>"#;
+
+/// Parse BDT add form data
+fn parse_bdt_add_form(body: &str, state: &mut WebState) -> &'static str {
+    let mut ip_str = String::new();
+    let mut port: u16 = 47808;
+    let mut mask_str = String::new();
+
+    for pair in body.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+        let value = urlencoding::decode(value).unwrap_or_default();
+
+        match key {
+            "ip" => ip_str = value.to_string(),
+            "port" => {
+                if let Ok(p) = value.parse::<u16>() {
+                    port = p;
+                }
+            }
+            "mask" => mask_str = value.to_string(),
+            _ => {}
+        }
+    }
+
+    // Parse IP address
+    let ip: Ipv4Addr = match ip_str.parse() {
+        Ok(ip) => ip,
+        Err(_) => return "Invalid IP address format",
+    };
+
+    // Parse subnet mask (default to 255.255.255.255 for host-specific)
+    let mask: Ipv4Addr = if mask_str.is_empty() {
+        Ipv4Addr::new(255, 255, 255, 255)
+    } else {
+        match mask_str.parse() {
+            Ok(m) => m,
+            Err(_) => return "Invalid subnet mask format",
+        }
+    };
+
+    // Create socket address
+    let addr = SocketAddr::new(std::net::IpAddr::V4(ip), port);
+
+    // Set request for main loop to process
+    state.bdt_add_request = Some((addr, mask));
+    info!("BDT add requested via web portal: {} mask {}", addr, mask);
+
+    "BDT entry add requested. Entry will be added."
+}
+
+/// Parse BDT remove form data
+fn parse_bdt_remove_form(body: &str, state: &mut WebState) -> &'static str {
+    let mut addr_str = String::new();
+
+    for pair in body.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+        let value = urlencoding::decode(value).unwrap_or_default();
+
+        if key == "addr" {
+            addr_str = value.to_string();
+        }
+    }
+
+    // Parse socket address (format: "IP:port")
+    let addr: SocketAddr = match addr_str.parse() {
+        Ok(a) => a,
+        Err(_) => return "Invalid address format (expected IP:port)",
+    };
+
+    state.bdt_remove_request = Some(addr);
+    info!("BDT remove requested via web portal: {}", addr);
+
+    "BDT entry remove requested. Entry will be removed."
+}
+
+/// Generate BDT JSON
+fn generate_bdt_json(state: &WebState) -> String {
+    let entries: Vec<String> = state.bdt_entries
+        .iter()
+        .map(|(addr, mask)| {
+            format!(
+                r#"{{"address":"{}","mask":"{}","reachability":{}}}"#,
+                addr, mask, ping_result_json(find_ping_result(state, addr))
+            )
+        })
+        .collect();
+    let hosts: Vec<String> = state.reachability
+        .iter()
+        .filter(|r| r.role != PingRole::BdtPeer)
+        .map(|r| format!(r#"{{"role":"{}","address":"{}","reachability":{}}}"#, r.role, r.addr, ping_result_json(Some(r))))
+        .collect();
+
+    format!(
+        r#"{{"entries":[{}],"hosts":[{}],"checking":{}}}"#,
+        entries.join(","),
+        hosts.join(","),
+        state.reachability_in_progress
+    )
+}
+
+/// Latest ping result for the host of a BDT entry
+fn find_ping_result<'a>(state: &'a WebState, addr: &SocketAddr) -> Option<&'a PingResult> {
+    state.reachability.iter().find(|r| std::net::IpAddr::V4(r.addr) == addr.ip())
+}
+
+fn ping_result_json(result: Option<&PingResult>) -> String {
+    match result {
+        Some(r) => format!(
+            r#"{{"reachable":{},"sent":{},"received":{},"loss_pct":{},"min_ms":{},"avg_ms":{},"max_ms":{},"age_secs":{},"error":{}}}"#,
+            r.reachable(),
+            r.transmitted,
+            r.received,
+            r.loss_pct(),
+            r.min_ms,
+            r.avg_ms,
+            r.max_ms,
+            r.checked_at.elapsed().as_secs(),
+            r.error.as_deref().map(|e| format!(r#""{}""#, json_escape(e))).unwrap_or_else(|| "null".to_string())
+        ),
+        None => "null".to_string(),
+    }
+}
+
+/// Ping status badge shown next to a host on the BDT page
+fn ping_result_html(result: Option<&PingResult>) -> String {
+    match result {
+        Some(r) => format!(
+            r#"<span class="ping {}" title="checked {}s ago">{}</span>"#,
+            if r.reachable() { "ok" } else { "fail" },
+            r.checked_at.elapsed().as_secs(),
+            html_escape(&r.summary())
+        ),
+        None => r#"<span class="ping">not checked</span>"#.to_string(),
+    }
+}
+
+/// This gateway's side of a pairing, once it has an IP address
+pub fn local_pair_info(state: &WebState) -> Option<PeerInfo> {
+    let ip = state.ip_address.parse::<Ipv4Addr>().ok().filter(|ip| !ip.is_unspecified())?;
+    Some(PeerInfo {
+        bip_address: SocketAddr::new(ip.into(), state.config.bacnet_ip_port),
+        ip_network: state.gateway_stats.ip_network.known().unwrap_or(state.config.ip_network),
+        mstp_network: state.config.mstp_network,
+        site_name: state.config.site_name.clone(),
+    })
+}
+
+/// Generate BDT page HTML
+fn generate_bdt_page(state: &WebState) -> String {
+    generate_bdt_page_with_message(state, "")
+}
+
+/// Generate BDT page HTML with optional message
+fn generate_bdt_page_with_message(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+
+    let entries_html: String = if state.bdt_entries.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No BDT entries configured</p>"#.to_string()
+    } else {
+        state.bdt_entries
+            .iter()
+            .map(|(addr, mask)| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">mask: {}</span>
+                        {}
+                        <form method="POST" action="/bdt/remove" style="display:inline">
+                            <input type="hidden" name="addr" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Remove</button>
+                        </form>
+                    </div>"#,
+                    addr, mask, ping_result_html(find_ping_result(state, addr)), addr
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let mut hosts: Vec<(PingRole, Ipv4Addr)> = Vec::new();
+    if let Some(gateway) = state.default_gateway {
+        hosts.push((PingRole::DefaultGateway, gateway));
+    }
+    if let Some(bbmd) = state.config.bbmd_address {
+        hosts.push((PingRole::Bbmd, bbmd));
+    }
+    let hosts_html: String = if hosts.is_empty() {
+        r#"<p style="color: #555;">No default gateway or BBMD configured</p>"#.to_string()
+    } else {
+        hosts
+            .iter()
+            .map(|(role, ip)| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">{}</span>
+                        {}
+                    </div>"#,
+                    ip,
+                    role,
+                    ping_result_html(state.reachability.iter().find(|r| r.addr == *ip))
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let pairing_html = match &state.pairing {
+        Some(pairing) => {
+            let peer = pairing.peer.as_ref().map(|p| {
+                let site = if p.site_name.is_empty() { String::new() } else { format!(" ({})", html_escape(&p.site_name)) };
+                format!(" - {}{}, MS/TP network {}", p.bip_address, site, p.mstp_network)
+            });
+            format!(
+                r#"<p class="ping {}">{}{}: {}</p>"#,
+                match pairing.stage {
+                    PairingStage::Paired => "ok",
+                    PairingStage::Failed(_) => "fail",
+                    _ => "",
+                },
+                pairing.peer_ip,
+                peer.unwrap_or_default(),
+                html_escape(&pairing.stage.to_string())
+            )
+        }
+        None => String::new(),
+    };
+    let pairing_busy = state.pairing.as_ref().is_some_and(|p| !p.stage.is_finished());
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - BDT Configuration</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <style>
+        .ping {{ color: #666; font-size: 0.8em; }}
+        .ping.ok {{ color: #6a6; }}
+        .ping.fail {{ color: #c66; }}
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 180px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+        .btn-danger {{ border-color: #633; }}
+        .btn-danger:hover {{ background: #633; border-color: #844; }}
+        .add-form {{ background: #111; border: 1px solid #222; padding: 16px; margin-top: 16px; }}
+        .add-form h3 {{ margin-bottom: 16px; font-size: 0.9em; }}
+        .form-row {{ display: flex; gap: 12px; align-items: end; flex-wrap: wrap; }}
+        .form-row .form-group {{ margin-bottom: 0; }}
+        .form-group.small {{ max-width: 100px; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/config">Config</a>
+            <a href="/bdt" class="active">BDT</a>
+            <a href="/fdt">FDT</a>
+            <a href="/routes">Routes</a>
+        </nav>
+
+        {}
+
+        <div class="card">
+            <h2>Broadcast Distribution Table</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                BDT entries define peer BBMDs for broadcast distribution across subnets.
+            </p>
+            {}
+        </div>
+
+        <div class="add-form">
+            <h3>Add BDT Entry</h3>
+            <form method="POST" action="/bdt/add">
+                <div class="form-row">
+                    <div class="form-group">
+                        <label>IP Address</label>
+                        <input type="text" name="ip" placeholder="192.168.1.100" required>
+                    </div>
+                    <div class="form-group small">
+                        <label>Port</label>
+                        <input type="number" name="port" value="47808" min="1" max="65535">
+                    </div>
+                    <div class="form-group">
+                        <label>Subnet Mask</label>
+                        <input type="text" name="mask" placeholder="255.255.255.255">
+                    </div>
+                    <button type="submit" class="btn">Add Entry</button>
+                </div>
+            </form>
+        </div>
+
+        <div style="margin-top: 16px; display: flex; gap: 8px;">
+            <form method="POST" action="/bdt/clear" onsubmit="return confirm('Clear all BDT entries?')">
+                <button type="submit" class="btn btn-danger">Clear All Entries</button>
+            </form>
+        </div>
+
+        <div class="add-form">
+            <h3>Pair with Peer BACman</h3>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Installs matching BDT entries and static routes on both gateways, then checks them with a test broadcast.
+                Leave the username empty to use an API key with the config scope.
+            </p>
+            {}
+            <form method="POST" action="/bdt/pair">
+                <div class="form-row">
+                    <div class="form-group">
+                        <label>Peer IP Address</label>
+                        <input type="text" name="peer" placeholder="10.20.0.5" required>
+                    </div>
+                    <div class="form-group">
+                        <label>Peer Username</label>
+                        <input type="text" name="user" autocomplete="off">
+                    </div>
+                    <div class="form-group">
+                        <label>Password or API Key</label>
+                        <input type="password" name="pass" autocomplete="off" required>
+                    </div>
+                    <button type="submit" class="btn" {}>{}</button>
+                </div>
+            </form>
+        </div>
+
+        <div class="card" style="margin-top: 16px;">
+            <h2>Reachability</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Sends {} ICMP echo requests to each BDT peer, the default gateway and the BBMD.
+                A host that answers ping but not BACnet points at BDT/BBMD settings rather than the network.
+            </p>
+            {}
+            <form method="POST" action="/bdt/ping" style="margin-top: 16px;">
+                <button type="submit" class="btn" {}>{}</button>
+            </form>
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        msg_html,
+        entries_html,
+        pairing_html,
+        if pairing_busy { "disabled" } else { "" },
+        if pairing_busy { "Pairing..." } else { "Pair" },
+        PING_COUNT,
+        hosts_html,
+        if state.reachability_in_progress { "disabled" } else { "" },
+        if state.reachability_in_progress { "Checking..." } else { "Check Reachability" }
+    )
+}
+
+/// Generate FDT JSON
+fn generate_fdt_json(state: &WebState) -> String {
+    let entries: Vec<String> = state.foreign_devices
+        .iter()
+        .map(|d| {
+            format!(
+                r#"{{"address":"{}","ttl_secs":{},"remaining_secs":{},"forwarded":{}}}"#,
+                d.address, d.ttl_seconds, d.remaining_seconds, d.forwarded
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{{"entries":[{}],"registrations_enabled":{}}}"#,
+        entries.join(","),
+        state.fd_registrations_enabled
+    )
+}
+
+/// Binding edit from a form body: `action=pin|unpin|remove` with `table=mstp|ip`
+/// and `key`, or `action=flush`
+fn parse_binding_edit(body: &str) -> Option<BindingEdit> {
+    let key = || BindingKey::parse(&form_value(body, "table")?, &form_value(body, "key")?);
+    match form_value(body, "action")?.as_str() {
+        "pin" => Some(BindingEdit::Pin(key()?, true)),
+        "unpin" => Some(BindingEdit::Pin(key()?, false)),
+        "remove" => Some(BindingEdit::Remove(key()?)),
+        "flush" => Some(BindingEdit::Flush),
+        _ => None,
+    }
+}
+
+/// Generate address bindings JSON
+fn generate_bindings_json(state: &WebState) -> String {
+    let entries = |bindings: &[AddressBindingSummary]| -> String {
+        bindings
+            .iter()
+            .map(|b| {
+                format!(
+                    r#"{{"mstp":{},"ip":"{}","age_secs":{},"pinned":{}}}"#,
+                    b.mstp_address, b.ip_address, b.age_seconds, b.pinned
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    format!(
+        r#"{{"mstp_to_ip":[{}],"ip_to_mstp":[{}]}}"#,
+        entries(&state.mstp_to_ip_bindings),
+        entries(&state.ip_to_mstp_bindings)
+    )
+}
+
+/// Generate address bindings page HTML with optional message
+fn generate_bindings_page(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+
+    // One table's entries; `table` and `key` name the entry in the edit form
+    let entries_html = |bindings: &[AddressBindingSummary], table: &str| -> String {
+        if bindings.is_empty() {
+            return r#"<p style="color: #555; text-align: center;">No bindings learned</p>"#.to_string();
+        }
+        bindings
+            .iter()
+            .map(|b| {
+                let (from, to, key) = if table == "mstp" {
+                    (format!("MS/TP {}", b.mstp_address), b.ip_address.to_string(), b.mstp_address.to_string())
+                } else {
+                    (b.ip_address.to_string(), format!("MS/TP {}", b.mstp_address), b.ip_address.to_string())
+                };
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{} &rarr; {}</span>
+                        <span class="mask">{}</span>
+                        <form method="POST" action="/bindings/edit" style="display:inline">
+                            <input type="hidden" name="action" value="{}">
+                            <input type="hidden" name="table" value="{}">
+                            <input type="hidden" name="key" value="{}">
+                            <button type="submit" class="btn btn-small">{}</button>
+                        </form>
+                        <form method="POST" action="/bindings/edit" style="display:inline">
+                            <input type="hidden" name="action" value="remove">
+                            <input type="hidden" name="table" value="{}">
+                            <input type="hidden" name="key" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Delete</button>
+                        </form>
+                    </div>"#,
+                    from,
+                    to,
+                    if b.pinned { "Pinned".to_string() } else { format!("Seen {}s ago", b.age_seconds) },
+                    if b.pinned { "unpin" } else { "pin" },
+                    table,
+                    key,
+                    if b.pinned { "Unpin" } else { "Pin" },
+                    table,
+                    key
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Address Bindings</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 260px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+        .btn-danger {{ border-color: #633; }}
+        .btn-danger:hover {{ background: #633; border-color: #844; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/config">Config</a>
+            <a href="/bdt">BDT</a>
+            <a href="/fdt">FDT</a>
+            <a href="/routes">Routes</a>
+            <a href="/bindings" class="active">Bindings</a>
+        </nav>
+
+        {}
+
+        <div class="card">
+            <h2>MS/TP to IP</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Address translations learned from routed traffic. Unpinned entries age out
+                when unused; pinned entries stay until deleted. Tables are not kept across a restart.
+            </p>
+            {}
+        </div>
+
+        <div class="card" style="margin-top: 16px;">
+            <h2>IP to MS/TP</h2>
+            {}
+        </div>
+
+        <div class="card" style="margin-top: 16px;">
+            <h2>Flush</h2>
+            <div class="bdt-entry">
+                <span class="addr">Remove all unpinned bindings</span>
+                <span class="mask">{} MS/TP to IP, {} IP to MS/TP</span>
+                <form method="POST" action="/bindings/edit" style="display:inline">
+                    <input type="hidden" name="action" value="flush">
+                    <button type="submit" class="btn btn-small btn-danger">Flush</button>
+                </form>
+            </div>
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        msg_html,
+        entries_html(&state.mstp_to_ip_bindings, "mstp"),
+        entries_html(&state.ip_to_mstp_bindings, "ip"),
+        state.mstp_to_ip_bindings.len(),
+        state.ip_to_mstp_bindings.len()
+    )
+}
+
+/// Generate FDT page HTML with optional message
+fn generate_fdt_page(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+
+    let entries_html: String = if state.foreign_devices.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No foreign devices registered</p>"#.to_string()
+    } else {
+        state.foreign_devices
+            .iter()
+            .map(|d| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">TTL {}s, {}s remaining, {} forwarded</span>
+                        <form method="POST" action="/fdt/remove" style="display:inline">
+                            <input type="hidden" name="addr" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Delete</button>
+                        </form>
+                    </div>"#,
+                    d.address, d.ttl_seconds, d.remaining_seconds, d.forwarded, d.address
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let accepting = state.fd_registrations_request.unwrap_or(state.fd_registrations_enabled);
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Foreign Devices</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 180px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+        .btn-danger {{ border-color: #633; }}
+        .btn-danger:hover {{ background: #633; border-color: #844; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/config">Config</a>
+            <a href="/bdt">BDT</a>
+            <a href="/fdt" class="active">FDT</a>
+            <a href="/routes">Routes</a>
+            <a href="/bindings">Bindings</a>
+        </nav>
+
+        {}
+
+        <div class="card">
+            <h2>Foreign Device Table</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Devices on other subnets registered with this gateway to send and receive broadcasts.
+                A deleted device is re-added when it next registers.
+            </p>
+            {}
+        </div>
+
+        <div class="card" style="margin-top: 16px;">
+            <h2>Registrations</h2>
+            <div class="bdt-entry">
+                <span class="addr">Register-Foreign-Device</span>
+                <span class="mask">{}</span>
+                <form method="POST" action="/fdt/registrations" style="display:inline">
+                    <input type="hidden" name="enabled" value="{}">
+                    <button type="submit" class="btn btn-small{}">{}</button>
+                </form>
+            </div>
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        msg_html,
+        entries_html,
+        if accepting { "Accepted" } else { "Rejected (NAK)" },
+        if accepting { 0 } else { 1 },
+        if accepting { " btn-danger" } else { "" },
+        if accepting { "Reject" } else { "Accept" }
+    )
+}
+
+/// Event log query from the `/events` and `/api/events` query string
+struct EventFilter {
+    since: u32,
+    category: Option<EventCategory>,
+    min_severity: Severity,
+}
+
+impl EventFilter {
+    fn from_uri(uri: &str) -> Self {
+        let query = uri.split_once('?').map(|(_, q)| q).unwrap_or("");
+        Self {
+            since: form_value(query, "since").and_then(|v| v.parse().ok()).unwrap_or(0),
+            category: form_value(query, "category").and_then(|v| EventCategory::parse(&v)),
+            min_severity: form_value(query, "severity").and_then(|v| Severity::parse(&v)).unwrap_or(Severity::Info),
+        }
+    }
+
+    fn events(&self) -> Vec<Event> {
+        events::with_log(|log| log.query(self.since, self.category, self.min_severity)).unwrap_or_default()
+    }
+}
+
+/// UTC time of an event, from the clock for this boot or as stored for a restored one
+fn event_utc(event: &Event) -> Option<String> {
+    event.at
+        .and_then(clock::utc_at)
+        .or_else(|| event.stored_utc_secs.map(|s| std::time::UNIX_EPOCH + std::time::Duration::from_secs(s)))
+        .map(clock::format_utc)
+}
+
+/// Seconds since boot at which an event happened (None for a previous boot)
+fn event_uptime_secs(state: &WebState, event: &Event) -> Option<u64> {
+    event.at.map(|at| at.saturating_duration_since(state.start_time).as_secs())
+}
+
+/// One event as a JSON object
+fn event_json(state: &WebState, event: &Event) -> String {
+    format!(
+        r#"{{"seq":{},"time":{},"uptime_secs":{},"previous_boot":{},"category":"{}","severity":"{}","message":"{}"}}"#,
+        event.seq,
+        event_utc(event).map(|t| format!(r#""{}""#, t)).unwrap_or_else(|| "null".to_string()),
+        event_uptime_secs(state, event).map(|s| s.to_string()).unwrap_or_else(|| "null".to_string()),
+        event.at.is_none(),
+        event.category.as_str(),
+        event.severity.as_str(),
+        json_escape(&event.message)
+    )
+}
+
+/// Matching events as a JSON array, oldest first
+fn events_json_array(state: &WebState, filter: &EventFilter) -> String {
+    let events: Vec<String> = filter.events().iter().map(|e| event_json(state, e)).collect();
+    format!("[{}]", events.join(","))
+}
+
+/// Generate JSON for the event log
+fn generate_events_json(state: &WebState, filter: &EventFilter) -> String {
+    format!(r#"{{"events":{}}}"#, events_json_array(state, filter))
+}
+
+/// Generate JSON for the unified error log: per-kind counts and recent errors, newest first
+fn generate_errors_json() -> String {
+    errors::with_log(|log| {
+        let counts: Vec<String> = ErrorKind::ALL
+            .iter()
+            .map(|&kind| format!(r#""{}":{}"#, kind.as_str(), log.count(kind)))
+            .collect();
+        let recent: Vec<String> = log
+            .recent()
+            .map(|r| {
+                format!(
+                    r#"{{"age_secs":{},"source":"{}","kind":"{}","detail":"{}"}}"#,
+                    r.at.elapsed().as_secs(),
+                    r.source.as_str(),
+                    r.kind.as_str(),
+                    json_escape(&r.detail)
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"total":{},"counts":{{{}}},"recent":[{}]}}"#,
+            log.total(),
+            counts.join(","),
+            recent.join(",")
+        )
+    })
+    .unwrap_or_else(|| r#"{"total":0,"counts":{},"recent":[]}"#.to_string())
+}
+
+/// Generate the event timeline page, newest first
+fn generate_events_page(state: &WebState, filter: &EventFilter) -> String {
+    let events = filter.events();
+    let entries_html: String = if events.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No events recorded</p>"#.to_string()
+    } else {
+        events
+            .iter()
+            .rev()
+            .map(|e| {
+                let when = match (event_utc(e), event_uptime_secs(state, e)) {
+                    (Some(utc), _) => utc,
+                    (None, Some(secs)) => format!("{} after boot", format_uptime(secs)),
+                    (None, None) => "time unknown".to_string(),
+                };
+                let color = match e.severity {
+                    Severity::Info => "#333",
+                    Severity::Warning => "#653",
+                    Severity::Error => "#733",
+                };
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="time">{}{}</span>
+                        <span class="chip" style="background: {};">{}</span>
+                        <span class="chip">{}</span>
+                        <span class="mask">{}</span>
+                    </div>"#,
+                    when,
+                    if e.at.is_none() { " (previous boot)" } else { "" },
+                    color,
+                    e.severity.as_str(),
+                    e.category.as_str(),
+                    html_escape(&e.message)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let category_options: String = EventCategory::ALL
+        .iter()
+        .map(|c| {
+            format!(
+                r#"<option value="{}" {}>{}</option>"#,
+                c.as_str(),
+                if filter.category == Some(*c) { "selected" } else { "" },
+                c.as_str()
+            )
+        })
+        .collect();
+    let severity_options: String = [Severity::Info, Severity::Warning, Severity::Error]
+        .iter()
+        .map(|s| {
+            format!(
+                r#"<option value="{}" {}>{} and above</option>"#,
+                s.as_str(),
+                if filter.min_severity == *s { "selected" } else { "" },
+                s.as_str()
+            )
+        })
+        .collect();
+
+    let crash_notice = match &state.crash_report {
+        Some(report) => format!(
+            r#"<div class="card">
+            <h2>Crash Report</h2>
+            <p>The gateway last restarted unexpectedly ({}). <a href="/crash">See what led up to it</a></p>
+        </div>"#,
+            report.reason.as_str()
+        ),
+        None => String::new(),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Events</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <meta http-equiv="refresh" content="10">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 8px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .time {{ color: #666; min-width: 200px; font-size: 0.8em; }}
+        .bdt-entry .chip {{ margin-left: 0; min-width: 56px; text-align: center; }}
+        .bdt-entry .mask {{ color: #fff; flex: 1; }}
+        .filters {{ display: flex; gap: 8px; margin-bottom: 16px; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/devices">Devices</a>
+            <a href="/diagnostics">Diagnostics</a>
+            <a href="/events" class="active">Events</a>
+        </nav>
+
+        {}
+        <div class="card">
+            <h2>Event Log <span class="chip">{} shown</span></h2>
+            <form method="GET" action="/events" class="filters">
+                <select name="category">
+                    <option value="">all categories</option>
+                    {}
+                </select>
+                <select name="severity">{}</select>
+                <button type="submit" class="btn btn-sm">Filter</button>
+            </form>
+            {}
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        crash_notice,
+        events.len(),
+        category_options,
+        severity_options,
+        entries_html
+    )
+}
+
+/// Generate the crash report page (blackbox timeline, newest last)
+fn generate_crash_page(state: &WebState, message: &str) -> String {
+    let message_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="card"><p>{}</p></div>"#, html_escape(message))
+    };
+
+    let report_html = match &state.crash_report {
+        None => r#"<p style="color: #555; text-align: center;">No unexpected reset recorded</p>"#.to_string(),
+        Some(report) => {
+            let reset_at = report.last_uptime_ms();
+            let entries: String = report
+                .entries
+                .iter()
+                .map(|entry| {
+                    let (chip, color, text) = match &entry.record {
+                        Record::Event { category, severity, text } => {
+                            let color = match severity {
+                                Severity::Info => "#333",
+                                Severity::Warning => "#653",
+                                Severity::Error => "#733",
+                            };
+                            (category.as_str(), color, html_escape(text))
+                        }
+                        Record::Frame(frame) => ("frame", "#235", frame.to_string()),
+                        Record::Heap { free } => ("heap", "#333", format!("{} bytes free", free)),
+                    };
+                    format!(
+                        r#"<div class="bdt-entry">
+                        <span class="time">-{:.1} s</span>
+                        <span class="chip" style="background: {};">{}</span>
+                        <span class="mask">{}</span>
+                    </div>"#,
+                        f64::from(reset_at.saturating_sub(entry.uptime_ms)) / 1000.0,
+                        color,
+                        chip,
+                        text
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(
+                r#"<p>Reset reason: <strong>{}</strong>, {} s after boot. Times are relative to the last entry recorded.</p>
+            {}
+            <form method="POST" action="/crash/clear" style="margin-top: 16px;">
+                <button type="submit" class="btn btn-sm">Clear Report</button>
+            </form>"#,
+                report.reason.as_str(),
+                reset_at / 1000,
+                if entries.is_empty() { "<p>The blackbox was empty.</p>".to_string() } else { entries }
+            )
+        }
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Crash Report</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 8px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .time {{ color: #666; min-width: 80px; font-size: 0.8em; }}
+        .bdt-entry .chip {{ margin-left: 0; min-width: 56px; text-align: center; }}
+        .bdt-entry .mask {{ color: #fff; flex: 1; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/devices">Devices</a>
+            <a href="/diagnostics">Diagnostics</a>
+            <a href="/events" class="active">Events</a>
+        </nav>
+
+        {}
+        <div class="card">
+            <h2>Crash Report</h2>
+            {}
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        message_html,
+        report_html
+    )
+}
+
+/// Parse static route add form data (network=N&hop_type=mstp|ip&hop=MAC or IP[:port])
+fn parse_route_add_form(body: &str, state: &mut WebState) -> &'static str {
+    let network = match form_value(body, "network").and_then(|v| v.parse::<u16>().ok()) {
+        Some(n) if (1..=65534).contains(&n) => n,
+        _ => return "Invalid network number (1-65534)",
+    };
+    if network == state.config.mstp_network || network == state.config.ip_network {
+        return "Network is directly connected to this gateway";
+    }
+
+    let hop = form_value(body, "hop").unwrap_or_default();
+    let hop = hop.trim();
+    let next_hop = match form_value(body, "hop_type").as_deref() {
+        Some("mstp") => match hop.parse::<u8>() {
+            Ok(mac) if mac <= 127 => RouteNextHop::Mstp(mac),
+            _ => return "Invalid MS/TP router MAC (0-127)",
+        },
+        Some("ip") => {
+            if let Ok(addr) = hop.parse::<SocketAddr>() {
+                RouteNextHop::Ip(addr)
+            } else if let Ok(ip) = hop.parse::<Ipv4Addr>() {
+                RouteNextHop::Ip(SocketAddr::new(std::net::IpAddr::V4(ip), 47808))
+            } else {
+                return "Invalid IP router address (expected IP or IP:port)";
+            }
+        }
+        _ => return "Invalid next hop type",
+    };
+
+    state.static_route_add_request = Some((network, next_hop));
+    info!("Static route add requested via web portal: network {} via {}", network, next_hop);
+
+    "Static route add requested. Route will be added."
+}
+
+/// Generate static routes JSON
+fn generate_routes_json(state: &WebState) -> String {
+    let entries: Vec<String> = state.static_routes
+        .iter()
+        .map(|(network, next_hop)| match next_hop {
+            RouteNextHop::Mstp(mac) => format!(r#"{{"network":{},"via":"mstp","mac":{}}}"#, network, mac),
+            RouteNextHop::Ip(addr) => format!(r#"{{"network":{},"via":"ip","address":"{}"}}"#, network, addr),
+        })
+        .collect();
+    let unreachable: Vec<String> = state.unreachable_networks
+        .iter()
+        .map(|n| format!(
+            r#"{{"network":{},"reason":{},"reported_by":"{}","rejects":{},"retry_in_secs":{}}}"#,
+            n.network, n.reason, json_escape(&n.reported_by), n.count,
+            n.until.saturating_duration_since(std::time::Instant::now()).as_secs()
+        ))
+        .collect();
+
+    format!(r#"{{"routes":[{}],"unreachable":[{}]}}"#, entries.join(","), unreachable.join(","))
+}
+
+/// Generate top talkers JSON
+fn generate_talkers_json(state: &WebState) -> String {
+    let rows = |talkers: &[TopTalker]| -> String {
+        talkers
+            .iter()
+            .map(|t| {
+                let (kind, address) = match t.address {
+                    TalkerAddress::Mstp(mac) => ("mstp", mac.to_string()),
+                    TalkerAddress::Ip(addr) => ("ip", format!(r#""{}""#, addr)),
+                };
+                format!(
+                    r#"{{"{}":{},"packets_sent":{},"bytes_sent":{},"packets_received":{},"bytes_received":{}}}"#,
+                    kind, address, t.packets_sent, t.bytes_sent, t.packets_received, t.bytes_received
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    format!(
+        r#"{{"window_secs":{},"by_packets":[{}],"by_bytes":[{}]}}"#,
+        TALKER_WINDOW.as_secs(),
+        rows(&state.top_talkers_by_packets),
+        rows(&state.top_talkers_by_bytes)
+    )
+}
+
+/// Generate redundancy pair JSON
+fn generate_failover_json(state: &WebState) -> String {
+    let optional = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_else(|| "null".to_string());
+    let peer = state.config.failover_peer.map(|a| format!(r#""{}""#, a)).unwrap_or_else(|| "null".to_string());
+    match &state.failover {
+        None => format!(r#"{{"role":"off","active":true,"peer":{}}}"#, peer),
+        Some(s) => format!(
+            r#"{{"role":"{}","active":{},"peer":{},"peer_heard_secs":{},"peer_active":{},"takeovers":{},"changed_secs":{}}}"#,
+            s.role.as_str(),
+            s.active,
+            peer,
+            optional(s.peer_heard_secs),
+            s.peer_active,
+            s.takeovers,
+            optional(s.changed_secs)
+        ),
+    }
+}
+
+/// Generate protocol analyzer JSON
+fn generate_analyzer_json(state: &WebState) -> String {
+    let sources: Vec<String> = state
+        .analyzer_reports
+        .iter()
+        .map(|r| {
+            let (kind, address) = match r.source {
+                TalkerAddress::Mstp(mac) => ("mstp", mac.to_string()),
+                TalkerAddress::Ip(addr) => ("ip", format!(r#""{}""#, addr)),
+            };
+            let violations: Vec<String> = Violation::ALL
+                .iter()
+                .zip(r.violations)
+                .map(|(v, count)| format!(r#""{}":{}"#, v.as_str(), count))
+                .collect();
+            let examples: Vec<String> = r
+                .examples
+                .iter()
+                .map(|e| {
+                    format!(
+                        r#"{{"violation":"{}","time":{},"length":{},"apdu":"{}"}}"#,
+                        e.violation.as_str(),
+                        utc_json(e.at),
+                        e.len,
+                        e.bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+                    )
+                })
+                .collect();
+            format!(
+                r#"{{"{}":{},"checked":{},"nonconformant":{},"violations":{{{}}},"examples":[{}]}}"#,
+                kind,
+                address,
+                r.checked,
+                r.nonconformant(),
+                violations.join(","),
+                examples.join(",")
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{{"enabled":{},"running_secs":{},"sources":[{}]}}"#,
+        state.analyzer_started.is_some(),
+        state.analyzer_started.map(|t| t.elapsed().as_secs().to_string()).unwrap_or_else(|| "null".to_string()),
+        sources.join(",")
+    )
+}
+
+/// Generate protocol analyzer page HTML with optional message
+fn generate_analyzer_page(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+
+    let enabled = state.analyzer_request.unwrap_or(state.analyzer_started.is_some());
+    let toggle_html = format!(
+        r#"<div class="bdt-entry">
+            <span class="addr">{}</span>
+            <span class="mask">{}</span>
+            <form method="POST" action="/analyzer" style="display:inline">
+                <input type="hidden" name="enabled" value="{}">
+                <button type="submit" class="btn btn-small">{}</button>
+            </form>
+        </div>"#,
+        if enabled { "Analyzing" } else { "Off" },
+        match state.analyzer_started {
+            Some(started) => format!("{} sources checked over {}s", state.analyzer_reports.len(), started.elapsed().as_secs()),
+            None => "Routed frames are not checked".to_string(),
+        },
+        if enabled { "0" } else { "1" },
+        if enabled { "Stop" } else { "Start" }
+    );
+
+    let sources_html = if state.analyzer_reports.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No frames checked</p>"#.to_string()
+    } else {
+        state
+            .analyzer_reports
+            .iter()
+            .map(|r| {
+                let counts: Vec<String> = Violation::ALL
+                    .iter()
+                    .zip(r.violations)
+                    .filter(|(_, count)| *count > 0)
+                    .map(|(v, count)| format!("{} {}", count, v.as_str()))
+                    .collect();
+                let examples: String = r
+                    .examples
+                    .iter()
+                    .rev()
+                    .map(|e| {
+                        format!(
+                            r#"<div class="example">{} ({} bytes): {}{}</div>"#,
+                            e.violation.as_str(),
+                            e.len,
+                            e.bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" "),
+                            if e.len > e.bytes.len() { " ..." } else { "" }
+                        )
+                    })
+                    .collect();
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">{} of {} nonconformant{}</span>
+                    </div>{}"#,
+                    r.source,
+                    r.nonconformant(),
+                    r.checked,
+                    if counts.is_empty() { String::new() } else { format!(": {}", counts.join(", ")) },
+                    examples
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Protocol Analyzer</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 220px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .example {{ color: #888; font-family: monospace; font-size: 0.75em; padding: 0 12px 8px; word-break: break-all; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/devices">Devices</a>
+            <a href="/diagnostics" class="active">Diagnostics</a>
+            <a href="/events">Events</a>
+        </nav>
+
+        {}
+
+        <div class="card">
+            <h2>Protocol Analyzer</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Checks every routed APDU against the BACnet encoding rules: header length, tag lengths,
+                opening/closing tags, fixed-size application tags and the parameters of I-Am, Who-Is,
+                ReadProperty and WriteProperty. Uses routing time, so leave it off when not investigating.
+                Counters start over each time it is started.
+            </p>
+            {}
+        </div>
+
+        <div class="card" style="margin-top: 16px;">
+            <h2>Sources</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                The MS/TP station or B/IP peer each frame arrived from, most nonconformant first,
+                with its last {} offending frames.
+            </p>
+            {}
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        msg_html,
+        toggle_html,
+        EXAMPLES_PER_SOURCE,
+        sources_html
+    )
+}
+
+/// Generate per-peer IP statistics JSON
+fn generate_peers_json(state: &WebState) -> String {
+    let peers: Vec<String> = state
+        .ip_peers
+        .iter()
+        .map(|p| {
+            let unanswered_pct = p
+                .unanswered_pct()
+                .map(|pct| format!("{:.1}", pct))
+                .unwrap_or_else(|| "null".to_string());
+            format!(
+                r#"{{"address":"{}","packets_received":{},"bytes_received":{},"packets_sent":{},"bytes_sent":{},"errors":{},"rejected":{},"dropped":{},"confirmed_requests":{},"unanswered":{},"unanswered_pct":{},"first_seen_secs":{},"last_seen_secs":{}}}"#,
+                p.addr,
+                p.packets_received,
+                p.bytes_received,
+                p.packets_sent,
+                p.bytes_sent,
+                p.errors,
+                p.rejected,
+                p.dropped,
+                p.confirmed_requests,
+                p.unanswered,
+                unanswered_pct,
+                p.first_seen.elapsed().as_secs(),
+                p.last_seen.elapsed().as_secs()
+            )
+        })
+        .collect();
+
+    format!(r#"{{"max_tracked":{},"peers":[{}]}}"#, MAX_TRACKED_PEERS, peers.join(","))
+}
+
+/// Generate the per-device response statistics JSON (today and one entry per past day)
+fn generate_sla_json(state: &WebState) -> String {
+    fn counters_json(c: &ResponseCounters) -> String {
+        let answered = c.answered() > 0;
+        format!(
+            r#""success":{},"errors":{},"timeouts":{},"min_ms":{},"avg_ms":{},"max_ms":{},"success_pct":{}"#,
+            c.success,
+            c.errors,
+            c.timeouts,
+            if answered { c.min_ms.to_string() } else { "null".to_string() },
+            c.avg_ms().map(|ms| ms.to_string()).unwrap_or_else(|| "null".to_string()),
+            if answered { c.max_ms.to_string() } else { "null".to_string() },
+            c.success_pct().map(|pct| format!("{:.1}", pct)).unwrap_or_else(|| "null".to_string())
+        )
+    }
+
+    let devices: Vec<String> = state
+        .sla_devices
+        .iter()
+        .map(|d| {
+            let device_instance = state
+                .discovered_devices
+                .iter()
+                .find(|dev| dev.mac_address == d.mac)
+                .map(|dev| dev.device_instance);
+            let object_name = device_instance
+                .and_then(|instance| state.device_name(instance))
+                .map(|name| format!(r#""{}""#, json_escape(name)))
+                .unwrap_or_else(|| "null".to_string());
+            let device_instance = device_instance
+                .map(|instance| instance.to_string())
+                .unwrap_or_else(|| "null".to_string());
+            let days: Vec<String> = d
+                .history
+                .iter()
+                .map(|(day, c)| format!(r#"{{"date":"{}",{}}}"#, clock::format_date(*day), counters_json(c)))
+                .collect();
+            format!(
+                r#"{{"mac":{},"device_instance":{},"object_name":{},"today":{{{}}},"days":[{}]}}"#,
+                d.mac,
+                device_instance,
+                object_name,
+                counters_json(&d.today),
+                days.join(",")
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{{"max_tracked":{},"history_days":{},"devices":[{}]}}"#,
+        MAX_SLA_DEVICES,
+        SLA_HISTORY_DAYS,
+        devices.join(",")
+    )
+}
+
+/// Generate the per-service counters and error histogram JSON
+fn generate_service_errors_json(state: &WebState) -> String {
+    let services: Vec<String> = state
+        .service_counters
+        .iter()
+        .map(|(service, c)| {
+            format!(
+                r#"{{"service":{},"name":"{}","requests":{},"acks":{},"errors":{},"rejects":{},"aborts":{},"timeouts":{}}}"#,
+                service,
+                service_stats::service_name(*service),
+                c.requests,
+                c.acks,
+                c.errors,
+                c.rejects,
+                c.aborts,
+                c.timeouts
+            )
+        })
+        .collect();
+    let errors: Vec<String> = state
+        .service_errors
+        .iter()
+        .map(|e| {
+            format!(
+                r#"{{"service":"{}","reason":"{}","count":{},"last_mac":{},"last_seen_secs":{}}}"#,
+                service_stats::service_name(e.service),
+                e.reason,
+                e.count,
+                e.last_mac,
+                e.last_seen.elapsed().as_secs()
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{{"services":[{}],"top_errors":[{}],"max_tracked":{},"untracked":{}}}"#,
+        services.join(","),
+        errors.join(","),
+        MAX_ERROR_REASONS,
+        state.service_errors_untracked
+    )
+}
+
+/// Generate the MS/TP capacity estimate JSON
+fn generate_capacity_json(state: &WebState) -> String {
+    let Some(c) = &state.capacity else {
+        return r#"{"available":false}"#.to_string();
+    };
+    let opt = |v: Option<u32>| v.map(|v| v.to_string()).unwrap_or_else(|| "null".to_string());
+    let projections: Vec<String> = PROJECTED_DEVICES
+        .iter()
+        .map(|&devices| {
+            let p = c.with_extra_devices(devices);
+            format!(
+                r#"{{"extra_devices":{},"token_loop_ms":{},"utilization_pct":{:.1},"fits":{}}}"#,
+                p.devices, p.token_loop_ms, p.utilization_pct, p.fits
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"available":true,"window_secs":{},"baud_rate":{},"masters":{},"token_loop_ms":{},"utilization_pct":{:.1},"data_pct":{:.1},"bytes_per_cycle":{},"data_bytes_per_cycle":{},"extra_packet_ms":{:.2},"extra_max_packet_ms":{:.2},"utilization_limit_pct":{:.0},"projections":[{}]}}"#,
+        c.window.as_secs(),
+        c.baud_rate,
+        c.masters,
+        c.token_loop_ms,
+        c.utilization_pct,
+        c.data_pct,
+        opt(c.bytes_per_cycle),
+        opt(c.data_bytes_per_cycle),
+        c.extra_packet_ms,
+        c.extra_max_packet_ms,
+        UTILIZATION_LIMIT_PCT,
+        projections.join(",")
+    )
+}
+
+/// Generate the MS/TP capacity page
+fn generate_capacity_page(state: &WebState) -> String {
+    let body = match &state.capacity {
+        None => r#"<div class="card"><p style="color: #555; text-align: center;">Collecting line statistics...</p></div>"#.to_string(),
+        Some(c) => {
+            let per_cycle = |v: Option<u32>| v.map(|v| format!("{} bytes", v)).unwrap_or_else(|| "- (no token)".to_string());
+            let rows: String = PROJECTED_DEVICES
+                .iter()
+                .map(|&devices| {
+                    let p = c.with_extra_devices(devices);
+                    let (color, verdict) = if p.fits { ("#4a4", "fits") } else { ("#c44", "tight") };
+                    format!(
+                        r#"<div class="bdt-entry">
+                <span class="addr">+{} devices</span>
+                <span class="mask">token loop {} ms, {:.1}% busy</span>
+                <span class="chip" style="background: {};">{}</span>
+            </div>"#,
+                        p.devices, p.token_loop_ms, p.utilization_pct, color, verdict
+                    )
+                })
+                .collect();
+            format!(
+                r#"<div class="card">
+            <h2>Line Utilization</h2>
+            <div class="status-grid">
+                <div class="status-item">
+                    <span class="label">Busy</span>
+                    <span class="value">{:.1}%</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Data Frames</span>
+                    <span class="value">{:.1}%</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Bytes per Token Cycle</span>
+                    <span class="value">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Data per Token Cycle</span>
+                    <span class="value">{}</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Token Loop (avg)</span>
+                    <span class="value">{} ms</span>
+                </div>
+                <div class="status-item">
+                    <span class="label">Masters</span>
+                    <span class="value">{}</span>
+                </div>
+            </div>
+            <p style="color: #555; font-size: 0.8em; margin-top: 16px;">
+                Over the last {} s at {} baud. Busy is the time some station was transmitting a valid frame;
+                what is not data frames is token passing and polling.
+            </p>
+        </div>
+
+        <div class="card">
+            <h2>Headroom</h2>
+            <p style="margin-bottom: 16px;">One more routed packet per token cycle adds about <strong>{:.1} ms</strong> to the token loop ({}-byte NPDU), up to {:.1} ms for a full-size frame.</p>
+            {}
+            <p style="color: #555; font-size: 0.8em; margin-top: 16px;">
+                Each added master is assumed to pass the token on and carry the average station's data.
+                Above {:.0}% busy, answers start waiting noticeably for the token. Retries and slow replies
+                are not included, so take this as a lower bound.
+            </p>
+        </div>"#,
+                c.utilization_pct,
+                c.data_pct,
+                per_cycle(c.bytes_per_cycle),
+                per_cycle(c.data_bytes_per_cycle),
+                c.token_loop_ms,
+                c.masters,
+                c.window.as_secs(),
+                c.baud_rate,
+                c.extra_packet_ms,
+                TYPICAL_ROUTED_NPDU,
+                c.extra_max_packet_ms,
+                rows,
+                UTILIZATION_LIMIT_PCT
+            )
+        }
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Capacity</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 8px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; min-width: 100px; }}
+        .bdt-entry .mask {{ color: #888; flex: 1; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status" class="active">Status</a>
+            <a href="/devices">Devices</a>
+            <a href="/diagnostics">Diagnostics</a>
+            <a href="/events">Events</a>
+        </nav>
+
+        {}
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        body
+    )
+}
+
+/// Generate the hotspot client list JSON
+fn generate_ap_clients_json(state: &WebState) -> String {
+    let clients: Vec<String> = state
+        .ap_clients
+        .iter()
+        .map(|c| {
+            format!(
+                r#"{{"mac":"{}","rssi":{},"ip":{}}}"#,
+                ap_clients::format_mac(&c.mac),
+                c.rssi,
+                c.ip.map(|ip| format!("\"{}\"", ip)).unwrap_or_else(|| "null".to_string())
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"lease_minutes":{},"clients":[{}]}}"#,
+        state.ap_lease_minutes.map(|m| m.to_string()).unwrap_or_else(|| "null".to_string()),
+        clients.join(",")
+    )
+}
+
+/// Generate router query JSON (null before the first query)
+fn generate_router_query_json(state: &WebState) -> String {
+    let Some(query) = &state.router_query else { return "null".to_string() };
+    let routes: Vec<String> = query.routes
+        .iter()
+        .map(|r| {
+            format!(
+                r#"{{"network":{},"port_id":{},"port_info":"{}"}}"#,
+                r.network,
+                r.port_id,
+                r.port_info.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+            )
+        })
+        .collect();
+    let networks: Vec<String> = query.networks.iter().map(|n| n.to_string()).collect();
+    format!(
+        r#"{{"target":"{}","kind":"{}","age_secs":{},"finished":{},"answered":{},"routes":[{}],"networks":[{}]}}"#,
+        query.target,
+        query.kind.as_str(),
+        query.sent_at.elapsed().as_secs(),
+        query.is_finished(std::time::Instant::now()),
+        query.answered,
+        routes.join(","),
+        networks.join(",")
+    )
+}
+
+/// Generate active transactions JSON
+fn generate_transactions_json(state: &WebState) -> String {
+    let transactions: Vec<String> = state.transactions
+        .iter()
+        .map(|t| {
+            format!(
+                r#"{{"invoke_id":{},"service":"{:?}","client":"{}","dest_network":{},"dest_mac":{},"age_ms":{},"timeout_ms":{},"retries":{},"max_retries":{},"segmented":{}}}"#,
+                t.invoke_id,
+                t.service,
+                t.client,
+                t.dest_network,
+                t.dest_mac,
+                t.sent_at.elapsed().as_millis(),
+                t.timeout.as_millis(),
+                t.retries,
+                t.max_retries,
+                t.segmented
+            )
+        })
+        .collect();
+    let reassemblies: Vec<String> = state.reassemblies
+        .iter()
+        .map(|r| {
+            format!(
+                r#"{{"invoke_id":{},"service_choice":{},"client":"{}","age_ms":{}}}"#,
+                r.invoke_id,
+                r.service_choice,
+                r.client,
+                r.started_at.elapsed().as_millis()
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{{"transactions":[{}],"reassemblies":[{}]}}"#,
+        transactions.join(","),
+        reassemblies.join(",")
+    )
+}
+
+/// Generate diagnostics page HTML with optional message
+fn generate_diagnostics_page(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+
+    let rows = |talkers: &[TopTalker]| -> String {
+        if talkers.is_empty() {
+            return r#"<p style="color: #555; text-align: center;">No routed traffic in the last window</p>"#.to_string();
+        }
+        talkers
+            .iter()
+            .enumerate()
+            .map(|(rank, t)| {
+                let name = match t.address {
+                    TalkerAddress::Mstp(mac) => state.discovered_devices.iter()
+                        .find(|d| d.mac_address == mac)
+                        .and_then(|d| {
+                            state.device_label(d.device_instance)
+                                .map(|l| l.name.as_str())
+                                .or_else(|| state.device_name(d.device_instance))
+                        })
+                        .map(|name| format!(" ({})", html_escape(name)))
+                        .unwrap_or_default(),
+                    TalkerAddress::Ip(_) => String::new(),
+                };
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="rank">{}</span>
+                        <span class="addr">{}{}</span>
+                        <span class="mask">{} pkts / {} bytes out, {} pkts / {} bytes in</span>
+                    </div>"#,
+                    rank + 1,
+                    t.address,
+                    name,
+                    t.packets_sent,
+                    t.bytes_sent,
+                    t.packets_received,
+                    t.bytes_received
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let transactions_html: String = if state.transactions.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No transactions in progress</p>"#.to_string()
+    } else {
+        state.transactions
+            .iter()
+            .map(|t| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="rank">{}</span>
+                        <span class="addr">{:?}{} to {}:{}</span>
+                        <span class="mask">from {}, age {:.1}s of {:.1}s, retries {}/{}</span>
+                        <form method="POST" action="/transactions/abort" style="display:inline">
+                            <input type="hidden" name="invoke_id" value="{}">
+                            <input type="hidden" name="dest_mac" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Abort</button>
+                        </form>
+                    </div>"#,
+                    t.invoke_id,
+                    t.service,
+                    if t.segmented { " (segmented)" } else { "" },
+                    t.dest_network,
+                    t.dest_mac,
+                    t.client,
+                    t.sent_at.elapsed().as_secs_f32(),
+                    t.timeout.as_secs_f32(),
+                    t.retries,
+                    t.max_retries,
+                    t.invoke_id,
+                    t.dest_mac
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let port_row = |name: &str, port: &str, enabled: bool, pending: Option<bool>| -> String {
+        let enabled = pending.unwrap_or(enabled);
+        format!(
+            r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">{}</span>
+                        <form method="POST" action="/ports" style="display:inline">
+                            <input type="hidden" name="port" value="{}">
+                            <input type="hidden" name="enabled" value="{}">
+                            <button type="submit" class="btn btn-small{}">{}</button>
+                        </form>
+                    </div>"#,
+            name,
+            if enabled { "In service" } else { "Disabled (Out_Of_Service)" },
+            port,
+            if enabled { 0 } else { 1 },
+            if enabled { " btn-danger" } else { "" },
+            if enabled { "Disable" } else { "Enable" }
+        )
+    };
+    let ports_html = format!(
+        "{}\n{}",
+        port_row("MS/TP Port", "mstp", state.mstp_port_enabled, state.mstp_port_enable_request),
+        port_row("BACnet/IP Port", "ip", state.ip_port_enabled, state.ip_port_enable_request)
+    );
+
+    let reassemblies_html: String = if state.reassemblies.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No segmented requests being reassembled</p>"#.to_string()
+    } else {
+        state.reassemblies
+            .iter()
+            .map(|r| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="rank">{}</span>
+                        <span class="addr">Service {} from {}</span>
+                        <span class="mask">first segment {}s ago</span>
+                    </div>"#,
+                    r.invoke_id,
+                    r.service_choice,
+                    r.client,
+                    r.started_at.elapsed().as_secs()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let s = &state.mstp_stats;
+    let max_master_html = match max_master_advice(state) {
+        _ if s.slave_mode => r#"<p style="color: #555; text-align: center;">Not polling for masters in slave mode</p>"#.to_string(),
+        _ if !s.ring_stable => format!(
+            r#"<p style="color: #555; text-align: center;">Max_Master {}: waiting for the token ring to settle</p>"#,
+            s.max_master
+        ),
+        None => format!(
+            r#"<p style="color: #555; text-align: center;">Max_Master {} already fits the highest master ({})</p>"#,
+            s.max_master,
+            s.discovered_masters.checked_ilog2().unwrap_or(0)
+        ),
+        Some(advice) => format!(
+            r#"<div class="bdt-entry">
+                        <span class="addr">Max_Master {} &rarr; {}</span>
+                        <span class="mask">highest master {}; unused addresses polled {} &rarr; {}; full poll sweep ~{} &rarr; ~{} ({}% shorter)</span>
+                        <form method="POST" action="/diagnostics/max-master" style="display:inline" onsubmit="return confirm('Set Max_Master to {} and save? Stations above it will no longer be found.')">
+                            <input type="hidden" name="max_master" value="{}">
+                            <button type="submit" class="btn btn-small">Apply</button>
+                        </form>
+                    </div>"#,
+            advice.current,
+            advice.recommended,
+            advice.highest_master,
+            advice.empty_now,
+            advice.empty_after,
+            format_uptime(advice.sweep_now.as_secs()),
+            format_uptime(advice.sweep_after.as_secs()),
+            advice.sweep_saving_pct(),
+            advice.recommended,
+            advice.recommended
+        ),
+    };
+
+    let (web_load_html, web_deferred, web_deferred_ms) = web_budget::with_budget(|budget| {
+        let timings = budget.timings();
+        let rows = if timings.is_empty() {
+            r#"<p style="color: #555; text-align: center;">No pages generated yet</p>"#.to_string()
+        } else {
+            timings
+                .iter()
+                .map(|t| {
+                    format!(
+                        r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">{} requests, average {:.1} ms, worst {:.1} ms</span>
+                    </div>"#,
+                        t.path,
+                        t.requests,
+                        t.average().as_secs_f32() * 1000.0,
+                        t.worst.as_secs_f32() * 1000.0
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        (rows, budget.deferred, budget.deferred_total.as_millis())
+    })
+    .unwrap_or_default();
+
+    let router_query_html = match &state.router_query {
+        None => r#"<p style="color: #555; text-align: center;">No router queried yet</p>"#.to_string(),
+        Some(query) => {
+            let age = query.sent_at.elapsed();
+            let status = if age < ROUTER_QUERY_WINDOW {
+                "waiting for answers...".to_string()
+            } else if query.answered {
+                format!("{} route(s), {} network(s) reported", query.routes.len(), query.networks.len())
+            } else {
+                "no answer".to_string()
+            };
+            let mut rows = vec![format!(
+                r#"<div class="bdt-entry">
+                        <span class="addr">{} to {}</span>
+                        <span class="mask">{}s ago, {}</span>
+                    </div>"#,
+                query.kind,
+                query.target,
+                age.as_secs(),
+                status
+            )];
+            rows.extend(query.routes.iter().map(|r| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="rank">{}</span>
+                        <span class="addr">Network {}</span>
+                        <span class="mask">routing table, port info {}</span>
+                    </div>"#,
+                    r.port_id,
+                    r.network,
+                    if r.port_info.is_empty() {
+                        "none".to_string()
+                    } else {
+                        r.port_info.iter().map(|b| format!("{:02X}", b)).collect::<String>()
+                    }
+                )
+            }));
+            rows.extend(query.networks.iter().map(|n| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="rank"></span>
+                        <span class="addr">Network {}</span>
+                        <span class="mask">I-Am-Router-To-Network</span>
+                    </div>"#,
+                    n
+                )
+            }));
+            rows.join("\n")
+        }
+    };
+
+    let mut service_rows: Vec<String> = state
+        .service_errors
+        .iter()
+        .take(DIAGNOSTICS_SERVICE_ERRORS)
+        .map(|e| {
+            format!(
+                r#"<div class="bdt-entry">
+                        <span class="rank">{}</span>
+                        <span class="addr">{} - {}</span>
+                        <span class="mask">last from MS/TP {}, {}s ago</span>
+                    </div>"#,
+                e.count,
+                service_stats::service_name(e.service),
+                html_escape(&e.reason.to_string()),
+                e.last_mac,
+                e.last_seen.elapsed().as_secs()
+            )
+        })
+        .collect();
+    if service_rows.is_empty() {
+        service_rows.push(r#"<p style="color: #555; text-align: center;">No error answers since boot</p>"#.to_string());
+    }
+    service_rows.extend(state.service_counters.iter().map(|(service, c)| {
+        format!(
+            r#"<div class="bdt-entry">
+                        <span class="rank"></span>
+                        <span class="addr">{}</span>
+                        <span class="mask">{} requests: {} acks, {} errors, {} rejects, {} aborts, {} timeouts</span>
+                    </div>"#,
+            service_stats::service_name(*service),
+            c.requests,
+            c.acks,
+            c.errors,
+            c.rejects,
+            c.aborts,
+            c.timeouts
+        )
+    }));
+    let service_errors_html = service_rows.join("\n");
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Diagnostics</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <meta http-equiv="refresh" content="10">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .rank {{ color: #555; min-width: 24px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 220px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+        .btn-danger {{ border-color: #633; }}
+        .btn-danger:hover {{ background: #633; border-color: #844; }}
+    </style>
 </head>
 <body>
     <div class="container">
         <h1>BACman Gateway</h1>
         <nav>
-            <a href="/status" class="active">Status</a>
-            <a href="/config">Configuration</a>
+            <a href="/status">Status</a>
+            <a href="/devices">Devices</a>
+            <a href="/diagnostics" class="active">Diagnostics</a>
+            <a href="/events">Events</a>
         </nav>
 
+        {}
+
         <div class="card">
-            <div class="card-header">
-                <h2>MS/TP Device Map <span class="chip" id="device-count">{} found</span></h2>
-                <button class="btn btn-sm" id="scanBtn" onclick="startScan()">Scan (Who-Is)</button>
-            </div>
-            <div class="device-grid" id="device-grid">{}</div>
-            <div class="grid-legend">
-                <span><span class="legend-box self"></span> This Device</span>
-                <span><span class="legend-box active"></span> Active Master</span>
-                <span><span class="legend-box"></span> Not Found</span>
-            </div>
-            <div id="scan-results" style="margin-top:12px;display:none;">
-                <div class="scan-status" id="scan-status"></div>
-                <div id="device-list"></div>
-            </div>
+            <h2>Port Maintenance</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                A disabled port routes nothing: unicasts for its network are rejected (router busy),
+                broadcasts are dropped, and its Network Port object reports Out_Of_Service.
+                The gateway's own device stays reachable. Ports are re-enabled on restart.
+                {} messages dropped or rejected so far.
+            </p>
+            {}
         </div>
 
         <div class="card">
-            <h2>State Machine</h2>
-            <div class="status-grid">
-                <div class="status-item">
-                    <span class="label">State</span>
-                    <span class="value" id="state">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Sole Master</span>
-                    <span class="value {}" id="sole_master">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Next Station</span>
-                    <span class="value" id="next_station">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Poll Station</span>
-                    <span class="value" id="poll_station">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Silence</span>
-                    <span class="value" id="silence">{} ms</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Masters Found</span>
-                    <span class="value" id="masters">{}</span>
-                </div>
-            </div>
+            <h2>Max_Master Tuning</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Masters poll every unused address up to Max_Master, one per {} token loops here.
+                Keeping Max_Master {} above the highest master finds new stations sooner.
+                Use the same value on the other masters on this trunk.
+            </p>
+            {}
         </div>
 
         <div class="card">
-            <h2>MS/TP Statistics</h2>
-            <div class="status-grid">
-                <div class="status-item">
-                    <span class="label">RX Frames</span>
-                    <span class="value" id="rx_frames">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">TX Frames</span>
-                    <span class="value" id="tx_frames">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Tokens Received</span>
-                    <span class="value" id="tokens_received">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Send Queue</span>
-                    <span class="value" id="send_queue">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Receive Queue</span>
-                    <span class="value" id="receive_queue">{}</span>
-                </div>
-            </div>
+            <h2>Top Talkers by Packets</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Routed traffic per MS/TP MAC and B/IP address over the last {}-{} seconds.
+                Broadcasts count against the broadcast address as destination.
+            </p>
+            {}
+            <p style="color: #555; font-size: 0.8em; margin-top: 16px;"><a href="/analyzer">Protocol analyzer: flag nonconformant frames per source</a></p>
         </div>
 
         <div class="card">
-            <h2>Token Loop Timing</h2>
-            <div class="status-grid">
-                <div class="status-item">
-                    <span class="label">Current</span>
-                    <span class="value" id="token_loop">{} ms</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Min</span>
-                    <span class="value" id="token_loop_min">{} ms</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Max</span>
-                    <span class="value" id="token_loop_max">{} ms</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Average</span>
-                    <span class="value" id="token_loop_avg">{} ms</span>
-                </div>
-            </div>
+            <h2>Top Talkers by Bytes</h2>
+            {}
         </div>
 
         <div class="card">
-            <h2>Errors</h2>
-            <div class="status-grid">
-                <div class="status-item">
-                    <span class="label">CRC Errors</span>
-                    <span class="value {}" id="crc_errors">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Frame Errors</span>
-                    <span class="value {}" id="frame_errors">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Reply Timeouts</span>
-                    <span class="value {}" id="reply_timeouts">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Token Pass Fail</span>
-                    <span class="value {}" id="token_pass_failures">{}</span>
-                </div>
-            </div>
+            <h2>Active Transactions</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Confirmed requests from IP clients waiting for an MS/TP response, by invoke ID.
+                Aborting drops the transaction and sends the client an Abort.
+            </p>
+            {}
         </div>
 
         <div class="card">
-            <h2>Gateway Routing</h2>
-            <div class="status-grid">
-                <div class="status-item">
-                    <span class="label">WiFi</span>
-                    <span class="value {}">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">IP Address</span>
-                    <span class="value auto-size">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">MS/TP to IP</span>
-                    <span class="value" id="mstp_to_ip">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">IP to MS/TP</span>
-                    <span class="value" id="ip_to_mstp">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Uptime</span>
-                    <span class="value" id="uptime">{}</span>
-                </div>
-            </div>
+            <h2>Segment Reassembly</h2>
+            {}
         </div>
 
         <div class="card">
-            <h2>Network Configuration</h2>
-            <div class="status-grid">
-                <div class="status-item">
-                    <span class="label">MS/TP Network</span>
-                    <span class="value">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">IP Network</span>
-                    <span class="value">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Station Address</span>
-                    <span class="value">{}</span>
-                </div>
-                <div class="status-item">
-                    <span class="label">Device Instance</span>
-                    <span class="value">{}</span>
-                </div>
-            </div>
+            <h2>Device Error Answers</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Error, Reject and Abort answers from MS/TP devices to routed requests, most frequent first.
+                A steady count usually means a head-end point reference the device does not have.
+            </p>
+            {}
         </div>
 
         <div class="card">
-            <h2>Tools</h2>
-            <div class="button-row">
-                <button class="btn" onclick="resetStats()">Reset Statistics</button>
-                <button class="btn" onclick="exportData()">Export JSON</button>
-            </div>
+            <h2>Web Server Load</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Page generation may use {} ms of CPU per second; past that, requests wait so routing keeps priority.
+                {} requests waited, {} ms in total.
+            </p>
+            {}
         </div>
 
-        <div id="device-modal" class="modal" onclick="closeModal(event)">
-            <div class="modal-content" onclick="event.stopPropagation()">
-                <h3>Device Info</h3>
-                <div id="modal-body"></div>
-                <button class="btn" onclick="closeModal()">Close</button>
-            </div>
+        <div class="card">
+            <h2>Remote Router Query</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Ask a B/IP router which networks it reaches. Initialize-Routing-Table with no ports
+                reads its routing table without changing it; Who-Is-Router-To-Network lists the networks it announces.
+                Answers from that address are collected for {} seconds.
+            </p>
+            <form method="POST" action="/diagnostics/router-query" style="display: flex; gap: 8px; margin-bottom: 16px;">
+                <input type="text" name="target" value="{}" maxlength="21" placeholder="router IP or IP:port" required>
+                <select name="kind">
+                    <option value="routing-table">Initialize-Routing-Table</option>
+                    <option value="who-is-router">Who-Is-Router-To-Network</option>
+                </select>
+                <button type="submit" class="btn btn-small">Query</button>
+            </form>
+            {}
         </div>
-
-        <p class="footer">BACman v0.1.0</p>
     </div>
 </body>
 </html>"#,
         CSS_STYLES,
-        masters_hex,
-        state.mstp_stats.station_address,
-        // Device Map card
-        state.mstp_stats.master_count,
-        generate_device_grid_html(state.mstp_stats.discovered_masters, state.mstp_stats.station_address),
-        // State Machine card
-        get_state_name(state.mstp_stats.current_state),
-        if state.mstp_stats.sole_master { "warning" } else { "" },
-        if state.mstp_stats.sole_master { "Yes" } else { "No" },
-        state.mstp_stats.next_station,
-        state.mstp_stats.poll_station,
-        state.mstp_stats.silence_ms,
-        state.mstp_stats.master_count,
-        // MS/TP Statistics card
-        state.mstp_stats.rx_frames,
-        state.mstp_stats.tx_frames,
-        state.mstp_stats.tokens_received,
-        state.mstp_stats.send_queue_len,
-        state.mstp_stats.receive_queue_len,
-        // Token Loop Timing card
-        state.mstp_stats.token_loop_time_ms,
-        state.mstp_stats.token_loop_min_ms,
-        state.mstp_stats.token_loop_max_ms,
-        state.mstp_stats.token_loop_avg_ms,
-        // Errors card
-        if state.mstp_stats.crc_errors > 0 { "error" } else { "" },
-        state.mstp_stats.crc_errors,
-        if state.mstp_stats.frame_errors > 0 { "error" } else { "" },
-        state.mstp_stats.frame_errors,
-        if state.mstp_stats.reply_timeouts > 0 { "error" } else { "" },
-        state.mstp_stats.reply_timeouts,
-        if state.mstp_stats.token_pass_failures > 0 { "error" } else { "" },
-        state.mstp_stats.token_pass_failures,
-        // Gateway Routing card
-        if state.wifi_connected { "ok" } else { "error" },
-        if state.wifi_connected { "Connected" } else { "Disconnected" },
-        state.ip_address,
-        state.gateway_stats.mstp_to_ip_packets,
-        state.gateway_stats.ip_to_mstp_packets,
-        state.uptime_formatted(),
-        // Network Configuration card
-        state.config.mstp_network,
-        state.config.ip_network,
-        state.config.mstp_address,
-        state.config.device_instance,
+        msg_html,
+        state.gateway_stats.port_disabled_drops,
+        ports_html,
+        state.mstp_stats.pfm_interval,
+        maxmaster::HEADROOM,
+        max_master_html,
+        TALKER_WINDOW.as_secs(),
+        TALKER_WINDOW.as_secs() * 2,
+        rows(&state.top_talkers_by_packets),
+        rows(&state.top_talkers_by_bytes),
+        transactions_html,
+        reassemblies_html,
+        service_errors_html,
+        BUDGET_PER_SEC.as_millis(),
+        web_deferred,
+        web_deferred_ms,
+        web_load_html,
+        ROUTER_QUERY_WINDOW.as_secs(),
+        state.router_query.as_ref().map(|q| q.target.to_string()).unwrap_or_default(),
+        router_query_html
     )
 }
 
-/// Generate HTML for the device grid (128 cells for addresses 0-127)
-fn generate_device_grid_html(discovered_masters: u128, station_address: u8) -> String {
-    let mut html = String::with_capacity(8192);
-    for i in 0..128u8 {
-        let is_present = (discovered_masters >> i) & 1 == 1;
-        let is_self = i == station_address;
-        let class = if is_self {
-            "grid-cell self"
-        } else if is_present {
-            "grid-cell active"
-        } else {
-            "grid-cell"
-        };
-        // Make active and self cells clickable to show device info
-        if is_present || is_self {
-            html.push_str(&format!(r#"<div class="{}" id="dev-{}" title="Address {}" onclick="showGridDeviceInfo({})">{}</div>"#, class, i, i, i, i));
-        } else {
-            html.push_str(&format!(r#"<div class="{}" id="dev-{}" title="Address {}">{}</div>"#, class, i, i, i));
-        }
-    }
-    html
-}
-
-/// Get state name from state number
-fn get_state_name(state: u8) -> &'static str {
-    match state {
-        0 => "Initialize",
-        1 => "Idle",
-        2 => "UseToken",
-        3 => "WaitForReply",
-        4 => "PassToken",
-        5 => "NoToken",
-        6 => "PollForMaster",
-        7 => "AnswerDataRequest",
-        8 => "DoneWithToken",
-        _ => "Unknown",
+/// Max_Master recommendation for the current ring, once it has settled
+fn max_master_advice(state: &WebState) -> Option<MaxMasterAdvice> {
+    let s = &state.mstp_stats;
+    if s.slave_mode || !s.ring_stable {
+        return None;
     }
+    maxmaster::recommend(s.discovered_masters, s.max_master, s.pfm_interval, s.token_loop_avg_ms)
 }
 
-/// Generate configuration page HTML
-fn generate_config_page(state: &WebState) -> String {
-    generate_config_page_with_message(state, "")
-}
-
-/// Generate configuration page with message
-fn generate_config_page_with_message(state: &WebState, message: &str) -> String {
-    let message_html = if message.is_empty() {
+/// Generate static routes page HTML with optional message
+fn generate_routes_page(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
         String::new()
     } else {
         format!(r#"<div class="message">{}</div>"#, message)
     };
 
-    format!(r#"<!DOCTYPE html>
+    let entries_html: String = if state.static_routes.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No static routes configured</p>"#.to_string()
+    } else {
+        state.static_routes
+            .iter()
+            .map(|(network, next_hop)| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">Network {}</span>
+                        <span class="mask">via {}</span>
+                        <form method="POST" action="/routes/remove" style="display:inline">
+                            <input type="hidden" name="network" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Remove</button>
+                        </form>
+                    </div>"#,
+                    network, next_hop, network
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let unreachable_html: String = if state.unreachable_networks.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No networks reported unreachable</p>"#.to_string()
+    } else {
+        state.unreachable_networks
+            .iter()
+            .map(|n| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">Network {}</span>
+                        <span class="mask">reason {} from {} ({} reject(s)), retry in {}s</span>
+                    </div>"#,
+                    n.network,
+                    n.reason,
+                    html_escape(&n.reported_by),
+                    n.count,
+                    n.until.saturating_duration_since(std::time::Instant::now()).as_secs()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
 <html>
 <head>
-    <title>BACman Gateway - Configuration</title>
+    <title>BACman Gateway - Static Routes</title>
     <meta name="viewport" content="width=device-width, initial-scale=1">
     <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 180px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+        .btn-danger {{ border-color: #633; }}
+        .btn-danger:hover {{ background: #633; border-color: #844; }}
+        .add-form {{ background: #111; border: 1px solid #222; padding: 16px; margin-top: 16px; }}
+        .add-form h3 {{ margin-bottom: 16px; font-size: 0.9em; }}
+        .form-row {{ display: flex; gap: 12px; align-items: end; flex-wrap: wrap; }}
+        .form-row .form-group {{ margin-bottom: 0; }}
+        .form-group.small {{ max-width: 100px; }}
+    </style>
 </head>
 <body>
     <div class="container">
         <h1>BACman Gateway</h1>
         <nav>
             <a href="/status">Status</a>
-            <a href="/config" class="active">Configuration</a>
+            <a href="/config">Config</a>
+            <a href="/bdt">BDT</a>
+            <a href="/fdt">FDT</a>
+            <a href="/routes" class="active">Routes</a>
         </nav>
 
         {}
 
-        <form method="POST" action="/config">
-            <div class="card">
-                <h2>WiFi Station Mode</h2>
-                <p class="hint">Connect to an existing WiFi network</p>
-                <div class="form-group">
-                    <label for="wifi_ssid">SSID</label>
-                    <input type="text" id="wifi_ssid" name="wifi_ssid" value="{}" maxlength="32">
-                </div>
-                <div class="form-group">
-                    <label for="wifi_pass">Password</label>
-                    <input type="password" id="wifi_pass" name="wifi_pass" placeholder="(leave blank to keep current)" maxlength="64">
-                </div>
-            </div>
+        <div class="card">
+            <h2>Static Routes</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Routes to networks beyond another router, for sites whose routers do not support Initialize-Routing-Table.
+                Used before rejecting a message as unreachable.
+            </p>
+            {}
+        </div>
 
-            <div class="card">
-                <h2>WiFi Access Point Mode</h2>
-                <p class="hint">Create a WiFi hotspot (activate via long-press on APConfig screen)</p>
-                <div class="form-group">
-                    <label for="ap_ssid">AP SSID</label>
-                    <input type="text" id="ap_ssid" name="ap_ssid" value="{}" maxlength="32">
-                </div>
-                <div class="form-group">
-                    <label for="ap_pass">AP Password (min 8 chars)</label>
-                    <input type="password" id="ap_pass" name="ap_pass" placeholder="(leave blank to keep current)" maxlength="64" minlength="8">
-                </div>
-            </div>
+        <div class="card">
+            <h2>Unreachable Networks</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Networks another router answered with Reject-Message-To-Network. Messages to them are rejected locally until the backoff ends.
+            </p>
+            {}
+        </div>
 
-            <div class="card">
-                <h2>MS/TP Settings</h2>
-                <div class="form-group">
-                    <label for="mstp_addr">Station Address (0-127)</label>
-                    <input type="number" id="mstp_addr" name="mstp_addr" value="{}" min="0" max="127">
-                </div>
-                <div class="form-group">
-                    <label for="mstp_max">Max Master (0-127)</label>
-                    <input type="number" id="mstp_max" name="mstp_max" value="{}" min="0" max="127">
-                </div>
-                <div class="form-group">
-                    <label for="mstp_baud">Baud Rate</label>
-                    <select id="mstp_baud" name="mstp_baud">
-                        <option value="9600" {}>9600</option>
-                        <option value="19200" {}>19200</option>
-                        <option value="38400" {}>38400</option>
-                        <option value="57600" {}>57600</option>
-                        <option value="76800" {}>76800</option>
-                        <option value="115200" {}>115200</option>
-                    </select>
-                </div>
-                <div class="form-group">
-                    <label for="mstp_net">MS/TP Network Number</label>
-                    <input type="number" id="mstp_net" name="mstp_net" value="{}" min="1" max="65534">
+        <div class="add-form">
+            <h3>Add Static Route</h3>
+            <form method="POST" action="/routes/add">
+                <div class="form-row">
+                    <div class="form-group small">
+                        <label>Network</label>
+                        <input type="number" name="network" min="1" max="65534" required>
+                    </div>
+                    <div class="form-group">
+                        <label>Next Hop</label>
+                        <select name="hop_type">
+                            <option value="mstp">MS/TP router (MAC)</option>
+                            <option value="ip">IP router (IP[:port])</option>
+                        </select>
+                    </div>
+                    <div class="form-group">
+                        <label>Router Address</label>
+                        <input type="text" name="hop" placeholder="12 or 192.168.1.1:47808" required>
+                    </div>
+                    <button type="submit" class="btn">Add Route</button>
                 </div>
-            </div>
+            </form>
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        msg_html,
+        entries_html,
+        unreachable_html
+    )
+}
 
-            <div class="card">
-                <h2>BACnet/IP Settings</h2>
-                <div class="form-group">
-                    <label for="ip_port">UDP Port</label>
-                    <input type="number" id="ip_port" name="ip_port" value="{}" min="1" max="65535">
-                </div>
-                <div class="form-group">
-                    <label for="ip_net">IP Network Number</label>
-                    <input type="number" id="ip_net" name="ip_net" value="{}" min="1" max="65534">
-                </div>
-            </div>
+/// Parse fault injection form data, starting from the current settings
+#[cfg(feature = "fault-injection")]
+fn parse_fault_injection_form(body: &str, current: FaultSettings) -> Result<FaultSettings, &'static str> {
+    let mut settings = current;
+    let pct = |key: &str| -> Result<Option<u8>, &'static str> {
+        match form_value(body, key) {
+            None => Ok(None),
+            Some(v) => match v.parse::<u8>() {
+                Ok(p) if p <= 100 => Ok(Some(p)),
+                _ => Err("Percentages must be 0-100"),
+            },
+        }
+    };
+    if let Some(p) = pct("drop")? {
+        settings.drop_pct = p;
+    }
+    if let Some(p) = pct("corrupt")? {
+        settings.corrupt_pct = p;
+    }
+    if let Some(p) = pct("duplicate")? {
+        settings.duplicate_pct = p;
+    }
+    if let Some(v) = form_value(body, "delay_ms") {
+        match v.parse::<u32>() {
+            Ok(ms) if ms <= MAX_DELAY_MS => settings.delay_ms = ms,
+            _ => return Err("delay_ms must be 0-10000"),
+        }
+    }
+    Ok(settings)
+}
+
+/// Generate fault injection settings and counters JSON
+#[cfg(feature = "fault-injection")]
+fn generate_fault_injection_json(state: &WebState, status: &str) -> String {
+    let stats_json = |s: &FaultStats| {
+        format!(
+            r#"{{"dropped":{},"corrupted":{},"duplicated":{},"delayed":{}}}"#,
+            s.dropped, s.corrupted, s.duplicated, s.delayed
+        )
+    };
+    let s = &state.fault_settings;
+    format!(
+        r#"{{"status":"{}","active":{},"drop":{},"corrupt":{},"duplicate":{},"delay_ms":{},"mstp":{},"ip":{}}}"#,
+        status,
+        s.is_active(),
+        s.drop_pct,
+        s.corrupt_pct,
+        s.duplicate_pct,
+        s.delay_ms,
+        stats_json(&state.fault_stats_mstp),
+        stats_json(&state.fault_stats_ip),
+    )
+}
+
+/// Generate GPIO trace state and pin assignment JSON
+#[cfg(feature = "gpio-trace")]
+fn generate_gpio_trace_json(status: &str) -> String {
+    format!(
+        r#"{{"status":"{}","enabled":{},"pins":{{"token":{},"tx":{},"crc_error":{}}}}}"#,
+        status,
+        gpio_trace::enabled(),
+        gpio_trace::TOKEN_PIN,
+        gpio_trace::TX_PIN,
+        gpio_trace::CRC_ERROR_PIN,
+    )
+}
+
+/// Number of admin accounts
+fn admin_count(users: &[UserAccount]) -> usize {
+    users.iter().filter(|u| u.role == Role::Admin).count()
+}
+
+/// Persist the portal accounts to NVS
+fn save_users(state: &WebState) -> &'static str {
+    if state.queue_nvs_write(NvsWrite::Users(state.users.clone())) {
+        ""
+    } else {
+        "NVS not available - accounts will be lost on reboot"
+    }
+}
+
+/// Parse account form data (username=X&password=Y&role=admin|viewer)
+/// and add the account, or replace the password and role of an existing one
+fn parse_user_add_form(body: &str, state: &mut WebState) -> &'static str {
+    let username = form_value(body, "username").unwrap_or_default();
+    if !auth::valid_username(&username) {
+        return "Invalid username (1-32 characters, no spaces or ':')";
+    }
+    let password = form_value(body, "password").unwrap_or_default();
+    if password.len() < auth::MIN_PASSWORD_LEN || password.len() > 64 {
+        return "Password must be 8-64 characters";
+    }
+    let role = match form_value(body, "role").as_deref() {
+        Some("admin") => Role::Admin,
+        Some("viewer") => Role::Viewer,
+        _ => return "Invalid role",
+    };
+
+    let existing = state.users.iter().position(|u| u.username == username);
+    match existing {
+        Some(index) => {
+            // Keep at least one admin so the portal cannot lock itself out
+            if state.users[index].role == Role::Admin && role != Role::Admin && admin_count(&state.users) == 1 {
+                return "Cannot demote the last admin account";
+            }
+        }
+        None => {
+            if state.users.is_empty() && role != Role::Admin {
+                return "The first account must be an admin";
+            }
+            if state.users.len() >= auth::MAX_USERS {
+                return "Account limit reached";
+            }
+        }
+    }
+    let account = match UserAccount::new(&username, &password, role) {
+        Ok(account) => account,
+        Err(e) => {
+            error!("Portal account '{}' not saved: {}", username, e);
+            return "Password hashing failed - account not saved";
+        }
+    };
+    match existing {
+        Some(index) => state.users[index] = account,
+        None => state.users.push(account),
+    }
+    info!("Portal account '{}' ({}) saved via web portal", username, role);
 
-            <div class="card">
-                <h2>Device Settings</h2>
-                <div class="form-group">
-                    <label for="dev_inst">Device Instance (0-4194303)</label>
-                    <input type="number" id="dev_inst" name="dev_inst" value="{}" min="0" max="4194303">
-                </div>
-                <div class="form-group">
-                    <label for="dev_name">Device Name</label>
-                    <input type="text" id="dev_name" name="dev_name" value="{}" maxlength="64">
-                </div>
-            </div>
+    match save_users(state) {
+        "" if existing.is_some() => "Account updated.",
+        "" => "Account added. Log in with the new credentials when prompted.",
+        err => err,
+    }
+}
 
-            <div class="button-row">
-                <button type="submit" class="btn btn-primary">Apply Changes</button>
-            </div>
-        </form>
+/// Remove a portal account, refusing to remove the last admin while other accounts remain
+fn remove_user(state: &mut WebState, username: &str) -> &'static str {
+    let Some(index) = state.users.iter().position(|u| u.username == username) else {
+        return "No such account";
+    };
+    if state.users[index].role == Role::Admin && admin_count(&state.users) == 1 && state.users.len() > 1 {
+        return "Cannot remove the last admin account while other accounts exist";
+    }
+    state.users.remove(index);
+    info!("Portal account '{}' removed via web portal", username);
 
-        <div class="card">
-            <h2>Persist Settings</h2>
-            <p>Save configuration to flash memory (NVS) for persistence across reboots.</p>
-            <div class="button-row">
-                <form method="POST" action="/save" style="display:inline">
-                    <button type="submit" class="btn btn-success">Save to NVS</button>
-                </form>
-                <form method="POST" action="/reset" style="display:inline" onsubmit="return confirm('Reset all settings to defaults?')">
-                    <button type="submit" class="btn btn-warning">Reset Defaults</button>
-                </form>
-                <form method="POST" action="/reboot" style="display:inline" onsubmit="return confirm('Reboot the gateway?')">
-                    <button type="submit" class="btn btn-danger">Reboot</button>
-                </form>
-            </div>
-        </div>
+    match save_users(state) {
+        "" if state.users.is_empty() => "Account removed. No accounts remain - the portal is open to everyone.",
+        "" => "Account removed.",
+        err => err,
+    }
+}
 
-        <p class="footer">BACman v0.1.0 | Changes take effect after reboot</p>
-    </div>
-</body>
-</html>"#,
-        CSS_STYLES,
-        message_html,
-        state.config.wifi_ssid,
-        state.config.ap_ssid,
-        state.config.mstp_address,
-        state.config.mstp_max_master,
-        if state.config.mstp_baud_rate == 9600 { "selected" } else { "" },
-        if state.config.mstp_baud_rate == 19200 { "selected" } else { "" },
-        if state.config.mstp_baud_rate == 38400 { "selected" } else { "" },
-        if state.config.mstp_baud_rate == 57600 { "selected" } else { "" },
-        if state.config.mstp_baud_rate == 76800 { "selected" } else { "" },
-        if state.config.mstp_baud_rate == 115200 { "selected" } else { "" },
-        state.config.mstp_network,
-        state.config.bacnet_ip_port,
-        state.config.ip_network,
-        state.config.device_instance,
-        state.config.device_name,
-    )
+/// Persist the API keys to NVS
+fn save_api_keys(state: &WebState) -> &'static str {
+    if state.queue_nvs_write(NvsWrite::ApiKeys(state.api_keys.clone())) {
+        ""
+    } else {
+        "NVS not available - keys will be lost on reboot"
+    }
 }
 
-/// Generate status JSON for API endpoint
-fn generate_status_json(state: &WebState) -> String {
-    // Convert discovered_masters bitmap to hex string for the device grid
-    let masters_hex = format!("{:032x}", state.mstp_stats.discovered_masters);
+/// Parse API key form data (name=X&scopes=read,control) and create the key.
+/// The message carries the new key, which is not stored and never shown again.
+fn parse_api_key_form(body: &str, state: &mut WebState) -> String {
+    let name = form_value(body, "name").unwrap_or_default();
+    if !auth::valid_username(&name) {
+        return "Invalid key name (1-32 characters, no spaces or ':')".to_string();
+    }
+    if state.api_keys.iter().any(|k| k.name == name) {
+        return "A key with this name exists - revoke it first to replace it".to_string();
+    }
+    if state.api_keys.len() >= auth::MAX_API_KEYS {
+        return "API key limit reached".to_string();
+    }
+    let Some(scopes) = form_value(body, "scopes").and_then(|s| ApiScopes::parse(&s)) else {
+        return "Invalid scopes".to_string();
+    };
 
-    format!(r#"{{"rx_frames":{},"tx_frames":{},"crc_errors":{},"frame_errors":{},"reply_timeouts":{},"tokens_received":{},"token_pass_failures":{},"token_loop_ms":{},"token_loop_min_ms":{},"token_loop_max_ms":{},"token_loop_avg_ms":{},"master_count":{},"mstp_to_ip":{},"ip_to_mstp":{},"wifi_connected":{},"discovered_masters":"{}","current_state":{},"next_station":{},"poll_station":{},"silence_ms":{},"station_address":{},"sole_master":{},"send_queue_len":{},"receive_queue_len":{},"uptime_secs":{},"uptime":"{}"}}"#,
-        state.mstp_stats.rx_frames,
-        state.mstp_stats.tx_frames,
-        state.mstp_stats.crc_errors,
-        state.mstp_stats.frame_errors,
-        state.mstp_stats.reply_timeouts,
-        state.mstp_stats.tokens_received,
-        state.mstp_stats.token_pass_failures,
-        state.mstp_stats.token_loop_time_ms,
-        state.mstp_stats.token_loop_min_ms,
-        state.mstp_stats.token_loop_max_ms,
-        state.mstp_stats.token_loop_avg_ms,
-        state.mstp_stats.master_count,
-        state.gateway_stats.mstp_to_ip_packets,
-        state.gateway_stats.ip_to_mstp_packets,
-        state.wifi_connected,
-        masters_hex,
-        state.mstp_stats.current_state,
-        state.mstp_stats.next_station,
-        state.mstp_stats.poll_station,
-        state.mstp_stats.silence_ms,
-        state.mstp_stats.station_address,
-        state.mstp_stats.sole_master,
-        state.mstp_stats.send_queue_len,
-        state.mstp_stats.receive_queue_len,
-        state.uptime_secs(),
-        state.uptime_formatted(),
-    )
+    let (key, secret) = ApiKey::generate(&name, scopes);
+    state.api_keys.push(key);
+    info!("API key '{}' ({}) created via web portal", name, scopes.format());
+
+    match save_api_keys(state) {
+        "" => format!(
+            "API key '{}' created: <code>{}</code> - copy it now, it is not shown again. Send it as <code>Authorization: Bearer &lt;key&gt;</code>.",
+            html_escape(&name),
+            secret
+        ),
+        err => err.to_string(),
+    }
 }
 
-/// Generate export JSON with all diagnostic data
-fn generate_export_json(state: &WebState) -> String {
-    let masters_hex = format!("{:032x}", state.mstp_stats.discovered_masters);
+/// Revoke an API key
+fn remove_api_key(state: &mut WebState, name: &str) -> &'static str {
+    let Some(index) = state.api_keys.iter().position(|k| k.name == name) else {
+        return "No such API key";
+    };
+    state.api_keys.remove(index);
+    info!("API key '{}' revoked via web portal", name);
 
-    // Build list of discovered device addresses
-    let mut devices = Vec::new();
-    for i in 0..128u8 {
-        if (state.mstp_stats.discovered_masters >> i) & 1 == 1 {
-            devices.push(i);
-        }
+    match save_api_keys(state) {
+        "" => "API key revoked.",
+        err => err,
     }
-    let devices_str: Vec<String> = devices.iter().map(|d| d.to_string()).collect();
+}
 
-    format!(r#"{{
-  "export_time": "{}",
-  "uptime_secs": {},
-  "uptime": "{}",
-  "device": {{
-    "name": "{}",
-    "instance": {},
-    "station_address": {},
-    "ip_address": "{}"
-  }},
-  "networks": {{
-    "mstp_network": {},
-    "ip_network": {},
-    "baud_rate": {}
-  }},
-  "mstp_stats": {{
-    "rx_frames": {},
-    "tx_frames": {},
-    "tokens_received": {},
-    "crc_errors": {},
-    "frame_errors": {},
-    "reply_timeouts": {},
-    "token_pass_failures": {},
-    "master_count": {},
-    "discovered_masters_hex": "{}",
-    "discovered_addresses": [{}]
-  }},
-  "token_loop_timing": {{
-    "current_ms": {},
-    "min_ms": {},
-    "max_ms": {},
-    "avg_ms": {}
-  }},
-  "queues": {{
-    "send_queue_len": {},
-    "receive_queue_len": {}
-  }},
-  "state_machine": {{
-    "current_state": "{}",
-    "sole_master": {},
-    "next_station": {},
-    "poll_station": {},
-    "silence_ms": {}
-  }},
-  "gateway_stats": {{
-    "mstp_to_ip_packets": {},
-    "ip_to_mstp_packets": {}
-  }},
-  "wifi": {{
-    "connected": {},
-    "ssid": "{}"
-  }}
-}}"#,
-        chrono_lite_timestamp(),
-        state.uptime_secs(),
-        state.uptime_formatted(),
-        state.config.device_name,
-        state.config.device_instance,
-        state.mstp_stats.station_address,
-        state.ip_address,
-        state.config.mstp_network,
-        state.config.ip_network,
-        state.config.mstp_baud_rate,
-        state.mstp_stats.rx_frames,
-        state.mstp_stats.tx_frames,
-        state.mstp_stats.tokens_received,
-        state.mstp_stats.crc_errors,
-        state.mstp_stats.frame_errors,
-        state.mstp_stats.reply_timeouts,
-        state.mstp_stats.token_pass_failures,
-        state.mstp_stats.master_count,
-        masters_hex,
-        devices_str.join(","),
-        state.mstp_stats.token_loop_time_ms,
-        state.mstp_stats.token_loop_min_ms,
-        state.mstp_stats.token_loop_max_ms,
-        state.mstp_stats.token_loop_avg_ms,
-        state.mstp_stats.send_queue_len,
-        state.mstp_stats.receive_queue_len,
-        get_state_name(state.mstp_stats.current_state),
-        state.mstp_stats.sole_master,
-        state.mstp_stats.next_station,
-        state.mstp_stats.poll_station,
-        state.mstp_stats.silence_ms,
-        state.gateway_stats.mstp_to_ip_packets,
-        state.gateway_stats.ip_to_mstp_packets,
-        state.wifi_connected,
-        state.config.wifi_ssid,
+/// Generate the portal account management page
+fn generate_users_page(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+
+    let entries_html: String = if state.users.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No accounts - the portal is open to everyone on the network</p>"#.to_string()
+    } else {
+        state.users
+            .iter()
+            .map(|user| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">{}</span>
+                        <form method="POST" action="/users/remove" style="display:inline" onsubmit="return confirm('Remove this account?')">
+                            <input type="hidden" name="username" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Remove</button>
+                        </form>
+                    </div>"#,
+                    html_escape(&user.username),
+                    user.role,
+                    html_escape(&user.username)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let keys_html: String = if state.api_keys.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No API keys</p>"#.to_string()
+    } else {
+        state.api_keys
+            .iter()
+            .map(|key| {
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{}</span>
+                        <span class="mask">{}</span>
+                        <form method="POST" action="/users/keys/remove" style="display:inline" onsubmit="return confirm('Revoke this API key?')">
+                            <input type="hidden" name="name" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Revoke</button>
+                        </form>
+                    </div>"#,
+                    html_escape(&key.name),
+                    key.scopes.format(),
+                    html_escape(&key.name)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Users</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+    <style>
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 180px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+        .btn-danger {{ border-color: #633; }}
+        .btn-danger:hover {{ background: #633; border-color: #844; }}
+        .add-form {{ background: #111; border: 1px solid #222; padding: 16px; margin-top: 16px; }}
+        .add-form h3 {{ margin-bottom: 16px; font-size: 0.9em; }}
+        .form-row {{ display: flex; gap: 12px; align-items: end; flex-wrap: wrap; }}
+        .form-row .form-group {{ margin-bottom: 0; }}
+    </style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/config">Config</a>
+            <a href="/users" class="active">Users</a>
+        </nav>
+
+        {}
+
+        <div class="card">
+            <h2>Portal Accounts</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Admins have full access. Viewers can see status and diagnostics and run device scans, but cannot change settings.
+                The portal requires a login once the first admin account is added.
+            </p>
+            {}
+        </div>
+
+        <div class="add-form">
+            <h3>Add or Update Account</h3>
+            <form method="POST" action="/users/add">
+                <div class="form-row">
+                    <div class="form-group">
+                        <label>Username</label>
+                        <input type="text" name="username" maxlength="32" required>
+                    </div>
+                    <div class="form-group">
+                        <label>Password</label>
+                        <input type="password" name="password" minlength="8" maxlength="64" required>
+                    </div>
+                    <div class="form-group">
+                        <label>Role</label>
+                        <select name="role">
+                            <option value="admin">Admin</option>
+                            <option value="viewer" {}>Viewer</option>
+                        </select>
+                    </div>
+                    <button type="submit" class="btn">Save Account</button>
+                </div>
+            </form>
+        </div>
+
+        <div class="card" style="margin-top: 16px;">
+            <h2>API Keys</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                For scripts calling /api endpoints with <code>Authorization: Bearer &lt;key&gt;</code>.
+                Read covers status and diagnostics, config covers settings, control covers scans, resets and other actions.
+                Keys are only checked once the first account exists; only a hash is kept.
+            </p>
+            {}
+        </div>
+
+        <div class="add-form">
+            <h3>Create API Key</h3>
+            <form method="POST" action="/users/keys/add">
+                <div class="form-row">
+                    <div class="form-group">
+                        <label>Name</label>
+                        <input type="text" name="name" maxlength="32" required>
+                    </div>
+                    <div class="form-group">
+                        <label>Scopes</label>
+                        <select name="scopes">
+                            <option value="read">Read</option>
+                            <option value="read,control">Read + control</option>
+                            <option value="read,config">Read + config</option>
+                            <option value="read,config,control">Read + config + control</option>
+                        </select>
+                    </div>
+                    <button type="submit" class="btn">Create Key</button>
+                </div>
+            </form>
+        </div>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        msg_html,
+        entries_html,
+        if state.users.is_empty() { "disabled" } else { "selected" },
+        keys_html
     )
 }
 
-/// Simple timestamp (uptime in seconds since no RTC)
-fn chrono_lite_timestamp() -> String {
-    let uptime = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
-    format!("uptime_{}s", uptime)
+/// Add, update or remove (empty name and notes) the label for one device
+fn set_device_label(state: &mut WebState, device_instance: u32, name: &str, notes: &str) -> Result<(), String> {
+    let name = name.trim();
+    let notes = notes.trim();
+    if device_instance > MAX_DEVICE_INSTANCE {
+        return Err(format!("Invalid device instance {} (must be 0-{})", device_instance, MAX_DEVICE_INSTANCE));
+    }
+    if name.len() > MAX_LABEL_NAME_LEN {
+        return Err(format!("Name for device {} is longer than {} bytes", device_instance, MAX_LABEL_NAME_LEN));
+    }
+    if notes.len() > MAX_LABEL_NOTES_LEN {
+        return Err(format!("Notes for device {} are longer than {} bytes", device_instance, MAX_LABEL_NOTES_LEN));
+    }
+
+    let labels = Arc::make_mut(&mut state.device_labels);
+    let existing = labels.iter().position(|l| l.device_instance == device_instance);
+    match existing {
+        Some(index) if name.is_empty() && notes.is_empty() => {
+            labels.remove(index);
+        }
+        Some(index) => {
+            labels[index].name = name.to_string();
+            labels[index].notes = notes.to_string();
+        }
+        None if name.is_empty() && notes.is_empty() => {}
+        None => {
+            if labels.len() >= MAX_DEVICE_LABELS {
+                return Err(format!("Label limit reached ({} devices)", MAX_DEVICE_LABELS));
+            }
+            labels.push(DeviceLabel {
+                device_instance,
+                name: name.to_string(),
+                notes: notes.to_string(),
+            });
+            labels.sort_by_key(|l| l.device_instance);
+        }
+    }
+    Ok(())
 }
 
-/// Generate JSON for discovered devices
-fn generate_devices_json(state: &WebState) -> String {
-    let mut json = String::from(r#"{"scan_in_progress":"#);
-    json.push_str(if state.scan_in_progress { "true" } else { "false" });
-    json.push_str(r#","devices":["#);
+/// Persist the device labels to NVS
+fn save_device_labels(state: &WebState) -> &'static str {
+    if state.queue_nvs_write(NvsWrite::DeviceLabels(state.device_labels.to_vec())) {
+        ""
+    } else {
+        "NVS not available - labels will be lost on reboot"
+    }
+}
 
-    for (i, device) in state.discovered_devices.iter().enumerate() {
-        if i > 0 {
-            json.push(',');
+/// Parse device label form data (instance=N&name=X&notes=Y)
+fn parse_device_label_form(body: &str, state: &mut WebState) -> String {
+    // Form encoding turns spaces into '+'
+    let body = &body.replace('+', "%20");
+    let Some(device_instance) = form_value(body, "instance").and_then(|v| v.trim().parse::<u32>().ok()) else {
+        return "Invalid device instance".to_string();
+    };
+    let name = form_value(body, "name").unwrap_or_default();
+    let notes = form_value(body, "notes").unwrap_or_default();
+
+    if let Err(e) = set_device_label(state, device_instance, &name, &notes) {
+        return e;
+    }
+    info!("Label for device {} saved via web portal", device_instance);
+
+    match save_device_labels(state) {
+        "" => "Label saved.".to_string(),
+        err => err.to_string(),
+    }
+}
+
+/// Parse a bulk label import (labels=<lines of "instance,name,notes">)
+///
+/// Each line adds or updates one label; a line with an empty name and notes
+/// removes it. Lines that fail validation are reported and skipped.
+fn parse_device_import_form(body: &str, state: &mut WebState) -> String {
+    // Form encoding turns spaces into '+'
+    let text = form_value(&body.replace('+', "%20"), "labels").unwrap_or_default();
+
+    let mut applied = 0;
+    let mut errors = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ',');
+        let instance = fields.next().unwrap_or("").trim();
+        let name = fields.next().unwrap_or("");
+        let notes = fields.next().unwrap_or("");
+        let result = match instance.parse::<u32>() {
+            Ok(device_instance) => set_device_label(state, device_instance, name, notes),
+            Err(_) => Err(format!("'{}' is not a device instance", instance)),
+        };
+        match result {
+            Ok(()) => applied += 1,
+            Err(e) => errors.push(format!("line {}: {}", line_no + 1, e)),
         }
-        json.push_str(&format!(
-            r#"{{"mac":{},"instance":{},"vendor":{},"max_apdu":{},"segmentation":{}}}"#,
-            device.mac_address,
-            device.device_instance,
-            device.vendor_id,
-            device.max_apdu_length,
-            device.segmentation
-        ));
     }
+    info!("Imported {} device labels via web portal ({} errors)", applied, errors.len());
 
-    json.push_str("]}");
-    json
+    let mut message = format!("Applied {} label line(s).", applied);
+    if !errors.is_empty() {
+        message.push_str(&format!(" Skipped {}: {}", errors.len(), errors.join("; ")));
+    }
+    if applied > 0 {
+        let err = save_device_labels(state);
+        if !err.is_empty() {
+            message = format!("{} {}", message, err);
+        }
+    }
+    message
 }
 
-/// CSS styles - Modern monochrome design
-const CSS_STYLES: &str = r#"
-* { box-sizing: border-box; margin: 0; padding: 0; }
-body { font-family: 'SF Mono', 'Fira Code', 'Consolas', monospace; background: #0a0a0a; color: #e0e0e0; line-height: 1.6; }
-.container { max-width: 800px; margin: 0 auto; padding: 24px; }
-h1 { color: #fff; text-align: center; margin-bottom: 24px; font-size: 1.5em; font-weight: 600; letter-spacing: 2px; text-transform: uppercase; }
-h2 { color: #fff; margin-bottom: 10px; font-size: 0.8em; font-weight: 500; letter-spacing: 1px; text-transform: uppercase; border-bottom: 1px solid #2a2a2a; padding-bottom: 6px; }
-nav { display: flex; justify-content: center; gap: 4px; margin-bottom: 24px; }
-nav a { color: #666; text-decoration: none; padding: 10px 24px; font-size: 0.85em; letter-spacing: 1px; text-transform: uppercase; border: 1px solid #222; transition: all 0.2s; }
-nav a:hover { color: #fff; border-color: #444; }
-nav a.active { color: #fff; background: #1a1a1a; border-color: #333; }
-.card { background: #111; border: 1px solid #222; padding: 16px; margin-bottom: 12px; }
-.card-header { display: flex; justify-content: space-between; align-items: center; margin-bottom: 10px; border-bottom: 1px solid #2a2a2a; padding-bottom: 6px; }
-.card-header h2 { margin-bottom: 0; border-bottom: none; padding-bottom: 0; }
-.status-grid { display: grid; grid-template-columns: repeat(auto-fit, minmax(120px, 1fr)); gap: 6px; }
-.status-item { background: #0a0a0a; border: 1px solid #1a1a1a; padding: 8px 10px; text-align: center; }
-.status-item .label { display: block; color: #555; font-size: 0.65em; letter-spacing: 1px; text-transform: uppercase; margin-bottom: 2px; }
-.status-item .value { display: block; font-size: 1.1em; font-weight: 600; color: #fff; font-variant-numeric: tabular-nums; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
-.status-item .value.auto-size { font-size: clamp(0.7em, 2.5vw, 1.1em); }
-.chip { display: inline-block; background: #333; color: #fff; padding: 2px 8px; font-size: 0.7em; font-weight: 400; margin-left: 8px; vertical-align: middle; }
-.status-item .value.ok { color: #888; }
-.status-item .value.error { color: #fff; background: #333; padding: 2px 8px; }
-.status-item .value.warning { color: #000; background: #fff; padding: 2px 8px; animation: blink 1s infinite; }
-@keyframes blink { 50% { opacity: 0.5; } }
-.device-grid { display: grid; grid-template-columns: repeat(16, 1fr); gap: 2px; margin-bottom: 12px; }
-.grid-cell { aspect-ratio: 1; background: #1a1a1a; border: 1px solid #222; display: flex; align-items: center; justify-content: center; font-size: 0.55em; color: #333; transition: all 0.2s; cursor: default; }
-.grid-cell.active { background: #333; color: #fff; border-color: #444; }
-.grid-cell.self { background: #fff; color: #000; border-color: #fff; font-weight: bold; }
-.grid-legend { display: flex; gap: 16px; justify-content: center; font-size: 0.75em; color: #666; }
-.legend-box { display: inline-block; width: 12px; height: 12px; border: 1px solid #333; margin-right: 4px; vertical-align: middle; }
-.legend-box.active { background: #333; }
-.legend-box.self { background: #fff; }
-.form-group { margin-bottom: 16px; }
-.form-group label { display: block; margin-bottom: 6px; color: #666; font-size: 0.75em; letter-spacing: 1px; text-transform: uppercase; }
-.hint { color: #555; font-size: 0.8em; margin: -8px 0 12px 0; font-style: italic; }
-.form-group input, .form-group select { width: 100%; padding: 12px; border: 1px solid #222; background: #0a0a0a; color: #fff; font-size: 0.95em; font-family: inherit; transition: border-color 0.2s; }
-.form-group input:focus, .form-group select:focus { outline: none; border-color: #444; }
-.form-group input::placeholder { color: #444; }
-.button-row { display: flex; gap: 6px; flex-wrap: wrap; margin-top: 12px; }
-.btn { padding: 8px 16px; border: 1px solid #333; background: transparent; color: #fff; cursor: pointer; font-size: 0.75em; font-family: inherit; letter-spacing: 1px; text-transform: uppercase; transition: all 0.2s; }
-.btn:hover { background: #1a1a1a; border-color: #444; }
-.btn-sm { padding: 4px 10px; font-size: 0.65em; }
-.btn-primary { background: #fff; color: #000; border-color: #fff; }
-.btn-primary:hover { background: #ccc; border-color: #ccc; }
-.btn-success { background: #333; border-color: #444; }
-.btn-success:hover { background: #444; }
-.btn-warning { background: #222; border-color: #333; }
-.btn-warning:hover { background: #333; }
-.btn-danger { background: #1a1a1a; border-color: #333; color: #888; }
-.btn-danger:hover { background: #2a2a2a; color: #fff; }
-.message { background: #111; border-left: 2px solid #444; padding: 16px; margin-bottom: 20px; font-size: 0.9em; }
-.footer { text-align: center; color: #333; margin-top: 32px; font-size: 0.75em; letter-spacing: 1px; }
-.footer a { color: #555; text-decoration: none; }
-.footer a:hover { color: #888; }
-.modal { display: none; position: fixed; top: 0; left: 0; width: 100%; height: 100%; background: rgba(0,0,0,0.8); justify-content: center; align-items: center; z-index: 1000; }
-.modal-content { background: #111; border: 1px solid #333; padding: 24px; max-width: 400px; width: 90%; }
-.modal-content h3 { margin-bottom: 16px; font-size: 1em; letter-spacing: 1px; text-transform: uppercase; border-bottom: 1px solid #222; padding-bottom: 8px; }
-.modal-content p { margin: 8px 0; font-size: 0.9em; }
-.modal-content p b { color: #888; }
-.device-row { display: flex; justify-content: space-between; padding: 12px; margin: 4px 0; background: #0a0a0a; border: 1px solid #1a1a1a; cursor: pointer; font-size: 0.85em; transition: all 0.2s; }
-.device-row:hover { background: #1a1a1a; border-color: #333; }
-.device-row span { color: #888; }
-.scan-status { color: #666; font-size: 0.85em; margin-bottom: 8px; }
-.grid-cell.active { cursor: pointer; }
-.grid-cell.active:hover { background: #444; transform: scale(1.1); }
-@media (max-width: 600px) { .container { padding: 16px; } .card { padding: 16px; } .btn { padding: 10px 16px; } .device-grid { grid-template-columns: repeat(8, 1fr); } .grid-cell { font-size: 0.5em; } }
-"#;
+/// Generate the device labels page
+fn generate_device_labels_page(state: &WebState, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, html_escape(message))
+    };
 
-/// HTML redirect to status page
-const HTML_REDIRECT_STATUS: &str = r#"<!DOCTYPE html>
-<html><head><meta http-equiv="refresh" content="0;url=/status"></head>
-<body>Redirecting to <a href="/status">status page</a>...</body></html>"#;
+    let entries_html: String = if state.device_labels.is_empty() {
+        r#"<p style="color: #555; text-align: center;">No device labels yet</p>"#.to_string()
+    } else {
+        state.device_labels
+            .iter()
+            .map(|label| {
+                let mac = state.discovered_devices.iter()
+                    .find(|d| d.device_instance == label.device_instance)
+                    .map(|d| format!("MAC {}", d.mac_address))
+                    .unwrap_or_else(|| "not seen".to_string());
+                let mac = match state.device_name(label.device_instance) {
+                    Some(name) => format!("{}, {}", mac, html_escape(name)),
+                    None => mac,
+                };
+                format!(
+                    r#"<div class="bdt-entry">
+                        <span class="addr">{} <span class="mask">({})</span></span>
+                        <span class="name">{}</span>
+                        <span class="mask">{}</span>
+                        <form method="POST" action="/devices/label" style="display:inline" onsubmit="return confirm('Remove this label?')">
+                            <input type="hidden" name="instance" value="{}">
+                            <button type="submit" class="btn btn-small btn-danger">Remove</button>
+                        </form>
+                    </div>"#,
+                    label.device_instance,
+                    mac,
+                    html_escape(&label.name),
+                    html_escape(&label.notes),
+                    label.device_instance
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
 
-/// HTML reboot page
-const HTML_REBOOT_PAGE: &str = r#"<!DOCTYPE html>
+    // Discovered devices without a label, so they can be named from this page
+    let unlabeled: Vec<String> = state.discovered_devices.iter()
+        .filter(|d| state.device_label(d.device_instance).is_none())
+        .map(|d| match state.device_name(d.device_instance) {
+            Some(name) => format!("{} {} (MAC {}, {})", d.device_instance, html_escape(name), d.mac_address, html_escape(&d.vendor_name())),
+            None => format!("{} (MAC {}, {})", d.device_instance, d.mac_address, html_escape(&d.vendor_name())),
+        })
+        .collect();
+    let unlabeled_html = if unlabeled.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"<p style="color: #555; font-size: 0.8em; margin-top: 12px;">Unlabeled devices: {}</p>"#,
+            unlabeled.join(", ")
+        )
+    };
+
+    // Current labels in import format so the whole table can be edited at once
+    let import_text: String = state.device_labels
+        .iter()
+        .map(|l| format!("{},{},{}\n", l.device_instance, l.name, l.notes))
+        .collect();
+
+    let names_status = match (state.device_names.len(), state.device_names_pending) {
+        (0, 0) => "No devices bound yet".to_string(),
+        (read, 0) => format!("{} device name(s) read", read),
+        (read, waiting) => format!("{} device name(s) read, {} waiting", read, waiting),
+    };
+
+    let inventory_running = state.inventory_requested || state.inventory_progress.is_some();
+    let inventory_status = match state.inventory_progress {
+        Some(p) => format!("Reading device {} of {}, {} objects found", (p.devices_done + 1).min(p.devices_total), p.devices_total, p.objects_found),
+        None if inventory_running => "Starting...".to_string(),
+        None if state.inventory_report.is_empty() => "No inventory taken yet".to_string(),
+        None => format!(
+            "Last inventory: {} devices, {} objects",
+            state.inventory_report.len(),
+            state.inventory_report.iter().map(|d| d.objects.len()).sum::<usize>()
+        ),
+    };
+    let inventory_downloads = if state.inventory_report.is_empty() {
+        String::new()
+    } else {
+        r#"<a href="/inventory.json" class="btn">Download JSON</a> <a href="/inventory.csv" class="btn">Download CSV</a>"#.to_string()
+    };
+    let inventory_action = if inventory_running {
+        r#"<input type="hidden" name="action" value="cancel"><button type="submit" class="btn btn-danger">Stop Inventory</button>"#
+    } else {
+        r#"<input type="hidden" name="action" value="start"><button type="submit" class="btn">Start Inventory</button>"#
+    };
+    // Refresh the status line while the walk runs
+    let inventory_script = if inventory_running {
+        r#"<script>
+        setInterval(() => fetch('/api/inventory').then(r => r.json()).then(d => {
+            if (!d.running) { location.href = '/devices'; return; }
+            if (d.devices_total) document.getElementById('inventory-status').textContent =
+                'Reading device ' + Math.min(d.devices_done + 1, d.devices_total) + ' of ' + d.devices_total + ', ' + d.objects_found + ' objects found';
+        }), 2000);
+    </script>"#
+    } else {
+        ""
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
 <html>
 <head>
-    <title>BACman Gateway - Rebooting</title>
+    <title>BACman Gateway - Devices</title>
     <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
     <style>
-        body { font-family: 'SF Mono', 'Fira Code', 'Consolas', monospace; background: #0a0a0a; color: #e0e0e0; display: flex; justify-content: center; align-items: center; min-height: 100vh; }
-        .message { text-align: center; }
-        h1 { color: #fff; font-size: 1.2em; font-weight: 500; letter-spacing: 2px; text-transform: uppercase; }
-        .spinner { width: 40px; height: 40px; border: 2px solid #222; border-top: 2px solid #fff; border-radius: 50%; animation: spin 1s linear infinite; margin: 24px auto; }
-        @keyframes spin { 0% { transform: rotate(0deg); } 100% { transform: rotate(360deg); } }
-        p { color: #555; font-size: 0.85em; letter-spacing: 1px; }
+        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
+        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 140px; }}
+        .bdt-entry .name {{ color: #fff; min-width: 160px; }}
+        .bdt-entry .mask {{ color: #666; flex: 1; }}
+        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
+        .btn-danger {{ border-color: #633; }}
+        .btn-danger:hover {{ background: #633; border-color: #844; }}
+        .add-form {{ background: #111; border: 1px solid #222; padding: 16px; margin-top: 16px; }}
+        .add-form h3 {{ margin-bottom: 16px; font-size: 0.9em; }}
+        .form-row {{ display: flex; gap: 12px; align-items: end; flex-wrap: wrap; }}
+        .form-row .form-group {{ margin-bottom: 0; }}
+        textarea {{ width: 100%; min-height: 160px; background: #0a0a0a; color: #e0e0e0; border: 1px solid #333; padding: 8px; font-family: inherit; margin-bottom: 12px; }}
     </style>
-    <script>setTimeout(() => location.href = '/status', 10000);</script>
 </head>
 <body>
-    <div class="message">
-        <h1>Rebooting</h1>
-        <div class="spinner"></div>
-        <p>The gateway is restarting. You will be redirected automatically.</p>
+    <div class="container">
+        <h1>BACman Gateway</h1>
+        <nav>
+            <a href="/status">Status</a>
+            <a href="/config">Config</a>
+            <a href="/devices" class="active">Devices</a>
+        </nav>
+
+        {}
+
+        <div class="card">
+            <h2>Device Labels</h2>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
+                Friendly names and notes are stored on the gateway by device instance and shown in the device map, scan results and exports.
+            </p>
+            {}
+            {}
+        </div>
+
+        <div class="add-form">
+            <h3>Add or Update Label</h3>
+            <form method="POST" action="/devices/label">
+                <div class="form-row">
+                    <div class="form-group">
+                        <label>Device Instance</label>
+                        <input type="number" name="instance" min="0" max="{}" required>
+                    </div>
+                    <div class="form-group">
+                        <label>Name</label>
+                        <input type="text" name="name" maxlength="{}">
+                    </div>
+                    <div class="form-group">
+                        <label>Notes</label>
+                        <input type="text" name="notes" maxlength="{}">
+                    </div>
+                    <button type="submit" class="btn">Save Label</button>
+                </div>
+            </form>
+        </div>
+
+        <div class="add-form">
+            <h3>Bulk Edit</h3>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 12px;">
+                One device per line as <b>instance,name,notes</b>. Lines with an empty name and notes remove the label; devices not listed are left unchanged.
+            </p>
+            <form method="POST" action="/devices/import">
+                <textarea name="labels">{}</textarea>
+                <button type="submit" class="btn">Apply</button>
+            </form>
+        </div>
+
+        <div class="add-form">
+            <h3>Device Names</h3>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 12px;">
+                The gateway reads each device's Object_Name over the port it was heard on and shows it beside the instance number. Names are read again after an hour, or when a device moves.
+            </p>
+            <p style="margin-bottom: 12px;">{}</p>
+            <form method="POST" action="/devices/names" style="display:inline"><button type="submit" class="btn">Read Names Again</button></form>
+        </div>
+
+        <div class="add-form">
+            <h3>Site Inventory</h3>
+            <p style="color: #555; font-size: 0.8em; margin-bottom: 12px;">
+                Reads the object list and every object name from each discovered MS/TP device, one request at a time while the bus is otherwise idle.
+            </p>
+            <p id="inventory-status" style="margin-bottom: 12px;">{}</p>
+            <form method="POST" action="/devices/inventory" style="display:inline">{}</form>
+            {}
+        </div>
     </div>
+    {}
 </body>
-</html>"#;
-
-/// Parse BDT add form data
-fn parse_bdt_add_form(body: &str, state: &mut WebState) -> &'static str {
-    let mut ip_str = String::new();
-    let mut port: u16 = 47808;
-    let mut mask_str = String::new();
+</html>"#,
+        CSS_STYLES,
+        msg_html,
+        entries_html,
+        unlabeled_html,
+        MAX_DEVICE_INSTANCE,
+        MAX_LABEL_NAME_LEN,
+        MAX_LABEL_NOTES_LEN,
+        html_escape(&import_text),
+        names_status,
+        inventory_status,
+        inventory_action,
+        inventory_downloads,
+        inventory_script
+    )
+}
 
-    for pair in body.split('&') {
-        let mut parts = pair.splitn(2, '=');
-        let key = parts.next().unwrap_or("");
-        let value = parts.next().unwrap_or("");
-        let value = urlencoding::decode(value).unwrap_or_default();
+/// Generate JSON for the device names read so far, by instance
+fn generate_device_names_json(state: &WebState) -> String {
+    let mut names: Vec<(&u32, &String)> = state.device_names.iter().collect();
+    names.sort();
+    let names: Vec<String> = names
+        .iter()
+        .map(|(instance, name)| format!(r#"{{"instance":{},"object_name":"{}"}}"#, instance, json_escape(name)))
+        .collect();
+    format!(r#"{{"pending":{},"names":[{}]}}"#, state.device_names_pending, names.join(","))
+}
 
-        match key {
-            "ip" => ip_str = value.to_string(),
-            "port" => {
-                if let Ok(p) = value.parse::<u16>() {
-                    port = p;
-                }
+/// Parse site inventory form data (action=start|cancel)
+fn parse_inventory_form(body: &str, state: &mut WebState) -> &'static str {
+    let running = state.inventory_requested || state.inventory_progress.is_some();
+    match form_value(body, "action").as_deref() {
+        Some("start") => {
+            if running {
+                return "An inventory is already running.";
             }
-            "mask" => mask_str = value.to_string(),
-            _ => {}
+            if state.discovered_devices.is_empty() {
+                return "No MS/TP devices discovered yet. Run a Who-Is scan first.";
+            }
+            state.inventory_requested = true;
+            info!("Site inventory of {} device(s) requested via web portal", state.discovered_devices.len());
+            "Inventory started. Devices are read one at a time in the background."
+        }
+        Some("cancel") => {
+            if !running {
+                return "No inventory is running.";
+            }
+            state.inventory_cancel_requested = true;
+            "Inventory stopped. Objects read so far are kept."
         }
+        _ => "Invalid inventory action",
     }
+}
 
-    // Parse IP address
-    let ip: Ipv4Addr = match ip_str.parse() {
-        Ok(ip) => ip,
-        Err(_) => return "Invalid IP address format",
-    };
+/// Generate site inventory progress JSON
+fn generate_inventory_status_json(state: &WebState) -> String {
+    let progress = state.inventory_progress;
+    format!(
+        r#"{{"running":{},"devices_done":{},"devices_total":{},"objects_found":{},"report_devices":{},"report_objects":{}}}"#,
+        state.inventory_requested || progress.is_some(),
+        progress.map_or(0, |p| p.devices_done),
+        progress.map_or(0, |p| p.devices_total),
+        progress.map_or(0, |p| p.objects_found),
+        state.inventory_report.len(),
+        state.inventory_report.iter().map(|d| d.objects.len()).sum::<usize>(),
+    )
+}
 
-    // Parse subnet mask (default to 255.255.255.255 for host-specific)
-    let mask: Ipv4Addr = if mask_str.is_empty() {
-        Ipv4Addr::new(255, 255, 255, 255)
-    } else {
-        match mask_str.parse() {
-            Ok(m) => m,
-            Err(_) => return "Invalid subnet mask format",
-        }
-    };
+/// Generate the site inventory report as JSON
+fn generate_inventory_json(devices: &[InventoryDevice]) -> String {
+    let devices_json: Vec<String> = devices
+        .iter()
+        .map(|d| {
+            let objects: Vec<String> = d.objects
+                .iter()
+                .map(|o| format!(
+                    r#"{{"type":"{}","instance":{},"name":"{}"}}"#,
+                    o.type_name(),
+                    o.instance(),
+                    json_escape(&o.name)
+                ))
+                .collect();
+            format!(
+                r#"{{"instance":{},"mac":{},"name":"{}","status":"{}","object_count":{},"objects":[{}]}}"#,
+                d.device_instance,
+                d.mac,
+                json_escape(&d.name),
+                d.outcome.as_str(),
+                d.object_count,
+                objects.join(",")
+            )
+        })
+        .collect();
+    format!(r#"{{"devices":[{}]}}"#, devices_json.join(","))
+}
 
-    // Create socket address
-    let addr = SocketAddr::new(std::net::IpAddr::V4(ip), port);
+/// Quote a CSV field when it contains a separator, quote or line break
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
 
-    // Set request for main loop to process
-    state.bdt_add_request = Some((addr, mask));
-    info!("BDT add requested via web portal: {} mask {}", addr, mask);
+/// Generate the site inventory report as CSV, one row per object
+fn generate_inventory_csv(devices: &[InventoryDevice]) -> String {
+    let mut csv = String::from("device_instance,mac,device_name,object_type,object_instance,object_name\n");
+    for d in devices {
+        for o in &d.objects {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                d.device_instance,
+                d.mac,
+                csv_field(&d.name),
+                o.type_name(),
+                o.instance(),
+                csv_field(&o.name)
+            ));
+        }
+    }
+    csv
+}
 
-    "BDT entry add requested. Entry will be added."
+/// Parse scan form data (profile=quick|ranged|directed&low=N&high=N&window=N)
+fn parse_scan_form(body: &str) -> Result<ScanProfile, String> {
+    let number = |key: &str| form_value(body, key).and_then(|v| v.trim().parse::<u32>().ok());
+    match form_value(body, "profile").as_deref() {
+        None | Some("") | Some("quick") => Ok(ScanProfile::Quick),
+        Some("directed") => Ok(ScanProfile::Directed),
+        Some("ranged") => ScanProfile::ranged(
+            number("low").unwrap_or(0),
+            number("high").unwrap_or(MAX_SCAN_INSTANCE),
+            number("window").ok_or("Invalid window size")?,
+        ),
+        Some(other) => Err(format!("Unknown scan profile '{}'", other)),
+    }
 }
 
-/// Parse BDT remove form data
-fn parse_bdt_remove_form(body: &str, state: &mut WebState) -> &'static str {
-    let mut addr_str = String::new();
+/// Parse wiring test form data (mac=X&count=N)
+fn parse_loopback_form(body: &str, state: &mut WebState) -> &'static str {
+    let mut mac: Option<u8> = None;
+    let mut count: u16 = 10;
 
     for pair in body.split('&') {
         let mut parts = pair.splitn(2, '=');
         let key = parts.next().unwrap_or("");
         let value = parts.next().unwrap_or("");
-        let value = urlencoding::decode(value).unwrap_or_default();
 
-        if key == "addr" {
-            addr_str = value.to_string();
+        match key {
+            "mac" => mac = value.parse().ok(),
+            "count" => count = value.parse().unwrap_or(10),
+            _ => {}
         }
     }
 
-    // Parse socket address (format: "IP:port")
-    let addr: SocketAddr = match addr_str.parse() {
-        Ok(a) => a,
-        Err(_) => return "Invalid address format (expected IP:port)",
+    let Some(mac) = mac.filter(|m| *m <= 127) else {
+        return "Invalid station address (must be 0-127).";
     };
+    if mac == state.mstp_stats.station_address {
+        return "Cannot test against the gateway's own station address.";
+    }
+    if state.loopback_result.in_progress || state.loopback_request.is_some() {
+        return "A wiring test is already running.";
+    }
 
-    state.bdt_remove_request = Some(addr);
-    info!("BDT remove requested via web portal: {}", addr);
-
-    "BDT entry remove requested. Entry will be removed."
-}
-
-/// Generate BDT JSON
-fn generate_bdt_json(state: &WebState) -> String {
-    let entries: Vec<String> = state.bdt_entries
-        .iter()
-        .map(|(addr, mask)| {
-            format!(
-                r#"{{"address":"{}","mask":"{}"}}"#,
-                addr, mask
-            )
-        })
-        .collect();
-
-    format!(r#"{{"entries":[{}]}}"#, entries.join(","))
+    state.loopback_request = Some((mac, count.clamp(1, LOOPBACK_MAX_PROBES)));
+    info!("Wiring test requested via web portal: station {} x{}", mac, count);
+    "Wiring test started."
 }
 
-/// Generate BDT page HTML
-fn generate_bdt_page(state: &WebState) -> String {
-    generate_bdt_page_with_message(state, "")
+/// Generate wiring test result JSON
+fn generate_loopback_json(result: &LoopbackTestResult) -> String {
+    format!(
+        r#"{{"target":{},"requested":{},"sent":{},"passed":{},"mismatched":{},"timeouts":{},"error_rate_pct":{:.1},"rtt_min_us":{},"rtt_max_us":{},"rtt_avg_us":{},"in_progress":{}}}"#,
+        result.target,
+        result.requested,
+        result.sent,
+        result.passed,
+        result.mismatched,
+        result.timeouts,
+        result.error_rate_pct(),
+        result.rtt_min_us,
+        result.rtt_max_us,
+        result.rtt_avg_us(),
+        result.in_progress,
+    )
 }
 
-/// Generate BDT page HTML with optional message
-fn generate_bdt_page_with_message(state: &WebState, message: &str) -> String {
+/// Generate MS/TP wiring test page
+fn generate_loopback_page(state: &WebState, message: &str) -> String {
     let msg_html = if message.is_empty() {
         String::new()
     } else {
         format!(r#"<div class="message">{}</div>"#, message)
     };
-
-    let entries_html: String = if state.bdt_entries.is_empty() {
-        r#"<p style="color: #555; text-align: center;">No BDT entries configured</p>"#.to_string()
-    } else {
-        state.bdt_entries
-            .iter()
-            .map(|(addr, mask)| {
-                format!(
-                    r#"<div class="bdt-entry">
-                        <span class="addr">{}</span>
-                        <span class="mask">mask: {}</span>
-                        <form method="POST" action="/bdt/remove" style="display:inline">
-                            <input type="hidden" name="addr" value="{}">
-                            <button type="submit" class="btn btn-small btn-danger">Remove</button>
-                        </form>
-                    </div>"#,
-                    addr, mask, addr
-                )
-            })
-            .collect::<Vec<_>>()
-            .join("\n")
-    };
+    let r = &state.loopback_result;
 
     format!(
         r#"<!DOCTYPE html>
 <html>
 <head>
-    <title>BACman Gateway - BDT Configuration</title>
+    <title>BACman Gateway - Wiring Test</title>
     <meta name="viewport" content="width=device-width, initial-scale=1">
     <style>{}</style>
     <style>
-        .bdt-entry {{ display: flex; align-items: center; gap: 16px; padding: 12px; background: #111; border: 1px solid #222; margin-bottom: 8px; }}
-        .bdt-entry .addr {{ color: #fff; font-weight: 500; min-width: 180px; }}
-        .bdt-entry .mask {{ color: #666; flex: 1; }}
-        .btn-small {{ padding: 4px 12px; font-size: 0.7em; }}
-        .btn-danger {{ border-color: #633; }}
-        .btn-danger:hover {{ background: #633; border-color: #844; }}
-        .add-form {{ background: #111; border: 1px solid #222; padding: 16px; margin-top: 16px; }}
-        .add-form h3 {{ margin-bottom: 16px; font-size: 0.9em; }}
         .form-row {{ display: flex; gap: 12px; align-items: end; flex-wrap: wrap; }}
         .form-row .form-group {{ margin-bottom: 0; }}
         .form-group.small {{ max-width: 100px; }}
@@ -1569,50 +8476,434 @@ fn generate_bdt_page_with_message(state: &WebState, message: &str) -> String {
         <nav>
             <a href="/status">Status</a>
             <a href="/config">Config</a>
-            <a href="/bdt" class="active">BDT</a>
+            <a href="/bdt">BDT</a>
+            <a href="/fdt">FDT</a>
+            <a href="/routes">Routes</a>
+            <a href="/test" class="active">Wiring Test</a>
         </nav>
 
         {}
 
         <div class="card">
-            <h2>Broadcast Distribution Table</h2>
+            <h2>MS/TP Wiring Test</h2>
             <p style="color: #555; font-size: 0.8em; margin-bottom: 16px;">
-                BDT entries define peer BBMDs for broadcast distribution across subnets.
+                Sends Test_Request frames to a station and validates the echoed Test_Response.
+                Test frames are optional in BACnet - not every device answers them.
             </p>
-            {}
-        </div>
-
-        <div class="add-form">
-            <h3>Add BDT Entry</h3>
-            <form method="POST" action="/bdt/add">
+            <form method="POST" action="/test/start">
                 <div class="form-row">
-                    <div class="form-group">
-                        <label>IP Address</label>
-                        <input type="text" name="ip" placeholder="192.168.1.100" required>
-                    </div>
                     <div class="form-group small">
-                        <label>Port</label>
-                        <input type="number" name="port" value="47808" min="1" max="65535">
+                        <label>Station</label>
+                        <input type="number" name="mac" min="0" max="127" value="{}" required>
                     </div>
-                    <div class="form-group">
-                        <label>Subnet Mask</label>
-                        <input type="text" name="mask" placeholder="255.255.255.255">
+                    <div class="form-group small">
+                        <label>Frames</label>
+                        <input type="number" name="count" min="1" max="{}" value="{}">
                     </div>
-                    <button type="submit" class="btn">Add Entry</button>
+                    <button type="submit" class="btn">Start Test</button>
                 </div>
             </form>
+            <form method="POST" action="/test/stop" style="margin-top: 8px;">
+                <button type="submit" class="btn btn-warning">Stop</button>
+            </form>
         </div>
 
-        <div style="margin-top: 16px; display: flex; gap: 8px;">
-            <form method="POST" action="/bdt/clear" onsubmit="return confirm('Clear all BDT entries?')">
-                <button type="submit" class="btn btn-danger">Clear All Entries</button>
-            </form>
+        <div class="card">
+            <h2>Result</h2>
+            <div class="status-grid">
+                <div class="status-item"><span class="label">Station</span><span class="value" id="target">{}</span></div>
+                <div class="status-item"><span class="label">Sent</span><span class="value" id="sent">{} / {}</span></div>
+                <div class="status-item"><span class="label">Passed</span><span class="value" id="passed">{}</span></div>
+                <div class="status-item"><span class="label">Mismatched</span><span class="value" id="mismatched">{}</span></div>
+                <div class="status-item"><span class="label">Timeouts</span><span class="value" id="timeouts">{}</span></div>
+                <div class="status-item"><span class="label">Error Rate</span><span class="value" id="error_rate">{:.1}%</span></div>
+                <div class="status-item"><span class="label">RTT Min</span><span class="value" id="rtt_min">{:.1} ms</span></div>
+                <div class="status-item"><span class="label">RTT Avg</span><span class="value" id="rtt_avg">{:.1} ms</span></div>
+                <div class="status-item"><span class="label">RTT Max</span><span class="value" id="rtt_max">{:.1} ms</span></div>
+            </div>
         </div>
     </div>
+    <script>
+        function refresh() {{
+            fetch('/api/loopback').then(r => r.json()).then(d => {{
+                document.getElementById('target').textContent = d.target;
+                document.getElementById('sent').textContent = d.sent + ' / ' + d.requested;
+                document.getElementById('passed').textContent = d.passed;
+                document.getElementById('mismatched').textContent = d.mismatched;
+                document.getElementById('timeouts').textContent = d.timeouts;
+                document.getElementById('error_rate').textContent = d.error_rate_pct.toFixed(1) + '%';
+                document.getElementById('rtt_min').textContent = (d.rtt_min_us / 1000).toFixed(1) + ' ms';
+                document.getElementById('rtt_avg').textContent = (d.rtt_avg_us / 1000).toFixed(1) + ' ms';
+                document.getElementById('rtt_max').textContent = (d.rtt_max_us / 1000).toFixed(1) + ' ms';
+            }}).catch(() => {{}});
+        }}
+        setInterval(refresh, 1000);
+    </script>
 </body>
 </html>"#,
         CSS_STYLES,
         msg_html,
-        entries_html
+        r.target,
+        LOOPBACK_MAX_PROBES,
+        if r.requested > 0 { r.requested } else { 10 },
+        r.target,
+        r.sent,
+        r.requested,
+        r.passed,
+        r.mismatched,
+        r.timeouts,
+        r.error_rate_pct(),
+        r.rtt_min_us as f32 / 1000.0,
+        r.rtt_avg_us() as f32 / 1000.0,
+        r.rtt_max_us as f32 / 1000.0,
+    )
+}
+
+/// Commissioning wizard step names (steps are numbered from 1)
+const WIZARD_STEPS: [&str; 5] = ["WiFi", "MS/TP", "Networks", "Device", "Finish"];
+
+/// Last wizard step (review and save)
+const WIZARD_LAST_STEP: u8 = WIZARD_STEPS.len() as u8;
+
+/// Get a single URL-decoded value from form data
+fn form_value(body: &str, key: &str) -> Option<String> {
+    body.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| urlencoding::decode(v).unwrap_or_default().to_string())
+}
+
+/// Check whether another master has been seen on the trunk at `addr`
+fn mstp_address_in_use(state: &WebState, addr: u8) -> bool {
+    addr <= 127
+        && addr != state.mstp_stats.station_address
+        && (state.mstp_stats.discovered_masters >> addr) & 1 == 1
+}
+
+/// Form fields entered on each wizard step
+const WIZARD_STEP_FIELDS: [&[&str]; 4] = [
+    &["wifi_ssid", "wifi_pass"],
+    &["mstp_addr", "mstp_max", "mstp_baud"],
+    &["mstp_net", "ip_net"],
+    &["dev_inst", "dev_name"],
+];
+
+/// Validate the values entered for a wizard step
+fn validate_wizard_step(step: u8, state: &WebState) -> Result<(), String> {
+    let config = &state.config;
+    match step {
+        1 => {
+            if config.wifi_ssid.is_empty() {
+                return Err("Enter the site WiFi network name (SSID).".to_string());
+            }
+        }
+        2 => {
+            if mstp_address_in_use(state, config.mstp_address) {
+                return Err(format!(
+                    "MS/TP address {} is already in use on the trunk - choose a free address.",
+                    config.mstp_address
+                ));
+            }
+        }
+        3 => {
+            let heard = &state.heard_networks;
+            for (port, network) in [("MS/TP", config.mstp_network), ("IP", config.ip_network)] {
+                if let Some(router) = heard.reachable.get(&network) {
+                    return Err(format!(
+                        "{} network {} is already announced by the router at {} - choose another number.",
+                        port, network, router
+                    ));
+                }
+            }
+            if let Some(number) = heard.ip_network.filter(|&n| n != config.ip_network) {
+                return Err(format!("Routers on the IP network give it number {} - use that number.", number));
+            }
+        }
+        _ => {}
+    }
+    // Cross-field errors on this step's fields (Max Master, networks, device instance)
+    let fields = WIZARD_STEP_FIELDS.get(step as usize - 1).copied().unwrap_or(&[]);
+    match config_check::check(config, &discovered_instances(state))
+        .into_iter()
+        .find(|i| i.is_error() && fields.contains(&i.field.as_str()))
+    {
+        Some(issue) => Err(format!("{}.", issue.message)),
+        None => Ok(()),
+    }
+}
+
+/// Apply a wizard step submission.
+/// Returns the step to show next with a message, or None once the configuration is saved.
+fn handle_wizard_form(body: &str, state: &mut WebState) -> Option<(u8, String)> {
+    let step = form_value(body, "step")
+        .and_then(|v| v.parse::<u8>().ok())
+        .unwrap_or(1)
+        .clamp(1, WIZARD_LAST_STEP);
+    let back = form_value(body, "action").as_deref() == Some("back");
+
+    let refused = parse_config_form(body, &mut state.config);
+
+    let next = if back {
+        step.saturating_sub(1).max(1)
+    } else {
+        if let Some(issue) = refused.first() {
+            return Some((step, issue.message.clone()));
+        }
+        if let Err(message) = validate_wizard_step(step, state) {
+            return Some((step, message));
+        }
+        if step == WIZARD_LAST_STEP {
+            if !state.queue_nvs_write(NvsWrite::Config(Box::new(state.config.clone()))) {
+                return Some((step, "NVS not available".to_string()));
+            }
+            info!("Commissioning completed via setup wizard");
+            events::record(EventCategory::Config, Severity::Info, "Configuration saved by setup wizard");
+            state.config.configured = true;
+            state.config.commissioning_step = 0;
+            return None;
+        }
+        step + 1
+    };
+
+    // Persist progress so the wizard resumes here after a reboot
+    state.config.commissioning_step = next;
+    state.queue_nvs_write(NvsWrite::CommissioningProgress(Box::new(state.config.clone())));
+    Some((next, String::new()))
+}
+
+/// Generate live address check JSON for the wizard's MS/TP step
+fn generate_address_check_json(state: &WebState, addr: u8) -> String {
+    let s = &state.mstp_stats;
+    let masters: Vec<String> = (0..128u8)
+        .filter(|a| (s.discovered_masters >> a) & 1 == 1 && *a != s.station_address)
+        .map(|a| a.to_string())
+        .collect();
+    format!(
+        r#"{{"address":{},"valid":{},"in_use":{},"rx_frames":{},"crc_errors":{},"masters":[{}]}}"#,
+        addr,
+        addr <= 127,
+        mstp_address_in_use(state, addr),
+        s.rx_frames,
+        s.crc_errors,
+        masters.join(",")
+    )
+}
+
+/// Generate the form fields for one wizard step
+fn generate_wizard_step_html(state: &WebState, step: u8) -> String {
+    let c = &state.config;
+    match step {
+        1 => format!(
+            r#"<h2>Step 1: WiFi</h2>
+            <div class="form-group">
+                <label for="wifi_ssid">Network Name (SSID)</label>
+                <input type="text" id="wifi_ssid" name="wifi_ssid" value="{}" maxlength="32" required>
+            </div>
+            <div class="form-group">
+                <label for="wifi_pass">Password</label>
+                <input type="password" id="wifi_pass" name="wifi_pass" placeholder="(unchanged)" minlength="8" maxlength="63">
+            </div>
+            <p class="hint">The gateway joins this network after commissioning. BACnet/IP traffic uses this interface.</p>"#,
+            html_escape(&c.wifi_ssid)
+        ),
+        2 => format!(
+            r#"<h2>Step 2: MS/TP</h2>
+            <div class="form-group">
+                <label for="mstp_addr">Station Address (0-127)</label>
+                <input type="number" id="mstp_addr" name="mstp_addr" value="{}" min="0" max="127" oninput="checkAddr()">
+                <p class="hint" id="addr-check">Listening on the trunk...</p>
+            </div>
+            <div class="form-group">
+                <label for="mstp_max">Max Master (0-127)</label>
+                <input type="number" id="mstp_max" name="mstp_max" value="{}" min="0" max="127">
+            </div>
+            <div class="form-group">
+                <label for="mstp_baud">Baud Rate</label>
+                <select id="mstp_baud" name="mstp_baud">
+                    <option value="9600" {}>9600</option>
+                    <option value="19200" {}>19200</option>
+                    <option value="38400" {}>38400</option>
+                    <option value="76800" {}>76800</option>
+                    <option value="115200" {}>115200</option>
+                </select>
+                <p class="hint" id="line-check"></p>
+            </div>
+            <script>
+                function checkAddr() {{
+                    const addr = document.getElementById('mstp_addr').value;
+                    fetch('/api/wizard/check-address', {{method: 'POST', body: 'addr=' + addr}})
+                        .then(r => r.json()).then(d => {{
+                            let t = !d.valid ? 'Address must be 0-127'
+                                : d.in_use ? 'Address ' + d.address + ' is IN USE by another master'
+                                : 'Address ' + d.address + ' looks free';
+                            if (d.masters.length) t += ' (masters seen: ' + d.masters.join(', ') + ')';
+                            document.getElementById('addr-check').textContent = t;
+                            document.getElementById('line-check').textContent = d.rx_frames == 0
+                                ? 'No MS/TP traffic received yet at the current baud rate'
+                                : d.rx_frames + ' frames received, ' + d.crc_errors + ' CRC errors at the current baud rate';
+                        }}).catch(() => {{}});
+                }}
+                checkAddr();
+                setInterval(checkAddr, 3000);
+            </script>"#,
+            c.mstp_address,
+            c.mstp_max_master,
+            if c.mstp_baud_rate == 9600 { "selected" } else { "" },
+            if c.mstp_baud_rate == 19200 { "selected" } else { "" },
+            if c.mstp_baud_rate == 38400 { "selected" } else { "" },
+            if c.mstp_baud_rate == 76800 { "selected" } else { "" },
+            if c.mstp_baud_rate == 115200 { "selected" } else { "" },
+        ),
+        3 => {
+            // Prefilled with numbers no router on the site announces
+            let (mstp_network, ip_network) = state.heard_networks.suggest(c.mstp_network, c.ip_network);
+            format!(
+                r#"<h2>Step 3: Network Numbers</h2>
+            <div class="form-group">
+                <label for="mstp_net">MS/TP Network Number (1-65534)</label>
+                <input type="number" id="mstp_net" name="mstp_net" value="{}" min="1" max="65534">
+            </div>
+            <div class="form-group">
+                <label for="ip_net">IP Network Number (1-65534)</label>
+                <input type="number" id="ip_net" name="ip_net" value="{}" min="1" max="65534">
+            </div>
+            <p class="hint">Each number must be unique across the whole BACnet internetwork. {}</p>"#,
+                mstp_network,
+                ip_network,
+                heard_networks_hint(&state.heard_networks, c.mstp_network, c.ip_network),
+            )
+        }
+        4 => format!(
+            r#"<h2>Step 4: Device</h2>
+            <div class="form-group">
+                <label for="dev_inst">Device Instance (0-4194302)</label>
+                <input type="number" id="dev_inst" name="dev_inst" value="{}" min="0" max="4194302">
+            </div>
+            <div class="form-group">
+                <label for="dev_name">Device Name</label>
+                <input type="text" id="dev_name" name="dev_name" value="{}" maxlength="64">
+            </div>
+            <button type="button" class="btn" onclick="testWhoIs()">Run Test Who-Is</button>
+            <p class="hint" id="whois-result" style="margin-top: 8px;"></p>
+            <script>
+                function testWhoIs() {{
+                    const out = document.getElementById('whois-result');
+                    out.textContent = 'Sending Who-Is on MS/TP...';
+                    fetch('/api/scan', {{method: 'POST'}}).then(() => setTimeout(() => {{
+                        fetch('/api/devices').then(r => r.json()).then(d => {{
+                            const inst = parseInt(document.getElementById('dev_inst').value);
+                            const clash = d.devices.some(x => x.instance === inst);
+                            out.textContent = d.devices.length + ' device(s) answered'
+                                + (clash ? ' - instance ' + inst + ' is ALREADY IN USE' : ' - instance ' + inst + ' is free');
+                        }});
+                    }}, 5000)).catch(() => {{ out.textContent = 'Who-Is failed'; }});
+                }}
+            </script>"#,
+            c.device_instance,
+            html_escape(&c.device_name),
+        ),
+        _ => format!(
+            r#"<h2>Step 5: Review</h2>
+            <div class="status-grid">
+                <div class="status-item"><span class="label">WiFi</span><span class="value">{}</span></div>
+                <div class="status-item"><span class="label">Station</span><span class="value">{}</span></div>
+                <div class="status-item"><span class="label">Baud</span><span class="value">{}</span></div>
+                <div class="status-item"><span class="label">MS/TP Net</span><span class="value">{}</span></div>
+                <div class="status-item"><span class="label">IP Net</span><span class="value">{}</span></div>
+                <div class="status-item"><span class="label">Instance</span><span class="value">{}</span></div>
+            </div>
+            <p class="hint" style="margin-top: 12px;">Finishing saves the configuration and reboots the gateway onto the site network.</p>"#,
+            html_escape(&c.wifi_ssid),
+            c.mstp_address,
+            c.mstp_baud_rate,
+            c.mstp_network,
+            c.ip_network,
+            c.device_instance,
+        ),
+    }
+}
+
+/// What the wizard's network step heard from other routers, and why its
+/// numbers differ from the configured ones
+fn heard_networks_hint(heard: &HeardNetworks, mstp_network: u16, ip_network: u16) -> String {
+    if heard.reachable.is_empty() && heard.ip_network.is_none() {
+        return "No other routers heard on the site network yet, so these numbers could not be checked.".to_string();
+    }
+    let mut hint = String::new();
+    if !heard.reachable.is_empty() {
+        let networks: Vec<String> = heard.reachable.keys().map(|n| n.to_string()).collect();
+        hint.push_str(&format!("Other routers announce network(s) {}. ", networks.join(", ")));
+    }
+    if let Some(number) = heard.ip_network {
+        hint.push_str(&format!("Routers on the IP network give it number {}. ", number));
+    }
+    for network in [mstp_network, ip_network] {
+        if let Some(router) = heard.reachable.get(&network) {
+            hint.push_str(&format!("{} is already used behind {}, so a free number is suggested. ", network, router));
+        }
+    }
+    hint.trim_end().to_string()
+}
+
+/// Generate the commissioning wizard page for a step
+fn generate_wizard_page(state: &WebState, step: u8, message: &str) -> String {
+    let msg_html = if message.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<div class="message">{}</div>"#, message)
+    };
+
+    let progress: String = WIZARD_STEPS.iter().enumerate()
+        .map(|(i, name)| {
+            let n = i as u8 + 1;
+            let class = if n == step { "active" } else { "" };
+            format!(r#"<a class="{}">{}. {}</a>"#, class, n, name)
+        })
+        .collect();
+
+    let back_button = if step > 1 {
+        r#"<button type="submit" name="action" value="back" class="btn" formnovalidate>Back</button>"#
+    } else {
+        ""
+    };
+    let next_label = if step == WIZARD_LAST_STEP { "Finish &amp; Reboot" } else { "Next" };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>BACman Gateway - Setup</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>{}</style>
+</head>
+<body>
+    <div class="container">
+        <h1>BACman Setup</h1>
+        <nav>{}</nav>
+
+        {}
+
+        <form method="POST" action="/wizard">
+            <input type="hidden" name="step" value="{}">
+            <div class="card">
+                {}
+            </div>
+            <div class="button-row">
+                <button type="submit" name="action" value="next" class="btn btn-primary">{}</button>
+                {}
+            </div>
+        </form>
+
+        <p class="footer">Progress is saved after each step | <a href="/config">Skip to full configuration</a></p>
+    </div>
+</body>
+</html>"#,
+        CSS_STYLES,
+        progress,
+        msg_html,
+        step,
+        generate_wizard_step_html(state, step),
+        next_label,
+        back_button,
     )
 }
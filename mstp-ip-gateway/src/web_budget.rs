@@ -0,0 +1,198 @@
+//! CPU budget for web page generation
+//!
+//! Building the larger portal pages takes long enough to starve the routing
+//! tasks and shows up as MS/TP silence spikes. Heavy handlers therefore run
+//! through `run`, which charges their duration against a token bucket that
+//! refills at `BUDGET_PER_SEC` per second. Once the bucket is overdrawn the
+//! next heavy request waits (sleeping, so routing gets the CPU) until it is
+//! paid back. Per-path durations are kept so the worst offenders show on the
+//! diagnostics page.
+
+use std::cmp::Reverse;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Handler time the web server may spend per second
+pub const BUDGET_PER_SEC: Duration = Duration::from_millis(200);
+
+/// Longest a request is held back, so clients don't time out
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// Distinct paths tracked; further paths are only counted in the totals
+pub const MAX_TRACKED_PATHS: usize = 12;
+
+/// Timings of one handler path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTiming {
+    pub path: &'static str,
+    pub requests: u32,
+    pub total: Duration,
+    pub worst: Duration,
+}
+
+impl PathTiming {
+    pub fn average(&self) -> Duration {
+        self.total / self.requests.max(1)
+    }
+}
+
+/// Token bucket plus handler statistics
+#[derive(Debug)]
+pub struct WorkBudget {
+    budget_us: i64,
+    /// Remaining credit in microseconds; negative while overdrawn
+    credit_us: i64,
+    refilled_at: Option<Instant>,
+    paths: Vec<PathTiming>,
+    pub deferred: u32,
+    pub deferred_total: Duration,
+}
+
+impl WorkBudget {
+    pub const fn new(budget_per_sec: Duration) -> Self {
+        let budget_us = budget_per_sec.as_micros() as i64;
+        Self {
+            budget_us,
+            credit_us: budget_us,
+            refilled_at: None,
+            paths: Vec::new(),
+            deferred: 0,
+            deferred_total: Duration::ZERO,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(last) = self.refilled_at {
+            let elapsed_us = now.saturating_duration_since(last).as_micros() as i64;
+            let earned = elapsed_us.saturating_mul(self.budget_us) / 1_000_000;
+            self.credit_us = self.credit_us.saturating_add(earned).min(self.budget_us);
+        }
+        self.refilled_at = Some(now);
+    }
+
+    /// How long heavy work starting at `now` should wait (zero with credit left)
+    pub fn delay_before(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.credit_us >= 0 || self.budget_us == 0 {
+            return Duration::ZERO;
+        }
+        let wait_us = (-self.credit_us).saturating_mul(1_000_000) / self.budget_us;
+        Duration::from_micros(wait_us as u64).min(MAX_DELAY)
+    }
+
+    /// Note a request that waited `delay` before running
+    pub fn note_deferred(&mut self, delay: Duration) {
+        self.deferred = self.deferred.saturating_add(1);
+        self.deferred_total += delay;
+    }
+
+    /// Charge `elapsed` of handler work on `path`, finished at `now`
+    pub fn charge(&mut self, path: &'static str, elapsed: Duration, now: Instant) {
+        self.refill(now);
+        self.credit_us = self.credit_us.saturating_sub(elapsed.as_micros() as i64);
+
+        let timing = match self.paths.iter().position(|t| t.path == path) {
+            Some(i) => &mut self.paths[i],
+            None if self.paths.len() < MAX_TRACKED_PATHS => {
+                self.paths.push(PathTiming { path, requests: 0, total: Duration::ZERO, worst: Duration::ZERO });
+                self.paths.last_mut().unwrap()
+            }
+            None => return,
+        };
+        timing.requests = timing.requests.saturating_add(1);
+        timing.total += elapsed;
+        timing.worst = timing.worst.max(elapsed);
+    }
+
+    /// Path timings, worst handler first
+    pub fn timings(&self) -> Vec<PathTiming> {
+        let mut timings = self.paths.clone();
+        timings.sort_by_key(|t| Reverse(t.worst));
+        timings
+    }
+
+    /// Forget the statistics (the bucket itself is left alone)
+    pub fn reset_stats(&mut self) {
+        self.paths.clear();
+        self.deferred = 0;
+        self.deferred_total = Duration::ZERO;
+    }
+}
+
+/// Process-wide budget shared by all HTTP handler threads
+static BUDGET: Mutex<WorkBudget> = Mutex::new(WorkBudget::new(BUDGET_PER_SEC));
+
+/// Run heavy handler work for `path` within the budget, waiting first if it is overdrawn
+pub fn run<R>(path: &'static str, work: impl FnOnce() -> R) -> R {
+    let delay = with_budget(|b| b.delay_before(Instant::now())).unwrap_or_default();
+    if !delay.is_zero() {
+        thread::sleep(delay);
+        with_budget(|b| b.note_deferred(delay));
+    }
+
+    let started = Instant::now();
+    let result = work();
+    let elapsed = started.elapsed();
+    with_budget(|b| b.charge(path, elapsed, Instant::now()));
+    result
+}
+
+/// Run `f` with the budget locked
+pub fn with_budget<R>(f: impl FnOnce(&mut WorkBudget) -> R) -> Option<R> {
+    BUDGET.lock().ok().map(|mut budget| f(&mut budget))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overdrawn_budget_delays_until_paid_back() {
+        let mut budget = WorkBudget::new(Duration::from_millis(200));
+        let t0 = Instant::now();
+        assert_eq!(budget.delay_before(t0), Duration::ZERO);
+
+        // A 300 ms page overdraws the 200 ms bucket by 100 ms: half a second to pay back
+        budget.charge("/status", Duration::from_millis(300), t0);
+        assert_eq!(budget.delay_before(t0), Duration::from_millis(500));
+        assert_eq!(budget.delay_before(t0 + Duration::from_millis(200)), Duration::from_millis(300));
+        assert_eq!(budget.delay_before(t0 + Duration::from_millis(500)), Duration::ZERO);
+
+        // Credit never builds up past one second's worth
+        let later = t0 + Duration::from_secs(60);
+        budget.charge("/status", Duration::from_millis(200), later);
+        assert_eq!(budget.delay_before(later), Duration::ZERO);
+        budget.charge("/status", Duration::from_millis(1), later);
+        assert!(budget.delay_before(later) > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let mut budget = WorkBudget::new(Duration::from_millis(200));
+        let t0 = Instant::now();
+        budget.charge("/api/export", Duration::from_secs(5), t0);
+        assert_eq!(budget.delay_before(t0), MAX_DELAY);
+    }
+
+    #[test]
+    fn test_timings_per_path_worst_first() {
+        let mut budget = WorkBudget::new(Duration::from_millis(200));
+        let t0 = Instant::now();
+        budget.charge("/status", Duration::from_millis(40), t0);
+        budget.charge("/status", Duration::from_millis(80), t0);
+        budget.charge("/config", Duration::from_millis(120), t0);
+
+        let timings = budget.timings();
+        assert_eq!(timings[0].path, "/config");
+        assert_eq!(timings[1].path, "/status");
+        assert_eq!(timings[1].requests, 2);
+        assert_eq!(timings[1].worst, Duration::from_millis(80));
+        assert_eq!(timings[1].average(), Duration::from_millis(60));
+
+        budget.note_deferred(Duration::from_millis(150));
+        budget.reset_stats();
+        assert!(budget.timings().is_empty());
+        assert_eq!(budget.deferred, 0);
+    }
+}
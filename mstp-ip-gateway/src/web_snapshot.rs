@@ -0,0 +1,123 @@
+//! Published snapshots of the web state
+//!
+//! Formatting a status page or the export JSON takes long enough that doing it
+//! with the `WebState` mutex held makes the main loop's `try_lock` stats
+//! writers give up for that pass. Pages are rendered from an immutable copy
+//! instead: the main loop publishes one while a portal client is polling, and
+//! handlers take the current `Arc` under a lock held only to clone the
+//! pointer. A reader that finds no snapshot, or one older than `MAX_AGE`,
+//! copies the live state itself, so pages are never staler than that.
+//!
+//! Handlers that change the state invalidate the snapshot when they let go of
+//! the lock, so the next page shows the change.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Oldest snapshot a page is rendered from
+pub const MAX_AGE: Duration = Duration::from_secs(1);
+
+/// How long after the last read writers keep publishing; an idle portal costs no copies
+pub const READER_WINDOW: Duration = Duration::from_secs(10);
+
+struct Published<T> {
+    current: Option<(Arc<T>, Instant)>,
+    last_read: Option<Instant>,
+}
+
+/// Latest published copy of a `T` shared behind a mutex elsewhere
+pub struct Snapshot<T> {
+    inner: Mutex<Published<T>>,
+}
+
+impl<T: Clone> Snapshot<T> {
+    pub const fn new() -> Self {
+        Self { inner: Mutex::new(Published { current: None, last_read: None }) }
+    }
+
+    /// Replace the snapshot with a copy of `value` (the caller holds its lock)
+    pub fn publish(&self, value: &T, now: Instant) {
+        let copy = Arc::new(value.clone());
+        self.inner.lock().unwrap().current = Some((copy, now));
+    }
+
+    /// Drop the snapshot so the next reader copies the live state
+    pub fn invalidate(&self) {
+        self.inner.lock().unwrap().current = None;
+    }
+
+    /// Whether a reader asked within `READER_WINDOW`
+    pub fn wanted(&self, now: Instant) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.last_read.is_some_and(|t| now.saturating_duration_since(t) < READER_WINDOW)
+    }
+
+    /// The snapshot if it is younger than `MAX_AGE`, otherwise a fresh copy of `source`
+    pub fn load(&self, source: &Mutex<T>, now: Instant) -> Arc<T> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.last_read = Some(now);
+            if let Some((snapshot, at)) = &inner.current {
+                if now.saturating_duration_since(*at) < MAX_AGE {
+                    return Arc::clone(snapshot);
+                }
+            }
+        }
+        // Lock order is source, then snapshot - the same as a publishing writer
+        let value = source.lock().unwrap();
+        let copy = Arc::new(value.clone());
+        self.inner.lock().unwrap().current = Some((Arc::clone(&copy), now));
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_copies_once_then_shares() {
+        let source = Mutex::new(vec![1, 2, 3]);
+        let snapshot = Snapshot::new();
+        let t0 = Instant::now();
+
+        let first = snapshot.load(&source, t0);
+        assert_eq!(*first, [1, 2, 3]);
+        source.lock().unwrap().push(4);
+
+        // Within MAX_AGE readers share the same copy
+        let second = snapshot.load(&source, t0 + Duration::from_millis(500));
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // Past it the live state is copied again
+        let third = snapshot.load(&source, t0 + MAX_AGE);
+        assert_eq!(*third, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_publish_and_invalidate() {
+        let source = Mutex::new(String::from("before"));
+        let snapshot = Snapshot::new();
+        let t0 = Instant::now();
+
+        snapshot.publish(&String::from("published"), t0);
+        assert_eq!(*snapshot.load(&source, t0), "published");
+
+        // A writer's change shows on the next read
+        *source.lock().unwrap() = String::from("after");
+        snapshot.invalidate();
+        assert_eq!(*snapshot.load(&source, t0), "after");
+    }
+
+    #[test]
+    fn test_wanted_only_while_read() {
+        let source = Mutex::new(0u32);
+        let snapshot = Snapshot::new();
+        let t0 = Instant::now();
+        assert!(!snapshot.wanted(t0));
+
+        snapshot.load(&source, t0);
+        assert!(snapshot.wanted(t0 + Duration::from_secs(9)));
+        assert!(!snapshot.wanted(t0 + READER_WINDOW));
+    }
+}
@@ -0,0 +1,357 @@
+//! WritePropertyMultiple proxying for MS/TP devices without WPM
+//!
+//! The write-side companion of the RPM proxy. Once a device has rejected
+//! WritePropertyMultiple as an unrecognized service, the gateway executes
+//! WPMs for it: each property value becomes one WriteProperty on the MS/TP
+//! side, in request order. Like the device itself, the proxy stops at the
+//! first write that fails and answers with a WritePropertyMultiple-Error
+//! naming it; if every write succeeds the client gets a SimpleACK.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::rpm_proxy::{encode_context_unsigned, read_context_unsigned, read_tag, PROXY_MAX_APDU_CODE};
+
+/// Confirmed service choices
+pub const SERVICE_WRITE_PROPERTY: u8 = 15;
+pub const SERVICE_WRITE_PROPERTY_MULTIPLE: u8 = 16;
+
+/// Most WPM translations in flight at once
+pub const MAX_WPM_PROXIES: usize = 4;
+
+/// Most property values a single proxied WPM may carry
+pub const MAX_PROXIED_WRITES: usize = 32;
+
+/// How long to wait for each WriteProperty answer
+pub const WPM_PROXY_WRITE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Retransmissions of an unanswered WriteProperty before the client is sent an Abort
+pub const WPM_PROXY_MAX_RETRIES: u8 = 2;
+
+/// One property value from a WriteAccessSpecification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyWrite {
+    pub object_id: u32,
+    pub property_id: u32,
+    pub array_index: Option<u32>,
+    /// Encoded value (contents of the [2] value tag)
+    pub value: Vec<u8>,
+    pub priority: Option<u8>,
+}
+
+/// What the gateway should do after a WriteProperty answer
+#[derive(Debug, PartialEq, Eq)]
+pub enum WpmStep {
+    /// Send this WriteProperty APDU to the device next
+    Next(Vec<u8>),
+    /// Finished: send this SimpleACK or WritePropertyMultiple-Error APDU to the client
+    Complete(Vec<u8>),
+    /// The device answered with something other than a SimpleACK or Error
+    Failed,
+}
+
+/// A WPM from an IP client being executed as WriteProperty calls
+#[derive(Debug)]
+pub struct WpmProxy {
+    /// IP client that sent the WPM
+    pub client: SocketAddr,
+    /// Invoke ID of the client's WPM
+    pub client_invoke_id: u8,
+    /// MS/TP device being written
+    pub dest_mac: u8,
+    /// Invoke ID of the outstanding WriteProperty
+    pub invoke_id: u8,
+    /// When the outstanding WriteProperty was sent
+    pub sent_at: Instant,
+    /// Retransmissions of the outstanding WriteProperty
+    pub retries: u8,
+    writes: Vec<PropertyWrite>,
+    written: usize,
+}
+
+impl WpmProxy {
+    /// Start proxying a complete, unsegmented WPM request APDU.
+    ///
+    /// Returns `None` when the request is malformed or carries too many values.
+    pub fn new(wpm_apdu: &[u8], client: SocketAddr, dest_mac: u8, invoke_id: u8, now: Instant) -> Option<Self> {
+        // [type/flags, max segs/max APDU, invoke ID, service, service data...]
+        if wpm_apdu.len() < 4
+            || wpm_apdu[0] & 0xF8 != 0x00
+            || wpm_apdu[3] != SERVICE_WRITE_PROPERTY_MULTIPLE
+        {
+            return None;
+        }
+        let writes = parse_wpm_request(&wpm_apdu[4..])?;
+        Some(Self {
+            client,
+            client_invoke_id: wpm_apdu[2],
+            dest_mac,
+            invoke_id,
+            sent_at: now,
+            retries: 0,
+            writes,
+            written: 0,
+        })
+    }
+
+    /// WriteProperty request APDU for the value currently being written
+    pub fn request(&self) -> Vec<u8> {
+        encode_write_property(self.invoke_id, &self.writes[self.written])
+    }
+
+    /// Values written so far and in total
+    pub fn progress(&self) -> (usize, usize) {
+        (self.written, self.writes.len())
+    }
+
+    /// Whether an APDU from `mac` answers the outstanding WriteProperty
+    pub fn matches(&self, mac: u8, apdu: &[u8]) -> bool {
+        mac == self.dest_mac && apdu.get(1) == Some(&self.invoke_id) && matches!(apdu[0] >> 4, 2 | 5 | 6 | 7)
+    }
+
+    /// Record the device's answer; the next WriteProperty (if any) uses `next_invoke_id`
+    pub fn record(&mut self, apdu: &[u8], next_invoke_id: u8, now: Instant) -> WpmStep {
+        if apdu.len() < 3 || apdu[2] != SERVICE_WRITE_PROPERTY {
+            return WpmStep::Failed;
+        }
+        match apdu[0] {
+            // SimpleACK: on to the next value
+            0x20 => {
+                self.written += 1;
+                if self.written < self.writes.len() {
+                    self.invoke_id = next_invoke_id;
+                    self.sent_at = now;
+                    self.retries = 0;
+                    return WpmStep::Next(self.request());
+                }
+                WpmStep::Complete(vec![0x20, self.client_invoke_id, SERVICE_WRITE_PROPERTY_MULTIPLE])
+            }
+            // Error: [invoke ID, service, error class, error code] - the remaining values are not written
+            0x50 if apdu.len() > 3 => {
+                WpmStep::Complete(encode_wpm_error(self.client_invoke_id, &apdu[3..], &self.writes[self.written]))
+            }
+            _ => WpmStep::Failed,
+        }
+    }
+}
+
+/// Position of the closing tag `number` that ends a value starting at `pos`,
+/// stepping over nested constructed values
+fn find_value_end(data: &[u8], mut pos: usize, number: u8) -> Option<usize> {
+    let mut depth = 0usize;
+    loop {
+        let tag = read_tag(data, pos)?;
+        if tag.context && tag.raw_lvt == 6 {
+            depth += 1;
+            pos += tag.header_len;
+        } else if tag.context && tag.raw_lvt == 7 {
+            if depth == 0 {
+                return tag.is_closing(number).then_some(pos);
+            }
+            depth -= 1;
+            pos += tag.header_len;
+        } else {
+            // Application Boolean carries its value in the tag itself
+            let content = if !tag.context && tag.number == 1 { 0 } else { tag.lvt as usize };
+            pos += tag.header_len + content;
+            if pos > data.len() {
+                return None;
+            }
+        }
+    }
+}
+
+/// Parse the list of WriteAccessSpecifications from WPM service data
+fn parse_wpm_request(data: &[u8]) -> Option<Vec<PropertyWrite>> {
+    let mut writes = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        // [0] objectIdentifier
+        let tag = read_tag(data, pos)?;
+        if !tag.is_context(0) || tag.lvt != 4 {
+            return None;
+        }
+        let object_id = read_context_unsigned(data, &mut pos, 0)?;
+
+        // [1] listOfProperties
+        let tag = read_tag(data, pos)?;
+        if !tag.is_opening(1) {
+            return None;
+        }
+        pos += tag.header_len;
+        loop {
+            let tag = read_tag(data, pos)?;
+            if tag.is_closing(1) {
+                pos += tag.header_len;
+                break;
+            }
+            let property_id = read_context_unsigned(data, &mut pos, 0)?;
+            let array_index = match read_tag(data, pos) {
+                Some(tag) if tag.is_context(1) => Some(read_context_unsigned(data, &mut pos, 1)?),
+                _ => None,
+            };
+
+            // [2] value
+            let tag = read_tag(data, pos)?;
+            if !tag.is_opening(2) {
+                return None;
+            }
+            let start = pos + tag.header_len;
+            let end = find_value_end(data, start, 2)?;
+            let value = data[start..end].to_vec();
+            pos = end + 1;
+
+            // [3] priority (1-16)
+            let priority = match read_tag(data, pos) {
+                Some(tag) if tag.is_context(3) => {
+                    let p = read_context_unsigned(data, &mut pos, 3)?;
+                    if !(1..=16).contains(&p) {
+                        return None;
+                    }
+                    Some(p as u8)
+                }
+                _ => None,
+            };
+
+            writes.push(PropertyWrite { object_id, property_id, array_index, value, priority });
+            if writes.len() > MAX_PROXIED_WRITES {
+                return None;
+            }
+        }
+    }
+    (!writes.is_empty()).then_some(writes)
+}
+
+/// WriteProperty-Request APDU for one property value
+pub fn encode_write_property(invoke_id: u8, w: &PropertyWrite) -> Vec<u8> {
+    let mut apdu = vec![0x00, PROXY_MAX_APDU_CODE, invoke_id, SERVICE_WRITE_PROPERTY, 0x0C];
+    apdu.extend_from_slice(&w.object_id.to_be_bytes());
+    apdu.extend_from_slice(&encode_context_unsigned(1, w.property_id));
+    if let Some(index) = w.array_index {
+        apdu.extend_from_slice(&encode_context_unsigned(2, index));
+    }
+    apdu.push(0x3E);
+    apdu.extend_from_slice(&w.value);
+    apdu.push(0x3F);
+    if let Some(priority) = w.priority {
+        apdu.extend_from_slice(&encode_context_unsigned(4, priority as u32));
+    }
+    apdu
+}
+
+/// WritePropertyMultiple-Error APDU: the device's error and the first failed write
+fn encode_wpm_error(invoke_id: u8, error: &[u8], failed: &PropertyWrite) -> Vec<u8> {
+    let mut apdu = vec![0x50, invoke_id, SERVICE_WRITE_PROPERTY_MULTIPLE];
+    // [0] errorType
+    apdu.push(0x0E);
+    apdu.extend_from_slice(error);
+    apdu.push(0x0F);
+    // [1] firstFailedWriteAttempt
+    apdu.push(0x1E);
+    apdu.push(0x0C);
+    apdu.extend_from_slice(&failed.object_id.to_be_bytes());
+    apdu.extend_from_slice(&encode_context_unsigned(1, failed.property_id));
+    if let Some(index) = failed.array_index {
+        apdu.extend_from_slice(&encode_context_unsigned(2, index));
+    }
+    apdu.push(0x1F);
+    apdu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> SocketAddr {
+        "192.168.1.50:47808".parse().unwrap()
+    }
+
+    /// WPM: AV-1 Present_Value = 21.5 @ priority 8, Description = "Zone";
+    /// BV-3 Priority_Array[8] = NULL
+    fn wpm_request() -> Vec<u8> {
+        vec![
+            0x00, 0x05, 0x31, SERVICE_WRITE_PROPERTY_MULTIPLE,
+            0x0C, 0x00, 0x80, 0x00, 0x01, 0x1E,
+            0x09, 0x55, 0x2E, 0x44, 0x41, 0xAC, 0x00, 0x00, 0x2F, 0x39, 0x08,
+            0x09, 0x1C, 0x2E, 0x75, 0x05, 0x00, b'Z', b'o', b'n', b'e', 0x2F,
+            0x1F,
+            0x0C, 0x01, 0x40, 0x00, 0x03, 0x1E,
+            0x09, 0x57, 0x19, 0x08, 0x2E, 0x00, 0x2F,
+            0x1F,
+        ]
+    }
+
+    #[test]
+    fn test_wpm_translated_to_write_property_sequence() {
+        let now = Instant::now();
+        let mut proxy = WpmProxy::new(&wpm_request(), client(), 5, 100, now).unwrap();
+        assert_eq!(proxy.client_invoke_id, 0x31);
+        assert_eq!(proxy.progress(), (0, 3));
+        assert_eq!(
+            proxy.request(),
+            vec![0x00, 0x03, 100, 15, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x19, 0x55, 0x3E, 0x44, 0x41, 0xAC, 0x00, 0x00, 0x3F, 0x49, 0x08]
+        );
+
+        let ack = [0x20, 100, 15];
+        assert!(proxy.matches(5, &ack));
+        assert!(!proxy.matches(6, &ack));
+        let WpmStep::Next(next) = proxy.record(&ack, 101, now) else { panic!("expected next write") };
+        assert_eq!(
+            next,
+            vec![0x00, 0x03, 101, 15, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x19, 0x1C, 0x3E, 0x75, 0x05, 0x00, b'Z', b'o', b'n', b'e', 0x3F]
+        );
+
+        let WpmStep::Next(next) = proxy.record(&[0x20, 101, 15], 102, now) else { panic!("expected next write") };
+        assert_eq!(next, vec![0x00, 0x03, 102, 15, 0x0C, 0x01, 0x40, 0x00, 0x03, 0x19, 0x57, 0x29, 0x08, 0x3E, 0x00, 0x3F]);
+
+        assert_eq!(
+            proxy.record(&[0x20, 102, 15], 103, now),
+            WpmStep::Complete(vec![0x20, 0x31, SERVICE_WRITE_PROPERTY_MULTIPLE])
+        );
+    }
+
+    #[test]
+    fn test_first_failure_ends_wpm_with_error() {
+        let now = Instant::now();
+        let mut proxy = WpmProxy::new(&wpm_request(), client(), 5, 100, now).unwrap();
+        assert!(matches!(proxy.record(&[0x20, 100, 15], 101, now), WpmStep::Next(_)));
+
+        // Description is read-only: property / write-access-denied
+        let error = [0x50, 101, 15, 0x91, 0x02, 0x91, 0x28];
+        assert_eq!(
+            proxy.record(&error, 102, now),
+            WpmStep::Complete(vec![
+                0x50, 0x31, SERVICE_WRITE_PROPERTY_MULTIPLE,
+                0x0E, 0x91, 0x02, 0x91, 0x28, 0x0F,
+                0x1E, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x19, 0x1C, 0x1F,
+            ])
+        );
+        assert_eq!(proxy.progress(), (1, 3));
+    }
+
+    #[test]
+    fn test_constructed_values_and_bad_requests() {
+        let now = Instant::now();
+        // Nested [0] inside the value is kept intact
+        let nested = [
+            0x00, 0x05, 0x01, SERVICE_WRITE_PROPERTY_MULTIPLE,
+            0x0C, 0x00, 0x80, 0x00, 0x01, 0x1E, 0x09, 0x55, 0x2E, 0x0E, 0x21, 0x01, 0x0F, 0x11, 0x2F, 0x1F,
+        ];
+        let proxy = WpmProxy::new(&nested, client(), 5, 7, now).unwrap();
+        assert_eq!(proxy.request()[11..], [0x3E, 0x0E, 0x21, 0x01, 0x0F, 0x11, 0x3F]);
+
+        // Priority outside 1-16
+        let bad_priority = [
+            0x00, 0x05, 0x01, SERVICE_WRITE_PROPERTY_MULTIPLE,
+            0x0C, 0x00, 0x80, 0x00, 0x01, 0x1E, 0x09, 0x55, 0x2E, 0x21, 0x01, 0x2F, 0x39, 0x11, 0x1F,
+        ];
+        assert!(WpmProxy::new(&bad_priority, client(), 5, 7, now).is_none());
+        // Value never closed
+        let truncated = [0x00, 0x05, 0x01, SERVICE_WRITE_PROPERTY_MULTIPLE, 0x0C, 0x00, 0x80, 0x00, 0x01, 0x1E, 0x09, 0x55, 0x2E, 0x21];
+        assert!(WpmProxy::new(&truncated, client(), 5, 7, now).is_none());
+
+        // A Reject for the WriteProperty fails the proxy
+        let mut proxy = WpmProxy::new(&wpm_request(), client(), 5, 100, now).unwrap();
+        assert_eq!(proxy.record(&[0x60, 100, 0x04], 101, now), WpmStep::Failed);
+    }
+}
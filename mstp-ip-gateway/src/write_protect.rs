@@ -0,0 +1,100 @@
+//! Hardware write-protect jumper
+//!
+//! A jumper from a spare GPIO to GND locks the configuration. While it is
+//! fitted the web portal, the serial console, WriteProperty to the gateway's
+//! Device object, B/IP Write-Broadcast-Distribution-Table and
+//! Initialize-Routing-Table are refused with an error naming the jumper, and
+//! a scheduled change waits. Sites that require physical presence for changes
+//! to life-safety-adjacent equipment fit it after commissioning.
+//!
+//! The main loop samples the pin (internal pull-up, so an open pin reads
+//! unlocked) and publishes the debounced state here.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Pins that can carry the jumper: free on the M5StickC Plus2 headers and
+/// with an internal pull-up (0 in the configuration = no jumper)
+pub const WRITE_PROTECT_PINS: [u8; 3] = [25, 32, 33];
+
+/// Why a change was refused, shown by the portal and the console
+pub const LOCKED_MESSAGE: &str = "Configuration is write-protected by the hardware jumper - remove it to make changes";
+
+/// Consecutive samples that must agree before the state changes
+const DEBOUNCE_SAMPLES: u8 = 3;
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the jumper is fitted
+pub fn is_locked() -> bool {
+    LOCKED.load(Ordering::Relaxed)
+}
+
+pub fn set_locked(locked: bool) {
+    LOCKED.store(locked, Ordering::Relaxed);
+}
+
+/// Whether `pin` can be configured for the jumper
+pub fn valid_pin(pin: u8) -> bool {
+    pin == 0 || WRITE_PROTECT_PINS.contains(&pin)
+}
+
+/// Debounces the sampled jumper so a loose contact does not toggle the lock
+#[derive(Debug)]
+pub struct Debouncer {
+    state: bool,
+    candidate: bool,
+    count: u8,
+}
+
+impl Debouncer {
+    pub const fn new(initial: bool) -> Self {
+        Self { state: initial, candidate: initial, count: 0 }
+    }
+
+    /// Feed one sample (true = jumper fitted); returns the new state when it changes
+    pub fn sample(&mut self, fitted: bool) -> Option<bool> {
+        if fitted == self.state {
+            self.count = 0;
+            return None;
+        }
+        if fitted != self.candidate {
+            self.candidate = fitted;
+            self.count = 0;
+        }
+        self.count += 1;
+        if self.count < DEBOUNCE_SAMPLES {
+            return None;
+        }
+        self.state = fitted;
+        self.count = 0;
+        Some(fitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer_needs_consecutive_samples() {
+        let mut debouncer = Debouncer::new(false);
+        assert_eq!(debouncer.sample(true), None);
+        assert_eq!(debouncer.sample(false), None);
+        assert_eq!(debouncer.sample(true), None);
+        assert_eq!(debouncer.sample(true), None);
+        assert_eq!(debouncer.sample(true), Some(true));
+        assert_eq!(debouncer.sample(true), None);
+
+        assert_eq!(debouncer.sample(false), None);
+        assert_eq!(debouncer.sample(false), None);
+        assert_eq!(debouncer.sample(false), Some(false));
+    }
+
+    #[test]
+    fn test_valid_pins() {
+        assert!(valid_pin(0));
+        assert!(valid_pin(32));
+        assert!(!valid_pin(26)); // RS-485 RX
+        assert!(!valid_pin(36)); // input only, no pull-up
+    }
+}